@@ -0,0 +1,136 @@
+//! Run the elisp benchmarks in `benches/elisp` under rune and report the wall
+//! time of each.
+//!
+//! Run with `cargo bench-elisp [REGEX]`. Pass `--emacs` (or `--emacs=PATH`)
+//! to also run the benchmarks under Emacs and compare the two. The results
+//! are written as JSON to `target/elisp-bench.json`, or the path given with
+//! `--json=PATH`.
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitCode};
+
+/// The prefix of the lines `bench.el` prints for each iteration.
+const RESULT_PREFIX: &str = "rune-bench: ";
+
+struct Options {
+    selector: Option<String>,
+    emacs: Option<String>,
+    json: PathBuf,
+}
+
+fn parse_args() -> Options {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let mut options =
+        Options { selector: None, emacs: None, json: root.join("target/elisp-bench.json") };
+    for arg in std::env::args().skip(1) {
+        if arg == "--emacs" {
+            options.emacs = Some("emacs".into());
+        } else if let Some(path) = arg.strip_prefix("--emacs=") {
+            options.emacs = Some(path.into());
+        } else if let Some(path) = arg.strip_prefix("--json=") {
+            options.json = path.into();
+        } else if !arg.starts_with('-') {
+            options.selector = Some(arg);
+        }
+        // ignore the flags passed by `cargo bench`, such as `--bench`
+    }
+    options
+}
+
+fn benchmark_files(dir: &Path) -> Vec<PathBuf> {
+    let mut files: Vec<_> = std::fs::read_dir(dir)
+        .expect("benchmark directory should exist")
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "el"))
+        .filter(|path| path.file_name().is_some_and(|name| name != "bench.el"))
+        .collect();
+    files.sort();
+    files
+}
+
+/// Run the benchmarks with `program` and return the times of each, in
+/// seconds.
+fn run(
+    program: &str,
+    dir: &Path,
+    selector: Option<&str>,
+) -> Result<BTreeMap<String, Vec<f64>>, String> {
+    let mut cmd = Command::new(program);
+    cmd.arg("--batch").arg("-l").arg(dir.join("bench.el"));
+    for file in benchmark_files(dir) {
+        cmd.arg("-l").arg(file);
+    }
+    let selector = selector.map_or("nil".to_owned(), |x| format!("{x:?}"));
+    cmd.arg("--eval").arg(format!("(rune-bench-run-and-exit {selector})"));
+    let output = cmd.output().map_err(|e| format!("failed to run {program}: {e}"))?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if !output.status.success() {
+        return Err(format!("{program} exited with {}:\n{stderr}", output.status));
+    }
+    let mut times: BTreeMap<String, Vec<f64>> = BTreeMap::new();
+    for line in stderr.lines() {
+        let Some(result) = line.strip_prefix(RESULT_PREFIX) else { continue };
+        let Some((name, time)) = result.split_once(' ') else { continue };
+        let time = time.parse().map_err(|_| format!("invalid time in {line:?}"))?;
+        times.entry(name.to_owned()).or_default().push(time);
+    }
+    Ok(times)
+}
+
+fn summary(times: &[f64]) -> serde_json::Value {
+    let min = times.iter().copied().fold(f64::INFINITY, f64::min);
+    let mean = times.iter().sum::<f64>() / times.len() as f64;
+    json!({ "times": times, "min": min, "mean": mean })
+}
+
+fn main() -> ExitCode {
+    let options = parse_args();
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("benches/elisp");
+    let selector = options.selector.as_deref();
+    let rune = match run(env!("CARGO_BIN_EXE_rune"), &dir, selector) {
+        Ok(times) => times,
+        Err(e) => {
+            eprintln!("{e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let emacs = match options.emacs.as_deref().map(|emacs| run(emacs, &dir, selector)) {
+        Some(Ok(times)) => Some(times),
+        Some(Err(e)) => {
+            eprintln!("{e}");
+            return ExitCode::FAILURE;
+        }
+        None => None,
+    };
+
+    let mut results = serde_json::Map::new();
+    println!("{:<20} {:>12} {:>12} {:>8}", "benchmark", "rune (s)", "emacs (s)", "ratio");
+    for (name, times) in &rune {
+        let mut result = json!({ "rune": summary(times) });
+        let rune_min = result["rune"]["min"].as_f64().unwrap();
+        let emacs_min = emacs.as_ref().and_then(|x| x.get(name)).map(|times| {
+            result["emacs"] = summary(times);
+            result["emacs"]["min"].as_f64().unwrap()
+        });
+        match emacs_min {
+            Some(emacs_min) => {
+                let ratio = rune_min / emacs_min;
+                println!("{name:<20} {rune_min:>12.4} {emacs_min:>12.4} {ratio:>8.2}");
+            }
+            None => println!("{name:<20} {rune_min:>12.4} {:>12} {:>8}", "-", "-"),
+        }
+        results.insert(name.clone(), result);
+    }
+
+    let report = json!({ "benchmarks": results });
+    if let Some(dir) = options.json.parent() {
+        let _ = std::fs::create_dir_all(dir);
+    }
+    if let Err(e) = std::fs::write(&options.json, format!("{report:#}\n")) {
+        eprintln!("failed to write {}: {e}", options.json.display());
+        return ExitCode::FAILURE;
+    }
+    println!("results written to {}", options.json.display());
+    ExitCode::SUCCESS
+}
@@ -0,0 +1,39 @@
+;;; bench.el --- Driver for the elisp benchmarks  -*- lexical-binding: t; -*-
+
+;;; Commentary:
+
+;; Each benchmark file registers its benchmarks with `rune-bench-define'.
+;; The files are loaded after this one and then run with
+;; `rune-bench-run-and-exit', which prints a line of the form
+;;
+;;   rune-bench: NAME SECONDS
+;;
+;; for every iteration of a benchmark.  Only functions that are also in
+;; Emacs are used, so the same files can be run under both.
+
+;;; Code:
+
+(defvar rune-bench-iterations 3
+  "The number of times to run each benchmark.")
+
+(defvar rune-bench--benchmarks nil
+  "The benchmarks as (NAME . FUNCTION), most recently defined first.")
+
+(defun rune-bench-define (name function)
+  "Define a benchmark NAME that calls FUNCTION."
+  (setq rune-bench--benchmarks (cons (cons name function) rune-bench--benchmarks)))
+
+(defun rune-bench-run-and-exit (&optional selector)
+  "Run the benchmarks whose names match the regexp SELECTOR and exit.
+If SELECTOR is nil, run all of them."
+  (dolist (bench (reverse rune-bench--benchmarks))
+    (when (or (null selector) (string-match selector (symbol-name (car bench))))
+      (let ((i 0))
+        (while (< i rune-bench-iterations)
+          (let ((start (float-time)))
+            (funcall (cdr bench))
+            (message "rune-bench: %s %s" (car bench) (- (float-time) start)))
+          (setq i (1+ i))))))
+  (kill-emacs 0))
+
+;;; bench.el ends here
@@ -0,0 +1,49 @@
+;;; bubble.el --- Bubble sort of a list and a vector  -*- lexical-binding: t; -*-
+
+(defconst elb-bubble-len 300)
+
+(defun elb-bubble-sort-vector (vec)
+  (let ((n (length vec))
+        (swapped t))
+    (while swapped
+      (setq swapped nil)
+      (let ((i 1))
+        (while (< i n)
+          (let ((prev (aref vec (1- i)))
+                (cur (aref vec i)))
+            (when (> prev cur)
+              (aset vec i prev)
+              (aset vec (1- i) cur)
+              (setq swapped t)))
+          (setq i (1+ i))))
+      (setq n (1- n)))
+    vec))
+
+(defun elb-bubble-sort-list (list)
+  (let ((swapped t))
+    (while swapped
+      (setq swapped nil)
+      (let ((cell list))
+        (while (cdr cell)
+          (when (> (car cell) (cadr cell))
+            (let ((tmp (car cell)))
+              (setcar cell (cadr cell))
+              (setcar (cdr cell) tmp)
+              (setq swapped t)))
+          (setq cell (cdr cell)))))
+    list))
+
+(defun elb-bubble-input ()
+  "Return a list of numbers in reverse order with some repeats."
+  (let ((list nil)
+        (i 0))
+    (while (< i elb-bubble-len)
+      (setq list (cons (% (* i 7) elb-bubble-len) list))
+      (setq i (1+ i)))
+    list))
+
+(rune-bench-define 'bubble (lambda () (elb-bubble-sort-list (elb-bubble-input))))
+(rune-bench-define 'bubble-vector
+                   (lambda () (elb-bubble-sort-vector (vconcat (elb-bubble-input)))))
+
+;;; bubble.el ends here
@@ -0,0 +1,33 @@
+;;; cons.el --- Cons cell allocation throughput  -*- lexical-binding: t; -*-
+
+(defun elb-cons-build (n)
+  "Build a list of N elements."
+  (let ((list nil)
+        (i 0))
+    (while (< i n)
+      (setq list (cons i list))
+      (setq i (1+ i)))
+    list))
+
+(defun elb-cons-churn (n)
+  "Allocate N short-lived lists, keeping only the last one."
+  (let ((i 0)
+        (list nil))
+    (while (< i n)
+      (setq list (list i i i i))
+      (setq i (1+ i)))
+    list))
+
+(defun elb-cons-copy (list n)
+  "Copy LIST N times with `mapcar'."
+  (let ((i 0))
+    (while (< i n)
+      (setq list (mapcar #'1+ list))
+      (setq i (1+ i)))
+    list))
+
+(rune-bench-define 'cons-build (lambda () (elb-cons-build 200000)))
+(rune-bench-define 'cons-churn (lambda () (elb-cons-churn 100000)))
+(rune-bench-define 'cons-copy (lambda () (elb-cons-copy (elb-cons-build 1000) 200)))
+
+;;; cons.el ends here
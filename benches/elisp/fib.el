@@ -0,0 +1,10 @@
+;;; fib.el --- Recursive fibonacci  -*- lexical-binding: t; -*-
+
+(defun elb-fib (n)
+  (if (< n 2)
+      n
+    (+ (elb-fib (- n 1)) (elb-fib (- n 2)))))
+
+(rune-bench-define 'fib (lambda () (elb-fib 22)))
+
+;;; fib.el ends here
@@ -0,0 +1,21 @@
+;;; funcall.el --- Calls through functions and aliases  -*- lexical-binding: t; -*-
+
+(defun elb-funcall-add (a b)
+  (+ a b))
+
+(defalias 'elb-funcall-alias #'elb-funcall-add)
+(defalias 'elb-funcall-alias-2 'elb-funcall-alias)
+
+(defun elb-funcall (n)
+  (let ((sum 0)
+        (i 0))
+    (while (< i n)
+      (setq sum (elb-funcall-add sum 1))
+      (setq sum (elb-funcall-alias sum 1))
+      (setq sum (funcall 'elb-funcall-alias-2 sum 1))
+      (setq i (1+ i)))
+    sum))
+
+(rune-bench-define 'funcall (lambda () (elb-funcall 100000)))
+
+;;; funcall.el ends here
@@ -0,0 +1,69 @@
+;;; nbody.el --- N-body simulation with floats  -*- lexical-binding: t; -*-
+
+;; Each body is a vector [X Y Z VX VY VZ MASS].
+
+(defconst elb-nbody-solar-mass (* 4 3.141592653589793 3.141592653589793))
+(defconst elb-nbody-days-per-year 365.24)
+
+(defun elb-nbody-make-body (x y z vx vy vz mass)
+  (vector x y z
+          (* vx elb-nbody-days-per-year)
+          (* vy elb-nbody-days-per-year)
+          (* vz elb-nbody-days-per-year)
+          (* mass elb-nbody-solar-mass)))
+
+(defun elb-nbody-system ()
+  (list
+   (elb-nbody-make-body 0.0 0.0 0.0 0.0 0.0 0.0 1.0)
+   (elb-nbody-make-body 4.84143144246472090e+00 -1.16032004402742839e+00
+                        -1.03622044471123109e-01 1.66007664274403694e-03
+                        7.69901118419740425e-03 -6.90460016972063023e-05
+                        9.54791938424326609e-04)
+   (elb-nbody-make-body 8.34336671824457987e+00 4.12479856412430479e+00
+                        -4.03523417114321381e-01 -2.76742510726862411e-03
+                        4.99852801234917238e-03 2.30417297573763929e-05
+                        2.85885980666130812e-04)
+   (elb-nbody-make-body 1.28943695621391310e+01 -1.51111514016986312e+01
+                        -2.23307578892655734e-01 2.96460137564761618e-03
+                        2.37847173959480950e-03 -2.96589568540237556e-05
+                        4.36624404335156298e-05)
+   (elb-nbody-make-body 1.53796971148509165e+01 -2.59193146099879641e+01
+                        1.79258772950371181e-01 2.68067772490389322e-03
+                        1.62824170038242295e-03 -9.51592254519715870e-05
+                        5.15138902046611451e-05)))
+
+(defun elb-nbody-advance (bodies dt)
+  (let ((rest bodies))
+    (while rest
+      (let ((a (car rest)))
+        (dolist (b (cdr rest))
+          (let* ((dx (- (aref a 0) (aref b 0)))
+                 (dy (- (aref a 1) (aref b 1)))
+                 (dz (- (aref a 2) (aref b 2)))
+                 (dist2 (+ (* dx dx) (* dy dy) (* dz dz)))
+                 (mag (/ dt (* dist2 (sqrt dist2))))
+                 (a-mass (* (aref a 6) mag))
+                 (b-mass (* (aref b 6) mag)))
+            (aset a 3 (- (aref a 3) (* dx b-mass)))
+            (aset a 4 (- (aref a 4) (* dy b-mass)))
+            (aset a 5 (- (aref a 5) (* dz b-mass)))
+            (aset b 3 (+ (aref b 3) (* dx a-mass)))
+            (aset b 4 (+ (aref b 4) (* dy a-mass)))
+            (aset b 5 (+ (aref b 5) (* dz a-mass))))))
+      (setq rest (cdr rest)))
+    (dolist (body bodies)
+      (aset body 0 (+ (aref body 0) (* dt (aref body 3))))
+      (aset body 1 (+ (aref body 1) (* dt (aref body 4))))
+      (aset body 2 (+ (aref body 2) (* dt (aref body 5)))))))
+
+(defun elb-nbody (steps)
+  (let ((bodies (elb-nbody-system))
+        (i 0))
+    (while (< i steps)
+      (elb-nbody-advance bodies 0.01)
+      (setq i (1+ i)))
+    bodies))
+
+(rune-bench-define 'nbody (lambda () (elb-nbody 2000)))
+
+;;; nbody.el ends here
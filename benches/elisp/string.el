@@ -0,0 +1,41 @@
+;;; string.el --- String building and searching  -*- lexical-binding: t; -*-
+
+(defun elb-string-build (n)
+  "Build a string of N numbers separated by commas."
+  (let ((parts nil)
+        (i 0))
+    (while (< i n)
+      (setq parts (cons (number-to-string i) parts))
+      (setq i (1+ i)))
+    (mapconcat #'identity (nreverse parts) ",")))
+
+(defun elb-string-concat (n)
+  (let ((str "")
+        (i 0))
+    (while (< i n)
+      (setq str (concat str (char-to-string (+ ?a (% i 26)))))
+      (setq i (1+ i)))
+    str))
+
+(defun elb-string-search (str)
+  "Count the numbers in STR that end in 7."
+  (let ((count 0)
+        (start 0))
+    (while (string-match "[0-9]*7," str start)
+      (setq count (1+ count))
+      (setq start (match-end 0)))
+    count))
+
+(defun elb-string-case (str n)
+  (let ((i 0))
+    (while (< i n)
+      (setq str (downcase (upcase str)))
+      (setq i (1+ i)))
+    str))
+
+(rune-bench-define 'string-build (lambda () (elb-string-build 20000)))
+(rune-bench-define 'string-concat (lambda () (elb-string-concat 2000)))
+(rune-bench-define 'string-search (lambda () (elb-string-search (elb-string-build 5000))))
+(rune-bench-define 'string-case (lambda () (elb-string-case (elb-string-concat 500) 500)))
+
+;;; string.el ends here
@@ -52,6 +52,7 @@ fn convert(lisp_name: impl Into<String>, rust_name: &str) -> String {
 #[derive(PartialEq)]
 enum DefvarType {
     Bool,
+    Local,
     Other,
 }
 
@@ -100,6 +101,8 @@ fn main() {
             for (start, _) in contents.match_indices("\ndefvar") {
                 let defvar_type = if contents[start..].starts_with("\ndefvar_bool!") {
                     DefvarType::Bool
+                } else if contents[start..].starts_with("\ndefvar_local!") {
+                    DefvarType::Local
                 } else if contents[start..].starts_with("\ndefvar!") {
                     DefvarType::Other
                 } else {
@@ -142,6 +145,17 @@ fn main() {
         }
     }
 
+    // A defun with the same name as a defvar, like `buffer-file-name`, uses the
+    // symbol of the defvar instead of creating its own
+    let (shared_defun, all_defun): (Vec<_>, Vec<_>) = all_defun
+        .into_iter()
+        .partition(|(_, _, lisp_name)| all_defvar.iter().any(|(_, name, _, _)| name == lisp_name));
+    let defvar_index = |lisp_name: &str| {
+        let pos = all_defvar.iter().position(|(_, name, _, _)| name == lisp_name).unwrap();
+        // nil and t come before the defsyms and defvars
+        2 + all_defsym.len() + pos
+    };
+
     let out_dir = std::env::var("OUT_DIR").unwrap();
     // println!("cargo:warning={out_dir}/sym.rs");
     let dest_path = Path::new(&out_dir).join("sym.rs");
@@ -173,9 +187,13 @@ pub(in crate::core) static BUILTIN_SYMBOLS: [SymbolCell; {symbol_len}] = [
         writeln!(f, "    SymbolCell::new_static(\"{sym_name}\"),").unwrap();
     }
 
-    for (_, name, _, _) in &all_defvar {
+    for (_, name, _, ty) in &all_defvar {
+        let constructor = match ty {
+            DefvarType::Local => "new_static_local",
+            _ => "new_static_special",
+        };
         #[rustfmt::skip]
-        writeln!(f, "    SymbolCell::new_static_special(\"{name}\"),").unwrap();
+        writeln!(f, "    SymbolCell::{constructor}(\"{name}\"),").unwrap();
     }
 
     // write the list of all defun to a file in out_dir
@@ -199,46 +217,69 @@ pub(in crate::core) static BUILTIN_SYMBOLS: [SymbolCell; {symbol_len}] = [
         #[rustfmt::skip]
         writeln!(f, "pub(crate) const {sym_name}: Symbol = Symbol::new_builtin({idx});").unwrap();
     }
+    for (_, name, lisp_name) in &shared_defun {
+        let sym_name = name.to_ascii_uppercase();
+        let idx = defvar_index(lisp_name);
+        if all_defvar[idx - 2 - all_defsym.len()].0 != sym_name {
+            #[rustfmt::skip]
+            writeln!(f, "pub(crate) const {sym_name}: Symbol = Symbol::new_builtin({idx});").unwrap();
+        }
+    }
 
     // all SubrFn
-    let subr_len = all_defun.len();
+    let subr_len = all_defun.len() + shared_defun.len();
     writeln!(f, "static SUBR_DEFS: [&crate::core::object::SubrFn; {subr_len}] = [",).unwrap();
-    for (subr_name, _, _) in &all_defun {
+    for (subr_name, _, _) in all_defun.iter().chain(&shared_defun) {
         writeln!(f, "    &{subr_name},",).unwrap();
     }
     // End SUBR_DEFS
     writeln!(f, "];\n").unwrap();
 
-    let defun_start = symbol_len - subr_len;
+    // The docstrings of the SubrFn, in the same order. They are kept out of
+    // the SubrFn so that every function doesn't pay for them.
+    writeln!(f, "static SUBR_DOCS: [&str; {subr_len}] = [",).unwrap();
+    for (subr_name, _, _) in all_defun.iter().chain(&shared_defun) {
+        let doc_name = subr_name.replace("::__subr_", "::__doc_");
+        writeln!(f, "    {doc_name},",).unwrap();
+    }
+    // End SUBR_DOCS
+    writeln!(f, "];\n").unwrap();
+    writeln!(
+        f,
+        "
+/// The docstring of a builtin function, or `None` if `subr` is not a defun.
+pub(crate) fn subr_doc(subr: &crate::core::object::SubrFn) -> Option<&'static str> {{
+    let idx = SUBR_DEFS.iter().position(|x| std::ptr::eq(*x, subr))?;
+    Some(SUBR_DOCS[idx])
+}}"
+    )
+    .unwrap();
+
+    let defun_start = symbol_len - all_defun.len();
     writeln!(
         f,
         "
 pub(crate) fn init_symbols() {{
     for (sym, func) in BUILTIN_SYMBOLS[{defun_start}..].iter().zip(SUBR_DEFS.iter()) {{
         unsafe {{ sym.set_func((*func).into()).unwrap(); }}
-    }}
-}}
-"
+    }}"
     )
     .unwrap();
+    for (i, (_, _, lisp_name)) in shared_defun.iter().enumerate() {
+        let idx = defvar_index(lisp_name);
+        let subr = all_defun.len() + i;
+        #[rustfmt::skip]
+        writeln!(f, "    unsafe {{ BUILTIN_SYMBOLS[{idx}].set_func(SUBR_DEFS[{subr}].into()).unwrap(); }}").unwrap();
+    }
+    writeln!(f, "}}").unwrap();
     // End mod sym
     writeln!(f, "}}").unwrap();
 
     writeln!(
         f,
         "
-use std::sync::LazyLock;
-pub(crate) static INTERNED_SYMBOLS: LazyLock<std::sync::Mutex<SymbolMap>> = LazyLock::new(|| std::sync::Mutex::new({{
-        let size: usize = {symbol_len};
-        let mut map = SymbolMapCore::with_capacity(size);
-        for sym in &sym::BUILTIN_SYMBOLS {{
-            map.pre_init(unsafe {{Symbol::from_ptr(sym as *const _)}});
-        }}
-        SymbolMap {{
-            map,
-            block: Block::new_global(),
-        }}
-    }}));
+pub(crate) static INTERNED_SYMBOLS: LazyLock<std::sync::Mutex<SymbolMap>> =
+    LazyLock::new(|| std::sync::Mutex::new(SymbolMap {{ block: Block::new_global() }}));
 "
     )
     .unwrap();
@@ -0,0 +1,159 @@
+//! Compile the AST into a program for the backtracking matcher.
+use crate::SyntaxClass;
+use crate::exec::Folding;
+use crate::parse::{Assertion, Class, Node};
+
+#[derive(Debug, Clone)]
+pub(crate) enum Inst {
+    Char(char),
+    Any,
+    Class(Box<Class>),
+    Syntax(SyntaxClass, bool),
+    Category(char, bool),
+    Assert(Assertion),
+    /// Try the first branch, backtracking to the second if it fails
+    Split(usize, usize),
+    Jmp(usize),
+    /// Record the current position in a capture slot
+    Save(usize),
+    Backref(usize),
+    /// Record the current position in a loop mark
+    SetMark(usize),
+    /// Fail if no text was consumed since the loop mark was set. This stops
+    /// loops whose body can match the empty string from spinning forever.
+    CheckProgress(usize),
+    Match,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Program {
+    pub(crate) insts: Vec<Inst>,
+    /// The number of capture groups, not including the whole match
+    pub(crate) groups: usize,
+    pub(crate) marks: usize,
+}
+
+impl Program {
+    pub(crate) fn slots(&self) -> usize {
+        (self.groups + 1) * 2 + self.marks
+    }
+
+    /// The literal character that every match must start with, if any.
+    pub(crate) fn first_char(&self) -> Option<char> {
+        for inst in &self.insts {
+            match inst {
+                Inst::Save(_) => {}
+                Inst::Char(c) => return Some(*c),
+                _ => return None,
+            }
+        }
+        None
+    }
+}
+
+pub(crate) struct Compiler {
+    insts: Vec<Inst>,
+    marks: usize,
+    groups: usize,
+    fold: Folding,
+}
+
+impl Compiler {
+    pub(crate) fn compile(node: &Node, groups: usize, fold: Folding) -> Program {
+        let mut compiler = Self { insts: Vec::new(), marks: 0, groups, fold };
+        compiler.insts.push(Inst::Save(0));
+        compiler.node(node);
+        compiler.insts.push(Inst::Save(1));
+        compiler.insts.push(Inst::Match);
+        Program { insts: compiler.insts, groups, marks: compiler.marks }
+    }
+
+    fn pc(&self) -> usize {
+        self.insts.len()
+    }
+
+    fn node(&mut self, node: &Node) {
+        match node {
+            Node::Empty => {}
+            Node::Char(c) => self.insts.push(Inst::Char(self.fold.pattern_char(*c))),
+            Node::Any => self.insts.push(Inst::Any),
+            Node::Class(class) => self.insts.push(Inst::Class(Box::new(class.clone()))),
+            Node::Assert(assertion) => self.insts.push(Inst::Assert(*assertion)),
+            Node::Syntax(class, negated) => self.insts.push(Inst::Syntax(*class, *negated)),
+            Node::Category(cat, negated) => self.insts.push(Inst::Category(*cat, *negated)),
+            Node::Backref(idx) => self.insts.push(Inst::Backref(*idx)),
+            Node::Group(Some(idx), inner) => {
+                self.insts.push(Inst::Save(idx * 2));
+                self.node(inner);
+                self.insts.push(Inst::Save(idx * 2 + 1));
+            }
+            Node::Group(None, inner) => self.node(inner),
+            Node::Concat(nodes) => nodes.iter().for_each(|x| self.node(x)),
+            Node::Alt(nodes) => {
+                let mut jumps = Vec::new();
+                let (last, rest) = nodes.split_last().unwrap();
+                for node in rest {
+                    let split = self.pc();
+                    self.insts.push(Inst::Split(split + 1, 0));
+                    self.node(node);
+                    jumps.push(self.pc());
+                    self.insts.push(Inst::Jmp(0));
+                    let next = self.pc();
+                    self.insts[split] = Inst::Split(split + 1, next);
+                }
+                self.node(last);
+                let end = self.pc();
+                for jump in jumps {
+                    self.insts[jump] = Inst::Jmp(end);
+                }
+            }
+            Node::Repeat { node, min, max, greedy } => {
+                for _ in 0..*min {
+                    self.node(node);
+                }
+                match max {
+                    None => self.star(node, *greedy),
+                    Some(max) => self.optional(node, max - min, *greedy),
+                }
+            }
+        }
+    }
+
+    fn split(&self, body: usize, next: usize, greedy: bool) -> Inst {
+        if greedy { Inst::Split(body, next) } else { Inst::Split(next, body) }
+    }
+
+    fn star(&mut self, node: &Node, greedy: bool) {
+        let guard = node.can_be_empty().then(|| {
+            self.marks += 1;
+            (self.groups + 1) * 2 + self.marks - 1
+        });
+        let start = self.pc();
+        self.insts.push(Inst::Split(0, 0));
+        if let Some(mark) = guard {
+            self.insts.push(Inst::SetMark(mark));
+        }
+        self.node(node);
+        if let Some(mark) = guard {
+            self.insts.push(Inst::CheckProgress(mark));
+        }
+        self.insts.push(Inst::Jmp(start));
+        let end = self.pc();
+        self.insts[start] = self.split(start + 1, end, greedy);
+    }
+
+    /// Compile `count` nested optional copies of `node`, so that once one copy
+    /// fails to match the rest are skipped.
+    fn optional(&mut self, node: &Node, count: u32, greedy: bool) {
+        let mut splits = Vec::new();
+        for _ in 0..count {
+            splits.push(self.pc());
+            self.insts.push(Inst::Split(0, 0));
+            self.node(node);
+        }
+        let end = self.pc();
+        for split in splits {
+            self.insts[split] = self.split(split + 1, end, greedy);
+        }
+    }
+}
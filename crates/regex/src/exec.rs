@@ -0,0 +1,341 @@
+//! A backtracking matcher for compiled programs.
+use crate::compile::{Inst, Program};
+use crate::parse::{Assertion, Class, ClassItem, NamedClass};
+use crate::{Error, SyntaxClass, Text, base_char};
+
+/// The maximum number of entries on the backtrack stack before giving up.
+const MAX_BACKTRACK: usize = 1 << 22;
+
+/// The number of instructions a search may run before giving up, besides
+/// [`STEPS_PER_BYTE`] for each byte of text it can look at. This bounds the
+/// time of patterns that backtrack exponentially without a deep stack.
+const MIN_STEPS: usize = 1 << 24;
+const STEPS_PER_BYTE: usize = 64;
+
+enum Frame {
+    /// Resume at `pc` with the text at `pos`
+    Branch { pc: usize, pos: usize },
+    /// Restore a slot to its previous value
+    Restore { slot: usize, old: Option<usize> },
+}
+
+pub(crate) struct Matcher<'a, T: Text + ?Sized> {
+    pub(crate) prog: &'a Program,
+    pub(crate) text: &'a T,
+    /// Text may be examined but not consumed past this position
+    pub(crate) limit: usize,
+    pub(crate) point: Option<usize>,
+    pub(crate) fold: Folding,
+    pub(crate) syntax: fn(char) -> SyntaxClass,
+    pub(crate) slots: Vec<Option<usize>>,
+    stack: Vec<Frame>,
+    /// Instructions left to run, shared by every `run` of this matcher
+    steps: usize,
+}
+
+/// The canonical case of `c`, which is its lowercase form unless that is
+/// more than one character.
+pub(crate) fn fold(c: char) -> char {
+    let mut lower = c.to_lowercase();
+    match (lower.next(), lower.next()) {
+        (Some(l), None) => l,
+        _ => c,
+    }
+}
+
+/// Which differences between characters are ignored when matching.
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct Folding {
+    /// Maps characters to their canonical case, if case is ignored
+    pub(crate) case: Option<fn(char) -> char>,
+    /// Whether a letter in the pattern matches its accented forms
+    pub(crate) chars: bool,
+}
+
+impl Folding {
+    /// The form of a pattern character that is compared against the text.
+    /// Patterns are converted when they are compiled.
+    pub(crate) fn pattern_char(self, c: char) -> char {
+        self.case.map_or(c, |canon| canon(c))
+    }
+
+    /// True if the character `c` matches `expect`, which has already been
+    /// converted with [`Self::pattern_char`].
+    pub(crate) fn matches(self, expect: char, c: char) -> bool {
+        c == expect
+            || self.pattern_char(c) == expect
+            || (self.chars && self.pattern_char(base_char(c)) == expect)
+    }
+}
+
+impl<'a, T: Text + ?Sized> Matcher<'a, T> {
+    pub(crate) fn new(
+        prog: &'a Program,
+        text: &'a T,
+        limit: usize,
+        point: Option<usize>,
+        fold: Folding,
+        syntax: fn(char) -> SyntaxClass,
+    ) -> Self {
+        Self {
+            prog,
+            text,
+            limit,
+            point,
+            fold,
+            syntax,
+            slots: vec![None; prog.slots()],
+            stack: Vec::new(),
+            steps: MIN_STEPS.saturating_add(limit.saturating_mul(STEPS_PER_BYTE)),
+        }
+    }
+
+    fn next_char(&self, pos: usize) -> Option<char> {
+        if pos < self.limit { self.text.char_at(pos) } else { None }
+    }
+
+    fn set_slot(&mut self, slot: usize, pos: usize) {
+        let old = self.slots[slot].replace(pos);
+        self.stack.push(Frame::Restore { slot, old });
+    }
+
+    /// Try to match the program starting exactly at `start`. On success the
+    /// capture positions are left in `slots`.
+    pub(crate) fn run(&mut self, start: usize) -> Result<bool, Error> {
+        self.slots.iter_mut().for_each(|x| *x = None);
+        self.stack.clear();
+        let mut pc = 0;
+        let mut pos = start;
+        loop {
+            self.steps = self.steps.checked_sub(1).ok_or(Error::TooSlow)?;
+            let matched = match &self.prog.insts[pc] {
+                Inst::Match => return Ok(true),
+                Inst::Char(expect) => match self.next_char(pos) {
+                    Some(c) if self.fold.matches(*expect, c) => {
+                        pos += c.len_utf8();
+                        true
+                    }
+                    _ => false,
+                },
+                Inst::Any => match self.next_char(pos) {
+                    Some(c) if c != '\n' => {
+                        pos += c.len_utf8();
+                        true
+                    }
+                    _ => false,
+                },
+                Inst::Class(class) => match self.next_char(pos) {
+                    Some(c) if self.class_matches(class, c) => {
+                        pos += c.len_utf8();
+                        true
+                    }
+                    _ => false,
+                },
+                Inst::Syntax(class, negated) => match self.next_char(pos) {
+                    Some(c) if ((self.syntax)(c) == *class) != *negated => {
+                        pos += c.len_utf8();
+                        true
+                    }
+                    _ => false,
+                },
+                Inst::Category(cat, negated) => match self.next_char(pos) {
+                    Some(c) if category_matches(*cat, c) != *negated => {
+                        pos += c.len_utf8();
+                        true
+                    }
+                    _ => false,
+                },
+                Inst::Assert(assertion) => self.assert(*assertion, pos),
+                Inst::Split(first, second) => {
+                    if self.stack.len() >= MAX_BACKTRACK {
+                        return Err(Error::StackOverflow);
+                    }
+                    self.stack.push(Frame::Branch { pc: *second, pos });
+                    pc = *first;
+                    continue;
+                }
+                Inst::Jmp(target) => {
+                    pc = *target;
+                    continue;
+                }
+                Inst::Save(slot) | Inst::SetMark(slot) => {
+                    self.set_slot(*slot, pos);
+                    true
+                }
+                Inst::CheckProgress(slot) => self.slots[*slot] != Some(pos),
+                Inst::Backref(idx) => match self.backref(*idx, pos) {
+                    Some(end) => {
+                        pos = end;
+                        true
+                    }
+                    None => false,
+                },
+            };
+            if matched {
+                pc += 1;
+                continue;
+            }
+            // backtrack to the last branch point
+            loop {
+                match self.stack.pop() {
+                    None => return Ok(false),
+                    Some(Frame::Restore { slot, old }) => self.slots[slot] = old,
+                    Some(Frame::Branch { pc: next_pc, pos: next_pos }) => {
+                        pc = next_pc;
+                        pos = next_pos;
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Match the text of group `idx` at `pos`, returning the end position.
+    /// A group that did not participate in the match fails.
+    fn backref(&self, idx: usize, mut pos: usize) -> Option<usize> {
+        let start = self.slots[idx * 2]?;
+        let end = self.slots[idx * 2 + 1]?;
+        let mut ref_pos = start;
+        while ref_pos < end {
+            let expect = self.text.char_at(ref_pos)?;
+            let actual = self.next_char(pos)?;
+            if !self.fold.matches(self.fold.pattern_char(expect), actual) {
+                return None;
+            }
+            ref_pos += expect.len_utf8();
+            pos += actual.len_utf8();
+        }
+        Some(pos)
+    }
+
+    fn is_word(&self, c: Option<char>) -> bool {
+        c.is_some_and(|c| (self.syntax)(c) == SyntaxClass::Word)
+    }
+
+    fn is_symbol(&self, c: Option<char>) -> bool {
+        c.is_some_and(|c| matches!((self.syntax)(c), SyntaxClass::Word | SyntaxClass::Symbol))
+    }
+
+    fn assert(&self, assertion: Assertion, pos: usize) -> bool {
+        let before = self.text.char_before(pos);
+        let after = self.text.char_at(pos);
+        match assertion {
+            Assertion::LineStart => matches!(before, None | Some('\n')),
+            Assertion::LineEnd => matches!(after, None | Some('\n')),
+            Assertion::TextStart => pos == 0,
+            Assertion::TextEnd => pos == self.text.len(),
+            Assertion::Point => self.point == Some(pos),
+            Assertion::WordBoundary => {
+                before.is_none() || after.is_none() || self.is_word(before) != self.is_word(after)
+            }
+            Assertion::NotWordBoundary => {
+                before.is_some() && after.is_some() && self.is_word(before) == self.is_word(after)
+            }
+            Assertion::WordStart => self.is_word(after) && !self.is_word(before),
+            Assertion::WordEnd => self.is_word(before) && !self.is_word(after),
+            Assertion::SymbolStart => self.is_symbol(after) && !self.is_symbol(before),
+            Assertion::SymbolEnd => self.is_symbol(before) && !self.is_symbol(after),
+        }
+    }
+
+    fn class_matches(&self, class: &Class, c: char) -> bool {
+        let base = base_char(c);
+        let found = self.class_contains(class, c)
+            || (self.fold.chars && base != c && self.class_contains(class, base));
+        found != class.negated
+    }
+
+    /// True if `c` is in `class`, in any case if case is ignored.
+    fn class_contains(&self, class: &Class, c: char) -> bool {
+        let matches = |c: char| class.items.iter().any(|item| self.item_matches(item, c));
+        matches(c)
+            || self.fold.case.is_some_and(|canon| {
+                let lower = canon(c);
+                let upper = c.to_uppercase().next().unwrap_or(c);
+                (lower != c && matches(lower)) || (upper != c && matches(upper))
+            })
+    }
+
+    fn item_matches(&self, item: &ClassItem, c: char) -> bool {
+        match item {
+            ClassItem::Range(start, end) => (*start..=*end).contains(&c),
+            ClassItem::Named(named) => match named {
+                NamedClass::Alpha => c.is_alphabetic(),
+                NamedClass::Alnum => c.is_alphanumeric(),
+                NamedClass::Digit => c.is_ascii_digit(),
+                NamedClass::XDigit => c.is_ascii_hexdigit(),
+                NamedClass::Space => (self.syntax)(c) == SyntaxClass::Whitespace,
+                NamedClass::Word => (self.syntax)(c) == SyntaxClass::Word,
+                NamedClass::Punct => {
+                    if c.is_ascii() {
+                        c.is_ascii_punctuation()
+                    } else {
+                        (self.syntax)(c) != SyntaxClass::Word
+                    }
+                }
+                // When case folding, both of these match any cased letter
+                NamedClass::Upper | NamedClass::Lower if self.fold.case.is_some() => {
+                    c.is_uppercase() || c.is_lowercase()
+                }
+                NamedClass::Upper => c.is_uppercase(),
+                NamedClass::Lower => c.is_lowercase(),
+                NamedClass::Blank => {
+                    c == ' '
+                        || c == '\t'
+                        || (!c.is_ascii() && c.is_whitespace() && !is_line_separator(c))
+                }
+                NamedClass::Cntrl => c.is_ascii_control(),
+                NamedClass::Graph => {
+                    if c.is_ascii() {
+                        c.is_ascii_graphic()
+                    } else {
+                        !c.is_whitespace() && !c.is_control()
+                    }
+                }
+                NamedClass::Print => {
+                    if c.is_ascii() {
+                        c == ' ' || c.is_ascii_graphic()
+                    } else {
+                        !c.is_control()
+                    }
+                }
+                NamedClass::Ascii | NamedClass::Unibyte => c.is_ascii(),
+                NamedClass::NonAscii | NamedClass::Multibyte => !c.is_ascii(),
+            },
+        }
+    }
+}
+
+fn is_line_separator(c: char) -> bool {
+    matches!(c, '\u{85}' | '\u{2028}' | '\u{2029}')
+}
+
+/// An approximation of the standard Emacs character categories, based on
+/// Unicode blocks.
+fn category_matches(category: char, c: char) -> bool {
+    let c = c as u32;
+    match category {
+        'a' => (0x20..0x7f).contains(&c),
+        'l' => (0xa0..0x250).contains(&c),
+        'g' => (0x370..0x400).contains(&c),
+        'y' => (0x400..0x530).contains(&c),
+        'b' => (0x600..0x700).contains(&c),
+        'w' => (0x590..0x600).contains(&c),
+        't' => (0xe00..0xe80).contains(&c),
+        'h' => (0xac00..0xd7a4).contains(&c) || (0x1100..0x1200).contains(&c),
+        'k' => (0x30a0..0x3100).contains(&c),
+        'r' => (0x3040..0x30a0).contains(&c),
+        'C' | 'c' => (0x4e00..0xa000).contains(&c) || (0x3400..0x4dc0).contains(&c),
+        'j' => {
+            (0x3040..0x3100).contains(&c)
+                || (0x4e00..0xa000).contains(&c)
+                || (0x3400..0x4dc0).contains(&c)
+        }
+        '|' => (0x2e80..0xa000).contains(&c) || (0xac00..0xd7a4).contains(&c),
+        '^' => (0x300..0x370).contains(&c),
+        '.' => c >= 0x80 && !(0x300..0x370).contains(&c),
+        'L' => !(0x590..0x800).contains(&c),
+        'R' => (0x590..0x800).contains(&c),
+        _ => false,
+    }
+}
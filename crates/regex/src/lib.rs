@@ -0,0 +1,603 @@
+//! A regular expression engine that understands Emacs regexp syntax.
+//!
+//! Emacs regexps differ from the syntax supported by the common Rust crates:
+//! groups and alternation are written `\(...\)` and `\|`, back references
+//! are supported, and there are assertions that depend on the syntax table,
+//! such as `\_<`. Matching is done with a backtracking matcher over any type
+//! implementing [`Text`], so a gap buffer can be searched in place through
+//! [`GapText`] without copying it into a contiguous string.
+//!
+//! Case folding converts the pattern to its canonical case when it is
+//! compiled, so only the text has to be converted while matching. Character
+//! folding lets a letter in the pattern match its accented forms, like `e`
+//! matching `é`, as Emacs does with `char-fold-to-regexp`.
+//!
+//! All positions are byte offsets into the text.
+#![expect(clippy::must_use_candidate)]
+#![expect(clippy::missing_errors_doc)]
+mod compile;
+mod exec;
+mod parse;
+
+use compile::{Compiler, Program};
+use exec::{Folding, Matcher, fold};
+use parse::{Node, Parser};
+use std::ops::Range;
+
+/// Errors from compiling or running a regexp. The messages match the ones
+/// GNU Emacs uses for `invalid-regexp`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Error {
+    Syntax(&'static str),
+    StackOverflow,
+    /// Matching ran out of steps, because the pattern backtracks too much
+    TooSlow,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Syntax(msg) => write!(f, "{msg}"),
+            Error::StackOverflow => write!(f, "Stack overflow in regexp matcher"),
+            Error::TooSlow => write!(f, "Regexp matcher took too long"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// The syntax class of a character, as used by `\w`, `\sC` and the word and
+/// symbol boundary assertions.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum SyntaxClass {
+    Whitespace,
+    Punctuation,
+    Word,
+    Symbol,
+    Open,
+    Close,
+    ExprPrefix,
+    String,
+    Paired,
+    Escape,
+    CharQuote,
+    CommentStart,
+    CommentEnd,
+    GenericComment,
+    GenericString,
+}
+
+impl SyntaxClass {
+    /// Convert a syntax code character (as in `\s-`) to a syntax class.
+    pub fn from_code(code: char) -> Option<Self> {
+        Some(match code {
+            ' ' | '-' => Self::Whitespace,
+            '.' => Self::Punctuation,
+            'w' => Self::Word,
+            '_' => Self::Symbol,
+            '(' => Self::Open,
+            ')' => Self::Close,
+            '\'' => Self::ExprPrefix,
+            '"' => Self::String,
+            '$' => Self::Paired,
+            '\\' => Self::Escape,
+            '/' => Self::CharQuote,
+            '<' => Self::CommentStart,
+            '>' => Self::CommentEnd,
+            '!' => Self::GenericComment,
+            '|' => Self::GenericString,
+            _ => return None,
+        })
+    }
+}
+
+/// The syntax class of `c` in the standard syntax table.
+pub fn standard_syntax(c: char) -> SyntaxClass {
+    match c {
+        ' ' | '\t' | '\n' | '\r' | '\x0c' => SyntaxClass::Whitespace,
+        '_' | '-' | '+' | '*' | '/' | '&' | '|' | '<' | '>' | '=' => SyntaxClass::Symbol,
+        '(' | '[' | '{' => SyntaxClass::Open,
+        ')' | ']' | '}' => SyntaxClass::Close,
+        '"' => SyntaxClass::String,
+        '\\' => SyntaxClass::Escape,
+        c if c.is_whitespace() => SyntaxClass::Whitespace,
+        c if c.is_ascii() && !c.is_ascii_alphanumeric() => SyntaxClass::Punctuation,
+        _ => SyntaxClass::Word,
+    }
+}
+
+/// The letters that the accented Latin-1 and Latin Extended-A letters are
+/// based on, starting from `À`. A `.` marks a letter with no base letter.
+const ACCENTED_BASES: &[u8; 192] = b"AAAAAA.CEEEEIIII.NOOOOO..UUUUY..aaaaaa.ceeeeiiii.nooooo..uuuuy.y\
+    AaAaAaCcCcCcCcDd..EeEeEeEeEeGgGgGgGgHh..IiIiIiIiI...JjKk.LlLlLl.\
+    ...NnNnNn...OoOoOo..RrRrRrSsSsSsSsTtTt..UuUuUuUuUuUuWwYyYZzZzZz.";
+
+/// The letter that the accented letter `c` is based on, like `e` for `é`, or
+/// `c` itself if it is not an accented Latin letter.
+pub fn base_char(c: char) -> char {
+    let base = (c as usize).checked_sub(0xC0).and_then(|idx| ACCENTED_BASES.get(idx));
+    match base {
+        Some(b'.') | None => c,
+        Some(base) => char::from(*base),
+    }
+}
+
+/// The accented letters that are based on `c`, as in [`base_char`].
+pub fn accented_chars(c: char) -> impl Iterator<Item = char> {
+    ACCENTED_BASES
+        .iter()
+        .enumerate()
+        .filter(move |(_, base)| c != '.' && char::from(**base) == c)
+        .filter_map(|(idx, _)| char::from_u32(0xC0 + idx as u32))
+}
+
+/// Text that can be searched. Positions are byte offsets and must lie on
+/// character boundaries.
+pub trait Text {
+    /// The length of the text in bytes.
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The character starting at `pos`, or `None` at the end of the text.
+    fn char_at(&self, pos: usize) -> Option<char>;
+
+    /// The character ending at `pos`, or `None` at the start of the text.
+    fn char_before(&self, pos: usize) -> Option<char>;
+}
+
+impl Text for str {
+    fn len(&self) -> usize {
+        str::len(self)
+    }
+
+    fn char_at(&self, pos: usize) -> Option<char> {
+        self.get(pos..)?.chars().next()
+    }
+
+    fn char_before(&self, pos: usize) -> Option<char> {
+        self.get(..pos)?.chars().next_back()
+    }
+}
+
+/// Text split into two halves, such as the two sides of the gap in a gap
+/// buffer. It is searched as if the halves were contiguous.
+#[derive(Debug, Copy, Clone)]
+pub struct GapText<'a> {
+    first: &'a str,
+    second: &'a str,
+}
+
+impl<'a> GapText<'a> {
+    pub fn new(first: &'a str, second: &'a str) -> Self {
+        Self { first, second }
+    }
+}
+
+impl Text for GapText<'_> {
+    fn len(&self) -> usize {
+        self.first.len() + self.second.len()
+    }
+
+    fn char_at(&self, pos: usize) -> Option<char> {
+        match pos.checked_sub(self.first.len()) {
+            Some(pos) => self.second.char_at(pos),
+            None => self.first.char_at(pos),
+        }
+    }
+
+    fn char_before(&self, pos: usize) -> Option<char> {
+        match pos.checked_sub(self.first.len()) {
+            Some(0) => self.first.char_before(self.first.len()),
+            Some(pos) => self.second.char_before(pos),
+            None => self.first.char_before(pos),
+        }
+    }
+}
+
+/// Configures and compiles a [`Regex`].
+#[derive(Debug, Clone)]
+pub struct RegexBuilder<'a> {
+    pattern: &'a str,
+    case_fold: bool,
+    case_table: fn(char) -> char,
+    char_fold: bool,
+    literal: bool,
+    syntax: fn(char) -> SyntaxClass,
+}
+
+impl<'a> RegexBuilder<'a> {
+    pub fn new(pattern: &'a str) -> Self {
+        Self {
+            pattern,
+            case_fold: false,
+            case_table: fold,
+            char_fold: false,
+            literal: false,
+            syntax: standard_syntax,
+        }
+    }
+
+    /// Treat the pattern as a literal string with no special characters, as
+    /// `search-forward` does.
+    #[must_use]
+    pub fn literal(mut self, literal: bool) -> Self {
+        self.literal = literal;
+        self
+    }
+
+    /// Ignore case differences when matching, like `case-fold-search`.
+    #[must_use]
+    pub fn case_fold(mut self, case_fold: bool) -> Self {
+        self.case_fold = case_fold;
+        self
+    }
+
+    /// Use `canon` to map characters to their canonical case when case is
+    /// ignored, like the case table of a buffer. Characters are lowercased by
+    /// default.
+    #[must_use]
+    pub fn case_table(mut self, canon: fn(char) -> char) -> Self {
+        self.case_table = canon;
+        self
+    }
+
+    /// Let a letter in the pattern match its accented forms, like `e` matching
+    /// `é`. An accented letter in the pattern still only matches itself.
+    #[must_use]
+    pub fn char_fold(mut self, char_fold: bool) -> Self {
+        self.char_fold = char_fold;
+        self
+    }
+
+    /// Use `syntax` to determine the syntax class of characters instead of
+    /// the standard syntax table.
+    #[must_use]
+    pub fn syntax_table(mut self, syntax: fn(char) -> SyntaxClass) -> Self {
+        self.syntax = syntax;
+        self
+    }
+
+    pub fn build(&self) -> Result<Regex, Error> {
+        let fold = Folding {
+            case: self.case_fold.then_some(self.case_table),
+            chars: self.char_fold,
+        };
+        let prog = if self.literal {
+            let node = Node::Concat(self.pattern.chars().map(Node::Char).collect());
+            Compiler::compile(&node, 0, fold)
+        } else {
+            let mut parser = Parser::new(self.pattern);
+            let node = parser.parse()?;
+            Compiler::compile(&node, parser.max_group, fold)
+        };
+        Ok(Regex { prog, fold, syntax: self.syntax })
+    }
+}
+
+/// A compiled Emacs regexp.
+#[derive(Debug, Clone)]
+pub struct Regex {
+    prog: Program,
+    fold: Folding,
+    syntax: fn(char) -> SyntaxClass,
+}
+
+/// The text to search, along with the bounds of the search.
+#[derive(Debug, Clone)]
+pub struct Input<'t, T: Text + ?Sized> {
+    text: &'t T,
+    range: Range<usize>,
+    point: Option<usize>,
+}
+
+impl<'t, T: Text + ?Sized> Input<'t, T> {
+    pub fn new(text: &'t T) -> Self {
+        Self { text, range: 0..text.len(), point: None }
+    }
+
+    /// Limit the search to `range`. Text outside the range is still visible to
+    /// assertions such as `\b`, but is never part of a match.
+    #[must_use]
+    pub fn range(mut self, range: Range<usize>) -> Self {
+        self.range = range;
+        self
+    }
+
+    /// Set the position matched by `\=`.
+    #[must_use]
+    pub fn point(mut self, point: usize) -> Self {
+        self.point = Some(point);
+        self
+    }
+}
+
+/// The positions of a match and its groups.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Captures {
+    slots: Vec<Option<usize>>,
+}
+
+impl Captures {
+    /// The range matched by group `idx`, where group 0 is the whole match.
+    /// Returns `None` if the group did not participate in the match.
+    pub fn get(&self, idx: usize) -> Option<Range<usize>> {
+        let start = (*self.slots.get(idx * 2)?)?;
+        let end = (*self.slots.get(idx * 2 + 1)?)?;
+        Some(start..end)
+    }
+
+    /// The number of groups, including the whole match.
+    pub fn len(&self) -> usize {
+        self.slots.len() / 2
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Option<Range<usize>>> + '_ {
+        (0..self.len()).map(|i| self.get(i))
+    }
+}
+
+impl Regex {
+    pub fn new(pattern: &str) -> Result<Self, Error> {
+        RegexBuilder::new(pattern).build()
+    }
+
+    /// The number of groups, including the whole match.
+    pub fn captures_len(&self) -> usize {
+        self.prog.groups + 1
+    }
+
+    fn matcher<'a, T: Text + ?Sized>(&'a self, input: &Input<'a, T>) -> Matcher<'a, T> {
+        Matcher::new(
+            &self.prog,
+            input.text,
+            input.range.end,
+            input.point,
+            self.fold,
+            self.syntax,
+        )
+    }
+
+    fn captures(&self, matcher: &Matcher<'_, impl Text + ?Sized>) -> Captures {
+        let len = self.captures_len() * 2;
+        Captures { slots: matcher.slots[..len].to_vec() }
+    }
+
+    /// Match only at the start of the input range.
+    pub fn looking_at<T: Text + ?Sized>(
+        &self,
+        input: &Input<'_, T>,
+    ) -> Result<Option<Captures>, Error> {
+        let mut matcher = self.matcher(input);
+        Ok(matcher.run(input.range.start)?.then(|| self.captures(&matcher)))
+    }
+
+    /// Find the first match that starts in the input range.
+    pub fn search_forward<T: Text + ?Sized>(
+        &self,
+        input: &Input<'_, T>,
+    ) -> Result<Option<Captures>, Error> {
+        let first = self.prog.first_char();
+        let mut matcher = self.matcher(input);
+        let mut pos = input.range.start;
+        loop {
+            let next = input.text.char_at(pos);
+            let candidate = match (first, next) {
+                (Some(first), Some(c)) => self.fold.matches(first, c),
+                (Some(_), None) => false,
+                (None, _) => true,
+            };
+            if candidate && matcher.run(pos)? {
+                return Ok(Some(self.captures(&matcher)));
+            }
+            match next {
+                Some(c) if pos < input.range.end => pos += c.len_utf8(),
+                _ => return Ok(None),
+            }
+        }
+    }
+
+    /// Find the match that starts closest to the end of the input range,
+    /// searching backward. The match may not extend past the end of the range.
+    pub fn search_backward<T: Text + ?Sized>(
+        &self,
+        input: &Input<'_, T>,
+    ) -> Result<Option<Captures>, Error> {
+        let mut matcher = self.matcher(input);
+        let mut pos = input.range.end;
+        loop {
+            if matcher.run(pos)? {
+                return Ok(Some(self.captures(&matcher)));
+            }
+            match input.text.char_before(pos) {
+                Some(c) if pos > input.range.start => pos -= c.len_utf8(),
+                _ => return Ok(None),
+            }
+        }
+    }
+
+    /// Return true if the regexp matches anywhere in `text`.
+    pub fn is_match<T: Text + ?Sized>(&self, text: &T) -> Result<bool, Error> {
+        Ok(self.search_forward(&Input::new(text))?.is_some())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn find(pattern: &str, text: &str) -> Option<Range<usize>> {
+        let re = Regex::new(pattern).unwrap();
+        re.search_forward(&Input::new(text)).unwrap().and_then(|x| x.get(0))
+    }
+
+    fn groups(pattern: &str, text: &str) -> Vec<Option<Range<usize>>> {
+        let re = Regex::new(pattern).unwrap();
+        re.search_forward(&Input::new(text)).unwrap().unwrap().iter().collect()
+    }
+
+    #[test]
+    fn test_literal() {
+        assert_eq!(find("bar", "foo bar baz"), Some(4..7));
+        assert_eq!(find("qux", "foo bar baz"), None);
+        assert_eq!(find("", "foo"), Some(0..0));
+        assert_eq!(find("λ", "aλb"), Some(1..3));
+    }
+
+    #[test]
+    fn test_repetition() {
+        assert_eq!(find("a*", "aaab"), Some(0..3));
+        assert_eq!(find("a+", "baaa"), Some(1..4));
+        assert_eq!(find("ba?", "bb"), Some(0..1));
+        assert_eq!(find("a*?b", "aaab"), Some(0..4));
+        assert_eq!(find("<.*?>", "<a><b>"), Some(0..3));
+        assert_eq!(find("<.*>", "<a><b>"), Some(0..6));
+        assert_eq!(find("a\\{2\\}", "aaa"), Some(0..2));
+        assert_eq!(find("a\\{2,\\}", "aaaa"), Some(0..4));
+        assert_eq!(find("xa\\{,2\\}", "xaaa"), Some(0..3));
+        assert_eq!(find("\\(a*\\)*b", "aab"), Some(0..3));
+        assert_eq!(find("\\(\\)*", "a"), Some(0..0));
+    }
+
+    #[test]
+    fn test_groups() {
+        assert_eq!(groups("\\(foo\\|bar\\)baz", "xbarbaz"), vec![Some(1..7), Some(1..4)]);
+        assert_eq!(groups("\\(a\\)\\|b", "b"), vec![Some(0..1), None]);
+        assert_eq!(
+            groups("\\(?2:a\\)\\(b\\)", "ab"),
+            vec![Some(0..2), None, Some(0..1), Some(1..2)]
+        );
+        assert_eq!(groups("\\(?:a\\)\\(b\\)", "ab"), vec![Some(0..2), Some(1..2)]);
+        assert_eq!(find("\\(ab\\)\\1", "ababab"), Some(0..4));
+        assert_eq!(find("\\(a\\|b\\)\\1", "abba"), Some(1..3));
+    }
+
+    #[test]
+    fn test_classes() {
+        assert_eq!(find("[0-9]+", "abc123"), Some(3..6));
+        assert_eq!(find("[^a-c]", "abcd"), Some(3..4));
+        assert_eq!(find("[[:space:]]", "ab c"), Some(2..3));
+        assert_eq!(find("[[:alpha:]_]+", "1foo_bar2"), Some(1..8));
+        assert_eq!(find("[]]", "a]"), Some(1..2));
+        assert_eq!(find("\\w+", "  hello world"), Some(2..7));
+        assert_eq!(find("\\W", "ab-c"), Some(2..3));
+        assert_eq!(find("\\s-+", "a  \tb"), Some(1..4));
+        assert_eq!(find("\\s_", "a-b"), Some(1..2));
+        assert_eq!(find(".", "\na"), Some(1..2));
+    }
+
+    #[test]
+    fn test_assertions() {
+        assert_eq!(find("^b", "a\nb"), Some(2..3));
+        assert_eq!(find("a$", "a\nb"), Some(0..1));
+        assert_eq!(find("\\`a", "ba"), None);
+        assert_eq!(find("a\\'", "aba"), Some(2..3));
+        assert_eq!(find("\\bfoo\\b", "afoo foo"), Some(5..8));
+        assert_eq!(find("\\Boo", "foo"), Some(1..3));
+        assert_eq!(find("\\<bar\\>", "foobar bar"), Some(7..10));
+        assert_eq!(find("\\_<foo\\_>", "foo-bar foo"), Some(8..11));
+        assert_eq!(find("\\_<foo-bar\\_>", "foo-bar"), Some(0..7));
+    }
+
+    #[test]
+    fn test_case_fold() {
+        let re = RegexBuilder::new("hello").case_fold(true).build().unwrap();
+        assert!(re.is_match("HeLLo").unwrap());
+        let re = RegexBuilder::new("[a-z]+").case_fold(true).build().unwrap();
+        let caps = re.search_forward(&Input::new("123ABC")).unwrap().unwrap();
+        assert_eq!(caps.get(0), Some(3..6));
+        let re = Regex::new("hello").unwrap();
+        assert!(!re.is_match("HELLO").unwrap());
+    }
+
+    #[test]
+    fn test_case_table() {
+        // A case table where only `a` and `A` are the same letter
+        let canon = |c| if c == 'A' { 'a' } else { c };
+        let re = RegexBuilder::new("Ab").case_fold(true).case_table(canon).build().unwrap();
+        assert!(re.is_match("ab").unwrap());
+        assert!(!re.is_match("aB").unwrap());
+    }
+
+    #[test]
+    fn test_char_fold() {
+        let re = RegexBuilder::new("cafe").char_fold(true).build().unwrap();
+        assert!(re.is_match("café").unwrap());
+        assert!(!Regex::new("cafe").unwrap().is_match("café").unwrap());
+        let re = RegexBuilder::new("café").char_fold(true).build().unwrap();
+        assert!(re.is_match("café").unwrap());
+        assert!(!re.is_match("cafe").unwrap());
+        let re = RegexBuilder::new("[a-z]+").char_fold(true).case_fold(true).build().unwrap();
+        let caps = re.search_forward(&Input::new("1ÀÉÎ2")).unwrap().unwrap();
+        assert_eq!(caps.get(0), Some(1..7));
+        let re = RegexBuilder::new("e").char_fold(true).case_fold(true).build().unwrap();
+        let caps = re.search_forward(&Input::new("xÉ")).unwrap().unwrap();
+        assert_eq!(caps.get(0), Some(1..3));
+        assert_eq!(base_char('ř'), 'r');
+        assert_eq!(base_char('ß'), 'ß');
+        assert_eq!(accented_chars('y').collect::<String>(), "ýÿŷ");
+    }
+
+    #[test]
+    fn test_literal_builder() {
+        let re = RegexBuilder::new("a.b\\(").literal(true).build().unwrap();
+        assert!(re.is_match("xa.b\\(").unwrap());
+        assert!(!re.is_match("axb\\(").unwrap());
+        assert_eq!(re.captures_len(), 1);
+        let re = RegexBuilder::new("Foo").literal(true).case_fold(true).build().unwrap();
+        let caps = re.search_backward(&Input::new("foo FOO")).unwrap().unwrap();
+        assert_eq!(caps.get(0), Some(4..7));
+    }
+
+    #[test]
+    fn test_gap_text() {
+        let text = GapText::new("foo b", "ar baz");
+        let re = Regex::new("bar").unwrap();
+        let caps = re.search_forward(&Input::new(&text)).unwrap().unwrap();
+        assert_eq!(caps.get(0), Some(4..7));
+        let re = Regex::new("\\bb").unwrap();
+        let caps = re.search_backward(&Input::new(&text)).unwrap().unwrap();
+        assert_eq!(caps.get(0), Some(8..9));
+        assert_eq!(text.char_before(5), Some('b'));
+        assert_eq!(text.char_at(5), Some('a'));
+    }
+
+    #[test]
+    fn test_search_bounds() {
+        let re = Regex::new("a+").unwrap();
+        let text = "aaa baa";
+        // a backward search can't extend past its starting point
+        let caps = re.search_backward(&Input::new(text).range(0..6)).unwrap().unwrap();
+        assert_eq!(caps.get(0), Some(5..6));
+        let caps = re.search_forward(&Input::new(text).range(1..7)).unwrap().unwrap();
+        assert_eq!(caps.get(0), Some(1..3));
+        let caps = re.looking_at(&Input::new(text).range(4..7)).unwrap();
+        assert!(caps.is_none());
+        let re = Regex::new("\\=b").unwrap();
+        let caps = re.search_forward(&Input::new(text).point(4)).unwrap().unwrap();
+        assert_eq!(caps.get(0), Some(4..5));
+    }
+
+    #[test]
+    fn test_stack_overflow() {
+        let text = "a".repeat(1 << 21);
+        let re = Regex::new("\\(a\\|b\\)*c").unwrap();
+        assert_eq!(re.looking_at(&Input::new(text.as_str())), Err(Error::StackOverflow));
+    }
+
+    #[test]
+    fn test_step_budget() {
+        // Exponential backtracking that never gets deep
+        let text = "a".repeat(60);
+        let re = Regex::new("\\(a\\|aa\\)*c").unwrap();
+        assert_eq!(re.looking_at(&Input::new(text.as_str())), Err(Error::TooSlow));
+        // The budget is shared by every starting position of a search
+        assert_eq!(re.search_forward(&Input::new(text.as_str())), Err(Error::TooSlow));
+    }
+}
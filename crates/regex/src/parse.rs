@@ -0,0 +1,459 @@
+//! Parse Emacs regexp syntax into an AST.
+use crate::{Error, SyntaxClass};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum Assertion {
+    /// `^`
+    LineStart,
+    /// `$`
+    LineEnd,
+    /// `` \` ``
+    TextStart,
+    /// `\'`
+    TextEnd,
+    /// `\=`
+    Point,
+    /// `\b`
+    WordBoundary,
+    /// `\B`
+    NotWordBoundary,
+    /// `\<`
+    WordStart,
+    /// `\>`
+    WordEnd,
+    /// `\_<`
+    SymbolStart,
+    /// `\_>`
+    SymbolEnd,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum NamedClass {
+    Alpha,
+    Alnum,
+    Digit,
+    XDigit,
+    Space,
+    Word,
+    Punct,
+    Upper,
+    Lower,
+    Blank,
+    Cntrl,
+    Graph,
+    Print,
+    Ascii,
+    NonAscii,
+    Multibyte,
+    Unibyte,
+}
+
+impl NamedClass {
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "alpha" => Self::Alpha,
+            "alnum" => Self::Alnum,
+            "digit" => Self::Digit,
+            "xdigit" => Self::XDigit,
+            "space" => Self::Space,
+            "word" => Self::Word,
+            "punct" => Self::Punct,
+            "upper" => Self::Upper,
+            "lower" => Self::Lower,
+            "blank" => Self::Blank,
+            "cntrl" => Self::Cntrl,
+            "graph" => Self::Graph,
+            "print" => Self::Print,
+            "ascii" => Self::Ascii,
+            "nonascii" => Self::NonAscii,
+            "multibyte" => Self::Multibyte,
+            "unibyte" => Self::Unibyte,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum ClassItem {
+    Range(char, char),
+    Named(NamedClass),
+}
+
+/// A bracket expression such as `[a-z_]` or `[^[:space:]]`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Class {
+    pub(crate) negated: bool,
+    pub(crate) items: Vec<ClassItem>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Node {
+    Empty,
+    Char(char),
+    /// `.`, which matches anything but a newline
+    Any,
+    Class(Class),
+    Assert(Assertion),
+    /// `\sC` or `\SC` when negated
+    Syntax(SyntaxClass, bool),
+    /// `\cC` or `\CC` when negated
+    Category(char, bool),
+    /// A capture group, or a shy group when the index is `None`
+    Group(Option<usize>, Box<Node>),
+    Concat(Vec<Node>),
+    Alt(Vec<Node>),
+    Repeat {
+        node: Box<Node>,
+        min: u32,
+        max: Option<u32>,
+        greedy: bool,
+    },
+    Backref(usize),
+}
+
+impl Node {
+    /// Return true if this node can match without consuming any text.
+    pub(crate) fn can_be_empty(&self) -> bool {
+        match self {
+            Node::Empty | Node::Assert(_) | Node::Backref(_) => true,
+            Node::Char(_) | Node::Any | Node::Class(_) | Node::Syntax(..) | Node::Category(..) => {
+                false
+            }
+            Node::Group(_, node) => node.can_be_empty(),
+            Node::Concat(nodes) => nodes.iter().all(Node::can_be_empty),
+            Node::Alt(nodes) => nodes.iter().any(Node::can_be_empty),
+            Node::Repeat { node, min, .. } => *min == 0 || node.can_be_empty(),
+        }
+    }
+}
+
+/// The largest count allowed in `\{n,m\}`, same as `RE_DUP_MAX` in GNU Emacs.
+const DUP_MAX: u32 = (1 << 16) - 1;
+
+pub(crate) struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    /// The highest group number seen so far
+    pub(crate) max_group: usize,
+    /// Groups that have been closed, and can therefore be back referenced
+    closed_groups: Vec<usize>,
+}
+
+impl Parser {
+    pub(crate) fn new(pattern: &str) -> Self {
+        Self {
+            chars: pattern.chars().collect(),
+            pos: 0,
+            max_group: 0,
+            closed_groups: Vec::new(),
+        }
+    }
+
+    pub(crate) fn parse(&mut self) -> Result<Node, Error> {
+        let node = self.parse_alt(0)?;
+        if self.pos < self.chars.len() {
+            // The only way to stop early at the top level is a stray `\)`
+            return Err(Error::Syntax("Unmatched ) or \\)"));
+        }
+        Ok(node)
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn peek_n(&self, n: usize) -> Option<char> {
+        self.chars.get(self.pos + n).copied()
+    }
+
+    fn looking_at(&self, s: &str) -> bool {
+        self.looking_at_idx(self.pos, s)
+    }
+
+    fn eat(&mut self, s: &str) -> bool {
+        if self.looking_at(s) {
+            self.pos += s.chars().count();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn at_alt_end(&self) -> bool {
+        self.pos >= self.chars.len() || self.looking_at("\\|") || self.looking_at("\\)")
+    }
+
+    fn parse_alt(&mut self, depth: usize) -> Result<Node, Error> {
+        let mut alts = vec![self.parse_concat(depth)?];
+        while self.eat("\\|") {
+            alts.push(self.parse_concat(depth)?);
+        }
+        Ok(if alts.len() == 1 { alts.pop().unwrap() } else { Node::Alt(alts) })
+    }
+
+    fn parse_concat(&mut self, depth: usize) -> Result<Node, Error> {
+        let mut items: Vec<Node> = Vec::new();
+        loop {
+            if self.at_alt_end() {
+                if depth == 0 && self.looking_at("\\)") {
+                    return Err(Error::Syntax("Unmatched ) or \\)"));
+                }
+                break;
+            }
+            let chr = self.peek().unwrap();
+            // `^` is only special at the start of an alternative
+            if chr == '^' && items.is_empty() {
+                self.pos += 1;
+                items.push(Node::Assert(Assertion::LineStart));
+                continue;
+            }
+            // postfix operators with nothing to operate on are literal
+            let at_start = matches!(items.as_slice(), [] | [Node::Assert(Assertion::LineStart)]);
+            let atom = match chr {
+                '*' | '+' | '?' if at_start => {
+                    self.pos += 1;
+                    Node::Char(chr)
+                }
+                _ => self.parse_atom()?,
+            };
+            let atom = self.parse_postfix(atom)?;
+            items.push(atom);
+        }
+        Ok(match items.len() {
+            0 => Node::Empty,
+            1 => items.pop().unwrap(),
+            _ => Node::Concat(items),
+        })
+    }
+
+    fn parse_postfix(&mut self, mut atom: Node) -> Result<Node, Error> {
+        loop {
+            let (min, max) = match self.peek() {
+                Some('*') => (0, None),
+                Some('+') => (1, None),
+                Some('?') => (0, Some(1)),
+                Some('\\') if self.peek_n(1) == Some('{') => {
+                    self.pos += 2;
+                    let (min, max) = self.parse_interval()?;
+                    atom = Node::Repeat { node: Box::new(atom), min, max, greedy: true };
+                    continue;
+                }
+                _ => return Ok(atom),
+            };
+            self.pos += 1;
+            let greedy = !self.eat("?");
+            atom = Node::Repeat { node: Box::new(atom), min, max, greedy };
+        }
+    }
+
+    fn parse_number(&mut self) -> Option<u32> {
+        let start = self.pos;
+        let mut num: u32 = 0;
+        while let Some(digit) = self.peek().and_then(|c| c.to_digit(10)) {
+            num = num.saturating_mul(10).saturating_add(digit);
+            self.pos += 1;
+        }
+        (self.pos != start).then_some(num)
+    }
+
+    /// Parse the contents of `\{...\}` after the opening brace.
+    fn parse_interval(&mut self) -> Result<(u32, Option<u32>), Error> {
+        const INVALID: Error = Error::Syntax("Invalid content of \\{\\}");
+        let min = self.parse_number();
+        let max = if self.eat(",") { self.parse_number() } else { Some(min.unwrap_or(0)) };
+        if !self.eat("\\}") {
+            return Err(INVALID);
+        }
+        let min = min.unwrap_or(0);
+        match max {
+            Some(max) if max < min || max > DUP_MAX => Err(INVALID),
+            _ if min > DUP_MAX => Err(INVALID),
+            _ => Ok((min, max)),
+        }
+    }
+
+    fn parse_atom(&mut self) -> Result<Node, Error> {
+        let chr = self.peek().unwrap();
+        self.pos += 1;
+        match chr {
+            '.' => Ok(Node::Any),
+            '[' => self.parse_class(),
+            '$' if self.at_alt_end() => Ok(Node::Assert(Assertion::LineEnd)),
+            '\\' => self.parse_escape(),
+            c => Ok(Node::Char(c)),
+        }
+    }
+
+    fn parse_group(&mut self) -> Result<Node, Error> {
+        let index = if self.eat("?") {
+            if self.eat(":") {
+                None
+            } else {
+                match self.parse_number() {
+                    Some(n) if n > 0 && self.eat(":") => Some(n as usize),
+                    _ => return Err(Error::Syntax("Invalid regular expression")),
+                }
+            }
+        } else {
+            Some(self.max_group + 1)
+        };
+        if let Some(idx) = index {
+            self.max_group = self.max_group.max(idx);
+        }
+        let inner = self.parse_alt(1)?;
+        if !self.eat("\\)") {
+            return Err(Error::Syntax("Unmatched ( or \\("));
+        }
+        if let Some(idx) = index {
+            self.closed_groups.push(idx);
+        }
+        Ok(Node::Group(index, Box::new(inner)))
+    }
+
+    fn parse_escape(&mut self) -> Result<Node, Error> {
+        let Some(chr) = self.peek() else { return Err(Error::Syntax("Trailing backslash")) };
+        self.pos += 1;
+        let node = match chr {
+            '(' => return self.parse_group(),
+            '{' => return Err(Error::Syntax("Invalid preceding regular expression")),
+            '1'..='9' => {
+                let idx = chr.to_digit(10).unwrap() as usize;
+                if !self.closed_groups.contains(&idx) {
+                    return Err(Error::Syntax("Invalid back reference"));
+                }
+                Node::Backref(idx)
+            }
+            'w' => Node::Syntax(SyntaxClass::Word, false),
+            'W' => Node::Syntax(SyntaxClass::Word, true),
+            's' | 'S' => {
+                let code = self.peek().ok_or(Error::Syntax("Invalid syntax designator"))?;
+                self.pos += 1;
+                let class = SyntaxClass::from_code(code)
+                    .ok_or(Error::Syntax("Invalid syntax designator"))?;
+                Node::Syntax(class, chr == 'S')
+            }
+            'c' | 'C' => {
+                let category = self.peek().ok_or(Error::Syntax("Invalid category designator"))?;
+                self.pos += 1;
+                Node::Category(category, chr == 'C')
+            }
+            '`' => Node::Assert(Assertion::TextStart),
+            '\'' => Node::Assert(Assertion::TextEnd),
+            '=' => Node::Assert(Assertion::Point),
+            'b' => Node::Assert(Assertion::WordBoundary),
+            'B' => Node::Assert(Assertion::NotWordBoundary),
+            '<' => Node::Assert(Assertion::WordStart),
+            '>' => Node::Assert(Assertion::WordEnd),
+            '_' if self.eat("<") => Node::Assert(Assertion::SymbolStart),
+            '_' if self.eat(">") => Node::Assert(Assertion::SymbolEnd),
+            '_' => return Err(Error::Syntax("Invalid \\_ sequence")),
+            c => Node::Char(c),
+        };
+        Ok(node)
+    }
+
+    /// Parse a bracket expression after the opening `[`. Backslash is not
+    /// special inside brackets.
+    fn parse_class(&mut self) -> Result<Node, Error> {
+        const UNMATCHED: Error = Error::Syntax("Unmatched [ or [^");
+        let negated = self.eat("^");
+        let mut items = Vec::new();
+        let mut first = true;
+        loop {
+            let Some(chr) = self.peek() else { return Err(UNMATCHED) };
+            if chr == ']' && !first {
+                self.pos += 1;
+                break;
+            }
+            first = false;
+            if self.looking_at("[:") {
+                let start = self.pos + 2;
+                let end = (start..self.chars.len()).find(|&i| self.looking_at_idx(i, ":]"));
+                if let Some(end) = end {
+                    let name: String = self.chars[start..end].iter().collect();
+                    let class = NamedClass::from_name(&name)
+                        .ok_or(Error::Syntax("Invalid character class name"))?;
+                    items.push(ClassItem::Named(class));
+                    self.pos = end + 2;
+                    continue;
+                }
+            }
+            self.pos += 1;
+            if self.peek() == Some('-') && self.peek_n(1).is_some_and(|c| c != ']') {
+                let end = self.peek_n(1).unwrap();
+                self.pos += 2;
+                items.push(ClassItem::Range(chr, end));
+            } else {
+                items.push(ClassItem::Range(chr, chr));
+            }
+        }
+        Ok(Node::Class(Class { negated, items }))
+    }
+
+    fn looking_at_idx(&self, idx: usize, s: &str) -> bool {
+        s.chars().enumerate().all(|(i, c)| self.chars.get(idx + i) == Some(&c))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(pattern: &str) -> Node {
+        Parser::new(pattern).parse().unwrap()
+    }
+
+    #[test]
+    fn test_context_dependent_operators() {
+        assert_eq!(parse("*a"), Node::Concat(vec![Node::Char('*'), Node::Char('a')]));
+        assert_eq!(
+            parse("a^$b"),
+            Node::Concat(vec![Node::Char('a'), Node::Char('^'), Node::Char('$'), Node::Char('b')])
+        );
+        assert_eq!(
+            parse("^*"),
+            Node::Concat(vec![Node::Assert(Assertion::LineStart), Node::Char('*')])
+        );
+        assert_eq!(
+            parse("a$"),
+            Node::Concat(vec![Node::Char('a'), Node::Assert(Assertion::LineEnd)])
+        );
+    }
+
+    #[test]
+    fn test_class() {
+        let Node::Class(class) = parse("[]a-c[:digit:]-]") else { panic!() };
+        assert!(!class.negated);
+        assert_eq!(
+            class.items,
+            vec![
+                ClassItem::Range(']', ']'),
+                ClassItem::Range('a', 'c'),
+                ClassItem::Named(NamedClass::Digit),
+                ClassItem::Range('-', '-'),
+            ]
+        );
+        let Node::Class(class) = parse("[^\\]") else { panic!() };
+        assert!(class.negated);
+        assert_eq!(class.items, vec![ClassItem::Range('\\', '\\')]);
+    }
+
+    #[test]
+    fn test_groups() {
+        let mut parser = Parser::new("\\(a\\)\\(?:b\\)\\(?5:c\\)\\(d\\)");
+        parser.parse().unwrap();
+        assert_eq!(parser.max_group, 6);
+    }
+
+    #[test]
+    fn test_errors() {
+        let err = |pattern| Parser::new(pattern).parse().unwrap_err();
+        assert_eq!(err("[a"), Error::Syntax("Unmatched [ or [^"));
+        assert_eq!(err("\\(a"), Error::Syntax("Unmatched ( or \\("));
+        assert_eq!(err("a\\)"), Error::Syntax("Unmatched ) or \\)"));
+        assert_eq!(err("\\1"), Error::Syntax("Invalid back reference"));
+        assert_eq!(err("a\\{2,1\\}"), Error::Syntax("Invalid content of \\{\\}"));
+        assert_eq!(err("a\\"), Error::Syntax("Trailing backslash"));
+        assert_eq!(err("[[:foo:]]"), Error::Syntax("Invalid character class name"));
+    }
+}
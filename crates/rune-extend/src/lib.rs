@@ -0,0 +1,330 @@
+//! A stable interface for defining lisp functions in Rust crates outside of
+//! Rune.
+//!
+//! The internals of Rune, such as the garbage collector, the tagged object
+//! representation and the `#[defun]` macro, change often and are not safe to
+//! use outside of it. This crate instead passes lisp objects to and from
+//! functions as an owned [`Value`], and objects that have no Rust
+//! representation as an opaque [`Handle`]. A function is described with a
+//! [`Subr`] and added to Rune at compile time with [`register!`].
+//!
+//! ```no_run
+//! use rune_extend::{Result, Subr, Value, arg};
+//!
+//! fn add(args: &[Value]) -> Result<Value> {
+//!     let a: i64 = arg(args, 0)?;
+//!     let b: i64 = arg(args, 1)?;
+//!     Ok(Value::Int(a + b))
+//! }
+//!
+//! rune_extend::register!(ADD: Subr::new("my-add", add).args(2, 0));
+//! ```
+//!
+//! The crate defining the functions has to be linked into Rune. Add it as a
+//! dependency of Rune and reference it from `src/extend.rs` with
+//! `use my_crate as _;`.
+#![expect(clippy::must_use_candidate)]
+#![expect(clippy::missing_errors_doc)]
+use std::fmt;
+
+/// A lisp object passed to or returned from an extension function.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Nil,
+    True,
+    Int(i64),
+    Float(f64),
+    String(String),
+    /// A symbol, by name. Keywords include the leading colon.
+    Symbol(String),
+    /// A proper list
+    List(Vec<Value>),
+    Vector(Vec<Value>),
+    /// Any other object
+    Object(Handle),
+}
+
+/// A reference to a lisp object that has no [`Value`] representation, such as
+/// a buffer or a function. Handles are only valid during the call they were
+/// passed to. Returning one gives back the object it refers to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Handle {
+    call: usize,
+    index: usize,
+}
+
+impl Handle {
+    #[doc(hidden)]
+    pub fn new(call: usize, index: usize) -> Self {
+        Self { call, index }
+    }
+
+    #[doc(hidden)]
+    pub fn call(self) -> usize {
+        self.call
+    }
+
+    #[doc(hidden)]
+    pub fn index(self) -> usize {
+        self.index
+    }
+}
+
+/// An error returned from an extension function.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    /// Signal a generic `error` with a message
+    Message(String),
+    /// Signal the error `symbol` with `data`
+    Signal { symbol: String, data: Vec<Value> },
+}
+
+impl Error {
+    pub fn new(message: impl Into<String>) -> Self {
+        Error::Message(message.into())
+    }
+
+    pub fn signal(symbol: impl Into<String>, data: Vec<Value>) -> Self {
+        Error::Signal { symbol: symbol.into(), data }
+    }
+
+    /// A `wrong-type-argument` error for `value`, which failed `predicate`.
+    pub fn wrong_type(predicate: &str, value: &Value) -> Self {
+        Self::signal("wrong-type-argument", vec![Value::Symbol(predicate.into()), value.clone()])
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Message(message) => write!(f, "{message}"),
+            Error::Signal { symbol, data } => write!(f, "{symbol}: {data:?}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Conversion from a [`Value`] argument.
+pub trait FromValue: Sized {
+    fn from_value(value: &Value) -> Result<Self>;
+}
+
+impl FromValue for Value {
+    fn from_value(value: &Value) -> Result<Self> {
+        Ok(value.clone())
+    }
+}
+
+impl FromValue for i64 {
+    fn from_value(value: &Value) -> Result<Self> {
+        match value {
+            Value::Int(x) => Ok(*x),
+            _ => Err(Error::wrong_type("integerp", value)),
+        }
+    }
+}
+
+impl FromValue for f64 {
+    fn from_value(value: &Value) -> Result<Self> {
+        match value {
+            Value::Float(x) => Ok(*x),
+            Value::Int(x) => Ok(*x as f64),
+            _ => Err(Error::wrong_type("numberp", value)),
+        }
+    }
+}
+
+impl FromValue for bool {
+    fn from_value(value: &Value) -> Result<Self> {
+        Ok(*value != Value::Nil)
+    }
+}
+
+impl FromValue for String {
+    fn from_value(value: &Value) -> Result<Self> {
+        match value {
+            Value::String(x) => Ok(x.clone()),
+            _ => Err(Error::wrong_type("stringp", value)),
+        }
+    }
+}
+
+impl FromValue for Handle {
+    fn from_value(value: &Value) -> Result<Self> {
+        match value {
+            Value::Object(x) => Ok(*x),
+            _ => Err(Error::wrong_type("objectp", value)),
+        }
+    }
+}
+
+impl<T: FromValue> FromValue for Option<T> {
+    fn from_value(value: &Value) -> Result<Self> {
+        match value {
+            Value::Nil => Ok(None),
+            _ => T::from_value(value).map(Some),
+        }
+    }
+}
+
+impl<T: FromValue> FromValue for Vec<T> {
+    fn from_value(value: &Value) -> Result<Self> {
+        match value {
+            Value::Nil => Ok(Vec::new()),
+            Value::List(x) | Value::Vector(x) => x.iter().map(T::from_value).collect(),
+            _ => Err(Error::wrong_type("sequencep", value)),
+        }
+    }
+}
+
+impl From<i64> for Value {
+    fn from(value: i64) -> Self {
+        Value::Int(value)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(value: f64) -> Self {
+        Value::Float(value)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Self {
+        if value { Value::True } else { Value::Nil }
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Self {
+        Value::String(value)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Value::String(value.to_owned())
+    }
+}
+
+impl From<()> for Value {
+    fn from((): ()) -> Self {
+        Value::Nil
+    }
+}
+
+impl From<Handle> for Value {
+    fn from(value: Handle) -> Self {
+        Value::Object(value)
+    }
+}
+
+impl From<Vec<Value>> for Value {
+    fn from(value: Vec<Value>) -> Self {
+        Value::List(value)
+    }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Value::Nil, Into::into)
+    }
+}
+
+/// Convert argument `index` of `args`. Optional arguments that were not
+/// passed are `nil`.
+pub fn arg<T: FromValue>(args: &[Value], index: usize) -> Result<T> {
+    T::from_value(args.get(index).unwrap_or(&Value::Nil))
+}
+
+/// The signature of an extension function. It is passed all of its arguments,
+/// which are checked against the [`Subr`] arity before the call.
+pub type SubrFn = fn(&[Value]) -> Result<Value>;
+
+/// The definition of an extension function.
+#[derive(Debug, Clone, Copy)]
+pub struct Subr {
+    /// The lisp name of the function
+    pub name: &'static str,
+    pub required: u16,
+    pub optional: u16,
+    /// Whether the function takes any number of arguments after the optional
+    /// ones
+    pub rest: bool,
+    pub func: SubrFn,
+}
+
+impl Subr {
+    /// A function that takes no arguments.
+    pub const fn new(name: &'static str, func: SubrFn) -> Self {
+        Self { name, required: 0, optional: 0, rest: false, func }
+    }
+
+    /// Set the number of required and optional arguments.
+    #[must_use]
+    pub const fn args(self, required: u16, optional: u16) -> Self {
+        Self { required, optional, ..self }
+    }
+
+    /// Accept any number of arguments after the optional ones.
+    #[must_use]
+    pub const fn rest(self) -> Self {
+        Self { rest: true, ..self }
+    }
+}
+
+/// Every function registered with [`register!`]. These are defined when Rune
+/// starts.
+#[linkme::distributed_slice]
+pub static SUBRS: [Subr];
+
+/// Register a [`Subr`] to be defined when Rune starts. The subr must be a
+/// constant expression.
+#[macro_export]
+macro_rules! register {
+    ($name:ident: $subr:expr) => {
+        #[$crate::__private::linkme::distributed_slice($crate::SUBRS)]
+        #[linkme(crate = $crate::__private::linkme)]
+        static $name: $crate::Subr = $subr;
+    };
+}
+
+#[doc(hidden)]
+pub mod __private {
+    pub use linkme;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_from_value() {
+        let args = [Value::Int(3), Value::Float(1.5), Value::String("a".into())];
+        assert_eq!(arg::<i64>(&args, 0), Ok(3));
+        assert_eq!(arg::<f64>(&args, 0), Ok(3.0));
+        assert_eq!(arg::<f64>(&args, 1), Ok(1.5));
+        assert_eq!(arg::<String>(&args, 2), Ok("a".into()));
+        assert_eq!(arg::<Option<i64>>(&args, 3), Ok(None));
+        assert_eq!(arg::<bool>(&args, 3), Ok(false));
+        assert_eq!(
+            arg::<i64>(&args, 2),
+            Err(Error::signal(
+                "wrong-type-argument",
+                vec![Value::Symbol("integerp".into()), Value::String("a".into())]
+            ))
+        );
+        let list = Value::List(vec![Value::Int(1), Value::Int(2)]);
+        assert_eq!(Vec::<i64>::from_value(&list), Ok(vec![1, 2]));
+    }
+
+    #[test]
+    fn test_subr() {
+        let subr = Subr::new("zero", |_| Ok(Value::Int(0))).args(1, 2).rest();
+        assert_eq!((subr.required, subr.optional, subr.rest), (1, 2, true));
+        assert_eq!((subr.func)(&[]), Ok(Value::Int(0)));
+    }
+}
@@ -12,15 +12,17 @@ pub(crate) fn expand(function: Function, spec: Spec) -> TokenStream {
         }
     }
 
+    let (required, optional, rest) = parse_call_signature(&function.args, spec.required);
+    let doc = function_doc(&function, required, optional, rest);
     let body = function.body;
     let subr = function.name;
     let subr_name = subr.to_string();
     let struct_name = format_ident!("__subr_{}", &subr_name);
+    let doc_name = format_ident!("__doc_{}", &subr_name);
     let func_name = format_ident!("__wrapper_fn_{}", &subr_name);
     let lisp_name = spec.name.unwrap_or_else(|| map_function_name(&subr_name));
-    let (required, optional, rest) = parse_call_signature(&function.args, spec.required);
 
-    let arg_conversion = get_arg_conversion(&function.args);
+    let arg_conversion = get_arg_conversion(&function.args, &lisp_name);
 
     let create_args = if !function.args.iter().any(|x| matches!(x, ArgType::Env(MUT))) {
         // If mut Env is not needed, then we can just pass a slice from the
@@ -100,14 +102,20 @@ pub(crate) fn expand(function: Function, spec: Spec) -> TokenStream {
                 optional: #optional,
                 rest: #rest,
                 advice: false,
-            }
+            },
+            extension: None,
         };
 
+        #[automatically_derived]
+        #[doc(hidden)]
+        #[allow(non_upper_case_globals)]
+        pub(crate) const #doc_name: &str = #doc;
+
         #body
     }
 }
 
-fn get_arg_conversion(args: &[ArgType]) -> Vec<TokenStream> {
+fn get_arg_conversion(args: &[ArgType], lisp_name: &str) -> Vec<TokenStream> {
     let is_mut = args.iter().any(|ty| matches!(ty, ArgType::Context(MUT)));
     args.iter()
         .enumerate()
@@ -141,6 +149,12 @@ fn get_arg_conversion(args: &[ArgType]) -> Vec<TokenStream> {
                 Gc::Obj => quote! {&args[(#idx).min(args.len())..]},
                 Gc::Other => unreachable!(),
             },
+            // Keywords<..>
+            ArgType::Keywords => {
+                let bind =
+                    quote! {crate::core::gc::Rt::bind_slice(&args[(#idx).min(args.len())..], cx)};
+                quote! {crate::core::object::Keywords::parse(#bind, #lisp_name)?}
+            }
             // ArgSlice
             ArgType::ArgSlice => {
                 let positional = args.iter().filter(|x| x.is_positional_arg()).count();
@@ -177,6 +191,37 @@ fn get_arg_conversion(args: &[ArgType]) -> Vec<TokenStream> {
         .collect()
 }
 
+/// Build the docstring of the function from its doc comments, followed by the
+/// calling convention in the same `(fn ARGS)` form Emacs uses.
+fn function_doc(function: &Function, required: u16, optional: u16, rest: bool) -> String {
+    let names: Vec<_> = std::iter::zip(&function.args, &function.arg_names)
+        .filter(|(ty, _)| !matches!(ty, ArgType::Context(_) | ArgType::Env(_)))
+        .map(|(_, name)| name.trim_start_matches('_').to_ascii_uppercase().replace('_', "-"))
+        .collect();
+    let positional = (required + optional) as usize;
+    let mut usage = Vec::new();
+    for (idx, name) in names.iter().take(positional).enumerate() {
+        if idx == required as usize {
+            usage.push("&optional".to_owned());
+        }
+        usage.push(name.clone());
+    }
+    if rest {
+        usage.push("&rest".to_owned());
+        usage.push(names.get(positional).cloned().unwrap_or_else(|| "ARGS".to_owned()));
+    }
+    let usage = if usage.is_empty() {
+        "(fn)".to_owned()
+    } else {
+        format!("(fn {})", usage.join(" "))
+    };
+    if function.doc.is_empty() {
+        usage
+    } else {
+        format!("{}\n\n{usage}", function.doc)
+    }
+}
+
 fn parse_call_signature(args: &[ArgType], spec_required: Option<u16>) -> (u16, u16, bool) {
     let required = {
         let actual_required = args.iter().filter(|x| x.is_required_arg()).count();
@@ -224,6 +269,7 @@ enum ArgType {
     Slice(Gc),
     SliceRt(Gc),
     ArgSlice,
+    Keywords,
     Option,
     OptionRt,
     Other,
@@ -242,7 +288,7 @@ impl ArgType {
 
     fn is_rest_arg(self) -> bool {
         use ArgType as A;
-        matches!(self, A::SliceRt(_) | A::Slice(_) | A::ArgSlice)
+        matches!(self, A::SliceRt(_) | A::Slice(_) | A::ArgSlice | A::Keywords)
     }
 }
 
@@ -250,6 +296,8 @@ pub(crate) struct Function {
     name: syn::Ident,
     body: syn::Item,
     args: Vec<ArgType>,
+    arg_names: Vec<String>,
+    doc: String,
     fallible: bool,
 }
 
@@ -262,14 +310,16 @@ impl syn::parse::Parse for Function {
 
 fn parse_fn(item: syn::Item) -> Result<Function, Error> {
     match item {
-        syn::Item::Fn(syn::ItemFn { ref sig, .. }) => {
+        syn::Item::Fn(syn::ItemFn { ref sig, ref attrs, .. }) => {
             if sig.unsafety.is_some() {
                 Err(Error::new_spanned(sig, "lisp functions cannot be `unsafe`"))
             } else {
                 let args = parse_signature(sig)?;
                 check_invariants(&args, sig)?;
+                let arg_names = parse_arg_names(sig);
+                let doc = parse_doc(attrs);
                 let fallible = return_type_is_result(&sig.output);
-                Ok(Function { name: sig.ident.clone(), body: item, args, fallible })
+                Ok(Function { name: sig.ident.clone(), body: item, args, arg_names, doc, fallible })
             }
         }
         _ => Err(Error::new_spanned(item, "`lisp_fn` attribute can only be used on functions")),
@@ -286,6 +336,13 @@ fn check_invariants(args: &[ArgType], sig: &syn::Signature) -> Result<(), Error>
                 "Can't have raw Gc pointer in function with mutable Context",
             ));
         }
+        let mut iter = sig.inputs.iter().zip(args.iter());
+        if let Some((arg, _)) = iter.find(|(_, ty)| matches!(ty, ArgType::Keywords)) {
+            return Err(Error::new_spanned(
+                arg,
+                "Can't have keyword arguments in function with mutable Context",
+            ));
+        }
     }
     let mut iter = sig.inputs.iter().zip(args.iter());
     if let Some((arg, _)) = iter.find(|(_, ty)| matches!(ty, ArgType::SliceRt(Gc::Other))) {
@@ -338,6 +395,40 @@ fn parse_signature(sig: &syn::Signature) -> Result<Vec<ArgType>, Error> {
     Ok(args)
 }
 
+fn parse_arg_names(sig: &syn::Signature) -> Vec<String> {
+    sig.inputs
+        .iter()
+        .map(|input| match input {
+            syn::FnArg::Typed(syn::PatType { pat, .. }) => match pat.as_ref() {
+                // `type_` is documented as TYPE
+                syn::Pat::Ident(ident) => ident.ident.to_string().trim_end_matches('_').to_owned(),
+                _ => "ARG".to_owned(),
+            },
+            syn::FnArg::Receiver(_) => "SELF".to_owned(),
+        })
+        .collect()
+}
+
+/// Join the `///` comments of the function into a single string.
+fn parse_doc(attrs: &[syn::Attribute]) -> String {
+    let lines: Vec<_> = attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("doc"))
+        .filter_map(|attr| match &attr.meta {
+            syn::Meta::NameValue(syn::MetaNameValue {
+                value: syn::Expr::Lit(syn::ExprLit { lit: syn::Lit::Str(doc), .. }),
+                ..
+            }) => Some(doc.value()),
+            _ => None,
+        })
+        .collect();
+    let lines: Vec<_> = lines
+        .iter()
+        .map(|line| line.strip_prefix(' ').unwrap_or(line).trim_end())
+        .collect();
+    lines.join("\n").trim().to_owned()
+}
+
 fn return_type_is_result(output: &syn::ReturnType) -> bool {
     match output {
         syn::ReturnType::Type(_, ty) => match ty.as_ref() {
@@ -356,6 +447,8 @@ fn get_arg_type(ty: &syn::Type) -> Result<ArgType, Error> {
                 "Rt" | "Rto" => get_rt_type(path, mutability.is_some())?,
                 _ => ArgType::Other,
             },
+            // A byte slice is a string argument, not rest arguments
+            syn::Type::Slice(slice) if is_u8(&slice.elem) => ArgType::Other,
             syn::Type::Slice(slice) => match get_arg_type(slice.elem.as_ref())? {
                 ArgType::Rt(rt) => ArgType::SliceRt(rt),
                 ArgType::Gc(gc) => ArgType::Slice(gc),
@@ -367,6 +460,7 @@ fn get_arg_type(ty: &syn::Type) -> Result<ArgType, Error> {
             let name = get_path_ident_name(path);
             match &*name {
                 "ArgSlice" => ArgType::ArgSlice,
+                "Keywords" => ArgType::Keywords,
                 "Rt" | "Rto" => get_rt_type(path, false)?,
                 "Option" => {
                     let outer = path.path.segments.last().unwrap();
@@ -389,6 +483,10 @@ fn get_arg_type(ty: &syn::Type) -> Result<ArgType, Error> {
     })
 }
 
+fn is_u8(ty: &syn::Type) -> bool {
+    matches!(ty, syn::Type::Path(path) if path.path.is_ident("u8"))
+}
+
 fn get_object_type(type_path: &syn::TypePath) -> ArgType {
     let outer_type = type_path.path.segments.last().unwrap();
     if outer_type.ident == "Object" {
@@ -459,11 +557,11 @@ mod test {
     #[test]
     fn sig() {
         test_sig(quote! {fn foo() -> u8 {}}, None, (0, 0, false));
-        test_sig(quote! {fn foo(vars: &[u8]) -> u8 {0}}, None, (0, 0, true));
+        test_sig(quote! {fn foo(vars: &[u16]) -> u8 {0}}, None, (0, 0, true));
         test_sig(quote! {fn foo(var: u8) -> u8 {0}}, None, (1, 0, false));
-        test_sig(quote! {fn foo(var0: u8, var1: u8, vars: &[u8]) -> u8 {0}}, None, (2, 0, true));
+        test_sig(quote! {fn foo(var0: u8, var1: u8, vars: &[u16]) -> u8 {0}}, None, (2, 0, true));
         test_sig(
-            quote! {fn foo(var0: u8, var1: Option<u8>, vars: &[u8]) -> u8 {0}},
+            quote! {fn foo(var0: u8, var1: Option<u8>, vars: &[u16]) -> u8 {0}},
             None,
             (1, 1, true),
         );
@@ -513,8 +611,10 @@ mod test {
         test_args(quote! {x: &[Object]}, &[ArgType::Slice(Gc::Obj)]);
         test_args(quote! {x: &[Gc<T>]}, &[ArgType::Slice(Gc::Other)]);
         test_args(quote! {x: &[Gc<T>]}, &[ArgType::Slice(Gc::Other)]);
-        test_args(quote! {x: &[u8]}, &[ArgType::Slice(Gc::Other)]);
+        test_args(quote! {x: &[u8]}, &[ArgType::Other]);
+        test_args(quote! {x: &[u16]}, &[ArgType::Slice(Gc::Other)]);
         test_args(quote! {x: ArgSlice}, &[ArgType::ArgSlice]);
+        test_args(quote! {x: Keywords<Foo>}, &[ArgType::Keywords]);
         test_args(quote! {x: &[Rt<Slot<Object>>]}, &[ArgType::SliceRt(Gc::Obj)]);
         test_args(quote! {x: &[Rto<Object>]}, &[ArgType::SliceRt(Gc::Obj)]);
         test_args(quote! {x: &mut Context}, &[ArgType::Context(MUT)]);
@@ -555,6 +655,8 @@ mod test {
         check_error(quote! {fn foo(a: Rt<Slot<Object>>) {}});
         check_error(quote! {fn foo(a: u8, b: &[Object], c: &[Object]) {}});
         check_error(quote! {fn foo(a: u8, b: Option<u8>, c: u8) {}});
+        check_error(quote! {fn foo(a: Keywords<Foo>, cx: &mut Context) {}});
+        check_error(quote! {fn foo(a: &[Object], b: Keywords<Foo>) {}});
     }
 
     #[test]
@@ -572,4 +674,29 @@ mod test {
         let result = expand(function, spec);
         println!("{result}");
     }
+
+    fn test_doc(stream: TokenStream, expect: &str) {
+        let function: Function = syn::parse2(stream).unwrap();
+        let (required, optional, rest) = parse_call_signature(&function.args, None);
+        assert_eq!(function_doc(&function, required, optional, rest), expect);
+    }
+
+    #[test]
+    fn test_docstring() {
+        test_doc(quote! {fn foo() {}}, "(fn)");
+        test_doc(
+            quote! {
+                /// Return the first element.
+                ///
+                /// More detail.
+                fn foo(list: Object, _flag: Option<u8>, env: &Rt<Env>, cx: &Context) {}
+            },
+            "Return the first element.\n\nMore detail.\n\n(fn LIST &optional FLAG)",
+        );
+        test_doc(
+            quote! {fn foo(object_list: u8, args: &[Object]) {}},
+            "(fn OBJECT-LIST &rest ARGS)",
+        );
+        test_doc(quote! {fn foo(type_: u8) {}}, "(fn TYPE)");
+    }
 }
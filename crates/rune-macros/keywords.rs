@@ -0,0 +1,81 @@
+use proc_macro2::TokenStream;
+use quote::{format_ident, quote};
+use syn::Error;
+
+pub(crate) fn expand(orig: &syn::DeriveInput) -> TokenStream {
+    match derive_keywords(orig) {
+        Ok(tokens) => tokens,
+        Err(e) => e.to_compile_error(),
+    }
+}
+
+fn derive_keywords(orig: &syn::DeriveInput) -> Result<TokenStream, Error> {
+    let name = &orig.ident;
+    let syn::Data::Struct(syn::DataStruct { fields: syn::Fields::Named(fields), .. }) = &orig.data
+    else {
+        return Err(Error::new_spanned(
+            orig,
+            "Keywords can only be derived for structs with named fields",
+        ));
+    };
+    let lifetimes: Vec<_> = orig.generics.lifetimes().collect();
+    let (impl_lifetime, struct_generics) = match lifetimes.as_slice() {
+        [] => (quote! {'ob}, quote! {}),
+        [param] => {
+            let lifetime = &param.lifetime;
+            (quote! {#lifetime}, quote! {<#lifetime>})
+        }
+        _ => {
+            return Err(Error::new_spanned(
+                &orig.generics,
+                "Keywords can have at most one lifetime",
+            ));
+        }
+    };
+
+    let mut idents = Vec::new();
+    let mut keywords = Vec::new();
+    for field in &fields.named {
+        if !is_option(&field.ty) {
+            return Err(Error::new_spanned(&field.ty, "Keyword arguments must be an `Option`"));
+        }
+        let ident = field.ident.as_ref().unwrap();
+        let sym_name = format_ident!("KW_{}", ident.to_string().to_ascii_uppercase());
+        idents.push(ident);
+        keywords.push(quote! {crate::core::env::sym::#sym_name});
+    }
+
+    Ok(quote! {
+        #[automatically_derived]
+        impl<#impl_lifetime> crate::core::object::KeywordArgs<#impl_lifetime> for #name #struct_generics {
+            fn from_plist(
+                plist: &[crate::core::object::Object<#impl_lifetime>],
+                name: &str,
+            ) -> anyhow::Result<Self> {
+                #(let mut #idents = None;)*
+                for pair in plist.chunks(2) {
+                    let &[key, value] = pair else {
+                        anyhow::bail!("Missing value for keyword {} in {name}", pair[0])
+                    };
+                    match key.untag() {
+                        // The first occurrence of a keyword takes precedence
+                        #(crate::core::object::ObjectType::Symbol(#keywords) => {
+                            if #idents.is_none() {
+                                #idents = Some(std::convert::TryFrom::try_from(value)?);
+                            }
+                        })*
+                        _ => anyhow::bail!("Invalid keyword argument {key} in {name}"),
+                    }
+                }
+                Ok(Self { #(#idents),* })
+            }
+        }
+    })
+}
+
+fn is_option(ty: &syn::Type) -> bool {
+    match ty {
+        syn::Type::Path(path) => path.path.segments.last().is_some_and(|x| x.ident == "Option"),
+        _ => false,
+    }
+}
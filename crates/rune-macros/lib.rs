@@ -4,11 +4,13 @@
 //!
 //! * [`macro@defun`]: Functions hydrated to emacs lisp.
 //! * [Trace](`macro@Trace`): Implement Trace for a struct.
+//! * [Keywords](`macro@Keywords`): Parse keyword arguments of a `defun`.
 use darling::{Error, FromMeta, ast::NestedMeta};
 use proc_macro::TokenStream;
 use syn::parse_macro_input;
 
 mod defun;
+mod keywords;
 mod trace;
 
 /// ## `#[defun]`
@@ -57,6 +59,30 @@ pub fn trace_derive(stream: TokenStream) -> TokenStream {
     trace::expand(&derived).into()
 }
 
+/// ## `Keywords`
+///
+/// Parse the keyword arguments of a `defun` into a struct. Each field is an
+/// `Option` named after its keyword, so the field `rehash_size` holds the value
+/// of `:rehash-size`, and needs a matching `defsym!(KW_REHASH_SIZE)`. The
+/// struct is taken as the last argument of the `defun`, wrapped in `Keywords`.
+/// Unknown keywords and keywords without a value are errors.
+///
+/// ```ignore
+/// #[derive(Keywords)]
+/// struct HashTableKeywords<'ob> {
+///     test: Option<Symbol<'ob>>,
+///     size: Option<Object<'ob>>,
+/// }
+///
+/// #[defun]
+/// fn make_hash_table(keyword_args: Keywords<HashTableKeywords>) -> LispHashTable {}
+/// ```
+#[proc_macro_derive(Keywords)]
+pub fn keywords_derive(stream: TokenStream) -> TokenStream {
+    let derived = parse_macro_input!(stream as syn::DeriveInput);
+    keywords::expand(&derived).into()
+}
+
 /// ## `elprop`
 ///
 /// Defines a function template to used for property testing functions. Each element can be a type,
@@ -93,3 +119,24 @@ pub fn elprop(_: TokenStream, fn_ts: TokenStream) -> TokenStream {
     // The handling of this macro is in elprop
     fn_ts
 }
+
+/// ## `elprop_compare`
+///
+/// Loosens how elprop compares the results of a function from Emacs and rune.
+/// Floats are always compared by value rather than by how they are printed.
+///
+/// ## Options:
+///
+/// - `float = <tolerance>`: allow floats to differ by this relative amount
+/// - `unordered`: the elements of a returned list can be in any order
+///
+/// ## Examples:
+///
+/// ```ignore
+/// #[elprop_compare(float = 1e-15)]
+/// ```
+#[proc_macro_attribute]
+pub fn elprop_compare(_: TokenStream, fn_ts: TokenStream) -> TokenStream {
+    // The handling of this macro is in elprop
+    fn_ts
+}
@@ -106,5 +106,25 @@ fn build_metrics(c: &mut Criterion) {
     group.finish();
 }
 
-criterion_group!(benches, realworld, resize, move_gap, build_metrics);
+fn insert_large(c: &mut Criterion) {
+    let mut group = c.benchmark_group("insert_large");
+    group.sample_size(20);
+
+    let line = "fn main() { println!(\"héllo wörld\"); }\n";
+    for mb in [1, 4, 16] {
+        let size = mb * 1024 * 1024;
+        let string = line.repeat(size / line.len());
+        group.throughput(Throughput::Bytes(string.len() as u64));
+        group.bench_function(id::from_parameter(format!("{mb}MB")), |b| {
+            b.iter(|| {
+                let mut buffer = Buffer::new();
+                buffer.insert(&string);
+                black_box(buffer.len_lines())
+            });
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, realworld, resize, move_gap, build_metrics, insert_large);
 criterion_main!(benches);
@@ -8,6 +8,7 @@
 #![expect(clippy::missing_panics_doc)]
 use crate::{
     Position,
+    count::{Counts, count, count_chars, count_newlines, is_char_boundary},
     metric::{BufferMetrics, Metric},
 };
 use get_size2::GetSize;
@@ -34,6 +35,8 @@ pub struct Buffer {
     gap_chars: usize,
     /// The current cursor.
     cursor: GapMetric,
+    /// The size of the buffer. Only the byte and character counts are kept up
+    /// to date, the line count lives in `metrics`.
     total: Metric,
     /// A mapping between byte and character positions. Doesn't account for the gap.
     metrics: BufferMetrics,
@@ -116,13 +119,13 @@ impl std::ops::Sub for GapMetric {
     type Output = Metric;
 
     fn sub(self, rhs: Self) -> Self::Output {
-        Metric { bytes: self.bytes - rhs.bytes, chars: self.chars - rhs.chars }
+        Metric { bytes: self.bytes - rhs.bytes, chars: self.chars - rhs.chars, lines: 0 }
     }
 }
 
 impl Display for GapMetric {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", Metric { bytes: self.bytes, chars: self.chars })
+        write!(f, "b:{}, c:{}", self.bytes, self.chars)
     }
 }
 
@@ -302,7 +305,8 @@ impl Buffer {
         if slice.is_empty() {
             return;
         }
-        self.metrics.insert(self.to_abs_pos(self.cursor), MetricBuilder::new(slice));
+        self.metrics
+            .insert(self.to_abs_pos_with_lines(self.cursor), MetricBuilder::new(slice));
         if self.gap_len() < slice.len() {
             self.grow(slice);
         } else {
@@ -350,7 +354,8 @@ impl Buffer {
         if end_bytes != beg_bytes {
             let beg = GapMetric { bytes: beg_bytes, chars: beg_chars };
             let end = GapMetric { bytes: end_bytes, chars: end_chars };
-            self.metrics.delete(self.to_abs_pos(beg), self.to_abs_pos(end));
+            self.metrics
+                .delete(self.to_abs_pos_with_lines(beg), self.to_abs_pos_with_lines(end));
             self.delete_byte_range(beg, end);
         }
     }
@@ -602,7 +607,28 @@ impl Buffer {
         } else {
             unreachable!()
         };
-        Metric { bytes, chars }
+        Metric { bytes, chars, lines: 0 }
+    }
+
+    /// Like `to_abs_pos`, but also counts the newlines before `pos`.
+    fn to_abs_pos_with_lines(&self, pos: GapMetric) -> Metric {
+        let mut metric = self.to_abs_pos(pos);
+        let (base, offset) = self.metrics.search_byte_chunk(metric.bytes);
+        let (s1, s2) = self.raw_slices(base.bytes, base.bytes + offset);
+        metric.lines = base.lines + count_newlines(s1) + count_newlines(s2);
+        metric
+    }
+
+    /// Get the data between two byte positions that don't account for the gap.
+    fn raw_slices(&self, start: usize, end: usize) -> (&[u8], &[u8]) {
+        let gap_len = self.gap_len();
+        if end <= self.gap_start {
+            (&self.data[start..end], &[])
+        } else if start >= self.gap_start {
+            (&self.data[start + gap_len..end + gap_len], &[])
+        } else {
+            (&self.data[start..self.gap_start], &self.data[self.gap_end..end + gap_len])
+        }
     }
 
     fn to_gapped_pos(&self, pos: Metric) -> GapMetric {
@@ -725,6 +751,44 @@ impl Buffer {
         }
     }
 
+    /// Get the number of newlines in the buffer.
+    #[inline]
+    pub fn len_lines(&self) -> usize {
+        self.metrics.len().lines
+    }
+
+    /// Convert the character position to a line number, which is the number of
+    /// newlines before it.
+    #[inline]
+    pub fn char_to_line(&self, pos: usize) -> usize {
+        let pos = pos.min(self.len_chars());
+        let bytes = self.char_to_byte(pos);
+        self.to_abs_pos_with_lines(GapMetric { bytes, chars: pos }).lines
+    }
+
+    /// Convert the line number to the character position of the start of that
+    /// line. Lines past the end of the buffer return the end of the buffer.
+    #[inline]
+    pub fn line_to_char(&self, line: usize) -> usize {
+        if line == 0 {
+            return 0;
+        }
+        if line > self.len_lines() {
+            return self.len_chars();
+        }
+        let (base, skip) = self.metrics.search_line(line - 1);
+        let (s1, s2) = self.raw_slices(base.bytes, self.total.bytes);
+        let (newline, _) = s1
+            .iter()
+            .chain(s2)
+            .enumerate()
+            .filter(|(_, b)| **b == b'\n')
+            .nth(skip)
+            .expect("line metric out of sync with text");
+        let (s1, s2) = self.raw_slices(base.bytes, base.bytes + newline + 1);
+        base.chars + count_chars(s1) + count_chars(s2)
+    }
+
     #[inline]
     fn to_str(&self, range: impl std::slice::SliceIndex<[u8], Output = [u8]>) -> &str {
         if cfg!(debug_assertions) {
@@ -784,14 +848,8 @@ impl Buffer {
 }
 
 fn metrics(slice: &str) -> Metric {
-    let chars = chars::count(slice);
-    Metric { bytes: slice.len(), chars }
-}
-
-#[expect(clippy::cast_possible_wrap)]
-const fn is_char_boundary(byte: u8) -> bool {
-    // This is bit magic equivalent to: b < 128 || b >= 192
-    (byte as i8) >= -0x40
+    let Counts { chars, lines } = count(slice.as_bytes());
+    Metric { bytes: slice.len(), chars, lines }
 }
 
 #[cfg(test)]
@@ -1075,4 +1133,56 @@ mod test {
         buffer.insert("AAAAAA\0\0AAAAAA");
         buffer.set_cursor(26);
     }
+
+    #[test]
+    fn test_lines() {
+        let mut buffer = Buffer::from("foo\nbar\n\nbaz");
+        assert_eq!(buffer.len_lines(), 3);
+        assert_eq!(buffer.char_to_line(0), 0);
+        assert_eq!(buffer.char_to_line(3), 0);
+        assert_eq!(buffer.char_to_line(4), 1);
+        assert_eq!(buffer.char_to_line(9), 3);
+        assert_eq!(buffer.char_to_line(100), 3);
+        assert_eq!(buffer.line_to_char(0), 0);
+        assert_eq!(buffer.line_to_char(1), 4);
+        assert_eq!(buffer.line_to_char(2), 8);
+        assert_eq!(buffer.line_to_char(3), 9);
+        assert_eq!(buffer.line_to_char(4), 12);
+
+        buffer.set_cursor(5);
+        buffer.insert("\u{B5}\n\u{B5}");
+        assert_eq!(buffer, "foo\nb\u{B5}\n\u{B5}ar\n\nbaz");
+        assert_eq!(buffer.len_lines(), 4);
+        assert_eq!(buffer.char_to_line(6), 1);
+        assert_eq!(buffer.char_to_line(7), 2);
+        assert_eq!(buffer.line_to_char(2), 7);
+        assert_eq!(buffer.line_to_char(3), 11);
+
+        buffer.delete_range(2, 8);
+        assert_eq!(buffer, "foar\n\nbaz");
+        assert_eq!(buffer.len_lines(), 2);
+        assert_eq!(buffer.line_to_char(1), 5);
+        assert_eq!(buffer.char_to_line(7), 2);
+    }
+
+    #[test]
+    fn test_lines_across_chunks() {
+        let line = "\u{B5}bcdefghi\n";
+        let text = line.repeat(50);
+        let mut buffer = Buffer::from(&*text);
+        assert_eq!(buffer.len_lines(), 50);
+        for i in 0..=50 {
+            assert_eq!(buffer.line_to_char(i), i * 10);
+            assert_eq!(buffer.char_to_line(i * 10), i);
+        }
+        buffer.set_cursor(255);
+        buffer.insert("\n\n");
+        buffer.delete_range(10, 40);
+        assert_eq!(buffer.len_lines(), 49);
+        assert_eq!(buffer.line_to_char(1), 10);
+        assert_eq!(buffer.char_to_line(225), 22);
+        assert_eq!(buffer.char_to_line(226), 23);
+        assert_eq!(buffer.char_to_line(227), 24);
+        assert_eq!(buffer.line_to_char(23), 226);
+    }
 }
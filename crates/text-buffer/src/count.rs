@@ -0,0 +1,118 @@
+//! Counting of chars and newlines in byte slices.
+//!
+//! The bytes are processed in fixed size blocks, and each block is counted
+//! into an array of per-lane counters. Because every lane is independent the
+//! compiler vectorizes the inner loop. The lane counters are `u8`, so they are
+//! summed into the total before they can overflow.
+
+/// The number of bytes processed at once.
+const LANES: usize = 32;
+/// The number of blocks that can be counted before a `u8` lane can overflow.
+const BLOCKS_PER_FLUSH: usize = u8::MAX as usize;
+
+/// The number of chars and newlines in a slice.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub(crate) struct Counts {
+    pub(crate) chars: usize,
+    pub(crate) lines: usize,
+}
+
+/// Count the chars and newlines in `bytes` in a single pass. A char is
+/// counted at each byte that starts a UTF-8 sequence.
+pub(crate) fn count(bytes: &[u8]) -> Counts {
+    let mut counts = Counts::default();
+    let mut blocks = bytes.chunks_exact(LANES);
+    loop {
+        let mut chars = [0u8; LANES];
+        let mut lines = [0u8; LANES];
+        let mut n = 0;
+        for block in blocks.by_ref().take(BLOCKS_PER_FLUSH) {
+            for ((c, l), &b) in chars.iter_mut().zip(&mut lines).zip(block) {
+                *c += u8::from(is_char_boundary(b));
+                *l += u8::from(b == b'\n');
+            }
+            n += 1;
+        }
+        counts.chars += sum(&chars);
+        counts.lines += sum(&lines);
+        if n < BLOCKS_PER_FLUSH {
+            break;
+        }
+    }
+    for &byte in blocks.remainder() {
+        counts.chars += usize::from(is_char_boundary(byte));
+        counts.lines += usize::from(byte == b'\n');
+    }
+    counts
+}
+
+/// Count the newlines in `bytes`.
+pub(crate) fn count_newlines(bytes: &[u8]) -> usize {
+    count_lanes(bytes, |b| b == b'\n')
+}
+
+/// Count the chars in `bytes`. A char is counted at each byte that starts a
+/// UTF-8 sequence.
+pub(crate) fn count_chars(bytes: &[u8]) -> usize {
+    count_lanes(bytes, is_char_boundary)
+}
+
+#[inline(always)]
+fn count_lanes(bytes: &[u8], pred: impl Fn(u8) -> bool) -> usize {
+    let mut total = 0;
+    let mut blocks = bytes.chunks_exact(LANES);
+    loop {
+        let mut lanes = [0u8; LANES];
+        let mut n = 0;
+        for block in blocks.by_ref().take(BLOCKS_PER_FLUSH) {
+            for (lane, &b) in lanes.iter_mut().zip(block) {
+                *lane += u8::from(pred(b));
+            }
+            n += 1;
+        }
+        total += sum(&lanes);
+        if n < BLOCKS_PER_FLUSH {
+            break;
+        }
+    }
+    total + blocks.remainder().iter().filter(|&&b| pred(b)).count()
+}
+
+fn sum(lanes: &[u8; LANES]) -> usize {
+    lanes.iter().map(|&x| usize::from(x)).sum()
+}
+
+#[expect(clippy::cast_possible_wrap)]
+pub(crate) const fn is_char_boundary(byte: u8) -> bool {
+    // This is bit magic equivalent to: b < 128 || b >= 192
+    (byte as i8) >= -0x40
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // The reference implementation is naive on purpose
+    #[expect(clippy::naive_bytecount)]
+    fn naive(bytes: &[u8]) -> Counts {
+        Counts {
+            chars: bytes.iter().filter(|&&b| is_char_boundary(b)).count(),
+            lines: bytes.iter().filter(|&&b| b == b'\n').count(),
+        }
+    }
+
+    #[test]
+    fn test_count() {
+        let text = "hello\nworld\n\u{1F600}αβγ\r\n";
+        // cover the remainder, a partial flush, and multiple flushes
+        for len in [0, 1, 31, 32, 33, 1000, LANES * BLOCKS_PER_FLUSH + 7, 100_000] {
+            let string = text.repeat(len / text.len() + 1);
+            let bytes = &string.as_bytes()[..len];
+            assert_eq!(count(bytes), naive(bytes), "len {len}");
+            assert_eq!(count_newlines(bytes), naive(bytes).lines);
+            assert_eq!(count_chars(bytes), naive(bytes).chars);
+        }
+        let string = "\n".repeat(LANES * BLOCKS_PER_FLUSH * 3);
+        assert_eq!(count(string.as_bytes()), Counts { chars: string.len(), lines: string.len() });
+    }
+}
@@ -1,4 +1,5 @@
 mod buffer;
+mod count;
 mod metric;
 mod position;
 
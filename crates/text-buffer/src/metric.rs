@@ -256,6 +256,19 @@ impl BufferMetrics {
         self.root.search_byte(bytes)
     }
 
+    /// Find the chunk containing byte position `bytes`. Unlike `search_byte`
+    /// this always returns the start of the chunk, so the line count of the
+    /// returned metric is exact.
+    pub(crate) fn search_byte_chunk(&self, bytes: usize) -> (Metric, usize) {
+        self.root.search_chunk(bytes, |x| x.bytes)
+    }
+
+    /// Find the chunk containing the newline with index `line`. Returns the
+    /// start of the chunk and the number of newlines in the chunk before it.
+    pub(crate) fn search_line(&self, line: usize) -> (Metric, usize) {
+        self.root.search_chunk(line, |x| x.lines)
+    }
+
     pub(crate) fn len(&self) -> Metric {
         self.root.metrics()
     }
@@ -863,6 +876,8 @@ impl Node {
         self.search_impl(bytes, |x| x.bytes)
     }
 
+    /// Search for `needle`. The line count of the returned metric does not
+    /// include any newlines inside the final chunk.
     fn search_impl(&self, needle: usize, getter: impl Fn(&Metric) -> usize) -> (Metric, usize) {
         self.assert_node_integrity();
         let mut needle = needle;
@@ -876,7 +891,7 @@ impl Node {
             if needle < pos {
                 // if it is ascii then we can just calculate the offset
                 if metric.is_ascii() {
-                    let offset = Metric { bytes: needle, chars: needle };
+                    let offset = Metric { bytes: needle, chars: needle, lines: 0 };
                     return (sum + offset, 0);
                 }
                 let child_sum = match &self {
@@ -895,6 +910,29 @@ impl Node {
         (sum, needle)
     }
 
+    /// Search for the chunk containing `needle` without taking the ascii
+    /// shortcut of `search_impl`.
+    fn search_chunk(&self, needle: usize, getter: impl Fn(&Metric) -> usize) -> (Metric, usize) {
+        let mut needle = needle;
+        let mut sum = Metric::default();
+        for (idx, metric) in self.metric_slice().iter().enumerate() {
+            let pos = getter(metric);
+            if needle < pos {
+                return match &self {
+                    Node::Internal(int) => {
+                        let (metric, offset) = int.children[idx].search_chunk(needle, getter);
+                        (sum + metric, offset)
+                    }
+                    Node::Leaf(_) => (sum, needle),
+                };
+            }
+            sum += *metric;
+            needle -= pos;
+        }
+        // we are beyond total size of the tree
+        (sum, needle)
+    }
+
     fn assert_node_integrity(&self) {
         if cfg!(debug_assertions) {
             match self {
@@ -1009,6 +1047,8 @@ impl fmt::Display for Node {
 pub(crate) struct Metric {
     pub(crate) bytes: usize,
     pub(crate) chars: usize,
+    /// The number of newlines
+    pub(crate) lines: usize,
 }
 
 impl PartialEq for Metric {
@@ -1040,6 +1080,7 @@ impl Sum for Metric {
         iter.fold(Self::default(), |a, b| Self {
             bytes: a.bytes + b.bytes,
             chars: a.chars + b.chars,
+            lines: a.lines + b.lines,
         })
     }
 }
@@ -1048,7 +1089,11 @@ impl Add for Metric {
     type Output = Self;
 
     fn add(self, rhs: Self) -> Self::Output {
-        Self { bytes: self.bytes + rhs.bytes, chars: self.chars + rhs.chars }
+        Self {
+            bytes: self.bytes + rhs.bytes,
+            chars: self.chars + rhs.chars,
+            lines: self.lines + rhs.lines,
+        }
     }
 }
 
@@ -1056,7 +1101,11 @@ impl Sub for Metric {
     type Output = Self;
 
     fn sub(self, rhs: Self) -> Self::Output {
-        Self { bytes: self.bytes - rhs.bytes, chars: self.chars - rhs.chars }
+        Self {
+            bytes: self.bytes - rhs.bytes,
+            chars: self.chars - rhs.chars,
+            lines: self.lines - rhs.lines,
+        }
     }
 }
 
@@ -1064,6 +1113,7 @@ impl AddAssign for Metric {
     fn add_assign(&mut self, rhs: Self) {
         self.bytes += rhs.bytes;
         self.chars += rhs.chars;
+        self.lines += rhs.lines;
     }
 }
 
@@ -1071,6 +1121,7 @@ impl SubAssign for Metric {
     fn sub_assign(&mut self, rhs: Self) {
         self.bytes -= rhs.bytes;
         self.chars -= rhs.chars;
+        self.lines -= rhs.lines;
     }
 }
 
@@ -1079,12 +1130,12 @@ mod test {
     use super::*;
 
     fn metric(x: usize) -> Metric {
-        Metric { bytes: x * 2, chars: x }
+        Metric { bytes: x * 2, chars: x, lines: 0 }
     }
 
     fn mock_search_char(root: &Node, needle: usize) -> Metric {
         let (metric, offset) = root.search_char(needle);
-        Metric { bytes: metric.bytes + offset * 2, chars: metric.chars + offset }
+        Metric { bytes: metric.bytes + offset * 2, chars: metric.chars + offset, lines: 0 }
     }
 
     struct TreeBuilderBasic {
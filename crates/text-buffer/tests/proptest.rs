@@ -42,6 +42,15 @@ fn slice(buffer: &Buffer, string: &str, char_beg: usize, char_end: usize) {
     assert_eq!(buffer_slice, string_slice);
 }
 
+fn lines(buffer: &Buffer, string: &str, pos: usize) {
+    assert_eq!(buffer.len_lines(), string.matches('\n').count());
+    let before = &string[..to_byte_idx(string, pos)];
+    let line = before.matches('\n').count();
+    assert_eq!(buffer.char_to_line(pos), line);
+    let line_start = before.rfind('\n').map_or(0, |x| before[..=x].chars().count());
+    assert_eq!(buffer.line_to_char(line), line_start);
+}
+
 fn insert(buffer: &mut Buffer, string: &mut String, pos: usize, ins_text: &str) {
     let len = buffer.len_chars();
     let point = pos % (len + 1);
@@ -51,6 +60,7 @@ fn insert(buffer: &mut Buffer, string: &mut String, pos: usize, ins_text: &str)
     string_insert(string, point, ins_text);
 
     assert_eq!(buffer, string);
+    lines(buffer, string, point);
 }
 
 fn delete(buffer: &mut Buffer, string: &mut String, beg: usize, end: usize) {
@@ -63,6 +73,7 @@ fn delete(buffer: &mut Buffer, string: &mut String, beg: usize, end: usize) {
     string_remove(string, beg, end);
 
     assert_eq!(buffer, string);
+    lines(buffer, string, beg.min(end));
 }
 
 #[derive(Arbitrary, Debug)]
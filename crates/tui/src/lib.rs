@@ -0,0 +1,712 @@
+//! A simple terminal display.
+//!
+//! Every redisplay lays out the visible part of the text into a [`Screen`],
+//! which a [`Display`] compares against what the terminal already shows so
+//! that only the rows that changed are repainted. The terminal is driven
+//! through crossterm.
+//! Long lines wrap, tabs expand to the next tab stop, and control characters
+//! are shown in caret notation. Colors are drawn with the closest ones the
+//! terminal has.
+//!
+//! All positions are character offsets into the text.
+#![expect(clippy::must_use_candidate)]
+#![expect(clippy::missing_errors_doc)]
+use crossterm::{
+    Command,
+    cursor::{Hide, MoveTo, Show},
+    queue,
+    style::{Attribute, Color, Print, SetAttribute, SetBackgroundColor, SetForegroundColor},
+    terminal::{
+        self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen, ScrollDown, ScrollUp,
+    },
+};
+use std::fmt;
+use std::io::{self, IsTerminal, Write};
+
+const TAB_WIDTH: usize = 8;
+
+/// The size of a terminal or window, in character cells.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Size {
+    pub rows: usize,
+    pub cols: usize,
+}
+
+/// The size of the terminal attached to stdout, or `None` if it is not a
+/// terminal.
+pub fn terminal_size() -> Option<Size> {
+    if !io::stdout().is_terminal() {
+        return None;
+    }
+    let (cols, rows) = terminal::size().ok()?;
+    if rows == 0 || cols == 0 {
+        return None;
+    }
+    Some(Size { rows: rows.into(), cols: cols.into() })
+}
+
+/// Puts the terminal in raw mode until it is dropped. Does nothing if stdin
+/// is not a terminal.
+#[derive(Debug)]
+pub struct RawMode(bool);
+
+impl RawMode {
+    pub fn new() -> Self {
+        Self(io::stdin().is_terminal() && terminal::enable_raw_mode().is_ok())
+    }
+}
+
+impl Default for RawMode {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        if self.0 {
+            let _ = terminal::disable_raw_mode();
+        }
+    }
+}
+
+/// Move the cursor to `row` and `col`, counting from 0.
+fn move_to(row: usize, col: usize) -> MoveTo {
+    MoveTo(col as u16, row as u16)
+}
+
+/// Limit scrolling to the first `rows` rows of the terminal, or let all of it
+/// scroll again if `None`. crossterm has no command for this.
+struct ScrollRegion(Option<usize>);
+
+impl Command for ScrollRegion {
+    fn write_ansi(&self, f: &mut impl fmt::Write) -> fmt::Result {
+        match self.0 {
+            Some(rows) => write!(f, "\x1b[1;{rows}r"),
+            None => f.write_str("\x1b[r"),
+        }
+    }
+}
+
+/// One of the 16 standard colors, with the codes that terminals that only
+/// have those colors understand. crossterm uses the codes of the 256 color
+/// palette for them.
+struct StandardColor {
+    index: u8,
+    foreground: bool,
+}
+
+impl Command for StandardColor {
+    fn write_ansi(&self, f: &mut impl fmt::Write) -> fmt::Result {
+        let base = if self.foreground { 30 } else { 40 };
+        let code = if self.index < 8 { base + self.index } else { base + 52 + self.index };
+        write!(f, "\x1b[{code}m")
+    }
+}
+
+/// Text laid out on the screen.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Screen {
+    /// The text of each row, already expanded into the cells it will fill.
+    pub rows: Vec<String>,
+    /// The row and column of point, or `None` if point is not visible.
+    pub cursor: Option<(usize, usize)>,
+    /// The first character that did not fit in the window, or `None` if the
+    /// text ended before the bottom of the window. Changes at or after this
+    /// position do not affect the screen.
+    pub end: Option<usize>,
+}
+
+/// How `c` is drawn when it starts at column `col` of a row `cols` wide.
+fn glyph(c: char, col: usize, cols: usize) -> String {
+    match c {
+        // tabs stop at the end of the row instead of wrapping
+        '\t' => " ".repeat((TAB_WIDTH - col % TAB_WIDTH).min(cols.saturating_sub(col)).max(1)),
+        '\x7f' => "^?".to_owned(),
+        c if c.is_ascii_control() => format!("^{}", char::from(c as u8 ^ 0x40)),
+        c if c.is_control() => format!("\\{:o}", c as u32),
+        c => c.to_string(),
+    }
+}
+
+/// Lay out `text` from the character `start` into a window of `size`. Text
+/// past the bottom of the window is not shown.
+pub fn layout(text: &str, start: usize, point: usize, size: Size) -> Screen {
+    let mut screen = Screen { rows: vec![String::new()], ..Screen::default() };
+    let mut col = 0;
+    let mut end = start;
+    for (pos, c) in text.chars().enumerate().skip(start) {
+        let mut glyph = if c == '\n' { String::new() } else { glyph(c, col, size.cols) };
+        if col > 0 && col + glyph.chars().count() > size.cols {
+            if screen.rows.len() == size.rows {
+                screen.end = Some(pos);
+                return screen;
+            }
+            screen.rows.push(String::new());
+            col = 0;
+            glyph = self::glyph(c, col, size.cols);
+        }
+        if pos == point {
+            screen.cursor = Some((screen.rows.len() - 1, col));
+        }
+        if c == '\n' {
+            if screen.rows.len() == size.rows {
+                screen.end = Some(pos);
+                return screen;
+            }
+            screen.rows.push(String::new());
+            col = 0;
+        } else {
+            col += glyph.chars().count();
+            if let Some(row) = screen.rows.last_mut() {
+                row.push_str(&glyph);
+            }
+        }
+        end = pos + 1;
+    }
+    if point >= end && point >= start {
+        let col = col.min(size.cols.saturating_sub(1));
+        screen.cursor = Some((screen.rows.len() - 1, col));
+    }
+    screen
+}
+
+/// The mode line row for `text` in a terminal `cols` wide, drawn in inverse
+/// video. The text is cut off at the end of the row, and padded to fill it.
+pub fn mode_line(text: &str, cols: usize) -> String {
+    let mut row = String::new();
+    let mut col = 0;
+    for c in text.chars() {
+        let glyph = if c == '\n' { " ".to_owned() } else { glyph(c, col, cols) };
+        let width = glyph.chars().count();
+        if col + width > cols {
+            break;
+        }
+        row.push_str(&glyph);
+        col += width;
+    }
+    let (reverse, normal) = (SetAttribute(Attribute::Reverse), SetAttribute(Attribute::NoReverse));
+    format!("{reverse}{row}{}{normal}", " ".repeat(cols - col))
+}
+
+/// The start of the line containing `pos`.
+fn line_start(text: &str, pos: usize) -> usize {
+    let before = text.chars().take(pos);
+    before.enumerate().filter(|(_, c)| *c == '\n').last().map_or(0, |(i, _)| i + 1)
+}
+
+/// Choose a window start that puts `point` on `row` of a window of `size`.
+/// The window always starts at the beginning of a line, so the row is only
+/// approximate when lines wrap.
+pub fn window_start(text: &str, point: usize, row: usize, size: Size) -> usize {
+    let row = row.min(size.rows.saturating_sub(1));
+    let size = Size { rows: row + 1, ..size };
+    let mut start = line_start(text, point);
+    while start > 0 {
+        let prev = line_start(text, start - 1);
+        match layout(text, prev, point, size).cursor {
+            Some((cursor_row, _)) if cursor_row <= row => start = prev,
+            _ => break,
+        }
+    }
+    start
+}
+
+/// Draw `screen` to `out`, filling `size`. The last row is the echo area,
+/// which shows `echo`.
+pub fn draw(screen: &Screen, echo: &str, size: Size, out: &mut impl Write) -> io::Result<()> {
+    // Hide the cursor while drawing and move it to the top left
+    queue!(out, Hide, move_to(0, 0))?;
+    for row in 0..size.rows.saturating_sub(1) {
+        let text = screen.rows.get(row).map_or("", String::as_str);
+        queue!(out, Print(text), Clear(ClearType::UntilNewLine), Print("\r\n"))?;
+    }
+    let echo: String = echo.chars().take(size.cols).collect();
+    queue!(out, Print(echo), Clear(ClearType::UntilNewLine))?;
+    if let Some((row, col)) = screen.cursor {
+        queue!(out, move_to(row, col))?;
+    }
+    queue!(out, Show)?;
+    out.flush()
+}
+
+/// What the terminal can display.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Capabilities {
+    /// The number of colors, which is [`Capabilities::TRUECOLOR`] if any
+    /// color can be shown.
+    pub colors: usize,
+    pub italics: bool,
+    /// Whether underlines can be curly, dotted or dashed.
+    pub underline_styles: bool,
+}
+
+impl Capabilities {
+    pub const TRUECOLOR: usize = 1 << 24;
+
+    /// The capabilities of the terminal described by the environment.
+    pub fn detect() -> Self {
+        Self::from_env(|name| std::env::var(name).ok())
+    }
+
+    /// The capabilities of the terminal described by the environment
+    /// variables that `var` looks up. `COLORTERM` tells if the terminal has
+    /// truecolor, and otherwise the colors and other features are guessed
+    /// from `TERM`.
+    pub fn from_env(var: impl Fn(&str) -> Option<String>) -> Self {
+        let term = var("TERM").unwrap_or_default();
+        let truecolor = var("COLORTERM").is_some_and(|x| x == "truecolor" || x == "24bit");
+        let colors = match term.as_str() {
+            "" | "dumb" => 0,
+            _ if truecolor || term.ends_with("-direct") => Self::TRUECOLOR,
+            _ if term.contains("256color") => 256,
+            _ if term.contains("16color") => 16,
+            _ => 8,
+        };
+        let italics = colors > 0 && term != "linux" && !term.starts_with("vt");
+        // VTE has had styled underlines since 0.51.2
+        let vte = var("VTE_VERSION").and_then(|x| x.parse::<u32>().ok());
+        let underline_styles =
+            ["xterm-kitty", "wezterm", "foot"].iter().any(|x| term.starts_with(x))
+                || vte.is_some_and(|x| x >= 5102);
+        Self { colors, italics, underline_styles }
+    }
+}
+
+/// A color given by its red, green and blue components.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Rgb {
+    pub red: u8,
+    pub green: u8,
+    pub blue: u8,
+}
+
+impl Rgb {
+    /// The color of `index` in the 256 color palette of xterm. The first 16
+    /// are the standard colors, then comes a 6x6x6 color cube and a ramp of
+    /// 24 grays.
+    pub const fn indexed(index: u8) -> Self {
+        const STANDARD: [[u8; 3]; 16] = [
+            [0x00, 0x00, 0x00],
+            [0xcd, 0x00, 0x00],
+            [0x00, 0xcd, 0x00],
+            [0xcd, 0xcd, 0x00],
+            [0x00, 0x00, 0xee],
+            [0xcd, 0x00, 0xcd],
+            [0x00, 0xcd, 0xcd],
+            [0xe5, 0xe5, 0xe5],
+            [0x7f, 0x7f, 0x7f],
+            [0xff, 0x00, 0x00],
+            [0x00, 0xff, 0x00],
+            [0xff, 0xff, 0x00],
+            [0x5c, 0x5c, 0xff],
+            [0xff, 0x00, 0xff],
+            [0x00, 0xff, 0xff],
+            [0xff, 0xff, 0xff],
+        ];
+        const fn level(x: u8) -> u8 {
+            if x == 0 { 0 } else { 55 + 40 * x }
+        }
+        match index {
+            0..16 => {
+                let [red, green, blue] = STANDARD[index as usize];
+                Self { red, green, blue }
+            }
+            16..232 => {
+                let x = index - 16;
+                Self { red: level(x / 36), green: level(x / 6 % 6), blue: level(x % 6) }
+            }
+            _ => {
+                let gray = 8 + 10 * (index - 232);
+                Self { red: gray, green: gray, blue: gray }
+            }
+        }
+    }
+
+    /// How different the colors look, using the "redmean" weighting of the
+    /// squared differences of the components.
+    fn distance(self, other: Self) -> u32 {
+        let mean = u32::from(self.red).midpoint(u32::from(other.red));
+        let diff = |a: u8, b: u8| u32::from(a.abs_diff(b)).pow(2);
+        (((512 + mean) * diff(self.red, other.red)) >> 8)
+            + 4 * diff(self.green, other.green)
+            + (((767 - mean) * diff(self.blue, other.blue)) >> 8)
+    }
+
+    /// The index of the closest color in the palette of a terminal with
+    /// `colors` colors, or `None` if it has none. Terminals with 256 colors
+    /// or more only use the color cube and the grays, since themes often
+    /// change the first 16 colors.
+    pub fn approximate(self, colors: usize) -> Option<u8> {
+        let palette = if colors >= 256 {
+            16..=255
+        } else {
+            0..=u8::try_from(colors.min(16)).ok()?.checked_sub(1)?
+        };
+        palette.min_by_key(|&index| self.distance(Self::indexed(index)))
+    }
+}
+
+/// The colors text is drawn in. Colors that are `None` are the terminal's
+/// own.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct Style {
+    pub foreground: Option<Rgb>,
+    pub background: Option<Rgb>,
+}
+
+impl Style {
+    /// The escape sequence that makes a terminal with `colors` colors draw
+    /// in this style. Colors the terminal doesn't have are replaced with the
+    /// closest ones it does.
+    pub fn escape(&self, colors: usize) -> String {
+        let mut escape = SetAttribute(Attribute::Reset).to_string();
+        for (foreground, color) in [(true, self.foreground), (false, self.background)] {
+            let Some(rgb @ Rgb { red, green, blue }) = color else { continue };
+            let color = if colors >= Capabilities::TRUECOLOR {
+                Color::Rgb { r: red, g: green, b: blue }
+            } else {
+                match rgb.approximate(colors) {
+                    Some(index @ 0..16) => {
+                        let _ = StandardColor { index, foreground }.write_ansi(&mut escape);
+                        continue;
+                    }
+                    Some(index) => Color::AnsiValue(index),
+                    None => continue,
+                }
+            };
+            let _ = match foreground {
+                true => SetForegroundColor(color).write_ansi(&mut escape),
+                false => SetBackgroundColor(color).write_ansi(&mut escape),
+            };
+        }
+        escape
+    }
+}
+
+/// What the terminal currently shows, so that it can be updated by
+/// repainting only the rows that changed.
+#[derive(Debug)]
+pub struct Display {
+    /// The text of every row of the terminal, with the echo area last.
+    rows: Vec<String>,
+    cursor: Option<(usize, usize)>,
+    /// The size of the terminal when it was last drawn, or `None` if its
+    /// contents are unknown.
+    size: Option<Size>,
+    style: Style,
+    capabilities: Capabilities,
+    /// The style the terminal was last cleared in, and the number of colors
+    /// it was drawn with.
+    drawn: (Style, usize),
+}
+
+impl Default for Display {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Display {
+    pub const fn new() -> Self {
+        let style = Style { foreground: None, background: None };
+        let capabilities =
+            Capabilities { colors: Capabilities::TRUECOLOR, italics: true, underline_styles: true };
+        Self {
+            rows: Vec::new(),
+            cursor: None,
+            size: None,
+            style,
+            capabilities,
+            drawn: (style, capabilities.colors),
+        }
+    }
+
+    /// Forget what the terminal shows, so the next update redraws all of it.
+    pub fn invalidate(&mut self) {
+        self.size = None;
+    }
+
+    /// Draw in `style` from now on. The whole terminal is redrawn by the next
+    /// update if the style changed, so that the blank parts of it get the new
+    /// background.
+    pub fn set_style(&mut self, style: Style) {
+        if style != self.style {
+            self.style = style;
+            self.invalidate();
+        }
+    }
+
+    /// Draw for a terminal with `capabilities` from now on. The whole terminal
+    /// is redrawn by the next update if they changed.
+    pub fn set_capabilities(&mut self, capabilities: Capabilities) {
+        if capabilities != self.capabilities {
+            self.capabilities = capabilities;
+            self.invalidate();
+        }
+    }
+
+    /// Update the terminal to show `screen` and `echo`, like [`draw`], but
+    /// only repaint the rows that differ from what it already shows. If the
+    /// text of the window has moved up or down, the terminal is scrolled so
+    /// the rows that are still visible don't have to be repainted. Returns
+    /// the number of rows repainted.
+    pub fn update(
+        &mut self,
+        screen: &Screen,
+        echo: &str,
+        size: Size,
+        out: &mut impl Write,
+    ) -> io::Result<usize> {
+        let window_rows = size.rows.saturating_sub(1);
+        let mut desired: Vec<String> = (0..window_rows)
+            .map(|row| screen.rows.get(row).cloned().unwrap_or_default())
+            .collect();
+        desired.push(echo.chars().take(size.cols).collect());
+
+        let mut output = Vec::new();
+        if self.size != Some(size) {
+            let escape = self.style.escape(self.capabilities.colors);
+            if escape != self.drawn.0.escape(self.drawn.1) {
+                output.extend_from_slice(escape.as_bytes());
+                self.drawn = (self.style, self.capabilities.colors);
+            }
+            queue!(output, Clear(ClearType::All))?;
+            self.rows = vec![String::new(); size.rows];
+            self.size = Some(size);
+        }
+        let window = &mut self.rows[..window_rows];
+        if let Some(shift) = scroll_distance(window, &desired[..window_rows]) {
+            let distance = shift.unsigned_abs();
+            let blank = || vec![String::new(); distance];
+            // Limit the scroll to the window so the echo area stays put
+            queue!(output, ScrollRegion(Some(window_rows)))?;
+            if shift > 0 {
+                queue!(output, ScrollUp(distance as u16))?;
+                window.rotate_left(distance);
+                window[window_rows - distance..].clone_from_slice(&blank());
+            } else {
+                queue!(output, ScrollDown(distance as u16))?;
+                window.rotate_right(distance);
+                window[..distance].clone_from_slice(&blank());
+            }
+            queue!(output, ScrollRegion(None))?;
+        }
+        let mut repainted = 0;
+        for (row, text) in desired.iter().enumerate() {
+            if self.rows[row] != *text {
+                queue!(output, move_to(row, 0), Print(text), Clear(ClearType::UntilNewLine))?;
+                repainted += 1;
+            }
+        }
+        if output.is_empty() && self.cursor == screen.cursor {
+            return Ok(0);
+        }
+        // Hide the cursor while drawing
+        queue!(out, Hide)?;
+        out.write_all(&output)?;
+        if let Some((row, col)) = screen.cursor {
+            queue!(out, move_to(row, col))?;
+        }
+        queue!(out, Show)?;
+        self.rows = desired;
+        self.cursor = screen.cursor;
+        out.flush()?;
+        Ok(repainted)
+    }
+}
+
+/// How many rows `current` has to be scrolled to best match `desired`,
+/// positive to move the text up. Returns `None` if scrolling would not save
+/// repainting any rows. Blank rows are not counted as matches, since they are
+/// cheap to repaint.
+fn scroll_distance(current: &[String], desired: &[String]) -> Option<isize> {
+    let matches = |shift: isize| {
+        let shifted = desired.iter().enumerate().filter_map(|(row, text)| {
+            let from = row.checked_add_signed(shift)?;
+            Some((text, current.get(from)?))
+        });
+        shifted.filter(|(text, current)| !text.is_empty() && text == current).count()
+    };
+    let unshifted = matches(0);
+    let len = isize::try_from(desired.len()).ok()?;
+    (1 - len..len)
+        .filter(|&shift| shift != 0)
+        .map(|shift| (matches(shift), shift))
+        .filter(|&(count, _)| count > unshifted)
+        // Prefer the shortest scroll among equally good ones
+        .max_by_key(|&(count, shift)| (count, -shift.abs()))
+        .map(|(_, shift)| shift)
+}
+
+/// Switch to the terminal's alternate screen, so the original contents are
+/// restored by [`leave_alternate_screen`].
+pub fn enter_alternate_screen(out: &mut impl Write) -> io::Result<()> {
+    queue!(out, EnterAlternateScreen, Clear(ClearType::All))?;
+    out.flush()
+}
+
+/// Return to the screen that was shown before [`enter_alternate_screen`].
+pub fn leave_alternate_screen(out: &mut impl Write) -> io::Result<()> {
+    queue!(out, SetAttribute(Attribute::Reset), LeaveAlternateScreen)?;
+    out.flush()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const SIZE: Size = Size { rows: 3, cols: 5 };
+
+    #[test]
+    fn test_layout() {
+        let screen = layout("ab\ncd", 0, 4, SIZE);
+        assert_eq!(screen.rows, vec!["ab", "cd"]);
+        assert_eq!(screen.cursor, Some((1, 1)));
+        // point at the end of the text
+        assert_eq!(layout("ab\ncd", 0, 5, SIZE).cursor, Some((1, 2)));
+        // long lines wrap and text past the window is not shown
+        let screen = layout("abcdefghijklmnopq", 0, 16, SIZE);
+        assert_eq!(screen.rows, vec!["abcde", "fghij", "klmno"]);
+        assert_eq!(screen.cursor, None);
+        assert_eq!(screen.end, Some(15));
+        assert_eq!(layout("ab\ncd", 0, 0, SIZE).end, None);
+        // starting partway through the text
+        let screen = layout("ab\ncd\nef", 3, 0, SIZE);
+        assert_eq!(screen.rows, vec!["cd", "ef"]);
+        assert_eq!(screen.cursor, None);
+    }
+
+    #[test]
+    fn test_glyphs() {
+        let screen = layout("a\tb\x01", 0, 2, Size { rows: 2, cols: 20 });
+        assert_eq!(screen.rows, vec!["a       b^A"]);
+        assert_eq!(screen.cursor, Some((0, 8)));
+        // tabs stop at the end of the row
+        let screen = layout("abcd\tx", 0, 5, SIZE);
+        assert_eq!(screen.rows, vec!["abcd ", "x"]);
+        let screen = layout("abcde\tx", 0, 5, SIZE);
+        assert_eq!(screen.rows, vec!["abcde", "     ", "x"]);
+    }
+
+    #[test]
+    fn test_mode_line() {
+        assert_eq!(mode_line("a\tb", 10), "\x1b[7ma       b \x1b[27m");
+        assert_eq!(mode_line("abc\x01def", 5), "\x1b[7mabc^A\x1b[27m");
+        assert_eq!(mode_line("", 2), "\x1b[7m  \x1b[27m");
+    }
+
+    #[test]
+    fn test_window_start() {
+        let text = "a\nb\nc\nd\ne\nf";
+        assert_eq!(window_start(text, 8, 1, SIZE), 6);
+        assert_eq!(window_start(text, 8, 0, SIZE), 8);
+        assert_eq!(window_start(text, 2, 2, SIZE), 0);
+        // rows past the bottom of the window are clamped
+        assert_eq!(window_start(text, 10, 10, SIZE), 6);
+    }
+
+    #[test]
+    fn test_draw() {
+        let screen = Screen { rows: vec!["ab".into()], cursor: Some((0, 1)), end: None };
+        let mut out = Vec::new();
+        draw(&screen, "hi", SIZE, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(out, "\x1b[?25l\x1b[1;1Hab\x1b[K\r\n\x1b[K\r\nhi\x1b[K\x1b[1;2H\x1b[?25h");
+    }
+
+    fn update(display: &mut Display, rows: &[&str], cursor: (usize, usize)) -> (usize, String) {
+        let rows = rows.iter().map(|&x| x.to_owned()).collect();
+        let screen = Screen { rows, cursor: Some(cursor), end: None };
+        let mut out = Vec::new();
+        let repainted = display.update(&screen, "", Size { rows: 5, cols: 5 }, &mut out).unwrap();
+        (repainted, String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn test_update() {
+        let mut display = Display::new();
+        let (repainted, out) = update(&mut display, &["a", "b"], (0, 0));
+        assert_eq!(repainted, 2);
+        assert_eq!(out, "\x1b[?25l\x1b[2J\x1b[1;1Ha\x1b[K\x1b[2;1Hb\x1b[K\x1b[1;1H\x1b[?25h");
+        // nothing changed
+        assert_eq!(update(&mut display, &["a", "b"], (0, 0)), (0, String::new()));
+        // only the cursor moved
+        let (repainted, out) = update(&mut display, &["a", "b"], (1, 0));
+        assert_eq!(repainted, 0);
+        assert_eq!(out, "\x1b[?25l\x1b[2;1H\x1b[?25h");
+        // one row changed
+        let (repainted, out) = update(&mut display, &["a", "c"], (1, 0));
+        assert_eq!(repainted, 1);
+        assert_eq!(out, "\x1b[?25l\x1b[2;1Hc\x1b[K\x1b[2;1H\x1b[?25h");
+        // everything is repainted after invalidating
+        display.invalidate();
+        assert_eq!(update(&mut display, &["a", "c"], (1, 0)).0, 2);
+    }
+
+    #[test]
+    fn test_update_scroll() {
+        let mut display = Display::new();
+        update(&mut display, &["a", "b", "c", "d"], (0, 0));
+        let (repainted, out) = update(&mut display, &["b", "c", "d", "e"], (0, 0));
+        assert_eq!(repainted, 1);
+        assert_eq!(out, "\x1b[?25l\x1b[1;4r\x1b[1S\x1b[r\x1b[4;1He\x1b[K\x1b[1;1H\x1b[?25h");
+        let (repainted, out) = update(&mut display, &["z", "b", "c", "d"], (0, 0));
+        assert_eq!(repainted, 1);
+        assert_eq!(out, "\x1b[?25l\x1b[1;4r\x1b[1T\x1b[r\x1b[1;1Hz\x1b[K\x1b[1;1H\x1b[?25h");
+    }
+
+    #[test]
+    fn test_style() {
+        let style = Style {
+            foreground: Some(Rgb { red: 255, green: 0, blue: 0 }),
+            background: Some(Rgb { red: 0, green: 0, blue: 16 }),
+        };
+        let escape = style.escape(Capabilities::TRUECOLOR);
+        assert_eq!(escape, "\x1b[0m\x1b[38;2;255;0;0m\x1b[48;2;0;0;16m");
+        let mut display = Display::new();
+        update(&mut display, &["a"], (0, 0));
+        display.set_style(style);
+        let (repainted, out) = update(&mut display, &["a"], (0, 0));
+        assert_eq!(repainted, 1);
+        assert!(out.starts_with(&format!("\x1b[?25l{escape}\x1b[2J")));
+        // the same style doesn't redraw anything
+        display.set_style(style);
+        assert_eq!(update(&mut display, &["a"], (0, 0)).0, 0);
+        // colors are downsampled for terminals with fewer of them
+        assert_eq!(style.escape(256), "\x1b[0m\x1b[38;5;196m\x1b[48;5;232m");
+        assert_eq!(style.escape(16), "\x1b[0m\x1b[91m\x1b[40m");
+        assert_eq!(style.escape(0), "\x1b[0m");
+        display.set_capabilities(Capabilities { colors: 8, ..Capabilities::detect() });
+        let out = update(&mut display, &["a"], (0, 0)).1;
+        assert!(out.starts_with("\x1b[?25l\x1b[0m\x1b[31m\x1b[40m\x1b[2J"));
+    }
+
+    #[test]
+    fn test_approximate() {
+        assert_eq!(Rgb::indexed(196), Rgb { red: 255, green: 0, blue: 0 });
+        assert_eq!(Rgb::indexed(244), Rgb { red: 128, green: 128, blue: 128 });
+        let orange = Rgb { red: 255, green: 135, blue: 0 };
+        assert_eq!(orange.approximate(256), Some(208));
+        assert_eq!(orange.approximate(Capabilities::TRUECOLOR), Some(208));
+        assert_eq!(orange.approximate(8), Some(3));
+        assert_eq!(orange.approximate(0), None);
+    }
+
+    #[test]
+    fn test_capabilities() {
+        let detect = |vars: &[(&str, &str)]| {
+            Capabilities::from_env(|name| vars.iter().find(|x| x.0 == name).map(|x| x.1.to_owned()))
+        };
+        let caps = detect(&[("TERM", "xterm-256color"), ("COLORTERM", "truecolor")]);
+        assert_eq!(caps.colors, Capabilities::TRUECOLOR);
+        assert!(caps.italics && !caps.underline_styles);
+        assert_eq!(detect(&[("TERM", "screen-256color")]).colors, 256);
+        assert_eq!(detect(&[("TERM", "xterm")]).colors, 8);
+        let caps = detect(&[("TERM", "linux")]);
+        assert!(!caps.italics);
+        assert_eq!(detect(&[("TERM", "dumb")]).colors, 0);
+        assert!(detect(&[("TERM", "xterm-kitty")]).underline_styles);
+        assert!(detect(&[("TERM", "xterm-256color"), ("VTE_VERSION", "6800")]).underline_styles);
+    }
+}
@@ -0,0 +1,262 @@
+//! Comparison of the printed results from Emacs and rune. The results are
+//! parsed so that differences that don't matter can be ignored: how floats are
+//! formatted, the print order and parameters of hash tables, and the names of
+//! uninterned symbols. Results that can't be parsed are compared as text.
+use super::data::Compare;
+use std::collections::HashMap;
+use std::iter::Peekable;
+use std::str::Chars;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Sexp {
+    Atom(String),
+    Float(f64),
+    /// The contents of a string, with escapes left as they were printed
+    String(String),
+    /// A symbol printed as `#:name`
+    Uninterned(String),
+    List(Vec<Sexp>),
+    Vector(Vec<Sexp>),
+    HashTable(Vec<(Sexp, Sexp)>),
+}
+
+/// Return true if the printed results from Emacs and rune are the same
+/// according to `rules`.
+pub(crate) fn equivalent(emacs: &str, rune: &str, rules: &Compare) -> bool {
+    if emacs == rune {
+        return true;
+    }
+    let (Some(emacs), Some(rune)) = (Parser::parse(emacs), Parser::parse(rune)) else {
+        return false;
+    };
+    let mut matcher = Matcher { rules, uninterned: HashMap::new() };
+    match (&emacs, &rune) {
+        (Sexp::List(e), Sexp::List(r)) if rules.unordered => {
+            matcher.unordered(e, r, Matcher::matches)
+        }
+        (e, r) => matcher.matches(e, r),
+    }
+}
+
+struct Matcher<'a> {
+    rules: &'a Compare,
+    /// The rune symbol that each uninterned symbol from Emacs corresponds to
+    uninterned: HashMap<String, String>,
+}
+
+impl Matcher<'_> {
+    fn matches(&mut self, emacs: &Sexp, rune: &Sexp) -> bool {
+        match (emacs, rune) {
+            (Sexp::Float(e), Sexp::Float(r)) => floats_match(*e, *r, self.rules.float_tolerance),
+            // Emacs may print uninterned symbols with a different name, but
+            // each one has to stand for the same symbol everywhere
+            (Sexp::Uninterned(e), Sexp::Atom(r) | Sexp::Uninterned(r)) => {
+                *self.uninterned.entry(e.clone()).or_insert_with(|| r.clone()) == *r
+            }
+            (Sexp::List(e), Sexp::List(r)) | (Sexp::Vector(e), Sexp::Vector(r)) => {
+                e.len() == r.len() && e.iter().zip(r).all(|(e, r)| self.matches(e, r))
+            }
+            (Sexp::HashTable(e), Sexp::HashTable(r)) => {
+                self.unordered(e, r, |m, (ek, ev), (rk, rv)| m.matches(ek, rk) && m.matches(ev, rv))
+            }
+            (e, r) => e == r,
+        }
+    }
+
+    /// Match every element of `emacs` with a different element of `rune`, in
+    /// any order.
+    fn unordered<T>(
+        &mut self,
+        emacs: &[T],
+        rune: &[T],
+        matches: impl Fn(&mut Self, &T, &T) -> bool,
+    ) -> bool {
+        if emacs.len() != rune.len() {
+            return false;
+        }
+        let mut used = vec![false; rune.len()];
+        'outer: for e in emacs {
+            for (r, used) in rune.iter().zip(&mut used) {
+                if *used {
+                    continue;
+                }
+                // undo any uninterned symbols matched by a failed attempt
+                let saved = self.uninterned.clone();
+                if matches(self, e, r) {
+                    *used = true;
+                    continue 'outer;
+                }
+                self.uninterned = saved;
+            }
+            return false;
+        }
+        true
+    }
+}
+
+#[expect(clippy::float_cmp)]
+fn floats_match(a: f64, b: f64, tolerance: f64) -> bool {
+    if a.is_nan() || b.is_nan() {
+        return a.is_nan() && b.is_nan();
+    }
+    if a == b {
+        // -0.0 and 0.0 print differently in elisp
+        return a.is_sign_negative() == b.is_sign_negative();
+    }
+    (a - b).abs() <= tolerance * a.abs().max(b.abs())
+}
+
+/// Parse the elisp float syntax, including `1.0e+INF` and `0.0e+NaN`.
+/// Integers, including ones written with a trailing dot, are not floats.
+fn parse_float(text: &str) -> Option<f64> {
+    let (sign, body) = match text.strip_prefix('-') {
+        Some(body) => (-1.0, body),
+        None => (1.0, text.strip_prefix('+').unwrap_or(text)),
+    };
+    if body.ends_with("e+INF") {
+        return Some(sign * f64::INFINITY);
+    }
+    if body.ends_with("e+NaN") {
+        return Some(f64::NAN);
+    }
+    let valid_chars = body.starts_with(|c: char| c.is_ascii_digit() || c == '.')
+        && body
+            .chars()
+            .all(|c| c.is_ascii_digit() || matches!(c, '.' | 'e' | 'E' | '+' | '-'));
+    let has_fraction = body
+        .split_once('.')
+        .is_some_and(|(_, frac)| frac.starts_with(|c: char| c.is_ascii_digit()));
+    if !valid_chars || !(has_fraction || body.contains(['e', 'E'])) {
+        return None;
+    }
+    body.parse::<f64>().ok().map(|x| sign * x)
+}
+
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+}
+
+impl Parser<'_> {
+    /// Parse `text` if it is a single printed object.
+    fn parse(text: &str) -> Option<Sexp> {
+        let mut parser = Parser { chars: text.chars().peekable() };
+        let sexp = parser.sexp()?;
+        parser.skip_whitespace();
+        parser.chars.peek().is_none().then_some(sexp)
+    }
+
+    fn sexp(&mut self) -> Option<Sexp> {
+        self.skip_whitespace();
+        match self.chars.next()? {
+            '(' => Some(Sexp::List(self.sequence(')')?)),
+            '[' => Some(Sexp::Vector(self.sequence(']')?)),
+            '"' => self.string(),
+            '\'' => Some(Sexp::List(vec![Sexp::Atom("quote".into()), self.sexp()?])),
+            '#' => self.hash_syntax(),
+            ')' | ']' => None,
+            c => Some(atom(self.atom_text(String::from(c)))),
+        }
+    }
+
+    fn hash_syntax(&mut self) -> Option<Sexp> {
+        match self.chars.next()? {
+            's' if self.chars.next_if_eq(&'(').is_some() => {
+                Some(hash_table_or_record(self.sequence(')')?))
+            }
+            ':' => Some(Sexp::Uninterned(self.atom_text(String::new()))),
+            '\'' => Some(Sexp::List(vec![Sexp::Atom("function".into()), self.sexp()?])),
+            '[' => {
+                Some(Sexp::List(vec![Sexp::Atom("#".into()), Sexp::Vector(self.sequence(']')?)]))
+            }
+            // unreadable objects such as #<buffer foo>
+            '<' => {
+                let mut text = String::from("#<");
+                for c in self.chars.by_ref() {
+                    text.push(c);
+                    if c == '>' {
+                        return Some(Sexp::Atom(text));
+                    }
+                }
+                None
+            }
+            c => Some(Sexp::Atom(self.atom_text(format!("#{c}")))),
+        }
+    }
+
+    fn sequence(&mut self, close: char) -> Option<Vec<Sexp>> {
+        let mut items = Vec::new();
+        loop {
+            self.skip_whitespace();
+            if self.chars.next_if_eq(&close).is_some() {
+                return Some(items);
+            }
+            items.push(self.sexp()?);
+        }
+    }
+
+    fn string(&mut self) -> Option<Sexp> {
+        let mut text = String::new();
+        while let Some(c) = self.chars.next() {
+            match c {
+                '"' => return Some(Sexp::String(text)),
+                '\\' => {
+                    text.push(c);
+                    text.push(self.chars.next()?);
+                }
+                c => text.push(c),
+            }
+        }
+        None
+    }
+
+    fn atom_text(&mut self, mut text: String) -> String {
+        while let Some(&c) = self.chars.peek() {
+            if c.is_whitespace() || matches!(c, '(' | ')' | '[' | ']' | '"' | '\'') {
+                break;
+            }
+            self.chars.next();
+            text.push(c);
+            if c == '\\' {
+                text.extend(self.chars.next());
+            }
+        }
+        text
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.chars.next_if(|c| c.is_whitespace()).is_some() {}
+    }
+}
+
+fn atom(text: String) -> Sexp {
+    match parse_float(&text) {
+        Some(x) => Sexp::Float(x),
+        None => Sexp::Atom(text),
+    }
+}
+
+/// Emacs prints hash tables as `#s(hash-table test equal data (k v ...))`
+/// with more parameters in older versions, and rune prints them as
+/// `#s(hash-table (k v ...))`. Only the data is compared. Anything else
+/// printed with `#s` is a record.
+fn hash_table_or_record(items: Vec<Sexp>) -> Sexp {
+    if items.first() != Some(&Sexp::Atom("hash-table".into())) {
+        return Sexp::List(vec![Sexp::Atom("#s".into()), Sexp::List(items)]);
+    }
+    let data = match &items[1..] {
+        [Sexp::List(data)] => Some(data),
+        params => params.windows(2).find_map(|pair| match pair {
+            [Sexp::Atom(key), Sexp::List(data)] if key == "data" => Some(data),
+            _ => None,
+        }),
+    };
+    let pairs = data.map_or_else(Vec::new, |data| {
+        data.chunks(2)
+            .filter_map(|pair| match pair {
+                [key, value] => Some((key.clone(), value.clone())),
+                _ => None,
+            })
+            .collect()
+    });
+    Sexp::HashTable(pairs)
+}
@@ -0,0 +1,219 @@
+//! Tracking which defuns have been tested. The results of every run are kept
+//! in `target/elprop/coverage.json` so that later runs can test the functions
+//! that need it most first: ones changed since a git revision, ones that have
+//! never been tested, and ones that failed last time.
+use super::data::{ArgType, Function, Type};
+use super::output::{Output, Status};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt::Write as _;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::{fs, io};
+
+/// A `#[defun]` found in the rune source.
+pub(crate) struct Defun {
+    pub(crate) name: String,
+    /// The source file, relative to the workspace root
+    pub(crate) file: PathBuf,
+    pub(crate) lines: RangeInclusive<usize>,
+    /// The function to test, or why elprop can't test it
+    pub(crate) function: Result<Function, String>,
+}
+
+impl Defun {
+    /// Whether any of the lines of this defun are in `changed`.
+    pub(crate) fn changed(&self, changed: &HashMap<PathBuf, Vec<RangeInclusive<usize>>>) -> bool {
+        changed.get(&self.file).is_some_and(|ranges| {
+            ranges
+                .iter()
+                .any(|r| r.start() <= self.lines.end() && r.end() >= self.lines.start())
+        })
+    }
+}
+
+/// The last result of testing a function.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct Record {
+    /// Seconds since the Unix epoch
+    pub(crate) tested_at: u64,
+    pub(crate) passed: bool,
+    pub(crate) cases: u32,
+    /// The argument types that were generated
+    pub(crate) args: Vec<ArgType>,
+    pub(crate) emacs_version: String,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub(crate) struct Coverage {
+    pub(crate) functions: BTreeMap<String, Record>,
+}
+
+impl Coverage {
+    /// Load the coverage from `path`, or start from nothing if it doesn't
+    /// exist or was written by an incompatible version of elprop.
+    pub(crate) fn load(path: &Path) -> Self {
+        fs::read_to_string(path)
+            .ok()
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default()
+    }
+
+    pub(crate) fn save(&self, path: &Path) -> io::Result<()> {
+        let json = serde_json::to_string_pretty(self).expect("Malformed coverage JSON");
+        fs::write(path, json)
+    }
+
+    /// The order to test functions in. Changed functions come first, then
+    /// untested ones, then ones that failed, then the least recently tested.
+    pub(crate) fn priority(&self, name: &str, changed: bool) -> (bool, bool, bool, u64) {
+        let record = self.functions.get(name);
+        (
+            !changed,
+            record.is_some(),
+            record.is_some_and(|r| r.passed),
+            record.map_or(0, |r| r.tested_at),
+        )
+    }
+
+    pub(crate) fn record(
+        &mut self,
+        outputs: &[Output],
+        functions: &[Function],
+        cases: u32,
+        emacs_version: &str,
+    ) {
+        let tested_at = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |x| x.as_secs());
+        for output in outputs {
+            let Some(func) = functions.iter().find(|f| f.name == output.function) else {
+                continue;
+            };
+            let record = Record {
+                tested_at,
+                passed: matches!(output.status, Status::Pass),
+                cases,
+                args: func.args.clone(),
+                emacs_version: emacs_version.to_owned(),
+            };
+            self.functions.insert(output.function.clone(), record);
+        }
+    }
+
+    /// An org summary of how much of `defuns` has been tested.
+    pub(crate) fn report(&self, defuns: &[Defun]) -> String {
+        let supported: Vec<_> = defuns.iter().filter(|d| d.function.is_ok()).collect();
+        let tested: Vec<_> = supported.iter().filter_map(|d| self.functions.get(&d.name)).collect();
+        let failing = tested.iter().filter(|r| !r.passed).count();
+
+        let mut out = String::from("* Elprop coverage\n");
+        let _ = writeln!(out, "- Defuns: {}", defuns.len());
+        let _ = writeln!(out, "- Supported by elprop: {}", supported.len());
+        let _ = writeln!(out, "- Tested: {}", tested.len());
+        let _ = writeln!(out, "- Passing: {}", tested.len() - failing);
+        let _ = writeln!(out, "- Failing: {failing}");
+
+        // For each argument type, the number of supported defuns that take it
+        // and the number of those that have been tested with it
+        let mut types: BTreeMap<String, (usize, usize)> = BTreeMap::new();
+        for defun in &supported {
+            let Ok(func) = &defun.function else { continue };
+            let record = self.functions.get(&defun.name);
+            for name in arg_type_names(&func.args) {
+                let entry = types.entry(name.clone()).or_default();
+                entry.0 += 1;
+                if record.is_some_and(|r| arg_type_names(&r.args).contains(&name)) {
+                    entry.1 += 1;
+                }
+            }
+        }
+        out.push_str(
+            "\n** Argument types\n| Type | Defuns | Tested |\n|------+--------+--------|\n",
+        );
+        for (name, (total, tested)) in types {
+            let _ = writeln!(out, "| {name} | {total} | {tested} |");
+        }
+
+        out.push_str("\n** Failing\n");
+        for defun in &supported {
+            if self.functions.get(&defun.name).is_some_and(|r| !r.passed) {
+                let _ = writeln!(out, "- ~{}~ ({})", defun.name, defun.file.display());
+            }
+        }
+        out.push_str("\n** Untested\n");
+        for defun in &supported {
+            if !self.functions.contains_key(&defun.name) {
+                let _ = writeln!(out, "- ~{}~ ({})", defun.name, defun.file.display());
+            }
+        }
+        out.push_str("\n** Not supported\n");
+        for defun in defuns {
+            if let Err(reason) = &defun.function {
+                let _ = writeln!(out, "- ~{}~ ({}): {reason}", defun.name, defun.file.display());
+            }
+        }
+        out
+    }
+}
+
+fn arg_type_names(args: &[ArgType]) -> BTreeSet<String> {
+    fn add(ty: &Type, names: &mut BTreeSet<String>) {
+        match ty {
+            Type::Multiple(types) | Type::CustomList(types) => {
+                for ty in types {
+                    add(ty, names);
+                }
+            }
+            Type::CustomString(_) => {
+                names.insert("CustomString".into());
+            }
+            ty => {
+                names.insert(format!("{ty:?}"));
+            }
+        }
+    }
+    let mut names = BTreeSet::new();
+    for ArgType::Required(ty) | ArgType::Optional(ty) in args {
+        add(ty, &mut names);
+    }
+    names
+}
+
+/// The lines in each file under `src` that differ from git revision `rev`,
+/// including uncommitted changes. Returns `None` if git fails.
+pub(crate) fn changed_lines(
+    workspace_root: &Path,
+    rev: &str,
+) -> Option<HashMap<PathBuf, Vec<RangeInclusive<usize>>>> {
+    let output = Command::new("git")
+        .current_dir(workspace_root)
+        .args(["diff", "--no-color", "--unified=0", rev, "--", "src"])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let mut changed: HashMap<PathBuf, Vec<_>> = HashMap::new();
+    let mut file = None;
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        if let Some(path) = line.strip_prefix("+++ ") {
+            file = path.strip_prefix("b/").map(PathBuf::from);
+            continue;
+        }
+        // @@ -old_start,old_len +new_start,new_len @@
+        if let (Some(hunk), Some(file)) = (line.strip_prefix("@@ "), &file) {
+            let Some(new) = hunk.split(' ').find_map(|x| x.strip_prefix('+')) else {
+                continue;
+            };
+            let (start, len) = new.split_once(',').unwrap_or((new, "1"));
+            let (Ok(start), Ok(len)) = (start.parse::<usize>(), len.parse::<usize>()) else {
+                continue;
+            };
+            // A hunk that only removes lines is reported at the line before
+            let end = start + len.saturating_sub(1);
+            changed.entry(file.clone()).or_default().push(start..=end);
+        }
+    }
+    Some(changed)
+}
@@ -2,6 +2,7 @@ use proc_macro2::{TokenStream, TokenTree};
 use prop::collection::VecStrategy;
 use proptest::prelude::*;
 use serde::{Deserialize, Serialize};
+use syn::punctuated::Punctuated;
 use syn::{FnArg, ItemFn};
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -42,7 +43,7 @@ impl Type {
         match self {
             Type::String => any::<String>().prop_map(ArbitraryType::String).boxed(),
             Type::Float => any::<f64>().prop_map(ArbitraryType::Float).boxed(),
-            Type::Cons => Self::cons_strategy().prop_map(ArbitraryType::Cons).boxed(),
+            Type::Cons => Self::cons_strategy().boxed(),
             Type::Symbol => Self::SYMBOL_CHARS.prop_map(ArbitraryType::Symbol).boxed(),
             Type::Integer => Self::fixnum_strategy().prop_map(ArbitraryType::Integer).boxed(),
             Type::PosInteger => {
@@ -51,25 +52,29 @@ impl Type {
             Type::Boolean => any::<bool>().prop_map(ArbitraryType::Boolean).boxed(),
             Type::True => Just(true).prop_map(ArbitraryType::Boolean).boxed(),
             Type::False => Just(false).prop_map(ArbitraryType::Boolean).boxed(),
-            Type::Unknown => Self::any_object_strategy(),
+            Type::Unknown => Self::nested_object_strategy(),
             Type::UnibyteString => "[a-zA-Z0-9 ]*".prop_map(ArbitraryType::UnibyteString).boxed(),
-            Type::Vector => prop::collection::vec(Self::any_object_strategy(), 0..10)
+            Type::Vector => Self::elements_strategy(Self::nested_object_strategy())
                 .prop_map(ArbitraryType::Vector)
                 .boxed(),
             Type::Record => {
-                (Self::SYMBOL_CHARS, prop::collection::vec(Self::any_object_strategy(), 0..10))
+                (Self::SYMBOL_CHARS, Self::elements_strategy(Self::nested_object_strategy()))
                     .prop_map(ArbitraryType::Record)
                     .boxed()
             }
-            Type::HashTable => todo!("Strategy for HashTable not implemented"),
+            Type::HashTable => Self::hash_table_strategy(),
             Type::ByteFn => any::<u8>().prop_map(ArbitraryType::ByteFn).boxed(),
             Type::Byte => any::<u8>().prop_map(ArbitraryType::Byte).boxed(),
-            Type::Subr => todo!("Strategy for Subr not implemented"),
+            Type::Subr => Self::subr_strategy(),
             Type::Buffer => any::<String>().prop_map(ArbitraryType::Buffer).boxed(),
             Type::Nil => Just(ArbitraryType::Nil).boxed(),
             Type::Char => any::<char>().prop_map(ArbitraryType::Char).boxed(),
             Type::CharTable => todo!("Strategy for CharTable not implemented"),
-            Type::Function => todo!("Strategy for Function not implemented"),
+            Type::Function => prop_oneof![
+                3 => Self::lambda_strategy(),
+                1 => Self::subr_strategy(),
+            ]
+            .boxed(),
             Type::Multiple(s) => combined_strategy(&s),
             Type::CustomString(s) => proptest::string::string_regex(&s)
                 .expect("Invalid proptest regex")
@@ -82,6 +87,40 @@ impl Type {
         }
     }
 
+    /// Builtin functions that are safe to call with any arguments of the
+    /// right arity.
+    const SUBRS: &'static [&'static str] =
+        &["identity", "ignore", "list", "vector", "null", "consp", "stringp", "+", "max"];
+    /// The largest arity of a generated lambda.
+    const MAX_ARITY: u8 = 3;
+    /// The most levels of conses and vectors nested inside each other.
+    const MAX_DEPTH: u32 = 3;
+    /// The most elements generated in all levels of a nested object.
+    const MAX_SIZE: u32 = 24;
+    /// The most elements in a single cons list or vector.
+    const MAX_LEN: u32 = 6;
+
+    fn hash_table_strategy() -> BoxedStrategy<ArbitraryType> {
+        let key = prop_oneof![
+            Self::fixnum_strategy().prop_map(ArbitraryType::Integer),
+            "[a-zA-Z0-9 ]*".prop_map(ArbitraryType::String),
+            Self::SYMBOL_CHARS.prop_map(ArbitraryType::Symbol),
+        ];
+        prop::collection::vec((key, Self::any_object_strategy()), 0..5)
+            .prop_map(ArbitraryType::HashTable)
+            .boxed()
+    }
+
+    fn lambda_strategy() -> BoxedStrategy<ArbitraryType> {
+        (0..=Self::MAX_ARITY).prop_map(ArbitraryType::Function).boxed()
+    }
+
+    fn subr_strategy() -> BoxedStrategy<ArbitraryType> {
+        prop::sample::select(Self::SUBRS)
+            .prop_map(|name| ArbitraryType::Subr(name.to_owned()))
+            .boxed()
+    }
+
     fn fixnum_strategy() -> BoxedStrategy<i64> {
         any::<i64>()
             .prop_filter("Fixnum", |x| *x >= Self::MIN_FIXNUM && *x <= Self::MAX_FIXNUM)
@@ -94,15 +133,43 @@ impl Type {
             .boxed()
     }
 
-    fn cons_strategy() -> (VecStrategy<BoxedStrategy<ArbitraryType>>, BoxedStrategy<bool>) {
-        (
-            prop::collection::vec(Self::any_object_strategy(), 0..10),
-            prop_oneof![
-                1 => Just(true),
-                3 => Just(false),
-            ]
-            .boxed(),
-        )
+    /// A list whose elements may themselves be conses or vectors. The outer
+    /// value is always a cons, so shrinking keeps the type that the function
+    /// signature asked for.
+    fn cons_strategy() -> impl Strategy<Value = ArbitraryType> {
+        Self::list_strategy(Self::nested_object_strategy())
+    }
+
+    fn list_strategy(
+        element: BoxedStrategy<ArbitraryType>,
+    ) -> impl Strategy<Value = ArbitraryType> {
+        // Proper lists come first so that failing cases shrink to them
+        let dotted = prop_oneof![
+            3 => Just(false),
+            1 => Just(true),
+        ];
+        (Self::elements_strategy(element), dotted).prop_map(ArbitraryType::Cons)
+    }
+
+    fn elements_strategy(
+        element: BoxedStrategy<ArbitraryType>,
+    ) -> VecStrategy<BoxedStrategy<ArbitraryType>> {
+        prop::collection::vec(element, 0..=Self::MAX_LEN as usize)
+    }
+
+    /// Any object, including conses and vectors nested up to
+    /// [`Self::MAX_DEPTH`] deep with at most [`Self::MAX_SIZE`] elements in
+    /// total. Shrinking removes elements and replaces nested collections with
+    /// atoms.
+    fn nested_object_strategy() -> BoxedStrategy<ArbitraryType> {
+        Self::any_object_strategy()
+            .prop_recursive(Self::MAX_DEPTH, Self::MAX_SIZE, Self::MAX_LEN, |inner| {
+                prop_oneof![
+                    Self::list_strategy(inner.clone()),
+                    Self::elements_strategy(inner).prop_map(ArbitraryType::Vector),
+                ]
+            })
+            .boxed()
     }
 
     pub(crate) fn any_object_strategy() -> BoxedStrategy<ArbitraryType> {
@@ -171,7 +238,7 @@ pub(crate) enum ArbitraryType {
     Byte(u8),
     Char(char),
     Buffer(String),
-    Subr(u8),
+    Subr(String),
 }
 
 pub(crate) fn print_args(args: &[Option<ArbitraryType>]) -> String {
@@ -210,15 +277,9 @@ impl std::fmt::Display for ArbitraryType {
                     write!(f, "{n}")
                 }
             }
-            ArbitraryType::Cons(list) => {
-                let mut cells: Vec<_> = list.0.iter().map(ToString::to_string).collect();
-                let len = list.0.len();
-                let dot_end = list.1;
-                if dot_end && len >= 2 {
-                    cells.insert(len - 1, ".".to_owned());
-                }
-                let string = cells.join(" ");
-                write!(f, "'({string})")
+            ArbitraryType::Cons((elements, dotted)) => {
+                write!(f, "'")?;
+                write_list(f, elements, *dotted)
             }
             ArbitraryType::Symbol(s) => write!(f, "'{s}"),
             ArbitraryType::Integer(n) => write!(f, "{n}"),
@@ -245,15 +306,11 @@ impl std::fmt::Display for ArbitraryType {
                 write!(f, "\"")
             }
             ArbitraryType::Nil => write!(f, "nil"),
-            ArbitraryType::Vector(vec) => {
-                let cells: Vec<_> = vec.iter().map(ToString::to_string).collect();
-                let string = cells.join(" ");
-                write!(f, "[{string}]")
-            }
+            ArbitraryType::Vector(vec) => write_vector(f, vec),
             ArbitraryType::HashTable(vec) => {
-                write!(f, "#s(hash-table data (")?;
+                write!(f, "#s(hash-table test equal data (")?;
                 for (key, value) in vec {
-                    write!(f, "{key} {value} ")?;
+                    write!(f, "{} {} ", Quoted(key), Quoted(value))?;
                 }
                 write!(f, "))")
             }
@@ -280,13 +337,7 @@ impl std::fmt::Display for ArbitraryType {
             ArbitraryType::Buffer(name) => {
                 write!(f, "(generate-new-buffer {name})")
             }
-            ArbitraryType::Subr(arity) => {
-                write!(f, "(lambda (")?;
-                for i in 0..*arity {
-                    write!(f, "arg{i} ")?;
-                }
-                write!(f, ") nil)")
-            }
+            ArbitraryType::Subr(name) => write!(f, "#'{name}"),
             ArbitraryType::Char(chr) => match chr {
                 '\n' => write!(f, "?\\n"),
                 '\t' => write!(f, "?\\t"),
@@ -304,6 +355,51 @@ impl std::fmt::Display for ArbitraryType {
     }
 }
 
+/// Prints an object as it is written inside a quoted form or a vector, where
+/// symbols and lists are not quoted again.
+struct Quoted<'a>(&'a ArbitraryType);
+
+impl std::fmt::Display for Quoted<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.0 {
+            ArbitraryType::Symbol(s) => write!(f, "{s}"),
+            ArbitraryType::Cons((elements, dotted)) => write_list(f, elements, *dotted),
+            ArbitraryType::Unknown(obj) => write!(f, "{}", Quoted(obj)),
+            x => write!(f, "{x}"),
+        }
+    }
+}
+
+fn write_list(
+    f: &mut std::fmt::Formatter<'_>,
+    elements: &[ArbitraryType],
+    dotted: bool,
+) -> std::fmt::Result {
+    write!(f, "(")?;
+    let len = elements.len();
+    for (i, x) in elements.iter().enumerate() {
+        if i > 0 {
+            write!(f, " ")?;
+        }
+        if dotted && len >= 2 && i == len - 1 {
+            write!(f, ". ")?;
+        }
+        write!(f, "{}", Quoted(x))?;
+    }
+    write!(f, ")")
+}
+
+fn write_vector(f: &mut std::fmt::Formatter<'_>, elements: &[ArbitraryType]) -> std::fmt::Result {
+    write!(f, "[")?;
+    for (i, x) in elements.iter().enumerate() {
+        if i > 0 {
+            write!(f, " ")?;
+        }
+        write!(f, "{}", Quoted(x))?;
+    }
+    write!(f, "]")
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub(crate) enum ArgType {
     Required(Type),
@@ -316,12 +412,29 @@ pub(crate) struct Function {
     pub(crate) args: Vec<ArgType>,
     pub(crate) ret: Option<Type>,
     pub(crate) fallible: bool,
+    pub(crate) compare: Compare,
+}
+
+/// How to compare the results of a function from Emacs and rune. Set with
+/// `#[elprop_compare(...)]` on the defun.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub(crate) struct Compare {
+    /// The relative difference allowed between floats
+    pub(crate) float_tolerance: f64,
+    /// Whether the elements of a returned list can be in any order
+    pub(crate) unordered: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub(crate) struct Config {
     pub(crate) test_count: u32,
     pub(crate) functions: Vec<Function>,
+    /// The number of Emacs and rune process pairs to test with in parallel
+    pub(crate) jobs: usize,
+    /// How long to wait for a single case to evaluate before killing it
+    pub(crate) timeout_ms: u64,
+    /// The Emacs executable to compare against
+    pub(crate) emacs: String,
 }
 
 #[allow(dead_code)]
@@ -484,7 +597,37 @@ impl Function {
         let args = Function::get_args(item);
 
         let (ret, fallible) = Self::get_output(item)?;
-        Ok(Function { name, args, ret, fallible })
+        let compare = Self::compare_rules(item);
+        Ok(Function { name, args, ret, fallible, compare })
+    }
+
+    fn compare_rules(item: &ItemFn) -> Compare {
+        let mut rules = Compare::default();
+        for attr in &item.attrs {
+            if !attr.path().is_ident("elprop_compare") {
+                continue;
+            }
+            let options = attr
+                .parse_args_with(Punctuated::<syn::Meta, syn::Token![,]>::parse_terminated)
+                .expect("Invalid elprop_compare options");
+            for option in options {
+                match option {
+                    syn::Meta::Path(path) if path.is_ident("unordered") => rules.unordered = true,
+                    syn::Meta::NameValue(syn::MetaNameValue {
+                        path,
+                        value: syn::Expr::Lit(syn::ExprLit { lit: syn::Lit::Float(value), .. }),
+                        ..
+                    }) if path.is_ident("float") => {
+                        rules.float_tolerance = value.base10_parse().expect("Invalid tolerance");
+                    }
+                    option => {
+                        let name = option.path().get_ident().map(ToString::to_string);
+                        panic!("Unknown elprop_compare option: {}", name.unwrap_or_default())
+                    }
+                }
+            }
+        }
+        rules
     }
 
     fn get_args(item: &ItemFn) -> Vec<ArgType> {
@@ -1,2 +1,8 @@
+// Only used by the runner
+#[allow(dead_code)]
+pub(crate) mod compare;
+// Only used by elprop
+#[allow(dead_code)]
+pub(crate) mod coverage;
 pub(crate) mod data;
 pub(crate) mod output;
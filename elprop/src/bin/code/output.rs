@@ -13,3 +13,10 @@ pub(crate) struct Output {
     pub(crate) function: String,
     pub(crate) status: Status,
 }
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct Report {
+    /// The value of `emacs-version` in the Emacs that was compared against
+    pub(crate) emacs_version: String,
+    pub(crate) outputs: Vec<Output>,
+}
@@ -1,19 +1,42 @@
 mod code;
 use clap::Parser;
+use code::coverage::{self, Coverage, Defun};
 use code::data::{Config, Function};
-use code::output::{Output, Status};
+use code::output::{Report, Status};
+use std::num::NonZero;
 use std::path::Path;
 use std::process::ExitCode;
-use std::{fs, path::PathBuf};
+use std::{fs, path::PathBuf, thread};
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 struct Cli {
-    /// The pattern to match rune functions against
-    pattern: String,
-    /// The number of tests to run per function (default 100)
+    /// The pattern to match rune functions against. All functions are
+    /// matched if it is omitted
+    pattern: Option<String>,
+    /// Only test this many of the matching functions. Functions changed since
+    /// `--changed` are tested first, then ones that have never been tested,
+    /// then ones that failed last time, then the least recently tested
+    #[arg(short, long)]
+    limit: Option<usize>,
+    /// The git revision to find changed functions from
+    #[arg(long, value_name = "REV", default_value = "HEAD")]
+    changed: String,
+    /// The number of tests to run per function (default 200)
     #[arg(short, long)]
     test_count: Option<u32>,
+    /// The number of functions to test in parallel, each with its own Emacs
+    /// and rune process (default is the number of CPUs)
+    #[arg(short, long)]
+    jobs: Option<usize>,
+    /// Seconds to wait for a single case before killing the process that is
+    /// evaluating it
+    #[arg(long, default_value_t = 5)]
+    timeout: u64,
+    /// The Emacs executable to compare against. Defaults to `$ELPROP_EMACS` or
+    /// `emacs`
+    #[arg(long)]
+    emacs: Option<String>,
 }
 
 fn main() -> ExitCode {
@@ -23,75 +46,74 @@ fn main() -> ExitCode {
     let workspace_root = crate_root.parent().unwrap();
     // go to the source directory
     let rust_src = workspace_root.join("src");
+    let elprop_target = workspace_root.join("target/elprop");
+    fs::create_dir_all(&elprop_target).unwrap();
+    let coverage_file = elprop_target.join("coverage.json");
+    let mut coverage = Coverage::load(&coverage_file);
+
     eprintln!("Generating Functions...");
-    let regex = regex::Regex::new(&cli.pattern).unwrap();
-    let functions = get_all_functions(&rust_src)
-        .into_iter()
-        .filter(|x| {
+    let mut defuns = Vec::new();
+    get_all_defuns(workspace_root, &rust_src, &mut defuns);
+    let changed = coverage::changed_lines(workspace_root, &cli.changed);
+    if changed.is_none() {
+        eprintln!("Unable to diff against {}, not prioritizing changed functions", cli.changed);
+    }
+    let changed = changed.unwrap_or_default();
+    let regex = regex::Regex::new(cli.pattern.as_deref().unwrap_or("")).unwrap();
+    let mut functions = defuns
+        .iter()
+        .filter_map(|defun| Some((defun, defun.function.as_ref().ok()?)))
+        .filter(|(_, x)| {
             let rust_name = x.name.replace(['-'], "_");
             regex.is_match(&x.name) || regex.is_match(&rust_name)
         })
+        .map(|(defun, x)| (coverage.priority(&x.name, defun.changed(&changed)), x.clone()))
         .collect::<Vec<_>>();
+    functions.sort_by_key(|(priority, _)| *priority);
+    functions.truncate(cli.limit.unwrap_or(usize::MAX));
+    let functions: Vec<Function> = functions.into_iter().map(|(_, x)| x).collect();
 
-    let config = Config { test_count: cli.test_count.unwrap_or(200), functions: functions.clone() };
+    let jobs = cli
+        .jobs
+        .unwrap_or_else(|| thread::available_parallelism().map_or(1, NonZero::get));
+    let emacs = cli
+        .emacs
+        .or_else(|| std::env::var("ELPROP_EMACS").ok())
+        .unwrap_or_else(|| "emacs".to_owned());
+    let test_count = cli.test_count.unwrap_or(200);
+    let config = Config {
+        test_count,
+        functions: functions.clone(),
+        jobs,
+        timeout_ms: cli.timeout * 1000,
+        emacs,
+    };
 
     let json = serde_json::to_string(&config);
-    let elprop_target = workspace_root.join("target/elprop");
-    fs::create_dir_all(&elprop_target).unwrap();
     let function_file = elprop_target.join("functions.json");
     fs::write(function_file, json.expect("Malformed JSON")).unwrap();
-    #[allow(unused_variables)]
-    let emacs_cmd_file = crate_root.join("src/elprop.el");
     let runner = workspace_root.join("target/debug/runner");
     eprintln!("Launching Proptest...");
-    let lisp_cmd = format!("(load-file \"{}\")", emacs_cmd_file.to_str().unwrap());
-    let child = std::process::Command::new("emacs")
-        .args(["-Q", "--batch", "--eval", &lisp_cmd])
-        .env("ELPROP_RUNNER", runner)
-        .output()
-        .expect("Failed to run emacs");
-    let output = String::from_utf8_lossy(&child.stdout);
-    // find the start of the text mapbacktrace if it exists in output
-    if let Some(panic_idx) = output.find("thread 'main' panicked") {
-        // get the text between START and END tags
-        let start_text = ";; ELPROP_START";
-        let end_text = ";; ELPROP_END";
-        let start = match output.find(start_text) {
-            Some(i) => i + start_text.len(),
-            None => 0,
-        };
-        let end = output.find(end_text).unwrap_or(output.len());
-        let source = &output[start..end];
-
-        let backtrace_end = match output[panic_idx..].find(";; ") {
-            Some(i) => i + panic_idx,
-            None => output.len(),
-        };
-        let backtrace = &output[panic_idx..backtrace_end];
-
-        println!("====================");
-        println!("Status: Failed (Rune Panicked)");
-        println!("Failing Input: {source}");
-        println!("{backtrace}");
+    let status = std::process::Command::new(runner).status().expect("Failed to run runner");
+    if !status.success() {
+        eprintln!("Runner failed: {status}");
         return ExitCode::FAILURE;
     }
 
-    println!("{output}");
-
-    let code = child.status.code().unwrap();
-    if code != 0 {
-        eprintln!("Emacs exited with code: {code}");
-        return ExitCode::from(code as u8);
-    }
-
     let output_file = elprop_target.join("output.json");
     let json_string = fs::read_to_string(output_file).expect("Unable to read output file");
-    let outputs: Vec<Output> =
+    let report: Report =
         serde_json::from_str(&json_string).expect("Unable to deserialize Output json");
 
+    coverage.record(&report.outputs, &functions, test_count, &report.emacs_version);
+    coverage.save(&coverage_file).expect("Unable to write coverage file");
+    let coverage_report = elprop_target.join("coverage.org");
+    fs::write(&coverage_report, coverage.report(&defuns)).expect("Unable to write coverage report");
+
+    println!("Emacs version: {}", report.emacs_version);
     let mut passed = true;
-    let count = outputs.len();
-    for output in outputs {
+    let count = report.outputs.len();
+    for output in report.outputs {
         println!("====================");
         let func = output.function;
         println!("Testing: {func}");
@@ -112,6 +134,7 @@ fn main() -> ExitCode {
         }
         println!();
     }
+    println!("Coverage report: {}", coverage_report.display());
     if count == 0 {
         println!("No tests run");
         ExitCode::SUCCESS
@@ -123,20 +146,22 @@ fn main() -> ExitCode {
     }
 }
 
-fn get_all_functions(pathbuf: &Path) -> Vec<Function> {
-    let mut functions = Vec::new();
-    for entry in fs::read_dir(pathbuf).unwrap() {
-        let path = entry.unwrap().path();
-        if path.extension().is_some_and(|ex| ex == "rs") {
+/// Collect the defuns from every Rust file under `dir`.
+fn get_all_defuns(workspace_root: &Path, dir: &Path, defuns: &mut Vec<Defun>) {
+    let mut entries: Vec<_> = fs::read_dir(dir).unwrap().map(|x| x.unwrap().path()).collect();
+    entries.sort();
+    for path in entries {
+        if path.is_dir() {
+            get_all_defuns(workspace_root, &path, defuns);
+        } else if path.extension().is_some_and(|ex| ex == "rs") {
             let contents = fs::read_to_string(&path).unwrap();
-            let names = get_fn_signatures(&contents);
-            functions.extend(names);
+            let file = path.strip_prefix(workspace_root).unwrap();
+            defuns.extend(get_fn_signatures(&contents, file));
         }
     }
-    functions
 }
 
-fn get_fn_signatures(string: &str) -> Vec<Function> {
+fn get_fn_signatures(string: &str, file: &Path) -> Vec<Defun> {
     syn::parse_file(string)
         .unwrap()
         .items
@@ -146,7 +171,16 @@ fn get_fn_signatures(string: &str) -> Vec<Function> {
             _ => None,
         })
         .filter(is_defun)
-        .filter_map(|x| Function::from_item(x).ok())
+        .map(|x| {
+            let start = x.attrs.first().map_or(x.sig.fn_token.span, |attr| attr.pound_token.span);
+            let end = x.block.brace_token.span.close();
+            Defun {
+                name: x.sig.ident.to_string().replace('_', "-"),
+                file: file.to_owned(),
+                lines: start.start().line..=end.end().line,
+                function: Function::from_item(x),
+            }
+        })
         .collect()
 }
 
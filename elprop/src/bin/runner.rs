@@ -1,14 +1,26 @@
 mod code;
-use code::output::{Output, Status};
+use code::compare;
+use code::data::{Compare, Function};
+use code::output::{Output, Report, Status};
 use proptest::prelude::TestCaseError;
 use proptest::test_runner::{Config, TestError, TestRunner};
+use serde::Deserialize;
 use std::cell::RefCell;
-use std::io::{BufRead, BufReader, Write};
-use std::process::Stdio;
-use std::{fs, path::PathBuf};
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::PathBuf;
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use std::{convert, fs};
 
-const START_TAG: &str = ";; ELPROP_START:";
-const END_TAG: &str = "\n;; ELPROP_END\n";
+const START_TAG: &str = ";; ELPROP_START";
+const END_TAG: &str = ";; ELPROP_END";
+/// The number of lines of stderr kept to report when a process crashes.
+const STDERR_LINES: usize = 40;
 
 fn main() {
     let crate_root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
@@ -22,117 +34,298 @@ fn main() {
     let config: code::data::Config =
         serde_json::from_str(&json_string).expect("Unable to deserialize json");
 
-    let mut runner = TestRunner::new(Config {
-        cases: config.test_count,
-        failure_persistence: None,
-        ..Config::default()
+    let server = crate_root.join("src/elprop.el");
+    let emacs = Program {
+        kind: Kind::Emacs,
+        path: PathBuf::from(&config.emacs),
+        args: vec!["-Q".into(), "--batch".into(), "-l".into(), server.display().to_string()],
+    };
+    let rune = Program {
+        kind: Kind::Rune,
+        path: workspace_root.join("target/debug/rune"),
+        args: vec!["--eval-stdin".into()],
+    };
+    let emacs_version = emacs_version(&config.emacs);
+    eprintln!("Comparing against Emacs {emacs_version}");
+
+    let cases = config.test_count;
+    let timeout = Duration::from_millis(config.timeout_ms);
+    let queue = Mutex::new(config.functions.into_iter().enumerate());
+    let next_function = || queue.lock().unwrap().next();
+    let outputs = Mutex::new(Vec::new());
+    thread::scope(|s| {
+        for _ in 0..config.jobs.max(1) {
+            s.spawn(|| {
+                let mut worker = Worker {
+                    emacs: Evaluator::spawn(&emacs),
+                    rune: Evaluator::spawn(&rune),
+                    timeout,
+                };
+                while let Some((idx, func)) = next_function() {
+                    let output = worker.test(func, cases);
+                    outputs.lock().unwrap().push((idx, output));
+                }
+            });
+        }
     });
 
-    let cmd = crate_root.parent().unwrap().join("target/debug/rune");
-    #[expect(clippy::zombie_processes)]
-    let mut child = std::process::Command::new(cmd)
-        .arg("--eval-stdin")
-        .stdin(Stdio::piped())
-        .stdout(Stdio::piped())
-        .spawn()
-        .expect("Failed to start rune");
-
-    let rune_stdin = RefCell::new(child.stdin.take().unwrap());
-    let rune_stdout = RefCell::new(child.stdout.take().unwrap());
-    let rune_panicked = RefCell::new(false);
-    let master_count = RefCell::new(0);
-
-    let outputs = RefCell::new(Vec::new());
-    let regex = regex::Regex::new("^\\((wrong-[a-zA-Z0-9-]*|[a-zA-Z0-9-]*error) ").unwrap();
-    for func in config.functions {
+    // report the functions in the order they were given
+    let mut outputs = outputs.into_inner().unwrap();
+    outputs.sort_by_key(|(idx, _)| *idx);
+    let outputs = outputs.into_iter().map(|(_, output)| output).collect();
+    let report = Report { emacs_version, outputs };
+    let json = serde_json::to_string(&report).expect("Malformed Output JSON");
+    let output_file = target.join("output.json");
+    fs::write(output_file, json).unwrap();
+}
+
+fn emacs_version(emacs: &str) -> String {
+    let output = Command::new(emacs)
+        .args(["-Q", "--batch", "--eval", "(princ emacs-version)"])
+        .output()
+        .unwrap_or_else(|e| panic!("Failed to run {emacs}: {e}"));
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+/// An Emacs and a rune process that test functions one at a time.
+struct Worker<'a> {
+    emacs: Evaluator<'a>,
+    rune: Evaluator<'a>,
+    timeout: Duration,
+}
+
+impl Worker<'_> {
+    fn test(&mut self, func: Function, cases: u32) -> Output {
         let name = func.name.clone();
+        let rules = func.compare.clone();
+        eprintln!("Testing: {name}");
+        let mut runner =
+            TestRunner::new(Config { cases, failure_persistence: None, ..Config::default() });
+        let this = RefCell::new(&mut *self);
         let result = runner.run(&func.strategy(), |input| {
-            if *rune_panicked.borrow() {
-                return Err(TestCaseError::Reject("Rune panicked".into()));
-            }
             let body = code::data::print_args(&input);
-            // send to emacs
-            println!(";; sending to Emacs");
-            let test_str = format!(";; ELPROP_START\n({name} {body})\n;; ELPROP_END");
-            println!("{test_str}");
-            // send to rune
-            println!(";; sending to rune");
-            match writeln!(rune_stdin.borrow_mut(), "{test_str}") {
-                Ok(()) => (),
-                Err(e) => {
-                    *rune_panicked.borrow_mut() = true;
-                    return Err(TestCaseError::Reject(format!("Rune panicked {e}").into()));
-                }
-            }
-
-            let mut reader = BufReader::new(std::io::stdin());
-            println!(";; reading from Emacs");
-            let emacs_output =
-                process_eval_result("Emacs", *master_count.borrow(), &mut reader, |text| {
-                    regex.is_match(text)
-                });
-
-            let mut rune_stdout = rune_stdout.borrow_mut();
-            let mut reader = BufReader::new(&mut *rune_stdout);
-            println!(";; reading from Rune");
-            let rune_output =
-                process_eval_result("Rune", *master_count.borrow(), &mut reader, |text| {
-                    text.starts_with("Error: ")
-                });
-            println!(";; done");
-
-            *master_count.borrow_mut() += 1;
-
-            match (emacs_output, rune_output) {
-                (Ok(e), Ok(r)) if e == r => Ok(()),
-                (Err(_), Err(_)) => Ok(()),
-                (Ok(e) | Err(e), Ok(r)) | (Ok(e), Err(r)) => {
-                    println!("\"Emacs: '{e}', Rune: '{r}'\"");
-                    Err(TestCaseError::Fail(format!("Emacs: {e}, Rune: {r}").into()))
-                }
-            }
+            this.borrow_mut().compare(&format!("({name} {body})"), &rules)
         });
-
-        // send the output of "result" to a file
-        // open the file in write mode
-
-        println!(";; sending output");
         let status = match result {
             Err(TestError::Fail(reason, value)) => Status::Fail(reason.to_string(), value),
             Err(TestError::Abort(reason)) => Status::Abort(reason.to_string()),
             Ok(()) => Status::Pass,
         };
-        let output = Output { function: name, status };
-        outputs.borrow_mut().push(output);
+        Output { function: name, status }
     }
 
-    let _ = child.kill();
-    let json = serde_json::to_string(&*outputs.borrow()).expect("Malformed Output JSON");
-    let output_file = target.join("output.json");
-    fs::write(output_file, json).unwrap();
-    println!(";; exit process");
+    fn compare(&mut self, form: &str, rules: &Compare) -> Result<(), TestCaseError> {
+        // evaluate in both at the same time
+        self.emacs.send(form);
+        self.rune.send(form);
+        let deadline = Instant::now() + self.timeout;
+        let emacs = self.emacs.receive(deadline);
+        let rune = self.rune.receive(deadline);
+        match (emacs, rune) {
+            (Eval::Value(e), Eval::Value(r)) if compare::equivalent(&e, &r, rules) => Ok(()),
+            (Eval::Error(_), Eval::Error(_)) | (Eval::Timeout, Eval::Timeout) => Ok(()),
+            // Nothing to compare against
+            (e @ (Eval::Timeout | Eval::Crashed(_)), _) => {
+                Err(TestCaseError::Reject(format!("Emacs {e}").into()))
+            }
+            (e, r) => {
+                let timeout = self.timeout;
+                let r = if matches!(r, Eval::Timeout) {
+                    format!("{r} after {timeout:?}")
+                } else {
+                    r.to_string()
+                };
+                Err(TestCaseError::Fail(format!("Emacs: {e}, Rune: {r}").into()))
+            }
+        }
+    }
 }
 
-fn process_eval_result(
-    name: &str,
-    master_count: usize,
-    reader: &mut impl BufRead,
-    test_fail: impl Fn(&str) -> bool,
-) -> Result<String, String> {
-    let mut line = String::new();
-    reader.read_line(&mut line).unwrap();
-    if line.contains("thread 'main' panicked") {
-        return Err("Rune panicked".to_owned());
+/// The result of evaluating a form.
+enum Eval {
+    Value(String),
+    Error(String),
+    Timeout,
+    /// The process exited. Holds its exit status and the end of its stderr.
+    Crashed(String),
+}
+
+impl fmt::Display for Eval {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Eval::Value(value) => write!(f, "{value}"),
+            Eval::Error(err) => write!(f, "error {err}"),
+            Eval::Timeout => write!(f, "timed out"),
+            Eval::Crashed(msg) => write!(f, "crashed ({msg})"),
+        }
     }
-    let count = line.strip_prefix(START_TAG).unwrap().trim().parse::<usize>().unwrap();
-    assert_eq!(
-        master_count, count,
-        "Count from {name} was off. actual {count}, expected {master_count}",
-    );
-    line.clear();
-    while !line.contains(END_TAG) {
-        reader.read_line(&mut line).unwrap();
+}
+
+/// A result printed by the Emacs server in elprop.el.
+#[derive(Deserialize)]
+struct EmacsResult {
+    id: usize,
+    error: bool,
+    value: String,
+}
+
+#[derive(Clone, Copy)]
+enum Kind {
+    Emacs,
+    Rune,
+}
+
+/// How to start an evaluator process.
+struct Program {
+    kind: Kind,
+    path: PathBuf,
+    args: Vec<String>,
+}
+
+/// A persistent process that evaluates the forms sent to its stdin. Its
+/// output is read on other threads so that waiting for a result can time
+/// out. The process is killed when this is dropped.
+struct Evaluator<'a> {
+    program: &'a Program,
+    child: Child,
+    stdin: ChildStdin,
+    stdout: Receiver<String>,
+    stderr: Arc<Mutex<VecDeque<String>>>,
+    stderr_reader: JoinHandle<()>,
+    /// The number of forms evaluated since the process was started
+    count: usize,
+}
+
+impl<'a> Evaluator<'a> {
+    fn spawn(program: &'a Program) -> Self {
+        let mut child = Command::new(&program.path)
+            .args(&program.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap_or_else(|e| panic!("Failed to start {}: {e}", program.path.display()));
+        let stdin = child.stdin.take().unwrap();
+
+        let (sender, stdout) = mpsc::channel();
+        let reader = child.stdout.take().unwrap();
+        thread::spawn(move || {
+            for_each_line(reader, |line| sender.send(line).is_ok());
+        });
+
+        let stderr = Arc::new(Mutex::new(VecDeque::new()));
+        let tail = Arc::clone(&stderr);
+        let reader = child.stderr.take().unwrap();
+        let stderr_reader = thread::spawn(move || {
+            for_each_line(reader, |line| {
+                let mut tail = tail.lock().unwrap();
+                if tail.len() == STDERR_LINES {
+                    tail.pop_front();
+                }
+                tail.push_back(line);
+                true
+            });
+        });
+        Self { program, child, stdin, stdout, stderr, stderr_reader, count: 0 }
+    }
+
+    /// Send `form` to be evaluated. The result is read with
+    /// [`Evaluator::receive`].
+    fn send(&mut self, form: &str) {
+        // If the process has exited that is reported when receiving
+        let _ = writeln!(self.stdin, "{START_TAG}\n{form}\n{END_TAG}");
+    }
+
+    /// Wait until `deadline` for the result of the last form sent. If the
+    /// process hangs or exits, it is restarted.
+    fn receive(&mut self, deadline: Instant) -> Eval {
+        let result = match self.program.kind {
+            Kind::Emacs => self.receive_emacs(deadline),
+            Kind::Rune => self.receive_rune(deadline),
+        }
+        .unwrap_or_else(convert::identity);
+        self.count += 1;
+        if matches!(result, Eval::Timeout | Eval::Crashed(_)) {
+            *self = Self::spawn(self.program);
+        }
+        result
+    }
+
+    fn receive_emacs(&mut self, deadline: Instant) -> Result<Eval, Eval> {
+        loop {
+            let line = self.next_line(deadline)?;
+            // skip anything else that was printed to stdout
+            let Some(start) = line.find("{\"id\":") else { continue };
+            let Ok(result) = serde_json::from_str::<EmacsResult>(&line[start..]) else {
+                continue;
+            };
+            if result.id == self.count {
+                let EmacsResult { error, value, .. } = result;
+                return Ok(if error { Eval::Error(value) } else { Eval::Value(value) });
+            }
+        }
+    }
+
+    fn receive_rune(&mut self, deadline: Instant) -> Result<Eval, Eval> {
+        let start = format!("{START_TAG}:{}", self.count);
+        while self.next_line(deadline)? != start {}
+        let mut lines = Vec::new();
+        loop {
+            let line = self.next_line(deadline)?;
+            if line == END_TAG {
+                break;
+            }
+            lines.push(line);
+        }
+        let text = lines.join("\n").trim().to_owned();
+        Ok(match text.strip_prefix("Error: ") {
+            Some(err) => Eval::Error(err.to_owned()),
+            None => Eval::Value(text),
+        })
+    }
+
+    fn next_line(&mut self, deadline: Instant) -> Result<String, Eval> {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        match self.stdout.recv_timeout(timeout) {
+            Ok(line) => Ok(line),
+            Err(RecvTimeoutError::Timeout) => Err(Eval::Timeout),
+            Err(RecvTimeoutError::Disconnected) => Err(self.crashed()),
+        }
+    }
+
+    fn crashed(&mut self) -> Eval {
+        let status = match self.child.wait() {
+            Ok(status) => status.to_string(),
+            Err(e) => e.to_string(),
+        };
+        // give the reader a moment to drain what is left of stderr
+        let start = Instant::now();
+        while !self.stderr_reader.is_finished() && start.elapsed() < Duration::from_secs(1) {
+            thread::sleep(Duration::from_millis(10));
+        }
+        let tail = self.stderr.lock().unwrap();
+        let stderr: Vec<_> = tail.iter().map(String::as_str).collect();
+        Eval::Crashed(format!("{status}\n{}", stderr.join("\n")))
+    }
+}
+
+impl Drop for Evaluator<'_> {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Call `f` with each line read from `reader` until it returns false. Invalid
+/// UTF-8 is replaced rather than ending the output.
+fn for_each_line(reader: impl Read, mut f: impl FnMut(String) -> bool) {
+    let mut reader = BufReader::new(reader);
+    let mut line = Vec::new();
+    while reader.read_until(b'\n', &mut line).is_ok_and(|n| n > 0) {
+        let text = String::from_utf8_lossy(&line);
+        if !f(text.trim_end_matches(['\n', '\r']).to_owned()) {
+            break;
+        }
+        line.clear();
     }
-    let text = line.strip_suffix(END_TAG).unwrap().trim().to_string();
-    if test_fail(&text) { Err(text) } else { Ok(text) }
 }
@@ -1,47 +1,28 @@
 ;;; -*- lexical-binding: t; -*-
 
-;; For interactive use from within Emacs.
-;; This will reset the code to it's initial state.
-;; make sure to set ELPROP_RUNNER as well
+;; Evaluation server for elprop. The runner starts this in a `--batch' Emacs
+;; and keeps it alive for many test cases. Each form is sent on stdin between
+;; ";; ELPROP_START" and ";; ELPROP_END" lines, and the result is printed to
+;; stdout as one line of JSON: {"id":N,"error":BOOL,"value":"..."}. The
+;; process exits when stdin is closed.
+(require 'json)
 (setq text-quoting-style 'straight)
 
-(when (null noninteractive)
-  (setenv "ELPROP_RUNNER" "~/rune/target/debug/runner")
-  (kill-buffer "*elprop-output*"))
-
-(let* ((buffer "*elprop-output*")
-       (process-connection-type nil)
-       (process (start-process "elprop-process" buffer (getenv "ELPROP_RUNNER")))
-       (runner-count 0)
-       (pointer 1))
-  (with-current-buffer buffer
-    (while (process-live-p process)
-      (save-excursion
-        (goto-char (point-min))
-        (when (search-forward "thread 'main' panicked" nil t)
-          (setq pointer (point))
-          ;; give enough time for a backtrace to be printed
-          (sleep-for 1)
-          (goto-char pointer)
-          (search-backward ";; ELPROP_START" nil t)
-          (let* ((backtrace (buffer-substring-no-properties
-                             (point)
-                             (point-max)))
-                 (escaped (replace-regexp-in-string "%" "%%" backtrace)))
-            (error escaped))))
-      (goto-char pointer)
-      (while-let ((sexp (and (search-forward ";; ELPROP_START" nil t)
-                             (save-excursion (search-forward ";; ELPROP_END" nil t))
-                             (condition-case e
-                                 (read (current-buffer))
-                               (end-of-file nil)))))
-        (setq pointer (point))
-        (let ((result (condition-case err
-                          (eval sexp)
-                        (error err))))
-          (process-send-string process (format ";; ELPROP_START:%d\n%S\n;; ELPROP_END\n"
-                                               runner-count
-                                               result)))
-        (cl-incf runner-count))
-      (sleep-for 0.01)))
-  (message "done"))
+(let ((count 0))
+  (while t
+    (let ((lines nil)
+          line)
+      ;; `read-from-minibuffer' reads a line from stdin in batch mode and
+      ;; signals an error at the end of input
+      (while (not (equal (setq line (read-from-minibuffer "")) ";; ELPROP_END"))
+        (push line lines))
+      (let* ((form (car (read-from-string (mapconcat #'identity (nreverse lines) "\n"))))
+             ;; Output of the tested function would get mixed up with ours
+             (result (condition-case err
+                         (let ((standard-output #'ignore))
+                           (list :error :json-false :value (format "%S" (eval form))))
+                       (error (list :error t :value (format "%S" err))))))
+        ;; Unlike `princ', this flushes stdout
+        (send-string-to-terminal
+         (concat (json-encode (cons :id (cons count result))) "\n"))
+        (setq count (1+ count))))))
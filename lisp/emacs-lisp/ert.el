@@ -0,0 +1,293 @@
+;;; ert.el --- ERT compatible unit tests for rune  -*- lexical-binding: t; -*-
+
+;;; Commentary:
+
+;; A small implementation of the parts of ERT used by the Emacs test
+;; suites, so they can be run against rune.  Tests are defined with
+;; `ert-deftest' and use `should', `should-not' and `should-error' for
+;; their assertions.  The suites are run from the command line with
+;;
+;;   rune --batch -l my-tests.el --eval '(ert-run-tests-batch-and-exit)'
+;;
+;; which exits with 0 if every test had its expected result, 1 if any
+;; did not, and 2 if the tests could not be run.
+
+;;; Code:
+
+(define-error 'ert-test-failed "Test failed")
+(define-error 'ert-test-skipped "Test skipped")
+
+(defvar ert--tests nil
+  "The names of all defined tests, most recently defined first.")
+
+(defun ert-get-test (symbol)
+  "Return the test named SYMBOL, or signal an error if there is none."
+  (or (get symbol 'ert--test)
+      (error "No test named `%S'" symbol)))
+
+(defun ert-test-boundp (symbol)
+  "Return non-nil if SYMBOL names a test."
+  (and (get symbol 'ert--test) t))
+
+(defun ert-delete-all-tests ()
+  "Make all symbols in the obarray name no test."
+  (dolist (name ert--tests)
+    (put name 'ert--test nil))
+  (setq ert--tests nil))
+
+(defun ert-set-test (name test)
+  "Make NAME refer to TEST, a plist describing the test."
+  (unless (memq name ert--tests)
+    (push name ert--tests))
+  (put name 'ert--test test)
+  name)
+
+(defmacro ert-deftest (name _args &rest docstring-keys-and-body)
+  "Define NAME as a test.
+BODY is evaluated when the test is run.  It can start with a
+docstring and keyword arguments:
+
+:expected-result TYPE  the result expected from the test, either
+                       `:passed' (the default) or `:failed'
+:tags TAGS             a list of symbols used to select the test
+
+\(fn NAME () [DOCSTRING] [:expected-result TYPE] [:tags TAGS] BODY...)"
+  (declare (doc-string 3) (indent 2))
+  (let ((body docstring-keys-and-body)
+        (documentation nil)
+        (expected-result :passed)
+        (tags nil))
+    (when (and (stringp (car body)) (cdr body))
+      (setq documentation (pop body)))
+    (while (keywordp (car body))
+      (let ((key (pop body))
+            (value (pop body)))
+        (cond ((eq key :expected-result) (setq expected-result value))
+              ((eq key :tags) (setq tags value))
+              (t (error "Unknown keyword %S in `ert-deftest' %S" key name)))))
+    `(ert-set-test ',name
+                   (list :name ',name
+                         :documentation ,documentation
+                         :expected-result-type ,expected-result
+                         :tags ,tags
+                         :body (lambda () ,@body)))))
+
+;;; Assertions
+
+(defun ert-fail (data)
+  "Signal that the current test failed with DATA."
+  (signal 'ert-test-failed (list data)))
+
+(defun ert-skip (data)
+  "Signal that the current test was skipped with DATA."
+  (signal 'ert-test-skipped (list data)))
+
+(defun ert--expand-form (form)
+  "Return an expression that evaluates FORM to (VALUE . EXPLANATION).
+If FORM is a function call, EXPLANATION is the call with its
+arguments evaluated.  Otherwise it is FORM itself."
+  (if (and (consp form)
+           (symbolp (car form))
+           (not (special-form-p (car form)))
+           (not (macrop (car form))))
+      (let ((args (make-symbol "args")))
+        `(let ((,args (list ,@(cdr form))))
+           (cons (apply #',(car form) ,args) (cons ',(car form) ,args))))
+    `(cons ,form ',form)))
+
+(defmacro should (form)
+  "Evaluate FORM.  If it returns nil, fail the current test."
+  (let ((result (make-symbol "result")))
+    `(let ((,result ,(ert--expand-form form)))
+       (unless (car ,result)
+         (ert-fail (list '(should ,form) :form (cdr ,result) :value (car ,result))))
+       (car ,result))))
+
+(defmacro should-not (form)
+  "Evaluate FORM.  If it returns non-nil, fail the current test."
+  (let ((result (make-symbol "result")))
+    `(let ((,result ,(ert--expand-form form)))
+       (when (car ,result)
+         (ert-fail (list '(should-not ,form) :form (cdr ,result) :value (car ,result))))
+       nil)))
+
+(defun ert--error-conditions (symbol)
+  "Return the conditions of the error SYMBOL."
+  (or (get symbol 'error-conditions)
+      (list symbol 'error)))
+
+(defun ert--should-error (form thunk type exclude-subtypes)
+  "Implementation of `should-error'."
+  (let* ((types (if (listp type) type (list type)))
+         (err (condition-case err
+                  (progn (funcall thunk) nil)
+                (error err))))
+    (unless err
+      (ert-fail (list (list 'should-error form) :form form
+                      :fail-reason "did not signal an error")))
+    (unless (if exclude-subtypes
+                (memq (car err) types)
+              (let ((conditions (ert--error-conditions (car err)))
+                    (found nil))
+                (dolist (type types)
+                  (when (memq type conditions)
+                    (setq found t)))
+                found))
+      (ert-fail (list (list 'should-error form) :form form :condition err
+                      :fail-reason (if exclude-subtypes
+                                       "the error signaled was not one of the expected types"
+                                     "the error signaled did not have the expected type"))))
+    err))
+
+(defmacro should-error (form &rest keys)
+  "Evaluate FORM and fail the current test unless it signals an error.
+Return the error.  KEYS can contain `:type', the error symbol or list
+of error symbols the error should have as a condition, and
+`:exclude-subtypes', which if non-nil means the error symbol itself has
+to be one of the types.
+
+\(fn FORM [:type TYPE] [:exclude-subtypes BOOLEAN])"
+  `(ert--should-error ',form (lambda () ,form)
+                      ,(or (plist-get keys :type) ''error)
+                      ,(plist-get keys :exclude-subtypes)))
+
+(defmacro skip-unless (form)
+  "Skip the current test unless FORM is non-nil."
+  `(unless ,form
+     (ert-skip '(skip-unless ,form))))
+
+(defmacro skip-when (form)
+  "Skip the current test if FORM is non-nil."
+  `(when ,form
+     (ert-skip '(skip-when ,form))))
+
+;;; Selectors
+
+(defun ert-select-tests (selector tests)
+  "Return the tests in TESTS matched by SELECTOR.
+TESTS is a list of test names, or t for all defined tests.  SELECTOR
+is one of:
+
+t or :expected  all tests
+nil             no tests
+a string        tests whose name matches the regexp
+a symbol        the test with that name
+\(member TESTS...)  the named tests
+\(tag TAG)          tests tagged with TAG
+\(not SELECTOR)     tests not matched by SELECTOR
+\(and SELECTORS...) tests matched by every SELECTOR
+\(or SELECTORS...)  tests matched by any SELECTOR
+\(satisfies PRED)   tests whose plist satisfies PRED"
+  (let ((tests (if (eq tests t) (reverse ert--tests) tests))
+        (selected nil))
+    (dolist (name tests)
+      (when (ert--test-selected-p selector (ert-get-test name))
+        (push name selected)))
+    (nreverse selected)))
+
+(defun ert--test-selected-p (selector test)
+  "Return non-nil if SELECTOR matches TEST."
+  (let ((name (plist-get test :name)))
+    (cond ((memq selector '(t :expected)) t)
+          ((null selector) nil)
+          ((stringp selector) (string-match selector (symbol-name name)))
+          ((symbolp selector) (eq selector name))
+          ((not (consp selector)) (error "Invalid test selector: %S" selector))
+          ((eq (car selector) 'member) (memq name (cdr selector)))
+          ((eq (car selector) 'tag) (memq (cadr selector) (plist-get test :tags)))
+          ((eq (car selector) 'not) (not (ert--test-selected-p (cadr selector) test)))
+          ((eq (car selector) 'and)
+           (let ((selected t))
+             (dolist (s (cdr selector))
+               (unless (ert--test-selected-p s test)
+                 (setq selected nil)))
+             selected))
+          ((eq (car selector) 'or)
+           (let ((selected nil))
+             (dolist (s (cdr selector))
+               (when (ert--test-selected-p s test)
+                 (setq selected t)))
+             selected))
+          ((eq (car selector) 'satisfies) (funcall (cadr selector) test))
+          (t (error "Invalid test selector: %S" selector)))))
+
+;;; Running tests
+
+(defun ert-run-test (test)
+  "Run TEST and return its result.
+The result is a cons of the status, one of `passed', `failed' or
+`skipped', and the condition the test signaled."
+  (condition-case err
+      (progn (funcall (plist-get test :body))
+             (list 'passed))
+    (error
+     (cons (if (eq (car err) 'ert-test-skipped) 'skipped 'failed) err))))
+
+(defun ert-test-result-expected-p (test result)
+  "Return non-nil if RESULT is the result TEST was expected to have."
+  (let ((type (plist-get test :expected-result-type)))
+    (cond ((eq (car result) 'skipped) t)
+          ((eq type :passed) (eq (car result) 'passed))
+          ((eq type :failed) (eq (car result) 'failed))
+          ((eq type t) t)
+          (t (error "Invalid expected result type: %S" type)))))
+
+(defun ert--status-label (result expected)
+  "Return the label printed for RESULT, padded to a fixed width."
+  (let ((label (symbol-name (car result))))
+    (unless (or expected (eq (car result) 'skipped))
+      (setq label (upcase label)))
+    (concat (make-string (- 9 (length label)) ?\s) label)))
+
+(defun ert-run-tests-batch (&optional selector)
+  "Run the tests selected by SELECTOR, printing the results.
+SELECTOR defaults to t, all tests.  Return a plist with the counts of
+the results, where `:unexpected' is the number of results that were not
+as expected."
+  (let* ((rune--deterministic t)
+         (selector (or selector t))
+         (tests (ert-select-tests selector t))
+         (total (length tests))
+         (count 0)
+         (passed 0)
+         (failed 0)
+         (skipped 0)
+         (unexpected nil))
+    (message "Running %s tests, selector `%S'" total selector)
+    (dolist (name tests)
+      (let* ((test (ert-get-test name))
+             (result (ert-run-test test))
+             (expected (ert-test-result-expected-p test result)))
+        (setq count (1+ count))
+        (cond ((eq (car result) 'passed) (setq passed (1+ passed)))
+              ((eq (car result) 'failed) (setq failed (1+ failed)))
+              (t (setq skipped (1+ skipped))))
+        (when (cdr result)
+          (message "Test %S condition:\n    %S" name (cdr result)))
+        (unless expected
+          (push (cons name result) unexpected))
+        (message "%s  %s/%s  %S" (ert--status-label result expected) count total name)))
+    (message "\nRan %s tests, %s results as expected, %s unexpected%s"
+             total (- total (length unexpected)) (length unexpected)
+             (if (> skipped 0) (format ", %s skipped" skipped) ""))
+    (when unexpected
+      (message "%s unexpected results:" (length unexpected))
+      (dolist (entry (reverse unexpected))
+        (message "%s  %S" (ert--status-label (cdr entry) nil) (car entry))))
+    (list :total total :passed passed :failed failed :skipped skipped
+          :unexpected (length unexpected))))
+
+(defun ert-run-tests-batch-and-exit (&optional selector)
+  "Run the tests selected by SELECTOR and exit.
+The exit code is 0 if every test had its expected result, 1 if any
+did not, and 2 if the tests could not be run."
+  (let ((stats (condition-case err
+                   (ert-run-tests-batch selector)
+                 (error
+                  (message "Error running tests: %S" err)
+                  (kill-emacs 2)))))
+    (kill-emacs (if (= (plist-get stats :unexpected) 0) 0 1))))
+
+(provide 'ert)
+
+;;; ert.el ends here
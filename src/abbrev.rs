@@ -0,0 +1,454 @@
+//! Abbrev tables and expansion.
+//!
+//! An abbrev table works like an obarray: it is a hash table that maps the
+//! name of each abbrev to an uninterned symbol. Hash tables compare keys by
+//! identity, so the names are keyed by the interned symbol of the same name.
+//! The value of the uninterned symbol is the expansion, its function is the
+//! hook run after expanding, and its property list holds `:count` and the
+//! other properties of the abbrev. Like in abbrev.el, the properties of the
+//! table itself are kept on the symbol named "".
+use crate::casefiddle::{CaseMode, casify_string};
+use crate::core::{
+    cons::Cons,
+    env::{ArgSlice, Env, intern, intern_soft, sym},
+    gc::{Context, Rt},
+    object::{Function, Gc, HashTable, LispHashTable, List, NIL, Object, ObjectType, Symbol},
+};
+use crate::data::{fset, get};
+use crate::editfns::{delete_region, insert_text};
+use anyhow::{Result, bail, ensure};
+use rune_core::macros::{call, root};
+use rune_macros::defun;
+
+defvar!(ABBREV_TABLE_NAME_LIST);
+defvar!(GLOBAL_ABBREV_TABLE);
+defvar_local!(LOCAL_ABBREV_TABLE);
+defvar_local!(ABBREV_MODE);
+defvar!(ABBREV_ALL_CAPS);
+defvar!(LAST_ABBREV);
+defvar!(LAST_ABBREV_TEXT);
+defvar!(LAST_ABBREV_LOCATION, 0);
+
+defsym!(KW_COUNT);
+defsym!(KW_SYSTEM);
+defsym!(KW_CASE_FIXED);
+defsym!(KW_ABBREV_TABLE_MODIFF);
+defsym!(NO_SELF_INSERT);
+
+/// The symbol named `name` in `table`.
+fn lookup<'ob>(table: &'ob LispHashTable, name: &str, cx: &Context) -> Option<Symbol<'ob>> {
+    let key = intern_soft(name, cx)?;
+    match table.get(key.into())?.untag() {
+        ObjectType::Symbol(symbol) => Some(symbol),
+        _ => None,
+    }
+}
+
+/// The symbol that holds the properties of `table`, or `None` if it is not
+/// an abbrev table.
+fn table_symbol<'ob>(table: Object<'ob>, env: &Rt<Env>, cx: &'ob Context) -> Option<Symbol<'ob>> {
+    let ObjectType::HashTable(table) = table.untag() else { return None };
+    let symbol = lookup(table, "", cx)?;
+    let modiff = get(symbol, sym::KW_ABBREV_TABLE_MODIFF, env, cx);
+    matches!(modiff.untag(), ObjectType::Int(_)).then_some(symbol)
+}
+
+fn check_table<'ob>(
+    table: Object<'ob>,
+    env: &Rt<Env>,
+    cx: &'ob Context,
+) -> Result<(&'ob LispHashTable, Symbol<'ob>)> {
+    match (table.untag(), table_symbol(table, env, cx)) {
+        (ObjectType::HashTable(hash), Some(symbol)) => Ok((hash, symbol)),
+        _ => bail!("Wrong type argument: abbrev-table-p, {table}"),
+    }
+}
+
+/// Set the properties in the plist `props` on `symbol`.
+fn put_props(symbol: Symbol, props: &[Object], env: &mut Rt<Env>) -> Result<()> {
+    for pair in props.chunks(2) {
+        let prop: Symbol = pair[0].try_into()?;
+        env.set_prop(symbol, prop, pair.get(1).copied().unwrap_or(NIL));
+    }
+    Ok(())
+}
+
+fn new_table<'ob>(
+    props: &[Object<'ob>],
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<&'ob LispHashTable> {
+    let hasher = std::hash::BuildHasherDefault::default();
+    let table: Gc<&LispHashTable> = cx.add_as(HashTable::with_hasher(hasher));
+    let table = table.untag();
+    let symbol = Symbol::new_uninterned("", cx);
+    table.insert(intern("", cx).into(), symbol.into());
+    env.set_prop(symbol, sym::KW_ABBREV_TABLE_MODIFF, cx.add(0));
+    put_props(symbol, props, env)?;
+    Ok(table)
+}
+
+/// Return a new, empty abbrev table with the properties in the plist PROPS.
+#[defun]
+fn make_abbrev_table<'ob>(
+    props: Option<Object<'ob>>,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<&'ob LispHashTable> {
+    let props: Vec<_> = match props {
+        Some(props) => List::try_from(props)?.elements().collect::<Result<_, _>>()?,
+        None => Vec::new(),
+    };
+    new_table(&props, env, cx)
+}
+
+/// Return t if OBJECT is an abbrev table.
+#[defun]
+fn abbrev_table_p(object: Object, env: &Rt<Env>, cx: &Context) -> bool {
+    table_symbol(object, env, cx).is_some()
+}
+
+/// Return the property PROP of the abbrev table TABLE.
+#[defun]
+fn abbrev_table_get<'ob>(
+    table: Object<'ob>,
+    prop: Symbol,
+    env: &Rt<Env>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    let (_, symbol) = check_table(table, env, cx)?;
+    Ok(get(symbol, prop, env, cx))
+}
+
+/// Set the property PROP of the abbrev table TABLE to VAL.
+#[defun]
+fn abbrev_table_put<'ob>(
+    table: Object<'ob>,
+    prop: Symbol,
+    val: Object<'ob>,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    let (_, symbol) = check_table(table, env, cx)?;
+    env.set_prop(symbol, prop, val);
+    Ok(val)
+}
+
+/// Define ABBREV in TABLE to expand to EXPANSION, and to run HOOK after it is
+/// expanded. PROPS is a plist of properties for the abbrev, like `:count`,
+/// the number of times it has been expanded, and `:system`.
+#[defun]
+fn define_abbrev<'ob>(
+    table: Object<'ob>,
+    abbrev: &str,
+    expansion: Object<'ob>,
+    hook: Option<Object<'ob>>,
+    props: ArgSlice,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<String> {
+    let props: Vec<Object> = Rt::bind_slice(env.stack.arg_slice(props), cx).to_vec();
+    add_abbrev(table, abbrev, expansion, hook, &props, env, cx)?;
+    Ok(abbrev.to_owned())
+}
+
+/// Define `abbrev` in `table` like `define-abbrev`.
+fn add_abbrev<'ob>(
+    table: Object<'ob>,
+    abbrev: &str,
+    expansion: Object<'ob>,
+    hook: Option<Object<'ob>>,
+    props: &[Object<'ob>],
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<()> {
+    let (table, table_symbol) = check_table(table, env, cx)?;
+    let symbol = match lookup(table, abbrev, cx) {
+        Some(symbol) => symbol,
+        None => {
+            let symbol = Symbol::new_uninterned(abbrev, cx);
+            table.insert(intern(abbrev, cx).into(), symbol.into());
+            symbol
+        }
+    };
+    env.set_var(symbol, expansion)?;
+    fset(symbol, hook.unwrap_or(NIL), cx)?;
+    env.set_prop(symbol, sym::KW_COUNT, cx.add(0));
+    put_props(symbol, props, env)?;
+    let modiff = match get(table_symbol, sym::KW_ABBREV_TABLE_MODIFF, env, cx).untag() {
+        ObjectType::Int(modiff) => modiff,
+        _ => 0,
+    };
+    env.set_prop(table_symbol, sym::KW_ABBREV_TABLE_MODIFF, cx.add(modiff + 1));
+    Ok(())
+}
+
+/// Define TABLENAME as a variable holding an abbrev table, creating the table
+/// if it doesn't exist yet. DEFINITIONS is a list of elements of the form
+/// `(ABBREVNAME EXPANSION [HOOK] [PROPS...])`, which are passed to
+/// `define-abbrev`. PROPS are set on the table.
+#[defun]
+fn define_abbrev_table<'ob>(
+    tablename: Symbol<'ob>,
+    definitions: Object<'ob>,
+    docstring: Option<Object<'ob>>,
+    props: ArgSlice,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<()> {
+    let mut props: Vec<Object> = Rt::bind_slice(env.stack.arg_slice(props), cx).to_vec();
+    // A keyword in place of the docstring starts the properties
+    if let Some(keyword) = docstring
+        && matches!(keyword.untag(), ObjectType::Symbol(_))
+    {
+        props.insert(0, keyword);
+    }
+    let table = match env.vars.get(tablename).map(|x| x.bind(cx)) {
+        Some(table) if table_symbol(table, env, cx).is_some() => table,
+        _ => {
+            let table: Object = new_table(&[], env, cx)?.into();
+            env.set_var(tablename, table)?;
+            table
+        }
+    };
+    let (_, symbol) = check_table(table, env, cx)?;
+    put_props(symbol, &props, env)?;
+    let names = env.vars.get(sym::ABBREV_TABLE_NAME_LIST).map_or(NIL, |x| x.bind(cx));
+    if !List::try_from(names)?.elements().any(|x| x.is_ok_and(|x| x == tablename)) {
+        env.set_var(sym::ABBREV_TABLE_NAME_LIST, Cons::new(tablename, names, cx).into())?;
+    }
+    for definition in List::try_from(definitions)? {
+        let args: Vec<_> = List::try_from(definition?)?.elements().collect::<Result<_, _>>()?;
+        ensure!(args.len() >= 2, "Invalid abbrev definition");
+        let name: &str = args[0].try_into()?;
+        let props = args.get(3..).unwrap_or_default();
+        add_abbrev(table, name, args[1], args.get(2).copied(), props, env, cx)?;
+    }
+    Ok(())
+}
+
+/// The abbrev in `table` that the text `name` refers to, if it has an
+/// expansion. Unless the table has the `:case-fixed` property, abbrevs are
+/// looked up in lower case.
+fn abbrev_symbol<'ob>(
+    name: &str,
+    table: Object<'ob>,
+    env: &Rt<Env>,
+    cx: &'ob Context,
+) -> Result<Option<Symbol<'ob>>> {
+    let (table, table_symbol) = check_table(table, env, cx)?;
+    let case_fixed = !get(table_symbol, sym::KW_CASE_FIXED, env, cx).is_nil();
+    let key = if case_fixed { name.to_owned() } else { name.to_lowercase() };
+    let Some(symbol) = lookup(table, &key, cx) else { return Ok(None) };
+    let expands = env.vars.get(symbol).is_some_and(|x| !x.bind(cx).is_nil());
+    Ok(expands.then_some(symbol))
+}
+
+/// The abbrev tables used in the current buffer: `local-abbrev-table`, which
+/// can be a list of tables, followed by `global-abbrev-table`. The global
+/// table is created the first time it is needed.
+fn active_tables<'ob>(env: &mut Rt<Env>, cx: &'ob Context) -> Result<Vec<Object<'ob>>> {
+    let mut tables = Vec::new();
+    let local = env.vars.get(sym::LOCAL_ABBREV_TABLE).map_or(NIL, |x| x.bind(cx));
+    match local.untag() {
+        ObjectType::NIL => {}
+        ObjectType::Cons(list) => {
+            for table in list {
+                tables.push(table?);
+            }
+        }
+        _ => tables.push(local),
+    }
+    let global = env.vars.get(sym::GLOBAL_ABBREV_TABLE).map_or(NIL, |x| x.bind(cx));
+    if table_symbol(global, env, cx).is_some() {
+        tables.push(global);
+    } else {
+        let global: Object = new_table(&[], env, cx)?.into();
+        env.set_var(sym::GLOBAL_ABBREV_TABLE, global)?;
+        tables.push(global);
+    }
+    Ok(tables)
+}
+
+/// Return the expansion of ABBREV in TABLE, or in the tables active in the
+/// current buffer if TABLE is nil.
+#[defun]
+fn abbrev_expansion<'ob>(
+    abbrev: &str,
+    table: Option<Object<'ob>>,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    let tables = match table {
+        Some(table) => vec![table],
+        None => active_tables(env, cx)?,
+    };
+    for table in tables {
+        if let Some(symbol) = abbrev_symbol(abbrev, table, env, cx)? {
+            return Ok(env.vars.get(symbol).map_or(NIL, |x| x.bind(cx)));
+        }
+    }
+    Ok(NIL)
+}
+
+/// Adjust the case of `expansion` to how its abbrev was typed. If `typed` is
+/// all upper case, every word of the expansion is capitalized, or the whole
+/// expansion upcased if it is a single word or `all_caps` is set. If
+/// `typed` only has some capitals, the first letter of the expansion is
+/// capitalized.
+fn fix_case(typed: &str, expansion: &str, all_caps: bool) -> String {
+    if !typed.chars().any(char::is_uppercase) {
+        return expansion.to_owned();
+    }
+    if !typed.chars().any(char::is_lowercase) {
+        let words = expansion.split(|c: char| !c.is_alphanumeric()).filter(|x| !x.is_empty());
+        let mode = if !all_caps && words.count() > 1 {
+            CaseMode::UpcaseInitials
+        } else {
+            CaseMode::Upcase
+        };
+        return casify_string(expansion, mode);
+    }
+    let mut fixed = String::with_capacity(expansion.len());
+    let mut chars = expansion.chars();
+    for c in chars.by_ref() {
+        if c.is_alphanumeric() {
+            fixed.extend(c.to_uppercase());
+            break;
+        }
+        fixed.push(c);
+    }
+    fixed.extend(chars);
+    fixed
+}
+
+/// Expand the abbrev before point, returning the abbrev symbol, or nil if
+/// there was nothing to expand. After the expansion the hook of the abbrev
+/// is run. If the hook is a symbol with a non-nil `no-self-insert' property
+/// and returns nil, this returns nil as if nothing was expanded.
+#[defun]
+pub(crate) fn expand_abbrev<'ob>(env: &mut Rt<Env>, cx: &'ob mut Context) -> Result<Object<'ob>> {
+    let buffer = env.current_buffer.get();
+    let end = buffer.point();
+    let mut start = end;
+    while start > buffer.point_min()
+        && buffer.text.char_at(start - 2).is_some_and(char::is_alphanumeric)
+    {
+        start -= 1;
+    }
+    if start == end {
+        return Ok(NIL);
+    }
+    let (s1, s2) = buffer.slice_with_gap(start, end)?;
+    let name = [s1, s2].concat();
+    let mut found = None;
+    for table in active_tables(env, cx)? {
+        found = abbrev_symbol(&name, table, env, cx)?;
+        if found.is_some() {
+            break;
+        }
+    }
+    let Some(abbrev) = found else { return Ok(NIL) };
+    let expansion = match env.vars.get(abbrev).map(|x| x.bind(cx).untag()) {
+        Some(ObjectType::String(expansion)) => expansion.to_string(),
+        _ => bail!("Invalid expansion for abbrev {name}"),
+    };
+    let count = match get(abbrev, sym::KW_COUNT, env, cx).untag() {
+        ObjectType::Int(count) => count,
+        _ => 0,
+    };
+    env.set_prop(abbrev, sym::KW_COUNT, cx.add(count + 1));
+    env.set_var(sym::LAST_ABBREV, abbrev.into())?;
+    env.set_var(sym::LAST_ABBREV_TEXT, cx.add(name.as_str()))?;
+    env.set_var(sym::LAST_ABBREV_LOCATION, cx.add(start as i64))?;
+    let all_caps = env.vars.get(sym::ABBREV_ALL_CAPS).is_some_and(|x| !x.bind(cx).is_nil());
+    let text = if name == abbrev.name() {
+        expansion
+    } else {
+        fix_case(&name, &expansion, all_caps)
+    };
+    let abbrev: Object = abbrev.into();
+    root!(abbrev, cx);
+    delete_region(start, end, env, cx)?;
+    insert_text(&text, env, cx)?;
+    let ObjectType::Symbol(symbol) = abbrev.bind(cx).untag() else { unreachable!() };
+    let Some(hook) = symbol.func(cx) else { return Ok(abbrev.bind(cx)) };
+    let hook: Object = hook.into();
+    let no_self_insert = match hook.untag() {
+        ObjectType::Symbol(hook) => !get(hook, sym::NO_SELF_INSERT, env, cx).is_nil(),
+        _ => false,
+    };
+    let hook: Function = hook.try_into()?;
+    root!(hook, cx);
+    let value = call!(hook; env, cx)?;
+    if no_self_insert && value.is_nil() {
+        return Ok(NIL);
+    }
+    Ok(abbrev.bind(cx))
+}
+
+/// Expand the abbrev before point when `chr`, which ends a word, is typed in
+/// `abbrev-mode`. Returns true if `chr` should not be inserted, because the
+/// hook of the abbrev is a symbol with a non-nil `no-self-insert' property.
+pub(crate) fn expand_for_insert(chr: char, env: &mut Rt<Env>, cx: &mut Context) -> Result<bool> {
+    let abbrev_mode = env.vars.get(sym::ABBREV_MODE).is_some_and(|x| !x.bind(cx).is_nil());
+    if chr.is_alphanumeric() || !abbrev_mode {
+        return Ok(false);
+    }
+    if expand_abbrev(env, cx)?.is_nil() {
+        return Ok(false);
+    }
+    let abbrev = env.vars.get(sym::LAST_ABBREV).map_or(NIL, |x| x.bind(cx));
+    let ObjectType::Symbol(abbrev) = abbrev.untag() else { return Ok(false) };
+    let Some(hook) = abbrev.func(cx) else { return Ok(false) };
+    Ok(match Object::from(hook).untag() {
+        ObjectType::Symbol(hook) => !get(hook, sym::NO_SELF_INSERT, env, cx).is_nil(),
+        _ => false,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::interpreter::assert_lisp;
+
+    #[test]
+    fn test_fix_case() {
+        assert_eq!(fix_case("foo", "find outer otter", false), "find outer otter");
+        assert_eq!(fix_case("Foo", "find outer otter", false), "Find outer otter");
+        assert_eq!(fix_case("FOO", "find outer otter", false), "Find Outer Otter");
+        assert_eq!(fix_case("FOO", "find outer otter", true), "FIND OUTER OTTER");
+        assert_eq!(fix_case("FOO", "find", false), "FIND");
+    }
+
+    #[test]
+    fn test_expand_abbrev() {
+        assert_lisp(
+            r#"(progn
+                 (define-abbrev-table 'test-abbrev-table
+                   '(("foo" "find outer otter") ("bq" "big question" nil :system t)))
+                 (defalias 'test-abbrev-hook #'(lambda () t))
+                 (put 'test-abbrev-hook 'no-self-insert t)
+                 (define-abbrev test-abbrev-table "x" "xyz" 'test-abbrev-hook)
+                 (setq local-abbrev-table test-abbrev-table)
+                 (setq abbrev-mode t)
+                 (insert "foo")
+                 (self-insert-command 1 ?\s)
+                 (insert "Foo")
+                 (self-insert-command 1 ?.)
+                 (insert " FOO")
+                 (expand-abbrev)
+                 (insert " x")
+                 (self-insert-command 1 ?\s)
+                 (list (buffer-string)
+                       (abbrev-table-p test-abbrev-table)
+                       (abbrev-expansion "bq" test-abbrev-table)
+                       (abbrev-table-get test-abbrev-table :abbrev-table-modiff)
+                       (get (progn (insert " foo") (expand-abbrev)) :count)
+                       last-abbrev-text
+                       (car (memq 'test-abbrev-table abbrev-table-name-list))))"#,
+            r#"("find outer otter Find outer otter. Find Outer Otter xyz"
+                t "big question" 3 4 "foo" test-abbrev-table)"#,
+        );
+    }
+}
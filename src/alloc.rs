@@ -1,6 +1,6 @@
 //! builtin lisp data structures.
 use crate::core::cons::Cons;
-use crate::core::gc::Context;
+use crate::core::gc::{Context, stats};
 use crate::core::object::{
     ByteFn, ByteString, FnArgs, Gc, IntoObject, LispVec, NIL, Object, RecordBuilder, Symbol,
 };
@@ -97,6 +97,15 @@ fn garbage_collect(cx: &mut Context) -> bool {
     true
 }
 
+/// Return a report of the objects allocated so far, by type and by the
+/// builtin function that allocated them. Rune has to be started with
+/// `--debug-alloc` to record allocations.
+#[defun(name = "rune--allocation-report")]
+fn allocation_report() -> Result<String> {
+    ensure!(stats::enabled(), "Allocations are not recorded; start rune with --debug-alloc");
+    Ok(stats::report())
+}
+
 #[cfg(test)]
 mod test {
     use rune_core::macros::root;
@@ -1,5 +1,11 @@
 //! Arithmetic operators.
-use crate::core::object::{Gc, IntoObject, Number, NumberType, ObjectType};
+use crate::core::{
+    env::{ArgSlice, Env, sym},
+    gc::{Context, Rt},
+    object::{Gc, IntoObject, NIL, Number, NumberType, Object, ObjectType},
+};
+use crate::eval::EvalError;
+use anyhow::Result;
 use float_cmp::ApproxEq;
 use rune_macros::defun;
 use std::cmp::PartialEq;
@@ -56,7 +62,7 @@ impl Neg for NumberValue {
     type Output = Self;
     fn neg(self) -> Self::Output {
         match self {
-            NumberValue::Int(x) => NumberValue::Int(-x),
+            NumberValue::Int(x) => NumberValue::Int(x.wrapping_neg()),
             NumberValue::Float(x) => NumberValue::Float(-x),
         }
     }
@@ -65,35 +71,35 @@ impl Neg for NumberValue {
 impl Add for NumberValue {
     type Output = Self;
     fn add(self, rhs: Self) -> Self::Output {
-        arith(self, rhs, Add::add, Add::add)
+        arith(self, rhs, i64::wrapping_add, Add::add)
     }
 }
 
 impl Sub for NumberValue {
     type Output = Self;
     fn sub(self, rhs: Self) -> Self::Output {
-        arith(self, rhs, Sub::sub, Sub::sub)
+        arith(self, rhs, i64::wrapping_sub, Sub::sub)
     }
 }
 
 impl Mul for NumberValue {
     type Output = Self;
     fn mul(self, rhs: Self) -> Self::Output {
-        arith(self, rhs, Mul::mul, Mul::mul)
+        arith(self, rhs, i64::wrapping_mul, Mul::mul)
     }
 }
 
 impl Div for NumberValue {
     type Output = Self;
     fn div(self, rhs: Self) -> Self::Output {
-        arith(self, rhs, Div::div, Div::div)
+        arith(self, rhs, i64::wrapping_div, Div::div)
     }
 }
 
 impl Rem for NumberValue {
     type Output = Self;
     fn rem(self, rhs: Self) -> Self::Output {
-        arith(self, rhs, Rem::rem, Rem::rem)
+        arith(self, rhs, i64::wrapping_rem, Rem::rem)
     }
 }
 
@@ -155,9 +161,32 @@ pub(crate) fn mul(numbers: &[Number]) -> NumberValue {
     numbers.iter().fold(NumberValue::Int(1), |acc, x| acc * x.val())
 }
 
+defsym!(ARITH_ERROR);
+
+/// Signal `arith-error` if `divisor` is an integer zero and `dividend` is an
+/// integer. Dividing a float by zero gives an infinity or NaN instead.
+fn check_divisor(dividend: NumberValue, divisor: NumberValue, env: &mut Rt<Env>) -> Result<()> {
+    if let (NumberValue::Int(_), NumberValue::Int(0)) = (dividend, divisor) {
+        return Err(EvalError::signal(sym::ARITH_ERROR.into(), NIL, env).into());
+    }
+    Ok(())
+}
+
 #[defun(name = "/")]
-pub(crate) fn div(number: Number, divisors: &[Number]) -> NumberValue {
-    divisors.iter().fold(number.val(), |acc, x| acc / x.val())
+pub(crate) fn div(
+    number: Number,
+    divisors: ArgSlice,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<NumberValue> {
+    let divisors: Vec<Object> = Rt::bind_slice(env.stack.arg_slice(divisors), cx).to_vec();
+    let mut quotient = number.val();
+    for divisor in divisors {
+        let divisor = Number::try_from(divisor)?.val();
+        check_divisor(quotient, divisor, env)?;
+        quotient = quotient / divisor;
+    }
+    Ok(quotient)
 }
 
 #[defun(name = "1+")]
@@ -224,14 +253,16 @@ fn logand(int_or_markers: &[Gc<i64>]) -> i64 {
 }
 
 #[defun(name = "mod")]
-pub(crate) fn modulo(x: Number, y: Number) -> NumberValue {
-    x.val() % y.val()
+pub(crate) fn modulo(x: Number, y: Number, env: &mut Rt<Env>) -> Result<NumberValue> {
+    check_divisor(x.val(), y.val(), env)?;
+    Ok(x.val() % y.val())
 }
 
 #[defun(name = "%")]
-pub(crate) fn remainder(x: i64, y: i64) -> i64 {
+pub(crate) fn remainder(x: i64, y: i64, env: &mut Rt<Env>) -> Result<i64> {
     // TODO: Handle markers
-    x % y
+    check_divisor(NumberValue::Int(x), NumberValue::Int(y), env)?;
+    Ok(x.wrapping_rem(y))
 }
 
 #[expect(clippy::trivially_copy_pass_by_ref)]
@@ -260,6 +291,7 @@ pub(crate) fn min(number_or_marker: Number, number_or_markers: &[Number]) -> Num
 mod test {
     use super::*;
     use crate::core::gc::{Context, RootSet};
+    use crate::interpreter::assert_lisp;
 
     #[test]
     fn test_add() {
@@ -288,11 +320,13 @@ mod test {
 
     #[test]
     fn test_div() {
-        let roots = &RootSet::default();
-        let cx = &Context::new(roots);
-
-        assert_eq!(div(cx.add_as(12.0), &[]), NumberValue::Float(12.0));
-        assert_eq!(div(12.into(), &[5.into(), 2.into()]), NumberValue::Int(1));
+        assert_lisp("(/ 12.0)", "12.0");
+        assert_lisp("(/ 12 5 2)", "1");
+        assert_lisp("(condition-case nil (/ 1 0) (arith-error 'zero))", "zero");
+        assert_lisp("(condition-case nil (% 1 0) (arith-error 'zero))", "zero");
+        assert_lisp("(condition-case nil (mod 1 0) (arith-error 'zero))", "zero");
+        assert_lisp("(/ 1.0 0)", "1.0e+INF");
+        assert_lisp("(integerp (* 36028797018963967 36028797018963967))", "t");
     }
 
     #[test]
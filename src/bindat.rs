@@ -0,0 +1,255 @@
+//! Packing and unpacking fixed-layout binary data.
+//!
+//! These implement the subset of `bindat` specs where every field has a
+//! fixed size, which covers most network protocol headers. A spec is a list
+//! of `(NAME TYPE)` fields, where TYPE is one of `u8`, `byte`, `u16`, `u24`,
+//! `u32`, their little endian `r` variants, `uint BITS`, `uintr BITS`,
+//! `str LEN`, `strz LEN`, `vec LEN [TYPE]`, `ip` or `fill LEN`. Multi-byte
+//! integers are big endian unless noted otherwise.
+use crate::core::{
+    cons::Cons,
+    env::{Env, sym},
+    gc::{Context, Rt},
+    object::{List, NIL, Object, ObjectType, Symbol},
+};
+use crate::fns::{assq, slice_into_list};
+use anyhow::{Result, bail, ensure};
+use rune_macros::defun;
+
+defsym!(FILL);
+
+#[derive(Debug, Clone, PartialEq)]
+enum FieldType {
+    Uint { bytes: usize, little: bool },
+    Str(usize),
+    Strz(usize),
+    Vec(usize, Box<FieldType>),
+    Fill(usize),
+}
+
+impl FieldType {
+    const BYTE: Self = FieldType::Uint { bytes: 1, little: false };
+
+    /// Parse a type from the elements of a field spec after the name.
+    fn parse(spec: &[Object]) -> Result<Self> {
+        let Some((ty, args)) = spec.split_first() else { bail!("Missing bindat field type") };
+        let ObjectType::Symbol(ty) = ty.untag() else { bail!("Invalid bindat field type: {ty}") };
+        let uint = |bytes, little| FieldType::Uint { bytes, little };
+        let field_type = match (ty.name(), args) {
+            ("u8" | "byte", []) => Self::BYTE,
+            ("u16", []) => uint(2, false),
+            ("u24", []) => uint(3, false),
+            ("u32", []) => uint(4, false),
+            ("u16r", []) => uint(2, true),
+            ("u24r", []) => uint(3, true),
+            ("u32r", []) => uint(4, true),
+            (name @ ("uint" | "uintr"), [bits]) => {
+                let bits: usize = (*bits).try_into()?;
+                ensure!(
+                    bits.is_multiple_of(8) && (8..=64).contains(&bits),
+                    "Invalid bindat integer size: {bits}"
+                );
+                uint(bits / 8, name == "uintr")
+            }
+            ("str", [len]) => FieldType::Str((*len).try_into()?),
+            ("strz", [len]) => FieldType::Strz((*len).try_into()?),
+            ("vec", [len]) => FieldType::Vec((*len).try_into()?, Box::new(Self::BYTE)),
+            ("vec", [len, ty @ ..]) => {
+                FieldType::Vec((*len).try_into()?, Box::new(Self::parse(ty)?))
+            }
+            ("ip", []) => FieldType::Vec(4, Box::new(Self::BYTE)),
+            ("fill", [len]) => FieldType::Fill((*len).try_into()?),
+            _ => bail!("Unsupported bindat field type: {ty}"),
+        };
+        Ok(field_type)
+    }
+
+    /// The number of bytes the field takes up.
+    fn size(&self) -> usize {
+        match self {
+            FieldType::Uint { bytes, .. } => *bytes,
+            FieldType::Str(len) | FieldType::Strz(len) | FieldType::Fill(len) => *len,
+            FieldType::Vec(len, ty) => len * ty.size(),
+        }
+    }
+
+    /// Unpack the field from `raw`, which is exactly the size of the field.
+    fn unpack<'ob>(&self, raw: &[u8], cx: &'ob Context) -> Object<'ob> {
+        match self {
+            FieldType::Uint { little, .. } => {
+                let fold = |acc: u64, byte: &u8| (acc << 8) | u64::from(*byte);
+                let value =
+                    if *little { raw.iter().rev().fold(0, fold) } else { raw.iter().fold(0, fold) };
+                cx.add(value as i64)
+            }
+            FieldType::Str(_) => cx.add(raw.to_vec()),
+            FieldType::Strz(_) => {
+                let end = raw.iter().position(|x| *x == 0).unwrap_or(raw.len());
+                cx.add(raw[..end].to_vec())
+            }
+            FieldType::Vec(_, ty) => {
+                let elements: Vec<_> =
+                    raw.chunks(ty.size().max(1)).map(|x| ty.unpack(x, cx)).collect();
+                cx.add(elements)
+            }
+            FieldType::Fill(_) => NIL,
+        }
+    }
+
+    /// Append the packed representation of `value` to `out`. A `nil` value
+    /// is packed as zeros.
+    fn pack(&self, value: Object, out: &mut Vec<u8>) -> Result<()> {
+        let start = out.len();
+        match (self, value.untag()) {
+            (_, ObjectType::NIL) | (FieldType::Fill(_), _) => {}
+            (FieldType::Uint { bytes, little }, ObjectType::Int(int)) => {
+                let be_bytes = (int as u64).to_be_bytes();
+                let be_bytes = &be_bytes[be_bytes.len() - bytes..];
+                if *little {
+                    out.extend(be_bytes.iter().rev());
+                } else {
+                    out.extend(be_bytes);
+                }
+            }
+            (FieldType::Str(len) | FieldType::Strz(len), _) => {
+                let Ok(bytes) = <&[u8]>::try_from(value) else {
+                    bail!("Invalid bindat string value: {value}")
+                };
+                out.extend(&bytes[..bytes.len().min(*len)]);
+            }
+            (FieldType::Vec(len, ty), ObjectType::Vec(vec)) => {
+                for element in vec.iter().take(*len) {
+                    ty.pack(element.get(), out)?;
+                }
+            }
+            (FieldType::Vec(len, ty), ObjectType::Cons(cons)) => {
+                for element in cons.elements().take(*len) {
+                    ty.pack(element?, out)?;
+                }
+            }
+            _ => bail!("Invalid bindat value {value} for field type {self:?}"),
+        }
+        // pad the rest of the field with zeros
+        out.resize(start + self.size(), 0);
+        Ok(())
+    }
+}
+
+struct Field<'ob> {
+    name: Option<Symbol<'ob>>,
+    ty: FieldType,
+}
+
+/// Parse a spec into its fields.
+fn parse_spec(spec: List) -> Result<Vec<Field>> {
+    let mut fields = Vec::new();
+    for field in spec {
+        let elements: Vec<_> = field?.as_list()?.collect::<Result<_, _>>()?;
+        let field = match elements.as_slice() {
+            [ty, _] if *ty == sym::FILL => Field { name: None, ty: FieldType::parse(&elements)? },
+            [name, ty @ ..] => {
+                let ObjectType::Symbol(name) = name.untag() else {
+                    bail!("Invalid bindat field name: {name}")
+                };
+                Field { name: Some(name), ty: FieldType::parse(ty)? }
+            }
+            [] => bail!("Empty bindat field spec"),
+        };
+        fields.push(field);
+    }
+    Ok(fields)
+}
+
+/// Unpack the bytes of RAW, starting at IDX, according to SPEC. Return an
+/// alist of each named field and its value.
+#[defun]
+fn bindat_unpack<'ob>(
+    spec: List,
+    raw: &[u8],
+    idx: Option<usize>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    let mut pos = idx.unwrap_or(0);
+    let mut alist: Vec<Object> = Vec::new();
+    for field in parse_spec(spec)? {
+        let end = pos + field.ty.size();
+        ensure!(end <= raw.len(), "bindat data is too short: {} bytes", raw.len());
+        if let Some(name) = field.name {
+            let value = field.ty.unpack(&raw[pos..end], cx);
+            alist.push(Cons::new(name, value, cx).into());
+        }
+        pos = end;
+    }
+    Ok(slice_into_list(&alist, None, cx))
+}
+
+/// Pack the values in the alist STRUCTURE according to SPEC and return them as
+/// a unibyte string. Fields missing from STRUCTURE are filled with zeros.
+#[defun]
+fn bindat_pack(spec: List, structure: List, env: &Rt<Env>, cx: &Context) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    for field in parse_spec(spec)? {
+        let value = match field.name {
+            Some(name) => match assq(name.into(), structure, env, cx)?.untag() {
+                ObjectType::Cons(cons) => cons.cdr(),
+                _ => NIL,
+            },
+            None => NIL,
+        };
+        field.ty.pack(value, &mut out)?;
+    }
+    Ok(out)
+}
+
+/// Return the number of bytes that SPEC takes up.
+#[defun]
+fn bindat_length(spec: List, _structure: Object) -> Result<usize> {
+    Ok(parse_spec(spec)?.iter().map(|x| x.ty.size()).sum())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::interpreter::assert_lisp;
+
+    #[test]
+    fn test_bindat_unpack() {
+        assert_lisp(
+            "(bindat-unpack '((a u8) (b u16) (c u16r) (d uint 24) (fill 1) (e ip))
+                            (unibyte-string 1 2 3 4 5 0 0 6 9 127 0 0 1))",
+            "((a . 1) (b . 515) (c . 1284) (d . 6) (e . [127 0 0 1]))",
+        );
+        assert_lisp("(bindat-unpack '((n u32)) (unibyte-string 9 0 0 0 1 0) 2)", "((n . 256))");
+        assert_lisp(
+            r#"(let ((s (bindat-unpack '((a str 3) (b strz 4)) (unibyte-string 97 98 99 100 101 0 102 103))))
+                 (list (equal (cdr (assq 'a s)) (string-to-unibyte "abc"))
+                       (equal (cdr (assq 'b s)) (string-to-unibyte "de"))))"#,
+            "(t t)",
+        );
+        assert_lisp("(bindat-unpack '((v vec 2 u16)) (unibyte-string 0 1 1 0))", "((v . [1 256]))");
+        assert_lisp(
+            "(condition-case nil (bindat-unpack '((a u32)) (unibyte-string 1)) (error 'short))",
+            "short",
+        );
+    }
+
+    #[test]
+    fn test_bindat_pack() {
+        assert_lisp(
+            "(equal (bindat-pack '((a u8) (b u16) (c u16r) (fill 1) (d str 3) (e vec 2))
+                                 '((a . 1) (b . 515) (c . 1284) (d . \"xy\") (e . [7 8])))
+                    (unibyte-string 1 2 3 4 5 0 120 121 0 7 8))",
+            "t",
+        );
+        assert_lisp("(equal (bindat-pack '((a u16) (b u8)) nil) (unibyte-string 0 0 0))", "t");
+        assert_lisp("(bindat-length '((a u16) (b vec 3 u32) (c ip)) nil)", "18");
+    }
+
+    #[test]
+    fn test_unibyte() {
+        assert_lisp(r#"(equal (string-to-unibyte "ab") (unibyte-string 97 98))"#, "t");
+        assert_lisp(r#"(condition-case nil (string-to-unibyte "é") (error 'err))"#, "err");
+        assert_lisp("(multibyte-char-to-unibyte ?a)", "97");
+        assert_lisp("(multibyte-char-to-unibyte 4194303)", "255");
+        assert_lisp("(multibyte-char-to-unibyte ?é)", "-1");
+    }
+}
@@ -1,21 +1,31 @@
 //! Buffer operations.
 use crate::{
     core::{
-        env::{Env, INTERNED_SYMBOLS},
+        cons::Cons,
+        env::{ArgSlice, Env, INTERNED_SYMBOLS, sym},
         error::{Type, TypeError},
-        gc::{Context, Rt},
-        object::{Gc, LispBuffer, NIL, Object, ObjectType, OptionalFlag},
+        gc::{Context, Rt, Rto},
+        object::{
+            Gc, LispBuffer, List, NIL, Object, ObjectType, OpenBuffer, OptionalFlag, Record,
+            RecordBuilder, Symbol,
+        },
     },
-    fns::slice_into_list,
+    data::get,
+    eval::{run_hook, run_hook_functions},
+    fileio::expand_file_name,
+    fns::{copy_sequence, plist_get, slice_into_list},
+    library::interval_tree::Interval,
 };
-use anyhow::{Result, bail};
-use rune_core::hashmap::HashMap;
+use anyhow::{Result, bail, ensure};
+use rune_core::hashmap::{HashMap, IndexMap};
+use rune_core::macros::root;
 use rune_macros::defun;
 use std::sync::LazyLock;
 use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
-type BufferMap = HashMap<String, &'static LispBuffer>;
-// static hashmap containing all the buffers
+type BufferMap = IndexMap<String, &'static LispBuffer>;
+// static map containing all the live buffers, most recently selected first
 pub(crate) static BUFFERS: LazyLock<Mutex<BufferMap>> = LazyLock::new(Mutex::default);
 
 #[defun]
@@ -25,11 +35,29 @@ pub(crate) fn set_buffer<'ob>(
     cx: &'ob Context,
 ) -> Result<Object<'ob>> {
     let buffer = resolve_buffer(buffer_or_name, cx)?;
-    env.set_buffer(buffer);
+    ensure!(env.with_buffer(buffer, |_| {}).is_ok(), "Selecting deleted buffer");
+    env.set_buffer(buffer, cx);
+    record_buffer(buffer);
     Ok(cx.add(buffer))
 }
 
-fn resolve_buffer<'ob>(buffer_or_name: Object, cx: &'ob Context) -> Result<&'ob LispBuffer> {
+/// Move `buffer` to the front of the buffer list.
+fn record_buffer(buffer: &LispBuffer) {
+    let mut buffer_list = BUFFERS.lock().unwrap();
+    if let Some(idx) = buffer_list.values().position(|b| *b == buffer) {
+        buffer_list.move_index(idx, 0);
+    }
+}
+
+#[defun]
+fn current_buffer<'ob>(env: &Rt<Env>, cx: &'ob Context) -> Object<'ob> {
+    cx.add(env.current_buffer.get().lisp_buffer(cx))
+}
+
+pub(crate) fn resolve_buffer<'ob>(
+    buffer_or_name: Object,
+    cx: &'ob Context,
+) -> Result<&'ob LispBuffer> {
     match buffer_or_name.untag() {
         ObjectType::Buffer(b) => Ok(b),
         ObjectType::String(name) => {
@@ -43,12 +71,45 @@ fn resolve_buffer<'ob>(buffer_or_name: Object, cx: &'ob Context) -> Result<&'ob
     }
 }
 
+/// Call `func` with BUFFER, or the current buffer if it is nil.
+fn with_buffer_or_current<T>(
+    buffer: Option<Gc<&LispBuffer>>,
+    env: &Rt<Env>,
+    mut func: impl FnMut(&OpenBuffer) -> T,
+) -> Result<T> {
+    match buffer {
+        Some(buffer) => env.with_buffer(buffer.untag(), func),
+        None => Ok(func(env.current_buffer.get())),
+    }
+}
+
+#[defun]
+fn buffer_modified_p(buffer: Option<Gc<&LispBuffer>>, env: &Rt<Env>) -> Result<bool> {
+    with_buffer_or_current(buffer, env, |b| b.modified_p())
+}
+
 #[defun]
-fn set_buffer_modified_p(flag: Object) -> Object {
-    // TODO: implement
+fn set_buffer_modified_p<'ob>(flag: Object<'ob>, env: &mut Rt<Env>) -> Object<'ob> {
+    // TODO: update the mode line once there is a display
+    restore_buffer_modified_p(flag, env)
+}
+
+#[defun]
+fn restore_buffer_modified_p<'ob>(flag: Object<'ob>, env: &mut Rt<Env>) -> Object<'ob> {
+    env.current_buffer.get_mut().set_modified_p(!flag.is_nil());
     flag
 }
 
+#[defun]
+fn buffer_modified_tick(buffer: Option<Gc<&LispBuffer>>, env: &Rt<Env>) -> Result<u64> {
+    with_buffer_or_current(buffer, env, |b| b.modiff)
+}
+
+#[defun]
+fn buffer_chars_modified_tick(buffer: Option<Gc<&LispBuffer>>, env: &Rt<Env>) -> Result<u64> {
+    with_buffer_or_current(buffer, env, |b| b.chars_modiff)
+}
+
 #[defun]
 fn buffer_live_p(buffer: Object, env: &Rt<Env>) -> bool {
     match buffer.untag() {
@@ -58,11 +119,39 @@ fn buffer_live_p(buffer: Object, env: &Rt<Env>) -> bool {
 }
 
 #[defun]
-fn buffer_name(buffer: Option<Gc<&LispBuffer>>, env: &Rt<Env>) -> Result<String> {
-    match buffer {
-        Some(buffer) => env.with_buffer(buffer.untag(), |b| b.name.to_string()),
-        None => Ok(env.current_buffer.get().name.to_string()),
+fn buffer_name(buffer: Option<Gc<&LispBuffer>>, env: &Rt<Env>) -> Option<String> {
+    // killed buffers have no name
+    with_buffer_or_current(buffer, env, |b| b.name.to_string()).ok()
+}
+
+/// Return the name of the file BUFFER is visiting, or nil if it is not
+/// visiting a file. BUFFER defaults to the current buffer.
+#[defun]
+fn buffer_file_name<'ob>(
+    buffer: Option<Gc<&LispBuffer>>,
+    env: &Rt<Env>,
+    cx: &'ob Context,
+) -> Object<'ob> {
+    let buffer = match buffer {
+        Some(buffer) => buffer.untag(),
+        None => env.current_buffer.get().lisp_buffer(cx),
+    };
+    env.buffer_local_value(sym::BUFFER_FILE_NAME, buffer, cx)
+}
+
+/// Return the buffer visiting file FILENAME, or nil if there is none.
+#[defun]
+fn get_file_buffer<'ob>(filename: &str, env: &Rt<Env>, cx: &'ob Context) -> Result<Object<'ob>> {
+    let filename = expand_file_name(filename, None, env, cx)?;
+    let buffers: Vec<_> = BUFFERS.lock().unwrap().values().map(|x| cx.bind(*x)).collect();
+    for buffer in buffers {
+        let visited = env.buffer_local_value(sym::BUFFER_FILE_NAME, buffer, cx);
+        let ObjectType::String(name) = visited.untag() else { continue };
+        if expand_file_name(name, None, env, cx)? == filename {
+            return Ok(cx.add(buffer));
+        }
     }
+    Ok(NIL)
 }
 
 #[defun]
@@ -72,9 +161,9 @@ fn rename_buffer(newname: &str, unique: OptionalFlag, env: &mut Rt<Env>) -> Resu
         return Ok(newname.to_string());
     }
     let mut buffer_list = BUFFERS.lock().unwrap();
-    let mut replace_buffer = |buffer_list: &mut HashMap<_, _>, newname: &str| {
-        let buffer = buffer_list.remove(&buf.name).unwrap();
-        buffer_list.insert(newname.into(), buffer);
+    let mut replace_buffer = |buffer_list: &mut BufferMap, newname: &str| {
+        let (idx, _, buffer) = buffer_list.shift_remove_full(&buf.name).unwrap();
+        buffer_list.shift_insert(idx, newname.into(), buffer);
         buf.name = newname.to_string();
     };
     if buffer_list.contains_key(newname) {
@@ -99,6 +188,7 @@ pub(crate) fn get_buffer_create<'ob>(
 ) -> Result<Object<'ob>> {
     match buffer_or_name.untag() {
         ObjectType::String(name) => {
+            ensure!(!name.is_empty(), "Empty string for buffer name is not allowed");
             let mut buffer_list = BUFFERS.lock().unwrap();
             match buffer_list.get(name.as_ref()) {
                 Some(b) => Ok(cx.add(*b)),
@@ -177,20 +267,62 @@ fn unique_buffer_name(name: &str, ignore: Option<&str>, buffer_list: &BufferMap)
     new_name
 }
 
+/// Kill the buffer specified by BUFFER-OR-NAME.
+///
+/// `kill-buffer-hook` is run with the buffer current before it is killed. If
+/// the buffer being killed is current, another live buffer is selected in its
+/// place. Return t if the buffer was killed and nil if it was already dead.
 #[defun]
-fn kill_buffer(buffer_or_name: Option<Object>, cx: &Context, env: &mut Rt<Env>) -> bool {
-    match buffer_or_name {
-        Some(buffer) => match resolve_buffer(buffer, cx) {
-            Ok(b) => env.with_buffer_mut(b, |b| b.kill()).unwrap_or(false),
-            Err(_) => false,
-        },
-        None => {
-            let killed = env.current_buffer.get_mut().kill();
-            // todo, we need to select a new buffer
-            env.current_buffer.release();
-            killed
-        }
+fn kill_buffer(
+    buffer_or_name: Option<&Rto<Object>>,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<bool> {
+    let buffer = match buffer_or_name {
+        Some(x) => resolve_buffer(x.bind(cx), cx)?,
+        None => env.current_buffer.get().lisp_buffer(cx),
+    };
+    if env.with_buffer(buffer, |_| {}).is_err() {
+        return Ok(false);
+    }
+    root!(buffer, cx);
+    let current = env.current_buffer.get().lisp_buffer(cx);
+    root!(current, cx);
+
+    env.set_buffer(buffer.bind(cx), cx);
+    let hook = env.vars.get(sym::KILL_BUFFER_HOOK).map(|x| x.bind(cx)).unwrap_or_default();
+    root!(hook, cx);
+    let result = run_hook_functions(hook, &[], env, cx);
+    let current = current.bind(cx);
+    if env.with_buffer(current, |_| {}).is_ok() {
+        env.set_buffer(current, cx);
+    }
+    result?;
+
+    let buffer = buffer.bind(cx);
+    // the hook may have killed the buffer itself
+    if env.with_buffer(buffer, |_| {}).is_err() {
+        return Ok(false);
+    }
+    detach_overlays(buffer, env, cx)?;
+    let other = {
+        let mut buffer_list = BUFFERS.lock().unwrap();
+        buffer_list.retain(|_, b| *b != buffer);
+        buffer_list.first().map(|(_, b)| cx.bind(*b))
+    };
+    if env.current_buffer == *buffer {
+        let other = match other {
+            Some(other) => other,
+            None => {
+                let scratch = get_buffer_create(cx.add("*scratch*"), None, cx)?;
+                let ObjectType::Buffer(scratch) = scratch.untag() else { unreachable!() };
+                scratch
+            }
+        };
+        env.set_buffer(other, cx);
     }
+    env.remove_buffer_locals(buffer, cx);
+    env.with_buffer_mut(buffer, |b| b.kill())
 }
 
 #[defun]
@@ -199,39 +331,439 @@ fn buffer_base_buffer(_buffer: OptionalFlag) -> bool {
     false
 }
 
-#[defun]
-fn get_file_buffer(_filename: &str) -> bool {
-    // TODO: implement file buffers
-    false
-}
-
 #[defun]
 fn buffer_list<'ob>(_frame: OptionalFlag, cx: &'ob Context) -> Object<'ob> {
     // TODO: implement frame parameter
     // TODO: remove this temp vector
     let mut buffer_list: Vec<Object> = Vec::new();
+    // buffers are kept in the order they were last selected
     for buffer in BUFFERS.lock().unwrap().values() {
         buffer_list.push(cx.add(*buffer));
     }
     slice_into_list(&buffer_list, None, cx)
 }
 
-// TODO: buffer local
-defvar!(FILL_COLUMN, 70);
-defvar!(INDENT_TABS_MODE);
-defvar!(LEFT_MARGIN, 0);
+// Overlays are represented as records of the form
+// `#s(overlay BUFFER PLIST ID FRONT-ADVANCE REAR-ADVANCE)`. Their positions
+// live in the interval tree of BUFFER under ID, so that they are adjusted as
+// the text is edited. A deleted overlay has a nil BUFFER. Overlays that are
+// attached to a buffer are kept alive by the environment so they can be found
+// again from the tree.
+const OVERLAY_BUFFER: usize = 1;
+const OVERLAY_PLIST: usize = 2;
+const OVERLAY_ID: usize = 3;
+const OVERLAY_FRONT_ADVANCE: usize = 4;
+const OVERLAY_REAR_ADVANCE: usize = 5;
+
+static NEXT_OVERLAY_ID: AtomicUsize = AtomicUsize::new(0);
+
+fn as_overlay<'ob>(obj: Object<'ob>) -> Result<&'ob Record> {
+    match obj.untag() {
+        ObjectType::Record(rec) if rec.first().is_some_and(|x| x.get() == sym::OVERLAY) => Ok(rec),
+        _ => Err(TypeError::new(Type::Overlay, obj).into()),
+    }
+}
+
+/// The buffer and id of an overlay, or `None` if it has been deleted.
+fn overlay_location(overlay: &Record) -> Result<Option<(&LispBuffer, usize)>> {
+    let ObjectType::Buffer(buffer) = overlay[OVERLAY_BUFFER].get().untag() else {
+        return Ok(None);
+    };
+    let id = overlay[OVERLAY_ID].get().try_into()?;
+    Ok(Some((buffer, id)))
+}
+
+/// The current interval of an overlay, or `None` if it has been deleted.
+fn overlay_interval(overlay: Object, env: &Rt<Env>) -> Result<Option<Interval>> {
+    let Some((buffer, id)) = overlay_location(as_overlay(overlay)?)? else { return Ok(None) };
+    Ok(env.with_buffer(buffer, |b| b.overlays.get(id).copied()).ok().flatten())
+}
+
+/// Add the overlay to `buffer` between the 1-based positions `beg` and `end`,
+/// which may be given in either order.
+fn attach_overlay(overlay: &Record, beg: usize, end: usize, buffer: &mut OpenBuffer) -> Result<()> {
+    let (beg, end) = if beg <= end { (beg, end) } else { (end, beg) };
+    let (start, end) = (buffer.clip_to_buffer(beg), buffer.clip_to_buffer(end));
+    buffer.overlays.insert(Interval {
+        id: overlay[OVERLAY_ID].get().try_into()?,
+        start,
+        end,
+        front_advance: !overlay[OVERLAY_FRONT_ADVANCE].get().is_nil(),
+        rear_advance: !overlay[OVERLAY_REAR_ADVANCE].get().is_nil(),
+    });
+    Ok(())
+}
+
+/// Find the overlay objects for `ids` in `buffer`, in the same order.
+fn find_overlays<'ob>(
+    buffer: &LispBuffer,
+    ids: &[usize],
+    env: &Rt<Env>,
+    cx: &'ob Context,
+) -> Vec<Object<'ob>> {
+    let index: HashMap<usize, usize> = ids.iter().enumerate().map(|(i, id)| (*id, i)).collect();
+    let mut found = vec![NIL; ids.len()];
+    for overlay in env.overlays.iter() {
+        let overlay = overlay.bind(cx);
+        let Ok(Some((owner, id))) = as_overlay(overlay).and_then(overlay_location) else {
+            continue;
+        };
+        if owner == buffer
+            && let Some(idx) = index.get(&id)
+        {
+            found[*idx] = overlay;
+        }
+    }
+    found
+}
+
+fn forget_overlay(overlay: Object, env: &mut Rt<Env>, cx: &Context) {
+    if let Some(idx) = env.overlays.iter().position(|x| x.bind(cx) == overlay) {
+        env.overlays.swap_remove(idx);
+    }
+}
+
+#[defun]
+fn overlayp(object: Object) -> bool {
+    as_overlay(object).is_ok()
+}
+
+/// Create a new overlay in BUFFER between BEG and END.
+///
+/// If FRONT-ADVANCE is non-nil, text inserted at the beginning of the overlay
+/// is excluded from it. If REAR-ADVANCE is non-nil, text inserted at the end
+/// of the overlay is included in it.
+#[defun]
+fn make_overlay<'ob>(
+    beg: usize,
+    end: usize,
+    buffer: Option<Gc<&LispBuffer>>,
+    front_advance: Option<Object<'ob>>,
+    rear_advance: Option<Object<'ob>>,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    let buffer = match buffer {
+        Some(buffer) => buffer.untag(),
+        None => env.current_buffer.get().lisp_buffer(cx),
+    };
+    let id = NEXT_OVERLAY_ID.fetch_add(1, Ordering::Relaxed);
+    let mut record = cx.vec_with_capacity(OVERLAY_REAR_ADVANCE + 1);
+    record.extend([
+        sym::OVERLAY.into(),
+        cx.add(buffer),
+        NIL,
+        id.into(),
+        front_advance.unwrap_or_default(),
+        rear_advance.unwrap_or_default(),
+    ]);
+    let overlay: Object = cx.add(RecordBuilder(record));
+    env.with_buffer_mut(buffer, |b| attach_overlay(as_overlay(overlay)?, beg, end, b))??;
+    env.overlays.push(overlay);
+    Ok(overlay)
+}
+
+#[defun]
+fn overlay_start(overlay: Object, env: &Rt<Env>) -> Result<Option<usize>> {
+    Ok(overlay_interval(overlay, env)?.map(|x| x.start + 1))
+}
+
+#[defun]
+fn overlay_end(overlay: Object, env: &Rt<Env>) -> Result<Option<usize>> {
+    Ok(overlay_interval(overlay, env)?.map(|x| x.end + 1))
+}
+
+#[defun]
+fn overlay_buffer(overlay: Object) -> Result<Object> {
+    Ok(as_overlay(overlay)?[OVERLAY_BUFFER].get())
+}
+
+#[defun]
+fn overlay_properties<'ob>(overlay: Object<'ob>, cx: &'ob Context) -> Result<Object<'ob>> {
+    copy_sequence(as_overlay(overlay)?[OVERLAY_PLIST].get(), cx)
+}
+
+#[defun]
+fn overlay_get<'ob>(overlay: Object<'ob>, prop: Object<'ob>) -> Result<Object<'ob>> {
+    plist_get(as_overlay(overlay)?[OVERLAY_PLIST].get(), prop)
+}
+
+#[defun]
+fn overlay_put<'ob>(
+    overlay: Object<'ob>,
+    prop: Object<'ob>,
+    value: Object<'ob>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    let overlay = as_overlay(overlay)?;
+    let plist = overlay[OVERLAY_PLIST].get();
+    let mut conses = List::try_from(plist)?.conses();
+    while let Some(key) = conses.next() {
+        let Some(val) = conses.next() else { break };
+        if key?.car().ptr_eq(prop) {
+            val?.set_car(value)?;
+            return Ok(value);
+        }
+    }
+    let plist = Cons::new(prop, Cons::new(value, plist, cx), cx);
+    overlay.try_mut()?[OVERLAY_PLIST].set(plist.into());
+    Ok(value)
+}
+
+/// Delete OVERLAY from its buffer. It can be added to a buffer again with
+/// `move-overlay`.
+#[defun]
+fn delete_overlay(overlay: Object, env: &mut Rt<Env>, cx: &Context) -> Result<()> {
+    let record = as_overlay(overlay)?;
+    if let Some((buffer, id)) = overlay_location(record)? {
+        // If the buffer was killed its overlays are already gone
+        let _ = env.with_buffer_mut(buffer, |b| b.overlays.remove(id));
+        record.try_mut()?[OVERLAY_BUFFER].set(NIL);
+    }
+    forget_overlay(overlay, env, cx);
+    Ok(())
+}
+
+/// Set the endpoints of OVERLAY to BEG and END in BUFFER. If BUFFER is nil the
+/// overlay stays in its current buffer, or the current buffer if it was
+/// deleted.
+#[defun]
+fn move_overlay<'ob>(
+    overlay: Object<'ob>,
+    beg: usize,
+    end: usize,
+    buffer: Option<Gc<&LispBuffer>>,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    let record = as_overlay(overlay)?;
+    let location = overlay_location(record)?;
+    let buffer = match (buffer, location) {
+        (Some(buffer), _) => buffer.untag(),
+        (None, Some((buffer, _))) => buffer,
+        (None, None) => env.current_buffer.get().lisp_buffer(cx),
+    };
+    ensure!(
+        env.with_buffer(buffer, |_| {}).is_ok(),
+        "Attempt to move overlay to a dead buffer"
+    );
+    match location {
+        Some((old, id)) => {
+            let _ = env.with_buffer_mut(old, |b| b.overlays.remove(id));
+        }
+        None => env.overlays.push(overlay),
+    }
+    env.with_buffer_mut(buffer, |b| attach_overlay(record, beg, end, b))??;
+    record.try_mut()?[OVERLAY_BUFFER].set(cx.add(buffer));
+    Ok(overlay)
+}
+
+/// Return a list of the overlays that contain the character at POS. If SORTED
+/// is non-nil, the list is in decreasing order of priority.
+#[defun]
+fn overlays_at<'ob>(
+    pos: usize,
+    sorted: OptionalFlag,
+    env: &Rt<Env>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    let buffer = env.current_buffer.get();
+    let pos = buffer.clip_to_buffer(pos);
+    let ids: Vec<_> = buffer
+        .overlays
+        .overlapping(pos, pos)
+        .iter()
+        .filter(|x| x.end > pos)
+        .map(|x| x.id)
+        .collect();
+    let mut overlays = find_overlays(buffer.lisp_buffer(cx), &ids, env, cx);
+    if sorted.is_some() {
+        let priority = |x: &Object| match overlay_get(*x, sym::PRIORITY.into()).map(|x| x.untag()) {
+            Ok(ObjectType::Int(priority)) => priority,
+            _ => 0,
+        };
+        overlays.sort_by_key(|x| std::cmp::Reverse(priority(x)));
+    }
+    Ok(slice_into_list(&overlays, None, cx))
+}
+
+/// Return a list of the overlays that overlap the region between BEG and END.
+///
+/// Empty overlays are included if they are at BEG, strictly between BEG and
+/// END, or at END when END is the end of the accessible part of the buffer.
+#[defun]
+fn overlays_in<'ob>(beg: usize, end: usize, env: &Rt<Env>, cx: &'ob Context) -> Object<'ob> {
+    let buffer = env.current_buffer.get();
+    let at_max = beg.max(end) >= buffer.point_max();
+    let (beg, end) = (buffer.clip_to_buffer(beg), buffer.clip_to_buffer(end));
+    let (beg, end) = if beg <= end { (beg, end) } else { (end, beg) };
+    let ids: Vec<_> = buffer
+        .overlays
+        .overlapping(beg, end)
+        .iter()
+        .filter(|x| {
+            if x.start == x.end {
+                x.start < end || at_max || beg == end
+            } else if beg == end {
+                x.end > beg
+            } else {
+                x.start < end && x.end > beg
+            }
+        })
+        .map(|x| x.id)
+        .collect();
+    let overlays = find_overlays(buffer.lisp_buffer(cx), &ids, env, cx);
+    slice_into_list(&overlays, None, cx)
+}
+
+/// Delete all the overlays in BUFFER, or the current buffer if it is nil.
+#[defun]
+fn delete_all_overlays(
+    buffer: Option<Gc<&LispBuffer>>,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<()> {
+    let buffer = match buffer {
+        Some(buffer) => buffer.untag(),
+        None => env.current_buffer.get().lisp_buffer(cx),
+    };
+    detach_overlays(buffer, env, cx)
+}
+
+fn detach_overlays(buffer: &LispBuffer, env: &mut Rt<Env>, cx: &Context) -> Result<()> {
+    let ids: Vec<_> = env.with_buffer(buffer, |b| b.overlays.iter().map(|x| x.id).collect())?;
+    env.with_buffer_mut(buffer, |b| b.overlays.clear())?;
+    for overlay in find_overlays(buffer, &ids, env, cx) {
+        as_overlay(overlay)?.try_mut()?[OVERLAY_BUFFER].set(NIL);
+        forget_overlay(overlay, env, cx);
+    }
+    Ok(())
+}
+
+/// Return the value of VARIABLE in BUFFER, which is its default value if it
+/// is not local to BUFFER.
+#[defun]
+fn buffer_local_value<'ob>(
+    variable: Symbol,
+    buffer: Gc<&LispBuffer>,
+    env: &Rt<Env>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    match env.local_value(variable, buffer.untag(), cx) {
+        Some(value) => Ok(value),
+        None => bail!("Void variable: {variable}"),
+    }
+}
+
+/// Switch the current buffer to Fundamental mode by removing its local
+/// variables and resetting its local keymap and syntax table. Variables with
+/// a non-nil `permanent-local` property are kept unless KILL-PERMANENT is
+/// non-nil. `change-major-mode-hook` is run first.
+#[defun]
+fn kill_all_local_variables(
+    kill_permanent: OptionalFlag,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<()> {
+    run_hook(sym::CHANGE_MAJOR_MODE_HOOK, env, cx)?;
+    for var in env.local_variables(cx) {
+        if kill_permanent.is_some() || get(var, sym::PERMANENT_LOCAL, env, cx).is_nil() {
+            env.kill_local(var, cx);
+        }
+    }
+    env.set_buffer_slot(sym::CURRENT_LOCAL_MAP, NIL, cx);
+    env.set_buffer_slot(sym::SYNTAX_TABLE, NIL, cx);
+    Ok(())
+}
+
+/// Run the hooks of a major mode, the symbols HOOKS, at the end of the mode
+/// function. If `delay-mode-hooks` is non-nil they are saved in
+/// `delayed-mode-hooks` instead, to run after the hooks of the mode that
+/// called this one. Otherwise the saved hooks run first, preceded by
+/// `change-major-mode-after-body-hook`. Then the file-local variables of the
+/// visited file are applied and `after-change-major-mode-hook` is run.
+#[defun]
+fn run_mode_hooks(hooks: ArgSlice, env: &mut Rt<Env>, cx: &mut Context) -> Result<()> {
+    let count = hooks.len();
+    let delayed = env.vars.get(sym::DELAYED_MODE_HOOKS).map_or(NIL, |x| x.bind(cx));
+    if env.vars.get(sym::DELAY_MODE_HOOKS).is_some_and(|x| !x.bind(cx).is_nil()) {
+        let mut delayed = delayed;
+        for i in 0..count {
+            delayed = Cons::new(env.stack[count - i - 1].bind(cx), delayed, cx).into();
+        }
+        return env.set_var(sym::DELAYED_MODE_HOOKS, delayed);
+    }
+    let delayed: Vec<_> = match delayed.untag() {
+        ObjectType::Cons(delayed) => delayed.elements().collect::<Result<_, _>>()?,
+        _ => Vec::new(),
+    };
+    root!(all, new(Vec), cx);
+    // The delayed hooks were pushed, so the first one is last
+    for hook in delayed.into_iter().rev() {
+        all.push(hook);
+    }
+    for i in 0..count {
+        all.push(env.stack[count - i - 1].bind(cx));
+    }
+    env.set_var(sym::DELAYED_MODE_HOOKS, NIL)?;
+    run_hook(sym::CHANGE_MAJOR_MODE_AFTER_BODY_HOOK, env, cx)?;
+    for i in 0..all.len() {
+        let ObjectType::Symbol(hook) = all[i].bind(cx).untag() else {
+            bail!(TypeError::new(Type::Symbol, all[i].bind(cx)))
+        };
+        if let Some(functions) = env.vars.get(hook) {
+            let functions = functions.bind(cx);
+            root!(functions, cx);
+            run_hook_functions(functions, &[], env, cx)?;
+        }
+    }
+    if env.vars.get(sym::BUFFER_FILE_NAME).is_some_and(|x| !x.bind(cx).is_nil()) {
+        let no_mode: Object = sym::NO_MODE.into();
+        root!(no_mode, cx);
+        if let Err(e) = crate::filelocal::hack_local_variables(Some(no_mode), None, env, cx) {
+            eprintln!("File local-variables error: {e}");
+        }
+    }
+    run_hook(sym::AFTER_CHANGE_MAJOR_MODE_HOOK, env, cx)
+}
+
+/// Major mode with no special features, which buffers start in.
+#[defun]
+fn fundamental_mode(env: &mut Rt<Env>, cx: &mut Context) -> Result<()> {
+    kill_all_local_variables(None, env, cx)?;
+    run_mode_hooks(ArgSlice::new(0), env, cx)
+}
+
+defvar_local!(FILL_COLUMN, 70);
+defvar_local!(INDENT_TABS_MODE);
+defvar_local!(LEFT_MARGIN, 0);
 defvar!(INHIBIT_COMPACTING_FONT_CACHES);
 defvar!(NO_UPDATE_AUTOLOADS);
-defvar!(TAB_WIDTH, 8);
-defvar!(TRUNCATE_LINES);
-defvar!(WORD_WRAP);
-defvar!(BIDI_DISPLAY_REORDERING);
+defvar_local!(TAB_WIDTH, 8);
+defvar_local!(CTL_ARROW, true);
+defvar_local!(TRUNCATE_LINES);
+defvar_local!(WORD_WRAP);
+defvar_local!(BIDI_DISPLAY_REORDERING);
+defvar!(KILL_BUFFER_HOOK);
+defvar_local!(MAJOR_MODE, sym::FUNDAMENTAL_MODE);
+defvar_local!(MODE_NAME, "Fundamental");
+defvar!(CHANGE_MAJOR_MODE_HOOK);
+defvar!(CHANGE_MAJOR_MODE_AFTER_BODY_HOOK);
+defvar!(AFTER_CHANGE_MAJOR_MODE_HOOK);
+defvar!(DELAY_MODE_HOOKS);
+defvar_local!(DELAYED_MODE_HOOKS);
+defsym!(PERMANENT_LOCAL);
+defsym!(NO_MODE);
+// Has a separate value in every buffer, see `Env::set_buffer`
 defvar!(BUFFER_FILE_NAME);
+defsym!(OVERLAY);
+defsym!(PRIORITY);
 
 #[cfg(test)]
 mod test {
     use super::*;
     use crate::core::gc::RootSet;
+    use crate::interpreter::assert_lisp;
 
     #[test]
     fn test_gen_new_buffer_name() {
@@ -268,4 +800,146 @@ mod test {
         let buffer = get_buffer_create(cx.add("test_create_buffer"), Some(NIL), cx).unwrap();
         assert!(matches!(buffer.untag(), ObjectType::Buffer(_)));
     }
+
+    #[test]
+    fn test_kill_buffer() {
+        assert_lisp(
+            r#"(let ((buffer (get-buffer-create "test_kill_buffer")))
+                 (setq kill-buffer-hook (list #'(lambda () (setq hook-ran (buffer-name)))))
+                 (list (kill-buffer buffer) hook-ran (buffer-live-p buffer)
+                       (buffer-name buffer) (get-buffer "test_kill_buffer")
+                       (kill-buffer buffer)))"#,
+            r#"(t "test_kill_buffer" nil nil nil nil)"#,
+        );
+        assert_lisp(
+            r#"(progn (set-buffer (get-buffer-create "test_kill_current_buffer"))
+                      (kill-buffer)
+                      (buffer-live-p (current-buffer)))"#,
+            "t",
+        );
+    }
+
+    #[test]
+    fn test_buffer_list_order() {
+        assert_lisp(
+            r#"(let ((first (get-buffer-create "test_buffer_list_1"))
+                     (second (get-buffer-create "test_buffer_list_2")))
+                 (set-buffer second)
+                 (set-buffer first)
+                 (and (memq second (memq first (buffer-list))) t))"#,
+            "t",
+        );
+    }
+
+    #[test]
+    fn test_buffer_modified() {
+        assert_lisp(
+            r#"(list (buffer-modified-p)
+                     (progn (insert "a") (buffer-modified-p))
+                     (progn (set-buffer-modified-p nil) (buffer-modified-p))
+                     (progn (set-buffer-modified-p t) (buffer-modified-p)))"#,
+            "(nil t nil t)",
+        );
+        assert_lisp(
+            r#"(let ((tick (buffer-modified-tick))
+                     (chars-tick (buffer-chars-modified-tick)))
+                 (insert "a")
+                 (list (> (buffer-modified-tick) tick)
+                       (> (buffer-chars-modified-tick) chars-tick)
+                       (progn (set-buffer-modified-p nil)
+                              (= (buffer-modified-tick) (buffer-chars-modified-tick)))))"#,
+            "(t t t)",
+        );
+    }
+
+    #[test]
+    fn test_overlays() {
+        assert_lisp(
+            r#"(progn (insert "hello world")
+                 (let ((ov (make-overlay 1 6)))
+                   (overlay-put ov 'face 'bold)
+                   (goto-char 1)
+                   (insert "xx")
+                   (list (overlay-start ov) (overlay-end ov) (overlay-get ov 'face)
+                         (eq (overlay-buffer ov) (current-buffer)) (overlayp ov))))"#,
+            "(1 8 bold t t)",
+        );
+        assert_lisp(
+            r#"(progn (insert "hello world")
+                 (let ((ov (make-overlay 3 8)))
+                   (delete-region 1 5)
+                   (list (overlay-start ov) (overlay-end ov)
+                         (progn (delete-overlay ov) (list (overlay-start ov) (overlay-buffer ov)))
+                         (progn (move-overlay ov 2 4) (list (overlay-start ov) (overlay-end ov))))))"#,
+            "(1 4 (nil nil) (2 4))",
+        );
+    }
+
+    #[test]
+    fn test_overlays_at_and_in() {
+        assert_lisp(
+            r#"(progn (insert "hello world")
+                 (let ((a (make-overlay 1 6)) (b (make-overlay 3 9)) (c (make-overlay 4 4)))
+                   (overlay-put b 'priority 5)
+                   (list (equal (overlays-at 4 t) (list b a))
+                         (length (overlays-at 6))
+                         (length (overlays-in 4 4))
+                         (length (overlays-in 7 12))
+                         (progn (delete-all-overlays) (overlays-in 1 12)))))"#,
+            "(t 1 3 1 nil)",
+        );
+    }
+
+    #[test]
+    fn test_buffer_local_variables() {
+        assert_lisp(
+            r#"(progn
+                 (setq test-local-var 1)
+                 (make-local-variable 'test-local-var)
+                 (setq test-local-var 2)
+                 (let ((other (get-buffer-create "local-variable-test")))
+                   (list test-local-var (default-value 'test-local-var)
+                         (with-current-buffer other test-local-var)
+                         (buffer-local-value 'test-local-var other)
+                         (local-variable-p 'test-local-var)
+                         (local-variable-p 'test-local-var other)
+                         (progn (kill-local-variable 'test-local-var) test-local-var))))"#,
+            "(2 1 1 1 t nil 1)",
+        );
+        assert_lisp(
+            r#"(progn
+                 (make-variable-buffer-local 'test-auto-local)
+                 (setq test-auto-local 5)
+                 (list test-auto-local (default-value 'test-auto-local)
+                       (local-variable-if-set-p 'test-auto-local)
+                       (with-current-buffer (get-buffer-create "auto-local-test")
+                         test-auto-local)))"#,
+            "(5 nil t nil)",
+        );
+    }
+
+    #[test]
+    fn test_mode_hooks() {
+        assert_lisp(
+            r#"(progn
+                 (set-default 'mode-name "Fundamental")
+                 (setq test-mode-runs nil)
+                 (setq test-mode-hook
+                       (list #'(lambda () (setq test-mode-runs (cons major-mode test-mode-runs)))))
+                 (make-local-variable 'test-kept)
+                 (make-local-variable 'test-killed)
+                 (put 'test-kept 'permanent-local t)
+                 (setq test-kept 2 test-killed 2)
+                 (kill-all-local-variables)
+                 (setq major-mode 'test-mode mode-name "Test")
+                 (setq delay-mode-hooks t)
+                 (run-mode-hooks 'test-mode-hook)
+                 (setq delay-mode-hooks nil)
+                 (let ((delayed delayed-mode-hooks))
+                   (run-mode-hooks)
+                   (list test-kept (boundp 'test-killed) delayed test-mode-runs mode-name
+                         (with-current-buffer (get-buffer-create "mode-test") mode-name))))"#,
+            r#"(2 nil (test-mode-hook) (test-mode) "Test" "Fundamental")"#,
+        );
+    }
 }
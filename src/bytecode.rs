@@ -3,8 +3,8 @@ use crate::core::cons::Cons;
 use crate::core::env::{CallFrame, Env, sym};
 use crate::core::gc::{Context, IntoRoot, Rt, Rto, Slot};
 use crate::core::object::{
-    ByteFn, ByteString, FnArgs, Function, FunctionType, Gc, LispVec, NIL, Object, ObjectType,
-    Symbol, WithLifetime,
+    ByteFn, ByteString, FnArgs, Function, FunctionType, Gc, LispHashTable, LispVec, NIL, Object,
+    ObjectType, Symbol, WithLifetime,
 };
 use crate::data::LispError;
 use crate::eval::{ErrorType, EvalError, EvalResult};
@@ -221,6 +221,7 @@ impl<'ob> RootedVM<'_, '_, '_> {
     }
 
     fn call(&mut self, arg_cnt: u16, cx: &'ob mut Context) -> Result<(), EvalError> {
+        crate::keyboard::maybe_quit(self.env, cx)?;
         let arg_cnt = usize::from(arg_cnt);
         let func: Function = self.env.stack[arg_cnt].bind(cx).try_into()?;
         let name = match func.untag() {
@@ -258,21 +259,33 @@ impl<'ob> RootedVM<'_, '_, '_> {
                 Err(e) => e,
             };
 
-            // we will fix this once we can handle different error types
-            #[expect(clippy::never_loop)]
+            if matches!(err.error, ErrorType::Exit(_)) {
+                return Err(err);
+            }
+            let quit = err.is_quit(self.env, cx);
             while let Some(handler) = self.handlers.bind_mut(cx).pop() {
-                match handler.condition.untag() {
-                    ObjectType::Symbol(sym::ERROR) => {}
+                let matches = match handler.condition.untag() {
+                    ObjectType::Symbol(sym::ERROR) => !quit,
+                    ObjectType::Symbol(sym::QUIT) => quit,
                     ObjectType::Cons(conditions) => {
+                        let mut matches = false;
                         for condition in conditions {
                             let condition = condition?;
                             // TODO: Handle different error symbols
-                            if condition != sym::DEBUG && condition != sym::ERROR {
+                            if condition == sym::QUIT {
+                                matches |= quit;
+                            } else if condition == sym::DEBUG || condition == sym::ERROR {
+                                matches |= !quit;
+                            } else {
                                 bail_err!("non-error conditions {condition} not yet supported")
                             }
                         }
+                        matches
                     }
                     x => bail_err!("Invalid condition handler: {x}"),
+                };
+                if !matches {
+                    continue;
                 }
 
                 let error = if let EvalError { error: ErrorType::Signal(id), .. } = err {
@@ -429,8 +442,9 @@ impl<'ob> RootedVM<'_, '_, '_> {
                     top.set(fns::nth(top.bind_as(cx)?, list.try_into()?)?);
                 }
                 op::Symbolp => {
-                    let top = self.env.stack.top();
-                    top.set(data::symbolp(top.bind(cx)));
+                    let top = self.env.stack.top().bind(cx);
+                    let is_symbol = data::symbolp(top, self.env, cx);
+                    self.env.stack.top().set(is_symbol);
                 }
                 op::Consp => {
                     let top = self.env.stack.top();
@@ -446,13 +460,15 @@ impl<'ob> RootedVM<'_, '_, '_> {
                 }
                 op::Eq => {
                     let v1 = self.env.stack.pop(cx);
-                    let top = self.env.stack.top();
-                    top.set(fns::eq(top.bind(cx), v1));
+                    let v2 = self.env.stack.top().bind(cx);
+                    let is_eq = fns::eq(v2, v1, self.env, cx);
+                    self.env.stack.top().set(is_eq);
                 }
                 op::Memq => {
                     let list = self.env.stack.pop(cx);
-                    let elt = self.env.stack.top();
-                    elt.set(fns::memq(elt.bind(cx), list.try_into()?)?);
+                    let elt = self.env.stack.top().bind(cx);
+                    let member = fns::memq(elt, list.try_into()?, self.env, cx)?;
+                    self.env.stack.top().set(member);
                 }
                 op::Not => {
                     let top = self.env.stack.top();
@@ -526,7 +542,7 @@ impl<'ob> RootedVM<'_, '_, '_> {
                 op::Fset => {
                     let def = self.env.stack.pop(cx);
                     let top = self.env.stack.top();
-                    top.set::<Object>(data::fset(top.bind_as(cx)?, def)?.into());
+                    top.set::<Object>(data::fset(top.bind_as(cx)?, def, cx)?.into());
                 }
                 op::Get => {
                     let prop = self.env.stack.pop(cx).try_into()?;
@@ -635,6 +651,10 @@ impl<'ob> RootedVM<'_, '_, '_> {
                 }
                 op::Goto => {
                     let offset = self.pc.arg2();
+                    // loops jump backwards, so check for quits to interrupt them
+                    if usize::from(offset) < self.pc.as_offset() {
+                        crate::keyboard::maybe_quit(self.env, cx)?;
+                    }
                     self.pc.goto(offset);
                 }
                 op::GotoIfNil => {
@@ -730,8 +750,9 @@ impl<'ob> RootedVM<'_, '_, '_> {
                 }
                 op::Assq => {
                     let alist = self.env.stack.pop(cx);
-                    let top = self.env.stack.top();
-                    top.set(fns::assq(top.bind(cx), alist.try_into()?)?);
+                    let key = self.env.stack.top().bind(cx);
+                    let elem = fns::assq(key, alist.try_into()?, self.env, cx)?;
+                    self.env.stack.top().set(elem);
                 }
                 op::Nreverse => {
                     let elt = self.env.stack.top();
@@ -785,7 +806,7 @@ impl<'ob> RootedVM<'_, '_, '_> {
                         unreachable!("switch table was not a hash table")
                     };
                     let cond = self.env.stack.pop(cx);
-                    if let Some(offset) = table.get(cond) {
+                    if let Some(offset) = switch_target(table, cond) {
                         let ObjectType::Int(offset) = offset.untag() else {
                             unreachable!("switch value was not a int")
                         };
@@ -887,6 +908,24 @@ fn byte_code<'ob>(
     Ok(call(fun, 0, "unnamed", &mut CallFrame::new(env), cx)?)
 }
 
+/// Find the jump target of COND in the jump table of a `switch`. Fixnums and
+/// symbols, which is what `pcase` and `cond` on symbols compile to, are
+/// compared by identity and can be looked up directly. The byte compiler also
+/// makes `eql` and `equal` jump tables for floats and strings, but our hash
+/// tables only hash by identity, so those are compared one at a time.
+fn switch_target<'ob>(table: &'ob LispHashTable, cond: Object) -> Option<Object<'ob>> {
+    if let Some(target) = table.get(cond) {
+        return Some(target);
+    }
+    match cond.untag() {
+        ObjectType::Int(_) | ObjectType::Symbol(_) => None,
+        _ => (0..table.len())
+            .filter_map(|i| table.get_index(i))
+            .find(|(key, _)| *key == cond)
+            .map(|(_, target)| target),
+    }
+}
+
 /// Number of arguments needed to fill out the remaining slots on the stack.
 /// If a function has 3 required args and 2 optional, and it is called with
 /// 4 arguments, then 1 will be returned. Indicating that 1 additional `nil`
@@ -1186,6 +1225,33 @@ mod test {
         check_bytecode!(bytecode, [], 7, cx);
     }
 
+    #[test]
+    fn test_switch_equal() {
+        use OpCode::*;
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+
+        let mut table = HashTable::default();
+        table.insert(cx.add("a"), 6.into());
+        table.insert(cx.add("b"), 8.into());
+
+        // (lambda (s)
+        //   (pcase s ("a" 1) ("b" 2)))
+        make_bytecode!(
+            bytecode,
+            257,
+            [
+                Duplicate, Constant0, Switch, Goto, 0x0A, 0x00, Constant1, Return, Constant2,
+                Return, Constant3, Return
+            ],
+            [table, 1, 2, false],
+            cx
+        );
+        check_bytecode!(bytecode, ["a"], 1, cx);
+        check_bytecode!(bytecode, ["b"], 2, cx);
+        check_bytecode!(bytecode, ["c"], false, cx);
+    }
+
     #[test]
     fn test_handlers() {
         use OpCode as O;
@@ -0,0 +1,241 @@
+//! Calling functions interactively.
+use crate::core::{
+    cons::Cons,
+    env::{CallFrame, Env, sym},
+    gc::{Context, Rt, Rto, Slot},
+    object::{Function, FunctionType, NIL, Object, ObjectType, OptionalFlag},
+};
+use crate::fns::slice_into_list;
+use crate::minibuf::read_line;
+use anyhow::{Result, bail};
+use rune_core::macros::{list, root};
+use rune_macros::defun;
+use std::io;
+
+defvar!(CURRENT_PREFIX_ARG);
+defvar!(PREFIX_ARG);
+defvar!(COMMAND_HISTORY);
+
+/// Interactive specs of the builtin functions that are commands.
+const BUILTIN_SPECS: &[(&str, &str)] = &[
+    ("backward-char", "^p"),
+    ("beginning-of-line", "^p"),
+    ("delete-backward-char", "p"),
+    ("delete-char", "p"),
+    ("delete-region", "r"),
+    ("end-of-line", "^p"),
+    ("forward-char", "^p"),
+    ("forward-line", "^p"),
+    ("narrow-to-region", "r"),
+    ("newline", "p"),
+    ("self-insert-command", "p"),
+    ("widen", ""),
+];
+
+/// Find the `(interactive ...)` form of FUNCTION, or `None` if it is not a
+/// command. Byte-compiled functions don't keep their interactive spec, so they
+/// are never commands.
+fn find_interactive_form<'ob>(
+    function: Object<'ob>,
+    cx: &'ob Context,
+) -> Result<Option<Object<'ob>>> {
+    let Ok(function) = Function::try_from(function) else { return Ok(None) };
+    let function = match function.untag() {
+        FunctionType::Symbol(sym) => match sym.follow_indirect(cx) {
+            Some(func) => func,
+            None => return Ok(None),
+        },
+        _ => function,
+    };
+    let func = match function.untag() {
+        FunctionType::SubrFn(subr) => {
+            let spec = BUILTIN_SPECS.iter().find(|(name, _)| *name == subr.name);
+            return Ok(spec.map(|(_, spec)| list![sym::INTERACTIVE, cx.add(*spec); cx]));
+        }
+        FunctionType::Cons(func) => func,
+        _ => return Ok(None),
+    };
+    let body_pos = match func.car().untag() {
+        ObjectType::Symbol(sym::CLOSURE) => 3,
+        ObjectType::Symbol(sym::LAMBDA) => 2,
+        _ => return Ok(None),
+    };
+    let mut body = func.elements().skip(body_pos);
+    let mut form = body.next().transpose()?;
+    // skip the docstring
+    if form.is_some_and(|x| matches!(x.untag(), ObjectType::String(_))) {
+        form = body.next().transpose()?;
+    }
+    let is_interactive =
+        |x: &Object| matches!(x.untag(), ObjectType::Cons(cons) if cons.car() == sym::INTERACTIVE);
+    Ok(form.filter(is_interactive))
+}
+
+/// Return the `(interactive ...)` form of CMD, or nil if it is not a command.
+#[defun]
+fn interactive_form<'ob>(cmd: Object<'ob>, cx: &'ob Context) -> Result<Object<'ob>> {
+    Ok(find_interactive_form(cmd, cx)?.unwrap_or_default())
+}
+
+/// Return non-nil if FUNCTION can be called interactively. Keyboard macros
+/// are commands too, unless FOR-CALL-INTERACTIVELY is non-nil.
+#[defun]
+fn commandp(function: Object, for_call_interactively: OptionalFlag, cx: &Context) -> Result<bool> {
+    match function.untag() {
+        ObjectType::String(_) | ObjectType::Vec(_) => Ok(for_call_interactively.is_none()),
+        _ => Ok(find_interactive_form(function, cx)?.is_some()),
+    }
+}
+
+/// Return the numeric meaning of the raw prefix argument RAW.
+#[defun]
+fn prefix_numeric_value(raw: Object) -> i64 {
+    match raw.untag() {
+        ObjectType::NIL => 1,
+        ObjectType::Symbol(sym::SUB) => -1,
+        ObjectType::Int(n) => n,
+        ObjectType::Cons(cons) => match cons.car().untag() {
+            ObjectType::Int(n) => n,
+            _ => 1,
+        },
+        _ => 1,
+    }
+}
+
+fn current_prefix_arg<'ob>(env: &Rt<Env>, cx: &'ob Context) -> Object<'ob> {
+    env.vars.get(sym::CURRENT_PREFIX_ARG).map(|x| x.bind(cx)).unwrap_or_default()
+}
+
+/// Push the arguments described by the interactive calling string `spec`
+/// onto `args`. Each line of the spec is a code letter followed by a prompt.
+fn interactive_args(
+    spec: &str,
+    args: &mut Rt<Vec<Slot<Object>>>,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<()> {
+    // TODO: `*` should check for a read-only buffer and `^` should handle
+    // shift selection
+    let spec = spec.trim_start_matches(['*', '@', '^']);
+    for line in spec.split('\n') {
+        let mut chars = line.chars();
+        let Some(code) = chars.next() else { continue };
+        let prompt = chars.as_str();
+        match code {
+            'p' => args.push(cx.add(prefix_numeric_value(current_prefix_arg(env, cx)))),
+            'P' => args.push(current_prefix_arg(env, cx)),
+            'd' => args.push(cx.add(env.current_buffer.get().point())),
+            'm' => match env.current_buffer.get().mark() {
+                Some(mark) => args.push(cx.add(mark)),
+                None => bail!("The mark is not set now"),
+            },
+            'r' => {
+                let buffer = env.current_buffer.get();
+                let Some(mark) = buffer.mark() else {
+                    bail!("The mark is not set now, so there is no region")
+                };
+                let point = buffer.point();
+                args.push(cx.add(point.min(mark)));
+                args.push(cx.add(point.max(mark)));
+            }
+            's' => {
+                let string = read_line(prompt, &mut io::stdin().lock(), env, cx)?;
+                args.push(cx.add(string));
+            }
+            'i' => args.push(NIL),
+            _ => bail!("Invalid control letter `{code}' in interactive calling string"),
+        }
+    }
+    Ok(())
+}
+
+/// Call FUNCTION, providing its arguments according to its interactive spec.
+/// If RECORD-FLAG is non-nil, the call is added to `command-history`.
+#[defun]
+pub(crate) fn call_interactively<'ob>(
+    function: &Rto<Object>,
+    record_flag: OptionalFlag,
+    _keys: OptionalFlag,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<Object<'ob>> {
+    root!(args, new(Vec), cx);
+    let Some(form) = find_interactive_form(function.bind(cx), cx)? else {
+        bail!("Wrong type argument: commandp, {}", function.bind(cx))
+    };
+    let spec = form.as_list()?.nth(1).transpose()?.unwrap_or_default();
+    match spec.untag() {
+        ObjectType::NIL => {}
+        ObjectType::String(spec) => {
+            let spec = spec.to_string();
+            interactive_args(&spec, args, env, cx)?;
+        }
+        _ => {
+            root!(spec, cx);
+            let values = crate::interpreter::eval(spec, None, env, cx)?;
+            for value in values.as_list()? {
+                args.push(value?);
+            }
+        }
+    }
+    if record_flag.is_some() {
+        let values: Vec<_> = args.iter().map(|x| x.bind(cx)).collect();
+        let call = Cons::new(function.bind(cx), slice_into_list(&values, None, cx), cx);
+        let history = env.vars.get(sym::COMMAND_HISTORY).map(|x| x.bind(cx)).unwrap_or_default();
+        env.set_var(sym::COMMAND_HISTORY, Cons::new(call, history, cx).into())?;
+    }
+    let func: Function = function.bind(cx).try_into()?;
+    root!(func, cx);
+    let frame = &mut CallFrame::new(env);
+    for arg in args.iter() {
+        frame.push_arg(arg.bind(cx));
+    }
+    func.call(frame, None, cx).map_err(Into::into)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::interpreter::assert_lisp;
+
+    #[test]
+    fn test_call_interactively() {
+        let prefix = "#'(lambda (n) (interactive \"p\") n)";
+        assert_lisp(&format!("(call-interactively {prefix})"), "1");
+        assert_lisp(&format!("(let ((current-prefix-arg 4)) (call-interactively {prefix}))"), "4");
+        assert_lisp(
+            &format!("(let ((current-prefix-arg '-)) (call-interactively {prefix}))"),
+            "-1",
+        );
+        assert_lisp(
+            &format!("(let ((current-prefix-arg '(16))) (call-interactively {prefix}))"),
+            "16",
+        );
+        assert_lisp(
+            r#"(progn (insert "hello") (set-mark 2)
+                 (call-interactively #'(lambda (b e) "doc" (interactive "r") (list b e))))"#,
+            "(2 6)",
+        );
+        assert_lisp(
+            "(call-interactively #'(lambda (a b) (interactive (list 1 2)) (+ a b)) t)",
+            "3",
+        );
+        assert_lisp(
+            r#"(progn (insert "hello") (goto-char 1) (call-interactively 'forward-char) (point))"#,
+            "2",
+        );
+    }
+
+    #[test]
+    fn test_commandp() {
+        assert_lisp(
+            "(list (commandp #'(lambda () (interactive) 1)) (commandp #'(lambda () 1))
+                   (commandp 'forward-char) (commandp 'car) (commandp \"keys\")
+                   (commandp \"keys\" t))",
+            "(t nil t nil t nil)",
+        );
+        assert_lisp(
+            "(interactive-form #'(lambda () \"doc\" (interactive \"p\")))",
+            "(interactive \"p\")",
+        );
+    }
+}
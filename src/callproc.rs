@@ -0,0 +1,186 @@
+//! The environment of subprocesses.
+use crate::core::{
+    env::{Env, sym},
+    gc::{Context, Rt},
+    object::{List, NIL, Object, ObjectType, OptionalFlag, TRUE},
+};
+use crate::fns::slice_into_list;
+use anyhow::{Result, ensure};
+use rune_macros::defun;
+
+defvar!(PROCESS_ENVIRONMENT);
+defvar!(INITIAL_ENVIRONMENT);
+
+/// The environment rune was started with, as `NAME=VALUE` strings. This is
+/// the initial value of `process-environment` and `initial-environment`.
+pub(crate) fn environment_strings() -> Vec<String> {
+    std::env::vars_os()
+        .map(|(name, value)| format!("{}={}", name.to_string_lossy(), value.to_string_lossy()))
+        .collect()
+}
+
+/// Find `variable` in `environment`, a list of `NAME=VALUE` strings. An entry
+/// that is just `NAME` means the variable is unset, which is returned as
+/// `Some(None)`. Returns `None` if there is no entry for it.
+fn lookup(variable: &str, environment: Object) -> Result<Option<Option<String>>> {
+    let environment: List = environment.try_into()?;
+    for entry in environment.elements() {
+        let ObjectType::String(entry) = entry?.untag() else { continue };
+        let entry: &str = entry.as_ref();
+        match entry.split_once('=') {
+            Some((name, value)) if name == variable => return Ok(Some(Some(value.to_owned()))),
+            None if entry == variable => return Ok(Some(None)),
+            _ => {}
+        }
+    }
+    Ok(None)
+}
+
+/// The value of `variable` in the environment that a subprocess would get.
+/// Variables that are not in `process-environment` come from the environment
+/// rune was started with.
+pub(crate) fn getenv_value(variable: &str, env: &Rt<Env>, cx: &Context) -> Result<Option<String>> {
+    let environment = env.vars.get(sym::PROCESS_ENVIRONMENT).map_or(NIL, |x| x.bind(cx));
+    match lookup(variable, environment)? {
+        Some(value) => Ok(value),
+        None => Ok(std::env::var_os(variable).map(|x| x.to_string_lossy().into_owned())),
+    }
+}
+
+/// The changes `process-environment` makes to the environment rune was
+/// started with, as `(NAME, VALUE)` pairs where a `VALUE` of `None` unsets
+/// the variable. Earlier entries take precedence over later ones.
+pub(crate) fn environment_overrides(
+    env: &Rt<Env>,
+    cx: &Context,
+) -> Result<Vec<(String, Option<String>)>> {
+    let environment = env.vars.get(sym::PROCESS_ENVIRONMENT).map_or(NIL, |x| x.bind(cx));
+    let environment: List = environment.try_into()?;
+    let mut overrides: Vec<(String, Option<String>)> = Vec::new();
+    for entry in environment.elements() {
+        let ObjectType::String(entry) = entry?.untag() else { continue };
+        let entry: &str = entry.as_ref();
+        let (name, value) = match entry.split_once('=') {
+            Some((name, value)) => (name, Some(value.to_owned())),
+            None => (entry, None),
+        };
+        if !overrides.iter().any(|x| x.0 == name) {
+            overrides.push((name.to_owned(), value));
+        }
+    }
+    Ok(overrides)
+}
+
+/// Get the value of environment variable VARIABLE, or nil if it is not set.
+///
+/// If ENVIRONMENT is a list it is searched instead of `process-environment`, and t is
+/// returned for an entry that unsets the variable.
+#[defun]
+fn getenv_internal<'ob>(
+    variable: &str,
+    environment: Option<Object>,
+    env: &Rt<Env>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    if let Some(environment) = environment {
+        return Ok(match lookup(variable, environment)? {
+            Some(Some(value)) => cx.add(value),
+            Some(None) => TRUE,
+            None => NIL,
+        });
+    }
+    Ok(getenv_value(variable, env, cx)?.map_or(NIL, |x| cx.add(x)))
+}
+
+/// Get the value of environment variable VARIABLE, or nil if it is not set.
+/// `process-environment` is searched first, then the environment rune was
+/// started with.
+#[defun]
+fn getenv(
+    variable: &str,
+    _frame: Option<Object>,
+    env: &Rt<Env>,
+    cx: &Context,
+) -> Result<Option<String>> {
+    getenv_value(variable, env, cx)
+}
+
+/// Set the value of environment variable VARIABLE to VALUE, or unset it if
+/// VALUE is nil. Returns VALUE.
+///
+/// This sets `process-environment` to a new list rather than modifying the
+/// old one, so it only lasts as long as the current binding of
+/// `process-environment`.
+#[defun]
+fn setenv<'ob>(
+    variable: &str,
+    value: Option<&str>,
+    substitute_env_vars: OptionalFlag,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    ensure!(substitute_env_vars.is_none(), "substitute-env-vars not implemented");
+    ensure!(!variable.contains('='), "Environment variable name `{variable}' contains `='");
+    let old = env.vars.get(sym::PROCESS_ENVIRONMENT).map_or(NIL, |x| x.bind(cx));
+    let old: List = old.try_into()?;
+    let mut entries = vec![match value {
+        Some(value) => cx.add(format!("{variable}={value}")),
+        None => cx.add(variable),
+    }];
+    for entry in old.elements() {
+        let entry = entry?;
+        let same = match entry.untag() {
+            ObjectType::String(s) => {
+                let s: &str = s.as_ref();
+                s.split_once('=').map_or(s, |(name, _)| name) == variable
+            }
+            _ => false,
+        };
+        if !same {
+            entries.push(entry);
+        }
+    }
+    let new = slice_into_list(&entries, None, cx);
+    env.set_var(sym::PROCESS_ENVIRONMENT, new)?;
+    Ok(value.map_or(NIL, |x| cx.add(x)))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::interpreter::assert_lisp;
+
+    #[test]
+    fn test_getenv() {
+        assert_lisp(
+            r#"(let ((process-environment '("FOO=bar" "HOME")))
+                 (list (getenv "FOO") (getenv "HOME") (getenv "RUNE_UNSET_VARIABLE")))"#,
+            r#"("bar" nil nil)"#,
+        );
+        assert_lisp(
+            r#"(list (getenv-internal "FOO" '("FOO=1" "FOO=2"))
+                     (getenv-internal "FOO" '("FOO"))
+                     (getenv-internal "FOO" '("FOOBAR=1")))"#,
+            r#"("1" t nil)"#,
+        );
+    }
+
+    #[test]
+    fn test_setenv() {
+        assert_lisp(
+            r#"(let ((process-environment '("A=1" "B=2")))
+                 (list (setenv "A" "3") (getenv "A")
+                       (progn (setenv "B" nil) (getenv "B"))
+                       process-environment))"#,
+            r#"("3" "3" nil ("B" "A=3"))"#,
+        );
+        // the change is undone when the binding ends
+        assert_lisp(
+            r#"(progn
+                 (setq process-environment '("A=1"))
+                 (let ((process-environment process-environment))
+                   (setenv "A" "2"))
+                 (getenv "A"))"#,
+            r#""1""#,
+        );
+    }
+}
@@ -96,7 +96,7 @@ fn capitalize_word<'ob>(offset: i64, env: &mut Rt<Env>) -> Object<'ob> {
     NIL
 }
 
-fn casify_string(s: &str, mode: CaseMode) -> String {
+pub(crate) fn casify_string(s: &str, mode: CaseMode) -> String {
     let mut out = String::with_capacity(s.len());
 
     for word in s.split_inclusive(|c: char| precedes_capitalization(c)) {
@@ -124,7 +124,7 @@ fn precedes_capitalization(c: char) -> bool {
     !c.is_alphanumeric()
 }
 
-enum CaseMode {
+pub(crate) enum CaseMode {
     Downcase,
     Upcase,
     Capitalize,
@@ -1,10 +1,116 @@
 //! Character and string utilities.
+//!
+//! Strings are either multibyte ([`LispString`]), which hold characters, or
+//! unibyte ([`ByteString`]), which hold bytes. Bytes 128 to 255 that are not
+//! part of any character are kept in multibyte text as raw byte characters,
+//! `0x3FFF80` to `0x3FFFFF`.
+//!
+//! [`LispString`]: crate::core::object::LispString
+//! [`ByteString`]: crate::core::object::ByteString
+use crate::coding::{decode_utf8, encode_utf8};
 use crate::core::{
-    gc::Context,
-    object::{Gc, Object, OptionalFlag, int_to_char},
+    env::{Env, sym},
+    error::{Type, TypeError},
+    gc::{Context, Rt},
+    object::{
+        CharTable, Gc, Object, ObjectType, OptionalFlag, RAW_BYTE_BASE, Symbol, char_raw_byte,
+        char_to_int, int_to_char, raw_byte_char,
+    },
 };
-use anyhow::Result;
+use anyhow::{Result, bail, ensure};
 use rune_macros::defun;
+use unicode_width::UnicodeWidthChar;
+
+defvar!(CHAR_WIDTH_TABLE, object::CharTableInner::new(None));
+
+/// How many columns characters take up on the display. Characters that are
+/// not in `char-width-table` get the width of their Unicode East Asian Width
+/// property.
+pub(crate) struct CharWidth<'ob> {
+    tab_width: usize,
+    ctl_arrow: bool,
+    table: Option<&'ob CharTable>,
+}
+
+impl<'ob> CharWidth<'ob> {
+    pub(crate) fn new(env: &Rt<Env>, cx: &'ob Context) -> Self {
+        let var = |sym: Symbol| env.vars.get(sym).map(|x| x.bind(cx));
+        let tab_width = match var(sym::TAB_WIDTH).map(|x| x.untag()) {
+            Some(ObjectType::Int(x @ 1..=1000)) => x as usize,
+            _ => 8,
+        };
+        let ctl_arrow = var(sym::CTL_ARROW).is_some_and(|x| !x.is_nil());
+        let table = match var(sym::CHAR_WIDTH_TABLE).map(|x| x.untag()) {
+            Some(ObjectType::CharTable(table)) => Some(table),
+            _ => None,
+        };
+        Self { tab_width, ctl_arrow, table }
+    }
+
+    pub(crate) fn tab_width(&self) -> usize {
+        self.tab_width
+    }
+
+    /// The width of `chr`. A tab is `tab-width` columns wide, control
+    /// characters are shown as `^C` (or `\003` if `ctl-arrow` is nil), and
+    /// raw bytes as octal escapes.
+    pub(crate) fn of(&self, chr: char) -> usize {
+        let escaped = if self.ctl_arrow { 2 } else { 4 };
+        match chr {
+            '\t' => self.tab_width,
+            '\n' => 0,
+            '\0'..' ' | '\x7F' => escaped,
+            _ if chr.is_ascii() => 1,
+            _ => {
+                let entry = self.table.map(|table| table.get(char_to_int(chr) as usize).untag());
+                match entry {
+                    Some(ObjectType::Int(width)) => usize::try_from(width).unwrap_or(0),
+                    _ if char_raw_byte(chr).is_some() => 4,
+                    _ => chr.width().unwrap_or(4),
+                }
+            }
+        }
+    }
+
+    /// The width of `byte` in a unibyte string.
+    fn of_byte(&self, byte: u8) -> usize {
+        self.of(raw_byte_char(byte))
+    }
+}
+
+/// Return the number of columns CHAR takes up when displayed.
+#[defun]
+fn char_width(char: char, env: &Rt<Env>, cx: &Context) -> usize {
+    CharWidth::new(env, cx).of(char)
+}
+
+/// Return the number of columns STRING takes up when displayed. If FROM or
+/// TO are non-nil, only the characters from FROM up to TO are counted.
+/// Negative positions count from the end of STRING.
+#[defun]
+fn string_width(
+    string: Object,
+    from: Option<i64>,
+    to: Option<i64>,
+    env: &Rt<Env>,
+    cx: &Context,
+) -> Result<usize> {
+    let widths = CharWidth::new(env, cx);
+    let (len, widths): (usize, Vec<usize>) = match string.untag() {
+        ObjectType::String(s) => (s.chars().count(), s.chars().map(|c| widths.of(c)).collect()),
+        ObjectType::ByteString(s) => (s.len(), s.iter().map(|&b| widths.of_byte(b)).collect()),
+        x => bail!(TypeError::new(Type::String, x)),
+    };
+    let position = |pos: Option<i64>, default: usize| -> Result<usize> {
+        let Some(pos) = pos else { return Ok(default) };
+        let idx = if pos < 0 { len as i64 + pos } else { pos };
+        ensure!((0..=len as i64).contains(&idx), "Args out of range: {pos}");
+        Ok(idx as usize)
+    };
+    let (from, to) = (position(from, 0)?, position(to, len)?);
+    ensure!(from <= to, "Args out of range: {from}, {to}");
+    Ok(widths[from..to].iter().sum())
+}
 
 #[defun]
 fn unibyte_string(bytes: &[Gc<i64>]) -> Result<Vec<u8>> {
@@ -12,6 +118,86 @@ fn unibyte_string(bytes: &[Gc<i64>]) -> Result<Vec<u8>> {
     Ok(unibyte?)
 }
 
+/// Return a unibyte string with the same bytes as STRING. Signal an error
+/// if STRING contains a character that is not ASCII or a raw byte.
+#[defun]
+fn string_to_unibyte<'ob>(string: Object<'ob>, cx: &'ob Context) -> Result<Object<'ob>> {
+    match string.untag() {
+        ObjectType::ByteString(_) => Ok(string),
+        ObjectType::String(s) => {
+            let mut bytes = Vec::with_capacity(s.len());
+            for (idx, chr) in s.chars().enumerate() {
+                match char_raw_byte(chr) {
+                    Some(byte) => bytes.push(byte),
+                    None if chr.is_ascii() => bytes.push(chr as u8),
+                    None => bail!("Can't convert {idx}th character to unibyte"),
+                }
+            }
+            Ok(cx.add(bytes))
+        }
+        x => Err(TypeError::new(Type::String, x).into()),
+    }
+}
+
+/// Return a multibyte string with the same characters as STRING. Bytes 128
+/// to 255 in a unibyte STRING become raw byte characters.
+#[defun]
+fn string_to_multibyte<'ob>(string: Object<'ob>, cx: &'ob Context) -> Result<Object<'ob>> {
+    match string.untag() {
+        ObjectType::String(_) => Ok(string),
+        ObjectType::ByteString(s) => {
+            let string: String = s.iter().map(|&b| raw_byte_char(b)).collect();
+            Ok(cx.add(string))
+        }
+        x => Err(TypeError::new(Type::String, x).into()),
+    }
+}
+
+/// Return a unibyte string with the bytes that make up STRING. Raw byte
+/// characters become single bytes and other characters are encoded as
+/// UTF-8.
+#[defun]
+fn string_as_unibyte<'ob>(string: Object<'ob>, cx: &'ob Context) -> Result<Object<'ob>> {
+    match string.untag() {
+        ObjectType::ByteString(_) => Ok(string),
+        ObjectType::String(s) => Ok(cx.add(encode_utf8(s))),
+        x => Err(TypeError::new(Type::String, x).into()),
+    }
+}
+
+/// Return a multibyte string by reading the bytes of STRING as UTF-8. Bytes
+/// that are not part of a valid sequence become raw byte characters.
+#[defun]
+fn string_as_multibyte<'ob>(string: Object<'ob>, cx: &'ob Context) -> Result<Object<'ob>> {
+    match string.untag() {
+        ObjectType::String(_) => Ok(string),
+        ObjectType::ByteString(s) => Ok(cx.add(decode_utf8(s))),
+        x => Err(TypeError::new(Type::String, x).into()),
+    }
+}
+
+/// Convert the multibyte character CH to a byte. Return -1 if CH is not
+/// ASCII or a raw byte.
+#[defun]
+fn multibyte_char_to_unibyte(ch: i64) -> i64 {
+    match ch {
+        0..0x80 => ch,
+        _ if (RAW_BYTE_BASE + 0x80..RAW_BYTE_BASE + 0x100).contains(&ch) => ch - RAW_BYTE_BASE,
+        _ => -1,
+    }
+}
+
+/// Convert the byte CH to a multibyte character. Bytes 128 to 255 become raw
+/// byte characters.
+#[defun]
+fn unibyte_char_to_multibyte(ch: i64) -> Result<i64> {
+    match ch {
+        0..0x80 => Ok(ch),
+        0x80..0x100 => Ok(RAW_BYTE_BASE + ch),
+        _ => bail!("Not a unibyte character: {ch}"),
+    }
+}
+
 #[defun]
 fn max_char(unicode: OptionalFlag) -> usize {
     if unicode.is_some() { std::char::MAX as usize } else { 0x3F_FFFF }
@@ -49,3 +235,65 @@ fn make_string<'ob>(
         Ok(cx.add(string))
     }
 }
+
+#[cfg(test)]
+mod test {
+    use crate::interpreter::assert_lisp;
+
+    #[test]
+    fn test_raw_bytes() {
+        assert_lisp(r#"(aref "λ\200" 1)"#, "4194176");
+        assert_lisp("(characterp #x3fffff)", "t");
+        assert_lisp("(aref (string (unibyte-char-to-multibyte 200)) 0)", "4194248");
+        assert_lisp("(multibyte-char-to-unibyte (unibyte-char-to-multibyte 200))", "200");
+        assert_lisp(r#"(string-bytes "λ\377")"#, "4");
+        assert_lisp(
+            "(progn (insert (make-string 2 ?x) (make-string 1 255))
+                    (list (point) (aref (buffer-string) 2)))",
+            "(4 4194303)",
+        );
+    }
+
+    #[test]
+    fn test_char_width() {
+        assert_lisp(
+            r"(let ((ctl-arrow t)) (list (char-width ?a) (char-width ?\t) (char-width ?\C-a)))",
+            "(1 8 2)",
+        );
+        assert_lisp(r"(list (char-width ?中) (char-width ?\u0301))", "(2 0)");
+        assert_lisp("(char-width #x3fff80)", "4");
+        assert_lisp(
+            r"(let ((tab-width 4) (ctl-arrow nil)) (list (char-width ?\t) (char-width ?\C-a)))",
+            "(4 4)",
+        );
+        assert_lisp(
+            "(let ((char-width-table (make-char-table nil)))
+               (aset char-width-table ?é 3)
+               (char-width ?é))",
+            "3",
+        );
+    }
+
+    #[test]
+    fn test_string_width() {
+        assert_lisp(r#"(string-width "abc中文")"#, "7");
+        assert_lisp(r#"(string-width "abc中文" 1 -1)"#, "4");
+        assert_lisp(r#"(string-width "a\tb")"#, "10");
+        assert_lisp(r#"(string-width "a\200")"#, "5");
+        assert_lisp(r#"(condition-case nil (string-width "abc" 2 1) (error 'range))"#, "range");
+    }
+
+    #[test]
+    fn test_string_conversion() {
+        assert_lisp(r#"(multibyte-string-p (string-to-multibyte "\200"))"#, "t");
+        assert_lisp(r#"(aref (string-to-multibyte "a\200") 1)"#, "4194176");
+        assert_lisp(r#"(string-to-multibyte "λ")"#, r#""λ""#);
+        assert_lisp(
+            r#"(equal (string-to-unibyte (string-to-multibyte "a\377")) (unibyte-string 97 255))"#,
+            "t",
+        );
+        assert_lisp(r#"(equal (string-as-unibyte "λ\377") (unibyte-string 206 187 255))"#, "t");
+        assert_lisp("(string-as-multibyte (unibyte-string 206 187 255))", r#""λ\377""#);
+        assert_lisp(r#"(multibyte-string-p (string-as-unibyte "λ"))"#, "nil");
+    }
+}
@@ -1,4 +1,11 @@
-use crate::core::object::{CharTable, CharTableInner, Object, Symbol};
+use crate::core::{
+    cons::Cons,
+    gc::Context,
+    object::{
+        CharTable, CharTableInner, Gc, List, Object, ObjectType, Symbol, char_to_int, int_to_char,
+    },
+};
+use anyhow::{Result, bail};
 use rune_macros::defun;
 
 #[defun]
@@ -6,6 +13,11 @@ fn make_char_table<'ob>(_purpose: Symbol<'ob>, init: Option<Object<'ob>>) -> Cha
     CharTableInner::new(init)
 }
 
+#[defun]
+fn char_table_parent(table: &CharTable) -> Option<&CharTable> {
+    table.parent()
+}
+
 #[defun]
 fn set_char_table_parent<'ob>(
     table: &'ob CharTable,
@@ -14,3 +26,46 @@ fn set_char_table_parent<'ob>(
     table.set_parent(parent);
     parent
 }
+
+/// Return a translation table that maps characters to characters. Each of
+/// ARGS is an alist of `(FROM . TO)` pairs.
+#[defun]
+fn make_translation_table<'ob>(args: &[Object<'ob>], cx: &'ob Context) -> Result<&'ob CharTable> {
+    let table: Gc<&CharTable> = cx.add_as(CharTableInner::new(None));
+    let table = table.untag();
+    for alist in args {
+        for pair in List::try_from(*alist)? {
+            let pair: &Cons = pair?.try_into()?;
+            match (pair.car().untag(), pair.cdr().untag()) {
+                (ObjectType::Int(from), ObjectType::Int(to)) => {
+                    int_to_char(to)?;
+                    table.set(usize::try_from(from)?, pair.cdr());
+                }
+                _ => bail!("Invalid translation: {pair}"),
+            }
+        }
+    }
+    Ok(table)
+}
+
+/// The character that `chr` is translated to by `table`.
+pub(crate) fn translate_char(table: &CharTable, chr: char) -> char {
+    match table.get(char_to_int(chr) as usize).untag() {
+        ObjectType::Int(to) => int_to_char(to).unwrap_or(chr),
+        _ => chr,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::interpreter::assert_lisp;
+
+    #[test]
+    fn test_translation_table() {
+        assert_lisp(
+            "(let ((table (make-translation-table '((?a . ?b)) '((?c . ?d)))))
+               (list (aref table ?a) (aref table ?c) (aref table ?e)))",
+            "(98 100 nil)",
+        );
+    }
+}
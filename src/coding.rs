@@ -0,0 +1,391 @@
+//! Coding systems for file and process I/O.
+//!
+//! Only a small set of coding systems is supported: `utf-8`, `latin-1` and
+//! `no-conversion` (also known as `binary` or `raw-text`), each with an
+//! optional `-unix`, `-dos` or `-mac` end-of-line variant. Invalid UTF-8
+//! sequences are decoded as raw byte characters, which are encoded back to
+//! the same bytes.
+use crate::core::{
+    env::{Env, intern, sym},
+    error::{Type, TypeError},
+    gc::{Context, Rt},
+    object::{NIL, Object, ObjectType, Symbol, char_raw_byte, raw_byte_char},
+};
+use anyhow::{Result, bail};
+use rune_macros::defun;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum Encoding {
+    Utf8,
+    Latin1,
+    Binary,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum Eol {
+    Unix,
+    Dos,
+    Mac,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) struct CodingSystem {
+    pub(crate) encoding: Encoding,
+    pub(crate) eol: Eol,
+}
+
+/// The result of decoding bytes with a coding system.
+#[derive(Debug, PartialEq)]
+pub(crate) enum Decoded {
+    Text(String),
+    Bytes(Vec<u8>),
+}
+
+impl CodingSystem {
+    pub(crate) const UTF_8: Self = Self { encoding: Encoding::Utf8, eol: Eol::Unix };
+    pub(crate) const BINARY: Self = Self { encoding: Encoding::Binary, eol: Eol::Unix };
+
+    /// Parse the name of a coding system.
+    pub(crate) fn from_name(name: &str) -> Option<Self> {
+        let (base, eol) = match name.rsplit_once('-') {
+            Some((base, "unix")) => (base, Eol::Unix),
+            Some((base, "dos")) => (base, Eol::Dos),
+            Some((base, "mac")) => (base, Eol::Mac),
+            _ => (name, Eol::Unix),
+        };
+        let encoding = match base {
+            "utf-8" | "utf-8-emacs" | "mule-utf-8" | "prefer-utf-8" | "undecided" | "us-ascii"
+            | "emacs-internal" | "utf-8-auto" => Encoding::Utf8,
+            "latin-1" | "iso-latin-1" | "iso-8859-1" => Encoding::Latin1,
+            "binary" | "no-conversion" | "raw-text" => Encoding::Binary,
+            _ => return None,
+        };
+        Some(Self { encoding, eol })
+    }
+
+    /// Resolve a coding system from a lisp object. `nil` means no conversion.
+    pub(crate) fn from_obj(obj: Object) -> Result<Self> {
+        match obj.untag() {
+            ObjectType::NIL => Ok(Self::BINARY),
+            ObjectType::Symbol(sym) => match Self::from_name(sym.name()) {
+                Some(x) => Ok(x),
+                None => bail!("Invalid coding system: {sym}"),
+            },
+            x => Err(TypeError::new(Type::Symbol, x).into()),
+        }
+    }
+
+    pub(crate) fn name(self) -> String {
+        let base = match self.encoding {
+            Encoding::Utf8 => "utf-8",
+            Encoding::Latin1 => "iso-latin-1",
+            Encoding::Binary => "no-conversion",
+        };
+        match (self.encoding, self.eol) {
+            (Encoding::Binary, _) => base.to_owned(),
+            (_, Eol::Unix) => format!("{base}-unix"),
+            (_, Eol::Dos) => format!("{base}-dos"),
+            (_, Eol::Mac) => format!("{base}-mac"),
+        }
+    }
+
+    pub(crate) fn decode(self, bytes: &[u8]) -> Decoded {
+        let string = match self.encoding {
+            Encoding::Binary => return Decoded::Bytes(bytes.to_vec()),
+            Encoding::Utf8 => decode_utf8(bytes),
+            Encoding::Latin1 => bytes.iter().map(|&b| char::from(b)).collect(),
+        };
+        let string = match self.eol {
+            Eol::Unix => string,
+            Eol::Dos => string.replace("\r\n", "\n"),
+            Eol::Mac => string.replace('\r', "\n"),
+        };
+        Decoded::Text(string)
+    }
+
+    /// Encode a string. Characters that can't be represented in the target
+    /// encoding are replaced with `?`. Raw byte characters are encoded as the
+    /// byte they stand for.
+    pub(crate) fn encode(self, string: &str) -> Vec<u8> {
+        let converted;
+        let string = match self.eol {
+            Eol::Unix => string,
+            Eol::Dos => {
+                converted = string.replace('\n', "\r\n");
+                &converted
+            }
+            Eol::Mac => {
+                converted = string.replace('\n', "\r");
+                &converted
+            }
+        };
+        match self.encoding {
+            Encoding::Utf8 => encode_utf8(string),
+            Encoding::Latin1 | Encoding::Binary => {
+                string.chars().map(|c| byte_of_char(c).unwrap_or(b'?')).collect()
+            }
+        }
+    }
+
+    /// The number of bytes at the end of `bytes` that can't be decoded yet,
+    /// because the bytes that follow them may change how they are decoded.
+    fn incomplete_suffix(self, bytes: &[u8]) -> usize {
+        let partial = match self.encoding {
+            Encoding::Utf8 => utf8_incomplete_suffix(bytes),
+            Encoding::Latin1 | Encoding::Binary => 0,
+        };
+        // A CR at the end may be the first half of a CRLF
+        let cr = partial == 0 && self.eol == Eol::Dos && bytes.last() == Some(&b'\r');
+        partial + usize::from(cr)
+    }
+}
+
+/// The length of the UTF-8 sequence that `bytes` ends with if it is missing
+/// some of its continuation bytes, or 0 if it is complete.
+fn utf8_incomplete_suffix(bytes: &[u8]) -> usize {
+    // A sequence is at most 4 bytes long, so only the last 3 bytes can be part
+    // of an incomplete one
+    for len in 1..=bytes.len().min(3) {
+        let width = match bytes[bytes.len() - len] {
+            0x80..=0xBF => continue,
+            0xC2..=0xDF => 2,
+            0xE0..=0xEF => 3,
+            0xF0..=0xF4 => 4,
+            _ => return 0,
+        };
+        return if width > len { len } else { 0 };
+    }
+    0
+}
+
+/// Decodes bytes that arrive in pieces, like the output of a process. Bytes
+/// at the end of a piece that may be the start of a character split across
+/// pieces are held back until the next one.
+#[derive(Debug)]
+pub(crate) struct StreamDecoder {
+    pub(crate) coding: CodingSystem,
+    pending: Vec<u8>,
+}
+
+impl StreamDecoder {
+    pub(crate) fn new(coding: CodingSystem) -> Self {
+        Self { coding, pending: Vec::new() }
+    }
+
+    /// Decode the next piece of the stream.
+    pub(crate) fn decode(&mut self, bytes: &[u8]) -> Decoded {
+        self.pending.extend_from_slice(bytes);
+        let held = self.coding.incomplete_suffix(&self.pending);
+        let rest = self.pending.split_off(self.pending.len() - held);
+        let complete = std::mem::replace(&mut self.pending, rest);
+        self.coding.decode(&complete)
+    }
+
+    /// Decode the bytes that were held back at the end of the stream.
+    pub(crate) fn finish(&mut self) -> Decoded {
+        let pending = std::mem::take(&mut self.pending);
+        self.coding.decode(&pending)
+    }
+}
+
+/// Decode UTF-8 text. Bytes that are not part of a valid sequence become raw
+/// byte characters, so that encoding the text again gives back the original
+/// bytes. So do the code points that rune uses for raw bytes internally.
+pub(crate) fn decode_utf8(bytes: &[u8]) -> String {
+    let mut string = String::with_capacity(bytes.len());
+    for chunk in bytes.utf8_chunks() {
+        for chr in chunk.valid().chars() {
+            match char_raw_byte(chr) {
+                Some(_) => string.extend(chr.encode_utf8(&mut [0; 4]).bytes().map(raw_byte_char)),
+                None => string.push(chr),
+            }
+        }
+        string.extend(chunk.invalid().iter().map(|&b| raw_byte_char(b)));
+    }
+    string
+}
+
+/// Encode text as UTF-8, with raw byte characters as single bytes.
+pub(crate) fn encode_utf8(string: &str) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(string.len());
+    for chr in string.chars() {
+        match char_raw_byte(chr) {
+            Some(byte) => bytes.push(byte),
+            None => bytes.extend_from_slice(chr.encode_utf8(&mut [0; 4]).as_bytes()),
+        }
+    }
+    bytes
+}
+
+/// The byte for a raw byte character or a character below 256.
+pub(crate) fn byte_of_char(chr: char) -> Option<u8> {
+    char_raw_byte(chr).or_else(|| u8::try_from(chr).ok())
+}
+
+/// Get the bytes that make up a lisp string. Multibyte strings whose
+/// characters all fit in a byte are treated as unibyte.
+fn string_bytes(string: Object) -> Result<Vec<u8>> {
+    match string.untag() {
+        ObjectType::ByteString(s) => Ok(s.to_vec()),
+        ObjectType::String(s) => match s.chars().map(byte_of_char).collect() {
+            Some(bytes) => Ok(bytes),
+            None => Ok(encode_utf8(s)),
+        },
+        x => Err(TypeError::new(Type::String, x).into()),
+    }
+}
+
+/// Get the coding system stored in a variable, or `None` if the variable is
+/// unset or nil.
+pub(crate) fn coding_system_from_var(
+    var: Symbol,
+    env: &Rt<Env>,
+    cx: &Context,
+) -> Result<Option<CodingSystem>> {
+    match env.vars.get(var).map(|x| x.bind(cx)) {
+        Some(x) if !x.is_nil() => Ok(Some(CodingSystem::from_obj(x)?)),
+        _ => Ok(None),
+    }
+}
+
+pub(crate) fn set_last_coding_system(coding: CodingSystem, env: &mut Rt<Env>, cx: &Context) {
+    let name: Object = intern(&coding.name(), cx).into();
+    env.vars.insert(sym::LAST_CODING_SYSTEM_USED, name);
+}
+
+#[defun]
+fn decode_coding_string<'ob>(
+    string: Object,
+    coding_system: Object,
+    _nocopy: Option<Object>,
+    _buffer: Option<Object>,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    let coding = CodingSystem::from_obj(coding_system)?;
+    set_last_coding_system(coding, env, cx);
+    let bytes = string_bytes(string)?;
+    Ok(match coding.decode(&bytes) {
+        Decoded::Text(text) => cx.add(text),
+        Decoded::Bytes(bytes) => cx.add(bytes),
+    })
+}
+
+#[defun]
+fn encode_coding_string<'ob>(
+    string: Object,
+    coding_system: Object,
+    _nocopy: Option<Object>,
+    _buffer: Option<Object>,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    let coding = CodingSystem::from_obj(coding_system)?;
+    set_last_coding_system(coding, env, cx);
+    let bytes = match string.untag() {
+        ObjectType::String(s) => coding.encode(s),
+        ObjectType::ByteString(s) => s.to_vec(),
+        x => bail!(TypeError::new(Type::String, x)),
+    };
+    Ok(cx.add(bytes))
+}
+
+#[defun]
+fn coding_system_p(object: Object) -> bool {
+    match object.untag() {
+        ObjectType::NIL => true,
+        ObjectType::Symbol(sym) => CodingSystem::from_name(sym.name()).is_some(),
+        _ => false,
+    }
+}
+
+#[defun]
+fn check_coding_system(coding_system: Object) -> Result<Object> {
+    CodingSystem::from_obj(coding_system)?;
+    Ok(coding_system)
+}
+
+#[defun]
+fn coding_system_eol_type(coding_system: Object) -> Result<Object> {
+    if coding_system.is_nil() {
+        return Ok(NIL);
+    }
+    let coding = CodingSystem::from_obj(coding_system)?;
+    Ok(match coding.eol {
+        Eol::Unix => 0.into(),
+        Eol::Dos => 1.into(),
+        Eol::Mac => 2.into(),
+    })
+}
+
+defvar!(CODING_SYSTEM_FOR_READ);
+defvar!(CODING_SYSTEM_FOR_WRITE);
+defvar!(LAST_CODING_SYSTEM_USED);
+defvar!(BUFFER_FILE_CODING_SYSTEM);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::object::char_to_int;
+    use crate::interpreter::assert_lisp;
+
+    #[test]
+    fn test_decode() {
+        let utf8 = CodingSystem::from_name("utf-8").unwrap();
+        assert_eq!(utf8.decode("λx".as_bytes()), Decoded::Text("λx".into()));
+        let raw = format!("a{}b", raw_byte_char(0xFF));
+        assert_eq!(utf8.decode(b"a\xffb"), Decoded::Text(raw.clone()));
+        assert_eq!(char_to_int(raw_byte_char(0xFF)), 0x3F_FFFF);
+        assert_eq!(utf8.encode(&raw), b"a\xffb");
+        // The code points used for raw bytes are not read as characters
+        let reserved = "\u{10FFFF}".as_bytes();
+        let Decoded::Text(text) = utf8.decode(reserved) else { unreachable!() };
+        assert_eq!(
+            text.chars().map(char_to_int).collect::<Vec<_>>(),
+            [0x3F_FFF4, 0x3F_FF8F, 0x3F_FFBF, 0x3F_FFBF]
+        );
+        assert_eq!(utf8.encode(&text), reserved);
+        let dos = CodingSystem::from_name("utf-8-dos").unwrap();
+        assert_eq!(dos.decode(b"a\r\nb"), Decoded::Text("a\nb".into()));
+        let latin1 = CodingSystem::from_name("latin-1").unwrap();
+        assert_eq!(latin1.decode(b"caf\xe9"), Decoded::Text("café".into()));
+        let binary = CodingSystem::from_name("binary").unwrap();
+        assert_eq!(binary.decode(b"\xff\x00"), Decoded::Bytes(vec![0xff, 0]));
+        assert!(CodingSystem::from_name("ebcdic").is_none());
+    }
+
+    #[test]
+    fn test_encode() {
+        let latin1 = CodingSystem::from_name("iso-latin-1-unix").unwrap();
+        assert_eq!(latin1.encode("café λ"), b"caf\xe9 ?");
+        let dos = CodingSystem::from_name("utf-8-dos").unwrap();
+        assert_eq!(dos.encode("a\nb"), b"a\r\nb");
+        assert_eq!(CodingSystem::UTF_8.encode("λ"), "λ".as_bytes());
+    }
+
+    #[test]
+    fn test_stream_decoder() {
+        let mut decoder = StreamDecoder::new(CodingSystem::UTF_8);
+        let bytes = "aλ€😀".as_bytes();
+        let decoded: String = bytes
+            .chunks(1)
+            .map(|x| match decoder.decode(x) {
+                Decoded::Text(text) => text,
+                Decoded::Bytes(_) => unreachable!(),
+            })
+            .collect();
+        assert_eq!(decoded, "aλ€😀");
+        assert_eq!(decoder.decode(b"b\xce"), Decoded::Text("b".into()));
+        assert_eq!(decoder.finish(), Decoded::Text(raw_byte_char(0xce).to_string()));
+        let mut dos = StreamDecoder::new(CodingSystem::from_name("utf-8-dos").unwrap());
+        assert_eq!(dos.decode(b"a\r"), Decoded::Text("a".into()));
+        assert_eq!(dos.decode(b"\nb"), Decoded::Text("\nb".into()));
+    }
+
+    #[test]
+    fn test_coding_string_roundtrip() {
+        assert_lisp("(decode-coding-string (encode-coding-string \"λ\" 'utf-8) 'utf-8)", "\"λ\"");
+        assert_lisp("(coding-system-p 'utf-8-unix)", "t");
+        assert_lisp("(coding-system-p 'ebcdic)", "nil");
+    }
+}
@@ -1,11 +1,9 @@
-use crate::derive_GcMoveable;
-
-use super::gc::{Block, GcHeap, GcState, Trace};
-use super::object::{CloneIn, Gc, IntoObject, NIL, ObjCell, Object, ObjectType};
+use super::gc::{AllocState, Block, GcHeap, GcMoveable, GcState, Trace};
+use super::object::{CloneIn, Gc, IntoObject, NIL, ObjCell, Object, ObjectType, PrintState};
 use anyhow::{Result, anyhow};
-use rune_core::hashmap::HashSet;
 use rune_macros::Trace;
 use std::fmt::{self, Debug, Display, Write};
+use std::ptr::NonNull;
 
 mod iter;
 pub(crate) use iter::*;
@@ -13,7 +11,21 @@ pub(crate) use iter::*;
 #[derive(Trace)]
 pub(crate) struct Cons(GcHeap<ConsInner>);
 
-derive_GcMoveable!(Cons);
+/// Cons cells are not moved by the collector (see [`ConsHeap`]), so moving
+/// one marks it instead and returns the same pointer.
+///
+/// [`ConsHeap`]: super::gc::ConsHeap
+impl GcMoveable for Cons {
+    type Value = NonNull<Self>;
+
+    fn move_value(&self, _to_space: &bumpalo::Bump) -> Option<(Self::Value, bool)> {
+        match self.0.allocation_state() {
+            AllocState::Global => None,
+            AllocState::Unmoved => Some((NonNull::from(self), super::gc::mark(self))),
+            AllocState::Forwarded(_) => unreachable!("cons cells are never forwarded"),
+        }
+    }
+}
 
 struct ConsInner {
     mutable: bool,
@@ -117,13 +129,13 @@ impl Trace for ConsInner {
 
 impl Display for Cons {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        self.display_walk(f, &mut HashSet::default())
+        self.display_walk(f, &mut PrintState::default())
     }
 }
 
 impl Debug for Cons {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        self.display_walk(f, &mut HashSet::default())
+        self.display_walk(f, &mut PrintState::default())
     }
 }
 
@@ -131,45 +143,48 @@ impl Cons {
     pub(super) fn display_walk(
         &self,
         f: &mut fmt::Formatter,
-        seen: &mut HashSet<*const u8>,
+        state: &mut PrintState,
     ) -> fmt::Result {
-        if self.is_backref(seen) {
-            return f.write_str("#0");
+        if !state.start(self.as_ptr(), f)? {
+            return Ok(());
         }
 
         f.write_char('(')?;
         let mut cons = self;
-
-        loop {
-            cons.car().untag().display_walk(f, seen)?;
+        let mut tails = 0;
+        let result = loop {
+            cons.car().untag().display_walk(f, state)?;
             match cons.cdr().untag() {
+                // a labeled tail has to be printed on its own
+                ObjectType::Cons(tail) if state.is_labeled(tail.as_ptr()) => {
+                    write!(f, " . ")?;
+                    break tail.display_walk(f, state);
+                }
                 ObjectType::Cons(tail) => {
                     cons = tail;
                     f.write_char(' ')?;
                 }
-                ObjectType::NIL => break,
+                ObjectType::NIL => break Ok(()),
                 x => {
                     write!(f, " . ")?;
-                    x.display_walk(f, seen)?;
-                    break;
+                    break x.display_walk(f, state);
                 }
             }
-            if cons.is_backref(seen) {
-                f.write_str(". #0")?;
-                break;
+            if let Some(depth) = state.depth(cons.as_ptr()) {
+                break write!(f, ". #{depth}");
             }
+            state.push_tail(cons.as_ptr());
+            tails += 1;
+        };
+        for _ in 0..=tails {
+            state.end();
         }
+        result?;
         f.write_char(')')
     }
 
-    fn is_backref(&self, seen: &mut HashSet<*const u8>) -> bool {
-        let ptr = (self as *const Self).cast();
-        if seen.contains(&ptr) {
-            true
-        } else {
-            seen.insert(ptr);
-            false
-        }
+    fn as_ptr(&self) -> *const u8 {
+        (self as *const Self).cast()
     }
 }
 
@@ -1,5 +1,5 @@
 use super::gc::{Context, ObjectMap, Rto, Slot};
-use super::object::{LispBuffer, Object, OpenBuffer, Symbol, WithLifetime};
+use super::object::{LispBuffer, NIL, Object, OpenBuffer, Symbol, WithLifetime};
 use anyhow::{Result, anyhow};
 use rune_macros::Trace;
 use std::cell::OnceCell;
@@ -10,16 +10,63 @@ pub(crate) use stack::*;
 pub(crate) use symbol_map::*;
 
 type PropertyMap<'a> = ObjectMap<Slot<Symbol<'a>>, Vec<(Slot<Symbol<'a>>, Slot<Object<'a>>)>>;
+type BufferLocalMap<'a> = ObjectMap<Slot<Object<'a>>, Vec<(Slot<Symbol<'a>>, Slot<Object<'a>>)>>;
+
+/// Variables that always have a separate value in each buffer. The value of the
+/// current buffer lives in `vars`, and the values of the other buffers are kept
+/// in `buffer_locals` until they become current again.
+const PER_BUFFER_VARS: [Symbol<'static>; 1] = [sym::BUFFER_FILE_NAME];
+
 #[derive(Debug, Default, Trace)]
 pub(crate) struct Env<'a> {
     pub(crate) vars: ObjectMap<Slot<Symbol<'a>>, Slot<Object<'a>>>,
     pub(crate) props: PropertyMap<'a>,
+    /// The values of the per-buffer and buffer-local variables in buffers that
+    /// are not current
+    buffer_locals: BufferLocalMap<'a>,
+    /// The variables that are local to the current buffer, besides the
+    /// per-buffer ones, with their default values. A variable without a
+    /// default value is void in the buffers where it is not local.
+    current_locals: Vec<(Slot<Symbol<'a>>, Option<Slot<Object<'a>>>)>,
+    /// Values of each buffer that are not variables, like the local keymap,
+    /// keyed by the function that returns them
+    buffer_slots: BufferLocalMap<'a>,
+    /// The syntax table of buffers that have not set their own
+    pub(crate) standard_syntax_table: Slot<Object<'a>>,
     pub(crate) catch_stack: Vec<Slot<Object<'a>>>,
     exception: (Slot<Object<'a>>, Slot<Object<'a>>),
     #[no_trace]
     exception_id: u32,
     binding_stack: Vec<(Slot<Symbol<'a>>, Option<Slot<Object<'a>>>)>,
-    pub(crate) match_data: Slot<Object<'a>>,
+    /// The start and end of each group of the last match, starting with the
+    /// whole match. Groups that did not match have no positions.
+    #[no_trace]
+    pub(crate) match_data: Vec<Option<(usize, usize)>>,
+    /// The buffer that the last match was in, or nil if it was in a string
+    pub(crate) match_buffer: Slot<Object<'a>>,
+    pub(crate) processes: Vec<Slot<Object<'a>>>,
+    /// The operating system side of `processes`
+    #[no_trace]
+    pub(crate) process_state: crate::process::ProcessMap,
+    /// The callbacks of the file notification watches, keyed by descriptor
+    pub(crate) file_watches: ObjectMap<Slot<Object<'a>>, Slot<Object<'a>>>,
+    /// Overlays that are attached to a buffer
+    pub(crate) overlays: Vec<Slot<Object<'a>>>,
+    /// The property lists of buffer text, indexed by the ids stored in the
+    /// buffers. Equal lists share an id.
+    pub(crate) text_properties: Vec<Slot<Object<'a>>>,
+    /// The attributes of each face on the frame, as a plist
+    pub(crate) faces: ObjectMap<Slot<Symbol<'a>>, Slot<Object<'a>>>,
+    /// The attributes that faces get on new frames, as a plist
+    pub(crate) face_defaults: ObjectMap<Slot<Symbol<'a>>, Slot<Object<'a>>>,
+    /// The frame object, or nil until it is first needed
+    pub(crate) frame: Slot<Object<'a>>,
+    /// The frame parameters that have been set, as an alist
+    pub(crate) frame_parameters: Slot<Object<'a>>,
+    /// Window objects, indexed by the ids in `window_tree`
+    pub(crate) windows: Vec<Slot<Object<'a>>>,
+    #[no_trace]
+    pub(crate) window_tree: crate::window::WindowTree<'a>,
     #[no_trace]
     pub(crate) current_buffer: CurrentBuffer<'a>,
     pub(crate) stack: LispStack<'a>,
@@ -85,11 +132,115 @@ impl<'a> RootedEnv<'a> {
         if sym.is_const() {
             Err(anyhow!("Attempt to set a constant symbol: {sym}"))
         } else {
+            if sym.is_local_if_set() {
+                self.make_local(sym);
+            }
             self.vars.insert(sym, value);
             Ok(())
         }
     }
 
+    /// Whether `var` has a local value in the current buffer.
+    pub(crate) fn is_local(&self, var: Symbol) -> bool {
+        PER_BUFFER_VARS.contains(&var) || self.current_locals.iter().any(|x| x.0 == var)
+    }
+
+    /// Give `var` a value local to the current buffer, which starts out as its
+    /// default value.
+    pub(crate) fn make_local(&mut self, var: Symbol) {
+        if !self.is_local(var) {
+            self.current_locals.push((var, self.vars.get(var)));
+        }
+    }
+
+    /// Remove the local value of `var` in the current buffer, so that it sees
+    /// the default value again.
+    pub(crate) fn kill_local(&mut self, var: Symbol, cx: &Context) {
+        let locals = self.current_locals.bind_mut(cx);
+        let Some(idx) = locals.iter().position(|x| *x.0 == var) else { return };
+        let (_, default) = locals.swap_remove(idx);
+        match default {
+            Some(default) => self.vars.insert(var, *default),
+            None => self.vars.remove(var),
+        }
+    }
+
+    /// The variables that are local to the current buffer, besides the
+    /// per-buffer ones.
+    pub(crate) fn local_variables<'ob>(&self, cx: &'ob Context) -> Vec<Symbol<'ob>> {
+        self.current_locals.iter().map(|x| x.0.bind(cx)).collect()
+    }
+
+    /// The value of `var` in buffers where it is not local.
+    pub(crate) fn default_value<'ob>(&self, var: Symbol, cx: &'ob Context) -> Option<Object<'ob>> {
+        let locals = self.current_locals.bind_ref(cx);
+        match locals.iter().find(|x| *x.0 == var) {
+            Some((_, default)) => default.as_deref().copied(),
+            None => self.vars.get(var).map(|x| x.bind(cx)),
+        }
+    }
+
+    /// Set the value of `var` in buffers where it is not local.
+    pub(crate) fn set_default(&mut self, var: Symbol, value: Object) -> Result<()> {
+        if var.is_const() {
+            return Err(anyhow!("Attempt to set a constant symbol: {var}"));
+        }
+        match self.current_locals.iter_mut().find(|x| x.0 == var) {
+            Some(local) => local.1.set(Some(value)),
+            None => self.vars.insert(var, value),
+        }
+        Ok(())
+    }
+
+    /// The value of `var` in `buffer`, which is its default value if it is not
+    /// local to the buffer.
+    pub(crate) fn local_value<'ob>(
+        &self,
+        var: Symbol,
+        buffer: &LispBuffer,
+        cx: &'ob Context,
+    ) -> Option<Object<'ob>> {
+        if self.current_buffer == *buffer {
+            return self.vars.get(var).map(|x| x.bind(cx));
+        }
+        let buffer: Object = cx.add(buffer);
+        let values = self.buffer_locals.get(buffer).map(|x| x.bind_ref(cx));
+        match values.and_then(|x| x.iter().find(|(sym, _)| *sym == var)) {
+            Some((_, value)) => Some(**value),
+            None if PER_BUFFER_VARS.contains(&var) => Some(NIL),
+            None => self.default_value(var, cx),
+        }
+    }
+
+    /// Whether `var` has a local value in `buffer`.
+    pub(crate) fn is_local_in(&self, var: Symbol, buffer: &LispBuffer, cx: &Context) -> bool {
+        if self.current_buffer == *buffer {
+            return self.is_local(var);
+        }
+        let buffer: Object = cx.add(buffer);
+        let values = self.buffer_locals.get(buffer).map(|x| x.bind_ref(cx));
+        PER_BUFFER_VARS.contains(&var) || values.is_some_and(|x| x.iter().any(|x| *x.0 == var))
+    }
+
+    /// The value of the current buffer for a slot like the local keymap, where
+    /// `key` is the function that returns it.
+    pub(crate) fn buffer_slot<'ob>(&self, key: Symbol, cx: &'ob Context) -> Option<Object<'ob>> {
+        let buffer: Object = cx.add(self.current_buffer.buf_ref);
+        let slots = self.buffer_slots.get(buffer)?.bind_ref(cx);
+        slots.iter().find(|x| *x.0 == key).map(|x| *x.1)
+    }
+
+    pub(crate) fn set_buffer_slot(&mut self, key: Symbol, value: Object, cx: &Context) {
+        let buffer: Object = cx.add(self.current_buffer.buf_ref);
+        match self.buffer_slots.get_mut(buffer) {
+            Some(slots) => match slots.iter_mut().find(|x| x.0 == key) {
+                Some(slot) => slot.1.set(value),
+                None => slots.push((key, value)),
+            },
+            None => self.buffer_slots.insert(buffer, vec![(key, value)]),
+        }
+    }
+
     pub(crate) fn set_prop(&mut self, symbol: Symbol, propname: Symbol, value: Object) {
         match self.props.get_mut(symbol) {
             Some(plist) => match plist.iter_mut().find(|x| x.0 == propname) {
@@ -131,11 +282,11 @@ impl<'a> RootedEnv<'a> {
         }
     }
 
-    pub(crate) fn defvar(&mut self, var: Symbol, value: Object) -> Result<()> {
+    pub(crate) fn defvar(&mut self, var: Symbol, value: Object, cx: &Context) -> Result<()> {
         // TOOD: Handle `eval-sexp` on defvar, which should always update the
         // value
-        if self.vars.get(var).is_none() {
-            self.set_var(var, value)?;
+        if self.default_value(var, cx).is_none() {
+            self.set_default(var, value)?;
             var.make_special();
         }
 
@@ -149,13 +300,71 @@ impl<'a> RootedEnv<'a> {
         Ok(())
     }
 
-    pub(crate) fn set_buffer(&mut self, buffer: &LispBuffer) {
+    pub(crate) fn set_buffer(&mut self, buffer: &LispBuffer, cx: &Context) {
         if buffer == self.current_buffer.buf_ref {
             return;
         }
+        let old: Object = cx.add(self.current_buffer.buf_ref);
+        let mut values: Vec<_> = PER_BUFFER_VARS
+            .into_iter()
+            .map(|var| (var, self.vars.get(var).map_or(NIL, |x| x.bind(cx))))
+            .collect();
+        // Save the local values of the old buffer and restore the defaults
+        let locals: Vec<_> = self.current_locals.bind_mut(cx).drain(..).collect();
+        for (var, default) in locals {
+            values.push((*var, self.vars.get(*var).map_or(NIL, |x| x.bind(cx))));
+            match default {
+                Some(default) => self.vars.insert(*var, *default),
+                None => self.vars.remove(*var),
+            }
+        }
+        self.buffer_locals.insert(old, values);
+
+        let new: Object = cx.add(buffer);
+        let values: Vec<(Symbol, Object)> = match self.buffer_locals.get(new) {
+            Some(values) => {
+                values.bind_ref(cx).iter().map(|(var, value)| (**var, **value)).collect()
+            }
+            None => Vec::new(),
+        };
+        for var in PER_BUFFER_VARS {
+            let value = values.iter().find(|x| x.0 == var).map_or(NIL, |x| x.1);
+            self.vars.insert(var, value);
+        }
+        for (var, value) in values {
+            if !PER_BUFFER_VARS.contains(&var) {
+                self.current_locals.push((var, self.vars.get(var)));
+                self.vars.insert(var, value);
+            }
+        }
         self.current_buffer.set(buffer);
     }
 
+    /// The value of the per-buffer variable `var` in `buffer`.
+    pub(crate) fn buffer_local_value<'ob>(
+        &self,
+        var: Symbol,
+        buffer: &LispBuffer,
+        cx: &'ob Context,
+    ) -> Object<'ob> {
+        debug_assert!(PER_BUFFER_VARS.contains(&var));
+        if self.current_buffer == *buffer {
+            return self.vars.get(var).map_or(NIL, |x| x.bind(cx));
+        }
+        let buffer: Object = cx.add(buffer);
+        let values = self.buffer_locals.get(buffer).map(|x| x.bind_ref(cx));
+        values
+            .and_then(|x| x.iter().find(|(sym, _)| *sym == var))
+            .map_or(NIL, |(_, value)| **value)
+    }
+
+    /// Forget the per-buffer variables of a killed buffer.
+    pub(crate) fn remove_buffer_locals(&mut self, buffer: &LispBuffer, cx: &Context) {
+        let buffer: Object = cx.add(buffer);
+        self.buffer_locals.remove(buffer);
+        self.buffer_slots.remove(buffer);
+    }
+
     pub(crate) fn with_buffer<T>(
         &self,
         buffer: &LispBuffer,
@@ -181,4 +390,19 @@ impl<'a> RootedEnv<'a> {
             Ok(func(&mut buffer))
         }
     }
+
+    /// Like `with_buffer_mut`, but returns `None` instead of waiting if the
+    /// buffer is in use by another thread.
+    pub(crate) fn try_with_buffer_mut<T>(
+        &mut self,
+        buffer: &LispBuffer,
+        mut func: impl FnMut(&mut OpenBuffer) -> T,
+    ) -> Option<T> {
+        if self.current_buffer == *buffer {
+            Some(func(self.current_buffer.get_mut()))
+        } else {
+            let mut buffer = buffer.try_lock()?;
+            Some(func(&mut buffer))
+        }
+    }
 }
@@ -1,59 +1,140 @@
 use crate::core::{
     gc::{Block, Context},
-    object::{CloneIn, Function, LispBuffer, Symbol, WithLifetime},
+    object::{CloneIn, Function, LispBuffer, Symbol, WithLifetime, hash_name},
 };
 use anyhow::Result;
-use rune_core::hashmap::HashMap;
+use std::collections::HashMap;
+use std::hash::{BuildHasherDefault, Hash, Hasher};
+use std::sync::{LazyLock, RwLock};
 
+/// The global symbol table. Looking up an existing symbol does not take the
+/// lock; it is only needed to create new symbols and global objects.
 pub(crate) struct SymbolMap {
-    map: SymbolMapCore,
     block: Block<true>,
 }
 
-struct SymbolMapCore {
-    map: HashMap<&'static str, Symbol<'static>>,
+/// The key used to look up a symbol by name. The hash is computed once per
+/// lookup and is cached in the symbol, so it never has to be recomputed when
+/// a table grows.
+#[derive(Clone, Copy)]
+struct SymbolKey<'a> {
+    hash: u64,
+    name: &'a str,
 }
 
-impl SymbolMapCore {
-    fn with_capacity(cap: usize) -> Self {
-        Self {
-            map: HashMap::with_capacity_and_hasher(cap, std::hash::BuildHasherDefault::default()),
-        }
+impl<'a> SymbolKey<'a> {
+    fn new(name: &'a str) -> Self {
+        Self { hash: hash_name(name), name }
     }
+}
 
-    fn get(&self, name: &str) -> Option<Symbol> {
-        self.map.get(name).map(|x| unsafe { x.with_lifetime() })
-    }
-
-    fn intern<'ob>(&mut self, name: &str, block: &Block<true>, cx: &'ob Context) -> Symbol<'ob> {
-        match self.get(name) {
-            Some(x) => cx.bind(x),
-            None => {
-                let name = name.to_owned();
-                // Leak the memory so that it is static
-                let static_name: &'static str = unsafe {
-                    let name_ptr: *const str = Box::into_raw(name.into_boxed_str());
-                    &*name_ptr
-                };
-                let sym = Symbol::new(static_name, block);
-                self.map.insert(static_name, unsafe { sym.with_lifetime() });
-                cx.bind(sym)
-            }
-        }
+impl PartialEq for SymbolKey<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.hash == other.hash && self.name == other.name
+    }
+}
+
+impl Eq for SymbolKey<'_> {}
+
+impl Hash for SymbolKey<'_> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_u64(self.hash);
+    }
+}
+
+/// A hasher that passes through the hash cached in a [`SymbolKey`].
+#[derive(Default)]
+struct KeyHasher(u64);
+
+impl Hasher for KeyHasher {
+    fn finish(&self) -> u64 {
+        self.0
     }
 
-    fn pre_init(&mut self, sym: Symbol<'static>) {
-        use std::collections::hash_map::Entry;
-        let name = sym.get().name();
-        let entry = self.map.entry(name);
-        assert!(matches!(entry, Entry::Vacant(_)), "Attempt to intitalize {name} twice");
-        entry.or_insert_with(|| sym);
+    fn write(&mut self, _: &[u8]) {
+        unreachable!("symbol keys are hashed with write_u64")
+    }
+
+    fn write_u64(&mut self, hash: u64) {
+        self.0 = hash;
+    }
+}
+
+type KeyMap = HashMap<SymbolKey<'static>, SharedSymbol, BuildHasherDefault<KeyHasher>>;
+
+/// An interned symbol that can be shared between threads. Interned symbols
+/// are never freed and their cells are designed to be shared (see
+/// [`SymbolCell`](crate::core::object::SymbolCell)).
+#[derive(Clone, Copy)]
+struct SharedSymbol(Symbol<'static>);
+
+unsafe impl Sync for SharedSymbol {}
+
+fn get(map: &KeyMap, key: SymbolKey) -> Option<Symbol<'static>> {
+    // SAFETY: The key is only borrowed for the lookup, so it does not need to
+    // outlive the name.
+    let key = unsafe { std::mem::transmute::<SymbolKey, SymbolKey<'static>>(key) };
+    map.get(&key).map(|x| x.0)
+}
+
+/// The builtin symbols. These are known at compile time and never change, so
+/// they can be read without a lock.
+static BUILTIN_INDEX: LazyLock<KeyMap> = LazyLock::new(|| {
+    let mut map =
+        KeyMap::with_capacity_and_hasher(sym::BUILTIN_SYMBOLS.len(), BuildHasherDefault::default());
+    for cell in &sym::BUILTIN_SYMBOLS {
+        let sym = unsafe { Symbol::from_ptr(cell) };
+        let key = SymbolKey { hash: cell.name_hash(), name: sym.get().name() };
+        let prev = map.insert(key, SharedSymbol(sym));
+        assert!(prev.is_none(), "Attempt to intitalize {} twice", key.name);
     }
+    map
+});
+
+const SHARDS: usize = 16;
+
+/// The symbols interned at runtime. These are split into shards by hash so
+/// that threads interning different symbols rarely contend for the same lock.
+static RUNTIME_SYMBOLS: LazyLock<[RwLock<KeyMap>; SHARDS]> =
+    LazyLock::new(|| std::array::from_fn(|_| RwLock::default()));
+
+/// hashbrown picks a bucket with the low bits of the hash and stores the top
+/// 7 bits in its control bytes, so shard on the bits just below those. Using
+/// either would leave every key in a shard sharing them.
+const SHARD_SHIFT: u32 = u64::BITS - 7 - SHARDS.trailing_zeros();
+
+fn shard(hash: u64) -> &'static RwLock<KeyMap> {
+    &RUNTIME_SYMBOLS[(hash >> SHARD_SHIFT) as usize % SHARDS]
+}
+
+/// Find the interned symbol named by `key`, if there is one.
+fn lookup(key: SymbolKey) -> Option<Symbol<'static>> {
+    get(&BUILTIN_INDEX, key).or_else(|| get(&shard(key.hash).read().unwrap(), key))
 }
 
 impl SymbolMap {
     pub(crate) fn intern<'ob>(&mut self, name: &str, cx: &'ob Context) -> Symbol<'ob> {
-        self.map.intern(name, &self.block, cx)
+        let key = SymbolKey::new(name);
+        // Another thread could have interned the symbol since it was last
+        // looked up, so check again now that we hold the lock.
+        if let Some(sym) = lookup(key) {
+            return cx.bind(sym);
+        }
+        // Leak the memory so that it is static
+        let name: &'static str = Box::leak(name.to_owned().into_boxed_str());
+        let sym = unsafe { Symbol::new(name, &self.block).with_lifetime() };
+        let key = SymbolKey { hash: key.hash, name };
+        shard(key.hash).write().unwrap().insert(key, SharedSymbol(sym));
+        cx.bind(sym)
+    }
+
+    /// All of the interned symbols.
+    pub(crate) fn symbols(&self) -> impl Iterator<Item = Symbol<'static>> + '_ {
+        let runtime: Vec<_> = RUNTIME_SYMBOLS
+            .iter()
+            .flat_map(|shard| shard.read().unwrap().values().map(|x| x.0).collect::<Vec<_>>())
+            .collect();
+        BUILTIN_INDEX.values().map(|x| x.0).chain(runtime)
     }
 
     pub(crate) fn set_func(&self, symbol: Symbol, func: Function) -> Result<()> {
@@ -71,10 +152,6 @@ impl SymbolMap {
     pub(crate) fn create_buffer(&self, name: &str) -> &LispBuffer {
         LispBuffer::create(name.to_owned(), &self.block)
     }
-
-    pub(crate) fn get(&self, name: &str) -> Option<Symbol> {
-        self.map.get(name)
-    }
 }
 
 // This file includes all symbol definitions. Generated by build.rs
@@ -82,7 +159,16 @@ include!(concat!(env!("OUT_DIR"), "/sym.rs"));
 
 /// Intern a new symbol based on `name`
 pub(crate) fn intern<'ob>(name: &str, cx: &'ob Context) -> Symbol<'ob> {
-    INTERNED_SYMBOLS.lock().unwrap().intern(name, cx)
+    match lookup(SymbolKey::new(name)) {
+        Some(sym) => cx.bind(sym),
+        None => INTERNED_SYMBOLS.lock().unwrap().intern(name, cx),
+    }
+}
+
+/// Return the interned symbol named `name`, if there is one. Unlike
+/// [`intern`], this never creates a symbol.
+pub(crate) fn intern_soft<'ob>(name: &str, cx: &'ob Context) -> Option<Symbol<'ob>> {
+    lookup(SymbolKey::new(name)).map(|x| cx.bind(x))
 }
 
 #[cfg(test)]
@@ -106,6 +192,33 @@ mod test {
         intern("foo", cx);
     }
 
+    #[test]
+    fn intern_lookup() {
+        let roots = &RootSet::default();
+        let cx = &Context::new(roots);
+        assert_eq!(intern("car", cx), sym::CAR);
+        assert_eq!(intern_soft("car", cx), Some(sym::CAR));
+        assert_eq!(intern_soft("symbol-map--not-interned", cx), None);
+        let sym = intern("symbol-map--interned", cx);
+        assert_eq!(intern_soft("symbol-map--interned", cx), Some(sym));
+        assert_eq!(sym.name_hash(), hash_name("symbol-map--interned"));
+        // symbols interned on other threads are the same symbol
+        let names: Vec<String> = (0..8).map(|i| format!("symbol-map--thread-{i}")).collect();
+        let ptrs = |names: &[String]| {
+            let roots = &RootSet::default();
+            let cx = &Context::new(roots);
+            names.iter().map(|x| intern(x, cx).as_ptr().addr()).collect::<Vec<_>>()
+        };
+        let other = std::thread::scope(|s| {
+            let handles: Vec<_> = (0..4).map(|_| s.spawn(|| ptrs(&names))).collect();
+            handles.into_iter().map(|x| x.join().unwrap()).collect::<Vec<_>>()
+        });
+        for thread in other {
+            let local: Vec<_> = names.iter().map(|x| intern(x, cx).as_ptr().addr()).collect();
+            assert_eq!(thread, local);
+        }
+    }
+
     #[test]
     fn symbol_func() {
         let roots = &RootSet::default();
@@ -154,7 +267,7 @@ mod test {
         }
         assert_eq!(cons, list!(4, 2, 3; cx));
         let sym = intern("cons-test", cx);
-        crate::data::fset(sym, cons).unwrap();
+        crate::data::fset(sym, cons, cx).unwrap();
         // is not mutable
         if let FunctionType::Cons(cons) = sym.func(cx).unwrap().untag() {
             assert!(cons.set_car(5.into()).is_err());
@@ -19,6 +19,12 @@ pub(crate) enum Type {
     List,
     Buffer,
     CharTable,
+    Process,
+    Overlay,
+    Window,
+    Frame,
+    RadixTree,
+    JsonrpcTransport,
 }
 
 /// Error provided if object was the wrong type
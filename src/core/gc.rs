@@ -3,7 +3,10 @@ mod trace;
 #[macro_use]
 mod context;
 mod heap;
+mod slab;
+pub(crate) mod stats;
 pub(crate) use context::*;
 pub(crate) use heap::*;
 pub(crate) use root::*;
+pub(in crate::core) use slab::*;
 pub(crate) use trace::*;
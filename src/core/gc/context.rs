@@ -1,5 +1,7 @@
 use super::GcState;
+use super::POISON;
 use super::Trace;
+use crate::core::cons::Cons;
 use crate::core::object::GcString;
 use crate::core::object::LispHashTable;
 use crate::core::object::{Gc, IntoObject, Object, UninternedSymbolMap, WithLifetime};
@@ -29,6 +31,8 @@ pub(in crate::core) enum DropStackElem {
 #[derive(Default)]
 pub(crate) struct Block<const CONST: bool> {
     pub(in crate::core) objects: bumpalo::Bump,
+    /// Cons cells, which are not moved by the collector. See [`ConsHeap`].
+    pub(in crate::core) conses: super::ConsHeap,
     // Allocations that will be dropped when the objects are moved. At that time
     // the allocation will get copied into the GC heap. This let's us avoid an
     // extra copy of memory when a vector is first made an object. The
@@ -51,12 +55,20 @@ pub(crate) struct Context<'rt> {
     pub(crate) block: Block<false>,
     root_set: &'rt RootSet,
     next_limit: usize,
+    /// The bytes that were live after the last collection.
+    live_bytes: usize,
+    /// The poisoned from-space of the last collection in debug builds. It is
+    /// kept until the next collection so that reads through stale pointers
+    /// see the poison instead of freed memory.
+    quarantine: Option<bumpalo::Bump>,
 }
 
 impl Drop for Context<'_> {
     fn drop(&mut self) {
+        // Nothing should survive, so don't reserve any space
+        self.live_bytes = 0;
         self.garbage_collect(true);
-        if self.block.objects.allocated_bytes() == 0 {
+        if self.block.objects.allocated_bytes() == 0 && self.block.conses.is_empty() {
             return;
         }
         if std::thread::panicking() {
@@ -102,6 +114,28 @@ impl Block<false> {
 }
 
 impl<const CONST: bool> Block<CONST> {
+    /// Allocate `obj` in the block. `data` is the size of the contents of the
+    /// object that are stored outside of it, such as the elements of a vector.
+    /// It is only used for the allocation statistics.
+    pub(in crate::core) fn alloc<T>(&self, obj: T, data: usize) -> &mut T {
+        if !CONST {
+            super::stats::record::<T>(data);
+        }
+        self.objects.alloc(obj)
+    }
+
+    /// Allocate a cons cell. Cons cells of a constant block are never freed,
+    /// so they are allocated with the other objects and can't be mutated.
+    pub(in crate::core) fn alloc_cons(&self, cons: Cons) -> &Cons {
+        if CONST {
+            let cons = self.alloc(cons, 0);
+            cons.mark_const();
+            return cons;
+        }
+        super::stats::record::<Cons>(0);
+        self.conses.alloc(cons)
+    }
+
     pub(crate) fn add<'ob, T, Tx>(&'ob self, obj: T) -> Object<'ob>
     where
         T: IntoObject<Out<'ob> = Tx>,
@@ -128,10 +162,6 @@ impl<const CONST: bool> Block<CONST> {
     /// Create a new Vec whose backing storage is already part of the GC
     /// heap. Does not require dropping when moved during garbage collection
     /// (unlike std::vec).
-    pub(crate) fn vec_new(&self) -> GcVec<'_, Object<'_>> {
-        GcVec::new_in(&self.objects)
-    }
-
     pub(crate) fn vec_with_capacity(&self, cap: usize) -> GcVec<'_, Object<'_>> {
         GcVec::with_capacity_in(cap, &self.objects)
     }
@@ -141,12 +171,24 @@ impl<'ob, 'rt> Context<'rt> {
     const MIN_GC_BYTES: usize = 2000;
     const GC_GROWTH_FACTOR: usize = 12; // divide by 10
     pub(crate) fn new(roots: &'rt RootSet) -> Self {
-        Self { block: Block::new_local(), root_set: roots, next_limit: Self::MIN_GC_BYTES }
+        Self {
+            block: Block::new_local(),
+            root_set: roots,
+            next_limit: Self::MIN_GC_BYTES,
+            live_bytes: 0,
+            quarantine: None,
+        }
     }
 
     pub(crate) fn from_block(block: Block<false>, roots: &'rt RootSet) -> Self {
         Block::assert_unique();
-        Context { block, root_set: roots, next_limit: Self::MIN_GC_BYTES }
+        Context {
+            block,
+            root_set: roots,
+            next_limit: Self::MIN_GC_BYTES,
+            live_bytes: 0,
+            quarantine: None,
+        }
     }
 
     pub(crate) fn bind<T>(&'ob self, obj: T) -> <T as WithLifetime<'ob>>::Out
@@ -161,12 +203,16 @@ impl<'ob, 'rt> Context<'rt> {
     }
 
     pub(crate) fn garbage_collect(&mut self, force: bool) {
-        let bytes = self.block.objects.allocated_bytes();
+        let bytes = self.block.objects.allocated_bytes() + self.block.conses.allocated_bytes();
         if cfg!(not(test)) && !force && bytes < self.next_limit {
             return;
         }
 
-        let mut state = GcState::new();
+        // Reserve space for about as much as survived the last collection, so
+        // that the copied objects are in one contiguous chunk. This keeps
+        // lists that were built over time close together after a collection.
+        super::stats::collected(bytes);
+        let mut state = GcState::with_capacity(self.live_bytes);
         for x in self.root_set.roots.borrow().iter() {
             // SAFETY: The contract of root structs will ensure that it removes
             // itself from this list before it drops.
@@ -176,8 +222,11 @@ impl<'ob, 'rt> Context<'rt> {
         }
 
         state.trace_stack();
+        self.block.conses.sweep();
 
-        self.next_limit = (state.to_space.allocated_bytes() * Self::GC_GROWTH_FACTOR) / 10;
+        self.live_bytes = state.to_space.allocated_bytes() - state.to_space.chunk_capacity();
+        let live = self.live_bytes + self.block.conses.allocated_bytes();
+        self.next_limit = (live * Self::GC_GROWTH_FACTOR) / 10;
         self.block.drop_stack.borrow_mut().clear();
         // Find all hashtables that have not been moved (i.e. They are no longer
         // accessible) and drop them. Otherwise, update the object pointer.
@@ -192,7 +241,24 @@ impl<'ob, 'rt> Context<'rt> {
             }
         });
 
-        self.block.objects = state.to_space;
+        let from_space = std::mem::replace(&mut self.block.objects, state.to_space);
+        if cfg!(debug_assertions) {
+            // Anything that still points into the from-space was not rooted.
+            // Poison it so that using it fails deterministically.
+            poison(&from_space);
+            self.quarantine = Some(from_space);
+        }
+    }
+}
+
+/// Overwrite every allocation in `space` with [`POISON`].
+fn poison(space: &bumpalo::Bump) {
+    // SAFETY: After a collection nothing may refer to the from-space, so
+    // there are no references to the bytes we overwrite.
+    unsafe {
+        for (ptr, len) in space.iter_allocated_chunks_raw() {
+            std::ptr::write_bytes(ptr, POISON, len);
+        }
     }
 }
 
@@ -255,6 +321,24 @@ mod test {
         cx.garbage_collect(true);
     }
 
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "used after it was garbage collected")]
+    fn test_unrooted_use_after_collect() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        // A live cell keeps the slab from being released, so the dead cells
+        // in it are poisoned
+        let live = list![0; cx];
+        root!(live, cx);
+        let cons = list![1, 2; cx];
+        // Deliberately hold the object across a collection without rooting it
+        let stale: Object<'static> = unsafe { cons.with_lifetime() };
+        cx.garbage_collect(true);
+        let ObjectType::Cons(cons) = stale.untag() else { unreachable!() };
+        let _ = cons.car();
+    }
+
     #[test]
     fn test_move_values() {
         let roots = &RootSet::default();
@@ -24,6 +24,10 @@ impl GcHeader {
         unsafe { self.header.is_present == HeaderData::PRESENT }
     }
 
+    fn is_poisoned(&self) -> bool {
+        unsafe { self.header.is_present == POISON }
+    }
+
     fn get_header(&self) -> Result<&HeaderData, NonNull<u8>> {
         if self.is_present() {
             Ok(unsafe { &*self.header })
@@ -61,6 +65,12 @@ impl HeaderData {
     }
 }
 
+/// In debug builds the from-space is overwritten with this byte after a
+/// collection. It is neither [`HeaderData::PRESENT`] nor the low byte of an
+/// aligned forwarding pointer, so a header that holds it belongs to an object
+/// that was used after it was collected.
+pub(in crate::core) const POISON: u8 = 0xDB;
+
 /// A block of memory allocated on the heap that is managed by the garbage collector.
 #[repr(C)]
 #[derive(Debug)]
@@ -109,6 +119,17 @@ impl<T> GcHeap<T> {
         }
     }
 
+    /// Panic if this object was in the from-space of a collection. This
+    /// happens when an object is held across a call that can garbage collect
+    /// without being rooted.
+    #[inline]
+    fn check_live(&self) {
+        debug_assert!(
+            !self.header().is_poisoned(),
+            "object was used after it was garbage collected; it must be rooted across calls that can collect"
+        );
+    }
+
     fn is_marked(&self) -> bool {
         self.header().get_header().unwrap().marked.get()
     }
@@ -212,12 +233,14 @@ impl<T> Deref for GcHeap<T> {
     type Target = T;
 
     fn deref(&self) -> &Self::Target {
+        self.check_live();
         &self.data
     }
 }
 
 impl<T> DerefMut for GcHeap<T> {
     fn deref_mut(&mut self) -> &mut Self::Target {
+        self.check_live();
         &mut self.data
     }
 }
@@ -712,6 +712,12 @@ where
         let root = unsafe { k.into_root() };
         self.as_mut().swap_remove(&root);
     }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (&Rt<K>, &Rt<V>)> {
+        use std::ptr::from_ref;
+        let inner = unsafe { &*from_ref(self.as_ref()).cast::<IndexMap<Rt<K>, Rt<V>>>() };
+        inner.iter()
+    }
 }
 
 impl<K, V> Trace for ObjectMap<K, V>
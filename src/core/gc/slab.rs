@@ -0,0 +1,229 @@
+//! Slab allocation of cons cells.
+//!
+//! Cons cells are the most common allocation, so they are not bump allocated
+//! and copied like other objects. Instead they are carved from page-sized
+//! slabs and reused through a free list, which means a cons cell never moves.
+//! Each slab starts with a bitmap that has a mark bit for every cell. The
+//! collector sets the bits of the cells it reaches, then [`ConsHeap::sweep`]
+//! puts the unmarked cells on the free list and releases slabs that have no
+//! live cells left.
+use super::POISON;
+use crate::core::cons::Cons;
+use std::alloc::{self, Layout};
+use std::cell::{Cell, RefCell};
+use std::mem::MaybeUninit;
+use std::ptr::{self, NonNull};
+
+/// The size and alignment of a slab. Slabs are aligned to their size, so the
+/// slab of a cell can be found by masking its address.
+const SLAB_SIZE: usize = 4096;
+const CELL_SIZE: usize = size_of::<Cons>();
+const MARK_WORDS: usize = 2;
+const CELLS: usize = (SLAB_SIZE - MARK_WORDS * size_of::<u64>()) / CELL_SIZE;
+
+#[repr(C)]
+struct Slab {
+    marks: [Cell<u64>; MARK_WORDS],
+    cells: [MaybeUninit<Cons>; CELLS],
+}
+
+const _: () = assert!(size_of::<Slab>() <= SLAB_SIZE && CELLS <= MARK_WORDS * 64);
+
+impl Slab {
+    const LAYOUT: Layout = match Layout::from_size_align(SLAB_SIZE, SLAB_SIZE) {
+        Ok(layout) => layout,
+        Err(_) => panic!("invalid slab layout"),
+    };
+
+    fn is_marked(&self, index: usize) -> bool {
+        self.marks[index / 64].get() & (1 << (index % 64)) != 0
+    }
+
+    fn live_cells(&self) -> usize {
+        self.marks.iter().map(|x| x.get().count_ones() as usize).sum()
+    }
+
+    fn cell(&self, index: usize) -> NonNull<FreeCell> {
+        NonNull::from(&self.cells[index]).cast()
+    }
+}
+
+/// A cell on the free list. The link is stored after the header, so a stale
+/// pointer to a freed cons still sees its header.
+#[repr(C)]
+struct FreeCell {
+    header: u64,
+    next: Option<NonNull<FreeCell>>,
+}
+
+const _: () = assert!(size_of::<FreeCell>() <= CELL_SIZE);
+
+/// The cons cells of a [`Block`](super::Block).
+#[derive(Default)]
+pub(in crate::core) struct ConsHeap {
+    slabs: RefCell<Vec<NonNull<Slab>>>,
+    free: Cell<Option<NonNull<FreeCell>>>,
+    /// Cells handed out since the last collection
+    allocated: Cell<usize>,
+    /// Cells that survived the last collection
+    live: Cell<usize>,
+}
+
+impl ConsHeap {
+    pub(in crate::core) fn alloc(&self, cons: Cons) -> &Cons {
+        let cell = match self.free.get() {
+            Some(cell) => cell,
+            None => self.grow(),
+        };
+        self.allocated.set(self.allocated.get() + 1);
+        // SAFETY: Cells on the free list are not used by any object, so we
+        // can take the cell and overwrite it.
+        unsafe {
+            self.free.set((*cell.as_ptr()).next);
+            let ptr = cell.cast::<Cons>().as_ptr();
+            ptr.write(cons);
+            &*ptr
+        }
+    }
+
+    /// Add a new slab and put all of its cells on the free list.
+    #[cold]
+    fn grow(&self) -> NonNull<FreeCell> {
+        let Some(slab) = NonNull::new(unsafe { alloc::alloc_zeroed(Slab::LAYOUT) }) else {
+            alloc::handle_alloc_error(Slab::LAYOUT)
+        };
+        let slab = slab.cast::<Slab>();
+        // Link the cells in reverse, so that they are handed out in address
+        // order and lists built one cell at a time are contiguous.
+        let mut free = self.free.get();
+        for index in (0..CELLS).rev() {
+            let cell = unsafe { slab.as_ref().cell(index) };
+            unsafe { (*cell.as_ptr()).next = free };
+            free = Some(cell);
+        }
+        self.slabs.borrow_mut().push(slab);
+        self.free.set(free);
+        free.unwrap()
+    }
+
+    /// The bytes of the cells that are in use, or that were allocated since
+    /// the last collection.
+    pub(in crate::core) fn allocated_bytes(&self) -> usize {
+        (self.live.get() + self.allocated.get()) * CELL_SIZE
+    }
+
+    pub(in crate::core) fn is_empty(&self) -> bool {
+        self.slabs.borrow().is_empty()
+    }
+
+    /// Free every cell that was not marked by the collection and clear the
+    /// marks for the next one.
+    pub(in crate::core) fn sweep(&self) {
+        let mut free = None;
+        let mut live = 0;
+        self.slabs.borrow_mut().retain(|slab| {
+            let ptr = slab.as_ptr();
+            let slab = unsafe { slab.as_ref() };
+            let cells = slab.live_cells();
+            if cells == 0 {
+                unsafe { alloc::dealloc(ptr.cast(), Slab::LAYOUT) };
+                return false;
+            }
+            live += cells;
+            for index in (0..CELLS).rev().filter(|x| !slab.is_marked(*x)) {
+                let cell = slab.cell(index).as_ptr();
+                unsafe {
+                    if cfg!(debug_assertions) {
+                        // Anything that still points to the cell was not
+                        // rooted. Poison it so that using it fails.
+                        ptr::write_bytes(cell.cast::<u8>(), POISON, CELL_SIZE);
+                    }
+                    (*cell).next = free;
+                }
+                free = NonNull::new(cell);
+            }
+            for word in &slab.marks {
+                word.set(0);
+            }
+            true
+        });
+        self.free.set(free);
+        self.live.set(live);
+        self.allocated.set(0);
+    }
+}
+
+impl Drop for ConsHeap {
+    fn drop(&mut self) {
+        for slab in self.slabs.get_mut().drain(..) {
+            unsafe { alloc::dealloc(slab.as_ptr().cast(), Slab::LAYOUT) };
+        }
+    }
+}
+
+/// Set the mark bit of `cons`, which must have been allocated in a
+/// [`ConsHeap`]. Returns true if it was not marked yet.
+pub(in crate::core) fn mark(cons: &Cons) -> bool {
+    let cell = ptr::from_ref(cons).cast::<u8>();
+    let slab = cell.map_addr(|x| x & !(SLAB_SIZE - 1)).cast::<Slab>();
+    // SAFETY: Slabs are aligned to their size, so masking the address of a
+    // cell gives the slab it is in.
+    let slab = unsafe { &*slab };
+    let index = (cell.addr() - slab.cells.as_ptr().addr()) / CELL_SIZE;
+    let word = &slab.marks[index / 64];
+    let bit = 1 << (index % 64);
+    let old = word.get();
+    word.set(old | bit);
+    old & bit == 0
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::gc::{Context, RootSet};
+    use crate::core::object::{NIL, Object, ObjectType};
+    use rune_core::macros::{list, root};
+
+    #[test]
+    fn test_cons_slabs() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        let slabs = |cx: &Context| cx.block.conses.slabs.borrow().len();
+        let len = CELLS as i64 * 3;
+        let mut list = NIL;
+        for i in (0..len).rev() {
+            list = Cons::new(i, list, cx).into();
+        }
+        root!(list, cx);
+        for i in 0..CELLS as i64 * 2 {
+            let _ = list![i, i; cx];
+        }
+        assert_eq!(slabs(cx), 7);
+        cx.garbage_collect(true);
+        // The slabs that only held garbage are released
+        assert_eq!(slabs(cx), 3);
+        assert_eq!(cx.block.conses.allocated_bytes(), CELLS * 3 * CELL_SIZE);
+        // and the cells that survived did not move
+        let mut obj = list.bind(cx);
+        for i in 0..len {
+            let ObjectType::Cons(cons) = obj.untag() else { unreachable!("Expected cons") };
+            assert_eq!(cons.car(), cx.add(i));
+            obj = cons.cdr();
+        }
+        assert_eq!(obj, NIL);
+
+        let keep: Object = list![1, 2, 3; cx];
+        root!(keep, cx);
+        let _ = list![4, 5, 6; cx];
+        assert_eq!(slabs(cx), 4);
+        cx.garbage_collect(true);
+        // The cells that were freed are reused before a slab is added
+        for i in 0..CELLS as i64 - 3 {
+            let _ = Cons::new1(i, cx);
+        }
+        assert_eq!(slabs(cx), 4);
+        let _ = Cons::new1(0, cx);
+        assert_eq!(slabs(cx), 5);
+        assert_eq!(keep.bind(cx), list![1, 2, 3; cx]);
+    }
+}
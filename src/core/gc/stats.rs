@@ -0,0 +1,235 @@
+//! Allocation statistics.
+//!
+//! When enabled with `--debug-alloc`, every object allocated in a [`Context`]
+//! is counted by its type and by the builtin function that was running when
+//! it was allocated. This shows which builtins allocate the most, and so are
+//! worth reducing allocation in. The statistics are kept per thread and
+//! reported by `rune--allocation-report`.
+//!
+//! [`Context`]: super::Context
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// The caller of allocations made outside of any builtin function, such as by
+/// the interpreter or the reader.
+const NO_CALLER: &str = "(none)";
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub(crate) struct Counter {
+    /// The number of objects allocated
+    pub(crate) count: u64,
+    /// The bytes allocated, including the contents of strings and vectors
+    pub(crate) bytes: u64,
+    /// The most bytes allocated between two garbage collections
+    pub(crate) peak: u64,
+    since_gc: u64,
+}
+
+impl Counter {
+    fn add(&mut self, bytes: u64) {
+        self.count += 1;
+        self.bytes += bytes;
+        self.since_gc += bytes;
+    }
+
+    fn collected(&mut self) {
+        self.peak = self.peak.max(self.since_gc);
+        self.since_gc = 0;
+    }
+}
+
+#[derive(Default)]
+struct Stats {
+    types: HashMap<&'static str, Counter>,
+    callers: HashMap<&'static str, Counter>,
+    /// The builtin function that is currently running
+    caller: Option<&'static str>,
+    /// The largest the heap has been when it was collected
+    heap_peak: usize,
+    collections: u64,
+    /// Strings whose text is stored in the object instead of a separate
+    /// allocation
+    inline_strings: u64,
+    /// String constants that the reader shared instead of allocating
+    shared_strings: u64,
+}
+
+thread_local! {
+    static STATS: RefCell<Stats> = RefCell::default();
+}
+
+/// Start recording allocations.
+pub(crate) fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+#[inline]
+pub(crate) fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Record the allocation of an object of type `T`, where `data` is the size
+/// of its contents that are stored outside of it.
+#[inline]
+pub(in crate::core) fn record<T>(data: usize) {
+    if enabled() {
+        record_slow(type_name::<T>(), (size_of::<T>() + data) as u64);
+    }
+}
+
+#[cold]
+fn record_slow(name: &'static str, bytes: u64) {
+    STATS.with_borrow_mut(|stats| {
+        stats.types.entry(name).or_default().add(bytes);
+        let caller = stats.caller.unwrap_or(NO_CALLER);
+        stats.callers.entry(caller).or_default().add(bytes);
+    });
+}
+
+/// Record that a string was stored inline.
+#[inline]
+pub(in crate::core) fn record_inline_string() {
+    if enabled() {
+        STATS.with_borrow_mut(|stats| stats.inline_strings += 1);
+    }
+}
+
+/// Record that the reader reused an equal string constant instead of
+/// allocating a new string.
+#[inline]
+pub(crate) fn record_shared_string() {
+    if enabled() {
+        STATS.with_borrow_mut(|stats| stats.shared_strings += 1);
+    }
+}
+
+/// The name of `T` without its module path.
+fn type_name<T>() -> &'static str {
+    let name = std::any::type_name::<T>();
+    name.rsplit("::").next().unwrap_or(name)
+}
+
+/// Record that the heap was collected when it was `heap_bytes` large.
+pub(in crate::core) fn collected(heap_bytes: usize) {
+    if !enabled() {
+        return;
+    }
+    STATS.with_borrow_mut(|stats| {
+        stats.types.values_mut().for_each(Counter::collected);
+        stats.callers.values_mut().for_each(Counter::collected);
+        stats.heap_peak = stats.heap_peak.max(heap_bytes);
+        stats.collections += 1;
+    });
+}
+
+/// Attributes the allocations made while it is alive to a builtin function.
+pub(crate) struct CallerGuard {
+    /// The caller to restore, if statistics are enabled
+    prev: Option<Option<&'static str>>,
+}
+
+impl CallerGuard {
+    #[inline]
+    pub(crate) fn new(name: &'static str) -> Self {
+        if !enabled() {
+            return Self { prev: None };
+        }
+        let prev = STATS.with_borrow_mut(|stats| stats.caller.replace(name));
+        Self { prev: Some(prev) }
+    }
+}
+
+impl Drop for CallerGuard {
+    fn drop(&mut self) {
+        if let Some(prev) = self.prev {
+            STATS.with_borrow_mut(|stats| stats.caller = prev);
+        }
+    }
+}
+
+fn write_table(out: &mut String, title: &str, counters: &HashMap<&'static str, Counter>) {
+    let mut rows: Vec<_> = counters.iter().map(|(name, counter)| (*name, *counter)).collect();
+    rows.sort_by(|a, b| b.1.bytes.cmp(&a.1.bytes).then(a.0.cmp(b.0)));
+    let _ = writeln!(out, "{title:<32} {:>12} {:>14} {:>14}", "count", "bytes", "peak bytes");
+    for (name, counter) in rows {
+        let Counter { count, bytes, peak, since_gc } = counter;
+        let peak = peak.max(since_gc);
+        let _ = writeln!(out, "{name:<32} {count:>12} {bytes:>14} {peak:>14}");
+    }
+}
+
+/// Return a report of the allocations recorded on this thread.
+pub(crate) fn report() -> String {
+    STATS.with_borrow(|stats| {
+        let mut out = String::new();
+        write_table(&mut out, "type", &stats.types);
+        out.push('\n');
+        write_table(&mut out, "caller", &stats.callers);
+        out.push('\n');
+        let _ = writeln!(out, "collections: {}", stats.collections);
+        let _ = writeln!(out, "heap high-water mark: {} bytes", stats.heap_peak);
+        let strings = stats.types.get("LispString").map_or(0, |x| x.count);
+        let _ = writeln!(out, "strings stored inline: {} of {strings}", stats.inline_strings);
+        let _ = writeln!(out, "string constants shared: {}", stats.shared_strings);
+        out
+    })
+}
+
+#[cfg(test)]
+pub(crate) fn type_counter(name: &str) -> Counter {
+    STATS.with_borrow(|stats| stats.types.get(name).copied().unwrap_or_default())
+}
+
+#[cfg(test)]
+pub(crate) fn string_counts() -> (u64, u64) {
+    STATS.with_borrow(|stats| (stats.inline_strings, stats.shared_strings))
+}
+
+#[cfg(test)]
+pub(crate) fn caller_counter(name: &str) -> Counter {
+    STATS.with_borrow(|stats| stats.callers.get(name).copied().unwrap_or_default())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::gc::{Context, RootSet};
+    use rune_core::macros::list;
+
+    #[test]
+    fn test_allocation_stats() {
+        enable();
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        let before = type_counter("Cons");
+        let _ = list![1, 2, 3; cx];
+        let after = type_counter("Cons");
+        assert_eq!(after.count - before.count, 3);
+        assert!(after.bytes > before.bytes);
+        {
+            let _guard = CallerGuard::new("test-allocation-stats");
+            let _ = cx.add(1.5);
+            let _ = cx.add("a string that is too long to be stored inline");
+        }
+        let _ = cx.add(2.5);
+        let counter = caller_counter("test-allocation-stats");
+        assert_eq!(counter.count, 2);
+        cx.garbage_collect(true);
+        assert!(caller_counter("test-allocation-stats").peak >= counter.bytes);
+        let (inline, shared) = string_counts();
+        let strings = type_counter("LispString");
+        let _ = cx.add("short");
+        let _ = cx.add("a string that is too long to be stored inline");
+        let _ = crate::reader::read_sharing_strings(r#"("shared" "shared")"#, cx).unwrap();
+        assert_eq!(type_counter("LispString").count - strings.count, 3);
+        assert_eq!(string_counts(), (inline + 1, shared + 1));
+        let report = report();
+        assert!(report.contains("strings stored inline"));
+        assert!(report.contains("LispFloat"));
+        assert!(report.contains("test-allocation-stats"));
+    }
+}
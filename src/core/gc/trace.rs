@@ -25,8 +25,10 @@ pub(crate) struct GcState {
 }
 
 impl GcState {
-    pub fn new() -> Self {
-        GcState { stack: Vec::new(), to_space: bumpalo::Bump::new() }
+    /// Create a state whose to-space can hold `bytes` without allocating
+    /// another chunk.
+    pub fn with_capacity(bytes: usize) -> Self {
+        GcState { stack: Vec::new(), to_space: bumpalo::Bump::with_capacity(bytes) }
     }
 
     pub fn push(&mut self, obj: Object) {
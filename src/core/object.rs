@@ -16,8 +16,10 @@ mod convert;
 mod float;
 mod func;
 mod hashtable;
+mod print;
 mod string;
 mod symbol;
+mod symbol_with_pos;
 mod tagged;
 mod vector;
 
@@ -28,8 +30,10 @@ pub(crate) use convert::*;
 pub(crate) use float::*;
 pub(crate) use func::*;
 pub(crate) use hashtable::*;
+pub(crate) use print::*;
 pub(crate) use string::*;
 pub(crate) use symbol::*;
+pub(crate) use symbol_with_pos::*;
 pub(crate) use tagged::*;
 pub(crate) use vector::*;
 
@@ -1,10 +1,12 @@
-use super::{Gc, Object, ObjectType, TagType, WithLifetime};
+use super::{Gc, Object, ObjectType, TagType, WithLifetime, int_to_char, raw_byte_char};
 use crate::{
     core::{
         error::{Type, TypeError},
         gc::{Block, Context, GcHeap, GcState, Trace},
     },
     derive_GcMoveable,
+    library::{interval_tree::IntervalTree, span_queue::SpanQueue, text_props::TextProperties},
+    syntax::SyntaxCache,
 };
 use anyhow::{Result, bail};
 use rune_macros::Trace;
@@ -44,33 +46,260 @@ impl OpenBuffer<'_> {
         cx.bind(self.back_ref)
     }
 
+    /// Insert `arg` at point, leaving point after the inserted text.
     pub(crate) fn insert(&mut self, arg: Object) -> Result<()> {
         match arg.untag() {
             ObjectType::Int(i) => {
-                let Ok(u_32) = i.try_into() else { bail!("{i} is an invalid char") };
-                let Some(chr) = char::from_u32(u_32) else { bail!("{i} is an Invalid char") };
-                self.get_mut().text.insert_char(chr);
+                let Ok(chr) = int_to_char(i) else { bail!("{i} is an invalid char") };
+                self.insert_str(chr.encode_utf8(&mut [0; 4]));
+            }
+            ObjectType::String(s) => self.insert_str(s),
+            // Bytes 128 to 255 of a unibyte string become raw byte characters
+            ObjectType::ByteString(s) => {
+                let text: String = s.iter().map(|&b| raw_byte_char(b)).collect();
+                self.insert_str(&text);
             }
-            ObjectType::String(s) => self.get_mut().text.insert(s),
             x => bail!(TypeError::new(Type::String, x)),
         }
         Ok(())
     }
 
+    /// Insert `text` at point, leaving point after the inserted text.
+    pub(crate) fn insert_str(&mut self, text: &str) {
+        let data = self.get_mut();
+        let start = data.text.cursor().chars();
+        data.text.insert(text);
+        let len = text.chars().count();
+        if len > 0 {
+            data.modified(start);
+            data.record_change(Change::Insert { beg: start, end: start + len });
+        }
+        // Text inserted at a marker goes after it
+        for pos in data.marker_positions().filter(|x| **x > start) {
+            *pos += len;
+        }
+        data.overlays.insert_text(start, len);
+        data.properties.insert_text(start, len);
+        data.syntax_cache.invalidate(start);
+        if let Some(queue) = &mut data.unfontified {
+            queue.insert_text(start, len);
+        }
+    }
+
+    /// The current position of point, starting from 1.
+    pub(crate) fn point(&self) -> usize {
+        self.get().text.cursor().chars() + 1
+    }
+
+    /// The smallest position in the accessible portion of the buffer.
+    pub(crate) fn point_min(&self) -> usize {
+        self.get().narrowing.start + 1
+    }
+
+    /// The largest position in the accessible portion of the buffer.
+    pub(crate) fn point_max(&self) -> usize {
+        let data = self.get();
+        data.text.len_chars().saturating_sub(data.narrowing.end_offset) + 1
+    }
+
+    /// Restrict editing to the text between `start` and `end`, which may be
+    /// given in either order. Point is moved into the new bounds.
+    pub(crate) fn narrow(&mut self, start: usize, end: usize) -> Result<()> {
+        let start = self.in_buffer(start)?;
+        let end = self.in_buffer(end)?;
+        let (start, end) = if start <= end { (start, end) } else { (end, start) };
+        let len = self.get().text.len_chars();
+        self.set_narrowing(Narrowing { start, end_offset: len - end });
+        Ok(())
+    }
+
+    /// Remove any restrictions on the accessible portion of the buffer.
+    pub(crate) fn widen(&mut self) {
+        self.get_mut().narrowing = Narrowing::default();
+    }
+
+    pub(crate) fn narrowing(&self) -> Narrowing {
+        self.get().narrowing
+    }
+
+    /// Restore a narrowing saved with [`Self::narrowing`]. The bounds are
+    /// clamped to the buffer, and point is moved inside them.
+    pub(crate) fn set_narrowing(&mut self, narrowing: Narrowing) {
+        let data = self.get_mut();
+        let len = data.text.len_chars();
+        let start = narrowing.start.min(len);
+        let end_offset = narrowing.end_offset.min(len - start);
+        data.narrowing = Narrowing { start, end_offset };
+        let point = self.point();
+        self.goto_char(point);
+    }
+
+    /// Move point to `pos`, clamped to the accessible portion of the buffer.
+    pub(crate) fn goto_char(&mut self, pos: usize) {
+        let pos = pos.clamp(self.point_min(), self.point_max());
+        self.get_mut().text.set_cursor(pos - 1);
+    }
+
+    /// The position of the mark, or `None` if it has not been set.
+    pub(crate) fn mark(&self) -> Option<usize> {
+        self.get().mark.map(|x| x + 1)
+    }
+
+    pub(crate) fn set_mark(&mut self, pos: Option<usize>) -> Result<()> {
+        let mark = match pos {
+            Some(pos) => Some(self.in_buffer(pos)?),
+            None => None,
+        };
+        self.get_mut().mark = mark;
+        Ok(())
+    }
+
     pub(crate) fn slice_with_gap(&self, beg: usize, end: usize) -> Result<(&str, &str)> {
         let beg = self.in_range(beg)?;
         let end = self.in_range(end)?;
         Ok(self.get().text.slice(beg..end))
     }
 
+    /// Delete the text between `beg` and `end`, which may be given in either
+    /// order.
     pub(crate) fn delete(&mut self, beg: usize, end: usize) -> Result<()> {
         let beg = self.in_range(beg)?;
         let end = self.in_range(end)?;
-        self.get_mut().text.delete_range(beg, end);
+        let (beg, end) = if beg <= end { (beg, end) } else { (end, beg) };
+        let data = self.get_mut();
+        if beg != end && data.undo_log.is_some() {
+            let (s1, s2) = data.text.slice(beg..end);
+            let text = [s1, s2].concat();
+            data.record_change(Change::Delete { pos: beg, text });
+        }
+        data.text.delete_range(beg, end);
+        if beg != end {
+            data.modified(beg);
+        }
+        for pos in data.marker_positions() {
+            if *pos >= end {
+                *pos -= end - beg;
+            } else if *pos > beg {
+                *pos = beg;
+            }
+        }
+        data.overlays.delete_text(beg, end);
+        data.properties.delete_text(beg, end);
+        data.syntax_cache.invalidate(beg);
+        if let Some(queue) = &mut data.unfontified {
+            queue.delete_text(beg, end);
+            // The text around the deletion may now match differently
+            queue.add(beg.saturating_sub(1), (beg + 1).min(data.text.len_chars()));
+        }
         Ok(())
     }
 
-    fn in_range(&self, pos: usize) -> Result<usize> {
+    /// True if the buffer has been modified since it was last marked as
+    /// unmodified.
+    pub(crate) fn modified_p(&self) -> bool {
+        let data = self.get();
+        data.save_modiff < data.modiff
+    }
+
+    pub(crate) fn set_modified_p(&mut self, flag: bool) {
+        let data = self.get_mut();
+        if !flag {
+            data.save_modiff = data.modiff;
+        } else if data.save_modiff >= data.modiff {
+            // modiff starts at 1, so this can't underflow
+            data.save_modiff = data.modiff - 1;
+        }
+    }
+
+    /// True if the buffer has changed since it was last auto-saved.
+    pub(crate) fn needs_auto_save(&self) -> bool {
+        let data = self.get();
+        data.auto_save_modiff < data.modiff && self.modified_p()
+    }
+
+    /// True if the buffer has been auto-saved since it was last marked as
+    /// unmodified.
+    pub(crate) fn recent_auto_save_p(&self) -> bool {
+        let data = self.get();
+        data.save_modiff < data.auto_save_modiff
+    }
+
+    pub(crate) fn set_auto_saved(&mut self) {
+        let data = self.get_mut();
+        data.auto_save_modiff = data.modiff;
+    }
+
+    /// The whole text of the buffer, ignoring any narrowing.
+    pub(crate) fn whole_text(&self) -> String {
+        let text = &self.get().text;
+        let (s1, s2) = text.slice(0..text.len_chars());
+        [s1, s2].concat()
+    }
+
+    /// Set the text properties between the character offsets `beg` and `end`.
+    /// This counts as a modification, but not a change to the characters.
+    pub(crate) fn set_properties(&mut self, beg: usize, end: usize, props: Option<usize>) {
+        let data = self.get_mut();
+        data.properties.set(beg, end, props);
+        data.modiff += 1;
+    }
+
+    /// Create a marker at `pos` that is adjusted as text is inserted and
+    /// deleted. The marker stays alive until it is removed with
+    /// [`Self::remove_marker`].
+    pub(crate) fn add_marker(&mut self, pos: usize) -> MarkerId {
+        let pos = self.clip_to_buffer(pos);
+        let markers = &mut self.get_mut().markers;
+        match markers.iter().position(Option::is_none) {
+            Some(idx) => {
+                markers[idx] = Some(pos);
+                MarkerId(idx)
+            }
+            None => {
+                markers.push(Some(pos));
+                MarkerId(markers.len() - 1)
+            }
+        }
+    }
+
+    /// Remove a marker, returning its current position.
+    pub(crate) fn remove_marker(&mut self, id: MarkerId) -> usize {
+        let pos = self.get_mut().markers[id.0].take().expect("marker was already removed");
+        pos + 1
+    }
+
+    /// Save point and the mark so they can be restored by
+    /// [`Self::restore_excursion`], even if the text around them is edited.
+    pub(crate) fn save_excursion(&mut self) -> Excursion {
+        let point = self.add_marker(self.point());
+        let mark = self.mark().map(|mark| self.add_marker(mark));
+        Excursion { point, mark }
+    }
+
+    pub(crate) fn restore_excursion(&mut self, excursion: Excursion) {
+        let point = self.remove_marker(excursion.point);
+        self.goto_char(point);
+        let mark = excursion.mark.map(|mark| self.remove_marker(mark) - 1);
+        self.get_mut().mark = mark;
+    }
+
+    /// Check that `pos` is in the accessible portion of the buffer and convert
+    /// it to a character offset.
+    pub(crate) fn in_range(&self, pos: usize) -> Result<usize> {
+        if pos < self.point_min() || pos > self.point_max() {
+            bail!("Position {pos} out of range in {}", self.get().name);
+        }
+        Ok(pos - 1)
+    }
+
+    /// Clamp `pos` to the whole buffer, ignoring any narrowing, and convert it
+    /// to a character offset.
+    pub(crate) fn clip_to_buffer(&self, pos: usize) -> usize {
+        pos.clamp(1, self.get().text.len_chars() + 1) - 1
+    }
+
+    /// Like `in_range`, but ignores any narrowing.
+    fn in_buffer(&self, pos: usize) -> Result<usize> {
         if pos == 0 || pos > self.get().text.len_chars() + 1 {
             bail!("Position {pos} out of range in {}", self.get().name);
         }
@@ -112,6 +341,112 @@ impl DerefMut for OpenBuffer<'_> {
 pub(crate) struct BufferData {
     pub(crate) name: String,
     pub(crate) text: TextBuffer,
+    /// The mark as a character offset from the start of the buffer
+    pub(crate) mark: Option<usize>,
+    pub(crate) narrowing: Narrowing,
+    /// Positions that are adjusted as the text is edited, as character
+    /// offsets. Removed markers leave a `None` that is reused.
+    markers: Vec<Option<usize>>,
+    /// The character offsets of the overlays in this buffer
+    pub(crate) overlays: IntervalTree,
+    /// The text property runs of this buffer, by character offset
+    pub(crate) properties: TextProperties,
+    /// Incremented each time the buffer is modified
+    pub(crate) modiff: u64,
+    /// The value of `modiff` at the last change to the text
+    pub(crate) chars_modiff: u64,
+    /// The value of `modiff` when the buffer was last marked unmodified
+    save_modiff: u64,
+    /// The file the buffer is auto-saved to, if auto-saving is enabled
+    pub(crate) auto_save_file_name: Option<String>,
+    /// The value of `modiff` when the buffer was last auto-saved
+    auto_save_modiff: u64,
+    /// True once the file visited by the buffer has been backed up
+    pub(crate) backed_up: bool,
+    /// The modification time of the visited file when it was last read or
+    /// written
+    pub(crate) visited_modtime: VisitedModtime,
+    /// The smallest character offset changed since the last redisplay. The
+    /// text before it still looks the same on the screen.
+    pub(crate) changed_from: Option<usize>,
+    /// The changes made to the text, oldest first, or `None` if undo is
+    /// disabled
+    pub(crate) undo_log: Option<Vec<Change>>,
+    /// The text that jit-lock has yet to fontify, or `None` if jit-lock is
+    /// off in this buffer
+    pub(crate) unfontified: Option<SpanQueue>,
+    /// The parse states cached by `syntax-ppss`
+    pub(crate) syntax_cache: SyntaxCache,
+}
+
+/// A change to the text of a buffer, as recorded in its undo log. Positions
+/// are character offsets.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Change {
+    /// The text between `beg` and `end` was inserted
+    Insert { beg: usize, end: usize },
+    /// `text` was deleted from `pos`
+    Delete { pos: usize, text: String },
+}
+
+/// The last known state of the file visited by a buffer.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum VisitedModtime {
+    /// The buffer is not visiting a file, or the time is not being tracked
+    #[default]
+    Unknown,
+    /// The file did not exist when it was visited
+    Nonexistent,
+    Time(std::time::SystemTime),
+}
+
+impl VisitedModtime {
+    /// The current state of the file at `path`.
+    pub(crate) fn of_file(path: impl AsRef<std::path::Path>) -> Self {
+        match std::fs::metadata(path).and_then(|x| x.modified()) {
+            Ok(time) => Self::Time(time),
+            Err(_) => Self::Nonexistent,
+        }
+    }
+}
+
+impl BufferData {
+    /// Record a change to the text starting at the character offset `pos`.
+    fn modified(&mut self, pos: usize) {
+        self.modiff += 1;
+        self.chars_modiff = self.modiff;
+        self.changed_from = Some(self.changed_from.map_or(pos, |x| x.min(pos)));
+    }
+
+    fn record_change(&mut self, change: Change) {
+        if let Some(log) = &mut self.undo_log {
+            log.push(change);
+        }
+    }
+
+    /// All the positions that need to be adjusted when the text changes.
+    fn marker_positions(&mut self) -> impl Iterator<Item = &mut usize> {
+        self.mark.iter_mut().chain(self.markers.iter_mut().flatten())
+    }
+}
+
+/// A handle to a marker created with [`OpenBuffer::add_marker`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) struct MarkerId(usize);
+
+/// The state saved by `save-excursion`.
+#[derive(Debug)]
+pub(crate) struct Excursion {
+    point: MarkerId,
+    mark: Option<MarkerId>,
+}
+
+/// The accessible portion of a buffer. The end is counted back from the end of
+/// the buffer so that text inserted inside the region extends it.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub(crate) struct Narrowing {
+    start: usize,
+    end_offset: usize,
 }
 
 #[derive(Debug)]
@@ -129,12 +464,33 @@ derive_GcMoveable!(LispBuffer);
 impl LispBuffer {
     pub(crate) fn create(name: String, block: &Block<true>) -> &LispBuffer {
         let buffer = unsafe { Self::new(name, block) };
-        block.objects.alloc(buffer)
+        block.alloc(buffer, 0)
     }
 
     pub(crate) unsafe fn new(name: String, _: &Block<true>) -> LispBuffer {
+        // Like in Emacs, undo is disabled in internal buffers
+        let undo_log = (!name.starts_with(' ')).then(Vec::new);
         let new = LispBufferInner {
-            text_buffer: Mutex::new(Some(BufferData { name, text: TextBuffer::new() })),
+            text_buffer: Mutex::new(Some(BufferData {
+                name,
+                text: TextBuffer::new(),
+                mark: None,
+                narrowing: Narrowing::default(),
+                markers: Vec::new(),
+                overlays: IntervalTree::default(),
+                properties: TextProperties::default(),
+                modiff: 1,
+                chars_modiff: 1,
+                save_modiff: 1,
+                auto_save_file_name: None,
+                auto_save_modiff: 1,
+                backed_up: false,
+                visited_modtime: VisitedModtime::Unknown,
+                changed_from: None,
+                undo_log,
+                unfontified: None,
+                syntax_cache: SyntaxCache::default(),
+            })),
         };
         Self(GcHeap::new(new, true))
     }
@@ -146,6 +502,13 @@ impl LispBuffer {
         }
         Ok(OpenBuffer { data: guard, back_ref: self })
     }
+
+    /// Like `lock`, but returns `None` if the buffer is in use elsewhere or
+    /// has been killed.
+    pub(in crate::core) fn try_lock(&self) -> Option<OpenBuffer<'_>> {
+        let guard = self.0.text_buffer.try_lock().ok()?;
+        guard.is_some().then(|| OpenBuffer { data: guard, back_ref: self })
+    }
 }
 
 impl PartialEq for LispBufferInner {
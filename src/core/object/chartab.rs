@@ -71,6 +71,20 @@ impl CharTable {
         unsafe { self.0.data.borrow_mut().insert(idx, Slot::new(item.with_lifetime())) };
     }
 
+    pub fn parent(&self) -> Option<&Self> {
+        self.0.parent.borrow().as_ref().map(|x| **x)
+    }
+
+    /// The value of characters that have not been set.
+    pub fn init(&self) -> Object<'_> {
+        *self.0.init
+    }
+
+    /// The characters that have been set, and their values.
+    pub fn entries(&self) -> Vec<(usize, Object<'_>)> {
+        self.0.data.borrow().iter().map(|(idx, x)| (*idx, **x)).collect()
+    }
+
     pub fn set_parent(&self, new: Option<&Self>) {
         let new_ptr = new.map(|n| unsafe { Slot::new(n.with_lifetime()) });
         *self.0.parent.borrow_mut() = new_ptr;
@@ -7,8 +7,9 @@ use crate::data::LispError;
 use super::{
     super::error::{Type, TypeError},
     ByteString, CharTable, LispHashTable, LispString, LispVec, NIL, OptionalFlag, TRUE,
+    int_to_char,
 };
-use super::{Gc, LispFloat, Object, ObjectType, Symbol};
+use super::{Gc, LispFloat, Object, ObjectType, Symbol, SymbolWithPos};
 use anyhow::Context;
 
 impl<'ob> TryFrom<Object<'ob>> for &'ob str {
@@ -43,6 +44,31 @@ impl<'ob> TryFrom<Object<'ob>> for Option<&'ob str> {
     }
 }
 
+/// Borrow the bytes of a string. Unlike `&str` this also accepts unibyte
+/// strings, so it can be used by functions that operate on raw bytes.
+impl<'ob> TryFrom<Object<'ob>> for &'ob [u8] {
+    type Error = TypeError;
+    fn try_from(obj: Object<'ob>) -> Result<Self, Self::Error> {
+        match obj.untag() {
+            ObjectType::String(x) => Ok(x.as_bytes()),
+            ObjectType::ByteString(x) => Ok(x.inner()),
+            x => Err(TypeError::new(Type::String, x)),
+        }
+    }
+}
+
+impl<'ob> TryFrom<Object<'ob>> for Option<&'ob [u8]> {
+    type Error = TypeError;
+    fn try_from(obj: Object<'ob>) -> Result<Self, Self::Error> {
+        match obj.untag() {
+            ObjectType::NIL => Ok(None),
+            ObjectType::String(x) => Ok(Some(x.as_bytes())),
+            ObjectType::ByteString(x) => Ok(Some(x.inner())),
+            x => Err(TypeError::new(Type::String, x)),
+        }
+    }
+}
+
 impl<'ob> TryFrom<Object<'ob>> for usize {
     type Error = anyhow::Error;
     fn try_from(obj: Object<'ob>) -> Result<Self, Self::Error> {
@@ -60,8 +86,7 @@ impl<'ob> TryFrom<Object<'ob>> for char {
     fn try_from(obj: Object<'ob>) -> Result<Self, Self::Error> {
         let err = || TypeError::new(Type::Char, obj);
         let ObjectType::Int(x) = obj.untag() else { Err(err())? };
-        let Ok(x) = u32::try_from(x) else { Err(err())? };
-        std::char::from_u32(x).ok_or_else(err)
+        int_to_char(x).map_err(|_| err())
     }
 }
 
@@ -93,6 +118,34 @@ impl TryFrom<Object<'_>> for OptionalFlag {
     }
 }
 
+/// Keyword arguments of a defun, such as `:test` and `:size`. `T` is a struct
+/// that derives [`Keywords`](rune_macros::Keywords), with a field for each
+/// keyword. This is taken as the last argument, in place of the rest args.
+pub(crate) struct Keywords<T>(T);
+
+/// Parse keyword arguments from a plist. Implemented by
+/// `#[derive(Keywords)]`.
+pub(crate) trait KeywordArgs<'ob>: Sized {
+    fn from_plist(plist: &[Object<'ob>], name: &str) -> anyhow::Result<Self>;
+}
+
+impl<T> Keywords<T> {
+    pub(crate) fn parse<'ob>(plist: &[Object<'ob>], name: &str) -> anyhow::Result<Self>
+    where
+        T: KeywordArgs<'ob>,
+    {
+        T::from_plist(plist, name).map(Self)
+    }
+}
+
+impl<T> std::ops::Deref for Keywords<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
 /// This function is required because we have no specialization yet.
 /// Essentially this let's us convert one type to another "in place"
 /// without the need to allocate a new slice. We ensure that the two
@@ -126,6 +179,7 @@ define_unbox!(ByteString, String, &'ob ByteString);
 define_unbox!(Vec, &'ob LispVec);
 define_unbox!(Symbol, Symbol<'ob>);
 define_unbox!(CharTable, &'ob CharTable);
+define_unbox!(SymbolWithPos, Symbol, &'ob SymbolWithPos);
 
 impl<'ob, T> From<Option<T>> for Object<'ob>
 where
@@ -170,4 +224,18 @@ mod test {
         let res = wrapper(vec.as_slice());
         assert_eq!(6, res.unwrap());
     }
+
+    #[test]
+    fn test_bytes() {
+        let roots = &RootSet::default();
+        let cx = &Context::new(roots);
+        let string = cx.add("foo");
+        let bytes = cx.add(b"\xff\x00".to_vec());
+        let s: &str = string.try_into().unwrap();
+        assert_eq!(s.as_ptr(), <&[u8]>::try_from(string).unwrap().as_ptr());
+        assert_eq!(<&[u8]>::try_from(bytes).unwrap(), b"\xff\x00");
+        assert!(<&str>::try_from(bytes).is_err());
+        assert!(<&[u8]>::try_from(cx.add(1)).is_err());
+        assert_eq!(<Option<&[u8]>>::try_from(NIL).unwrap(), None);
+    }
 }
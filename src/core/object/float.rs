@@ -41,12 +41,7 @@ impl<'new> CloneIn<'new, &'new LispFloat> for LispFloat {
 
 impl Display for LispFloat {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        let float = **self;
-        if float.fract() == 0.0_f64 {
-            write!(f, "{float:.1}")
-        } else {
-            write!(f, "{float}")
-        }
+        write!(f, "{}", crate::library::number::float_to_string(**self))
     }
 }
 
@@ -6,7 +6,7 @@ use super::{Object, WithLifetime};
 use crate::{
     core::{
         env::Env,
-        gc::{GcHeap, Rt, Slot},
+        gc::{GcHeap, Rt, Slot, stats},
     },
     derive_GcMoveable,
 };
@@ -175,11 +175,13 @@ impl FnArgs {
 pub(crate) type BuiltInFn =
     for<'ob> fn(usize, &mut Rt<Env>, &'ob mut Context) -> Result<Object<'ob>>;
 
-#[derive(Eq)]
 pub(crate) struct SubrFn {
     pub(crate) subr: BuiltInFn,
     pub(crate) args: FnArgs,
     pub(crate) name: &'static str,
+    /// The definition of a function from an extension crate. These all share
+    /// the same `subr` and are dispatched through [`crate::extend::call`].
+    pub(crate) extension: Option<&'static rune_extend::Subr>,
 }
 define_unbox!(SubrFn, Func, &'ob SubrFn);
 
@@ -190,6 +192,10 @@ impl SubrFn {
         env: &mut Rt<Env>,
         cx: &'ob mut Context,
     ) -> Result<Object<'ob>> {
+        let _caller = stats::CallerGuard::new(self.name);
+        if let Some(extension) = self.extension {
+            return crate::extend::call(extension, arg_cnt, env, cx);
+        }
         (self.subr)(arg_cnt, env, cx)
     }
 }
@@ -219,16 +225,20 @@ impl PartialEq for SubrFn {
     fn eq(&self, other: &Self) -> bool {
         let lhs = self.subr as *const BuiltInFn;
         let rhs = other.subr as *const BuiltInFn;
-        lhs == rhs
+        let extension = |x: &Self| x.extension.map(std::ptr::from_ref::<rune_extend::Subr>);
+        lhs == rhs && extension(self) == extension(other)
     }
 }
 
+impl Eq for SubrFn {}
+
 #[cfg(test)]
 mod test {
     use super::*;
 
     fn check_arg_spec(spec: i64) {
-        assert_eq!(spec, FnArgs::from_arg_spec(spec).unwrap().into_arg_spec().try_into().unwrap());
+        let round_trip = FnArgs::from_arg_spec(spec).unwrap().into_arg_spec();
+        assert_eq!(spec, i64::try_from(round_trip).unwrap());
     }
 
     #[test]
@@ -2,11 +2,11 @@
 //! need it to support being both thread local and global. Second we need
 //! iterate and mutate at the same time. Third we need to be able to clean up
 //! the heap allocation when it is garbage collected.
-use super::{CloneIn, Gc, IntoObject, ObjCell, Object, WithLifetime};
+use super::{CloneIn, Gc, IntoObject, ObjCell, Object, PrintState, WithLifetime};
 use crate::core::env::INTERNED_SYMBOLS;
 use crate::core::gc::{Block, GcHeap, GcState, Trace};
 use crate::derive_GcMoveable;
-use rune_core::hashmap::{HashSet, IndexMap};
+use rune_core::hashmap::IndexMap;
 use rune_macros::Trace;
 use std::cell::RefCell;
 use std::fmt::{self, Debug, Display, Write};
@@ -24,13 +24,13 @@ derive_GcMoveable!(LispHashTable);
 
 impl Debug for LispHashTable {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        self.display_walk(f, &mut HashSet::default())
+        self.display_walk(f, &mut PrintState::default())
     }
 }
 
 impl Display for LispHashTable {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        self.display_walk(f, &mut HashSet::default())
+        self.display_walk(f, &mut PrintState::default())
     }
 }
 
@@ -188,26 +188,24 @@ impl LispHashTable {
     pub(super) fn display_walk(
         &self,
         f: &mut fmt::Formatter,
-        seen: &mut HashSet<*const u8>,
+        state: &mut PrintState,
     ) -> fmt::Result {
-        let ptr = (&*self.0 as *const HashTableCore).cast();
-        if seen.contains(&ptr) {
-            return write!(f, "#0");
+        if !state.start((self as *const Self).cast(), f)? {
+            return Ok(());
         }
-        seen.insert(ptr);
-
         write!(f, "#s(hash-table (")?;
         self.0.with(|x| {
             for (i, (k, v)) in x.iter().enumerate() {
                 if i != 0 {
                     f.write_char(' ')?;
                 }
-                k.untag().display_walk(f, seen)?;
+                k.untag().display_walk(f, state)?;
                 f.write_char(' ')?;
-                v.untag().display_walk(f, seen)?;
+                v.untag().display_walk(f, state)?;
             }
             Ok(())
         })?;
+        state.end();
         write!(f, "))")
     }
 }
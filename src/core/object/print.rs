@@ -0,0 +1,142 @@
+//! State for printing objects that share structure.
+use super::{LispHashTable, LispVec, ObjectType, Record};
+use crate::core::cons::Cons;
+use rune_core::hashmap::HashMap;
+use std::fmt;
+
+/// Tracks the objects that have been printed so that circular objects can be
+/// printed without looping forever.
+///
+/// By default an object that contains itself is printed as `#N`, where N is
+/// the depth of the enclosing object it refers to. When labels are used (like
+/// `print-circle`), each object that appears more than once is printed as
+/// `#N=OBJECT` the first time and as `#N#` after that.
+#[derive(Default)]
+pub(crate) struct PrintState {
+    /// The objects currently being printed, outermost first.
+    parents: Vec<*const u8>,
+    /// The label of each object that appears more than once, or 0 if the
+    /// object has not been printed yet. `None` if labels are not used.
+    labels: Option<HashMap<*const u8, usize>>,
+    next_label: usize,
+}
+
+impl PrintState {
+    /// Create a state that labels every object that appears more than once in
+    /// `obj`.
+    pub(crate) fn with_labels(obj: ObjectType) -> Self {
+        let mut counts = HashMap::default();
+        count_refs(obj, &mut counts);
+        let labels = counts.into_iter().filter(|(_, n)| *n > 1).map(|(ptr, _)| (ptr, 0)).collect();
+        Self { labels: Some(labels), ..Self::default() }
+    }
+
+    /// Start printing the object at `ptr`. If the object has already been
+    /// printed, a reference to it is written instead and this returns false.
+    /// Otherwise the caller prints the object and then calls [`Self::end`].
+    pub(crate) fn start(
+        &mut self,
+        ptr: *const u8,
+        f: &mut impl fmt::Write,
+    ) -> Result<bool, fmt::Error> {
+        if let Some(labels) = &mut self.labels {
+            match labels.get_mut(&ptr) {
+                Some(label) if *label == 0 => {
+                    self.next_label += 1;
+                    *label = self.next_label;
+                    write!(f, "#{label}=")?;
+                }
+                Some(label) => {
+                    write!(f, "#{label}#")?;
+                    return Ok(false);
+                }
+                None => {}
+            }
+        } else if let Some(depth) = self.depth(ptr) {
+            write!(f, "#{depth}")?;
+            return Ok(false);
+        }
+        self.parents.push(ptr);
+        Ok(true)
+    }
+
+    /// Finish printing the object passed to the last call to [`Self::start`].
+    pub(crate) fn end(&mut self) {
+        self.parents.pop();
+    }
+
+    /// The depth of `ptr` if it is currently being printed. Only used without
+    /// labels.
+    pub(crate) fn depth(&self, ptr: *const u8) -> Option<usize> {
+        self.parents.iter().position(|x| *x == ptr)
+    }
+
+    /// Mark the tail of a list as being printed. Tails are popped with the
+    /// list by calling [`Self::end`] for each of them.
+    pub(crate) fn push_tail(&mut self, ptr: *const u8) {
+        self.parents.push(ptr);
+    }
+
+    /// True if `ptr` is printed with a label, so it can't be printed as part
+    /// of an enclosing list.
+    pub(crate) fn is_labeled(&self, ptr: *const u8) -> bool {
+        self.labels.as_ref().is_some_and(|x| x.contains_key(&ptr))
+    }
+}
+
+/// Count the references to each cons, vector, record, and hash table in `obj`.
+/// Objects are not walked again after the first reference.
+fn count_refs(mut obj: ObjectType, counts: &mut HashMap<*const u8, usize>) {
+    loop {
+        let ptr: *const u8 = match obj {
+            ObjectType::Cons(x) => (x as *const Cons).cast(),
+            ObjectType::Vec(x) => (x as *const LispVec).cast(),
+            ObjectType::Record(x) => (x as *const Record).cast(),
+            ObjectType::HashTable(x) => (x as *const LispHashTable).cast(),
+            _ => return,
+        };
+        let count = counts.entry(ptr).or_insert(0);
+        *count += 1;
+        if *count > 1 {
+            return;
+        }
+        match obj {
+            ObjectType::Cons(cons) => {
+                count_refs(cons.car().untag(), counts);
+                // loop over the cdr so long lists don't overflow the stack
+                obj = cons.cdr().untag();
+            }
+            ObjectType::Vec(vec) => {
+                vec.iter().for_each(|x| count_refs(x.get().untag(), counts));
+                return;
+            }
+            ObjectType::Record(record) => {
+                record.iter().for_each(|x| count_refs(x.get().untag(), counts));
+                return;
+            }
+            ObjectType::HashTable(table) => {
+                for i in 0..table.len() {
+                    if let Some((key, value)) = table.get_index(i) {
+                        count_refs(key.untag(), counts);
+                        count_refs(value.untag(), counts);
+                    }
+                }
+                return;
+            }
+            _ => return,
+        }
+    }
+}
+
+/// Displays an object with labels for every object that appears more than
+/// once in it, the way it is printed when `print-circle` is non-nil.
+#[cfg(test)]
+pub(crate) struct DisplayCircle<'ob>(pub(crate) super::Object<'ob>);
+
+#[cfg(test)]
+impl fmt::Display for DisplayCircle<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let obj = self.0.untag();
+        obj.display_walk(f, &mut PrintState::with_labels(obj))
+    }
+}
@@ -1,11 +1,15 @@
-use super::{CloneIn, IntoObject};
+use super::{CloneIn, IntoObject, char_raw_byte};
 use crate::core::gc::{AllocState, Block, GcHeap, GcMoveable, GcState, Trace};
 use std::cell::Cell;
-use std::fmt::{Debug, Display};
+use std::fmt::{Debug, Display, Write as _};
 use std::ops::Deref;
 use std::ptr::NonNull;
 
 pub(crate) type GcString<'a> = bumpalo::collections::String<'a>;
+/// A multibyte string. The contents are always valid UTF-8, which is
+/// guaranteed by the `&str` or `String` it is created from, so it can be
+/// borrowed as a `&str` without copying or validating it again. Defuns that
+/// take a `&str` argument get a reference straight into the string.
 pub(crate) struct LispString(GcHeap<LispStringInner>);
 
 // This type needs to be this complex due to to string mutation.
@@ -15,7 +19,82 @@ pub(crate) struct LispString(GcHeap<LispStringInner>);
 //
 // Case 2: The new char is a different size:
 // Need to allocate a new string and update the cell to point to that.
-struct LispStringInner(Cell<*mut str>);
+//
+// Short strings are stored inline in the cell instead of in a separate
+// allocation. Most strings are short (property names, file extensions,
+// single characters), so this saves an allocation and a pointer chase for
+// most of them.
+struct LispStringInner(Cell<Repr>);
+
+/// Strings of up to this many bytes are stored inline.
+const INLINE_CAP: usize = 15;
+/// Set in the last byte of a [`Repr`] when the string is stored inline.
+const INLINE_TAG: u8 = 0x80;
+
+/// The storage of a string. The last byte of the representation tells the
+/// variants apart. For a heap string it is the most significant byte of the
+/// length, which is always 0, and for an inline string it holds
+/// [`INLINE_TAG`] and the length.
+#[repr(C)]
+#[derive(Clone, Copy)]
+union Repr {
+    heap: HeapRepr,
+    inline: InlineRepr,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct HeapRepr {
+    ptr: *mut u8,
+    /// The length in little endian byte order, so that its most significant
+    /// byte overlaps the inline tag on every platform.
+    len_le: usize,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct InlineRepr {
+    buf: [u8; INLINE_CAP],
+    tag: u8,
+}
+
+const _: () = assert!(size_of::<HeapRepr>() == size_of::<InlineRepr>());
+
+impl Repr {
+    fn heap(string: *mut str) -> Self {
+        let len_le = (string as *mut [u8]).len().to_le();
+        Repr { heap: HeapRepr { ptr: string.cast::<u8>(), len_le } }
+    }
+
+    fn inline(string: &str) -> Self {
+        assert!(string.len() <= INLINE_CAP, "string is too long to store inline");
+        let mut buf = [0; INLINE_CAP];
+        buf[..string.len()].copy_from_slice(string.as_bytes());
+        Repr { inline: InlineRepr { buf, tag: INLINE_TAG | string.len() as u8 } }
+    }
+}
+
+impl LispStringInner {
+    fn is_inline(&self) -> bool {
+        // SAFETY: Both variants are plain bytes and the tag byte is always
+        // initialized.
+        unsafe { (*self.0.as_ptr()).inline.tag & INLINE_TAG != 0 }
+    }
+
+    /// A pointer to the bytes of the string and its length.
+    fn raw_parts(&self) -> (*mut u8, usize) {
+        let repr = self.0.as_ptr();
+        unsafe {
+            if self.is_inline() {
+                let len = ((*repr).inline.tag & !INLINE_TAG) as usize;
+                ((&raw mut (*repr).inline.buf).cast::<u8>(), len)
+            } else {
+                let heap = (*repr).heap;
+                (heap.ptr, usize::from_le(heap.len_le))
+            }
+        }
+    }
+}
 
 impl GcMoveable for LispString {
     type Value = std::ptr::NonNull<LispString>;
@@ -25,7 +104,10 @@ impl GcMoveable for LispString {
             AllocState::Forwarded(f) => Some((f.cast::<Self>(), false)),
             AllocState::Global => None,
             AllocState::Unmoved => {
-                let ptr = {
+                let ptr = if self.inner().len() <= INLINE_CAP {
+                    // Heap strings that are short enough are moved inline
+                    NonNull::from(to_space.alloc(LispString::new_inline(self, false)))
+                } else {
                     let mut new = GcString::from_str_in(self, to_space);
                     let lisp_str = unsafe { LispString::new(new.as_mut_str(), false) };
                     std::mem::forget(new);
@@ -56,7 +138,10 @@ impl Display for LispString {
             match c {
                 '\\' => output.push_str("\\\\"),
                 '"' => output.push_str("\\\""),
-                c => output.push(c),
+                c => match char_raw_byte(c) {
+                    Some(byte) => write!(output, "\\{byte:03o}")?,
+                    None => output.push(c),
+                },
             }
         }
         Display::fmt(&output, f)
@@ -86,12 +171,24 @@ impl Deref for LispString {
 }
 
 impl LispString {
+    /// Strings of up to this many bytes should be created with
+    /// [`LispString::new_inline`].
+    pub(in crate::core) const INLINE_CAP: usize = INLINE_CAP;
+
     pub(in crate::core) unsafe fn new(string: *mut str, constant: bool) -> Self {
-        Self(GcHeap::new(LispStringInner(Cell::new(string)), constant))
+        Self(GcHeap::new(LispStringInner(Cell::new(Repr::heap(string))), constant))
+    }
+
+    /// Create a string that is stored inline. `string` must be no longer than
+    /// [`LispString::INLINE_CAP`].
+    pub(in crate::core) fn new_inline(string: &str, constant: bool) -> Self {
+        Self(GcHeap::new(LispStringInner(Cell::new(Repr::inline(string))), constant))
     }
 
     pub(crate) fn inner(&self) -> &str {
-        unsafe { &*self.0.0.get() }
+        let (ptr, len) = self.0.raw_parts();
+        // SAFETY: strings are only created from valid UTF-8
+        unsafe { std::str::from_utf8_unchecked(std::slice::from_raw_parts(ptr, len)) }
     }
 }
 
@@ -101,10 +198,8 @@ impl LispString {
     }
 
     pub(crate) fn clear(&self) {
-        let inner_mut_str = unsafe { &mut *self.0.0.get() };
-        for byte in unsafe { inner_mut_str.as_bytes_mut().iter_mut() } {
-            *byte = b'\0';
-        }
+        let (ptr, len) = self.0.raw_parts();
+        unsafe { std::ptr::write_bytes(ptr, b'\0', len) };
     }
 }
 
@@ -132,6 +227,8 @@ impl<'a> From<&'a LispString> for &'a [u8] {
     }
 }
 
+/// A unibyte string. Defuns that take a `&[u8]` argument borrow the bytes of
+/// either a unibyte or a multibyte string.
 pub(crate) struct ByteString(GcHeap<*mut [u8]>);
 type ByteVec<'a> = bumpalo::collections::Vec<'a, u8>;
 
@@ -217,6 +314,7 @@ impl Debug for ByteString {
 #[cfg(test)]
 mod test {
     use crate::core::gc::{Context, RootSet};
+    use crate::core::object::{Object, ObjectType};
     use rune_core::macros::root;
 
     #[test]
@@ -234,6 +332,41 @@ mod test {
         assert_eq!(s1, s2);
     }
 
+    #[test]
+    fn test_inline_string() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        let is_inline = |obj: Object| match obj.untag() {
+            ObjectType::String(x) => x.0.is_inline(),
+            _ => unreachable!(),
+        };
+        let short = cx.add("fifteen bytes!!");
+        let long = cx.add("sixteen bytes!!!");
+        let owned = cx.add(String::from("owned"));
+        let mut gc_string = cx.string_with_capacity(5);
+        gc_string.push_str("bump");
+        let gc_string = cx.add(gc_string);
+        assert!(is_inline(short));
+        assert!(!is_inline(long));
+        assert!(is_inline(owned));
+        assert!(!is_inline(gc_string));
+        root!(short, cx);
+        root!(long, cx);
+        root!(owned, cx);
+        root!(gc_string, cx);
+        cx.garbage_collect(true);
+        // short strings are moved inline
+        assert!(is_inline(gc_string.bind(cx)));
+        assert_eq!(gc_string.bind(cx), cx.add("bump"));
+        assert_eq!(long.bind(cx), cx.add("sixteen bytes!!!"));
+        assert_eq!(owned.bind(cx), cx.add("owned"));
+        let ObjectType::String(string) = short.bind(cx).untag() else { unreachable!() };
+        assert_eq!(string.inner(), "fifteen bytes!!");
+        string.clear();
+        assert_eq!(string.inner(), "\0".repeat(15));
+        assert_eq!(string.len(), 15);
+    }
+
     #[test]
     fn test_byte_string_aliasing() {
         let roots = &RootSet::default();
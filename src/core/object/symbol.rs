@@ -1,12 +1,13 @@
 use crate::core::env::sym::BUILTIN_SYMBOLS;
 use crate::core::gc::{Block, Context, GcHeap, GcMoveable, GcState, Trace, TracePtr};
 use crate::core::object::{CloneIn, Function, FunctionType, Gc, IntoObject, TagType, WithLifetime};
+use crate::data::LispError;
 use anyhow::{Result, bail};
 use std::cell::Cell;
 use std::fmt;
 use std::hash::{Hash, Hasher};
 use std::marker::PhantomData;
-use std::sync::atomic::{AtomicBool, AtomicPtr, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicU64, Ordering};
 
 /// The allocation of a global symbol. This is shared between threads, so the
 /// interned value of a symbol will be the same location no matter which thread
@@ -19,10 +20,71 @@ pub(crate) struct SymbolCell(GcHeap<SymbolCellData>);
 
 struct SymbolCellData {
     name: SymbolName,
+    /// The hash of the name, used to look up interned symbols
+    hash: u64,
     // We can't use AtomicCell due to this issue:
     // https://github.com/crossbeam-rs/crossbeam/issues/748
     func: Option<AtomicPtr<u8>>,
     special: AtomicBool,
+    /// Whether the variable becomes buffer-local when it is set
+    local_if_set: AtomicBool,
+    /// The function at the end of the alias chain, if the function cell holds
+    /// a symbol
+    indirect: IndirectCache,
+}
+
+/// Incremented whenever a function cell changes, which invalidates every
+/// [`IndirectCache`].
+static FUNCTION_GENERATION: AtomicU64 = AtomicU64::new(EMPTY_GENERATION + 1);
+/// The generation of a cache that has never been set
+const EMPTY_GENERATION: u64 = 1;
+/// The most symbols followed to find the function at the end of an alias
+/// chain. Longer chains are assumed to be cyclic.
+pub(crate) const MAX_FUNCTION_INDIRECTION: usize = 100;
+
+/// A cache of the function found by following the alias chain of a symbol.
+/// The function is valid while `generation` matches [`FUNCTION_GENERATION`].
+/// `generation` is 0 while the cache is being written, and acts as a
+/// sequence lock so that a function is never read together with the
+/// generation of another write.
+struct IndirectCache {
+    func: AtomicPtr<u8>,
+    generation: AtomicU64,
+}
+
+impl IndirectCache {
+    const fn new() -> Self {
+        Self {
+            func: AtomicPtr::new(std::ptr::null_mut()),
+            generation: AtomicU64::new(EMPTY_GENERATION),
+        }
+    }
+
+    fn get(&self) -> Option<*mut u8> {
+        let generation = self.generation.load(Ordering::Acquire);
+        if generation == 0 || generation != FUNCTION_GENERATION.load(Ordering::Acquire) {
+            return None;
+        }
+        let func = self.func.load(Ordering::Acquire);
+        (self.generation.load(Ordering::Acquire) == generation).then_some(func)
+    }
+
+    /// Cache `func`, which was resolved when the function generation was
+    /// `generation`. If another thread is writing the cache, this does
+    /// nothing.
+    fn set(&self, func: *mut u8, generation: u64) {
+        let current = self.generation.load(Ordering::Acquire);
+        if current == 0
+            || self
+                .generation
+                .compare_exchange(current, 0, Ordering::SeqCst, Ordering::Relaxed)
+                .is_err()
+        {
+            return;
+        }
+        self.func.store(func, Ordering::SeqCst);
+        self.generation.store(generation, Ordering::SeqCst);
+    }
 }
 
 #[derive(Debug)]
@@ -81,7 +143,7 @@ impl<'a> Symbol<'a> {
 
     pub(in crate::core) unsafe fn from_ptr(ptr: *const SymbolCell) -> Self {
         let base = BUILTIN_SYMBOLS.as_ptr().addr();
-        let ptr = ptr.map_addr(|x| (x.wrapping_sub(base)));
+        let ptr = ptr.map_addr(|x| x.wrapping_sub(base));
         Self { data: ptr.cast::<u8>(), marker: PhantomData }
     }
 
@@ -97,6 +159,26 @@ impl<'a> Symbol<'a> {
     pub(crate) fn is_special(self) -> bool {
         self.0.special.load(Ordering::Acquire)
     }
+
+    pub(crate) fn make_local_if_set(self) {
+        self.0.local_if_set.store(true, Ordering::Release);
+    }
+
+    /// Whether setting the variable makes it local to the current buffer, as
+    /// with `make-variable-buffer-local`.
+    pub(crate) fn is_local_if_set(self) -> bool {
+        self.0.local_if_set.load(Ordering::Acquire)
+    }
+
+    /// Like [`follow_indirect`](SymbolCell::follow_indirect), but signal
+    /// `cyclic-function-indirection` if the alias chain is too long.
+    pub(crate) fn indirect_function<'ob>(
+        self,
+        cx: &'ob Context,
+    ) -> Result<Option<Function<'ob>>, LispError> {
+        self.follow_chain(cx)
+            .map_err(|()| LispError::cyclic_function_indirection(self, cx))
+    }
 }
 
 unsafe impl Send for Symbol<'_> {}
@@ -207,6 +289,20 @@ impl<'ob> Symbol<'ob> {
     }
 }
 
+/// Hash a symbol name. This is FNV-1a, which is fast for short strings and
+/// can be computed in a const context for the builtin symbols.
+pub(crate) const fn hash_name(name: &str) -> u64 {
+    let bytes = name.as_bytes();
+    let mut hash = 0xcbf2_9ce4_8422_2325;
+    let mut i = 0;
+    while i < bytes.len() {
+        hash ^= bytes[i] as u64;
+        hash = u64::wrapping_mul(hash, 0x0100_0000_01b3);
+        i += 1;
+    }
+    hash
+}
+
 impl SymbolCell {
     const NULL: *mut u8 = std::ptr::null_mut();
     #[expect(clippy::declare_interior_mutable_const)]
@@ -218,14 +314,17 @@ impl SymbolCell {
 
     fn new_normal(name: &'static str, block: &Block<true>) -> Self {
         // We have to do this workaround because starts_with is not const
-        if name.as_bytes()[0] == b':' {
+        if !name.is_empty() && name.as_bytes()[0] == b':' {
             Self::new_const(name, block)
         } else {
             Self(GcHeap::new(
                 SymbolCellData {
                     name: SymbolName::Interned(name),
+                    hash: hash_name(name),
                     func: Some(Self::EMTPTY),
                     special: AtomicBool::new(false),
+                    local_if_set: AtomicBool::new(false),
+                    indirect: IndirectCache::new(),
                 },
                 true,
             ))
@@ -239,8 +338,11 @@ impl SymbolCell {
         } else {
             Self(GcHeap::new_pure(SymbolCellData {
                 name: SymbolName::Interned(name),
+                hash: hash_name(name),
                 func: Some(Self::EMTPTY),
                 special: AtomicBool::new(false),
+                local_if_set: AtomicBool::new(false),
+                indirect: IndirectCache::new(),
             }))
         }
     }
@@ -248,8 +350,23 @@ impl SymbolCell {
     pub(in crate::core) const fn new_static_special(name: &'static str) -> Self {
         Self(GcHeap::new_pure(SymbolCellData {
             name: SymbolName::Interned(name),
+            hash: hash_name(name),
+            func: Some(Self::EMTPTY),
+            special: AtomicBool::new(true),
+            local_if_set: AtomicBool::new(false),
+            indirect: IndirectCache::new(),
+        }))
+    }
+
+    /// A special variable that becomes buffer-local when it is set.
+    pub(in crate::core) const fn new_static_local(name: &'static str) -> Self {
+        Self(GcHeap::new_pure(SymbolCellData {
+            name: SymbolName::Interned(name),
+            hash: hash_name(name),
             func: Some(Self::EMTPTY),
             special: AtomicBool::new(true),
+            local_if_set: AtomicBool::new(true),
+            indirect: IndirectCache::new(),
         }))
     }
 
@@ -257,8 +374,11 @@ impl SymbolCell {
         Self(GcHeap::new(
             SymbolCellData {
                 name: SymbolName::Interned(name),
+                hash: hash_name(name),
                 func: None,
                 special: AtomicBool::new(true),
+                local_if_set: AtomicBool::new(false),
+                indirect: IndirectCache::new(),
             },
             true,
         ))
@@ -267,8 +387,11 @@ impl SymbolCell {
     pub(in crate::core) const fn new_static_const(name: &'static str) -> Self {
         Self(GcHeap::new_pure(SymbolCellData {
             name: SymbolName::Interned(name),
+            hash: hash_name(name),
             func: None,
             special: AtomicBool::new(true),
+            local_if_set: AtomicBool::new(false),
+            indirect: IndirectCache::new(),
         }))
     }
 
@@ -279,8 +402,11 @@ impl SymbolCell {
         Self(GcHeap::new(
             SymbolCellData {
                 name: SymbolName::Uninterned(Cell::new(name)),
+                hash: hash_name(name),
                 func: Some(Self::EMTPTY),
                 special: AtomicBool::new(false),
+                local_if_set: AtomicBool::new(false),
+                indirect: IndirectCache::new(),
             },
             C,
         ))
@@ -293,6 +419,11 @@ impl SymbolCell {
         }
     }
 
+    /// The hash of the symbol's name, computed when the symbol was created.
+    pub(crate) fn name_hash(&self) -> u64 {
+        self.0.hash
+    }
+
     pub(crate) fn interned(&self) -> bool {
         matches!(self.0.name, SymbolName::Interned(_))
     }
@@ -326,12 +457,36 @@ impl SymbolCell {
     }
 
     /// Follow the chain of symbols to find the function at the end, if any.
+    /// Chains longer than [`MAX_FUNCTION_INDIRECTION`] are treated as void.
     pub(crate) fn follow_indirect<'ob>(&self, cx: &'ob Context) -> Option<Function<'ob>> {
-        let func = self.func(cx)?;
-        match func.untag() {
-            FunctionType::Symbol(sym) => sym.follow_indirect(cx),
-            _ => Some(func),
+        self.follow_chain(cx).ok().flatten()
+    }
+
+    /// Follow the chain of symbols for at most [`MAX_FUNCTION_INDIRECTION`]
+    /// links. Returns `Err` if the chain is longer than that, which it always
+    /// is when it loops back on itself.
+    fn follow_chain<'ob>(&self, cx: &'ob Context) -> Result<Option<Function<'ob>>, ()> {
+        let Some(func) = self.func(cx) else { return Ok(None) };
+        let FunctionType::Symbol(mut sym) = func.untag() else { return Ok(Some(func)) };
+        // Functions reachable from an interned symbol are in the global
+        // block, so they never move. Uninterned symbols can hold local
+        // functions, which are not cached.
+        let cache = self.interned();
+        if let Some(ptr) = self.0.indirect.get().filter(|_| cache) {
+            return Ok(Some(unsafe { Gc::from_raw_ptr(ptr) }));
+        }
+        let generation = FUNCTION_GENERATION.load(Ordering::Acquire);
+        for _ in 0..MAX_FUNCTION_INDIRECTION {
+            let Some(func) = sym.func(cx) else { return Ok(None) };
+            let FunctionType::Symbol(next) = func.untag() else {
+                if cache {
+                    self.0.indirect.set(func.into_ptr().cast_mut(), generation);
+                }
+                return Ok(Some(func));
+            };
+            sym = next;
         }
+        Err(())
     }
 
     /// Set the function for this symbol. This function is unsafe to call and
@@ -345,12 +500,14 @@ impl SymbolCell {
         };
         let val = func.into_ptr().cast_mut();
         fn_cell.store(val, Ordering::Release);
+        FUNCTION_GENERATION.fetch_add(1, Ordering::AcqRel);
         Ok(())
     }
 
     pub(crate) fn unbind_func(&self) {
         if let Some(func) = &self.0.func {
             func.store(Self::NULL, Ordering::Release);
+            FUNCTION_GENERATION.fetch_add(1, Ordering::AcqRel);
         }
     }
 }
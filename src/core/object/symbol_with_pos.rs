@@ -0,0 +1,66 @@
+use super::{CloneIn, Gc, IntoObject, Symbol};
+use crate::{
+    core::gc::{Block, GcHeap, Slot},
+    derive_GcMoveable,
+};
+use rune_macros::Trace;
+use std::fmt;
+
+#[derive(PartialEq, Eq, Trace)]
+pub(crate) struct SymbolWithPosInner<'ob> {
+    sym: Slot<Symbol<'ob>>,
+    #[no_trace]
+    pos: usize,
+}
+
+impl<'ob> SymbolWithPosInner<'ob> {
+    pub(crate) fn new(sym: Symbol<'ob>, pos: usize) -> Self {
+        Self { sym: Slot::new(sym), pos }
+    }
+}
+
+/// A symbol annotated with the position it was read from. The byte compiler
+/// uses these to report where a warning happened. They are only `eq` to the
+/// bare symbol when `symbols-with-pos-enabled` is non-nil.
+#[derive(PartialEq, Eq, Trace)]
+pub(crate) struct SymbolWithPos(GcHeap<SymbolWithPosInner<'static>>);
+
+derive_GcMoveable!(SymbolWithPos);
+
+impl SymbolWithPos {
+    pub(in crate::core) unsafe fn new(inner: SymbolWithPosInner<'_>, constant: bool) -> Self {
+        // transmute lifetime to static
+        let inner = unsafe {
+            std::mem::transmute::<SymbolWithPosInner<'_>, SymbolWithPosInner<'static>>(inner)
+        };
+        Self(GcHeap::new(inner, constant))
+    }
+
+    /// The symbol without its position.
+    pub(crate) fn sym(&self) -> Symbol<'_> {
+        *self.0.sym
+    }
+
+    pub(crate) fn pos(&self) -> usize {
+        self.0.pos
+    }
+}
+
+impl<'new> CloneIn<'new, &'new Self> for SymbolWithPos {
+    fn clone_in<const C: bool>(&self, bk: &'new Block<C>) -> Gc<&'new Self> {
+        let sym = self.sym().clone_in(bk).untag();
+        SymbolWithPosInner::new(sym, self.pos()).into_obj(bk)
+    }
+}
+
+impl fmt::Display for SymbolWithPos {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "#<symbol {} at {}>", self.sym(), self.pos())
+    }
+}
+
+impl fmt::Debug for SymbolWithPos {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{self}")
+    }
+}
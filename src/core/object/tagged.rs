@@ -4,11 +4,12 @@ use super::{
         error::{Type, TypeError},
         gc::Block,
     },
-    ByteFnPrototype, ByteString, CharTableInner, GcString, LispBuffer,
+    ByteFnPrototype, ByteString, CharTableInner, GcString, LispBuffer, SymbolWithPos,
+    SymbolWithPosInner,
 };
 use super::{
-    ByteFn, CharTable, HashTable, LispFloat, LispHashTable, LispString, LispVec, Record,
-    RecordBuilder, SubrFn, Symbol, SymbolCell,
+    ByteFn, CharTable, HashTable, LispFloat, LispHashTable, LispString, LispVec, PrintState,
+    Record, RecordBuilder, SubrFn, Symbol, SymbolCell,
 };
 use crate::core::{
     env::sym,
@@ -16,11 +17,10 @@ use crate::core::{
 };
 use bumpalo::collections::Vec as GcVec;
 use private::{Tag, TaggedPtr};
-use rune_core::hashmap::HashSet;
 use std::marker::PhantomData;
 use std::{fmt, ptr::NonNull};
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub(crate) struct RawObj {
     ptr: *const u8,
 }
@@ -293,6 +293,7 @@ object_trait_impls!(Record);
 object_trait_impls!(LispHashTable);
 object_trait_impls!(LispBuffer);
 object_trait_impls!(CharTable);
+object_trait_impls!(SymbolWithPos);
 
 /// Trait for types that can be managed by the GC. This trait is implemented for
 /// as many types as possible, even for types that are already Gc managed, Like
@@ -340,7 +341,7 @@ impl IntoObject for f64 {
     type Out<'ob> = &'ob LispFloat;
 
     fn into_obj<const C: bool>(self, block: &Block<C>) -> Gc<Self::Out<'_>> {
-        let ptr = block.objects.alloc(LispFloat::new(self, C));
+        let ptr = block.alloc(LispFloat::new(self, C), 0);
         unsafe { Self::Out::tag_ptr(ptr) }
     }
 }
@@ -369,10 +370,7 @@ impl IntoObject for Cons {
     type Out<'ob> = &'ob Cons;
 
     fn into_obj<const C: bool>(self, block: &Block<C>) -> Gc<Self::Out<'_>> {
-        let ptr = block.objects.alloc(self);
-        if C {
-            ptr.mark_const();
-        }
+        let ptr = block.alloc_cons(self);
         unsafe { Self::Out::tag_ptr(ptr) }
     }
 }
@@ -381,7 +379,7 @@ impl IntoObject for ByteFnPrototype {
     type Out<'ob> = &'ob ByteFn;
 
     fn into_obj<const C: bool>(self, block: &Block<C>) -> Gc<Self::Out<'_>> {
-        let ptr = block.objects.alloc(ByteFn::new(self, C));
+        let ptr = block.alloc(ByteFn::new(self, C), 0);
         unsafe { Self::Out::tag_ptr(ptr) }
     }
 }
@@ -390,7 +388,7 @@ impl IntoObject for SymbolCell {
     type Out<'ob> = Symbol<'ob>;
 
     fn into_obj<const C: bool>(self, block: &Block<C>) -> Gc<Self::Out<'_>> {
-        let ptr = block.objects.alloc(self);
+        let ptr = block.alloc(self, 0);
         let sym = unsafe { Symbol::from_ptr(ptr) };
         unsafe { Self::Out::tag_ptr(sym.get_ptr()) }
     }
@@ -400,10 +398,14 @@ impl IntoObject for String {
     type Out<'ob> = &'ob LispString;
 
     fn into_obj<const C: bool>(self, block: &Block<C>) -> Gc<Self::Out<'_>> {
+        if self.len() <= LispString::INLINE_CAP {
+            return self.as_str().into_obj(block);
+        }
         unsafe {
             let mut this = self;
+            let len = this.len();
             let ptr = this.as_mut_str();
-            let ptr = block.objects.alloc(LispString::new(ptr, C));
+            let ptr = block.alloc(LispString::new(ptr, C), len);
             block.drop_stack.borrow_mut().push(DropStackElem::String(this));
             Self::Out::tag_ptr(ptr)
         }
@@ -416,7 +418,8 @@ impl IntoObject for GcString<'_> {
     fn into_obj<const C: bool>(self, block: &Block<C>) -> Gc<Self::Out<'_>> {
         unsafe {
             let mut this = self;
-            let ptr = block.objects.alloc(LispString::new(this.as_mut_str(), C));
+            let len = this.len();
+            let ptr = block.alloc(LispString::new(this.as_mut_str(), C), len);
             std::mem::forget(this);
             Self::Out::tag_ptr(ptr)
         }
@@ -427,6 +430,13 @@ impl IntoObject for &str {
     type Out<'ob> = <String as IntoObject>::Out<'ob>;
 
     fn into_obj<const C: bool>(self, block: &Block<C>) -> Gc<Self::Out<'_>> {
+        if self.len() <= LispString::INLINE_CAP {
+            let ptr = block.alloc(LispString::new_inline(self, C), 0);
+            if !C {
+                crate::core::gc::stats::record_inline_string();
+            }
+            return unsafe { Self::Out::tag_ptr(ptr) };
+        }
         GcString::from_str_in(self, &block.objects).into_obj(block)
     }
 }
@@ -436,8 +446,9 @@ impl IntoObject for Vec<u8> {
 
     fn into_obj<const C: bool>(self, block: &Block<C>) -> Gc<Self::Out<'_>> {
         let mut this = self;
+        let len = this.len();
         let slice = this.as_mut_slice();
-        let ptr = block.objects.alloc(ByteString::new(slice, C));
+        let ptr = block.alloc(ByteString::new(slice, C), len);
         block.drop_stack.borrow_mut().push(DropStackElem::ByteString(this));
         unsafe { <&ByteString>::tag_ptr(ptr) }
     }
@@ -448,9 +459,10 @@ impl IntoObject for Vec<Object<'_>> {
 
     fn into_obj<const C: bool>(mut self, block: &Block<C>) -> Gc<Self::Out<'_>> {
         unsafe {
+            let len = size_of_val(self.as_slice());
             // having the reference implicity cast a ptr triggers UB
             let ptr = self.as_mut_slice() as *mut [Object];
-            let ptr = block.objects.alloc(LispVec::new(ptr, C));
+            let ptr = block.alloc(LispVec::new(ptr, C), len);
             block.drop_stack.borrow_mut().push(DropStackElem::Vec(self.with_lifetime()));
             <&LispVec>::tag_ptr(ptr)
         }
@@ -462,9 +474,10 @@ impl IntoObject for GcVec<'_, Object<'_>> {
 
     fn into_obj<const C: bool>(self, block: &Block<C>) -> Gc<Self::Out<'_>> {
         unsafe {
+            let len = size_of_val(self.as_slice());
             // having the reference implicity cast a ptr triggers UB
             let ptr = self.into_bump_slice_mut() as *mut [Object];
-            let ptr = block.objects.alloc(LispVec::new(ptr, C));
+            let ptr = block.alloc(LispVec::new(ptr, C), len);
             <&LispVec>::tag_ptr(ptr)
         }
     }
@@ -486,8 +499,9 @@ impl IntoObject for RecordBuilder<'_> {
     fn into_obj<const C: bool>(self, block: &Block<C>) -> Gc<Self::Out<'_>> {
         unsafe {
             // record is the same layout as lispvec, just a different newtype wrapper
+            let len = size_of_val(self.0.as_slice());
             let ptr = self.0.into_bump_slice_mut() as *mut [Object];
-            let ptr = block.objects.alloc(LispVec::new(ptr, C));
+            let ptr = block.alloc(LispVec::new(ptr, C), len);
             <&Record>::tag_ptr(ptr)
         }
     }
@@ -498,7 +512,7 @@ impl IntoObject for HashTable<'_> {
 
     fn into_obj<const C: bool>(self, block: &Block<C>) -> Gc<Self::Out<'_>> {
         unsafe {
-            let ptr = block.objects.alloc(LispHashTable::new(self, C));
+            let ptr = block.alloc(LispHashTable::new(self, C), 0);
             block.lisp_hashtables.borrow_mut().push(ptr);
             <&LispHashTable>::tag_ptr(ptr)
         }
@@ -510,7 +524,18 @@ impl IntoObject for CharTableInner<'_> {
 
     fn into_obj<const C: bool>(self, block: &Block<C>) -> Gc<Self::Out<'_>> {
         unsafe {
-            let ptr = block.objects.alloc(CharTable::new(self, C));
+            let ptr = block.alloc(CharTable::new(self, C), 0);
+            <Self::Out<'_>>::tag_ptr(ptr)
+        }
+    }
+}
+
+impl IntoObject for SymbolWithPosInner<'_> {
+    type Out<'ob> = &'ob SymbolWithPos;
+
+    fn into_obj<const C: bool>(self, block: &Block<C>) -> Gc<Self::Out<'_>> {
+        unsafe {
+            let ptr = block.alloc(SymbolWithPos::new(self, C), 0);
             <Self::Out<'_>>::tag_ptr(ptr)
         }
     }
@@ -535,6 +560,7 @@ mod private {
         ByteFn,
         Buffer,
         CharTable,
+        SymbolWithPos,
     }
 
     /// Trait for tagged pointers. Anything that can be stored and passed around
@@ -642,6 +668,9 @@ impl<'a> TaggedPtr for ObjectType<'a> {
                 Tag::HashTable => ObjectType::HashTable(<&LispHashTable>::from_obj_ptr(ptr)),
                 Tag::Buffer => ObjectType::Buffer(<&LispBuffer>::from_obj_ptr(ptr)),
                 Tag::CharTable => ObjectType::CharTable(<&CharTable>::from_obj_ptr(ptr)),
+                Tag::SymbolWithPos => {
+                    ObjectType::SymbolWithPos(<&SymbolWithPos>::from_obj_ptr(ptr))
+                }
             }
         }
     }
@@ -661,6 +690,7 @@ impl<'a> TaggedPtr for ObjectType<'a> {
             ObjectType::SubrFn(x) => TaggedPtr::tag(x).into(),
             ObjectType::Buffer(x) => TaggedPtr::tag(x).into(),
             ObjectType::CharTable(x) => TaggedPtr::tag(x).into(),
+            ObjectType::SymbolWithPos(x) => TaggedPtr::tag(x).into(),
         }
     }
 }
@@ -767,14 +797,55 @@ impl TaggedPtr for i64 {
     }
 }
 
+/// The character code of the raw byte 0. Emacs represents the raw bytes 128
+/// to 255 in multibyte text as the characters `0x3FFF80` to `0x3FFFFF`.
+pub(crate) const RAW_BYTE_BASE: i64 = 0x3F_FF00;
+
+/// Raw bytes are outside of Unicode, so they can't be stored in a `char`
+/// directly. Inside rune they are the last 128 code points of the
+/// supplementary private use area, and are converted back to their Emacs
+/// character codes whenever they become an integer. Those code points are
+/// reserved for raw bytes: [`int_to_char`] does not accept them as lisp
+/// characters and UTF-8 text that contains them is decoded as raw bytes, so
+/// a raw byte is never mistaken for a character.
+const RAW_BYTE_CHAR_BASE: u32 = 0x10_FF00;
+
+/// The character that represents `byte` in multibyte text. ASCII bytes are
+/// just themselves.
+pub(crate) fn raw_byte_char(byte: u8) -> char {
+    if byte.is_ascii() {
+        char::from(byte)
+    } else {
+        char::from_u32(RAW_BYTE_CHAR_BASE + u32::from(byte)).unwrap()
+    }
+}
+
+/// If `chr` represents a raw byte, return the byte.
+pub(crate) fn char_raw_byte(chr: char) -> Option<u8> {
+    match u32::from(chr).checked_sub(RAW_BYTE_CHAR_BASE) {
+        Some(byte @ 0x80..=0xFF) => Some(byte as u8),
+        _ => None,
+    }
+}
+
+/// The lisp character code of `chr`.
+pub(crate) fn char_to_int(chr: char) -> i64 {
+    match char_raw_byte(chr) {
+        Some(byte) => RAW_BYTE_BASE + i64::from(byte),
+        None => i64::from(u32::from(chr)),
+    }
+}
+
+/// The `char` for the lisp character code `int`. The code points that stand
+/// for raw bytes internally are not characters.
 pub(crate) fn int_to_char(int: i64) -> Result<char, TypeError> {
     let err = TypeError::new(Type::Char, TagType::tag(int));
-    match u32::try_from(int) {
-        Ok(x) => match char::from_u32(x) {
-            Some(c) => Ok(c),
-            None => Err(err),
-        },
-        Err(_) => Err(err),
+    if (RAW_BYTE_BASE + 0x80..RAW_BYTE_BASE + 0x100).contains(&int) {
+        return Ok(raw_byte_char((int - RAW_BYTE_BASE) as u8));
+    }
+    match u32::try_from(int).ok().and_then(char::from_u32) {
+        Some(c) if char_raw_byte(c).is_none() => Ok(c),
+        _ => Err(err),
     }
 }
 
@@ -928,6 +999,19 @@ impl TaggedPtr for &CharTable {
     }
 }
 
+impl TaggedPtr for &SymbolWithPos {
+    type Ptr = SymbolWithPos;
+    const TAG: Tag = Tag::SymbolWithPos;
+
+    unsafe fn from_obj_ptr(ptr: *const u8) -> Self {
+        &*ptr.cast::<Self::Ptr>()
+    }
+
+    fn get_ptr(self) -> *const Self::Ptr {
+        self as *const Self::Ptr
+    }
+}
+
 impl<T> TracePtr for Gc<T> {
     fn trace_ptr(&self, state: &mut GcState) {
         match self.as_obj().untag() {
@@ -943,6 +1027,7 @@ impl<T> TracePtr for Gc<T> {
             ObjectType::ByteFn(x) => x.trace(state),
             ObjectType::Buffer(x) => x.trace(state),
             ObjectType::CharTable(x) => x.trace(state),
+            ObjectType::SymbolWithPos(x) => x.trace(state),
         }
     }
 }
@@ -1060,6 +1145,7 @@ pub(crate) enum ObjectType<'ob> {
     SubrFn(&'static SubrFn) = Tag::SubrFn as u8,
     Buffer(&'static LispBuffer) = Tag::Buffer as u8,
     CharTable(&'static CharTable) = Tag::CharTable as u8,
+    SymbolWithPos(&'ob SymbolWithPos) = Tag::SymbolWithPos as u8,
 }
 
 /// The Object defintion that contains all other possible lisp objects. This
@@ -1081,7 +1167,8 @@ cast_gc!(ObjectType<'ob> => NumberType<'ob>,
          &'ob ByteFn,
          &'ob SubrFn,
          &'ob LispBuffer,
-         &'ob CharTable
+         &'ob CharTable,
+         &'ob SymbolWithPos
 );
 
 impl ObjectType<'_> {
@@ -1102,6 +1189,7 @@ impl ObjectType<'_> {
             ObjectType::ByteFn(_) | ObjectType::SubrFn(_) => Type::Func,
             ObjectType::Buffer(_) => Type::Buffer,
             ObjectType::CharTable(_) => Type::CharTable,
+            ObjectType::SymbolWithPos(_) => Type::Symbol,
         }
     }
 }
@@ -1155,7 +1243,7 @@ impl TagType for u32 {
 impl TagType for char {
     type Out = i64;
     fn tag(self) -> Gc<Self::Out> {
-        TagType::tag(i64::from(self as u32))
+        TagType::tag(char_to_int(self))
     }
 }
 
@@ -1354,6 +1442,17 @@ impl<'ob> TryFrom<Object<'ob>> for Gc<&'ob CharTable> {
     }
 }
 
+impl<'ob> TryFrom<Object<'ob>> for Gc<&'ob SymbolWithPos> {
+    type Error = TypeError;
+
+    fn try_from(value: Object<'ob>) -> Result<Self, Self::Error> {
+        match value.get_tag() {
+            Tag::SymbolWithPos => unsafe { Ok(cast_gc(value)) },
+            _ => Err(TypeError::new(Type::Symbol, value)),
+        }
+    }
+}
+
 impl<'ob> std::ops::Deref for Gc<&'ob Cons> {
     type Target = Cons;
 
@@ -1390,6 +1489,7 @@ where
             ObjectType::HashTable(x) => x.clone_in(bk).into(),
             ObjectType::Buffer(x) => x.clone_in(bk).into(),
             ObjectType::CharTable(x) => x.clone_in(bk).into(),
+            ObjectType::SymbolWithPos(x) => x.clone_in(bk).into(),
         };
         let Ok(x) = Gc::<U>::try_from(obj) else { unreachable!() };
         x
@@ -1430,6 +1530,7 @@ impl GcMoveable for Object<'_> {
                 (sym.as_ptr(), moved)
             }
             ObjectType::CharTable(x) => cast_pair(x.move_value(to_space)?),
+            ObjectType::SymbolWithPos(x) => cast_pair(x.move_value(to_space)?),
         };
 
         let tag = self.get_tag();
@@ -1602,13 +1703,13 @@ impl<T> Hash for Gc<T> {
 
 impl fmt::Display for ObjectType<'_> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        self.display_walk(f, &mut HashSet::default())
+        self.display_walk(f, &mut PrintState::default())
     }
 }
 
 impl fmt::Debug for ObjectType<'_> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        self.display_walk(f, &mut HashSet::default())
+        self.display_walk(f, &mut PrintState::default())
     }
 }
 
@@ -1616,15 +1717,15 @@ impl ObjectType<'_> {
     pub(crate) fn display_walk(
         &self,
         f: &mut fmt::Formatter,
-        seen: &mut HashSet<*const u8>,
+        state: &mut PrintState,
     ) -> fmt::Result {
         use fmt::Display as D;
         match self {
             ObjectType::Int(x) => D::fmt(x, f),
-            ObjectType::Cons(x) => x.display_walk(f, seen),
-            ObjectType::Vec(x) => x.display_walk(f, seen),
-            ObjectType::Record(x) => x.display_walk(f, seen),
-            ObjectType::HashTable(x) => x.display_walk(f, seen),
+            ObjectType::Cons(x) => x.display_walk(f, state),
+            ObjectType::Vec(x) => x.display_walk(f, state),
+            ObjectType::Record(x) => x.display_walk(f, state),
+            ObjectType::HashTable(x) => x.display_walk(f, state),
             ObjectType::String(x) => write!(f, "\"{x}\""),
             ObjectType::ByteString(x) => write!(f, "\"{x}\""),
             ObjectType::Symbol(x) => D::fmt(x, f),
@@ -1633,6 +1734,7 @@ impl ObjectType<'_> {
             ObjectType::Float(x) => D::fmt(x, f),
             ObjectType::Buffer(x) => D::fmt(x, f),
             ObjectType::CharTable(x) => D::fmt(x, f),
+            ObjectType::SymbolWithPos(x) => D::fmt(x, f),
         }
     }
 }
@@ -1641,6 +1743,7 @@ impl ObjectType<'_> {
 mod test {
     use super::{MAX_FIXNUM, MIN_FIXNUM, TagType};
     use crate::core::gc::{Context, RootSet};
+    use crate::core::{cons::Cons, object::DisplayCircle};
     use rune_core::macros::list;
 
     #[test]
@@ -1663,5 +1766,17 @@ mod test {
 
         cons.as_cons().set_car(cons).unwrap();
         assert_eq!(format!("{cons}"), "(#0 . #0)");
+        assert_eq!(format!("{}", DisplayCircle(cons)), "#1=(#1# . #1#)");
+
+        // shared structure that is not circular is printed in full
+        let shared = list![1; cx];
+        let list = list![shared, shared; cx];
+        assert_eq!(format!("{list}"), "((1) (1))");
+        assert_eq!(format!("{}", DisplayCircle(list)), "(#1=(1) #1#)");
+
+        let tail = list![2, 3; cx];
+        let list = list![Cons::new(1, tail, cx), tail; cx];
+        assert_eq!(format!("{list}"), "((1 2 3) (2 3))");
+        assert_eq!(format!("{}", DisplayCircle(list)), "((1 . #1=(2 3)) #1#)");
     }
 }
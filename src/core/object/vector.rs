@@ -1,11 +1,10 @@
-use super::{CloneIn, Gc, IntoObject, MutObjCell, ObjCell, Object};
+use super::{CloneIn, Gc, IntoObject, MutObjCell, ObjCell, Object, PrintState};
 use crate::{
     core::gc::{Block, GcHeap, GcState, Trace},
     derive_GcMoveable,
 };
 use anyhow::{Result, anyhow};
 use bumpalo::collections::Vec as GcVec;
-use rune_core::hashmap::HashSet;
 use rune_macros::Trace;
 use std::{
     cell::Cell,
@@ -102,13 +101,13 @@ impl Trace for LispVecInner {
 
 impl fmt::Display for LispVec {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        self.display_walk(f, &mut HashSet::default())
+        self.display_walk(f, &mut PrintState::default())
     }
 }
 
 impl fmt::Debug for LispVec {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        self.display_walk(f, &mut HashSet::default())
+        self.display_walk(f, &mut PrintState::default())
     }
 }
 
@@ -116,21 +115,19 @@ impl LispVec {
     pub(super) fn display_walk(
         &self,
         f: &mut fmt::Formatter,
-        seen: &mut HashSet<*const u8>,
+        state: &mut PrintState,
     ) -> fmt::Result {
-        let ptr = (&*self.0 as *const LispVecInner).cast();
-        if seen.contains(&ptr) {
-            return write!(f, "#0");
+        if !state.start((self as *const Self).cast(), f)? {
+            return Ok(());
         }
-        seen.insert(ptr);
-
         f.write_char('[')?;
         for (i, x) in self.iter().enumerate() {
             if i != 0 {
                 f.write_char(' ')?;
             }
-            x.get().untag().display_walk(f, seen)?;
+            x.get().untag().display_walk(f, state)?;
         }
+        state.end();
         f.write_char(']')
     }
 }
@@ -168,7 +165,7 @@ impl<'new> CloneIn<'new, &'new Self> for Record {
 
 impl fmt::Display for Record {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        self.display_walk(f, &mut HashSet::default())
+        self.display_walk(f, &mut PrintState::default())
     }
 }
 
@@ -185,20 +182,19 @@ impl Record {
     pub(super) fn display_walk(
         &self,
         f: &mut fmt::Formatter,
-        seen: &mut HashSet<*const u8>,
+        state: &mut PrintState,
     ) -> fmt::Result {
-        let ptr = (self as *const Self).cast();
-        if seen.contains(&ptr) {
-            return write!(f, "#0");
+        if !state.start((self as *const Self).cast(), f)? {
+            return Ok(());
         }
-        seen.insert(ptr);
         write!(f, "#s(")?;
         for (i, x) in self.iter().enumerate() {
             if i != 0 {
                 f.write_char(' ')?;
             }
-            x.get().untag().display_walk(f, seen)?;
+            x.get().untag().display_walk(f, state)?;
         }
+        state.end();
         f.write_char(')')
     }
 }
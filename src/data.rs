@@ -3,14 +3,20 @@ use crate::core::{
     cons::Cons,
     env::{Env, INTERNED_SYMBOLS, sym},
     error::{Type, TypeError},
-    gc::{Context, Rt},
+    gc::{Context, Rt, Rto},
     object::{
-        IntoObject, List, ListType, NIL, Number, Object, ObjectType, SubrFn, Symbol, WithLifetime,
+        Function, Gc, IntoObject, LispBuffer, List, ListType, MAX_FUNCTION_INDIRECTION, NIL,
+        Number, NumberType, Object, ObjectType, SubrFn, Symbol, SymbolWithPos, SymbolWithPosInner,
+        WithLifetime, char_to_int,
     },
 };
-use anyhow::{Result, anyhow};
-use rune_core::{hashmap::HashSet, macros::list};
-use rune_macros::defun;
+use crate::library::number;
+use anyhow::{Result, anyhow, bail, ensure};
+use rune_core::{
+    hashmap::HashSet,
+    macros::{call, list, root},
+};
+use rune_macros::{defun, elprop};
 use std::sync::LazyLock;
 use std::sync::Mutex;
 
@@ -21,7 +27,14 @@ pub(crate) static FEATURES: LazyLock<Mutex<HashSet<Symbol<'static>>>> =
     LazyLock::new(Mutex::default);
 
 #[defun]
-pub(crate) fn fset<'ob>(symbol: Symbol<'ob>, definition: Object) -> Result<Symbol<'ob>> {
+pub(crate) fn fset<'ob>(
+    symbol: Symbol<'ob>,
+    definition: Object,
+    cx: &'ob Context,
+) -> Result<Symbol<'ob>> {
+    if is_cyclic_alias(symbol, definition, cx) {
+        return Err(LispError::cyclic_function_indirection(symbol, cx).into());
+    }
     if definition.is_nil() {
         symbol.unbind_func();
     } else {
@@ -32,13 +45,30 @@ pub(crate) fn fset<'ob>(symbol: Symbol<'ob>, definition: Object) -> Result<Symbo
     Ok(symbol)
 }
 
+/// Return true if making `definition` the function of `symbol` would create
+/// an alias chain that loops back to `symbol` or is too long to follow from
+/// `symbol`.
+fn is_cyclic_alias(symbol: Symbol, definition: Object, cx: &Context) -> bool {
+    let mut func = definition;
+    for _ in 0..=MAX_FUNCTION_INDIRECTION {
+        let ObjectType::Symbol(sym) = func.untag() else { return false };
+        if sym == symbol {
+            return true;
+        }
+        let Some(next) = sym.func(cx) else { return false };
+        func = next.into();
+    }
+    true
+}
+
 #[defun]
 pub(crate) fn defalias<'ob>(
     symbol: Symbol<'ob>,
     definition: Object,
     _docstring: Option<&str>,
+    cx: &'ob Context,
 ) -> Result<Symbol<'ob>> {
-    fset(symbol, definition)
+    fset(symbol, definition, cx)
 }
 
 #[defun]
@@ -78,20 +108,41 @@ pub(crate) fn get<'ob>(
     }
 }
 
+/// Return non-nil if VARIABLE has a local value in BUFFER, which defaults to
+/// the current buffer.
 #[defun]
-pub(crate) fn local_variable_if_set_p(_sym: Symbol) -> bool {
-    // TODO: Implement buffer locals
-    false
+pub(crate) fn local_variable_p(
+    variable: Symbol,
+    buffer: Option<Gc<&LispBuffer>>,
+    env: &Rt<Env>,
+    cx: &Context,
+) -> bool {
+    match buffer {
+        Some(buffer) => env.is_local_in(variable, buffer.untag(), cx),
+        None => env.is_local(variable),
+    }
 }
 
+/// Return non-nil if VARIABLE is local in BUFFER, or would become local when
+/// it is set.
+#[defun]
+pub(crate) fn local_variable_if_set_p(
+    variable: Symbol,
+    buffer: Option<Gc<&LispBuffer>>,
+    env: &Rt<Env>,
+    cx: &Context,
+) -> bool {
+    variable.is_local_if_set() || local_variable_p(variable, buffer, env, cx)
+}
+
+/// Return the value of SYMBOL in buffers where it is not local.
 #[defun]
 pub(crate) fn default_value<'ob>(
     symbol: Symbol,
     env: &Rt<Env>,
     cx: &'ob Context,
 ) -> Result<Object<'ob>> {
-    // TODO: Implement buffer locals
-    symbol_value(symbol, env, cx).ok_or_else(|| anyhow!("Void variable: {symbol}"))
+    env.default_value(symbol, cx).ok_or_else(|| anyhow!("Void variable: {symbol}"))
 }
 
 #[defun]
@@ -144,8 +195,8 @@ pub(crate) fn makunbound<'ob>(symbol: Symbol<'ob>, env: &mut Rt<Env>) -> Symbol<
 }
 
 #[defun]
-pub(crate) fn default_boundp(symbol: Symbol, env: &Rt<Env>) -> bool {
-    env.vars.get(symbol).is_some()
+pub(crate) fn default_boundp(symbol: Symbol, env: &Rt<Env>, cx: &Context) -> bool {
+    env.default_value(symbol, cx).is_some()
 }
 
 #[defun]
@@ -159,8 +210,12 @@ pub(crate) fn nlistp(object: Object) -> bool {
 }
 
 #[defun]
-pub(crate) fn symbolp(object: Object) -> bool {
-    matches!(object.untag(), ObjectType::Symbol(_))
+pub(crate) fn symbolp(object: Object, env: &Rt<Env>, cx: &Context) -> bool {
+    match object.untag() {
+        ObjectType::Symbol(_) => true,
+        ObjectType::SymbolWithPos(_) => symbols_with_pos_enabled(env, cx),
+        _ => false,
+    }
 }
 
 #[defun]
@@ -199,6 +254,17 @@ pub(crate) fn vectorp(object: Object) -> bool {
     matches!(object.untag(), ObjectType::Vec(_))
 }
 
+#[defun]
+pub(crate) fn arrayp(object: Object) -> bool {
+    matches!(
+        object.untag(),
+        ObjectType::Vec(_)
+            | ObjectType::String(_)
+            | ObjectType::ByteString(_)
+            | ObjectType::CharTable(_)
+    )
+}
+
 #[defun]
 pub(crate) fn recordp(object: Object) -> bool {
     matches!(object.untag(), ObjectType::Record(_))
@@ -253,17 +319,29 @@ pub(crate) fn multibyte_string_p(object: Object) -> bool {
     matches!(object.untag(), ObjectType::String(_))
 }
 
+/// Parse STRING as a decimal number and return the number. Leading spaces
+/// and tabs are ignored, as is any text after the number. If BASE is given,
+/// STRING is parsed as an integer in that base, which must be between 2 and
+/// 16. Return 0 if STRING does not start with a number.
 #[defun]
-fn string_to_number<'ob>(string: &str, base: Option<i64>, cx: &'ob Context) -> Number<'ob> {
-    // TODO: Handle trailing characters, which should be ignored
+#[elprop("[ \t]*[-+]?[0-9]*\\.?[0-9]*(e[-+]?[0-9]*)?[a-z.]*", _)]
+fn string_to_number<'ob>(string: &str, base: Option<i64>, cx: &'ob Context) -> Result<Number<'ob>> {
     let base = base.unwrap_or(10);
-    let string = string.trim();
-    match i64::from_str_radix(string, base as u32) {
-        Ok(x) => x.into(),
-        Err(_) => match string.parse::<f64>() {
-            Ok(x) => cx.add_as(x),
-            Err(_) => 0.into(),
-        },
+    ensure!((2..=16).contains(&base), "args-out-of-range: {base}");
+    let string = string.trim_start_matches([' ', '\t']);
+    Ok(match number::parse_prefix(string, base as u32) {
+        Some((number::Number::Int(x), _)) => x.into(),
+        Some((number::Number::Float(x), _)) => cx.add_as(x),
+        None => 0.into(),
+    })
+}
+
+/// Return the decimal representation of NUMBER as a string.
+#[defun]
+fn number_to_string(number: Number) -> String {
+    match number.untag() {
+        NumberType::Int(x) => x.to_string(),
+        NumberType::Float(x) => number::float_to_string(**x),
     }
 }
 
@@ -278,9 +356,42 @@ pub(crate) fn defvar<'ob>(
     set(symbol, value, env)
 }
 
+/// Make VARIABLE become local to the current buffer whenever it is set. A
+/// void VARIABLE gets a default value of nil.
+#[defun]
+pub(crate) fn make_variable_buffer_local<'ob>(
+    variable: Symbol<'ob>,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<Symbol<'ob>> {
+    if env.default_value(variable, cx).is_none() {
+        env.set_default(variable, NIL)?;
+    }
+    variable.make_local_if_set();
+    Ok(variable)
+}
+
+/// Give VARIABLE a value local to the current buffer. It starts out as the
+/// default value, and setting it doesn't change the value in other buffers.
 #[defun]
-pub(crate) fn make_variable_buffer_local(variable: Symbol) -> Symbol {
-    // TODO: Implement
+pub(crate) fn make_local_variable<'ob>(
+    variable: Symbol<'ob>,
+    env: &mut Rt<Env>,
+) -> Result<Symbol<'ob>> {
+    ensure!(!variable.is_const(), "Attempt to make a constant symbol local: {variable}");
+    env.make_local(variable);
+    Ok(variable)
+}
+
+/// Remove the value of VARIABLE local to the current buffer, so that the
+/// default value is seen again.
+#[defun]
+pub(crate) fn kill_local_variable<'ob>(
+    variable: Symbol<'ob>,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Symbol<'ob> {
+    env.kill_local(variable, cx);
     variable
 }
 
@@ -299,9 +410,10 @@ fn subr_arity<'ob>(subr: &SubrFn, cx: &'ob Context) -> Object<'ob> {
 
 #[defun]
 fn ash(value: i64, count: i64) -> i64 {
-    let shift = if count >= 0 { std::ops::Shl::shl } else { std::ops::Shr::shr };
-    let result = shift(value.abs(), count.abs());
-    if value >= 0 { result } else { -result }
+    let shift = if count >= 0 { i64::checked_shl } else { i64::checked_shr };
+    let bits = u32::try_from(count.unsigned_abs()).unwrap_or(u32::MAX);
+    let result = shift(value.wrapping_abs(), bits).unwrap_or(0);
+    if value >= 0 { result } else { result.wrapping_neg() }
 }
 
 #[defun]
@@ -357,7 +469,7 @@ pub(crate) fn aref<'ob>(array: Object<'ob>, idx: usize, cx: &'ob Context) -> Res
             }
         },
         ObjectType::String(string) => match string.chars().nth(idx) {
-            Some(x) => Ok((i64::from(x as u32)).into()),
+            Some(x) => Ok(char_to_int(x).into()),
             None => {
                 let len = string.len();
                 Err(anyhow!("index {idx} is out of bounds. Length was {len}"))
@@ -387,34 +499,150 @@ fn type_of(object: Object) -> Object {
         ObjectType::Symbol(_) => sym::SYMBOL.into(),
         ObjectType::Cons(_) => sym::CONS.into(),
         ObjectType::Vec(_) => sym::VECTOR.into(),
-        ObjectType::Record(x) => x.first().expect("record was missing type").get(),
+        ObjectType::Record(x) => match x.first().expect("record was missing type").get().untag() {
+            // records made by EIEIO and `cl-defstruct` with a class object
+            // as their type use the name of the class
+            ObjectType::Record(class) if class.len() > 1 => class[1].get(),
+            _ => x[0].get(),
+        },
         ObjectType::ByteFn(_) => sym::COMPILED_FUNCTION.into(),
         ObjectType::HashTable(_) => sym::HASH_TABLE.into(),
         ObjectType::String(_) | ObjectType::ByteString(_) => sym::STRING.into(),
         ObjectType::SubrFn(_) => sym::SUBR.into(),
         ObjectType::Buffer(_) => sym::BUFFER.into(),
         ObjectType::CharTable(_) => sym::CHAR_TABLE.into(),
+        ObjectType::SymbolWithPos(_) => sym::SYMBOL_WITH_POS.into(),
     }
 }
 
+/// Return whether OBJECT is of the builtin type TYPE, or None if TYPE is not
+/// a builtin type. These are the types listed in `cl--typeof-types` and the
+/// other types that have a predicate in this file.
+fn builtin_typep(object: Object, type_: Symbol) -> Option<bool> {
+    let is_type = match type_ {
+        sym::TRUE => true,
+        sym::NIL => false,
+        sym::ATOM => atom(object),
+        sym::NULL => object.is_nil(),
+        sym::LIST => listp(object),
+        sym::SEQUENCE => listp(object) || arrayp(object),
+        sym::ARRAY => arrayp(object),
+        sym::SYMBOL => matches!(object.untag(), ObjectType::Symbol(_)),
+        sym::KEYWORD => keywordp(object),
+        sym::BOOLEAN => object.is_nil() || object == sym::TRUE,
+        sym::NUMBER | sym::NUMBER_OR_MARKER | sym::REAL => numberp(object),
+        sym::INTEGER | sym::FIXNUM => integerp(object),
+        sym::NATNUM => matches!(object.untag(), ObjectType::Int(x) if x >= 0),
+        sym::CHARACTER => {
+            matches!(object.untag(), ObjectType::Int(x) if (0..=0x3F_FFFF).contains(&x))
+        }
+        sym::FUNCTION => functionp(object),
+        sym::BYTE_CODE_FUNCTION => byte_code_function_p(object),
+        sym::RECORD => recordp(object),
+        sym::CONS
+        | sym::FLOAT
+        | sym::STRING
+        | sym::VECTOR
+        | sym::HASH_TABLE
+        | sym::CHAR_TABLE
+        | sym::SUBR
+        | sym::BUFFER
+        | sym::COMPILED_FUNCTION
+        | sym::SYMBOL_WITH_POS => type_of(object) == type_,
+        _ => return None,
+    };
+    Some(is_type)
+}
+
+/// Return whether OBJECT is a structure of type TYPE or one of its children,
+/// or None if TYPE is not a structure type. This walks the parents of the
+/// `cl--class` record of OBJECT's type.
+fn struct_typep(object: Object, type_: Symbol, env: &Rt<Env>, cx: &Context) -> Option<bool> {
+    if !matches!(get(type_, sym::CL__CLASS, env, cx).untag(), ObjectType::Record(_)) {
+        return None;
+    }
+    let ObjectType::Symbol(tag) = type_of(object).untag() else { return Some(false) };
+    let mut classes = vec![get(tag, sym::CL__CLASS, env, cx)];
+    while let Some(class) = classes.pop() {
+        // A class is a record of the form [TYPE NAME DOCSTRING PARENTS ...]
+        let ObjectType::Record(class) = class.untag() else { continue };
+        if class.get(1).is_some_and(|x| x.get() == type_) {
+            return Some(true);
+        }
+        if let Some(ObjectType::Cons(parents)) = class.get(3).map(|x| x.get().untag()) {
+            classes.extend(parents.elements().filter_map(Result::ok));
+        }
+    }
+    Some(false)
+}
+
+/// Return non-nil if VAL is of type TYPE.
+///
+/// This version only knows about builtin types, `cl-defstruct` types and
+/// types with a `cl-deftype-satisfies` property, which covers the types that
+/// `cl-defmethod` can dispatch on. It is replaced by the full version when
+/// cl-macs is loaded.
 #[defun]
-pub(crate) fn indirect_function<'ob>(object: Object<'ob>, cx: &'ob Context) -> Object<'ob> {
-    match object.untag() {
-        ObjectType::Symbol(sym) => match sym.follow_indirect(cx) {
+fn cl_typep(
+    val: &Rto<Object>,
+    type_: &Rto<Object>,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<bool> {
+    let ObjectType::Symbol(type_) = type_.untag(cx) else {
+        bail!("Unsupported type: {}", type_.bind(cx))
+    };
+    let object = val.bind(cx);
+    if let Some(is_type) = builtin_typep(object, type_) {
+        return Ok(is_type);
+    }
+    if let Some(is_type) = struct_typep(object, type_, env, cx) {
+        return Ok(is_type);
+    }
+    let pred = get(type_, sym::CL_DEFTYPE_SATISFIES, env, cx);
+    if pred.is_nil() {
+        bail!("Unknown type: {type_}");
+    }
+    let pred: Function = pred.try_into()?;
+    root!(pred, cx);
+    let object = val.bind(cx);
+    Ok(!call!(pred, object; env, cx)?.is_nil())
+}
+
+#[defun]
+pub(crate) fn indirect_function<'ob>(object: Object<'ob>, cx: &'ob Context) -> Result<Object<'ob>> {
+    Ok(match object.untag() {
+        ObjectType::Symbol(sym) => match sym.indirect_function(cx)? {
             Some(func) => func.into(),
             None => NIL,
         },
         _ => object,
-    }
+    })
 }
 
+/// Announce that FEATURE is available. The `eval-after-load` forms for
+/// FEATURE are run at the end of the file being loaded, or right away if
+/// FEATURE is provided outside of `load`.
 #[defun]
-pub(crate) fn provide<'ob>(feature: Symbol<'ob>, _subfeatures: Option<&Cons>) -> Symbol<'ob> {
-    let mut features = FEATURES.lock().unwrap();
-    // TODO: SYMBOL - need to trace this
-    let feat = unsafe { feature.with_lifetime() };
-    features.insert(feat);
-    feature
+pub(crate) fn provide<'ob>(
+    feature: &Rto<Object>,
+    _subfeatures: Option<&Rto<Object>>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<Symbol<'ob>> {
+    {
+        let feat: Symbol = feature.bind(cx).try_into()?;
+        let mut features = FEATURES.lock().unwrap();
+        // TODO: SYMBOL - need to trace this
+        let feat = unsafe { feat.with_lifetime() };
+        features.insert(feat);
+    }
+    crate::lread::record_load_entry(sym::PROVIDE, feature.bind(cx), env, cx);
+    let loading = env.vars.get(sym::LOAD_IN_PROGRESS).is_some_and(|x| !x.bind(cx).is_nil());
+    if !loading {
+        crate::lread::run_after_load_functions(feature, env, cx)?;
+    }
+    Ok(feature.bind(cx).try_into()?)
 }
 
 #[defun]
@@ -467,16 +695,64 @@ pub(crate) fn cons<'ob>(car: Object, cdr: Object, cx: &'ob Context) -> Object<'o
 }
 
 // Symbol with position
+defvar_bool!(SYMBOLS_WITH_POS_ENABLED, false);
+
+/// True if symbols with position should be treated as their bare symbol.
+pub(crate) fn symbols_with_pos_enabled(env: &Rt<Env>, cx: &Context) -> bool {
+    env.vars
+        .get(sym::SYMBOLS_WITH_POS_ENABLED)
+        .is_some_and(|x| !x.bind(cx).is_nil())
+}
+
+/// Return the bare symbol of SYM, which is a symbol or a symbol with position.
 #[defun]
-fn bare_symbol(sym: Symbol) -> Symbol {
-    // TODO: implement
-    sym
+fn bare_symbol<'ob>(sym: Object<'ob>) -> Result<Symbol<'ob>> {
+    match sym.untag() {
+        ObjectType::Symbol(sym) => Ok(sym),
+        ObjectType::SymbolWithPos(sym) => Ok(sym.sym()),
+        x => Err(TypeError::new(Type::Symbol, x).into()),
+    }
 }
 
+/// Return t if OBJECT is a symbol with position.
 #[defun]
-fn symbol_with_pos_p(_sym: Object) -> bool {
-    // TODO: implement
-    false
+fn symbol_with_pos_p(object: Object) -> bool {
+    matches!(object.untag(), ObjectType::SymbolWithPos(_))
+}
+
+/// Return the position of the symbol with position SYMBOL.
+#[defun]
+fn symbol_with_pos_pos(symbol: &SymbolWithPos) -> usize {
+    symbol.pos()
+}
+
+/// Return the bare symbol of ARG if it is a symbol with position, otherwise
+/// ARG.
+#[defun]
+pub(crate) fn remove_pos_from_symbol(arg: Object) -> Object {
+    match arg.untag() {
+        ObjectType::SymbolWithPos(sym) => sym.sym().into(),
+        _ => arg,
+    }
+}
+
+/// Make a symbol with position from SYMBOL and POSITION.
+///
+/// SYMBOL can be a symbol or a symbol with position, whose position is
+/// replaced. POSITION can be a position or a symbol with position, whose
+/// position is used.
+#[defun]
+fn position_symbol<'ob>(
+    symbol: Object,
+    position: Object,
+    cx: &'ob Context,
+) -> Result<Gc<&'ob SymbolWithPos>> {
+    let symbol = bare_symbol(symbol)?;
+    let position = match position.untag() {
+        ObjectType::SymbolWithPos(sym) => sym.pos(),
+        _ => usize::try_from(position)?,
+    };
+    Ok(cx.add_as(SymbolWithPosInner::new(symbol, position)))
 }
 
 #[derive(Debug, PartialEq)]
@@ -494,6 +770,7 @@ impl std::fmt::Display for LispError {
 }
 
 defsym!(WRONG_NUMBER_OF_ARGUMENTS);
+defsym!(CYCLIC_FUNCTION_INDIRECTION);
 impl LispError {
     pub(crate) fn new(message: &Cons) -> Self {
         Self { message: unsafe { message.with_lifetime() } }
@@ -513,6 +790,11 @@ impl LispError {
         let list = list![sym::WRONG_NUMBER_OF_ARGUMENTS, func, expected, actual; cx];
         Self::new(list.try_into().unwrap())
     }
+
+    pub(crate) fn cyclic_function_indirection(symbol: Symbol, cx: &Context) -> Self {
+        let list = list![sym::CYCLIC_FUNCTION_INDIRECTION, symbol; cx];
+        Self::new(list.try_into().unwrap())
+    }
 }
 
 unsafe impl Send for LispError {}
@@ -530,12 +812,148 @@ mod test {
         assert_eq!(ash(-8, -1), -4);
         assert_eq!(ash(256, -8), 1);
         assert_eq!(ash(-8, 1), -16);
+        assert_eq!(ash(1, 64), 0);
+    }
+
+    #[test]
+    fn test_arrayp() {
+        assert_lisp(
+            r#"(list (arrayp "a") (arrayp [1]) (arrayp '(1)) (arrayp nil))"#,
+            "(t t nil nil)",
+        );
+    }
+
+    #[test]
+    fn test_type_of_record() {
+        assert_lisp("(type-of (record 'foo 1))", "foo");
+        assert_lisp("(type-of (record (record 'class 'my-class) 1))", "my-class");
+    }
+
+    #[test]
+    fn test_cl_typep() {
+        assert_lisp(
+            r#"(list (cl-typep 1 'integer) (cl-typep 1 'number) (cl-typep 1.0 'integer)
+                     (cl-typep nil 'list) (cl-typep nil 'null) (cl-typep "a" 'sequence)
+                     (cl-typep '(1) 'array) (cl-typep -1 'natnum) (cl-typep :a 'keyword))"#,
+            "(t t nil t t t nil nil t)",
+        );
+        assert_lisp(
+            "(progn (put 'typep-test 'cl-deftype-satisfies 'stringp)
+                    (list (cl-typep \"a\" 'typep-test) (cl-typep 1 'typep-test)))",
+            "(t nil)",
+        );
+        // a child struct is also of the parent type
+        assert_lisp(
+            "(let ((parent (record 'cl-structure-class 'typep-parent nil nil))
+                   (child (record 'cl-structure-class 'typep-child nil nil)))
+               (aset child 3 (list parent))
+               (put 'typep-parent 'cl--class parent)
+               (put 'typep-child 'cl--class child)
+               (list (cl-typep (record 'typep-child) 'typep-parent)
+                     (cl-typep (record 'typep-parent) 'typep-child)
+                     (cl-typep 1 'typep-parent)))",
+            "(t nil nil)",
+        );
+    }
+
+    #[test]
+    fn test_string_to_number() {
+        assert_lisp(
+            r#"(list (string-to-number " 12abc") (string-to-number "-1.5e2x") (string-to-number "1.")
+                     (string-to-number "x") (string-to-number "ff" 16) (string-to-number "1.5" 16)
+                     (string-to-number "1.0e+INF"))"#,
+            "(12 -150.0 1 0 255 1 1.0e+INF)",
+        );
+        assert_lisp(
+            "(list (number-to-string 1) (number-to-string 1.0) (number-to-string 1e20) (number-to-string 0.1))",
+            r#"("1" "1.0" "1e+20" "0.1")"#,
+        );
     }
 
     #[test]
     fn test_functionp() {
         assert_lisp("(functionp '(lambda nil))", "t");
     }
+
+    #[test]
+    fn test_symbol_with_pos() {
+        assert_lisp("(symbol-with-pos-p (position-symbol 'foo 12))", "t");
+        assert_lisp("(symbol-with-pos-p 'foo)", "nil");
+        assert_lisp("(bare-symbol (position-symbol 'foo 12))", "foo");
+        assert_lisp("(bare-symbol 'foo)", "foo");
+        assert_lisp("(symbol-with-pos-pos (position-symbol (position-symbol 'foo 3) 12))", "12");
+        assert_lisp("(symbol-with-pos-pos (position-symbol 'foo (position-symbol 'bar 7)))", "7");
+        assert_lisp("(remove-pos-from-symbol (position-symbol 'foo 12))", "foo");
+        assert_lisp("(remove-pos-from-symbol 1)", "1");
+        assert_lisp("(type-of (position-symbol 'foo 12))", "symbol-with-pos");
+        assert_lisp("(eq (position-symbol 'foo 12) 'foo)", "nil");
+        assert_lisp("(symbolp (position-symbol 'foo 12))", "nil");
+        assert_lisp(
+            "(let ((symbols-with-pos-enabled t)) (eq (position-symbol 'foo 12) 'foo))",
+            "t",
+        );
+        assert_lisp(
+            "(let ((symbols-with-pos-enabled t)) (eq (position-symbol 'foo 12) 'bar))",
+            "nil",
+        );
+        assert_lisp(
+            "(let ((symbols-with-pos-enabled t)) (symbolp (position-symbol 'foo 12)))",
+            "t",
+        );
+        assert_lisp("(prin1-to-string (position-symbol 'foo 12))", r##""#<symbol foo at 12>""##);
+        assert_lisp(
+            "(let ((print-symbols-bare t)) (prin1-to-string (position-symbol 'foo 12)))",
+            r#""foo""#,
+        );
+    }
+
+    #[test]
+    fn test_alias_redefined() {
+        assert_lisp(
+            "(progn (defalias 'data-test-alias-a #'car)
+                    (defalias 'data-test-alias-b 'data-test-alias-a)
+                    (let ((x (data-test-alias-b '(1 2))))
+                      (defalias 'data-test-alias-a #'cdr)
+                      (list x (data-test-alias-b '(1 2)) (fboundp 'data-test-alias-b))))",
+            "(1 (2) t)",
+        );
+    }
+
+    #[test]
+    fn test_cyclic_alias() {
+        assert_lisp(
+            "(progn (defalias 'data-test-cycle-a 'data-test-cycle-b)
+                    (defalias 'data-test-cycle-b 'data-test-cycle-c)
+                    (list (condition-case err (defalias 'data-test-cycle-c 'data-test-cycle-a)
+                            (error err))
+                          (condition-case err (fset 'data-test-cycle-d 'data-test-cycle-d)
+                            (error (car err)))
+                          (fboundp 'data-test-cycle-c)))",
+            "((cyclic-function-indirection data-test-cycle-c) cyclic-function-indirection nil)",
+        );
+    }
+
+    #[test]
+    fn test_indirection_depth() {
+        assert_lisp(
+            r#"(let ((prev 'car) (i 0) (result nil))
+                 (condition-case err
+                     (while (< i 150)
+                       (setq i (1+ i))
+                       (let ((sym (intern (format "data-test-deep-alias-%d" i))))
+                         (defalias sym prev)
+                         (setq prev sym)))
+                   (error (setq result (list (car err)))))
+                 (setq result (cons i (cons (funcall prev '(1 2)) result)))
+                 (defalias 'data-test-deep-alias-1 'data-test-deep-tail-a)
+                 (defalias 'data-test-deep-tail-a 'data-test-deep-tail-b)
+                 (defalias 'data-test-deep-tail-b 'car)
+                 (list result
+                       (condition-case err (indirect-function prev) (error (car err)))
+                       (condition-case err (funcall prev '(1 2)) (error (car err)))))"#,
+            "((101 1 cyclic-function-indirection) cyclic-function-indirection cyclic-function-indirection)",
+        );
+    }
 }
 
 defsym!(MANY);
@@ -546,3 +964,16 @@ defsym!(HASH_TABLE);
 defsym!(BUFFER);
 defsym!(SUBR);
 defsym!(CHAR_TABLE);
+defsym!(SYMBOL_WITH_POS);
+defsym!(NUMBER);
+defsym!(NUMBER_OR_MARKER);
+defsym!(REAL);
+defsym!(FIXNUM);
+defsym!(NATNUM);
+defsym!(CHARACTER);
+defsym!(BOOLEAN);
+defsym!(KEYWORD);
+defsym!(SEQUENCE);
+defsym!(BYTE_CODE_FUNCTION);
+defsym!(CL__CLASS);
+defsym!(CL_DEFTYPE_SATISFIES);
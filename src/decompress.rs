@@ -0,0 +1,133 @@
+//! Decompressing zlib and gzip data in buffers.
+//!
+//! Support for this is behind the `zlib` cargo feature. Compressed data is
+//! expected to be in the buffer as bytes, either raw byte characters, the way
+//! they are inserted by the `binary` coding system, or characters below 256.
+use crate::coding::byte_of_char;
+use crate::core::{
+    env::Env,
+    gc::{Context, Rt},
+    object::{NIL, Object, OptionalFlag, TRUE, raw_byte_char},
+};
+use anyhow::{Result, bail};
+use rune_macros::defun;
+
+/// Decompress `input`, which is either gzip or zlib data. Return the data
+/// that was decompressed, the number of input bytes that were not used, and
+/// whether decompression succeeded.
+#[cfg(feature = "zlib")]
+fn inflate(input: &[u8]) -> (Vec<u8>, usize, bool) {
+    use flate2::bufread::{MultiGzDecoder, ZlibDecoder};
+    use std::io::Read;
+
+    let mut output = Vec::new();
+    // gzip data starts with a magic number
+    if input.starts_with(&[0x1f, 0x8b]) {
+        let mut decoder = MultiGzDecoder::new(input);
+        let ok = decoder.read_to_end(&mut output).is_ok();
+        let unused = decoder.get_ref().len();
+        (output, unused, ok)
+    } else {
+        let mut decoder = ZlibDecoder::new(input);
+        let ok = decoder.read_to_end(&mut output).is_ok();
+        let unused = decoder.get_ref().len();
+        (output, unused, ok)
+    }
+}
+
+#[cfg(not(feature = "zlib"))]
+fn inflate(_input: &[u8]) -> (Vec<u8>, usize, bool) {
+    unreachable!("zlib support is not enabled")
+}
+
+/// Return t if zlib decompression is available in this instance of Emacs.
+#[defun]
+fn zlib_available_p() -> bool {
+    cfg!(feature = "zlib")
+}
+
+/// Decompress a gzip or zlib compressed region.
+///
+/// Replace the text in the region with the decompressed data and return t.
+/// If decompression fails and ALLOW-PARTIAL is nil, return nil and leave the
+/// region unchanged. Otherwise replace the region with whatever data was
+/// decompressed and return the number of bytes that were not used.
+#[defun]
+fn zlib_decompress_region<'ob>(
+    start: usize,
+    end: usize,
+    allow_partial: OptionalFlag,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    if !zlib_available_p() {
+        bail!("zlib is not available");
+    }
+    let buffer = env.current_buffer.get_mut();
+    let (beg, end) = (start.min(end), start.max(end));
+    let input = {
+        let (s1, s2) = buffer.slice_with_gap(beg, end)?;
+        let bytes = s1.chars().chain(s2.chars()).map(byte_of_char);
+        let Some(input) = bytes.collect::<Option<Vec<_>>>() else {
+            bail!("The region contains multibyte characters")
+        };
+        input
+    };
+    let (output, unused, ok) = inflate(&input);
+    if !ok && allow_partial.is_none() {
+        return Ok(NIL);
+    }
+    let text: String = output.into_iter().map(raw_byte_char).collect();
+    buffer.delete(beg, end)?;
+    buffer.goto_char(beg);
+    buffer.insert_str(&text);
+    Ok(if ok { TRUE } else { cx.add(unused as i64) })
+}
+
+#[cfg(all(test, feature = "zlib"))]
+mod test {
+    use crate::interpreter::assert_lisp;
+    use flate2::write::{GzEncoder, ZlibEncoder};
+    use std::io::Write;
+
+    /// Lisp code that inserts `bytes` as raw byte characters.
+    fn insert_bytes(bytes: &[u8]) -> String {
+        let chars: Vec<_> = bytes.iter().map(u8::to_string).collect();
+        format!("(insert {})", chars.join(" "))
+    }
+
+    #[test]
+    fn test_zlib_decompress_region() {
+        let mut zlib = ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        zlib.write_all(b"hello hello hello").unwrap();
+        let zlib = zlib.finish().unwrap();
+        assert_lisp(
+            &format!(
+                "(progn {} (list (zlib-decompress-region 1 (point-max)) (buffer-string)))",
+                insert_bytes(&zlib)
+            ),
+            r#"(t "hello hello hello")"#,
+        );
+
+        let mut gzip = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gzip.write_all(b"compressed").unwrap();
+        let gzip = gzip.finish().unwrap();
+        assert_lisp(
+            &format!(
+                "(progn (insert \"a\") {} (insert \"b\")
+                   (list (zlib-decompress-region 2 (1- (point-max))) (buffer-string)))",
+                insert_bytes(&gzip)
+            ),
+            r#"(t "acompressedb")"#,
+        );
+
+        let truncated = insert_bytes(&gzip[..gzip.len() / 2]);
+        assert_lisp(
+            &format!(
+                "(progn {truncated} (list (zlib-decompress-region 1 (point-max)) (length (buffer-string))))"
+            ),
+            &format!("(nil {})", gzip.len() / 2),
+        );
+        assert_lisp("(zlib-available-p)", "t");
+    }
+}
@@ -1,21 +1,366 @@
-use crate::core::{
-    gc::Context,
-    object::{NIL, Object, OptionalFlag, TRUE},
+//! Directory listing and file attributes.
+use crate::{
+    core::{
+        cons::Cons,
+        env::{Env, sym},
+        gc::{Context, Rt},
+        object::{NIL, Object, OptionalFlag, TRUE},
+    },
+    fileio::expand_file_name,
+    fns::slice_into_list,
 };
+use anyhow::{Context as _, Result};
 use rune_core::macros::list;
 use rune_macros::defun;
+use rune_regex::Regex;
 use std::path::Path;
 
+/// Return the sorted names of the entries in `directory`. `.` and `..` are
+/// included as they are in GNU Emacs.
+fn directory_entries(directory: &str, regexp: Option<&str>, nosort: bool) -> Result<Vec<String>> {
+    let re = match regexp {
+        Some(re) => Some(Regex::new(re)?),
+        None => None,
+    };
+    let dir =
+        std::fs::read_dir(directory).with_context(|| format!("Opening directory: {directory}"))?;
+    let mut names = vec![".".to_owned(), "..".to_owned()];
+    for entry in dir {
+        names.push(entry?.file_name().to_string_lossy().into_owned());
+    }
+    if let Some(re) = re {
+        let mut matched = Vec::new();
+        for name in names {
+            if re.is_match(name.as_str())? {
+                matched.push(name);
+            }
+        }
+        names = matched;
+    }
+    if !nosort {
+        names.sort();
+    }
+    Ok(names)
+}
+
+fn full_name(directory: &str, name: &str) -> String {
+    Path::new(directory).join(name).to_string_lossy().into_owned()
+}
+
+#[defun]
+fn directory_files<'ob>(
+    directory: &str,
+    full: OptionalFlag,
+    matching: Option<&str>,
+    nosort: OptionalFlag,
+    count: Option<usize>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    let mut names = directory_entries(directory, matching, nosort.is_some())?;
+    if let Some(count) = count {
+        names.truncate(count);
+    }
+    let files: Vec<Object> = names
+        .iter()
+        .map(|x| {
+            if full.is_some() {
+                cx.add(full_name(directory, x))
+            } else {
+                cx.add(x.as_str())
+            }
+        })
+        .collect();
+    Ok(slice_into_list(&files, None, cx))
+}
+
 #[defun]
-fn file_attributes<'ob>(filename: &str, _id_format: OptionalFlag, cx: &'ob Context) -> Object<'ob> {
+fn directory_files_and_attributes<'ob>(
+    directory: &str,
+    full: OptionalFlag,
+    matching: Option<&str>,
+    nosort: OptionalFlag,
+    id_format: Option<Object>,
+    count: Option<usize>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    let mut names = directory_entries(directory, matching, nosort.is_some())?;
+    if let Some(count) = count {
+        names.truncate(count);
+    }
+    let files: Vec<Object> = names
+        .iter()
+        .map(|x| {
+            let path = full_name(directory, x);
+            let attrs = file_attributes(&path, id_format, cx);
+            let name = if full.is_some() { cx.add(path) } else { cx.add(x.as_str()) };
+            Cons::new(name, attrs, cx).into()
+        })
+        .collect();
+    Ok(slice_into_list(&files, None, cx))
+}
+
+#[defun]
+fn file_name_all_completions<'ob>(
+    file: &str,
+    directory: &str,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    let names = directory_entries(directory, None, false)?;
+    let completions: Vec<Object> = names
+        .iter()
+        .filter(|x| x.starts_with(file))
+        .map(|x| {
+            if Path::new(directory).join(x).is_dir() {
+                cx.add(format!("{x}/"))
+            } else {
+                cx.add(x.as_str())
+            }
+        })
+        .collect();
+    Ok(slice_into_list(&completions, None, cx))
+}
+
+/// Whether `chr` is in the character class `class`, which is the part of a
+/// `[...]` wildcard between the brackets.
+fn class_matches(class: &[char], chr: char) -> bool {
+    let (negated, class) = match class.split_first() {
+        Some(('!', rest)) => (true, rest),
+        _ => (false, class),
+    };
+    let mut i = 0;
+    let mut found = false;
+    while i < class.len() {
+        if i + 2 < class.len() && class[i + 1] == '-' {
+            found |= (class[i]..=class[i + 2]).contains(&chr);
+            i += 3;
+        } else {
+            found |= class[i] == chr;
+            i += 1;
+        }
+    }
+    found != negated
+}
+
+/// The length of the `[...]` wildcard at the start of `pattern`, or `None` if
+/// the bracket is not closed. A `]` right after the opening bracket (or after
+/// `[!`) is part of the class.
+fn class_len(pattern: &[char]) -> Option<usize> {
+    let start = if pattern.get(1) == Some(&'!') { 2 } else { 1 };
+    let end = pattern.iter().skip(start + 1).position(|&x| x == ']')?;
+    Some(start + 1 + end + 1)
+}
+
+/// Whether `name` matches the shell wildcard `pattern`. `*` matches any
+/// sequence of characters and `?` any single character, including a leading
+/// `.`. This matches the same names as the regexp from `wildcard-to-regexp`.
+fn wildcard_match(pattern: &[char], name: &[char]) -> bool {
+    let (mut p, mut n) = (0, 0);
+    // Where to resume if the last `*` needs to match more characters
+    let mut backtrack = None;
+    while n < name.len() {
+        let step = match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, n));
+                p += 1;
+                continue;
+            }
+            Some('?') => Some(1),
+            Some('[') => match class_len(&pattern[p..]) {
+                Some(len) => class_matches(&pattern[p + 1..p + len - 1], name[n]).then_some(len),
+                None => (name[n] == '[').then_some(1),
+            },
+            Some(&chr) => (chr == name[n]).then_some(1),
+            None => None,
+        };
+        match (step, backtrack) {
+            (Some(len), _) => {
+                p += len;
+                n += 1;
+            }
+            (None, Some((star, start))) => {
+                p = star + 1;
+                n = start + 1;
+                backtrack = Some((star, start + 1));
+            }
+            (None, None) => return false,
+        }
+    }
+    pattern[p..].iter().all(|&x| x == '*')
+}
+
+fn has_wildcard(component: &str) -> bool {
+    component.contains(['*', '?', '['])
+}
+
+/// Matches one component of a file name pattern.
+enum ComponentMatcher {
+    Wildcard(Vec<char>),
+    Regexp(Regex),
+}
+
+impl ComponentMatcher {
+    fn new(component: &str, regexp: bool) -> Result<Self> {
+        Ok(if regexp {
+            Self::Regexp(Regex::new(&format!("\\`{component}\\'"))?)
+        } else {
+            Self::Wildcard(component.chars().collect())
+        })
+    }
+
+    fn is_match(&self, name: &str) -> Result<bool> {
+        match self {
+            Self::Wildcard(pattern) => {
+                let name: Vec<char> = name.chars().collect();
+                Ok(wildcard_match(pattern, &name))
+            }
+            Self::Regexp(re) => Ok(re.is_match(name)?),
+        }
+    }
+}
+
+/// Append a file name component to a directory name, which may be empty.
+fn join_component(dir: &str, name: &str) -> String {
+    if dir.is_empty() || dir.ends_with('/') {
+        format!("{dir}{name}")
+    } else {
+        format!("{dir}/{name}")
+    }
+}
+
+/// Return the files that match `pattern`, which may contain wildcards in any
+/// of its components. `resolve` turns the file names built from the pattern
+/// into the names used to access the file system.
+fn expand_wildcards(
+    pattern: &str,
+    regexp: bool,
+    resolve: impl Fn(&str) -> Result<String>,
+) -> Result<Vec<String>> {
+    let is_dir = pattern.ends_with('/');
+    let mut components = pattern.split('/').filter(|x| !x.is_empty()).peekable();
+    let mut candidates =
+        vec![if pattern.starts_with('/') { "/".to_owned() } else { String::new() }];
+    while let Some(component) = components.next() {
+        let last = components.peek().is_none();
+        if !has_wildcard(component) {
+            for candidate in &mut candidates {
+                *candidate = join_component(candidate, component);
+            }
+            continue;
+        }
+        let matcher = ComponentMatcher::new(component, regexp)?;
+        let mut matched = Vec::new();
+        for candidate in &candidates {
+            let dir = resolve(if candidate.is_empty() { "." } else { candidate })?;
+            let Ok(entries) = std::fs::read_dir(&dir) else { continue };
+            let mut names = Vec::new();
+            for entry in entries {
+                let entry = entry?;
+                let name = entry.file_name().to_string_lossy().into_owned();
+                // Only directories can have more components matched in them
+                if ((last && !is_dir) || Path::new(&dir).join(&name).is_dir())
+                    && matcher.is_match(&name)?
+                {
+                    names.push(name);
+                }
+            }
+            names.sort();
+            matched.extend(names.iter().map(|name| join_component(candidate, name)));
+        }
+        candidates = matched;
+    }
+    let mut files = Vec::new();
+    for candidate in candidates {
+        let path = resolve(&candidate)?;
+        if Path::new(&path).symlink_metadata().is_ok() && (!is_dir || Path::new(&path).is_dir()) {
+            files.push(if is_dir { join_component(&candidate, "") } else { candidate });
+        }
+    }
+    Ok(files)
+}
+
+/// Return a list of the names of the files that match PATTERN.
+///
+/// Every component of PATTERN may contain the wildcards `*`, `?` and `[...]`,
+/// with the meaning they have in `wildcard-to-regexp`. If FULL is non-nil the
+/// names are absolute, and otherwise they are relative in the same way as
+/// PATTERN. If REGEXP is non-nil the components of PATTERN that contain
+/// wildcard characters are matched as regexps instead.
+#[defun]
+fn file_expand_wildcards<'ob>(
+    pattern: &str,
+    full: OptionalFlag,
+    regexp: OptionalFlag,
+    env: &Rt<Env>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    let pattern = if full.is_some() {
+        expand_file_name(pattern, None, env, cx)?
+    } else {
+        pattern.to_owned()
+    };
+    let resolve = |name: &str| expand_file_name(name, None, env, cx);
+    let files = expand_wildcards(&pattern, regexp.is_some(), resolve)?;
+    let files: Vec<Object> = files.into_iter().map(|x| cx.add(x)).collect();
+    Ok(slice_into_list(&files, None, cx))
+}
+
+/// Convert the shell wildcard WILDCARD to a regexp that matches the same
+/// file names.
+#[defun]
+fn wildcard_to_regexp(wildcard: &str) -> String {
+    let chars: Vec<char> = wildcard.chars().collect();
+    let mut regexp = String::from("\\`");
+    let mut i = 0;
+    while i < chars.len() {
+        let bracket = if chars[i] == '[' { class_len(&chars[i..]) } else { None };
+        if let Some(len) = bracket {
+            let class = &chars[i + 1..i + len - 1];
+            match class.first() {
+                Some('!') => regexp.push_str("[^"),
+                // `^` is not special in a wildcard class, so put a NUL (which
+                // can't be in a file name) in front of it
+                Some('^') => regexp.push_str("[\0"),
+                _ => regexp.push('['),
+            }
+            let skip = usize::from(class.first() == Some(&'!'));
+            regexp.extend(&class[skip..]);
+            regexp.push(']');
+            i += len;
+            continue;
+        }
+        match chars[i] {
+            '*' => regexp.push_str("[^\0]*"),
+            '?' => regexp.push_str("[^\0]"),
+            chr @ ('.' | '+' | '^' | '$' | '\\' | '[') => {
+                regexp.push('\\');
+                regexp.push(chr);
+            }
+            chr => regexp.push(chr),
+        }
+        i += 1;
+    }
+    regexp.push_str("\\'");
+    regexp
+}
+
+#[defun]
+fn file_attributes<'ob>(
+    filename: &str,
+    id_format: Option<Object>,
+    cx: &'ob Context,
+) -> Object<'ob> {
     let file = Path::new(filename);
-    if file.exists() { metadata_attributes(file, cx) } else { NIL }
+    if file.symlink_metadata().is_ok() {
+        metadata_attributes(file, id_format.is_some_and(|x| x == sym::STRING), cx)
+    } else {
+        NIL
+    }
 }
 
 #[cfg(unix)]
-fn metadata_attributes<'ob>(file: &Path, cx: &'ob Context) -> Object<'ob> {
+fn metadata_attributes<'ob>(file: &Path, id_string: bool, cx: &'ob Context) -> Object<'ob> {
     use std::os::unix::fs::MetadataExt;
-    let metadata = &file.metadata().unwrap();
+    let Ok(metadata) = &file.symlink_metadata() else { return NIL };
 
     //  0. t for directory, string (name linked to) for symbolic link, or nil.
     let file_type = get_file_type(file, cx);
@@ -23,22 +368,28 @@ fn metadata_attributes<'ob>(file: &Path, cx: &'ob Context) -> Object<'ob> {
     let links = metadata.nlink();
     //  2. File uid as a string or (if ID-FORMAT is integer or a string value
     //   cannot be looked up) as an integer.
-    let uid = metadata.uid();
+    let uid = match id_string.then(|| user_name(metadata.uid())).flatten() {
+        Some(name) => cx.add(name),
+        None => cx.add(i64::from(metadata.uid())),
+    };
     //  3. File gid, likewise.
-    let gid = metadata.gid();
+    let gid = match id_string.then(|| group_name(metadata.gid())).flatten() {
+        Some(name) => cx.add(name),
+        None => cx.add(i64::from(metadata.gid())),
+    };
     //  4. Last access time, in the style of current-time.
     //   (See a note below about access time on FAT-based filesystems.)
-    let atime = metadata.atime();
+    let atime = lisp_time(metadata.atime(), metadata.atime_nsec(), cx);
     //  5. Last modification time, likewise.  This is the time of the last
     //   change to the file's contents.
-    let mtime = metadata.mtime();
+    let mtime = lisp_time(metadata.mtime(), metadata.mtime_nsec(), cx);
     //  6. Last status change time, likewise.  This is the time of last change
     //   to the file's attributes: owner and group, access mode bits, etc.
-    let ctime = metadata.ctime();
+    let ctime = lisp_time(metadata.ctime(), metadata.ctime_nsec(), cx);
     //  7. Size in bytes, as an integer.
     let size = metadata.size();
     //  8. File modes, as a string of ten letters or dashes as in ls -l.
-    let mode = metadata.mode();
+    let mode = file_mode_string(metadata.mode());
     //  9. An unspecified value, present only for backward compatibility.
     // 10. inode number, as a nonnegative integer.
     let inode = metadata.ino();
@@ -47,8 +398,77 @@ fn metadata_attributes<'ob>(file: &Path, cx: &'ob Context) -> Object<'ob> {
     list![file_type, links, uid, gid, atime, mtime, ctime, size, mode, TRUE, inode, dev; cx]
 }
 
+/// Convert a unix timestamp to the `(HIGH LOW USEC PSEC)` format used by
+/// `current-time`.
+#[cfg(unix)]
+fn lisp_time<'ob>(secs: i64, nsecs: i64, cx: &'ob Context) -> Object<'ob> {
+    list![secs >> 16, secs & 0xffff, nsecs / 1000, (nsecs % 1000) * 1000; cx]
+}
+
+/// Format the mode bits of a file the way `ls -l` does.
+#[cfg(unix)]
+fn file_mode_string(mode: u32) -> String {
+    let file_type = match mode & 0o170_000 {
+        0o040_000 => 'd',
+        0o120_000 => 'l',
+        0o020_000 => 'c',
+        0o060_000 => 'b',
+        0o010_000 => 'p',
+        0o140_000 => 's',
+        _ => '-',
+    };
+    let mut string = String::with_capacity(10);
+    string.push(file_type);
+    let bit = |mask: u32, chr: char| if mode & mask != 0 { chr } else { '-' };
+    let exec = |mask: u32, special: u32, set: char, unset: char| match (
+        mode & mask != 0,
+        mode & special != 0,
+    ) {
+        (true, true) => set,
+        (false, true) => unset,
+        (true, false) => 'x',
+        (false, false) => '-',
+    };
+    string.push(bit(0o400, 'r'));
+    string.push(bit(0o200, 'w'));
+    string.push(exec(0o100, 0o4000, 's', 'S'));
+    string.push(bit(0o040, 'r'));
+    string.push(bit(0o020, 'w'));
+    string.push(exec(0o010, 0o2000, 's', 'S'));
+    string.push(bit(0o004, 'r'));
+    string.push(bit(0o002, 'w'));
+    string.push(exec(0o001, 0o1000, 't', 'T'));
+    string
+}
+
+#[cfg(unix)]
+pub(crate) fn user_name(uid: u32) -> Option<String> {
+    // SAFETY: getpwuid returns a pointer to static storage or null
+    unsafe {
+        let passwd = libc::getpwuid(uid);
+        if passwd.is_null() {
+            return None;
+        }
+        let name = std::ffi::CStr::from_ptr((*passwd).pw_name);
+        Some(name.to_string_lossy().into_owned())
+    }
+}
+
+#[cfg(unix)]
+fn group_name(gid: u32) -> Option<String> {
+    // SAFETY: getgrgid returns a pointer to static storage or null
+    unsafe {
+        let group = libc::getgrgid(gid);
+        if group.is_null() {
+            return None;
+        }
+        let name = std::ffi::CStr::from_ptr((*group).gr_name);
+        Some(name.to_string_lossy().into_owned())
+    }
+}
+
 #[cfg(windows)]
-fn metadata_attributes<'ob>(file: &Path, cx: &'ob Context) -> Object<'ob> {
+fn metadata_attributes<'ob>(file: &Path, _id_string: bool, cx: &'ob Context) -> Object<'ob> {
     use std::os::windows::fs::MetadataExt;
     let metadata = &file.metadata().unwrap();
 
@@ -83,3 +503,74 @@ fn get_file_type<'ob>(file: &Path, cx: &'ob Context) -> Object<'ob> {
         NIL
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    #[cfg(unix)]
+    fn test_file_mode_string() {
+        assert_eq!(file_mode_string(0o100_644), "-rw-r--r--");
+        assert_eq!(file_mode_string(0o040_755), "drwxr-xr-x");
+        assert_eq!(file_mode_string(0o104_755), "-rwsr-xr-x");
+        assert_eq!(file_mode_string(0o041_777), "drwxrwxrwt");
+    }
+
+    fn matches(pattern: &str, name: &str) -> bool {
+        let pattern: Vec<char> = pattern.chars().collect();
+        let name: Vec<char> = name.chars().collect();
+        wildcard_match(&pattern, &name)
+    }
+
+    #[test]
+    fn test_wildcard_match() {
+        assert!(matches("*.el", "foo.el"));
+        assert!(matches("*.el", ".dir-locals.el"));
+        assert!(!matches("*.el", "foo.elc"));
+        assert!(matches("f?o*", "foo"));
+        assert!(matches("*a*b*", "xxaxxbxx"));
+        assert!(!matches("*a*b", "xxaxxbxx"));
+        assert!(matches("[a-c]x", "bx"));
+        assert!(!matches("[!a-c]x", "bx"));
+        assert!(matches("[]]", "]"));
+        assert!(matches("[!]]", "a"));
+        assert!(matches("[ab", "[ab"));
+        assert!(matches("λ?", "λμ"));
+    }
+
+    #[test]
+    fn test_wildcard_to_regexp() {
+        assert_eq!(wildcard_to_regexp("*.el"), "\\`[^\0]*\\.el\\'");
+        assert_eq!(wildcard_to_regexp("a?[!bc]"), "\\`a[^\0][^bc]\\'");
+        assert_eq!(wildcard_to_regexp("[^x]$"), "\\`[\0^x]\\$\\'");
+    }
+
+    #[test]
+    #[cfg(not(miri))]
+    fn test_expand_wildcards() {
+        let dir = env!("CARGO_MANIFEST_DIR");
+        let resolve = |name: &str| Ok(Path::new(dir).join(name).to_string_lossy().into_owned());
+        let files = expand_wildcards("src/dire?.rs", false, resolve).unwrap();
+        assert_eq!(files, ["src/dired.rs"]);
+        let files = expand_wildcards("crates/*/Cargo.toml", false, resolve).unwrap();
+        assert!(files.iter().any(|x| x == "crates/regex/Cargo.toml"));
+        let files = expand_wildcards("crates/*/src/", false, resolve).unwrap();
+        assert!(files.iter().all(|x| x.starts_with("crates/") && x.ends_with("/src/")));
+        let files = expand_wildcards("src/di.*\\.rs", true, resolve).unwrap();
+        assert_eq!(files, ["src/dired.rs"]);
+        assert!(expand_wildcards("src/missing*", false, resolve).unwrap().is_empty());
+    }
+
+    #[test]
+    #[cfg(not(miri))]
+    fn test_directory_entries() {
+        let dir = env!("CARGO_MANIFEST_DIR");
+        let all = directory_entries(dir, None, false).unwrap();
+        assert!(all.iter().any(|x| x == "Cargo.toml"));
+        assert_eq!(all[0], ".");
+        let toml = directory_entries(dir, Some("\\.toml$"), false).unwrap();
+        assert!(toml.iter().all(|x| x.ends_with(".toml")));
+        assert!(toml.iter().any(|x| x == "Cargo.toml"));
+    }
+}
@@ -0,0 +1,166 @@
+//! Documentation strings.
+//!
+//! The docstrings of builtin functions are the doc comments of their defun.
+//! The `defun` macro turns them into constants that build.rs collects into a
+//! static side table, so they don't take up space in each function or on the
+//! heap until someone asks for them.
+use crate::core::{
+    env::{Env, sym},
+    gc::{Context, Rt, Rto},
+    object::{Function, FunctionType, Gc, NIL, Object, ObjectType, OptionalFlag, Symbol},
+};
+use crate::data::get;
+use anyhow::{Result, bail};
+use rune_core::macros::{call, root};
+use rune_macros::defun;
+
+defsym!(FUNCTION_DOCUMENTATION);
+defsym!(VARIABLE_DOCUMENTATION);
+defsym!(SUBSTITUTE_COMMAND_KEYS);
+
+/// Return the unprocessed docstring of FUNCTION, or nil if it doesn't have
+/// one. FUNCTION should not be a symbol.
+fn function_doc<'ob>(function: Object<'ob>, cx: &'ob Context) -> Result<Object<'ob>> {
+    let Ok(func) = Function::try_from(function) else {
+        bail!("Invalid function: {function}")
+    };
+    match func.untag() {
+        FunctionType::SubrFn(subr) => Ok(match sym::subr_doc(subr) {
+            Some(doc) => cx.add(doc),
+            None => NIL,
+        }),
+        // Byte-compiled functions don't keep their docstring
+        FunctionType::ByteFn(_) | FunctionType::Symbol(_) => Ok(NIL),
+        FunctionType::Cons(cons) => {
+            let body_pos = match cons.car().untag() {
+                ObjectType::Symbol(sym::CLOSURE) => 3,
+                ObjectType::Symbol(sym::LAMBDA) => 2,
+                ObjectType::Symbol(sym::MACRO) => return function_doc(cons.cdr(), cx),
+                _ => bail!("Invalid function: {function}"),
+            };
+            let form = cons.elements().nth(body_pos).transpose()?;
+            let is_string = |x: &Object| matches!(x.untag(), ObjectType::String(_));
+            Ok(form.filter(is_string).unwrap_or_default())
+        }
+    }
+}
+
+/// Pass DOC through `substitute-command-keys` unless RAW is non-nil. This is
+/// skipped when help.el is not loaded.
+fn substitute_command_keys<'ob>(
+    doc: &Rto<Object>,
+    raw: OptionalFlag,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<Object<'ob>> {
+    let is_string = matches!(doc.untag(cx), ObjectType::String(_));
+    if raw.is_some() || !is_string || !sym::SUBSTITUTE_COMMAND_KEYS.has_func() {
+        return Ok(doc.bind(cx));
+    }
+    let func: Function = sym::SUBSTITUTE_COMMAND_KEYS.into();
+    root!(func, cx);
+    let doc = doc.bind(cx);
+    Ok(call!(func, doc; env, cx)?)
+}
+
+/// Convert VALUE, a documentation property, into a docstring. Properties that
+/// are not strings are evaluated to get the docstring.
+fn property_doc<'ob>(
+    value: &Rto<Object>,
+    raw: OptionalFlag,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<Object<'ob>> {
+    match value.untag(cx) {
+        ObjectType::NIL => Ok(NIL),
+        ObjectType::String(_) => substitute_command_keys(value, raw, env, cx),
+        _ => {
+            let doc = crate::interpreter::eval(value, None, env, cx)?;
+            root!(doc, cx);
+            substitute_command_keys(doc, raw, env, cx)
+        }
+    }
+}
+
+/// Return the documentation string of FUNCTION.
+///
+/// Unless RAW is non-nil, the string is passed through
+/// `substitute-command-keys`. If FUNCTION is a symbol with a
+/// `function-documentation` property, that is used instead.
+#[defun]
+fn documentation<'ob>(
+    function: &Rto<Object>,
+    raw: OptionalFlag,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<Object<'ob>> {
+    let doc = match function.untag(cx) {
+        ObjectType::Symbol(symbol) => {
+            let prop = get(symbol, sym::FUNCTION_DOCUMENTATION, env, cx);
+            if !prop.is_nil() {
+                root!(prop, cx);
+                return property_doc(prop, raw, env, cx);
+            }
+            let Some(func) = symbol.follow_indirect(cx) else { bail!("Void Function: {symbol}") };
+            function_doc(func.into(), cx)?
+        }
+        _ => function_doc(function.bind(cx), cx)?,
+    };
+    root!(doc, cx);
+    substitute_command_keys(doc, raw, env, cx)
+}
+
+/// Return the documentation string that is SYMBOL's PROP property.
+///
+/// This is like `get`, but a property that is not a string is evaluated to
+/// produce the docstring. Unless RAW is non-nil, the string is passed through
+/// `substitute-command-keys`.
+#[defun]
+fn documentation_property<'ob>(
+    symbol: &Rto<Gc<Symbol>>,
+    prop: &Rto<Gc<Symbol>>,
+    raw: OptionalFlag,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<Object<'ob>> {
+    let value = get(symbol.untag(cx), prop.untag(cx), env, cx);
+    root!(value, cx);
+    property_doc(value, raw, env, cx)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::interpreter::assert_lisp;
+
+    #[test]
+    fn test_documentation() {
+        assert_lisp(
+            r#"(equal (documentation 'prefix-numeric-value) "Return the numeric meaning of the raw prefix argument RAW.\n\n(fn RAW)")"#,
+            "t",
+        );
+        assert_lisp("(documentation 'car)", r#""(fn LIST)""#);
+        assert_lisp(r#"(documentation #'(lambda (x) "Add one." (1+ x)))"#, r#""Add one.""#);
+        assert_lisp(r#"(documentation #'(lambda (x) (1+ x)))"#, "nil");
+        assert_lisp(
+            r#"(progn (defalias 'doc-test-alias 'car) (equal (documentation 'doc-test-alias) (documentation 'car)))"#,
+            "t",
+        );
+        assert_lisp(
+            r#"(progn (put 'doc-test-prop 'function-documentation "Overridden.") (fset 'doc-test-prop 'car) (documentation 'doc-test-prop))"#,
+            r#""Overridden.""#,
+        );
+    }
+
+    #[test]
+    fn test_documentation_property() {
+        assert_lisp(
+            r#"(progn (put 'doc-test-var 'variable-documentation "A variable.") (documentation-property 'doc-test-var 'variable-documentation))"#,
+            r#""A variable.""#,
+        );
+        assert_lisp(
+            r#"(progn (put 'doc-test-form 'variable-documentation '(concat "A " "form.")) (documentation-property 'doc-test-form 'variable-documentation t))"#,
+            r#""A form.""#,
+        );
+        assert_lisp("(documentation-property 'doc-test-none 'variable-documentation)", "nil");
+    }
+}
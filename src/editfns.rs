@@ -1,18 +1,29 @@
 //! Buffer editing utilities.
+use crate::buffer::resolve_buffer;
+use crate::callproc::getenv_value;
 use crate::core::{
-    env::{ArgSlice, Env},
-    gc::{Context, Rt},
-    object::{Object, ObjectType},
+    env::{ArgSlice, Env, sym},
+    gc::{Context, Rt, Rto},
+    object::{NIL, Object, ObjectType, OpenBuffer, OptionalFlag, Symbol, TRUE},
 };
+use crate::eval::{EvalError, run_hook_functions};
+use crate::library::diff::diff;
 use anyhow::{Result, bail, ensure};
+use rune_core::macros::root;
 use rune_macros::defun;
+use std::time::{Duration, Instant};
 use std::{fmt::Write as _, io::Write};
 
 #[defun]
-fn message(format_string: &str, args: &[Object]) -> Result<String> {
+fn message(format_string: &str, args: &[Object], env: &Rt<Env>, cx: &Context) -> Result<String> {
     let message = format(format_string, args)?;
-    println!("MESSAGE: {message}");
-    std::io::stdout().flush()?;
+    // there is no echo area when running noninteractively
+    if env.vars.get(sym::NONINTERACTIVE).is_some_and(|x| !x.bind(cx).is_nil()) {
+        eprintln!("{message}");
+    } else {
+        println!("MESSAGE: {message}");
+        std::io::stdout().flush()?;
+    }
     Ok(message)
 }
 
@@ -83,80 +94,555 @@ fn char_to_string(chr: char) -> String {
 }
 
 #[defun]
-pub(crate) fn insert(args: ArgSlice, env: &mut Rt<Env>, cx: &Context) -> Result<()> {
-    let env = &mut **env; // Deref into rooted type so we can split the borrow
+pub(crate) fn insert(args: ArgSlice, env: &mut Rt<Env>, cx: &mut Context) -> Result<()> {
+    let beg = env.current_buffer.get().point();
+    signal_before_change(beg, beg, env, cx)?;
+    let (beg, end) = {
+        let env = &mut **env; // Deref into rooted type so we can split the borrow
+        let buffer = env.current_buffer.get_mut();
+        let beg = buffer.point();
+        let args = Rt::bind_slice(env.stack.arg_slice(args), cx);
+        for arg in args {
+            buffer.insert(*arg)?;
+        }
+        (beg, buffer.point())
+    };
+    signal_after_change(beg, end, 0, env, cx)
+}
+
+/// Insert `text` at point, running the change hooks.
+pub(crate) fn insert_text(text: &str, env: &mut Rt<Env>, cx: &mut Context) -> Result<()> {
+    let beg = env.current_buffer.get().point();
+    signal_before_change(beg, beg, env, cx)?;
     let buffer = env.current_buffer.get_mut();
-    let args = Rt::bind_slice(env.stack.arg_slice(args), cx);
-    for arg in args {
-        buffer.insert(*arg)?;
+    let beg = buffer.point();
+    buffer.insert_str(text);
+    let end = buffer.point();
+    signal_after_change(beg, end, 0, env, cx)
+}
+
+defvar!(BEFORE_CHANGE_FUNCTIONS);
+defvar!(AFTER_CHANGE_FUNCTIONS);
+defvar!(INHIBIT_MODIFICATION_HOOKS);
+
+/// Run `before-change-functions` for a change to the text between `beg` and
+/// `end`.
+pub(crate) fn signal_before_change(
+    beg: usize,
+    end: usize,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<()> {
+    run_change_hook(sym::BEFORE_CHANGE_FUNCTIONS, &[beg as i64, end as i64], env, cx)
+}
+
+/// Run `after-change-functions` once the text between `beg` and `end` has
+/// replaced `old_len` characters.
+pub(crate) fn signal_after_change(
+    beg: usize,
+    end: usize,
+    old_len: usize,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<()> {
+    crate::treesit::after_change(beg, end, old_len, env);
+    let args = [beg as i64, end as i64, old_len as i64];
+    run_change_hook(sym::AFTER_CHANGE_FUNCTIONS, &args, env, cx)
+}
+
+fn run_change_hook(hook: Symbol, args: &[i64], env: &mut Rt<Env>, cx: &mut Context) -> Result<()> {
+    let inhibit = env.vars.get(sym::INHIBIT_MODIFICATION_HOOKS);
+    if inhibit.is_some_and(|x| !x.bind(cx).is_nil()) {
+        return Ok(());
+    }
+    let functions = env.vars.get(hook).map(|x| x.bind(cx)).unwrap_or_default();
+    if functions.is_nil() {
+        return Ok(());
     }
-    Ok(())
+    root!(functions, cx);
+    // changes made by the hooks don't run them again
+    env.varbind(sym::INHIBIT_MODIFICATION_HOOKS, TRUE, cx);
+    let result = run_hook_functions(functions, args, env, cx);
+    env.unbind(1, cx);
+    result
 }
 
-// TODO: this should not throw and error. Buffer will always be present.
+defsym!(BEGINNING_OF_BUFFER);
+defsym!(END_OF_BUFFER);
+
 #[defun]
-pub(crate) fn goto_char(position: usize, env: &mut Rt<Env>) -> Result<()> {
-    let buffer = env.current_buffer.get_mut();
-    buffer.text.set_cursor(position);
-    Ok(())
+pub(crate) fn goto_char(position: usize, env: &mut Rt<Env>) -> usize {
+    env.current_buffer.get_mut().goto_char(position);
+    position
 }
 
-// TODO: this should not throw and error. Buffer will always be present.
 #[defun]
-pub(crate) fn point_max(env: &mut Rt<Env>) -> Result<usize> {
-    let buffer = env.current_buffer.get_mut();
-    // TODO: Handle narrowing
-    Ok(buffer.text.len_chars() + 1)
+pub(crate) fn point_max(env: &Rt<Env>) -> usize {
+    env.current_buffer.get().point_max()
 }
 
 #[defun]
-pub(crate) fn point_min() -> usize {
-    // TODO: Handle narrowing
-    1
+pub(crate) fn point_min(env: &Rt<Env>) -> usize {
+    env.current_buffer.get().point_min()
 }
 
 #[defun]
-pub(crate) fn point_marker(env: &mut Rt<Env>) -> usize {
+pub(crate) fn point_marker(env: &Rt<Env>) -> usize {
     // TODO: Implement marker objects
-    env.current_buffer.get_mut().text.cursor().chars()
+    env.current_buffer.get().point()
+}
+
+#[defun]
+pub(crate) fn delete_region(
+    start: usize,
+    end: usize,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<()> {
+    let (beg, end) = if start <= end { (start, end) } else { (end, start) };
+    let buffer = env.current_buffer.get();
+    buffer.in_range(beg)?;
+    buffer.in_range(end)?;
+    signal_before_change(beg, end, env, cx)?;
+    // the hooks may have changed the buffer, so check the region again
+    env.current_buffer.get_mut().delete(beg, end)?;
+    signal_after_change(beg, beg, end - beg, env, cx)
+}
+
+/// Replace the accessible portion of the current buffer with the accessible
+/// portion of SOURCE, a buffer or the name of one.
+///
+/// Only the text that differs is replaced, so markers, point, text
+/// properties and overlays in the unchanged text stay where they were. If
+/// finding the differences takes longer than MAX-SECS seconds or needs more
+/// than MAX-COSTS inserted and deleted characters, the whole text is replaced
+/// instead. Return t if only the differences were replaced, and nil
+/// otherwise.
+#[defun]
+fn replace_buffer_contents(
+    source: &Rto<Object>,
+    max_secs: Option<f64>,
+    max_costs: Option<usize>,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<bool> {
+    let source = resolve_buffer(source.bind(cx), cx)?;
+    if env.current_buffer == *source {
+        return Ok(true);
+    }
+    let chars = |buffer: &OpenBuffer| -> Result<Vec<char>> {
+        let (s1, s2) = buffer.slice_with_gap(buffer.point_min(), buffer.point_max())?;
+        Ok(s1.chars().chain(s2.chars()).collect())
+    };
+    let new = env.with_buffer(source, chars)??;
+    let buffer = env.current_buffer.get();
+    let (start, end) = (buffer.point_min(), buffer.point_max());
+    let old = chars(buffer)?;
+    let deadline = max_secs.map(|x| Instant::now() + Duration::from_secs_f64(x.max(0.0)));
+    let Some(hunks) = diff(&old, &new, max_costs.unwrap_or(1_000_000), deadline) else {
+        delete_region(start, end, env, cx)?;
+        env.current_buffer.get_mut().goto_char(start);
+        insert_text(&new.iter().collect::<String>(), env, cx)?;
+        return Ok(false);
+    };
+    let excursion = env.current_buffer.get_mut().save_excursion();
+    let mut result = Ok(());
+    // Replace from the end, so the positions of earlier hunks don't change
+    for hunk in hunks.into_iter().rev() {
+        let beg = start + hunk.old.start;
+        result = delete_region(beg, start + hunk.old.end, env, cx).and_then(|()| {
+            env.current_buffer.get_mut().goto_char(beg);
+            insert_text(&new[hunk.new].iter().collect::<String>(), env, cx)
+        });
+        if result.is_err() {
+            break;
+        }
+    }
+    env.current_buffer.get_mut().restore_excursion(excursion);
+    result.map(|()| true)
+}
+
+/// Return the text between START and END, which must be in the accessible
+/// portion of the buffer.
+fn substring(start: usize, end: usize, buffer: &OpenBuffer) -> Result<String> {
+    let (beg, end) = if start <= end { (start, end) } else { (end, start) };
+    let (s1, s2) = buffer.slice_with_gap(beg, end)?;
+    Ok([s1, s2].concat())
+}
+
+/// Return the contents of part of the current buffer as a string.
+///
+/// Strings do not hold text properties yet, so this is the same as
+/// `buffer-substring-no-properties`.
+#[defun]
+fn buffer_substring(start: usize, end: usize, env: &Rt<Env>) -> Result<String> {
+    substring(start, end, env.current_buffer.get())
+}
+
+#[defun]
+fn buffer_substring_no_properties(start: usize, end: usize, env: &Rt<Env>) -> Result<String> {
+    substring(start, end, env.current_buffer.get())
+}
+
+/// Return the accessible portion of the current buffer as a string.
+#[defun]
+fn buffer_string(env: &Rt<Env>) -> Result<String> {
+    let buffer = env.current_buffer.get();
+    substring(buffer.point_min(), buffer.point_max(), buffer)
+}
+
+#[defun]
+fn point(env: &Rt<Env>) -> usize {
+    env.current_buffer.get().point()
+}
+
+/// Move point `n` characters, signaling `beginning-of-buffer` or
+/// `end-of-buffer` if that would leave the buffer. Point is left at the limit
+/// when an error is signaled.
+fn move_point(n: i64, env: &mut Rt<Env>) -> Result<()> {
+    let buffer = env.current_buffer.get_mut();
+    let point = buffer.point() as i64;
+    let target = point.saturating_add(n);
+    let (min, max) = (buffer.point_min() as i64, buffer.point_max() as i64);
+    let error = if target < min {
+        sym::BEGINNING_OF_BUFFER
+    } else if target > max {
+        sym::END_OF_BUFFER
+    } else {
+        buffer.goto_char(target as usize);
+        return Ok(());
+    };
+    buffer.goto_char(target.clamp(min, max) as usize);
+    Err(EvalError::signal(error.into(), NIL, env).into())
+}
+
+#[defun]
+fn forward_char(n: Option<i64>, env: &mut Rt<Env>) -> Result<()> {
+    move_point(n.unwrap_or(1), env)
+}
+
+#[defun]
+fn backward_char(n: Option<i64>, env: &mut Rt<Env>) -> Result<()> {
+    move_point(-n.unwrap_or(1), env)
+}
+
+/// Insert the character that invoked this command N times. C is the
+/// character to insert, defaulting to `last-command-event'.
+#[defun]
+fn self_insert_command(
+    n: &Rto<Object>,
+    c: Option<char>,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<()> {
+    let n: i64 = n.bind(cx).try_into()?;
+    ensure!(n >= 0, "Negative repetition argument {n}");
+    let c = match c {
+        Some(c) => c,
+        None => match env.vars.get(sym::LAST_COMMAND_EVENT) {
+            Some(event) => event.bind(cx).try_into()?,
+            None => return Ok(()),
+        },
+    };
+    if crate::abbrev::expand_for_insert(c, env, cx)? {
+        return Ok(());
+    }
+    insert_text(&c.to_string().repeat(n as usize), env, cx)
+}
+
+/// Insert N newlines at point.
+#[defun]
+fn newline(n: Option<i64>, env: &mut Rt<Env>, cx: &mut Context) -> Result<()> {
+    let n = n.unwrap_or(1);
+    ensure!(n >= 0, "Negative repetition argument {n}");
+    insert_text(&"\n".repeat(n as usize), env, cx)
+}
+
+/// Delete `n` characters after point, or before point if `n` is negative.
+fn delete_chars(n: i64, env: &mut Rt<Env>, cx: &mut Context) -> Result<()> {
+    let point = env.current_buffer.get().point();
+    let Ok(end) = usize::try_from(point as i64 + n) else { bail!("Beginning of buffer") };
+    delete_region(point, end, env, cx)
+}
+
+/// Delete the N characters after point, or before point if N is negative.
+#[defun]
+fn delete_char(
+    n: &Rto<Object>,
+    _killflag: OptionalFlag,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<()> {
+    let n: i64 = n.bind(cx).try_into()?;
+    delete_chars(n, env, cx)
+}
+
+/// Delete the N characters before point.
+#[defun]
+fn delete_backward_char(
+    n: &Rto<Object>,
+    _killflag: OptionalFlag,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<()> {
+    let n: i64 = n.bind(cx).try_into()?;
+    delete_chars(-n, env, cx)
 }
 
 #[defun]
-fn delete_region(start: usize, end: usize, env: &mut Rt<Env>) -> Result<()> {
-    env.current_buffer.get_mut().delete(start, end)
+fn bobp(env: &Rt<Env>) -> bool {
+    let buf = env.current_buffer.get();
+    buf.point() == buf.point_min()
+}
+
+#[defun]
+fn eobp(env: &Rt<Env>) -> bool {
+    let buf = env.current_buffer.get();
+    buf.point() == buf.point_max()
 }
 
 #[defun]
 fn bolp(env: &Rt<Env>) -> bool {
     let buf = env.current_buffer.get();
     let chars = buf.text.cursor().chars();
-    chars == 0 || buf.text.char_at(chars - 1).unwrap() == '\n'
+    buf.point() == buf.point_min() || buf.text.char_at(chars - 1).unwrap() == '\n'
 }
 
 #[defun]
-fn point(env: &Rt<Env>) -> usize {
-    env.current_buffer.get().text.cursor().chars()
+fn eolp(env: &Rt<Env>) -> bool {
+    let buf = env.current_buffer.get();
+    let chars = buf.text.cursor().chars();
+    buf.point() == buf.point_max() || buf.text.char_at(chars) == Some('\n')
+}
+
+/// The position of the start of the line `n - 1` lines away from point.
+pub(crate) fn line_beginning(buffer: &OpenBuffer, n: i64) -> usize {
+    let text = &buffer.text;
+    let line = text.char_to_line(text.cursor().chars()) as i64 + n - 1;
+    let pos = if line <= 0 { 0 } else { text.line_to_char(line as usize) };
+    (pos + 1).clamp(buffer.point_min(), buffer.point_max())
+}
+
+/// The position of the end of the line `n - 1` lines away from point.
+pub(crate) fn line_end(buffer: &OpenBuffer, n: i64) -> usize {
+    let text = &buffer.text;
+    let line = text.char_to_line(text.cursor().chars()) as i64 + n - 1;
+    let pos = if line < 0 {
+        0
+    } else if line as usize >= text.len_lines() {
+        text.len_chars()
+    } else {
+        text.line_to_char(line as usize + 1) - 1
+    };
+    (pos + 1).clamp(buffer.point_min(), buffer.point_max())
 }
 
 #[defun]
-fn system_name() -> String {
-    hostname::get()
-        .expect("Failed to get hostname")
-        .into_string()
-        .expect("Failed to convert OsString to String")
+fn line_beginning_position(n: Option<i64>, env: &Rt<Env>) -> usize {
+    line_beginning(env.current_buffer.get(), n.unwrap_or(1))
+}
+
+#[defun]
+fn line_end_position(n: Option<i64>, env: &Rt<Env>) -> usize {
+    line_end(env.current_buffer.get(), n.unwrap_or(1))
+}
+
+#[defun]
+fn beginning_of_line(n: Option<i64>, env: &mut Rt<Env>) {
+    let buffer = env.current_buffer.get_mut();
+    let pos = line_beginning(buffer, n.unwrap_or(1));
+    buffer.goto_char(pos);
+}
+
+#[defun]
+fn end_of_line(n: Option<i64>, env: &mut Rt<Env>) {
+    let buffer = env.current_buffer.get_mut();
+    let pos = line_end(buffer, n.unwrap_or(1));
+    buffer.goto_char(pos);
+}
+
+/// Move to the start of the line `n` lines away from point. Returns the number
+/// of lines that could not be moved, which is negative when moving backward.
+#[defun]
+pub(crate) fn forward_line(n: Option<i64>, env: &mut Rt<Env>) -> i64 {
+    let n = n.unwrap_or(1);
+    let buffer = env.current_buffer.get_mut();
+    let (start, end) = (buffer.point_min() - 1, buffer.point_max() - 1);
+    let text = &buffer.text;
+    let point = text.cursor().chars();
+    let target = text.char_to_line(point) as i64 + n;
+    let first = text.char_to_line(start) as i64;
+    let last = text.char_to_line(end) as i64;
+    if target < first {
+        buffer.goto_char(start + 1);
+        target - first
+    } else if target > last {
+        // A partial line at the end of the buffer counts as a line moved
+        let partial = end != point && text.char_at(end - 1) != Some('\n');
+        buffer.goto_char(end + 1);
+        target - last - i64::from(partial)
+    } else {
+        let pos = text.line_to_char(target as usize);
+        buffer.goto_char(pos + 1);
+        0
+    }
+}
+
+#[defun]
+fn count_lines(
+    start: usize,
+    end: usize,
+    _ignore_invisible_lines: OptionalFlag,
+    env: &Rt<Env>,
+) -> Result<usize> {
+    let buffer = env.current_buffer.get();
+    let start = buffer.in_range(start)?;
+    let end = buffer.in_range(end)?;
+    let (start, end) = (start.min(end), start.max(end));
+    let text = &buffer.text;
+    let newlines = text.char_to_line(end) - text.char_to_line(start);
+    // A region that doesn't end in a newline has a partial last line
+    let partial = end > start && text.char_at(end - 1) != Some('\n');
+    Ok(newlines + usize::from(partial))
+}
+
+#[defun]
+fn line_number_at_pos(
+    position: Option<usize>,
+    absolute: OptionalFlag,
+    env: &Rt<Env>,
+) -> Result<usize> {
+    let buffer = env.current_buffer.get();
+    let pos = match position {
+        Some(pos) => buffer.in_range(pos)?,
+        None => buffer.text.cursor().chars(),
+    };
+    let text = &buffer.text;
+    let first = if absolute.is_some() { 0 } else { text.char_to_line(buffer.point_min() - 1) };
+    Ok(text.char_to_line(pos) - first + 1)
+}
+
+#[defun]
+fn narrow_to_region(start: usize, end: usize, env: &mut Rt<Env>) -> Result<()> {
+    env.current_buffer.get_mut().narrow(start, end)
+}
+
+#[defun]
+fn widen(env: &mut Rt<Env>) {
+    env.current_buffer.get_mut().widen();
+}
+
+#[defun]
+fn mark(_force: OptionalFlag, env: &Rt<Env>) -> Option<usize> {
+    // TODO: handle `mark-even-if-inactive' once transient mark mode exists
+    env.current_buffer.get().mark()
+}
+
+#[defun]
+fn set_mark(pos: Option<usize>, env: &mut Rt<Env>) -> Result<Option<usize>> {
+    env.current_buffer.get_mut().set_mark(pos)?;
+    Ok(pos)
+}
+
+#[defun]
+fn push_mark(
+    location: Option<usize>,
+    _nomsg: OptionalFlag,
+    _activate: OptionalFlag,
+    env: &mut Rt<Env>,
+) -> Result<()> {
+    // TODO: maintain the mark ring
+    let buffer = env.current_buffer.get_mut();
+    let location = location.unwrap_or_else(|| buffer.point());
+    buffer.set_mark(Some(location))
+}
+
+fn region(env: &Rt<Env>) -> Result<(usize, usize)> {
+    let buffer = env.current_buffer.get();
+    let Some(mark) = buffer.mark() else {
+        bail!("The mark is not set now, so there is no region")
+    };
+    let point = buffer.point();
+    Ok((point.min(mark), point.max(mark)))
+}
+
+#[defun]
+fn region_beginning(env: &Rt<Env>) -> Result<usize> {
+    Ok(region(env)?.0)
+}
+
+#[defun]
+fn region_end(env: &Rt<Env>) -> Result<usize> {
+    Ok(region(env)?.1)
+}
+
+#[defun]
+fn system_name() -> Option<String> {
+    hostname::get().ok().map(|x| x.to_string_lossy().into_owned())
+}
+
+/// Return the name under which the user logged in. If UID is given, return
+/// the login name of the user with that uid, or nil if there is none.
+/// Otherwise the name comes from `LOGNAME` or `USER` in
+/// `process-environment`.
+#[defun]
+fn user_login_name(uid: Option<i64>, env: &Rt<Env>, cx: &Context) -> Result<Option<String>> {
+    if let Some(uid) = uid {
+        return Ok(u32::try_from(uid).ok().and_then(user_name));
+    }
+    let name = match getenv_value("LOGNAME", env, cx)? {
+        Some(name) => Some(name),
+        None => getenv_value("USER", env, cx)?,
+    };
+    Ok(name.or_else(current_user_name))
+}
+
+#[cfg(unix)]
+fn user_name(uid: u32) -> Option<String> {
+    crate::dired::user_name(uid)
+}
+
+#[cfg(unix)]
+fn current_user_name() -> Option<String> {
+    user_name(unsafe { libc::getuid() })
+}
+
+#[cfg(not(unix))]
+fn user_name(_uid: u32) -> Option<String> {
+    None
+}
+
+#[cfg(not(unix))]
+fn current_user_name() -> Option<String> {
+    std::env::var("USERNAME").ok()
 }
 
 #[cfg(test)]
 mod test {
-    use crate::core::object::NIL;
     use crate::{
         buffer::{get_buffer_create, set_buffer},
         core::gc::RootSet,
+        interpreter::assert_lisp,
     };
-    use rune_core::macros::root;
 
     use super::*;
 
+    #[test]
+    fn test_user_login_name() {
+        assert_lisp("(user-login-name -1)", "nil");
+        assert_lisp(
+            r#"(let ((process-environment '("LOGNAME=rune-login" "USER=rune-user")))
+                 (user-login-name))"#,
+            r#""rune-login""#,
+        );
+        assert_lisp(
+            r#"(let ((process-environment '("LOGNAME" "USER=rune-user")))
+                 (user-login-name))"#,
+            r#""rune-user""#,
+        );
+        assert_lisp("(stringp (system-name))", "t");
+    }
+
     #[test]
     fn test_format() {
         assert_eq!(&format("%s", &[1.into()]).unwrap(), "1");
@@ -203,7 +689,208 @@ mod test {
         insert(ArgSlice::new(2), env, cx).unwrap();
 
         assert_eq!(env.current_buffer.get(), "hello world");
-        delete_region(2, 4, env).unwrap();
+        delete_region(2, 4, env, cx).unwrap();
         assert_eq!(env.current_buffer.get(), "hlo world");
     }
+
+    #[test]
+    fn test_replace_buffer_contents() {
+        assert_lisp(
+            r#"(let ((source (get-buffer-create "replace-contents-source")))
+                 (with-current-buffer source (insert "hello big world!"))
+                 (insert "hello world")
+                 (goto-char 9)
+                 (list (replace-buffer-contents source) (buffer-string) (point)))"#,
+            r#"(t "hello big world!" 13)"#,
+        );
+        assert_lisp(
+            r#"(let ((source (get-buffer-create "replace-contents-costly")))
+                 (with-current-buffer source (insert "abc"))
+                 (insert "xyz")
+                 (list (replace-buffer-contents source nil 1) (buffer-string)))"#,
+            r#"(nil "abc")"#,
+        );
+    }
+
+    #[test]
+    fn test_editing_commands() {
+        assert_lisp(
+            "(let ((last-command-event ?x)) (self-insert-command 3) (buffer-string))",
+            "\"xxx\"",
+        );
+        assert_lisp("(progn (self-insert-command 1 ?a) (newline 2) (buffer-string))", "\"a\n\n\"");
+        assert_lisp(
+            "(progn (insert \"hello\") (goto-char 3) (delete-char 2) (delete-backward-char 1) (buffer-string))",
+            "\"ho\"",
+        );
+    }
+
+    #[test]
+    fn test_point_motion() {
+        assert_lisp("(progn (insert \"hello\") (list (point) (point-min) (point-max)))", "(6 1 6)");
+        assert_lisp(
+            "(progn (insert \"hello\") (goto-char 0) (list (point) (bobp) (eobp)))",
+            "(1 t nil)",
+        );
+        assert_lisp(
+            "(progn (insert \"hello\") (goto-char 20) (list (point) (bobp) (eobp)))",
+            "(6 nil t)",
+        );
+        assert_lisp("(progn (insert \"hello\") (goto-char 1) (forward-char 2) (point))", "3");
+        assert_lisp("(progn (insert \"hello\") (backward-char) (backward-char 2) (point))", "3");
+        assert_lisp(
+            "(progn (insert \"hello\") (goto-char 3) (condition-case err (forward-char 10) (error (list err (point)))))",
+            "((end-of-buffer) 6)",
+        );
+        assert_lisp(
+            "(progn (insert \"hello\") (condition-case err (backward-char 10) (error (list err (point)))))",
+            "((beginning-of-buffer) 1)",
+        );
+        assert_lisp(
+            "(progn (insert \"ab\") (goto-char 2) (insert \"x\") (list (point) (point-max)))",
+            "(3 4)",
+        );
+    }
+
+    #[test]
+    fn test_mark_and_region() {
+        assert_lisp("(progn (insert \"hello\") (mark))", "nil");
+        assert_lisp(
+            "(progn (insert \"hello\") (push-mark 2) (list (mark) (region-beginning) (region-end)))",
+            "(2 2 6)",
+        );
+        assert_lisp(
+            "(progn (insert \"hello\") (push-mark) (goto-char 2) (list (region-beginning) (region-end)))",
+            "(2 6)",
+        );
+        // The mark moves with edits before it
+        assert_lisp(
+            "(progn (insert \"hello\") (set-mark 4) (goto-char 1) (insert \"xy\") (mark))",
+            "6",
+        );
+        assert_lisp("(progn (insert \"hello\") (set-mark 4) (delete-region 2 5) (mark))", "2");
+        assert_lisp("(progn (insert \"hello\") (set-mark 4) (delete-region 3 1) (mark))", "2");
+        assert_lisp("(condition-case err (region-beginning) (error 'no-mark))", "no-mark");
+    }
+
+    #[test]
+    fn test_line_motion() {
+        let text = "\"foo\\nbar\\n\\nbaz\"";
+        let check = |body: &str, expect: &str| {
+            assert_lisp(&format!("(progn (insert {text}) {body})"), expect);
+        };
+        check("(goto-char 6) (list (line-beginning-position) (line-end-position))", "(5 8)");
+        check(
+            "(goto-char 6) (list (line-beginning-position 2) (line-end-position 0))",
+            "(9 4)",
+        );
+        check(
+            "(goto-char 6) (list (line-beginning-position -5) (line-end-position 10))",
+            "(1 13)",
+        );
+        check("(goto-char 6) (beginning-of-line) (point)", "5");
+        check("(goto-char 6) (end-of-line) (point)", "8");
+        check("(goto-char 6) (list (forward-line) (point))", "(0 9)");
+        check("(goto-char 6) (list (forward-line 0) (point))", "(0 5)");
+        check("(goto-char 6) (list (forward-line -1) (point))", "(0 1)");
+        check("(goto-char 6) (list (forward-line -3) (point))", "(-2 1)");
+        // The partial last line counts as a line moved
+        check("(goto-char 6) (list (forward-line 3) (point))", "(0 13)");
+        check("(goto-char 6) (list (forward-line 5) (point))", "(2 13)");
+        check("(list (forward-line 1) (point))", "(1 13)");
+        check(
+            "(list (count-lines 1 13) (count-lines 5 9) (count-lines 5 10) (count-lines 3 3))",
+            "(4 1 2 0)",
+        );
+        check(
+            "(list (line-number-at-pos) (line-number-at-pos 1) (line-number-at-pos 9))",
+            "(4 1 3)",
+        );
+        assert_lisp("(progn (insert \"foo\\n\") (list (forward-line 1) (point)))", "(1 5)");
+    }
+
+    #[test]
+    fn test_narrowing() {
+        let text = "\"foo\\nbar\\nbaz\"";
+        let check = |body: &str, expect: &str| {
+            assert_lisp(&format!("(progn (insert {text}) {body})"), expect);
+        };
+        check("(narrow-to-region 9 5) (list (point) (point-min) (point-max))", "(9 5 9)");
+        check("(narrow-to-region 5 9) (goto-char 1) (list (point) (bobp) (bolp))", "(5 t t)");
+        check(
+            "(narrow-to-region 6 8) (list (line-beginning-position) (line-end-position))",
+            "(6 8)",
+        );
+        check("(narrow-to-region 5 9) (goto-char 5) (list (forward-line 2) (point))", "(1 9)");
+        check(
+            "(narrow-to-region 5 12) (list (line-number-at-pos) (line-number-at-pos nil t))",
+            "(2 3)",
+        );
+        check("(narrow-to-region 5 9) (goto-char 6) (insert \"xx\") (point-max)", "11");
+        check("(narrow-to-region 5 9) (widen) (list (point-min) (point-max))", "(1 12)");
+        check(
+            "(narrow-to-region 5 9) (condition-case err (delete-region 1 3) (error 'out-of-range))",
+            "out-of-range",
+        );
+        check(
+            "(narrow-to-region 5 8) (goto-char 5) (re-search-forward \"\\\\`bar\\\\'\")",
+            "8",
+        );
+        check("(narrow-to-region 5 9) (goto-char 5) (search-forward \"baz\" nil t)", "nil");
+        check(
+            "(list (save-restriction (narrow-to-region 5 9) (point-max)) (point-min) (point-max))",
+            "(9 1 12)",
+        );
+        check(
+            "(condition-case err (save-restriction (narrow-to-region 5 9) (signal 'error nil)) (error nil)) (point-max)",
+            "12",
+        );
+    }
+
+    #[test]
+    fn test_buffer_substring() {
+        let text = "\"foo\\nbar\\nbaz\"";
+        let check = |body: &str, expect: &str| {
+            assert_lisp(&format!("(progn (insert {text}) {body})"), expect);
+        };
+        check("(buffer-substring 2 6)", "\"oo\nb\"");
+        check("(buffer-substring-no-properties 7 3)", "\"o\nba\"");
+        check("(buffer-string)", "\"foo\nbar\nbaz\"");
+        check("(narrow-to-region 5 8) (buffer-string)", "\"bar\"");
+        check(
+            "(narrow-to-region 5 8) (condition-case err (buffer-substring 1 6) (error 'out-of-range))",
+            "out-of-range",
+        );
+    }
+
+    #[test]
+    fn test_change_hooks() {
+        assert_lisp(
+            r#"(progn
+                 (setq changes nil)
+                 (setq before-change-functions
+                       (list #'(lambda (beg end) (setq changes (cons (list 'before beg end) changes)))))
+                 (setq after-change-functions
+                       (list #'(lambda (beg end len) (setq changes (cons (list 'after beg end len) changes)))))
+                 (insert "hello")
+                 (delete-region 4 2)
+                 (reverse changes))"#,
+            "((before 1 1) (after 1 6 0) (before 2 4) (after 2 2 2))",
+        );
+        // changes made by a hook don't run the hooks again
+        assert_lisp(
+            r#"(progn
+                 (setq after-change-functions (list #'(lambda (&rest _) (insert "!"))))
+                 (insert "a")
+                 (point))"#,
+            "3",
+        );
+        assert_lisp(
+            r#"(let ((inhibit-modification-hooks t))
+                 (setq after-change-functions (list #'(lambda (&rest _) (signal 'error nil))))
+                 (insert "a")
+                 (point))"#,
+            "2",
+        );
+    }
 }
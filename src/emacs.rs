@@ -1,13 +1,97 @@
 //! The Emacs environment and runtime.
+use crate::core::env::{Env, sym};
+use crate::core::gc::{Context, Rt, Rto};
+use crate::core::object::{NIL, Object, ObjectType};
+use crate::eval::{EvalError, exit_code, run_hook_functions};
+use anyhow::Result;
+use rune_core::macros::root;
 use rune_macros::defun;
+use std::io::Write;
 
+defvar!(KILL_EMACS_HOOK);
+defvar!(CONFIRM_KILL_EMACS);
+
+/// Exit rune. If ARG is an integer it is used as the exit code, otherwise the
+/// exit code is 0.
+///
+/// `kill-emacs-hook` is run first. If `confirm-kill-emacs` is nil, so there
+/// was no chance to save changes interactively, buffers with changes that
+/// have not been auto-saved are auto-saved.
+///
+/// This is the only way lisp exits the process. It unwinds to the top level,
+/// which exits once any terminal state has been restored.
 #[defun]
-fn kill_emacs() {}
+pub(crate) fn kill_emacs(
+    arg: Option<&Rto<Object>>,
+    _restart: Option<&Rto<Object>>,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<()> {
+    let code = match arg.map(|x| x.bind(cx).untag()) {
+        Some(ObjectType::Int(code)) => code as u8,
+        _ => 0,
+    };
+    let code = shut_down(env, cx).unwrap_or(code);
+    Err(EvalError::exit(code).into())
+}
+
+/// Get ready for rune to exit by running `kill-emacs-hook` and saving
+/// buffers, as described in `kill-emacs`. Errors are reported but don't stop
+/// the exit. Returns the exit code if the hook called `kill-emacs` itself.
+pub(crate) fn shut_down(env: &mut Rt<Env>, cx: &mut Context) -> Option<u8> {
+    // Only run the hook once, even if a function on it calls kill-emacs
+    let hook = env.vars.get(sym::KILL_EMACS_HOOK).map(|x| x.bind(cx)).unwrap_or_default();
+    root!(hook, cx);
+    env.vars.insert(sym::KILL_EMACS_HOOK, NIL);
+    let mut code = None;
+    if let Err(e) = run_hook_functions(hook, &[], env, cx) {
+        code = exit_code(&e);
+        if code.is_none() {
+            eprintln!("Error in kill-emacs-hook: {e}");
+        }
+    }
+    let confirm = env.vars.get(sym::CONFIRM_KILL_EMACS).is_some_and(|x| !x.bind(cx).is_nil());
+    if !confirm && let Err(e) = crate::fileio::do_auto_save(None, None, env, cx) {
+        eprintln!("Error: {e}");
+    }
+    let _ = std::io::stdout().flush();
+    let _ = std::io::stderr().flush();
+    code
+}
 
 defvar!(EMACS_VERSION, "27.1");
 defvar!(SYSTEM_TYPE, "darwin");
 defvar!(DUMP_MODE);
 defvar!(COMMAND_LINE_ARGS, list![""]);
+defvar!(COMMAND_LINE_ARGS_LEFT);
 defvar!(DEFAULT_DIRECTORY, "");
 defvar_bool!(NONINTERACTIVE, true);
 defvar!(AFTER_INIT_TIME);
+defvar!(USER_EMACS_DIRECTORY, "~/.config/rune/");
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::env::intern;
+    use crate::core::gc::RootSet;
+    use crate::core::object::TRUE;
+
+    #[test]
+    fn test_kill_emacs() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        sym::init_symbols();
+        root!(env, new(Env), cx);
+        // condition-case can't stop the exit
+        let form = "(progn (setq confirm-kill-emacs t)
+                      (setq kill-emacs-hook (list #'(lambda () (setq hook-ran t))))
+                      (condition-case nil (kill-emacs 3) (error nil)))";
+        let obj = crate::reader::read(form, cx).unwrap().0;
+        root!(obj, cx);
+        let err = crate::interpreter::eval(obj, None, env, cx).unwrap_err();
+        assert_eq!(exit_code(&err), Some(3));
+        let hook_ran = env.vars.get(intern("hook-ran", cx)).map(|x| x.bind(cx));
+        assert_eq!(hook_ran, Some(TRUE));
+        assert_eq!(env.vars.get(sym::KILL_EMACS_HOOK).map(|x| x.bind(cx)), Some(NIL));
+    }
+}
@@ -10,7 +10,7 @@ use crate::core::{
     gc::Context,
     object::{FunctionType, Gc, Object},
 };
-use crate::data::LispError;
+use crate::data::{LispError, get, remove_pos_from_symbol};
 use crate::fns::{assq, eq};
 use crate::rooted_iter;
 use anyhow::{Result, anyhow, bail, ensure};
@@ -30,6 +30,8 @@ pub(crate) struct EvalError {
 pub(crate) enum ErrorType {
     Throw(u32),
     Signal(u32),
+    /// `kill-emacs` was called with this exit code
+    Exit(u8),
     Err(anyhow::Error),
 }
 
@@ -41,6 +43,7 @@ impl Display for EvalError {
             ErrorType::Err(e) => writeln!(f, "{e}")?,
             ErrorType::Throw(_) => writeln!(f, "No catch for throw")?,
             ErrorType::Signal(_) => writeln!(f, "Signal")?,
+            ErrorType::Exit(code) => writeln!(f, "Exit with code {code}")?,
         }
         Ok(())
     }
@@ -62,6 +65,12 @@ impl EvalError {
         Self { backtrace: Vec::new(), error: ErrorType::Throw(env.set_exception(tag, data)) }
     }
 
+    /// Unwind to the top level and exit with `code`. Only `kill-emacs` should
+    /// create this.
+    pub(crate) fn exit(code: u8) -> Self {
+        Self { backtrace: Vec::new(), error: ErrorType::Exit(code) }
+    }
+
     pub(crate) fn new(error: impl Into<Self>) -> Self {
         error.into()
     }
@@ -78,6 +87,32 @@ impl EvalError {
         self
     }
 
+    /// Whether this is a `quit` signal. Quits are not errors, so handlers
+    /// for `error` don't catch them.
+    pub(crate) fn is_quit(&self, env: &Rt<Env>, cx: &Context) -> bool {
+        match self.error {
+            ErrorType::Signal(id) => {
+                env.get_exception(id).is_some_and(|(symbol, _)| symbol.bind(cx) == sym::QUIT)
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether this was signaled with `condition`, or with an error symbol
+    /// that has `condition` in its `error-conditions`.
+    pub(crate) fn has_condition(&self, condition: Symbol, env: &Rt<Env>, cx: &Context) -> bool {
+        let ErrorType::Signal(id) = self.error else { return false };
+        let Some((symbol, _)) = env.get_exception(id) else { return false };
+        let Ok(symbol) = Symbol::try_from(symbol.bind(cx)) else { return false };
+        if symbol == condition {
+            return true;
+        }
+        let conditions = get(symbol, sym::ERROR_CONDITIONS, env, cx);
+        conditions
+            .as_list()
+            .is_ok_and(|mut x| x.any(|x| x.is_ok_and(|x| x == condition)))
+    }
+
     pub(crate) fn print_backtrace(&self) {
         println!("BEGIN_BACKTRACE");
         for (i, x) in self.backtrace.iter().enumerate() {
@@ -89,7 +124,19 @@ impl EvalError {
 
 impl From<anyhow::Error> for EvalError {
     fn from(e: anyhow::Error) -> Self {
-        Self::new_error(e)
+        match e.downcast::<EvalError>() {
+            Ok(e) => e,
+            Err(e) => Self::new_error(e),
+        }
+    }
+}
+
+/// The exit code if `err` is unwinding from a call to `kill-emacs`. Code that
+/// handles errors itself should let these through.
+pub(crate) fn exit_code(err: &anyhow::Error) -> Option<u8> {
+    match err.downcast_ref::<EvalError>()?.error {
+        ErrorType::Exit(code) => Some(code),
+        _ => None,
     }
 }
 
@@ -179,21 +226,8 @@ fn run_hooks<'ob>(hooks: ArgSlice, env: &mut Rt<Env>, cx: &'ob mut Context) -> R
             ObjectType::Symbol(sym) => {
                 if let Some(val) = env.vars.get(sym) {
                     let val = val.bind(cx);
-                    match val.untag() {
-                        ObjectType::Cons(hook_list) => {
-                            rooted_iter!(hooks, hook_list, cx);
-                            while let Some(hook) = hooks.next()? {
-                                let func = hook.try_as()?;
-                                call!(func; env, cx)?;
-                            }
-                        }
-                        ObjectType::NIL => {}
-                        _ => {
-                            let func: Function = val.try_into()?;
-                            root!(func, cx);
-                            call!(func; env, cx)?;
-                        }
-                    }
+                    root!(val, cx);
+                    run_hook_functions(val, &[], env, cx)?;
                 }
             }
             x => bail!(TypeError::new(Type::Symbol, x)),
@@ -202,6 +236,49 @@ fn run_hooks<'ob>(hooks: ArgSlice, env: &mut Rt<Env>, cx: &'ob mut Context) -> R
     Ok(NIL)
 }
 
+/// Call the functions in the builtin hook variable `hook` with no arguments.
+pub(crate) fn run_hook(hook: Symbol<'static>, env: &mut Rt<Env>, cx: &mut Context) -> Result<()> {
+    if let Some(val) = env.vars.get(hook) {
+        let val = val.bind(cx);
+        root!(val, cx);
+        run_hook_functions(val, &[], env, cx)?;
+    }
+    Ok(())
+}
+
+/// Call each function in `functions`, the value of a hook variable, with
+/// `args`.
+pub(crate) fn run_hook_functions(
+    functions: &Rto<Object>,
+    args: &[i64],
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<()> {
+    let call_hook = |func: &Rto<Function>, env: &mut Rt<Env>, cx: &mut Context| {
+        let frame = &mut CallFrame::new(env);
+        for arg in args {
+            frame.push_arg(*arg);
+        }
+        func.call(frame, None, cx).map(|_| ())
+    };
+    let val = functions.bind(cx);
+    match val.untag() {
+        ObjectType::Cons(hook_list) => {
+            rooted_iter!(hooks, hook_list, cx);
+            while let Some(hook) = hooks.next()? {
+                call_hook(hook.try_as()?, env, cx)?;
+            }
+        }
+        ObjectType::NIL => {}
+        _ => {
+            let func: Function = val.try_into()?;
+            root!(func, cx);
+            call_hook(func, env, cx)?;
+        }
+    }
+    Ok(())
+}
+
 #[defun]
 fn run_hook_with_args<'ob>(
     hook: &Rto<Object>,
@@ -261,7 +338,7 @@ pub(crate) fn autoload_do_load<'ob>(
         "autoload arguments are not yet implemented"
     );
     root!(file, cx);
-    crate::lread::load(file, None, None, cx, env)?;
+    crate::lread::load(file, None, None, None, None, cx, env)?;
     match funname {
         Some(func) => match func.untag(cx).func(cx) {
             Some(x) => Ok(x.into()),
@@ -284,7 +361,7 @@ fn autoload<'ob>(
         Ok(sym::NIL)
     } else {
         let autoload = list![sym::AUTOLOAD, file, docstring, interactive, load_type; cx];
-        crate::data::fset(function, autoload)
+        crate::data::fset(function, autoload, cx)
     }
 }
 
@@ -296,13 +373,20 @@ pub(crate) fn macroexpand<'ob>(
     env: &mut Rt<Env>,
 ) -> Result<Object<'ob>> {
     let ObjectType::Cons(cons) = form.untag(cx) else { return Ok(form.bind(cx)) };
-    let ObjectType::Symbol(sym) = cons.car().untag() else { return Ok(form.bind(cx)) };
-    // shadow the macro based on ENVIRONMENT
+    // the byte compiler reads forms with symbols with position
+    let ObjectType::Symbol(sym) = remove_pos_from_symbol(cons.car()).untag() else {
+        return Ok(form.bind(cx));
+    };
+    // shadow the macro based on ENVIRONMENT. An entry with a nil definition
+    // means the name is not a macro there.
     let func = match environment {
-        Some(env) => match assq(sym.into(), env.bind(cx).try_into()?)?.untag() {
-            ObjectType::Cons(cons) => Some(cons.cdr().try_into()?),
-            _ => get_macro_func(sym, cx),
-        },
+        Some(environment) => {
+            match assq(sym.into(), environment.bind(cx).try_into()?, env, cx)?.untag() {
+                ObjectType::Cons(cons) if cons.cdr().is_nil() => None,
+                ObjectType::Cons(cons) => Some(cons.cdr().try_into()?),
+                _ => get_macro_func(sym, cx),
+            }
+        }
         _ => get_macro_func(sym, cx),
     };
     let Some(macro_func) = func else { return Ok(form.bind(cx)) };
@@ -316,7 +400,7 @@ pub(crate) fn macroexpand<'ob>(
     let new_form = macro_func.call(&mut frame, Some(&name), cx)?;
     drop(frame);
     root!(new_form, cx); // polonius
-    if eq(new_form.bind(cx), form.bind(cx)) {
+    if eq(new_form.bind(cx), form.bind(cx), env, cx) {
         Ok(form.bind(cx))
     } else {
         // recursively expand the macro's
@@ -401,7 +485,7 @@ fn set_default_toplevel_value<'ob>(
     value: Object,
     env: &'ob mut Rt<Env>,
 ) -> Result<Object<'ob>> {
-    env.set_var(symbol, value)?;
+    env.set_default(symbol, value)?;
     Ok(NIL)
 }
 
@@ -411,8 +495,7 @@ fn set_default<'ob>(
     value: Object<'ob>,
     env: &'ob mut Rt<Env>,
 ) -> Result<Object<'ob>> {
-    // TODO: implement buffer local variables
-    env.set_var(symbol, value)?;
+    env.set_default(symbol, value)?;
     Ok(value)
 }
 
@@ -442,7 +525,9 @@ impl Rto<Function<'_>> {
                     .map_err(|e| e.add_trace(name, frame.arg_slice()))
             }
             FunctionType::Symbol(sym) => {
-                let Some(func) = sym.follow_indirect(cx) else { bail_err!("Void Function: {sym}") };
+                let Some(func) = sym.indirect_function(cx)? else {
+                    bail_err!("Void Function: {sym}")
+                };
                 if let Ok((sym::AUTOLOAD, _)) = func.as_cons_pair() {
                     // TODO: inifinite loop if autoload does not resolve
                     root!(sym, cx);
@@ -472,6 +557,7 @@ pub(crate) fn add_trace(err: anyhow::Error, name: &str, args: &[Rto<Object>]) ->
 }
 
 defsym!(FUNCTION);
+defsym!(ERROR_CONDITIONS);
 defsym!(QUOTE);
 defsym!(MACRO);
 defsym!(UNQUOTE, ",");
@@ -485,6 +571,9 @@ defsym!(CONDITION_CASE);
 defsym!(UNWIND_PROTECT);
 defsym!(SAVE_EXCURSION);
 defsym!(SAVE_CURRENT_BUFFER);
+defsym!(SAVE_RESTRICTION);
+defsym!(SAVE_MATCH_DATA);
+defsym!(WITH_CURRENT_BUFFER);
 defsym!(WHILE);
 defsym!(INLINE);
 defsym!(PROGN);
@@ -507,3 +596,21 @@ defsym!(VOID_VARIABLE);
 
 defvar!(DEBUG_ON_ERROR, false);
 defvar!(INTERNAL_MAKE_INTERPRETED_CLOSURE_FUNCTION);
+
+#[cfg(test)]
+mod test {
+    use crate::interpreter::assert_lisp;
+
+    #[test]
+    fn test_macroexpand_environment() {
+        assert_lisp(
+            "(progn (defalias 'em-test (cons 'macro #'(lambda (x) (list 'bar x))))
+                    (list (macroexpand '(em-test a)) (macroexpand '(em-test a) '((em-test)))))",
+            "((bar a) (em-test a))",
+        );
+        assert_lisp(
+            "(macroexpand '(foo a) (list (cons 'foo #'(lambda (x) (list 'bar x)))))",
+            "(bar a)",
+        );
+    }
+}
@@ -0,0 +1,218 @@
+//! Functions defined in other crates through `rune-extend`.
+//!
+//! Extension functions can't use the `#[defun]` machinery, so each one is
+//! wrapped in a [`SubrFn`] that converts its arguments to owned
+//! [`Value`]s before calling it. Objects with no `Value` representation are
+//! passed as handles that index into a table kept for the duration of the
+//! call. No garbage collection happens during the call, so the objects in the
+//! table don't move.
+use crate::core::{
+    env::{Env, INTERNED_SYMBOLS, intern},
+    gc::{Context, Rt},
+    object::{FnArgs, NIL, Object, ObjectType, SubrFn, TRUE},
+};
+use crate::data::LispError;
+use crate::eval::EvalError;
+use crate::fns::slice_into_list;
+use anyhow::{Result, anyhow, bail, ensure};
+use rune_extend::{Handle, Subr, Value};
+use std::sync::{
+    LazyLock,
+    atomic::{AtomicUsize, Ordering},
+};
+
+// Extension crates are linked into rune by referencing them here, e.g.
+// `use my_extension as _;`.
+
+/// Lists and vectors nested deeper than this are passed as handles, which
+/// also stops cyclic vectors from being converted forever.
+const MAX_DEPTH: usize = 64;
+
+static EXTENSION_SUBRS: LazyLock<Vec<SubrFn>> = LazyLock::new(|| {
+    rune_extend::SUBRS
+        .iter()
+        .map(|subr| SubrFn {
+            subr: unreachable_subr,
+            args: FnArgs {
+                required: subr.required,
+                optional: subr.optional,
+                rest: subr.rest,
+                advice: false,
+            },
+            name: subr.name,
+            extension: Some(subr),
+        })
+        .collect()
+});
+
+/// Extension functions are dispatched on their definition in
+/// [`SubrFn::call`], so the wrapper is never called.
+fn unreachable_subr<'ob>(_: usize, _: &mut Rt<Env>, _: &'ob mut Context) -> Result<Object<'ob>> {
+    bail!("extension functions are called through their definition")
+}
+
+/// Used to tell handles from different calls apart.
+static CALL_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Define all the extension functions linked into rune.
+pub(crate) fn init_extensions(cx: &Context) {
+    for subr in EXTENSION_SUBRS.iter() {
+        let symbol = intern(subr.name, cx);
+        let map = INTERNED_SYMBOLS.lock().unwrap();
+        if let Err(e) = map.set_func(symbol, subr.into()) {
+            eprintln!("Failed to define extension function {}: {e}", subr.name);
+        }
+    }
+}
+
+/// The objects referred to by handles in a single call.
+struct Handles<'ob> {
+    call: usize,
+    objects: Vec<Object<'ob>>,
+}
+
+impl<'ob> Handles<'ob> {
+    fn make_value(&mut self, obj: Object<'ob>, depth: usize) -> Value {
+        match obj.untag() {
+            ObjectType::NIL => Value::Nil,
+            ObjectType::TRUE => Value::True,
+            ObjectType::Int(x) => Value::Int(x),
+            ObjectType::Float(x) => Value::Float(**x),
+            ObjectType::String(x) => Value::String((**x).to_owned()),
+            ObjectType::Symbol(x) => Value::Symbol(x.name().to_owned()),
+            ObjectType::Cons(cons) if depth < MAX_DEPTH => {
+                match cons.elements().collect::<Result<Vec<_>, _>>() {
+                    Ok(elements) => Value::List(
+                        elements.into_iter().map(|x| self.make_value(x, depth + 1)).collect(),
+                    ),
+                    // dotted and circular lists
+                    Err(_) => self.handle(obj),
+                }
+            }
+            ObjectType::Vec(vec) if depth < MAX_DEPTH => {
+                Value::Vector(vec.iter().map(|x| self.make_value(x.get(), depth + 1)).collect())
+            }
+            _ => self.handle(obj),
+        }
+    }
+
+    fn handle(&mut self, obj: Object<'ob>) -> Value {
+        self.objects.push(obj);
+        Value::Object(Handle::new(self.call, self.objects.len() - 1))
+    }
+
+    fn to_object(&self, value: &Value, cx: &'ob Context) -> Result<Object<'ob>> {
+        let obj = match value {
+            Value::Nil => NIL,
+            Value::True => TRUE,
+            Value::Int(x) => cx.add(*x),
+            Value::Float(x) => cx.add(*x),
+            Value::String(x) => cx.add(x.as_str()),
+            Value::Symbol(x) => intern(x, cx).into(),
+            Value::List(x) => slice_into_list(&self.to_objects(x, cx)?, None, cx),
+            Value::Vector(x) => cx.add(self.to_objects(x, cx)?),
+            Value::Object(handle) => {
+                ensure!(
+                    handle.call() == self.call,
+                    "Handle used outside of the call it was passed to"
+                );
+                let obj = self.objects.get(handle.index());
+                *obj.ok_or_else(|| anyhow!("Invalid handle: {handle:?}"))?
+            }
+        };
+        Ok(obj)
+    }
+
+    fn to_objects(&self, values: &[Value], cx: &'ob Context) -> Result<Vec<Object<'ob>>> {
+        values.iter().map(|x| self.to_object(x, cx)).collect()
+    }
+}
+
+/// Call the extension function `subr` with the arguments on the stack.
+pub(crate) fn call<'ob>(
+    subr: &'static Subr,
+    arg_cnt: usize,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<Object<'ob>> {
+    let cx: &'ob Context = cx;
+    let (required, optional) = (subr.required as usize, subr.optional as usize);
+    if arg_cnt < required || (!subr.rest && required + optional < arg_cnt) {
+        let upper = subr.required + subr.optional;
+        let expected = if arg_cnt > upper as usize { upper } else { subr.required };
+        return Err(LispError::arg_cnt(subr.name, expected, arg_cnt as u16, cx).into());
+    }
+    let call = CALL_COUNT.fetch_add(1, Ordering::Relaxed);
+    let mut handles = Handles { call, objects: Vec::new() };
+    let args: Vec<Value> =
+        env.stack[..arg_cnt].iter().map(|x| handles.make_value(x.bind(cx), 0)).collect();
+    match (subr.func)(&args) {
+        Ok(value) => handles.to_object(&value, cx),
+        Err(rune_extend::Error::Message(message)) => Err(anyhow!(message)),
+        Err(rune_extend::Error::Signal { symbol, data }) => {
+            let data = slice_into_list(&handles.to_objects(&data, cx)?, None, cx);
+            Err(EvalError::signal(intern(&symbol, cx).into(), data, env).into())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::{env::sym, gc::RootSet};
+    use crate::interpreter::eval;
+    use rune_core::macros::{rebind, root};
+    use rune_extend::arg;
+
+    fn sum(args: &[Value]) -> rune_extend::Result<Value> {
+        let total = (0..args.len()).map(|i| arg::<i64>(args, i)).sum::<rune_extend::Result<i64>>();
+        Ok(Value::Int(total?))
+    }
+
+    fn identity(args: &[Value]) -> rune_extend::Result<Value> {
+        Ok(args[0].clone())
+    }
+
+    rune_extend::register!(TEST_SUM: Subr::new("rune-extend--test-sum", sum).args(1, 0).rest());
+    rune_extend::register!(
+        TEST_IDENTITY: Subr::new("rune-extend--test-identity", identity).args(1, 0)
+    );
+
+    fn assert_extension(compare: &str, expect: &str) {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        sym::init_symbols();
+        init_extensions(cx);
+        root!(env, new(Env), cx);
+        let compare = {
+            let obj = crate::reader::read(compare, cx).unwrap().0;
+            root!(obj, cx);
+            rebind!(eval(obj, None, env, cx).unwrap())
+        };
+        let expect = crate::reader::read(expect, cx).unwrap().0;
+        assert_eq!(compare, expect);
+    }
+
+    #[test]
+    fn test_extension_call() {
+        assert_extension("(rune-extend--test-sum 1 2 3)", "6");
+        assert_extension(
+            "(condition-case err (rune-extend--test-sum 1 'a) (error (car err)))",
+            "wrong-type-argument",
+        );
+        assert_extension(
+            "(condition-case err (rune-extend--test-sum) (error (car err)))",
+            "wrong-number-of-arguments",
+        );
+        assert_extension(
+            r#"(rune-extend--test-identity '(1 "a" [b 2.5] :key nil t))"#,
+            r#"(1 "a" [b 2.5] :key nil t)"#,
+        );
+        assert_extension(
+            "(let ((x (cons 1 2))) (list (eq (rune-extend--test-identity x) x)
+                                         (eq (rune-extend--test-identity (current-buffer)) (current-buffer))))",
+            "(t t)",
+        );
+        assert_extension("(subrp (symbol-function 'rune-extend--test-sum))", "t");
+    }
+}
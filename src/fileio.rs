@@ -1,17 +1,34 @@
 //! File I/O.
+use crate::buffer::BUFFERS;
+use crate::coding::{CodingSystem, Decoded, coding_system_from_var, set_last_coding_system};
 use crate::core::{
     cons::Cons,
     env::{Env, sym},
     error::{Type, TypeError},
-    gc::{Context, Rt},
-    object::{Number, Object, ObjectType, OptionalFlag},
+    gc::{Context, Rt, Rto},
+    object::{
+        Gc, LispBuffer, Number, Object, ObjectType, OpenBuffer, OptionalFlag, VisitedModtime,
+        raw_byte_char,
+    },
 };
-use anyhow::{Result, bail, ensure};
+use crate::editfns::{signal_after_change, signal_before_change};
+use crate::eval::EvalError;
+use crate::library::filename;
+use crate::minibuf::yes_or_no_p;
+use crate::timefns::{lisp_to_system_time, system_time_to_lisp};
+use anyhow::{Context as _, Result, bail, ensure};
+use rune_core::macros::list;
 use rune_macros::defun;
-use std::path::{Component, MAIN_SEPARATOR, Path};
+use std::io::{Seek, SeekFrom, Write};
+use std::path::{MAIN_SEPARATOR, Path};
 
 defvar!(FILE_NAME_HANDLER_ALIST);
 
+/// Return the user's home directory, used to expand `~`.
+fn home_directory() -> String {
+    std::env::var("HOME").unwrap_or_else(|_| "/".to_owned())
+}
+
 #[defun]
 pub(crate) fn expand_file_name(
     name: &str,
@@ -19,23 +36,27 @@ pub(crate) fn expand_file_name(
     env: &Rt<Env>,
     cx: &Context,
 ) -> Result<String> {
-    // TODO: this needs to be tested to ensure it has the same behavior as GNU
-    // Emacs. It doesn't do any normalization for one thing.
-    if Path::new(name).is_absolute() {
-        Ok(name.to_owned())
-    } else if let Some(dir) = default_directory {
-        let path = Path::new(dir);
-        Ok(path.join(name).to_string_lossy().to_string())
+    let home = home_directory();
+    let dir: &str = match default_directory {
+        Some(dir) => dir,
+        None => match env.vars.get(sym::DEFAULT_DIRECTORY).map(|x| x.untag(cx)) {
+            Some(ObjectType::String(dir)) => dir.as_ref(),
+            _ => "/",
+        },
+    };
+    // default-directory may itself be relative
+    let dir = if filename::is_absolute(dir) {
+        dir.to_owned()
     } else {
-        let dir = env.vars.get(sym::DEFAULT_DIRECTORY).unwrap();
-        match dir.untag(cx) {
-            ObjectType::String(dir) => {
-                let path = Path::new(dir.as_ref());
-                Ok(path.join(name).to_string_lossy().to_string())
-            }
-            _ => unreachable!("`default-directory' should be a string"),
-        }
-    }
+        let cwd = std::env::current_dir()?;
+        filename::concat(&cwd.to_string_lossy(), [dir])
+    };
+    Ok(filename::expand(name, &dir, &home))
+}
+
+#[defun]
+fn abbreviate_file_name(filename: &str) -> String {
+    filename::abbreviate(filename, &home_directory())
 }
 
 #[defun]
@@ -47,11 +68,7 @@ fn car_less_than_car(a: &Cons, b: &Cons) -> Result<bool> {
 
 #[defun]
 fn file_name_as_directory(filename: &str) -> String {
-    if filename.ends_with(MAIN_SEPARATOR) {
-        filename.to_owned()
-    } else {
-        format!("{filename}{MAIN_SEPARATOR}")
-    }
+    filename::as_directory(filename)
 }
 
 #[defun]
@@ -62,67 +79,50 @@ fn file_directory_p(filename: &str) -> bool {
 /// Return dirname sans final path separator, unless the string consists entirely of separators.
 #[defun]
 fn directory_file_name(dirname: &str) -> &str {
-    let path = Path::new(dirname);
-    let mut path_components = path.components();
-    if path_components.clone().next().is_none() {
-        return "";
-    }
-
-    if path_components.all(|c| c == Component::RootDir || c == Component::Normal("".as_ref())) {
-        return "/";
-    }
-
-    dirname.strip_suffix(MAIN_SEPARATOR).unwrap_or(dirname)
+    filename::directory_file_name(dirname)
 }
 
 /// Returns true if the path is absolute
 #[defun]
 fn file_name_absolute_p(filename: &str) -> bool {
-    let path = Path::new(filename);
-    // TODO: GNU Emacs has special handling for ~user directories, where the user exists.
-    //   so as per example in the manual, ~rms/foo is considered absolute if user `rms` exists
-    //   doing this here would require "knowing" the list of valid users and looking for ~path
-    //   components.
-    path.is_absolute()
+    // TODO: GNU Emacs only treats ~user as absolute if `user` exists.
+    filename::is_absolute(filename)
 }
 
 /// Returns the directory part of `filename`, as a directory name, or nil if filename does not include a directory part.
 #[defun]
-fn file_name_directory(filename: &str) -> Option<String> {
-    // TODO: GNU Emacs docs stipulate that "On MS-DOS [ed: presumably windows,
-    // too] it can also end in a colon."
-    if !filename.contains(MAIN_SEPARATOR) {
-        return None;
-    }
-
-    if filename.ends_with(MAIN_SEPARATOR) {
-        return Some(filename.into());
-    }
-
-    let path = Path::new(filename);
-    let parent = path.parent()?;
-
-    // Special case for root path so we don't end up returning '//'
-    if parent.parent().is_none() {
-        return Some(format!("{MAIN_SEPARATOR}"));
-    }
-    let parent_path = parent.to_str()?;
-    Some(format!("{parent_path}{MAIN_SEPARATOR}"))
+fn file_name_directory(filename: &str) -> Option<&str> {
+    filename::directory(filename)
 }
 
 /// Returns the non-directory part of `filename`
 #[defun]
 fn file_name_nondirectory(filename: &str) -> &str {
-    if filename.ends_with(MAIN_SEPARATOR) {
-        return "";
+    filename::nondirectory(filename)
+}
+
+/// Return the extension of `filename`. If `period` is non-nil, the
+/// extension includes the leading period, and an empty string is returned
+/// instead of nil when there is no extension.
+#[defun]
+fn file_name_extension(filename: &str, period: OptionalFlag) -> Option<String> {
+    let ext = filename::extension(filename);
+    match (ext, period) {
+        (Some(ext), Some(())) => Some(format!(".{ext}")),
+        (Some(ext), None) => Some(ext.to_owned()),
+        (None, Some(())) => Some(String::new()),
+        (None, None) => None,
     }
+}
 
-    let path = Path::new(filename);
-    let Some(file_name) = path.file_name() else {
-        return "";
-    };
+#[defun]
+fn file_name_sans_extension(filename: &str) -> &str {
+    filename::sans_extension(filename)
+}
 
-    file_name.to_str().unwrap()
+#[defun]
+fn file_name_base(filename: &str) -> &str {
+    filename::base(filename)
 }
 
 /// Return non-nil if NAME ends with a directory separator character.
@@ -184,67 +184,555 @@ fn case_insensitive(_filename: &str) -> bool {
     false
 }
 
-#[test]
-#[cfg(not(miri))]
-fn test_case_sensative_call() {
-    let _ = file_name_case_insensitive_p("/");
+defsym!(EXCL);
+defsym!(FILE_ALREADY_EXISTS);
+// Non-nil means `write-region` does not wait for the data to reach the disk.
+defvar_bool!(WRITE_REGION_INHIBIT_FSYNC, false);
+
+/// Where `write-region` puts the text in the file.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum WriteMode {
+    Replace,
+    Append,
+    /// Overwrite the file starting at this byte offset
+    At(u64),
+}
+
+/// Signal `file-already-exists` for `filename`.
+fn file_already_exists(filename: &str, env: &mut Rt<Env>, cx: &Context) -> anyhow::Error {
+    let data = list![cx.add("File already exists"), cx.add(filename); cx];
+    EvalError::signal(sym::FILE_ALREADY_EXISTS.into(), data, env).into()
 }
 
+/// Write the text between START and END in the current buffer to FILENAME.
+/// If START is nil, the whole buffer is written, ignoring any narrowing.
+///
+/// If APPEND is an integer, the text is written at that byte offset in the
+/// file, and if it is otherwise non-nil the text is added to the end of the
+/// file.
+///
+/// If VISIT is t, the buffer visits FILENAME afterwards and is marked as
+/// unmodified. If VISIT is a string, the buffer visits that file instead.
+/// Writing a visited file replaces it atomically, by writing a temporary file
+/// next to it and renaming that over the original.
+///
+/// LOCKNAME is the file that is locked while writing, which defaults to the
+/// file that is visited. If MUSTBENEW is `excl`, signal `file-already-exists`
+/// if FILENAME exists, and if it is otherwise non-nil ask before overwriting
+/// it. Unless `write-region-inhibit-fsync` is non-nil, the data is flushed to
+/// the disk before returning.
 #[defun]
 #[expect(clippy::too_many_arguments)]
 fn write_region(
-    start: i64,
-    end: i64,
+    start: Object,
+    end: Object,
     filename: &str,
-    append: OptionalFlag,
-    visit: OptionalFlag,
-    lockname: OptionalFlag,
-    mustbenew: OptionalFlag,
-    env: &Rt<Env>,
+    append: Option<Object>,
+    visit: Option<Object>,
+    lockname: Option<&str>,
+    mustbenew: Option<Object>,
+    env: &mut Rt<Env>,
+    cx: &Context,
 ) -> Result<()> {
-    use std::io::Write;
-    ensure!(append.is_none(), "append not implemented");
-    ensure!(visit.is_none(), "visit not implemented");
-    ensure!(lockname.is_none(), "lockname not implemented");
-    ensure!(mustbenew.is_none(), "mustbenew not implemented");
-    // Open filename for writing
-    let mut file = std::fs::OpenOptions::new()
-        .write(true)
-        .create(true)
-        .truncate(true)
-        .open(filename)
-        .unwrap();
+    let start: Option<usize> = (!start.is_nil()).then(|| start.try_into()).transpose()?;
+    let end: Option<usize> = (!end.is_nil()).then(|| end.try_into()).transpose()?;
+    let mode = match append.map(|x| x.untag()) {
+        None => WriteMode::Replace,
+        Some(ObjectType::Int(offset)) => WriteMode::At(u64::try_from(offset)?),
+        Some(_) => WriteMode::Append,
+    };
+    let exclusive = mustbenew.is_some_and(|x| x == sym::EXCL);
+    let inhibit_fsync = env.vars.get(sym::WRITE_REGION_INHIBIT_FSYNC);
+    let fsync = inhibit_fsync.is_none_or(|x| x.bind(cx).is_nil());
+    let coding = match coding_system_from_var(sym::CODING_SYSTEM_FOR_WRITE, env, cx)? {
+        Some(coding) => coding,
+        None => coding_system_from_var(sym::BUFFER_FILE_CODING_SYSTEM, env, cx)?
+            .unwrap_or(CodingSystem::UTF_8),
+    };
+    let filename = expand_file_name(filename, None, env, cx)?;
+    let visit = match visit.map(|x| x.untag()) {
+        None => None,
+        Some(ObjectType::String(name)) => Some(expand_file_name(name, None, env, cx)?),
+        Some(_) => Some(filename.clone()),
+    };
+    if mustbenew.is_some() && !exclusive && Path::new(&filename).exists() {
+        let prompt = format!("File {filename} exists; overwrite anyway? ");
+        if yes_or_no_p(&prompt, env, cx)?.is_nil() {
+            return Err(file_already_exists(&filename, env, cx));
+        }
+    }
+    let lockname = match lockname {
+        Some(name) => Some(expand_file_name(name, None, env, cx)?),
+        None => visit.clone(),
+    };
+    if let Some(lockname) = &lockname {
+        crate::filelock::lock(lockname, env, cx)?;
+    }
+    backup_buffer(&filename, env, cx)?;
     let b = env.current_buffer.get();
-    let (s1, s2) = b.slice_with_gap(start as usize, end as usize)?;
-    write!(file, "{s1}")?;
-    write!(file, "{s2}")?;
+    let (s1, s2) = match start {
+        Some(start) => b.slice_with_gap(start, end.unwrap_or(b.point_max()))?,
+        None => b.text.slice(0..b.text.len_chars()),
+    };
+    let mut contents = coding.encode(s1);
+    contents.extend(coding.encode(s2));
+    let path = Path::new(&filename);
+    let written = if visit.is_some() && mode == WriteMode::Replace && !exclusive {
+        write_atomically(path, &contents, fsync)
+    } else {
+        write_file(path, &contents, mode, exclusive, fsync)
+    };
+    if let Some(lockname) = &lockname {
+        crate::filelock::unlock(lockname)?;
+    }
+    match written {
+        Err(e) if exclusive && e.kind() == std::io::ErrorKind::AlreadyExists => {
+            return Err(file_already_exists(&filename, env, cx));
+        }
+        written => written.with_context(|| format!("Opening output file: {filename}"))?,
+    }
+    set_last_coding_system(coding, env, cx);
+    if let Some(visit) = visit {
+        env.set_var(sym::BUFFER_FILE_NAME, cx.add(visit))?;
+        let buffer = env.current_buffer.get_mut();
+        buffer.visited_modtime = VisitedModtime::of_file(path);
+        buffer.set_modified_p(false);
+    }
+    Ok(())
+}
+
+/// Write `contents` to `path` in place. If `exclusive` is true the file must
+/// not exist yet.
+fn write_file(
+    path: &Path,
+    contents: &[u8],
+    mode: WriteMode,
+    exclusive: bool,
+    fsync: bool,
+) -> std::io::Result<()> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true);
+    match mode {
+        WriteMode::Replace => options.truncate(true),
+        WriteMode::Append => options.append(true),
+        WriteMode::At(_) => &mut options,
+    };
+    if exclusive {
+        options.create_new(true);
+    }
+    let mut file = options.open(path)?;
+    if let WriteMode::At(offset) = mode {
+        file.seek(SeekFrom::Start(offset))?;
+    }
+    file.write_all(contents)?;
+    if fsync {
+        file.sync_all()?;
+    }
+    Ok(())
+}
+
+/// Replace the contents of `path` by writing a temporary file in the same
+/// directory and renaming it over `path`, so that the file is never seen half
+/// written. The permissions of an existing file are kept.
+fn write_atomically(path: &Path, contents: &[u8], fsync: bool) -> std::io::Result<()> {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let temp = path.with_file_name(format!(".{name}.tmp{}", std::process::id()));
+    let result = write_file(&temp, contents, WriteMode::Replace, false, fsync).and_then(|()| {
+        if let Ok(metadata) = std::fs::metadata(path) {
+            std::fs::set_permissions(&temp, metadata.permissions())?;
+        }
+        std::fs::rename(&temp, path)
+    });
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temp);
+    }
+    result
+}
+
+defsym!(MAKE_BACKUP_FILES);
+
+/// Copy `filename` to its backup file the first time the buffer visiting it
+/// writes to it, if `make-backup-files` is non-nil.
+fn backup_buffer(filename: &str, env: &mut Rt<Env>, cx: &Context) -> Result<()> {
+    let enabled = env.vars.get(sym::MAKE_BACKUP_FILES).is_some_and(|x| !x.bind(cx).is_nil());
+    if !enabled || env.current_buffer.get().backed_up {
+        return Ok(());
+    }
+    let visited = match env.vars.get(sym::BUFFER_FILE_NAME).map(|x| x.untag(cx)) {
+        Some(ObjectType::String(name)) => expand_file_name(name, None, env, cx)?,
+        _ => return Ok(()),
+    };
+    let filename = expand_file_name(filename, None, env, cx)?;
+    if filename != visited {
+        return Ok(());
+    }
+    // A file that doesn't exist yet has nothing to back up
+    if Path::new(&filename).exists() {
+        let backup = format!("{filename}~");
+        std::fs::copy(&filename, &backup).with_context(|| format!("Backing up to {backup}"))?;
+    }
+    env.current_buffer.get_mut().backed_up = true;
+    Ok(())
+}
+
+// TODO: buffer local
+defvar!(BUFFER_AUTO_SAVE_FILE_NAME);
+
+/// Auto-save all buffers that have changed since they were last auto-saved.
+/// If CURRENT-ONLY is non-nil, only auto-save the current buffer.
+///
+/// The current buffer is auto-saved to `buffer-auto-save-file-name`, or not
+/// at all if it is nil. Other buffers keep the file name they had when they
+/// were last current.
+#[defun]
+pub(crate) fn do_auto_save(
+    _no_message: OptionalFlag,
+    current_only: OptionalFlag,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<()> {
+    let name = match env.vars.get(sym::BUFFER_AUTO_SAVE_FILE_NAME).map(|x| x.untag(cx)) {
+        Some(ObjectType::String(name)) => Some(expand_file_name(name, None, env, cx)?),
+        _ => None,
+    };
+    env.current_buffer.get_mut().auto_save_file_name = name;
+    let buffers: Vec<_> = if current_only.is_some() {
+        vec![env.current_buffer.get().lisp_buffer(cx)]
+    } else {
+        BUFFERS.lock().unwrap().values().map(|x| cx.bind(*x)).collect()
+    };
+    for buffer in buffers {
+        // Buffers that are in use by another thread are left to that thread
+        if let Some(result) = env.try_with_buffer_mut(buffer, auto_save_buffer) {
+            result?;
+        }
+    }
+    Ok(())
+}
+
+fn auto_save_buffer(buffer: &mut OpenBuffer) -> Result<()> {
+    let Some(name) = &buffer.auto_save_file_name else { return Ok(()) };
+    if buffer.needs_auto_save() {
+        std::fs::write(name, buffer.whole_text()).with_context(|| format!("Auto-saving {name}"))?;
+        buffer.set_auto_saved();
+    }
+    Ok(())
+}
+
+/// Mark the current buffer as auto-saved with its current text.
+#[defun]
+fn set_buffer_auto_saved(env: &mut Rt<Env>) {
+    env.current_buffer.get_mut().set_auto_saved();
+}
+
+/// Return t if the current buffer has been auto-saved since it was last
+/// saved.
+#[defun]
+fn recent_auto_save_p(env: &Rt<Env>) -> bool {
+    env.current_buffer.get().recent_auto_save_p()
+}
+
+#[defun]
+fn insert_file_contents<'ob>(
+    filename: &Rto<Object>,
+    visit: OptionalFlag,
+    beg: Option<usize>,
+    end: Option<usize>,
+    replace: OptionalFlag,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<Object<'ob>> {
+    let filename = expand_file_name(filename.bind(cx).try_into()?, None, env, cx)?;
+    if visit.is_some() {
+        ensure!(beg.is_none() && end.is_none(), "Attempt to visit less than an entire file");
+        env.set_var(sym::BUFFER_FILE_NAME, cx.add(filename.as_str()))?;
+        env.current_buffer.get_mut().visited_modtime = VisitedModtime::of_file(&filename);
+    }
+    let bytes = std::fs::read(&filename).with_context(|| format!("Opening input file: {filename}"));
+    // A buffer visiting a file that doesn't exist yet is still unmodified
+    if visit.is_some() && bytes.is_err() {
+        env.current_buffer.get_mut().set_modified_p(false);
+    }
+    let bytes = bytes?;
+    let end = end.unwrap_or(bytes.len()).min(bytes.len());
+    let beg = beg.unwrap_or(0).min(end);
+    let coding = coding_system_from_var(sym::CODING_SYSTEM_FOR_READ, env, cx)?
+        .unwrap_or(CodingSystem::UTF_8);
+    let text = match coding.decode(&bytes[beg..end]) {
+        Decoded::Text(text) => text,
+        Decoded::Bytes(bytes) => bytes.into_iter().map(raw_byte_char).collect(),
+    };
+    set_last_coding_system(coding, env, cx);
+    let chars = text.chars().count();
+    if replace.is_some() {
+        let buffer = env.current_buffer.get();
+        let (start, end) = (buffer.point_min(), buffer.point_max());
+        signal_before_change(start, end, env, cx)?;
+        env.current_buffer.get_mut().delete(start, end)?;
+        signal_after_change(start, start, end - start, env, cx)?;
+    }
+    let point = env.current_buffer.get().point();
+    signal_before_change(point, point, env, cx)?;
+    let buffer = env.current_buffer.get_mut();
+    // point is left before the inserted text
+    let point = buffer.point();
+    buffer.insert_str(&text);
+    buffer.goto_char(point);
+    signal_after_change(point, point + chars, 0, env, cx)?;
+    if visit.is_some() {
+        env.current_buffer.get_mut().set_modified_p(false);
+    }
+    Ok(list![cx.add(filename), chars; cx])
+}
+
+/// Return the modification time of the file visited by the current buffer,
+/// as it was when the buffer last read or wrote it. Return 0 if the time is
+/// not known, and -1 if the file did not exist.
+#[defun]
+fn visited_file_modtime<'ob>(env: &Rt<Env>, cx: &'ob Context) -> Object<'ob> {
+    match env.current_buffer.get().visited_modtime {
+        VisitedModtime::Unknown => 0.into(),
+        VisitedModtime::Nonexistent => (-1).into(),
+        VisitedModtime::Time(time) => system_time_to_lisp(time, cx),
+    }
+}
+
+/// Update the recorded modification time of the visited file. If TIME-FLAG
+/// is nil, the current modification time of the file is used. Otherwise it
+/// is a value as returned by `visited-file-modtime`.
+#[defun]
+fn set_visited_file_modtime(
+    time_flag: Option<Object>,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<()> {
+    let modtime = match time_flag {
+        None => match env.vars.get(sym::BUFFER_FILE_NAME).map(|x| x.untag(cx)) {
+            Some(ObjectType::String(name)) => {
+                VisitedModtime::of_file(expand_file_name(name, None, env, cx)?)
+            }
+            _ => return Ok(()),
+        },
+        Some(time) => match time.untag() {
+            ObjectType::Int(0) => VisitedModtime::Unknown,
+            ObjectType::Int(-1) => VisitedModtime::Nonexistent,
+            _ => VisitedModtime::Time(lisp_to_system_time(time)?),
+        },
+    };
+    env.current_buffer.get_mut().visited_modtime = modtime;
     Ok(())
 }
 
+/// Forget the recorded modification time of the visited file, so that the
+/// next save doesn't check it.
+#[defun]
+fn clear_visited_file_modtime(env: &mut Rt<Env>) {
+    env.current_buffer.get_mut().visited_modtime = VisitedModtime::Unknown;
+}
+
+/// Return t if the file visited by BUF has not changed since BUF last read or
+/// wrote it. Also return t if BUF is not visiting a file or the time is not
+/// known. BUF defaults to the current buffer.
+#[defun]
+fn verify_visited_file_modtime(
+    buf: Option<Gc<&LispBuffer>>,
+    env: &Rt<Env>,
+    cx: &Context,
+) -> Result<bool> {
+    let buffer = match buf {
+        Some(buffer) => buffer.untag(),
+        None => env.current_buffer.get().lisp_buffer(cx),
+    };
+    let visited = env.buffer_local_value(sym::BUFFER_FILE_NAME, buffer, cx);
+    let ObjectType::String(name) = visited.untag() else { return Ok(true) };
+    let name = expand_file_name(name, None, env, cx)?;
+    let modtime = env.with_buffer(buffer, |b| b.visited_modtime)?;
+    Ok(modtime == VisitedModtime::Unknown || modtime == VisitedModtime::of_file(name))
+}
+
+#[defun]
+fn make_directory_internal(directory: &str) -> Result<()> {
+    std::fs::create_dir(directory).with_context(|| format!("Creating directory: {directory}"))
+}
+
+/// Create `dir`. If `parents` is non-nil, also create any missing parent
+/// directories. Returns non-nil if `dir` already existed as a directory and
+/// `parents` was non-nil.
+#[defun]
+fn make_directory(dir: &str, parents: OptionalFlag) -> Result<bool> {
+    let path = Path::new(dir);
+    if parents.is_some() {
+        let existed = path.is_dir();
+        std::fs::create_dir_all(path).with_context(|| format!("Creating directory: {dir}"))?;
+        Ok(existed)
+    } else {
+        make_directory_internal(dir)?;
+        Ok(false)
+    }
+}
+
+#[defun]
+fn delete_directory(directory: &str, recursive: OptionalFlag, _trash: OptionalFlag) -> Result<()> {
+    let path = Path::new(directory);
+    let result = if recursive.is_some() {
+        std::fs::remove_dir_all(path)
+    } else {
+        std::fs::remove_dir(path)
+    };
+    result.with_context(|| format!("Removing directory: {directory}"))
+}
+
 /// Concatenate components to directory, inserting path separators as required.
 #[defun]
 fn file_name_concat(directory: &str, rest_components: &[Object]) -> Result<String> {
-    let mut path = String::from(directory);
-
+    let mut components = Vec::with_capacity(rest_components.len());
     // All components must be stringp...
     for r_c in rest_components {
-        let ObjectType::String(s) = r_c.untag() else {
-            bail!(TypeError::new(Type::String, r_c));
-        };
-
-        // Append separator before adding the new element, but only if the
-        // existing path isn't already terminated with a "/"
-        if !path.ends_with(MAIN_SEPARATOR) {
-            path.push(MAIN_SEPARATOR)
+        match r_c.untag() {
+            ObjectType::String(s) => components.push(s.as_ref()),
+            ObjectType::NIL => {}
+            _ => bail!(TypeError::new(Type::String, r_c)),
         }
-
-        path.push_str(s.as_ref());
     }
-
-    Ok(path)
+    Ok(filename::concat(directory, components))
 }
 
 // TODO: file-relative-name -- requires knowing the current buffer's default directory
 // TODO: file-name-sans-versions
 // TODO: find-file-name-handler: https://www.gnu.org/software/emacs/manual/html_node/elisp/Magic-File-Names.html
 //   required by file-name-extension  & file-name-sans-extension library & file-relative-name functions (among others)
+
+/// A scratch directory for tests that is removed when dropped, so it is
+/// cleaned up even when an assertion fails.
+#[cfg(test)]
+pub(crate) struct TempDir(std::path::PathBuf);
+
+#[cfg(test)]
+impl TempDir {
+    pub(crate) fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("rune-test-{name}-{}", std::process::id()));
+        std::fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::interpreter::assert_lisp;
+
+    #[test]
+    #[cfg(not(miri))]
+    fn test_case_sensative_call() {
+        let _ = file_name_case_insensitive_p("/");
+    }
+
+    #[test]
+    #[cfg(not(miri))]
+    fn test_auto_save_and_backup() {
+        let dir = TempDir::new("auto-save");
+        let auto_save = dir.path().join("#file#");
+        let file = dir.path().join("file");
+        std::fs::write(&file, "old").unwrap();
+        let (auto_save, file) = (auto_save.to_str().unwrap(), file.to_str().unwrap());
+        assert_lisp(
+            &format!(
+                r#"(progn
+                     (setq buffer-auto-save-file-name "{auto_save}")
+                     (setq buffer-file-name "{file}")
+                     (setq make-backup-files t)
+                     (insert "new")
+                     (let ((before (recent-auto-save-p)))
+                       (do-auto-save nil t)
+                       (write-region 1 4 "{file}")
+                       (insert " text")
+                       (write-region 1 9 "{file}")
+                       (list before (recent-auto-save-p))))"#
+            ),
+            "(nil t)",
+        );
+        assert_eq!(std::fs::read_to_string(auto_save).unwrap(), "new");
+        assert_eq!(std::fs::read_to_string(file).unwrap(), "new text");
+        assert_eq!(std::fs::read_to_string(format!("{file}~")).unwrap(), "old");
+    }
+
+    #[test]
+    #[cfg(not(miri))]
+    fn test_write_region_modes() {
+        let dir = TempDir::new("write-region");
+        let file = dir.path().join("file");
+        let file = file.to_str().unwrap();
+        assert_lisp(
+            &format!(
+                r#"(progn
+                     (setq write-region-inhibit-fsync t)
+                     (insert "abc")
+                     (write-region nil nil "{file}" nil nil nil 'excl)
+                     (write-region 1 3 "{file}" t)
+                     (write-region 3 4 "{file}" 1)
+                     (condition-case err
+                         (write-region nil nil "{file}" nil nil nil 'excl)
+                       (error (car err))))"#
+            ),
+            "file-already-exists",
+        );
+        assert_eq!(std::fs::read_to_string(file).unwrap(), "accab");
+    }
+
+    #[test]
+    #[cfg(not(miri))]
+    fn test_visit_file() {
+        let dir = TempDir::new("visit");
+        let file = dir.path().join("file");
+        std::fs::write(&file, "hello").unwrap();
+        let file = file.to_str().unwrap();
+        assert_lisp(
+            &format!(
+                r#"(progn
+                     (set-buffer (get-buffer-create "rune-visit-test"))
+                     (insert-file-contents "{file}" t)
+                     (list buffer-file-name (buffer-modified-p) (verify-visited-file-modtime)
+                           (consp (visited-file-modtime))
+                           (eq (get-file-buffer "{file}") (current-buffer))
+                           (save-current-buffer
+                             (set-buffer (get-buffer-create "rune-visit-other"))
+                             (list buffer-file-name (buffer-file-name (get-buffer "rune-visit-test"))))
+                           (progn (insert "new ") (buffer-modified-p))
+                           (progn (write-region nil nil "{file}" nil t) (buffer-modified-p))
+                           (verify-visited-file-modtime)
+                           (progn (set-visited-file-modtime 0) (visited-file-modtime))))"#
+            ),
+            &format!(r#"("{file}" nil t t t (nil "{file}") t nil t 0)"#),
+        );
+        assert_eq!(std::fs::read_to_string(file).unwrap(), "new hello");
+        // the temporary file was renamed over the original
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    #[cfg(not(miri))]
+    fn test_make_delete_directory() {
+        let dir = TempDir::new("mkdir");
+        let base = dir.path().join("a");
+        let nested = base.join("b");
+        let nested = nested.to_str().unwrap();
+        assert!(make_directory(nested, None).is_err());
+        assert!(!make_directory(nested, Some(())).unwrap());
+        assert!(make_directory(nested, Some(())).unwrap());
+        let base = base.to_str().unwrap();
+        assert!(delete_directory(base, None, None).is_err());
+        delete_directory(base, Some(()), None).unwrap();
+        assert!(!Path::new(base).exists());
+    }
+}
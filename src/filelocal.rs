@@ -0,0 +1,637 @@
+//! File-local and directory-local variables.
+//!
+//! A file can set variables for the buffer visiting it on its first line,
+//! between `-*-` markers, or in a `Local Variables:` block near its end. A
+//! `.dir-locals.el` file sets variables for the files in its directory and the
+//! directories below it. Each variable is only set if its value is safe (see
+//! `safe-local-variable-p`), unless `enable-local-variables` is `:all`. The
+//! values are local to the buffer.
+use crate::{
+    core::{
+        cons::Cons,
+        env::{Env, intern, sym},
+        gc::{Context, Rt, Rto, Slot},
+        object::{Function, NIL, Object, ObjectType, OptionalFlag, Symbol, TRUE},
+    },
+    data::get,
+    eval::run_hook,
+    fileio::expand_file_name,
+    fns::{equal, slice_into_list},
+    library::filename,
+    reader,
+};
+use anyhow::{Result, bail};
+use rune_core::macros::{call, list, root};
+use rune_macros::defun;
+use std::path::Path;
+
+defvar!(ENABLE_LOCAL_VARIABLES, true);
+defvar!(ENABLE_LOCAL_EVAL, sym::MAYBE);
+defvar!(SAFE_LOCAL_VARIABLE_VALUES);
+defvar!(HACK_LOCAL_VARIABLES_HOOK);
+defvar!(DIR_LOCALS_FILE, ".dir-locals.el");
+// Alist of classes of directory-local variables and their variables.
+defvar!(DIR_LOCALS_CLASS_ALIST);
+// List of `(DIRECTORY CLASS MTIME)` for the directories whose variables are
+// known. MTIME is the modification time of the `.dir-locals.el` file they
+// were read from, or nil if the class was set with
+// `dir-locals-set-directory-class`.
+defvar!(DIR_LOCALS_DIRECTORY_CACHE);
+defvar_local!(DIR_LOCAL_VARIABLES_ALIST);
+defsym!(MAYBE);
+defsym!(MODE);
+defsym!(KW_ALL);
+defsym!(SAFE_LOCAL_VARIABLE);
+defsym!(RISKY_LOCAL_VARIABLE);
+defsym!(DERIVED_MODE_PARENT);
+
+/// How far from the end of a file the `Local Variables:` block is looked
+/// for.
+const LOCAL_VARIABLES_SEARCH_LIMIT: usize = 3000;
+
+/// The contents of the `-*-` line at the start of `text`, which is on the
+/// second line if the first one starts with `#!`.
+fn prop_line(text: &str) -> Option<&str> {
+    let mut lines = text.lines();
+    let first = lines.next()?;
+    let line = if first.starts_with("#!") { lines.next()? } else { first };
+    let (_, rest) = line.split_once("-*-")?;
+    let (contents, _) = rest.split_once("-*-")?;
+    Some(contents.trim())
+}
+
+/// The text of the `Local Variables:` block near the end of `text`, with the
+/// prefix and suffix that surround the first line of the block removed from
+/// every line. The block is only looked for in the last page of the text.
+fn local_variables_block(text: &str) -> Option<String> {
+    const START: &str = "local variables:";
+    let mut start = text.len().saturating_sub(LOCAL_VARIABLES_SEARCH_LIMIT);
+    while !text.is_char_boundary(start) {
+        start += 1;
+    }
+    let mut tail = &text[start..];
+    if let Some(page) = tail.rfind("\n\x0c") {
+        tail = &tail[page..];
+    }
+    let pos = tail.to_ascii_lowercase().rfind(START)?;
+    let line_start = tail[..pos].rfind('\n').map_or(0, |x| x + 1);
+    let prefix = tail[line_start..pos].trim_end();
+    let (suffix, body) = tail[pos + START.len()..].split_once('\n')?;
+    let suffix = suffix.trim();
+    let mut block = String::new();
+    for line in body.lines() {
+        let line = line.strip_prefix(prefix)?.trim_end();
+        let line = line.strip_suffix(suffix).unwrap_or(line).trim_end();
+        if line.trim().eq_ignore_ascii_case("end:") {
+            return Some(block);
+        }
+        block.push_str(line);
+        block.push('\n');
+    }
+    // The block is not terminated
+    None
+}
+
+/// Read the `NAME: VALUE` pairs in `text`, separated by `;` or newlines,
+/// into `vars` as conses.
+fn read_pairs(text: &str, vars: &mut Rt<Vec<Slot<Object>>>, cx: &Context) -> Result<()> {
+    let mut rest = text;
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_whitespace() || c == ';');
+        if rest.is_empty() {
+            return Ok(());
+        }
+        let Some((name, value)) = rest.split_once(':') else {
+            bail!("Malformed local variable: {rest}")
+        };
+        let name = name.trim();
+        let (value_obj, len) = match reader::read(value, cx) {
+            Ok(x) => x,
+            Err(e) => bail!("Malformed value for local variable {name}: {e}"),
+        };
+        vars.push(Object::from(Cons::new(intern(name, cx), value_obj, cx)));
+        rest = &value[len..];
+    }
+}
+
+/// The name of the major mode function that `mode` names, like
+/// `emacs-lisp-mode` for `emacs-lisp`.
+fn mode_function(mode: Object) -> Option<String> {
+    let name = match mode.untag() {
+        ObjectType::Symbol(mode) if mode != sym::NIL => mode.name().to_lowercase(),
+        ObjectType::String(mode) => mode.to_lowercase(),
+        _ => return None,
+    };
+    Some(format!("{name}-mode"))
+}
+
+/// Return the symbol and value of an element of a local variable alist.
+fn local_binding(binding: Object) -> Option<(Symbol, Object)> {
+    let ObjectType::Cons(cons) = binding.untag() else { return None };
+    let ObjectType::Symbol(var) = cons.car().untag() else { return None };
+    Some((var, cons.cdr()))
+}
+
+/// Whether `value` is safe for a local variable.
+enum Safety<'ob> {
+    Safe,
+    Unsafe,
+    /// It is safe if this `safe-local-variable` predicate returns non-nil
+    Ask(Function<'ob>),
+}
+
+fn safety<'ob>(var: Symbol, value: Object, env: &Rt<Env>, cx: &'ob Context) -> Safety<'ob> {
+    let safe_values = env.vars.get(sym::SAFE_LOCAL_VARIABLE_VALUES).map_or(NIL, |x| x.bind(cx));
+    if let ObjectType::Cons(safe_values) = safe_values.untag() {
+        let safe = |(safe_var, safe_value)| safe_var == var && equal(safe_value, value);
+        if safe_values.elements().flatten().filter_map(local_binding).any(safe) {
+            return Safety::Safe;
+        }
+    }
+    let predicate = get(var, sym::SAFE_LOCAL_VARIABLE, env, cx);
+    if predicate.is_nil() {
+        return Safety::Unsafe;
+    }
+    match Function::try_from(predicate) {
+        Ok(predicate) => Safety::Ask(predicate),
+        Err(_) => Safety::Unsafe,
+    }
+}
+
+/// Return non-nil if VAL is a safe value for the local variable SYM. It is
+/// safe if `(SYM . VAL)` is in `safe-local-variable-values`, or if the
+/// `safe-local-variable` property of SYM is a function that returns non-nil
+/// for VAL.
+#[defun]
+fn safe_local_variable_p(
+    sym: &Rto<Object>,
+    val: &Rto<Object>,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<bool> {
+    let sym: Symbol = sym.bind(cx).try_into()?;
+    Ok(match safety(sym, val.bind(cx), env, cx) {
+        Safety::Safe => true,
+        Safety::Unsafe => false,
+        Safety::Ask(predicate) => {
+            root!(predicate, cx);
+            !call!(predicate, val; env, cx)?.is_nil()
+        }
+    })
+}
+
+/// Return non-nil if SYM could be dangerous as a file-local variable. That is
+/// the case if it has a `risky-local-variable` property, or if its name ends
+/// in something like `-function` or `-hook` and it has no
+/// `safe-local-variable` property.
+#[defun]
+fn risky_local_variable_p(sym: Symbol, _val: Option<Object>, env: &Rt<Env>, cx: &Context) -> bool {
+    const RISKY_SUFFIXES: [&str; 12] = [
+        "-command",
+        "-function",
+        "-hook",
+        "-map",
+        "-predicate",
+        "-program",
+        "-form",
+        "-forms",
+        "-frame-alist",
+        "-map-alist",
+        "-mode-alist",
+        "-font-lock-keywords",
+    ];
+    if !get(sym, sym::RISKY_LOCAL_VARIABLE, env, cx).is_nil() {
+        return true;
+    }
+    let name = sym.name();
+    let base = name.strip_suffix('s').unwrap_or(name);
+    let risky_name = RISKY_SUFFIXES.iter().any(|x| name.ends_with(x) || base.ends_with(x));
+    risky_name && get(sym, sym::SAFE_LOCAL_VARIABLE, env, cx).is_nil()
+}
+
+/// Set the local variables in `vars`, an alist, that are allowed by
+/// `enable-local-variables` and `enable-local-eval`. An `eval` entry
+/// evaluates its value instead, and `mode` entries are skipped.
+fn apply_local_variables(
+    vars: &Rt<Vec<Slot<Object>>>,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<()> {
+    let enable = env.vars.get(sym::ENABLE_LOCAL_VARIABLES).map_or(NIL, |x| x.bind(cx));
+    if enable.is_nil() {
+        return Ok(());
+    }
+    let all = enable == sym::KW_ALL;
+    for i in 0..vars.len() {
+        let Some((var, value)) = local_binding(vars[i].bind(cx)) else { continue };
+        if var == sym::MODE || var.name() == "coding" {
+            continue;
+        }
+        if var == sym::EVAL {
+            let enable_eval = env.vars.get(sym::ENABLE_LOCAL_EVAL).map_or(NIL, |x| x.bind(cx));
+            let allowed = all
+                || enable_eval == sym::TRUE
+                || (!enable_eval.is_nil() && matches!(safety(var, value, env, cx), Safety::Safe));
+            if allowed {
+                root!(value, cx);
+                crate::interpreter::eval(value, None, env, cx)?;
+            }
+            continue;
+        }
+        let safe = all
+            || (!risky_local_variable_p(var, None, env, cx)
+                && match safety(var, value, env, cx) {
+                    Safety::Safe => true,
+                    Safety::Unsafe => false,
+                    Safety::Ask(predicate) => {
+                        root!(predicate, cx);
+                        root!(value, cx);
+                        let value = value.bind(cx);
+                        !call!(predicate, value; env, cx)?.is_nil()
+                    }
+                });
+        if safe {
+            let Some((var, value)) = local_binding(vars[i].bind(cx)) else { continue };
+            env.make_local(var);
+            env.set_var(var, value)?;
+        }
+    }
+    Ok(())
+}
+
+/// Whether the major mode `mode` is `parent` or derived from it.
+fn mode_derived_p(mode: Object, parent: Symbol, env: &Rt<Env>, cx: &Context) -> bool {
+    let mut mode = mode;
+    // Guard against cycles in the parents
+    for _ in 0..100 {
+        match mode.untag() {
+            ObjectType::Symbol(x) if x == parent => return true,
+            ObjectType::Symbol(x) if x != sym::NIL => {
+                mode = get(x, sym::DERIVED_MODE_PARENT, env, cx);
+            }
+            _ => return false,
+        }
+    }
+    false
+}
+
+/// Add the variables in the directory-local `variables` of the directory
+/// `root` that apply to `file` in the major mode `mode` to `vars`.
+fn collect_class_variables(
+    variables: Object,
+    root: &str,
+    file: &str,
+    mode: Object,
+    vars: &mut Rt<Vec<Slot<Object>>>,
+    env: &Rt<Env>,
+    cx: &Context,
+) -> Result<()> {
+    let ObjectType::Cons(variables) = variables.untag() else { return Ok(()) };
+    for entry in variables.elements() {
+        let ObjectType::Cons(entry) = entry?.untag() else { continue };
+        let alist = match entry.car().untag() {
+            ObjectType::String(subdir) => {
+                let subdir = as_directory(filename::concat(root, [subdir.as_ref()]));
+                if file.starts_with(&subdir) {
+                    collect_class_variables(entry.cdr(), &subdir, file, mode, vars, env, cx)?;
+                }
+                continue;
+            }
+            ObjectType::NIL => entry.cdr(),
+            ObjectType::Symbol(key) if mode_derived_p(mode, key, env, cx) => entry.cdr(),
+            _ => continue,
+        };
+        if let ObjectType::Cons(alist) = alist.untag() {
+            for binding in alist.elements() {
+                let binding = binding?;
+                if local_binding(binding).is_some() {
+                    vars.push(binding);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn as_directory(mut name: String) -> String {
+    if !name.ends_with('/') {
+        name.push('/');
+    }
+    name
+}
+
+fn file_mtime(path: &Path) -> Option<i64> {
+    let modified = path.metadata().ok()?.modified().ok()?;
+    let since_epoch = modified.duration_since(std::time::UNIX_EPOCH).ok()?;
+    i64::try_from(since_epoch.as_nanos()).ok()
+}
+
+/// The entry of `dir-locals-directory-cache` for `dir`.
+fn cached_directory<'ob>(dir: &str, env: &Rt<Env>, cx: &'ob Context) -> Option<&'ob Cons> {
+    let cache = env.vars.get(sym::DIR_LOCALS_DIRECTORY_CACHE)?.bind(cx);
+    let ObjectType::Cons(cache) = cache.untag() else { return None };
+    cache.elements().flatten().find_map(|entry| match entry.untag() {
+        ObjectType::Cons(entry) if entry.car() == dir => Some(entry),
+        _ => None,
+    })
+}
+
+/// Return the directory that holds the directory-local variables for FILE.
+/// That is the closest directory above FILE that has a `.dir-locals.el` file
+/// or has had a class set with `dir-locals-set-directory-class`. The
+/// directory name ends in a slash.
+#[defun]
+fn dir_locals_find_file(file: &str, env: &Rt<Env>, cx: &Context) -> Result<Option<String>> {
+    let file = expand_file_name(file, None, env, cx)?;
+    let locals_file = match env.vars.get(sym::DIR_LOCALS_FILE).map(|x| x.untag(cx)) {
+        Some(ObjectType::String(name)) => name.to_string(),
+        _ => ".dir-locals.el".to_owned(),
+    };
+    for dir in Path::new(&file).ancestors().skip(1) {
+        let dir = as_directory(dir.to_string_lossy().into_owned());
+        if cached_directory(&dir, env, cx).is_some() || Path::new(&dir).join(&locals_file).is_file()
+        {
+            return Ok(Some(dir));
+        }
+    }
+    Ok(None)
+}
+
+/// Set the directory-local variables of CLASS to VARIABLES. VARIABLES is an
+/// alist whose keys are nil for variables that apply in every mode, a major
+/// mode for variables that apply in that mode and the modes derived from it,
+/// or a subdirectory name with a list of the same form for that directory.
+#[defun]
+fn dir_locals_set_class_variables<'ob>(
+    class: Symbol<'ob>,
+    variables: Object<'ob>,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    let alist = env.vars.get(sym::DIR_LOCALS_CLASS_ALIST).map_or(NIL, |x| x.bind(cx));
+    let mut classes = vec![Cons::new(class, variables, cx).into()];
+    if let ObjectType::Cons(alist) = alist.untag() {
+        for entry in alist.elements() {
+            let entry = entry?;
+            if local_binding(entry).is_none_or(|x| x.0 != class) {
+                classes.push(entry);
+            }
+        }
+    }
+    env.set_var(sym::DIR_LOCALS_CLASS_ALIST, slice_into_list(&classes, None, cx))?;
+    Ok(variables)
+}
+
+/// Make the files in DIRECTORY use the directory-local variables of CLASS.
+/// MTIME is the modification time of the file the variables were read from,
+/// which is used to notice when it changes.
+#[defun]
+fn dir_locals_set_directory_class<'ob>(
+    directory: &str,
+    class: Symbol<'ob>,
+    mtime: Option<Object<'ob>>,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    let dir = as_directory(expand_file_name(directory, None, env, cx)?);
+    let cache = env.vars.get(sym::DIR_LOCALS_DIRECTORY_CACHE).map_or(NIL, |x| x.bind(cx));
+    let entry = list![cx.add(dir.as_str()), class, mtime.unwrap_or_default(); cx];
+    let mut entries = vec![entry];
+    if let ObjectType::Cons(cache) = cache.untag() {
+        for elem in cache.elements() {
+            let elem = elem?;
+            let same_dir = matches!(elem.untag(), ObjectType::Cons(x) if x.car() == dir.as_str());
+            if !same_dir {
+                entries.push(elem);
+            }
+        }
+    }
+    env.set_var(sym::DIR_LOCALS_DIRECTORY_CACHE, slice_into_list(&entries, None, cx))?;
+    Ok(class.into())
+}
+
+/// The directory-local variables of the class of `dir`, reading them from
+/// its `.dir-locals.el` file if they are not cached or the file has changed.
+fn dir_class_variables<'ob>(dir: &str, env: &mut Rt<Env>, cx: &'ob Context) -> Result<Object<'ob>> {
+    let locals_file = match env.vars.get(sym::DIR_LOCALS_FILE).map(|x| x.untag(cx)) {
+        Some(ObjectType::String(name)) => name.to_string(),
+        _ => ".dir-locals.el".to_owned(),
+    };
+    let path = Path::new(dir).join(locals_file);
+    let mtime = file_mtime(&path);
+    let cached = cached_directory(dir, env, cx).and_then(|entry| {
+        let mut rest = entry.elements().skip(1).flatten();
+        let (class, cached_mtime) = (rest.next()?, rest.next().unwrap_or_default());
+        // A class that was not read from a file is always up to date
+        let fresh = match cached_mtime.untag() {
+            ObjectType::NIL => true,
+            ObjectType::Int(x) => Some(x) == mtime,
+            _ => false,
+        };
+        fresh.then_some(class)
+    });
+    let class = match cached {
+        Some(class) => class,
+        None => {
+            let Some(mtime) = mtime else { return Ok(NIL) };
+            let text = std::fs::read_to_string(&path)?;
+            let variables = match reader::read(&text, cx) {
+                Ok((variables, _)) => variables,
+                Err(e) => bail!("Error reading {}: {e}", path.display()),
+            };
+            let class = intern(dir, cx);
+            dir_locals_set_class_variables(class, variables, env, cx)?;
+            dir_locals_set_directory_class(dir, class, Some(cx.add(mtime)), env, cx)?;
+            class.into()
+        }
+    };
+    let alist = env.vars.get(sym::DIR_LOCALS_CLASS_ALIST).map_or(NIL, |x| x.bind(cx));
+    let ObjectType::Cons(alist) = alist.untag() else { return Ok(NIL) };
+    Ok(alist
+        .elements()
+        .flatten()
+        .filter_map(local_binding)
+        .find(|x| Object::from(x.0) == class)
+        .map_or(NIL, |x| x.1))
+}
+
+/// Add the directory-local variables for the current buffer to `vars`.
+fn collect_dir_locals(
+    vars: &mut Rt<Vec<Slot<Object>>>,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<()> {
+    let file = match env.vars.get(sym::BUFFER_FILE_NAME).map(|x| x.untag(cx)) {
+        Some(ObjectType::String(name)) => expand_file_name(name, None, env, cx)?,
+        // Buffers that don't visit a file use the variables of their default
+        // directory
+        _ => filename::concat(&expand_file_name(".", None, env, cx)?, ["x"]),
+    };
+    let Some(dir) = dir_locals_find_file(&file, env, cx)? else { return Ok(()) };
+    let variables = dir_class_variables(&dir, env, cx)?;
+    let mode = env.vars.get(sym::MAJOR_MODE).map_or(NIL, |x| x.bind(cx));
+    collect_class_variables(variables, &dir, &file, mode, vars, env, cx)
+}
+
+/// Find the directory-local variables for the current buffer and store them
+/// in `dir-local-variables-alist`, without setting them. The variables are
+/// read from `.dir-locals.el` files, which are cached until they change.
+#[defun]
+fn hack_dir_local_variables(env: &mut Rt<Env>, cx: &mut Context) -> Result<()> {
+    root!(vars, new(Vec), cx);
+    collect_dir_locals(vars, env, cx)?;
+    let vars: Vec<Object> = vars.iter().map(|x| x.bind(cx)).collect();
+    let alist = slice_into_list(&vars, None, cx);
+    env.set_var(sym::DIR_LOCAL_VARIABLES_ALIST, alist)
+}
+
+/// Parse and set the file-local variables of the current buffer.
+///
+/// The variables come from the `-*-` line at the start of the buffer, the
+/// `Local Variables:` block near its end, and the directory-local variables
+/// of the visited file, which the file-local ones override. A `mode`
+/// variable calls that major mode function first. Only the variables allowed
+/// by `enable-local-variables` are set, and `eval` forms are only evaluated
+/// if `enable-local-eval` allows them. `hack-local-variables-hook` is run
+/// afterwards.
+///
+/// If HANDLE-MODE is t, only return the major mode function named by the
+/// file, or nil if there is none, without setting anything. If it is any
+/// other non-nil value the `mode` variable is ignored. If INHIBIT-LOCALS is
+/// non-nil only the mode is handled.
+#[defun]
+pub(crate) fn hack_local_variables<'ob>(
+    handle_mode: Option<&Rto<Object>>,
+    inhibit_locals: OptionalFlag,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<Object<'ob>> {
+    let text = env.current_buffer.get().whole_text();
+    let mut mode_name = None;
+    root!(file_vars, new(Vec), cx);
+    match prop_line(&text) {
+        Some(contents) if contents.contains(':') => read_pairs(contents, file_vars, cx)?,
+        Some(contents) if !contents.is_empty() => mode_name = Some(contents.to_owned()),
+        _ => {}
+    }
+    if let Some(block) = local_variables_block(&text) {
+        read_pairs(&block, file_vars, cx)?;
+    }
+    let mode = match mode_name {
+        Some(name) => mode_function(cx.add(name)),
+        None => file_vars
+            .iter()
+            .filter_map(|x| local_binding(x.bind(cx)))
+            .find(|x| x.0 == sym::MODE)
+            .and_then(|x| mode_function(x.1)),
+    };
+    match handle_mode.map(|x| x.bind(cx)) {
+        Some(x) if x == TRUE => return Ok(mode.map_or(NIL, |x| intern(&x, cx).into())),
+        Some(_) => {}
+        None => {
+            let mode = mode.map(|x| intern(&x, cx)).filter(|x| x.has_func());
+            if let Some(mode) = mode {
+                let mode: Function = Object::from(mode).try_into()?;
+                root!(mode, cx);
+                call!(mode; env, cx)?;
+            }
+        }
+    }
+    if inhibit_locals.is_some() {
+        return Ok(NIL);
+    }
+    root!(dir_vars, new(Vec), cx);
+    collect_dir_locals(dir_vars, env, cx)?;
+    apply_local_variables(dir_vars, env, cx)?;
+    apply_local_variables(file_vars, env, cx)?;
+    run_hook(sym::HACK_LOCAL_VARIABLES_HOOK, env, cx)?;
+    Ok(NIL)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::interpreter::assert_lisp;
+
+    #[test]
+    fn test_prop_line() {
+        assert_eq!(prop_line("-*- lisp -*-\n"), Some("lisp"));
+        assert_eq!(prop_line("#!/bin/sh\n# -*- mode: sh; x: 1 -*-\n"), Some("mode: sh; x: 1"));
+        assert_eq!(prop_line("no props\n-*- lisp -*-"), None);
+    }
+
+    #[test]
+    fn test_local_variables_block() {
+        let text = "text\n;; Local Variables:\n;; fill-column: 60\n;; foo: \"a;b\"\n;; End:\n";
+        assert_eq!(local_variables_block(text).unwrap(), " fill-column: 60\n foo: \"a;b\"\n");
+        let text = "/* Local Variables: */\n/* x: 1 */\n/* end: */\n";
+        assert_eq!(local_variables_block(text).unwrap(), " x: 1\n");
+        // not terminated
+        assert!(local_variables_block("# Local Variables:\n# x: 1\n").is_none());
+        // only the last page is searched
+        assert!(local_variables_block("# Local Variables:\n# End:\n\n\x0c\n").is_none());
+    }
+
+    #[test]
+    fn test_hack_local_variables() {
+        assert_lisp(
+            r#"(progn
+                 (put 'test-local-number 'safe-local-variable 'integerp)
+                 (setq enable-local-variables t test-local-number 0 test-local-unsafe 0)
+                 (insert "-*- test-local-number: 5; test-local-unsafe: 3 -*-\n")
+                 (insert ";; Local Variables:\n;; eval: (setq test-local-unsafe 4)\n;; End:\n")
+                 (hack-local-variables)
+                 (list test-local-number test-local-unsafe))"#,
+            "(5 0)",
+        );
+        assert_lisp(
+            r#"(progn
+                 (insert "-*- mode: emacs-lisp -*-\n")
+                 (hack-local-variables t))"#,
+            "emacs-lisp-mode",
+        );
+        assert_lisp(
+            r#"(progn
+                 (setq enable-local-variables :all test-local-unsafe 0)
+                 (insert "-*- test-local-unsafe: (1 2) -*-\n")
+                 (hack-local-variables)
+                 test-local-unsafe)"#,
+            "(1 2)",
+        );
+    }
+
+    #[test]
+    #[cfg(not(miri))]
+    fn test_dir_locals() {
+        let dir = crate::fileio::TempDir::new("dir-locals");
+        let dir = dir.path();
+        let sub = dir.join("sub");
+        std::fs::create_dir_all(&sub).unwrap();
+        std::fs::write(
+            dir.join(".dir-locals.el"),
+            r#"((nil . ((test-dir-local . 1)))
+                (text-mode . ((test-dir-local . 2)))
+                ("sub" . ((nil . ((test-dir-local . 3))))))"#,
+        )
+        .unwrap();
+        let (dir_name, sub_name) = (dir.to_str().unwrap(), sub.to_str().unwrap());
+        assert_lisp(
+            &format!(
+                r#"(progn
+                     (put 'test-dir-local 'safe-local-variable 'integerp)
+                     (setq enable-local-variables t)
+                     (setq buffer-file-name "{dir_name}/file")
+                     (hack-local-variables)
+                     (let ((top test-dir-local))
+                       (setq major-mode 'text-mode)
+                       (hack-local-variables)
+                       (let ((text test-dir-local))
+                         (setq buffer-file-name "{sub_name}/file")
+                         (hack-local-variables)
+                         (list top text test-dir-local
+                               (dir-locals-find-file "{sub_name}/file")))))"#
+            ),
+            &format!(r#"(1 2 3 "{dir_name}/")"#),
+        );
+    }
+}
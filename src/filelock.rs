@@ -1,13 +1,203 @@
+//! File locks, which warn about editing a file that another session is also
+//! editing.
+//!
+//! The lock for a file is a symbolic link named `.#FILE` next to it, whose
+//! target is `USER@HOST.PID` for the session that holds it. This is the same
+//! format GNU Emacs uses, so the two respect each other's locks.
+use crate::core::{
+    env::{Env, sym},
+    gc::{Context, Rt},
+    object::{NIL, Object, ObjectType, TRUE},
+};
+use crate::eval::EvalError;
+use crate::fileio::expand_file_name;
+use anyhow::Result;
+use rune_core::macros::list;
 use rune_macros::defun;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
 
-use crate::core::object::OptionalFlag;
+defsym!(FILE_LOCKED);
+// Non-nil means lock files are created for files that are being edited.
+defvar_bool!(CREATE_LOCKFILES, true);
 
+/// Who holds the lock on a file.
+#[derive(Debug, PartialEq)]
+enum LockOwner {
+    None,
+    Us,
+    /// Another session, as `USER@HOST`
+    Other(String),
+}
+
+fn lock_file_name(filename: &str) -> PathBuf {
+    let path = Path::new(filename);
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!(".#{name}"))
+}
+
+fn host_name() -> String {
+    hostname::get().map(|x| x.to_string_lossy().into_owned()).unwrap_or_default()
+}
+
+/// The target of the lock files created by this session.
+fn lock_info() -> String {
+    let user = std::env::var("USER").or_else(|_| std::env::var("LOGNAME")).unwrap_or_default();
+    format!("{user}@{}.{}", host_name(), std::process::id())
+}
+
+fn process_alive(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else { return false };
+    // Signal 0 only checks whether the process exists
+    let alive = unsafe { libc::kill(pid, 0) == 0 };
+    alive || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+fn lock_owner(lock: &Path) -> LockOwner {
+    let Ok(target) = std::fs::read_link(lock) else { return LockOwner::None };
+    let target = target.to_string_lossy();
+    // Emacs may add `:BOOT-TIME` after the pid
+    let info = target.split_once(':').map_or(&*target, |x| x.0);
+    let Some((owner, pid)) = info.rsplit_once('.') else {
+        return LockOwner::Other(info.to_owned());
+    };
+    let host = owner.split_once('@').map_or("", |x| x.1);
+    match pid.parse::<u32>() {
+        Ok(pid) if host == host_name() && pid == std::process::id() => LockOwner::Us,
+        // A lock left behind by a session on this host that has exited
+        Ok(pid) if host == host_name() && !process_alive(pid) => LockOwner::None,
+        _ => LockOwner::Other(owner.to_owned()),
+    }
+}
+
+/// Lock `filename` for this session. Signals `file-locked` if another
+/// session holds the lock.
+pub(crate) fn lock(filename: &str, env: &mut Rt<Env>, cx: &Context) -> Result<()> {
+    if env.vars.get(sym::CREATE_LOCKFILES).is_none_or(|x| x.bind(cx).is_nil()) {
+        return Ok(());
+    }
+    let lock = lock_file_name(filename);
+    match lock_owner(&lock) {
+        LockOwner::Us => Ok(()),
+        LockOwner::None => {
+            // Replace a stale lock
+            let _ = std::fs::remove_file(&lock);
+            match std::os::unix::fs::symlink(lock_info(), &lock) {
+                // Files in directories we can't write to are not locked
+                Err(e) if e.kind() != ErrorKind::PermissionDenied => Err(e.into()),
+                _ => Ok(()),
+            }
+        }
+        LockOwner::Other(owner) => {
+            let data = list![cx.add(filename), cx.add(owner); cx];
+            Err(EvalError::signal(sym::FILE_LOCKED.into(), data, env).into())
+        }
+    }
+}
+
+/// Remove the lock on `filename` if this session holds it.
+pub(crate) fn unlock(filename: &str) -> Result<()> {
+    let lock = lock_file_name(filename);
+    if lock_owner(&lock) == LockOwner::Us {
+        std::fs::remove_file(lock)?;
+    }
+    Ok(())
+}
+
+/// The file visited by the current buffer, if it is modified.
+fn modified_buffer_file(env: &Rt<Env>, cx: &Context) -> Result<Option<String>> {
+    if !env.current_buffer.get().modified_p() {
+        return Ok(None);
+    }
+    match env.vars.get(sym::BUFFER_FILE_NAME).map(|x| x.untag(cx)) {
+        Some(ObjectType::String(name)) => Ok(Some(expand_file_name(name, None, env, cx)?)),
+        _ => Ok(None),
+    }
+}
+
+/// Lock FILE for this session. Signals `file-locked` if another session has
+/// locked it. Nothing is done if `create-lockfiles` is nil.
+#[defun]
+fn lock_file(file: &str, env: &mut Rt<Env>, cx: &Context) -> Result<()> {
+    let file = expand_file_name(file, None, env, cx)?;
+    lock(&file, env, cx)
+}
+
+/// Unlock FILE if this session has locked it.
 #[defun]
-fn lock_buffer(_file: OptionalFlag) {
-    // TODO: implement
+fn unlock_file(file: &str, env: &Rt<Env>, cx: &Context) -> Result<()> {
+    unlock(&expand_file_name(file, None, env, cx)?)
 }
 
+/// Return nil if FILENAME is not locked, t if this session has locked it, or
+/// the name of the user who locked it otherwise.
 #[defun]
-fn unlock_buffer() {
-    // TODO: implement
+fn file_locked_p<'ob>(filename: &str, env: &Rt<Env>, cx: &'ob Context) -> Result<Object<'ob>> {
+    let filename = expand_file_name(filename, None, env, cx)?;
+    Ok(match lock_owner(&lock_file_name(&filename)) {
+        LockOwner::None => NIL,
+        LockOwner::Us => TRUE,
+        LockOwner::Other(owner) => {
+            let user = owner.split_once('@').map_or(owner.as_str(), |x| x.0);
+            cx.add(user)
+        }
+    })
+}
+
+/// Lock FILE, or the file visited by the current buffer, if the buffer is
+/// modified.
+#[defun]
+fn lock_buffer(file: Option<&str>, env: &mut Rt<Env>, cx: &Context) -> Result<()> {
+    let Some(visited) = modified_buffer_file(env, cx)? else { return Ok(()) };
+    let file = match file {
+        Some(file) => expand_file_name(file, None, env, cx)?,
+        None => visited,
+    };
+    lock(&file, env, cx)
+}
+
+/// Unlock the file visited by the current buffer, if the buffer is modified.
+#[defun]
+fn unlock_buffer(env: &Rt<Env>, cx: &Context) -> Result<()> {
+    match modified_buffer_file(env, cx)? {
+        Some(file) => unlock(&file),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fileio::TempDir;
+    use crate::interpreter::assert_lisp;
+
+    #[test]
+    #[cfg(not(miri))]
+    fn test_lock_file() {
+        let dir = TempDir::new("lock");
+        let file = dir.path().join("file");
+        let file = file.to_str().unwrap();
+        assert_lisp(
+            &format!(
+                r#"(list (progn (setq create-lockfiles t) (file-locked-p "{file}"))
+                         (progn (lock-file "{file}") (file-locked-p "{file}"))
+                         (progn (unlock-file "{file}") (file-locked-p "{file}")))"#
+            ),
+            "(nil t nil)",
+        );
+        let lock = lock_file_name(file);
+        std::os::unix::fs::symlink("other@elsewhere.1", &lock).unwrap();
+        assert_eq!(lock_owner(&lock), LockOwner::Other("other@elsewhere".into()));
+        assert_lisp(&format!(r#"(file-locked-p "{file}")"#), "\"other\"");
+        assert_lisp(
+            &format!(
+                r#"(progn (setq create-lockfiles t)
+                          (condition-case err (lock-file "{file}") (error err)))"#
+            ),
+            &format!(r#"(file-locked "{file}" "other@elsewhere")"#),
+        );
+        // Only the session that holds a lock removes it
+        assert_lisp(&format!(r#"(unlock-file "{file}")"#), "nil");
+        assert!(lock.symlink_metadata().is_ok());
+    }
 }
@@ -0,0 +1,274 @@
+//! File notifications.
+//!
+//! Watches are backed by the native notification API of the platform
+//! (inotify, FSEvents, kqueue, ...) through the `notify` crate. The watchers
+//! run on their own threads and queue the events they see, which are then
+//! delivered to the lisp callbacks by [`dispatch_file_events`] when lisp waits
+//! for input, like in `accept-process-output`.
+//!
+//! Like in filenotify.el, a file is watched through its directory so that the
+//! watch survives the file being replaced by a rename.
+use crate::core::{
+    env::{Env, sym},
+    gc::{Context, Rt, Rto},
+    object::{Function, List, Object, ObjectType, Symbol},
+};
+use anyhow::{Context as _, Result, bail};
+use notify::{
+    EventKind, RecommendedWatcher, RecursiveMode, Watcher,
+    event::{ModifyKind, RenameMode},
+};
+use rune_core::{
+    hashmap::HashMap,
+    macros::{call, list, root},
+};
+use rune_macros::defun;
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        LazyLock, Mutex,
+        atomic::{AtomicI64, Ordering},
+    },
+};
+
+struct Watch {
+    /// Stops watching when dropped
+    _watcher: RecommendedWatcher,
+    /// The file or directory being watched
+    file: PathBuf,
+    /// True if only events for `file` itself are reported, instead of for
+    /// the files in it
+    file_only: bool,
+    change: bool,
+    attribute_change: bool,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Action {
+    Created,
+    Deleted,
+    Changed,
+    Renamed,
+    AttributeChanged,
+}
+
+impl Action {
+    fn symbol(self) -> Symbol<'static> {
+        match self {
+            Action::Created => sym::CREATED,
+            Action::Deleted => sym::DELETED,
+            Action::Changed => sym::CHANGED,
+            Action::Renamed => sym::RENAMED,
+            Action::AttributeChanged => sym::ATTRIBUTE_CHANGED,
+        }
+    }
+}
+
+/// An event seen by a watcher that has not been delivered yet.
+struct FileEvent {
+    descriptor: i64,
+    action: Action,
+    file: PathBuf,
+    file1: Option<PathBuf>,
+}
+
+// The OS state of all watches, keyed by descriptor. The callbacks are kept in
+// `Env::file_watches`.
+static WATCHES: LazyLock<Mutex<HashMap<i64, Watch>>> = LazyLock::new(Mutex::default);
+static PENDING: Mutex<Vec<FileEvent>> = Mutex::new(Vec::new());
+static NEXT_DESCRIPTOR: AtomicI64 = AtomicI64::new(1);
+
+/// Translate an event from `notify` to the actions used by
+/// `file-notify-add-watch`.
+fn queue_event(descriptor: i64, event: notify::Event) {
+    let mut paths = event.paths.into_iter();
+    let Some(file) = paths.next() else { return };
+    let action = match event.kind {
+        EventKind::Create(_) | EventKind::Modify(ModifyKind::Name(RenameMode::To)) => {
+            Action::Created
+        }
+        EventKind::Remove(_) | EventKind::Modify(ModifyKind::Name(RenameMode::From)) => {
+            Action::Deleted
+        }
+        EventKind::Modify(ModifyKind::Name(_)) => Action::Renamed,
+        EventKind::Modify(ModifyKind::Metadata(_)) => Action::AttributeChanged,
+        EventKind::Modify(_) => Action::Changed,
+        EventKind::Access(_) | EventKind::Any | EventKind::Other => return,
+    };
+    let file1 = if action == Action::Renamed { paths.next() } else { None };
+    PENDING.lock().unwrap().push(FileEvent { descriptor, action, file, file1 });
+}
+
+/// Whether `watch` is interested in `event`.
+fn wants_event(watch: &Watch, event: &FileEvent) -> bool {
+    let action_wanted = if event.action == Action::AttributeChanged {
+        watch.attribute_change
+    } else {
+        watch.change
+    };
+    let file_wanted =
+        !watch.file_only || event.file == watch.file || event.file1.as_ref() == Some(&watch.file);
+    action_wanted && file_wanted
+}
+
+/// Deliver the queued file notification events to their callbacks.
+pub(crate) fn dispatch_file_events(env: &mut Rt<Env>, cx: &mut Context) -> Result<()> {
+    let events: Vec<_> = {
+        let watches = WATCHES.lock().unwrap();
+        let mut pending = PENDING.lock().unwrap();
+        pending
+            .drain(..)
+            .filter(|x| watches.get(&x.descriptor).is_some_and(|watch| wants_event(watch, x)))
+            .collect()
+    };
+    for event in events {
+        let Some(callback) = callback_of(event.descriptor, env, cx)? else { continue };
+        root!(callback, cx);
+        let descriptor = cx.add(event.descriptor);
+        let action = event.action.symbol();
+        let file = cx.add(event.file.to_string_lossy().into_owned());
+        let arg = match event.file1 {
+            Some(file1) => {
+                let file1 = cx.add(file1.to_string_lossy().into_owned());
+                list![descriptor, action, file, file1; cx]
+            }
+            None => list![descriptor, action, file; cx],
+        };
+        call!(callback, arg; env, cx)?;
+    }
+    Ok(())
+}
+
+fn callback_of<'ob>(
+    descriptor: i64,
+    env: &Rt<Env>,
+    cx: &'ob Context,
+) -> Result<Option<Function<'ob>>> {
+    match env.file_watches.get(cx.add(descriptor)) {
+        Some(callback) => Ok(Some(callback.bind(cx).try_into()?)),
+        None => Ok(None),
+    }
+}
+
+/// Add a watch for file system events on FILE, which may be a file or a
+/// directory. FLAGS is a list of the kinds of events to report: `change`
+/// for changes to the contents and `attribute-change` for changes to the
+/// attributes. Returns a descriptor for the watch.
+///
+/// CALLBACK is called with one argument of the form (DESCRIPTOR ACTION FILE
+/// [FILE1]), where ACTION is one of `created`, `deleted`, `changed`,
+/// `renamed`, `attribute-changed` or `stopped`. FILE1 is the new name of a
+/// renamed file.
+#[defun]
+fn file_notify_add_watch<'ob>(
+    file: &str,
+    flags: List,
+    callback: Object<'ob>,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    let file = crate::fileio::expand_file_name(file, None, env, cx)?;
+    let path = Path::new(&file);
+    let file_only = !path.is_dir();
+    let dir = if file_only {
+        match path.parent() {
+            Some(dir) if dir.is_dir() => dir,
+            _ => bail!("No such file or directory: {file}"),
+        }
+    } else {
+        path
+    };
+    let mut change = false;
+    let mut attribute_change = false;
+    for flag in flags {
+        match flag?.untag() {
+            ObjectType::Symbol(sym::CHANGE) => change = true,
+            ObjectType::Symbol(sym::ATTRIBUTE_CHANGE) => attribute_change = true,
+            flag => bail!("Invalid file notification flag: {flag}"),
+        }
+    }
+    let descriptor = NEXT_DESCRIPTOR.fetch_add(1, Ordering::Relaxed);
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        if let Ok(event) = event {
+            queue_event(descriptor, event);
+        }
+    })?;
+    watcher
+        .watch(dir, RecursiveMode::NonRecursive)
+        .with_context(|| format!("Cannot watch {}", dir.display()))?;
+    let watch =
+        Watch { _watcher: watcher, file: path.to_owned(), file_only, change, attribute_change };
+    WATCHES.lock().unwrap().insert(descriptor, watch);
+    let descriptor = cx.add(descriptor);
+    env.file_watches.insert(descriptor, callback);
+    Ok(descriptor)
+}
+
+/// Remove the watch DESCRIPTOR. Its callback is called a last time with the
+/// action `stopped`.
+#[defun]
+fn file_notify_rm_watch(
+    descriptor: &Rto<Object>,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<()> {
+    let ObjectType::Int(id) = descriptor.untag(cx) else { return Ok(()) };
+    let Some(watch) = WATCHES.lock().unwrap().remove(&id) else { return Ok(()) };
+    let callback = callback_of(id, env, cx);
+    env.file_watches.remove(descriptor.bind(cx));
+    let Some(callback) = callback? else { return Ok(()) };
+    root!(callback, cx);
+    let file = cx.add(watch.file.to_string_lossy().into_owned());
+    let arg = list![descriptor.bind(cx), sym::STOPPED, file; cx];
+    call!(callback, arg; env, cx)?;
+    Ok(())
+}
+
+/// Return t if DESCRIPTOR is a watch that has not been removed.
+#[defun]
+fn file_notify_valid_p(descriptor: Object) -> bool {
+    match descriptor.untag() {
+        ObjectType::Int(id) => WATCHES.lock().unwrap().contains_key(&id),
+        _ => false,
+    }
+}
+
+defsym!(CHANGE);
+defsym!(ATTRIBUTE_CHANGE);
+defsym!(CREATED);
+defsym!(DELETED);
+defsym!(CHANGED);
+defsym!(RENAMED);
+defsym!(ATTRIBUTE_CHANGED);
+defsym!(STOPPED);
+
+#[cfg(test)]
+mod test {
+    use crate::fileio::TempDir;
+    use crate::interpreter::assert_lisp;
+
+    #[test]
+    #[cfg(not(miri))]
+    fn test_file_notify() {
+        let dir = TempDir::new("notify");
+        let dir = dir.path().to_str().unwrap();
+        assert_lisp(
+            &format!(
+                r#"(progn
+                     (setq rune-test-notify-events nil)
+                     (let ((desc (file-notify-add-watch
+                                  "{dir}" '(change)
+                                  #'(lambda (event)
+                                    (setq rune-test-notify-events
+                                          (cons (nth 1 event) rune-test-notify-events))))))
+                       (make-directory "{dir}/new")
+                       (accept-process-output nil 1)
+                       (let ((valid (file-notify-valid-p desc)))
+                         (file-notify-rm-watch desc)
+                         (list valid (file-notify-valid-p desc) (car rune-test-notify-events)
+                               (and (memq 'created rune-test-notify-events) t)))))"#
+            ),
+            "(t nil stopped t)",
+        );
+    }
+}
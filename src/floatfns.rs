@@ -8,7 +8,7 @@ use crate::{
     },
 };
 
-use rune_macros::defun;
+use rune_macros::{defun, elprop_compare};
 
 #[inline(always)]
 fn coerce(arg: Number) -> f64 {
@@ -131,7 +131,9 @@ fn expt(x: Number, y: Number) -> NumberValue {
     }
 }
 
+// Emacs uses log2 and log10 for those bases, which can round differently
 #[defun]
+#[elprop_compare(float = 1e-15)]
 fn log(arg: Number, base: Option<f64>) -> f64 {
     if let Some(base) = base {
         coerce(arg).log(base)
@@ -148,7 +150,7 @@ fn sqrt(arg: Number) -> f64 {
 #[defun]
 fn abs(arg: Number) -> NumberValue {
     match arg.untag() {
-        NumberType::Int(i) => NumberValue::Int(i.abs()),
+        NumberType::Int(i) => NumberValue::Int(i.wrapping_abs()),
         NumberType::Float(f) => NumberValue::Float(f.abs()),
     }
 }
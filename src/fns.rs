@@ -1,16 +1,19 @@
 //! General purpose lisp functions
 use crate::{
+    buffer::resolve_buffer,
+    coding::encode_utf8,
     core::{
         cons::Cons,
         env::{Env, sym},
         error::{Type, TypeError},
         gc::{Context, Rt, Rto},
         object::{
-            Function, Gc, HashTable, IntoObject, LispHashTable, LispString, LispVec, List,
-            ListType, NIL, Object, ObjectType, OptionalFlag, Symbol, WithLifetime,
+            Function, Gc, HashTable, IntoObject, Keywords, LispHashTable, LispString, LispVec,
+            List, ListType, NIL, Object, ObjectType, OpenBuffer, OptionalFlag, Symbol,
+            WithLifetime, char_raw_byte, char_to_int, raw_byte_char,
         },
     },
-    data::aref,
+    data::{aref, remove_pos_from_symbol, symbols_with_pos_enabled},
     library::filevercmp::filevercmp,
     rooted_iter,
 };
@@ -19,7 +22,7 @@ use base64::Engine;
 use fallible_iterator::FallibleIterator;
 use fallible_streaming_iterator::FallibleStreamingIterator;
 use rune_core::macros::{call, list, rebind, root};
-use rune_macros::{defun, elprop};
+use rune_macros::{Keywords, defun, elprop};
 
 #[defun]
 fn identity(arg: Object) -> Object {
@@ -50,9 +53,20 @@ pub(crate) fn build_list<'ob, E>(
     Ok(head.into())
 }
 
+/// Return t if the two args are the same lisp object. A symbol with position
+/// is the same as its bare symbol when `symbols-with-pos-enabled` is non-nil.
 #[defun]
-pub(crate) fn eq(obj1: Object, obj2: Object) -> bool {
-    obj1.ptr_eq(obj2)
+pub(crate) fn eq(obj1: Object, obj2: Object, env: &Rt<Env>, cx: &Context) -> bool {
+    if obj1.ptr_eq(obj2) {
+        return true;
+    }
+    match (obj1.untag(), obj2.untag()) {
+        (ObjectType::SymbolWithPos(_), _) | (_, ObjectType::SymbolWithPos(_)) => {
+            let (bare1, bare2) = (remove_pos_from_symbol(obj1), remove_pos_from_symbol(obj2));
+            bare1.ptr_eq(bare2) && symbols_with_pos_enabled(env, cx)
+        }
+        _ => false,
+    }
 }
 
 #[defun]
@@ -75,13 +89,13 @@ fn equal_including_properties<'ob>(o1: Object<'ob>, o2: Object<'ob>) -> bool {
 }
 
 #[defun]
-fn plist_get<'ob>(plist: Object<'ob>, prop: Object<'ob>) -> Result<Object<'ob>> {
+pub(crate) fn plist_get<'ob>(plist: Object<'ob>, prop: Object<'ob>) -> Result<Object<'ob>> {
     let Ok(plist) = List::try_from(plist) else { return Ok(NIL) };
     // TODO: this function should never fail. Need to implement safe iterator
     let mut iter = plist.elements();
     while let Some(cur_prop) = iter.next() {
         let Some(value) = iter.next() else { return Ok(NIL) };
-        if eq(cur_prop?, prop) {
+        if cur_prop?.ptr_eq(prop) {
             return Ok(value?);
         }
     }
@@ -101,24 +115,13 @@ fn plist_member<'ob>(
             continue;
         }
         let value = value?;
-        if eq(value.car(), prop) {
+        if value.car().ptr_eq(prop) {
             return Ok(value.into());
         }
     }
     Ok(NIL)
 }
 
-#[defun]
-pub(crate) fn prin1_to_string(object: Object, _noescape: Option<Object>) -> String {
-    format!("{object}")
-}
-
-#[defun]
-fn string_to_multibyte(string: &LispString) -> &LispString {
-    // TODO: Handle the unibyte case
-    string
-}
-
 #[defun]
 fn string_search(needle: &str, haystack: &str, start_pos: Option<usize>) -> Option<usize> {
     let start = start_pos.unwrap_or(0);
@@ -303,10 +306,18 @@ pub(crate) fn append<'ob>(
 }
 
 #[defun]
-pub(crate) fn assq<'ob>(key: Object<'ob>, alist: List<'ob>) -> Result<Object<'ob>> {
+pub(crate) fn assq<'ob>(
+    key: Object<'ob>,
+    alist: List<'ob>,
+    env: &Rt<Env>,
+    cx: &Context,
+) -> Result<Object<'ob>> {
+    let with_pos = symbols_with_pos_enabled(env, cx);
+    let key = if with_pos { remove_pos_from_symbol(key) } else { key };
     for elem in alist {
         if let ObjectType::Cons(cons) = elem?.untag() {
-            if eq(key, cons.car()) {
+            let car = if with_pos { remove_pos_from_symbol(cons.car()) } else { cons.car() };
+            if key.ptr_eq(car) {
                 return Ok(cons.into());
             }
         }
@@ -318,7 +329,7 @@ pub(crate) fn assq<'ob>(key: Object<'ob>, alist: List<'ob>) -> Result<Object<'ob
 fn rassq<'ob>(key: Object<'ob>, alist: List<'ob>) -> Result<Object<'ob>> {
     for elem in alist {
         if let ObjectType::Cons(cons) = elem?.untag() {
-            if eq(key, cons.cdr()) {
+            if key.ptr_eq(cons.cdr()) {
                 return Ok(cons.into());
             }
         }
@@ -419,7 +430,7 @@ pub(crate) fn delete<'ob>(elt: Object<'ob>, list: List<'ob>) -> Result<Object<'o
 
 #[defun]
 pub(crate) fn delq<'ob>(elt: Object<'ob>, list: List<'ob>) -> Result<Object<'ob>> {
-    delete_from_list(elt, list, eq)
+    delete_from_list(elt, list, |x, y| x.ptr_eq(y))
 }
 
 fn member_of_list<'ob>(elt: Object<'ob>, list: List<'ob>, eq_fn: EqFunc) -> Result<Object<'ob>> {
@@ -431,8 +442,18 @@ fn member_of_list<'ob>(elt: Object<'ob>, list: List<'ob>, eq_fn: EqFunc) -> Resu
 }
 
 #[defun]
-pub(crate) fn memq<'ob>(elt: Object<'ob>, list: List<'ob>) -> Result<Object<'ob>> {
-    member_of_list(elt, list, eq)
+pub(crate) fn memq<'ob>(
+    elt: Object<'ob>,
+    list: List<'ob>,
+    env: &Rt<Env>,
+    cx: &Context,
+) -> Result<Object<'ob>> {
+    if symbols_with_pos_enabled(env, cx) {
+        let elt = remove_pos_from_symbol(elt);
+        member_of_list(elt, list, |x, y| remove_pos_from_symbol(x).ptr_eq(y))
+    } else {
+        member_of_list(elt, list, |x, y| x.ptr_eq(y))
+    }
 }
 
 #[defun]
@@ -494,8 +515,11 @@ pub(crate) fn defvaralias<'ob>(
 }
 
 #[defun]
-// TODO: implement
-pub(crate) fn featurep(_feature: Symbol, _subfeature: Option<Symbol>) {}
+pub(crate) fn featurep(feature: Symbol, subfeature: Option<Object>) -> bool {
+    // TODO: implement subfeatures
+    let feature = unsafe { feature.with_lifetime() };
+    subfeature.is_none() && crate::data::FEATURES.lock().unwrap().contains(&feature)
+}
 
 #[defun]
 pub(crate) fn require<'ob>(
@@ -505,20 +529,33 @@ pub(crate) fn require<'ob>(
     env: &mut Rt<Env>,
     cx: &'ob mut Context,
 ) -> Result<Symbol<'ob>> {
-    // TODO: Fix this unsafe into_root
-    let feat = unsafe { feature.untag(cx).with_lifetime() };
-    if crate::data::FEATURES.lock().unwrap().contains(&feat) {
+    crate::lread::record_load_entry(sym::REQUIRE, feature.untag(cx).into(), env, cx);
+    if featurep(feature.untag(cx), None) {
         return Ok(feature.untag(cx));
     }
+    // When no filename is given, only load files with one of the `load-suffixes'
+    let must_suffix = filename.is_none().then_some(());
     let file = match filename {
         Some(file) => file.untag(cx),
         None => feature.untag(cx).get().name(),
     };
     let file = file.into_obj(cx);
     root!(file, cx);
-    match crate::lread::load(file, noerror, None, cx, env) {
-        Ok(_) => Ok(feature.untag(cx)),
-        Err(e) => Err(e),
+    match crate::lread::load(file, noerror, Some(()), None, must_suffix, cx, env) {
+        Ok(true) => {}
+        Ok(false) => return Ok(sym::NIL),
+        Err(e) if noerror.is_some() && crate::eval::exit_code(&e).is_none() => {
+            return Ok(sym::NIL);
+        }
+        Err(e) => return Err(e),
+    }
+    let feature = feature.untag(cx);
+    if featurep(feature, None) {
+        Ok(feature)
+    } else if noerror.is_some() {
+        Ok(sym::NIL)
+    } else {
+        bail!("Required feature `{feature}' was not provided")
     }
 }
 
@@ -528,6 +565,9 @@ pub(crate) fn concat(sequences: &[Object]) -> Result<String> {
     for elt in sequences {
         match elt.untag() {
             ObjectType::String(string) => concat += string,
+            ObjectType::ByteString(string) => {
+                concat.extend(string.iter().map(|&b| raw_byte_char(b)))
+            }
             ObjectType::NIL => continue,
             _ => bail!("Currently only concatenating strings are supported"),
         }
@@ -543,7 +583,7 @@ pub(crate) fn vconcat<'ob>(sequences: &[Object], cx: &'ob Context) -> Result<Gc<
             // TODO: need to correctly handle unibyte strings (no unicode codepoints)
             ObjectType::String(string) => {
                 for chr in string.chars() {
-                    concated.push((chr as i64).into());
+                    concated.push(char_to_int(chr).into());
                 }
             }
             ObjectType::Cons(cons) => {
@@ -628,20 +668,13 @@ pub(crate) fn elt<'ob>(sequence: Object<'ob>, n: usize, cx: &'ob Context) -> Res
 
 #[defun]
 pub(crate) fn string_equal<'ob>(s1: Object<'ob>, s2: Object<'ob>) -> Result<bool> {
-    let s1 = match s1.untag() {
-        ObjectType::String(x) => x.as_bytes(),
-        ObjectType::ByteString(x) => x.inner(),
-        ObjectType::Symbol(x) => x.get().as_bytes(),
-        _ => bail!(TypeError::new(Type::String, s1)),
-    };
-    let s2 = match s2.untag() {
-        ObjectType::String(x) => x.as_bytes(),
-        ObjectType::ByteString(x) => x.inner(),
-        ObjectType::Symbol(x) => (x.get()).as_bytes(),
-        _ => bail!(TypeError::new(Type::String, s2)),
+    let bytes = |obj: Object<'ob>| -> Result<&'ob [u8], TypeError> {
+        match obj.untag() {
+            ObjectType::Symbol(x) => Ok(x.get().as_bytes()),
+            _ => obj.try_into(),
+        }
     };
-
-    Ok(s1 == s2)
+    Ok(bytes(s1)? == bytes(s2)?)
 }
 
 #[defun]
@@ -708,13 +741,88 @@ pub(crate) fn compare_strings<'ob>(
     Ok(true.into())
 }
 
+/// Return the Levenshtein distance between STRING1 and STRING2. If
+/// BYTECOMPARE is non-nil the bytes of the strings are compared instead of
+/// their characters.
 #[defun]
-pub(crate) fn string_distance(string1: &str, string2: &str, bytecompare: OptionalFlag) -> i64 {
-    if bytecompare.is_none() {
-        levenshtein_distance(string1.chars(), string2.chars())
-    } else {
-        levenshtein_distance(string1.as_bytes().iter(), string2.as_bytes().iter())
+pub(crate) fn string_distance(
+    string1: Object,
+    string2: Object,
+    bytecompare: OptionalFlag,
+) -> Result<i64> {
+    let units = |string: Object| -> Result<Vec<u32>> {
+        Ok(match string.untag() {
+            ObjectType::String(s) if bytecompare.is_some() => {
+                encode_utf8(s).into_iter().map(u32::from).collect()
+            }
+            ObjectType::String(s) => s.chars().map(u32::from).collect(),
+            ObjectType::ByteString(s) if bytecompare.is_some() => {
+                s.iter().map(|&b| u32::from(b)).collect()
+            }
+            // the characters of a unibyte string are raw bytes
+            ObjectType::ByteString(s) => s.iter().map(|&b| u32::from(raw_byte_char(b))).collect(),
+            x => bail!(TypeError::new(Type::String, x)),
+        })
+    };
+    Ok(levenshtein_distance(units(string1)?.into_iter(), units(string2)?.into_iter()))
+}
+
+/// Score how well PATTERN matches STRING for flex completion, where the
+/// characters of PATTERN have to appear in STRING in order but not next to
+/// each other. Return nil if they don't.
+///
+/// The score is between 0 and 1, and only 1 if PATTERN is STRING. It is the
+/// length of PATTERN divided by the length of STRING, divided again by one
+/// plus the number of holes in the match, which are the gaps between matched
+/// characters. Text before the first match and after the last one is not a
+/// hole. The match with the fewest holes is used. Case is ignored if
+/// `completion-ignore-case` is non-nil.
+#[defun(name = "rune--flex-score")]
+fn flex_score(pattern: &str, string: &str, env: &Rt<Env>, cx: &Context) -> Option<f64> {
+    let ignore_case =
+        env.vars.get(sym::COMPLETION_IGNORE_CASE).is_some_and(|x| !x.bind(cx).is_nil());
+    let fold = |c: char| if ignore_case { c.to_lowercase().next().unwrap_or(c) } else { c };
+    let pattern: Vec<char> = pattern.chars().map(fold).collect();
+    let string: Vec<char> = string.chars().map(fold).collect();
+    let holes = flex_holes(&pattern, &string)?;
+    if string.is_empty() {
+        return Some(1.0);
+    }
+    Some(pattern.len() as f64 / (string.len() * (1 + holes)) as f64)
+}
+
+/// The fewest holes in a match of `pattern` in `string`, or `None` if it
+/// doesn't match. This is a dynamic program over the characters of both that
+/// keeps one column for each of the two states a position in `string` can
+/// be in.
+fn flex_holes(pattern: &[char], string: &[char]) -> Option<usize> {
+    const NONE: usize = usize::MAX;
+    if pattern.is_empty() {
+        return Some(0);
+    }
+    let m = pattern.len();
+    // matched[i]: the fewest holes with pattern[..i] matched and pattern[i - 1]
+    // at the current position of string
+    // skipped[i]: the same, but with the current position in a hole after
+    // pattern[i - 1]
+    let mut matched = vec![NONE; m + 1];
+    let mut skipped = vec![NONE; m + 1];
+    let mut best = NONE;
+    for &chr in string {
+        // go backwards so that index i - 1 still holds the previous position
+        for i in (1..=m).rev() {
+            skipped[i] = matched[i].saturating_add(1).min(skipped[i]);
+            matched[i] = if pattern[i - 1] != chr {
+                NONE
+            } else if i == 1 {
+                0
+            } else {
+                matched[i - 1].min(skipped[i - 1])
+            };
+        }
+        best = best.min(matched[m]);
     }
+    (best != NONE).then_some(best)
 }
 
 #[inline]
@@ -752,9 +860,18 @@ pub(crate) fn levenshtein_distance<T: PartialEq, I: Iterator<Item = T>>(s1: I, s
     v0[t.len()]
 }
 
+/// Return the number of bytes in STRING. Raw byte characters in a multibyte
+/// string count as two bytes, like in GNU Emacs.
 #[defun]
-pub(crate) fn string_bytes(string: &str) -> usize {
-    string.len()
+pub(crate) fn string_bytes(string: Object) -> Result<usize> {
+    match string.untag() {
+        ObjectType::String(s) => Ok(s
+            .chars()
+            .map(|c| if char_raw_byte(c).is_some() { 2 } else { c.len_utf8() })
+            .sum()),
+        ObjectType::ByteString(s) => Ok(s.len()),
+        x => Err(TypeError::new(Type::String, x).into()),
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -836,23 +953,53 @@ pub(crate) fn clear_string(string: &LispString) -> Result<Object> {
 defsym!(KW_TEST);
 defsym!(KW_DOCUMENTATION);
 
+defsym!(KW_SIZE);
+defsym!(KW_WEAKNESS);
+defsym!(KW_REHASH_SIZE);
+defsym!(KW_REHASH_THRESHOLD);
+defsym!(KW_PURECOPY);
+defsym!(KEY);
+defsym!(VALUE);
+defsym!(KEY_OR_VALUE);
+defsym!(KEY_AND_VALUE);
+
+/// The keyword arguments of `make-hash-table`. The rehash parameters and
+/// `:purecopy` are obsolete and ignored.
+#[derive(Keywords)]
+#[expect(dead_code)]
+pub(crate) struct HashTableKeywords<'ob> {
+    test: Option<Symbol<'ob>>,
+    size: Option<Object<'ob>>,
+    weakness: Option<Symbol<'ob>>,
+    rehash_size: Option<Object<'ob>>,
+    rehash_threshold: Option<Object<'ob>>,
+    purecopy: Option<Object<'ob>>,
+}
+
 #[defun]
 pub(crate) fn make_hash_table<'ob>(
-    keyword_args: &[Object<'ob>],
+    keyword_args: Keywords<HashTableKeywords<'ob>>,
     cx: &'ob Context,
 ) -> Result<Object<'ob>> {
-    let kw_test_pos = keyword_args.iter().step_by(2).position(|&x| x == sym::KW_TEST);
-    if let Some(i) = kw_test_pos {
-        let Some(val) = keyword_args.get((i * 2) + 1) else {
-            bail!("Missing keyword value for :test")
-        };
-        if *val != sym::EQ && *val != sym::EQUAL && *val != sym::EQL {
-            // TODO: we are currently only using `equal', but eq should be okay
-            bail!("only `eq' and `equal' keywords support for make-hash-table :test. Found {val}");
-        }
-    }
-    // TODO, the rest of the keywords need to be supported here
-    let map = HashTable::with_hasher(std::hash::BuildHasherDefault::default());
+    match keyword_args.test {
+        // TODO: we are currently only using `equal', but eq should be okay
+        None | Some(sym::EQ | sym::EQL | sym::EQUAL) => {}
+        Some(test) => bail!("Invalid hash table test: {test}"),
+    }
+    let size = match keyword_args.size.map(|x| x.untag()) {
+        None | Some(ObjectType::NIL) => 0,
+        Some(ObjectType::Int(size)) if size >= 0 => usize::try_from(size)?,
+        Some(size) => bail!("Invalid hash table size: {size}"),
+    };
+    // TODO: weak tables are not supported yet, so entries are always kept
+    match keyword_args.weakness {
+        None
+        | Some(
+            sym::NIL | sym::TRUE | sym::KEY | sym::VALUE | sym::KEY_OR_VALUE | sym::KEY_AND_VALUE,
+        ) => {}
+        Some(weakness) => bail!("Invalid hash table weakness: {weakness}"),
+    }
+    let map = HashTable::with_capacity_and_hasher(size, std::hash::BuildHasherDefault::default());
     Ok(cx.add(map))
 }
 
@@ -883,6 +1030,18 @@ pub(crate) fn puthash<'ob>(
     value
 }
 
+// Non-nil means `mapatoms` visits symbols sorted by name, so that output that
+// depends on iteration order is the same on every run. Hash tables always
+// iterate in insertion order. It is set by the `deterministic` cargo feature,
+// by the ERT runner and when evaluating forms from stdin for elprop.
+defvar_bool!(RUNE__DETERMINISTIC, false);
+
+/// Return true if iteration order should not depend on how symbols were
+/// interned. See `rune--deterministic`.
+pub(crate) fn deterministic(env: &Rt<Env>, cx: &Context) -> bool {
+    env.vars.get(sym::RUNE__DETERMINISTIC).is_some_and(|x| !x.bind(cx).is_nil())
+}
+
 #[defun]
 fn remhash(key: Object, table: &LispHashTable) -> Result<()> {
     let Some(idx) = table.get_index_of(key) else { return Ok(()) };
@@ -929,7 +1088,7 @@ fn maphash(
 }
 
 #[defun]
-fn copy_sequence<'ob>(arg: Object<'ob>, cx: &'ob Context) -> Result<Object<'ob>> {
+pub(crate) fn copy_sequence<'ob>(arg: Object<'ob>, cx: &'ob Context) -> Result<Object<'ob>> {
     match arg.untag() {
         ObjectType::Vec(x) => Ok(cx.add(x.to_vec())),
         ObjectType::Cons(x) => {
@@ -968,7 +1127,6 @@ fn substring(string: &str, from: Option<usize>, to: Option<usize>) -> Result<Str
     Ok(new_string.to_owned())
 }
 
-defsym!(MD5);
 defsym!(SHA1);
 defsym!(SHA224);
 defsym!(SHA256);
@@ -977,13 +1135,127 @@ defsym!(SHA512);
 
 #[defun]
 fn secure_hash_algorithms<'ob>(cx: &'ob Context) -> Object<'ob> {
-    // https://crates.io/crates/md-5
-    // https://crates.io/crates/sha1
-    // https://crates.io/crates/sha2
-    // https://crates.io/crates/digest ?
     list![sym::MD5, sym::SHA1, sym::SHA224, sym::SHA256, sym::SHA384, sym::SHA512; cx]
 }
 
+/// Call `func` with the bytes of OBJECT, a string or buffer, between START and
+/// END. These are character indices for strings and positions for buffers.
+/// The text of a buffer is passed in the pieces it is stored in, so it
+/// doesn't have to be copied.
+fn with_hash_input<T>(
+    object: Object,
+    start: Option<i64>,
+    end: Option<i64>,
+    env: &Rt<Env>,
+    mut func: impl FnMut(&[&[u8]]) -> T,
+) -> Result<T> {
+    let string_range = |len: usize| -> Result<(usize, usize)> {
+        let bound = |idx: Option<i64>, default: usize| -> Result<usize> {
+            let idx = idx.map_or(default as i64, |x| if x < 0 { x + len as i64 } else { x });
+            ensure!((0..=len as i64).contains(&idx), "Args out of range: {start:?}, {end:?}");
+            Ok(idx as usize)
+        };
+        let (start, end) = (bound(start, 0)?, bound(end, len)?);
+        ensure!(start <= end, "Args out of range: {start}, {end}");
+        Ok((start, end))
+    };
+    match object.untag() {
+        ObjectType::String(string) => {
+            let (start, end) = string_range(string.chars().count())?;
+            let mut indices = string.char_indices().map(|(i, _)| i).chain([string.len()]);
+            let beg = indices.nth(start).unwrap();
+            let end = if end == start { beg } else { indices.nth(end - start - 1).unwrap() };
+            Ok(func(&[&string.as_bytes()[beg..end]]))
+        }
+        ObjectType::ByteString(string) => {
+            let (start, end) = string_range(string.len())?;
+            Ok(func(&[&string.inner()[start..end]]))
+        }
+        ObjectType::Buffer(buffer) => env.with_buffer(buffer, |b| -> Result<T> {
+            let start = start.map_or(b.point_min(), |x| x as usize);
+            let end = end.map_or(b.point_max(), |x| x as usize);
+            let (s1, s2) = b.slice_with_gap(start.min(end), start.max(end))?;
+            Ok(func(&[s1.as_bytes(), s2.as_bytes()]))
+        })?,
+        _ => Err(TypeError::new(Type::String, object).into()),
+    }
+}
+
+fn digest<D: sha2::Digest>(data: &[&[u8]]) -> Vec<u8> {
+    let mut hasher = D::new();
+    for chunk in data {
+        hasher.update(chunk);
+    }
+    hasher.finalize().to_vec()
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    use std::fmt::Write as _;
+    bytes.iter().fold(String::with_capacity(bytes.len() * 2), |mut acc, x| {
+        write!(acc, "{x:02x}").unwrap();
+        acc
+    })
+}
+
+/// Return the secure hash of OBJECT, a buffer or string, using ALGORITHM.
+///
+/// ALGORITHM is a symbol from `secure-hash-algorithms`. START and END limit
+/// the hash to part of OBJECT. The hash is returned as a hexadecimal string,
+/// or as a unibyte string of the raw bytes if BINARY is non-nil.
+#[defun]
+fn secure_hash<'ob>(
+    algorithm: Symbol,
+    object: Object,
+    start: Option<i64>,
+    end: Option<i64>,
+    binary: OptionalFlag,
+    env: &Rt<Env>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    let hasher = match algorithm {
+        sym::MD5 => digest::<::md5::Md5>,
+        sym::SHA1 => digest::<sha1::Sha1>,
+        sym::SHA224 => digest::<sha2::Sha224>,
+        sym::SHA256 => digest::<sha2::Sha256>,
+        sym::SHA384 => digest::<sha2::Sha384>,
+        sym::SHA512 => digest::<sha2::Sha512>,
+        _ => bail!("Invalid algorithm arg: {algorithm}"),
+    };
+    let hash = with_hash_input(object, start, end, env, hasher)?;
+    if binary.is_some() { Ok(cx.add(hash)) } else { Ok(cx.add(to_hex(&hash))) }
+}
+
+/// Return the MD5 message digest of OBJECT, a buffer or string, as a
+/// hexadecimal string. START and END limit the digest to part of OBJECT.
+#[defun]
+fn md5(
+    object: Object,
+    start: Option<i64>,
+    end: Option<i64>,
+    _coding_system: Option<Object>,
+    _noerror: Option<Object>,
+    env: &Rt<Env>,
+) -> Result<String> {
+    let hash = with_hash_input(object, start, end, env, digest::<::md5::Md5>)?;
+    Ok(to_hex(&hash))
+}
+
+/// Return a hash of the contents of BUFFER-OR-NAME, or the current buffer if
+/// it is nil. The hash covers the whole buffer, even if it is narrowed, and
+/// changes whenever the text does, so it can tell if a buffer was edited.
+#[defun]
+fn buffer_hash(buffer_or_name: Option<Object>, env: &Rt<Env>, cx: &Context) -> Result<String> {
+    let hash = |b: &OpenBuffer| {
+        let (s1, s2) = b.text.slice(0..b.text.len_chars());
+        digest::<sha1::Sha1>(&[s1.as_bytes(), s2.as_bytes()])
+    };
+    let hash = match buffer_or_name {
+        Some(buffer) => env.with_buffer(resolve_buffer(buffer, cx)?, hash)?,
+        None => hash(env.current_buffer.get()),
+    };
+    Ok(to_hex(&hash))
+}
+
 #[defun]
 fn enable_debug() -> bool {
     crate::debug::enable_debug();
@@ -1007,9 +1279,9 @@ fn disable_debug() -> bool {
 /// into shorter lines.
 #[defun]
 #[elprop("[\x00-\x7F]*", _)]
-fn base64_encode_string(string: &str, line_break: OptionalFlag) -> Result<String> {
+fn base64_encode_string(string: &str, no_line_break: OptionalFlag) -> Result<String> {
     if string.is_ascii() {
-        Ok(base64_encode(string, line_break.is_some(), true, false))
+        Ok(base64_encode(string, no_line_break.is_none(), true, false))
     } else {
         Err(anyhow!("Multibyte character in data for base64 encoding"))
     }
@@ -1030,11 +1302,60 @@ fn base64url_encode_string(string: &str, no_pad: OptionalFlag) -> Result<String>
     }
 }
 
-fn base64_encode(string: &str, _line_break: bool, pad: bool, base64url: bool) -> String {
+/// The length of lines in base64 output with line breaks.
+const BASE64_LINE_LENGTH: usize = 76;
+
+fn base64_encode(string: &str, line_break: bool, pad: bool, base64url: bool) -> String {
     let config = base64::engine::GeneralPurposeConfig::new().with_encode_padding(pad);
     let alphabets = if base64url { base64::alphabet::URL_SAFE } else { base64::alphabet::STANDARD };
     let engine = base64::engine::GeneralPurpose::new(&alphabets, config);
-    engine.encode(string)
+    let encoded = engine.encode(string);
+    if !line_break {
+        return encoded;
+    }
+    // the encoded string is all ASCII, so the chunks are valid UTF-8
+    let lines: Vec<_> = encoded.as_bytes().chunks(BASE64_LINE_LENGTH).collect();
+    String::from_utf8(lines.join(&b'\n')).unwrap()
+}
+
+/// Base64-decode STRING and return the result as a string.
+///
+/// Optional argument BASE64URL means the URL variant of base 64 encoding is
+/// used. Newlines and padding are optional. If IGNORE-INVALID is non-nil,
+/// invalid characters are ignored instead of signaling an error.
+#[defun]
+fn base64_decode_string<'ob>(
+    string: &str,
+    base64url: OptionalFlag,
+    ignore_invalid: OptionalFlag,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    let alphabet = if base64url.is_some() {
+        base64::alphabet::URL_SAFE
+    } else {
+        base64::alphabet::STANDARD
+    };
+    let config = base64::engine::GeneralPurposeConfig::new()
+        .with_decode_padding_mode(base64::engine::DecodePaddingMode::Indifferent);
+    let engine = base64::engine::GeneralPurpose::new(&alphabet, config);
+    let is_valid = |c: char| match c {
+        'A'..='Z' | 'a'..='z' | '0'..='9' | '=' => true,
+        '+' | '/' => base64url.is_none(),
+        '-' | '_' => base64url.is_some(),
+        _ => false,
+    };
+    let input: String = if ignore_invalid.is_some() {
+        string.chars().filter(|c| is_valid(*c)).collect()
+    } else {
+        string.chars().filter(|c| !matches!(c, '\n' | '\r')).collect()
+    };
+    let Ok(decoded) = engine.decode(input) else { bail!("Invalid base64 data") };
+    // ASCII text is the same in unibyte and multibyte strings
+    if decoded.is_ascii() {
+        Ok(cx.add(String::from_utf8(decoded).unwrap()))
+    } else {
+        Ok(cx.add(decoded))
+    }
 }
 
 #[cfg(test)]
@@ -1045,10 +1366,21 @@ mod test {
     fn test_base64_encode_string() {
         assert_lisp("(base64-encode-string \"hello\")", "\"aGVsbG8=\"");
         assert_lisp(
-            "(base64-encode-string \"Lorem ipsum dolor sit amet, consectetur adipiscing elit, sed do eiusmod tempor incididunt ut labore et dolore magna aliqua. Ut enim ad minim veniam, quis nostrud exercitation ullamco laboris nisi ut aliquip ex ea commodo consequat. Duis aute irure dolor in reprehenderit in voluptate velit esse cillum dolore eu fugiat nulla pariatur. Excepteur sint occaecat cupidatat non proident, sunt in culpa qui officia deserunt mollit anim id est laborum\")",
+            "(base64-encode-string \"Lorem ipsum dolor sit amet, consectetur adipiscing elit, sed do eiusmod tempor incididunt ut labore et dolore magna aliqua. Ut enim ad minim veniam, quis nostrud exercitation ullamco laboris nisi ut aliquip ex ea commodo consequat. Duis aute irure dolor in reprehenderit in voluptate velit esse cillum dolore eu fugiat nulla pariatur. Excepteur sint occaecat cupidatat non proident, sunt in culpa qui officia deserunt mollit anim id est laborum\" t)",
             "\"TG9yZW0gaXBzdW0gZG9sb3Igc2l0IGFtZXQsIGNvbnNlY3RldHVyIGFkaXBpc2NpbmcgZWxpdCwgc2VkIGRvIGVpdXNtb2QgdGVtcG9yIGluY2lkaWR1bnQgdXQgbGFib3JlIGV0IGRvbG9yZSBtYWduYSBhbGlxdWEuIFV0IGVuaW0gYWQgbWluaW0gdmVuaWFtLCBxdWlzIG5vc3RydWQgZXhlcmNpdGF0aW9uIHVsbGFtY28gbGFib3JpcyBuaXNpIHV0IGFsaXF1aXAgZXggZWEgY29tbW9kbyBjb25zZXF1YXQuIER1aXMgYXV0ZSBpcnVyZSBkb2xvciBpbiByZXByZWhlbmRlcml0IGluIHZvbHVwdGF0ZSB2ZWxpdCBlc3NlIGNpbGx1bSBkb2xvcmUgZXUgZnVnaWF0IG51bGxhIHBhcmlhdHVyLiBFeGNlcHRldXIgc2ludCBvY2NhZWNhdCBjdXBpZGF0YXQgbm9uIHByb2lkZW50LCBzdW50IGluIGN1bHBhIHF1aSBvZmZpY2lhIGRlc2VydW50IG1vbGxpdCBhbmltIGlkIGVzdCBsYWJvcnVt\"",
         );
-        // assert_lisp("(base64-encode-string \"Lorem ipsum dolor sit amet, consectetur adipiscing elit, sed do eiusmod tempor incididunt ut labore et dolore magna aliqua. Ut enim ad minim veniam, quis nostrud exercitation ullamco laboris nisi ut aliquip ex ea commodo consequat. Duis aute irure dolor in reprehenderit in voluptate velit esse cillum dolore eu fugiat nulla pariatur. Excepteur sint occaecat cupidatat non proident, sunt in culpa qui officia deserunt mollit anim id est laborum\" t)", "\"TG9yZW0gaXBzdW0gZG9sb3Igc2l0IGFtZXQsIGNvbnNlY3RldHVyIGFkaXBpc2NpbmcgZWxpdCwg\nc2VkIGRvIGVpdXNtb2QgdGVtcG9yIGluY2lkaWR1bnQgdXQgbGFib3JlIGV0IGRvbG9yZSBtYWdu\nYSBhbGlxdWEuIFV0IGVuaW0gYWQgbWluaW0gdmVuaWFtLCBxdWlzIG5vc3RydWQgZXhlcmNpdGF0\naW9uIHVsbGFtY28gbGFib3JpcyBuaXNpIHV0IGFsaXF1aXAgZXggZWEgY29tbW9kbyBjb25zZXF1\nYXQuIER1aXMgYXV0ZSBpcnVyZSBkb2xvciBpbiByZXByZWhlbmRlcml0IGluIHZvbHVwdGF0ZSB2\nZWxpdCBlc3NlIGNpbGx1bSBkb2xvcmUgZXUgZnVnaWF0IG51bGxhIHBhcmlhdHVyLiBFeGNlcHRl\ndXIgc2ludCBvY2NhZWNhdCBjdXBpZGF0YXQgbm9uIHByb2lkZW50LCBzdW50IGluIGN1bHBhIHF1\naSBvZmZpY2lhIGRlc2VydW50IG1vbGxpdCBhbmltIGlkIGVzdCBsYWJvcnVt\"");
+        assert_lisp(
+            "(base64-encode-string \"Lorem ipsum dolor sit amet, consectetur adipiscing elit, sed do eiusmod tempor incididunt ut labore et dolore magna aliqua. Ut enim ad minim veniam, quis nostrud exercitation ullamco laboris nisi ut aliquip ex ea commodo consequat. Duis aute irure dolor in reprehenderit in voluptate velit esse cillum dolore eu fugiat nulla pariatur. Excepteur sint occaecat cupidatat non proident, sunt in culpa qui officia deserunt mollit anim id est laborum\")",
+            "\"TG9yZW0gaXBzdW0gZG9sb3Igc2l0IGFtZXQsIGNvbnNlY3RldHVyIGFkaXBpc2NpbmcgZWxpdCwg\nc2VkIGRvIGVpdXNtb2QgdGVtcG9yIGluY2lkaWR1bnQgdXQgbGFib3JlIGV0IGRvbG9yZSBtYWdu\nYSBhbGlxdWEuIFV0IGVuaW0gYWQgbWluaW0gdmVuaWFtLCBxdWlzIG5vc3RydWQgZXhlcmNpdGF0\naW9uIHVsbGFtY28gbGFib3JpcyBuaXNpIHV0IGFsaXF1aXAgZXggZWEgY29tbW9kbyBjb25zZXF1\nYXQuIER1aXMgYXV0ZSBpcnVyZSBkb2xvciBpbiByZXByZWhlbmRlcml0IGluIHZvbHVwdGF0ZSB2\nZWxpdCBlc3NlIGNpbGx1bSBkb2xvcmUgZXUgZnVnaWF0IG51bGxhIHBhcmlhdHVyLiBFeGNlcHRl\ndXIgc2ludCBvY2NhZWNhdCBjdXBpZGF0YXQgbm9uIHByb2lkZW50LCBzdW50IGluIGN1bHBhIHF1\naSBvZmZpY2lhIGRlc2VydW50IG1vbGxpdCBhbmltIGlkIGVzdCBsYWJvcnVt\"",
+        );
+    }
+
+    #[test]
+    fn test_featurep() {
+        assert_lisp("(featurep 'rune-test-feature)", "nil");
+        assert_lisp("(progn (provide 'rune-test-feature) (featurep 'rune-test-feature))", "t");
+        assert_lisp("(require 'rune-test-feature)", "rune-test-feature");
+        assert_lisp("(require 'rune-test-missing-feature nil t)", "nil");
     }
 
     #[test]
@@ -1104,6 +1436,26 @@ mod test {
         assert_lisp("(assq 6 '((1 . 2) (3 . 4) (5 . 6)))", "nil");
     }
 
+    #[test]
+    fn test_memq_assq_symbol_with_pos() {
+        assert_lisp(
+            "(let ((s (position-symbol 'foo 5)))
+               (list (memq s '(bar foo)) (assq s '((foo . 1)))
+                     (let ((symbols-with-pos-enabled t))
+                       (list (memq s '(bar foo)) (assq s '((foo . 1)))
+                             (and (memq 'foo (list s)) t) (and (assq 'foo (list (cons s 2))) t)))))",
+            "(nil nil ((foo) (foo . 1) t t))",
+        );
+    }
+
+    #[test]
+    fn test_concat() {
+        assert_lisp(r#"(concat "foo" nil "bar")"#, r#""foobar""#);
+        // ERT pads its status labels with a unibyte `make-string`
+        assert_lisp(r#"(concat (make-string 3 ?\s) "FAILED")"#, r#""   FAILED""#);
+        assert_lisp(r#"(aref (concat "a" (make-string 1 255)) 1)"#, "4194303");
+    }
+
     #[test]
     fn test_string_equal() {
         assert_lisp("(string-equal \"hello\" \"hello\")", "t");
@@ -1122,6 +1474,24 @@ mod test {
         assert_lisp("(string-distance \"hello\" \"hello\")", "0");
         assert_lisp("(string-distance \"hello\" \"jello\")", "1");
         assert_lisp("(string-distance \"hello\" \"world\")", "4");
+        assert_lisp(r#"(string-distance "aλ" "ab")"#, "1");
+        assert_lisp(r#"(string-distance "aλ" "ab" t)"#, "2");
+        assert_lisp(r#"(string-distance "a\377" (string-to-multibyte "a\377"))"#, "0");
+    }
+
+    #[test]
+    fn test_flex_score() {
+        assert_lisp(r#"(rune--flex-score "foo" "foo")"#, "1.0");
+        assert_lisp(r#"(rune--flex-score "foo" "foobar")"#, "0.5");
+        assert_lisp(r#"(rune--flex-score "foo" "barfoobaz")"#, "0.3333333333333333");
+        // f-oo has one hole while f-o-o has two
+        assert_lisp(r#"(rune--flex-score "foo" "fabrobazoo")"#, "0.15");
+        assert_lisp(r#"(rune--flex-score "oof" "foobar")"#, "nil");
+        assert_lisp(r#"(rune--flex-score "FB" "foobar")"#, "nil");
+        assert_lisp(
+            r#"(let ((completion-ignore-case t)) (rune--flex-score "FB" "foobar"))"#,
+            "0.16666666666666666",
+        );
     }
 
     #[test]
@@ -1255,6 +1625,57 @@ mod test {
         assert_lisp("(base64url-encode-string \"hello\" 0)", "\"aGVsbG8\"");
     }
 
+    #[test]
+    fn test_base64_decode_string() {
+        assert_lisp("(base64-decode-string \"aGVsbG8=\")", "\"hello\"");
+        assert_lisp("(base64-decode-string \"aGVs\nbG8\")", "\"hello\"");
+        assert_lisp("(equal (base64-decode-string \"P_8\" t) (unibyte-string 63 255))", "t");
+        assert_lisp("(base64-decode-string \"aGV*sbG8=\" nil t)", "\"hello\"");
+        assert_lisp(
+            "(condition-case nil (base64-decode-string \"aGV*sbG8=\") (error 'err))",
+            "err",
+        );
+    }
+
+    #[test]
+    fn test_secure_hash() {
+        assert_lisp("(md5 \"abc\")", "\"900150983cd24fb0d6963f7d28e17f72\"");
+        assert_lisp("(secure-hash 'sha1 \"abc\")", "\"a9993e364706816aba3e25717850c26c9cd0d89d\"");
+        assert_lisp(
+            "(secure-hash 'sha256 \"xxabcxx\" 2 -2)",
+            "\"ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad\"",
+        );
+        assert_lisp(
+            "(progn (insert \"abc\") (secure-hash 'sha224 (current-buffer)))",
+            "\"23097d223405d8228642a477bda255b32aadbce4bda0b3f7e36c9da7\"",
+        );
+        assert_lisp("(length (secure-hash 'sha512 \"abc\" nil nil t))", "64");
+        assert_lisp("(condition-case nil (secure-hash 'sha3 \"abc\") (error 'err))", "err");
+    }
+
+    #[test]
+    fn test_buffer_hash() {
+        assert_lisp(
+            r#"(progn (insert "abc") (goto-char 2) (insert "x") (narrow-to-region 2 3)
+                      (list (equal (buffer-hash) (secure-hash 'sha1 "axbc"))
+                            (equal (buffer-hash (buffer-name)) (buffer-hash))
+                            (equal (secure-hash 'md5 (current-buffer)) (md5 "x"))))"#,
+            "(t t t)",
+        );
+    }
+
+    #[test]
+    fn test_make_hash_table() {
+        assert_lisp(
+            "(hash-table-p (make-hash-table :test #'equal :size 10 :weakness 'key :test 'foo))",
+            "t",
+        );
+        assert_lisp("(condition-case nil (make-hash-table :test 'foo) (error 'err))", "err");
+        assert_lisp("(condition-case nil (make-hash-table :size -1) (error 'err))", "err");
+        assert_lisp("(condition-case nil (make-hash-table :test) (error 'err))", "err");
+        assert_lisp("(condition-case nil (make-hash-table :foo 1) (error 'err))", "err");
+    }
+
     #[test]
     #[cfg(miri)]
     fn test_maphash() {
@@ -1266,6 +1687,18 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_remhash_order() {
+        assert_lisp(
+            "(let ((h (make-hash-table)) (keys nil))
+               (puthash 1 1 h) (puthash 2 2 h) (puthash 3 3 h)
+               (remhash 1 h)
+               (maphash #'(lambda (k _) (setq keys (cons k keys))) h)
+               keys)",
+            "(3 2)",
+        );
+    }
+
     #[test]
     fn test_legnth() {
         assert_lisp("(length nil)", "0");
@@ -0,0 +1,466 @@
+//! The native core of font-lock.
+//!
+//! The regexps in `font-lock-keywords` are matched against whole lines, and
+//! the faces of a region are all worked out before any of them are applied,
+//! so the `face` property is set once for each run of text with the same
+//! face. Keywords whose matcher is a function are not supported and are
+//! skipped.
+//!
+//! When jit-lock is on in a buffer, the text that has yet to be fontified is
+//! kept in a queue on the buffer. Edits add the changed text to the queue,
+//! and redisplay only fontifies the queued text that it is about to show.
+use crate::core::{
+    env::{Env, sym},
+    gc::{Context, Rt},
+    object::{LispBuffer, NIL, Object, ObjectType, OpenBuffer, OptionalFlag},
+};
+use crate::fns::slice_into_list;
+use crate::library::span_queue::SpanQueue;
+use crate::textprop::set_property;
+use anyhow::{Result, bail};
+use rune_macros::defun;
+use rune_regex::{Input, Regex, RegexBuilder};
+
+defvar_local!(FONT_LOCK_KEYWORDS);
+defvar_local!(FONT_LOCK_KEYWORDS_CASE_FOLD_SEARCH);
+defvar!(FONT_LOCK_KEYWORD_FACE, sym::FONT_LOCK_KEYWORD_FACE);
+defsym!(FACE);
+defsym!(KEEP);
+defsym!(PREPEND);
+
+/// How a highlight treats text that an earlier keyword already fontified.
+#[derive(Debug, Clone, Copy)]
+enum Override {
+    /// Skip the match if any of it has a face
+    Never,
+    Always,
+    /// Only fontify the parts without a face
+    Keep,
+    /// Put the face before the existing faces
+    Prepend,
+    /// Put the face after the existing faces
+    Append,
+}
+
+/// A `(SUBEXP FACENAME [OVERRIDE [LAXMATCH]])` form of a keyword.
+struct Highlight<'ob> {
+    subexp: usize,
+    face: Object<'ob>,
+    mode: Override,
+    /// Don't signal an error if `subexp` did not match
+    laxmatch: bool,
+}
+
+struct Keyword<'ob> {
+    regex: Regex,
+    highlights: Vec<Highlight<'ob>>,
+}
+
+impl<'ob> Highlight<'ob> {
+    fn new(subexp: usize, face: Object<'ob>) -> Self {
+        Self { subexp, face, mode: Override::Never, laxmatch: false }
+    }
+
+    /// Apply the face to `faces`, the faces of the characters it matched.
+    fn apply(&self, faces: &mut [Option<Object<'ob>>], cx: &'ob Context) {
+        if self.face.is_nil() {
+            return;
+        }
+        match self.mode {
+            Override::Never => {
+                if faces.iter().all(Option::is_none) {
+                    faces.fill(Some(self.face));
+                }
+            }
+            Override::Always => faces.fill(Some(self.face)),
+            Override::Keep => {
+                for face in faces.iter_mut().filter(|x| x.is_none()) {
+                    *face = Some(self.face);
+                }
+            }
+            Override::Prepend | Override::Append => {
+                // The faces are combined once for each run with the same face
+                let mut idx = 0;
+                while idx < faces.len() {
+                    let old = faces[idx];
+                    let run = faces[idx..].iter().take_while(|x| same_face(**x, old)).count();
+                    let new = match old {
+                        Some(old) => self.combine(old, cx),
+                        None => self.face,
+                    };
+                    faces[idx..idx + run].fill(Some(new));
+                    idx += run;
+                }
+            }
+        }
+    }
+
+    /// Combine the face with `old`, which is a face or a list of faces.
+    fn combine(&self, old: Object<'ob>, cx: &'ob Context) -> Object<'ob> {
+        let mut faces: Vec<Object> = match old.untag() {
+            ObjectType::Cons(cons) => cons.elements().filter_map(Result::ok).collect(),
+            _ => vec![old],
+        };
+        match self.mode {
+            Override::Prepend => faces.insert(0, self.face),
+            _ => faces.push(self.face),
+        }
+        slice_into_list(&faces, None, cx)
+    }
+}
+
+fn same_face(a: Option<Object>, b: Option<Object>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => a.ptr_eq(b),
+        (a, b) => a.is_none() && b.is_none(),
+    }
+}
+
+/// The face named by a FACENAME form. The value of a variable like
+/// `font-lock-keyword-face` is used, and a quoted face is unquoted.
+fn face_name<'ob>(
+    form: Object<'ob>,
+    buffer: &LispBuffer,
+    env: &Rt<Env>,
+    cx: &'ob Context,
+) -> Object<'ob> {
+    match form.untag() {
+        ObjectType::Symbol(var) => env.local_value(var, buffer, cx).unwrap_or(form),
+        ObjectType::Cons(cons) if cons.car() == sym::QUOTE => match cons.cdr().untag() {
+            ObjectType::Cons(quoted) => quoted.car(),
+            _ => form,
+        },
+        _ => form,
+    }
+}
+
+/// Parse a `(SUBEXP FACENAME [OVERRIDE [LAXMATCH]])` form. Anchored
+/// highlights are not supported and return `None`.
+fn parse_highlight<'ob>(
+    highlight: Object<'ob>,
+    buffer: &LispBuffer,
+    env: &Rt<Env>,
+    cx: &'ob Context,
+) -> Result<Option<Highlight<'ob>>> {
+    let items: Vec<Object> = highlight.as_list()?.collect::<Result<_, _>>()?;
+    let Some(ObjectType::Int(subexp)) = items.first().map(|x| x.untag()) else {
+        return Ok(None);
+    };
+    let face = face_name(items.get(1).copied().unwrap_or(NIL), buffer, env, cx);
+    let mode = match items.get(2).copied().unwrap_or(NIL) {
+        x if x.is_nil() => Override::Never,
+        x if x == sym::KEEP => Override::Keep,
+        x if x == sym::PREPEND => Override::Prepend,
+        x if x == sym::APPEND => Override::Append,
+        _ => Override::Always,
+    };
+    let laxmatch = items.get(3).is_some_and(|x| !x.is_nil());
+    Ok(Some(Highlight { subexp: subexp.try_into()?, face, mode, laxmatch }))
+}
+
+/// Parse an element of `font-lock-keywords`. Returns `None` if its matcher
+/// is not a regexp.
+fn parse_keyword<'ob>(
+    keyword: Object<'ob>,
+    case_fold: bool,
+    buffer: &LispBuffer,
+    env: &Rt<Env>,
+    cx: &'ob Context,
+) -> Result<Option<Keyword<'ob>>> {
+    let keyword_face = face_name(sym::FONT_LOCK_KEYWORD_FACE.into(), buffer, env, cx);
+    let (matcher, highlights) = match keyword.untag() {
+        ObjectType::String(_) => (keyword, vec![Highlight::new(0, keyword_face)]),
+        ObjectType::Cons(cons) => {
+            let rest = cons.cdr();
+            let highlights = match rest.untag() {
+                // (MATCHER . SUBEXP)
+                ObjectType::Int(subexp) => vec![Highlight::new(subexp.try_into()?, keyword_face)],
+                // (MATCHER . 'FACENAME)
+                ObjectType::Cons(form) if form.car() == sym::QUOTE => {
+                    vec![Highlight::new(0, face_name(rest, buffer, env, cx))]
+                }
+                // (MATCHER . HIGHLIGHT)
+                ObjectType::Cons(form) if matches!(form.car().untag(), ObjectType::Int(_)) => {
+                    parse_highlight(rest, buffer, env, cx)?.into_iter().collect()
+                }
+                // (MATCHER HIGHLIGHT ...)
+                ObjectType::Cons(_) => {
+                    let mut highlights = Vec::new();
+                    for highlight in rest.as_list()? {
+                        highlights.extend(parse_highlight(highlight?, buffer, env, cx)?);
+                    }
+                    highlights
+                }
+                // (MATCHER . FACENAME)
+                _ => vec![Highlight::new(0, face_name(rest, buffer, env, cx))],
+            };
+            (cons.car(), highlights)
+        }
+        _ => return Ok(None),
+    };
+    let ObjectType::String(matcher) = matcher.untag() else { return Ok(None) };
+    let regex = RegexBuilder::new(matcher).case_fold(case_fold).build()?;
+    Ok(Some(Keyword { regex, highlights }))
+}
+
+/// Compile the `font-lock-keywords` of `buffer`.
+fn compile_keywords<'ob>(
+    buffer: &LispBuffer,
+    env: &Rt<Env>,
+    cx: &'ob Context,
+) -> Result<Vec<Keyword<'ob>>> {
+    let keywords = env.local_value(sym::FONT_LOCK_KEYWORDS, buffer, cx).unwrap_or(NIL);
+    let case_fold = env
+        .local_value(sym::FONT_LOCK_KEYWORDS_CASE_FOLD_SEARCH, buffer, cx)
+        .is_some_and(|x| !x.is_nil());
+    let mut compiled = Vec::new();
+    for keyword in keywords.as_list()? {
+        compiled.extend(parse_keyword(keyword?, case_fold, buffer, env, cx)?);
+    }
+    Ok(compiled)
+}
+
+/// Extend the character offsets `beg` and `end` to whole lines in the
+/// accessible portion of `buffer`. Returns the new start and the text of the
+/// lines.
+fn line_region(buffer: &OpenBuffer, beg: usize, end: usize) -> (usize, String) {
+    let begv = buffer.point_min() - 1;
+    let zv = buffer.point_max() - 1;
+    let beg = beg.clamp(begv, zv);
+    let end = end.clamp(beg, zv);
+    let (s1, s2) = buffer.text.slice(begv..beg);
+    let before = match s2.rfind('\n') {
+        Some(idx) => s2[idx + 1..].chars().count(),
+        None => s2.chars().count() + s1[s1.rfind('\n').map_or(0, |x| x + 1)..].chars().count(),
+    };
+    let start = beg - before;
+    let at_line_start = end == start || {
+        let (s1, s2) = buffer.text.slice(end - 1..end);
+        s1.ends_with('\n') || s2.ends_with('\n')
+    };
+    let after = if at_line_start {
+        0
+    } else {
+        let (s1, s2) = buffer.text.slice(end..zv);
+        match s1.find('\n') {
+            Some(idx) => s1[..=idx].chars().count(),
+            None => {
+                let line_end = s2.find('\n').map_or(s2.len(), |x| x + 1);
+                s1.chars().count() + s2[..line_end].chars().count()
+            }
+        }
+    };
+    let (s1, s2) = buffer.text.slice(start..end + after);
+    (start, [s1, s2].concat())
+}
+
+/// Call `func` without marking `buffer` as modified, since faces are not a
+/// change to the text.
+fn without_modifying(
+    buffer: &LispBuffer,
+    env: &mut Rt<Env>,
+    func: impl FnOnce(&mut Rt<Env>) -> Result<()>,
+) -> Result<()> {
+    let modified = env.with_buffer(buffer, |b| b.modified_p())?;
+    let result = func(env);
+    if !modified {
+        env.with_buffer_mut(buffer, |b| b.set_modified_p(false))?;
+    }
+    result
+}
+
+/// Fontify the text between the character offsets `beg` and `end` of
+/// `buffer` with `keywords`, after extending it to whole lines.
+fn fontify_region<'ob>(
+    keywords: &[Keyword<'ob>],
+    buffer: &LispBuffer,
+    beg: usize,
+    end: usize,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<()> {
+    let (beg, text) = env.with_buffer(buffer, |b| line_region(b, beg, end))?;
+    // The byte offset of each character, to convert the match positions
+    let offsets: Vec<usize> = text.char_indices().map(|x| x.0).collect();
+    let to_char = |byte: usize| offsets.partition_point(|x| *x < byte);
+    let mut faces: Vec<Option<Object>> = vec![None; offsets.len()];
+    for keyword in keywords {
+        let mut pos = 0;
+        while pos <= text.len() {
+            let input = Input::new(text.as_str()).range(pos..text.len());
+            let Some(caps) = keyword.regex.search_forward(&input)? else { break };
+            for highlight in &keyword.highlights {
+                match caps.get(highlight.subexp) {
+                    Some(range) => {
+                        let matched = &mut faces[to_char(range.start)..to_char(range.end)];
+                        highlight.apply(matched, cx);
+                    }
+                    None if highlight.laxmatch => {}
+                    None => bail!("No match {} in highlight", highlight.subexp),
+                }
+            }
+            let whole = caps.get(0).unwrap();
+            // Step over an empty match so the search moves on
+            pos = match text[whole.end..].chars().next() {
+                Some(c) if whole.is_empty() => whole.end + c.len_utf8(),
+                None if whole.is_empty() => break,
+                _ => whole.end,
+            };
+        }
+    }
+    let end = beg + faces.len();
+    without_modifying(buffer, env, |env| {
+        let mut idx = 0;
+        while idx < faces.len() {
+            let face = faces[idx];
+            let run = faces[idx..].iter().take_while(|x| same_face(**x, face)).count();
+            set_property(buffer, beg + idx, beg + idx + run, sym::FACE.into(), face, env, cx)?;
+            idx += run;
+        }
+        Ok(())
+    })?;
+    env.with_buffer_mut(buffer, |b| {
+        if let Some(queue) = &mut b.unfontified {
+            queue.take(beg, end);
+        }
+    })
+}
+
+/// Fontify the text between the character offsets `beg` and `end` of
+/// `buffer` that jit-lock has yet to fontify. Nothing is done if jit-lock is
+/// off in the buffer.
+pub(crate) fn fontify_pending(
+    buffer: &LispBuffer,
+    beg: usize,
+    end: usize,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<()> {
+    let spans =
+        env.with_buffer_mut(buffer, |b| b.unfontified.as_mut().map(|x| x.take(beg, end)))?;
+    let Some(spans) = spans.filter(|x| !x.is_empty()) else { return Ok(()) };
+    let keywords = compile_keywords(buffer, env, cx)?;
+    for (beg, end) in spans {
+        fontify_region(&keywords, buffer, beg, end, env, cx)?;
+    }
+    Ok(())
+}
+
+/// Fontify the text between BEG and END in the current buffer using
+/// `font-lock-keywords`. The region is extended to whole lines.
+#[defun]
+fn font_lock_fontify_region(
+    beg: usize,
+    end: usize,
+    _loudly: OptionalFlag,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<()> {
+    let current = env.current_buffer.get();
+    let (beg, end) = (current.in_range(beg)?, current.in_range(end)?);
+    let buffer = current.lisp_buffer(cx);
+    let keywords = compile_keywords(buffer, env, cx)?;
+    fontify_region(&keywords, buffer, beg.min(end), beg.max(end), env, cx)
+}
+
+/// Remove the faces from the text between BEG and END in the current buffer.
+#[defun]
+fn font_lock_unfontify_region(
+    beg: usize,
+    end: usize,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<()> {
+    let current = env.current_buffer.get();
+    let (beg, end) = (current.in_range(beg)?, current.in_range(end)?);
+    let buffer = current.lisp_buffer(cx);
+    without_modifying(buffer, env, |env| {
+        set_property(buffer, beg.min(end), beg.max(end), sym::FACE.into(), None, env, cx)
+    })
+}
+
+/// Turn on jit-lock in the current buffer if ARG is non-nil, or turn it off
+/// otherwise. While it is on, text is fontified when redisplay shows it.
+#[defun]
+fn jit_lock_mode(arg: OptionalFlag, env: &mut Rt<Env>) {
+    let buffer = env.current_buffer.get_mut();
+    buffer.unfontified = arg.map(|_| SpanQueue::new(0, buffer.text.len_chars()));
+}
+
+/// Make jit-lock fontify the text between START and END again. They default
+/// to the accessible portion of the current buffer.
+#[defun]
+fn jit_lock_refontify(start: Option<usize>, end: Option<usize>, env: &mut Rt<Env>) -> Result<()> {
+    let buffer = env.current_buffer.get_mut();
+    let start = buffer.in_range(start.unwrap_or(buffer.point_min()))?;
+    let end = buffer.in_range(end.unwrap_or(buffer.point_max()))?;
+    if let Some(queue) = &mut buffer.unfontified {
+        queue.add(start.min(end), start.max(end));
+    }
+    Ok(())
+}
+
+/// Fontify the text between START and END that jit-lock has yet to fontify.
+/// They default to the accessible portion of the current buffer.
+#[defun]
+fn jit_lock_fontify_now(
+    start: Option<usize>,
+    end: Option<usize>,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<()> {
+    let current = env.current_buffer.get();
+    let start = current.in_range(start.unwrap_or(current.point_min()))?;
+    let end = current.in_range(end.unwrap_or(current.point_max()))?;
+    let buffer = current.lisp_buffer(cx);
+    fontify_pending(buffer, start.min(end), start.max(end), env, cx)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::interpreter::assert_lisp;
+
+    #[test]
+    fn test_fontify_region() {
+        assert_lisp(
+            r#"(progn
+                 (insert "foo bar\nbaz foo")
+                 (setq font-lock-keywords
+                       '(("foo" . bold)
+                         ("\\(ba\\)\\(r\\|z\\)" (1 'italic) (2 underline))
+                         ("o+" 0 shadow keep)
+                         ("a" 0 'bold prepend)))
+                 (set-buffer-modified-p nil)
+                 (font-lock-fontify-region 1 2)
+                 (let ((first-line (list (get-text-property 1 'face) (get-text-property 3 'face)
+                                         (get-text-property 4 'face) (get-text-property 6 'face)
+                                         (get-text-property 7 'face)
+                                         (get-text-property 10 'face))))
+                   (font-lock-fontify-region 9 (point-max))
+                   (list first-line (get-text-property 10 'face) (get-text-property 13 'face)
+                         (buffer-modified-p)
+                         (progn (font-lock-unfontify-region 1 (point-max))
+                                (next-property-change 1)))))"#,
+            "((bold bold nil (bold italic) underline nil) (bold italic) bold nil nil)",
+        );
+    }
+
+    #[test]
+    fn test_jit_lock() {
+        assert_lisp(
+            r#"(progn
+                 (setq font-lock-keywords '(("foo" . bold)))
+                 (insert "foo\nfoo")
+                 (jit-lock-mode t)
+                 (let ((before (get-text-property 1 'face)))
+                   (jit-lock-fontify-now 1 3)
+                   (let ((first (list (get-text-property 1 'face) (get-text-property 5 'face))))
+                     (goto-char (point-max))
+                     (insert " foo")
+                     (jit-lock-fontify-now)
+                     (list before first (get-text-property 5 'face)
+                           (get-text-property 9 'face)))))"#,
+            "(nil (bold nil) bold bold)",
+        );
+    }
+}
@@ -0,0 +1,251 @@
+//! Frames.
+//!
+//! There is a single terminal frame, which is the root of the window tree.
+//! It is always the size of the terminal, so its `width` and `height` are
+//! read from the terminal whenever they are asked for and can't be set. The
+//! `name` and `buffer-list` parameters have defaults until they are set, and
+//! other parameters are stored as they are given.
+//!
+//! A resize of the terminal is noted by the SIGWINCH handler. The command
+//! loop then runs `window-size-change-functions` before it redisplays.
+use crate::core::{
+    cons::Cons,
+    env::{Env, sym},
+    error::{Type, TypeError},
+    gc::{Context, Rt, Rto},
+    object::{Function, IntoObject, NIL, Object, ObjectType, RecordBuilder, Symbol},
+};
+use crate::fns::{assq, slice_into_list};
+use crate::rooted_iter;
+use crate::window::init_windows;
+use crate::xdisp::{frame_size, invalidate_display};
+use anyhow::{Result, bail};
+use fallible_streaming_iterator::FallibleStreamingIterator;
+use rune_core::macros::call;
+use rune_macros::defun;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+defsym!(FRAME);
+defsym!(NAME);
+defsym!(WIDTH);
+defsym!(HEIGHT);
+defvar!(WINDOW_SIZE_CHANGE_FUNCTIONS);
+
+/// Set by the SIGWINCH handler and cleared when the resize hooks are run.
+static RESIZED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn handle_resize(_: libc::c_int) {
+    RESIZED.store(true, Ordering::Relaxed);
+}
+
+/// Note resizes of the terminal. The handler is installed without
+/// `SA_RESTART`, so waiting for a key is interrupted and the command loop
+/// can redraw at the new size straight away.
+pub(crate) fn install_resize_handler() {
+    let handler = handle_resize as extern "C" fn(libc::c_int);
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = handler as libc::sighandler_t;
+        libc::sigemptyset(&raw mut action.sa_mask);
+        libc::sigaction(libc::SIGWINCH, &raw const action, std::ptr::null_mut());
+    }
+}
+
+/// The frame object, which is made the first time it is needed.
+pub(crate) fn frame_object<'ob>(env: &mut Rt<Env>, cx: &'ob Context) -> Object<'ob> {
+    if env.frame.bind(cx).is_nil() {
+        let mut record = cx.vec_with_capacity(1);
+        record.push(sym::FRAME.into());
+        let frame: Object = RecordBuilder(record).into_obj(cx).into();
+        env.frame.set(frame);
+    }
+    env.frame.bind(cx)
+}
+
+fn is_frame(obj: Object) -> bool {
+    match obj.untag() {
+        ObjectType::Record(rec) => rec.first().is_some_and(|x| x.get() == sym::FRAME),
+        _ => false,
+    }
+}
+
+/// Check that FRAME is the frame. Nil stands for the selected frame, which
+/// is the only one.
+fn frame_arg(frame: Option<Object>) -> Result<()> {
+    match frame {
+        Some(frame) if !is_frame(frame) => bail!(TypeError::new(Type::Frame, frame)),
+        _ => Ok(()),
+    }
+}
+
+/// The parameters of the frame as an alist, with the defaults of parameters
+/// that haven't been set.
+fn parameters<'ob>(env: &mut Rt<Env>, cx: &'ob Context) -> Result<Object<'ob>> {
+    init_windows(env, cx);
+    let stored = env.frame_parameters.bind(cx);
+    let is_set =
+        |param: Symbol| assq(param.into(), stored.try_into()?, env, cx).map(|x| !x.is_nil());
+    let size = frame_size();
+    // The last row of the terminal is the echo area
+    let mut alist: Vec<Object> = vec![
+        Cons::new(sym::WIDTH, size.cols, cx).into(),
+        Cons::new(sym::HEIGHT, size.rows.saturating_sub(1), cx).into(),
+    ];
+    if !is_set(sym::NAME)? {
+        alist.push(Cons::new(sym::NAME, "F1", cx).into());
+    }
+    if !is_set(sym::BUFFER_LIST)? {
+        let buffers: Vec<Object> =
+            env.window_tree.buffers().into_iter().map(|x| cx.add(x)).collect();
+        alist.push(Cons::new(sym::BUFFER_LIST, slice_into_list(&buffers, None, cx), cx).into());
+    }
+    Ok(slice_into_list(&alist, Some(stored), cx))
+}
+
+/// Set the frame parameter `param` to `value`. The size of a terminal frame
+/// follows the terminal, so `width` and `height` are ignored.
+fn set_parameter(param: Symbol, value: Object, env: &mut Rt<Env>, cx: &Context) -> Result<()> {
+    if param == sym::WIDTH || param == sym::HEIGHT {
+        return Ok(());
+    }
+    let mut alist = vec![Cons::new(param, value, cx).into()];
+    for elem in env.frame_parameters.bind(cx).as_list()? {
+        let elem = elem?;
+        if let ObjectType::Cons(cons) = elem.untag()
+            && cons.car() != param
+        {
+            alist.push(elem);
+        }
+    }
+    env.frame_parameters.set(slice_into_list(&alist, None, cx));
+    Ok(())
+}
+
+/// Run `window-size-change-functions` with the frame if the terminal has
+/// been resized since the last time this was called.
+pub(crate) fn run_resize_hooks(env: &mut Rt<Env>, cx: &mut Context) -> Result<()> {
+    if !RESIZED.swap(false, Ordering::Relaxed) {
+        return Ok(());
+    }
+    // The terminal may have rewrapped what was on the screen
+    invalidate_display();
+    let functions = env.vars.get(sym::WINDOW_SIZE_CHANGE_FUNCTIONS).map_or(NIL, |x| x.bind(cx));
+    let functions = match functions.untag() {
+        ObjectType::Cons(_) | ObjectType::NIL => functions,
+        _ => Cons::new1(functions, cx).into(),
+    };
+    rooted_iter!(functions, functions, cx);
+    while let Some(func) = functions.next()? {
+        let func: &Rto<Function> = func.try_as()?;
+        let frame = frame_object(env, cx);
+        call!(func, frame; env, cx)?;
+    }
+    Ok(())
+}
+
+#[defun]
+fn framep(object: Object) -> bool {
+    is_frame(object)
+}
+
+#[defun]
+fn frame_live_p(object: Object) -> bool {
+    is_frame(object)
+}
+
+#[defun]
+fn selected_frame<'ob>(env: &mut Rt<Env>, cx: &'ob Context) -> Object<'ob> {
+    frame_object(env, cx)
+}
+
+#[defun]
+fn frame_list<'ob>(env: &mut Rt<Env>, cx: &'ob Context) -> Object<'ob> {
+    let frame = frame_object(env, cx);
+    Cons::new1(frame, cx).into()
+}
+
+/// Return the parameters of FRAME as an alist. The `width` and `height` are
+/// the size of the terminal in columns and lines, not counting the echo
+/// area. Unless they have been set, `name` is "F1" and `buffer-list` is the
+/// buffers shown in the windows of the frame.
+#[defun]
+fn frame_parameters<'ob>(
+    frame: Option<Object>,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    frame_arg(frame)?;
+    parameters(env, cx)
+}
+
+/// Return the value of PARAMETER of FRAME, or nil if it has none.
+#[defun]
+fn frame_parameter<'ob>(
+    frame: Object,
+    parameter: Symbol,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    let frame = (!frame.is_nil()).then_some(frame);
+    frame_arg(frame)?;
+    let params = parameters(env, cx)?;
+    Ok(match assq(parameter.into(), params.try_into()?, env, cx)?.untag() {
+        ObjectType::Cons(cons) => cons.cdr(),
+        _ => NIL,
+    })
+}
+
+/// Set the parameters of FRAME from ALIST, a list of (PARAMETER . VALUE).
+/// The size of a terminal frame follows the terminal, so `width` and
+/// `height` are ignored.
+#[defun]
+fn modify_frame_parameters(
+    frame: Object,
+    alist: Object,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<()> {
+    let frame = (!frame.is_nil()).then_some(frame);
+    frame_arg(frame)?;
+    for elem in alist.as_list()? {
+        let cons: &Cons = elem?.try_into()?;
+        set_parameter(cons.car().try_into()?, cons.cdr(), env, cx)?;
+    }
+    Ok(())
+}
+
+/// Set PARAMETER of FRAME to VALUE. See `modify-frame-parameters`.
+#[defun]
+fn set_frame_parameter(
+    frame: Object,
+    parameter: Symbol,
+    value: Object,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<()> {
+    let frame = (!frame.is_nil()).then_some(frame);
+    frame_arg(frame)?;
+    set_parameter(parameter, value, env, cx)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::interpreter::assert_lisp;
+
+    #[test]
+    fn test_frame_parameters() {
+        assert_lisp(
+            "(list (framep (selected-frame)) (eq (car (frame-list)) (selected-frame))
+                   (eq (window-frame) (selected-frame)) (framep (selected-window))
+                   (frame-parameter nil 'name) (<= 0 (frame-parameter nil 'width))
+                   (equal (frame-parameter nil 'buffer-list) (list (current-buffer)))
+                   (progn (set-frame-parameter nil 'name \"main\")
+                          (modify-frame-parameters (selected-frame) '((width . 3) (test . 1)))
+                          (list (frame-parameter nil 'name) (frame-parameter nil 'test)
+                                (eq (frame-parameter nil 'width) 3)
+                                (length (frame-parameters))))
+                   (condition-case nil (frame-parameters 1) (error 'error)))",
+            "(t t t nil \"F1\" t t (\"main\" 1 nil 5) error)",
+        );
+    }
+}
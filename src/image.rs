@@ -0,0 +1,177 @@
+//! Image descriptors.
+//!
+//! There is no window system, so images can never be displayed. An image is
+//! only a descriptor of the form `(image :type TYPE PROPS...)`, which can be
+//! created and checked so that packages that show images when they can still
+//! load and run in batch mode.
+use crate::{
+    core::{
+        cons::Cons,
+        env::sym,
+        gc::Context,
+        object::{List, Object, ObjectType, Symbol},
+    },
+    fns::slice_into_list,
+};
+use anyhow::{Result, bail, ensure};
+use rune_macros::defun;
+
+/// The image types and the file extensions they are guessed from.
+const IMAGE_TYPES: [(Symbol<'static>, &[&str]); 8] = [
+    (sym::PNG, &["png"]),
+    (sym::JPEG, &["jpg", "jpeg"]),
+    (sym::GIF, &["gif"]),
+    (sym::TIFF, &["tif", "tiff"]),
+    (sym::SVG, &["svg"]),
+    (sym::WEBP, &["webp"]),
+    (sym::XPM, &["xpm"]),
+    (sym::PBM, &["pbm"]),
+];
+
+/// Guess the type of the image in the file `name` from its extension.
+fn type_of_file(name: &str) -> Option<Symbol<'static>> {
+    let (_, ext) = name.rsplit_once('.')?;
+    let ext = ext.to_ascii_lowercase();
+    IMAGE_TYPES
+        .iter()
+        .find(|(_, exts)| exts.contains(&ext.as_str()))
+        .map(|(ty, _)| *ty)
+}
+
+/// Guess the type of the image in `data` from its header.
+fn type_of_data(data: &str) -> Option<Symbol<'static>> {
+    let header = data.trim_start();
+    let ty = if data.starts_with("\u{89}PNG") {
+        sym::PNG
+    } else if data.starts_with("\u{ff}\u{d8}") {
+        sym::JPEG
+    } else if data.starts_with("GIF8") {
+        sym::GIF
+    } else if data.starts_with("II*\0") || data.starts_with("MM\0*") {
+        sym::TIFF
+    } else if data.starts_with("RIFF") && data.get(8..12) == Some("WEBP") {
+        sym::WEBP
+    } else if data.starts_with("/* XPM */") {
+        sym::XPM
+    } else if header.starts_with("<svg") || header.starts_with("<?xml") {
+        sym::SVG
+    } else if data.starts_with('P') && data[1..].starts_with(['1', '2', '3', '4', '5', '6']) {
+        sym::PBM
+    } else {
+        return None;
+    };
+    Some(ty)
+}
+
+/// Return t if OBJECT is an image descriptor, a list of the form (image
+/// :type TYPE ...) with a `:file` or `:data` property.
+#[defun]
+fn valid_image_p(object: Object) -> Result<bool> {
+    let ObjectType::Cons(cons) = object.untag() else { return Ok(false) };
+    if cons.car() != sym::IMAGE {
+        return Ok(false);
+    }
+    let Ok(props) = List::try_from(cons.cdr()) else { return Ok(false) };
+    let props: Vec<_> = props.elements().collect::<Result<_, _>>()?;
+    if props.len() % 2 != 0 {
+        return Ok(false);
+    }
+    let get = |key: Symbol| props.chunks(2).find(|x| x[0] == key).map(|x| x[1]);
+    let typed = get(sym::KW_TYPE).is_some_and(|x| matches!(x.untag(), ObjectType::Symbol(_)));
+    let source = [sym::KW_FILE, sym::KW_DATA].into_iter().any(|x| get(x).is_some());
+    Ok(typed && source)
+}
+
+/// Return non-nil if images of TYPE can be displayed. Without a window
+/// system no image type is available, so this always returns nil.
+#[defun]
+fn image_type_available_p(_image_type: Symbol) -> bool {
+    false
+}
+
+/// Create an image descriptor for FILE-OR-DATA. If DATA-P is nil,
+/// FILE-OR-DATA is the name of an image file, otherwise it is the image data
+/// itself. TYPE is the image type, or nil to guess it from the file name or
+/// data. PROPS are added to the descriptor.
+///
+/// The image is not loaded, since there is no window system to display it.
+#[defun]
+fn create_image<'ob>(
+    file_or_data: &str,
+    image_type: Option<Symbol>,
+    data_p: Option<Object>,
+    props: &[Object<'ob>],
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    ensure!(
+        props.len().is_multiple_of(2),
+        "Image properties must be a property list: {props:?}"
+    );
+    let data_p = data_p.is_some_and(|x| !x.is_nil());
+    let guessed = if data_p { type_of_data(file_or_data) } else { type_of_file(file_or_data) };
+    let Some(ty) = image_type.or(guessed) else { bail!("Cannot determine image type") };
+    let source = if data_p { sym::KW_DATA } else { sym::KW_FILE };
+    let mut plist = vec![sym::KW_TYPE.into(), ty.into(), source.into(), cx.add(file_or_data)];
+    plist.extend_from_slice(props);
+    Ok(Cons::new(sym::IMAGE, slice_into_list(&plist, None, cx), cx).into())
+}
+
+/// Return the size of image SPEC as a pair (WIDTH . HEIGHT), in canonical
+/// characters or in pixels if PIXELS is non-nil. Images are never
+/// displayed without a window system, so the size is always 0.
+#[defun]
+fn image_size<'ob>(
+    spec: Object,
+    pixels: Option<Object>,
+    _frame: Option<Object>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    ensure!(valid_image_p(spec)?, "Invalid image specification: {spec}");
+    Ok(match pixels {
+        Some(x) if !x.is_nil() => Cons::new(0, 0, cx).into(),
+        _ => Cons::new(0.0, 0.0, cx).into(),
+    })
+}
+
+/// Flush the cached image SPEC. Nothing is cached, so this does nothing.
+#[defun]
+fn image_flush(spec: Object, _frame: Option<Object>) -> Result<()> {
+    ensure!(valid_image_p(spec)?, "Invalid image specification: {spec}");
+    Ok(())
+}
+
+defsym!(IMAGE);
+defsym!(KW_TYPE);
+defsym!(KW_FILE);
+defsym!(KW_DATA);
+defsym!(PNG);
+defsym!(JPEG);
+defsym!(GIF);
+defsym!(TIFF);
+defsym!(SVG);
+defsym!(WEBP);
+defsym!(XPM);
+defsym!(PBM);
+
+#[cfg(test)]
+mod test {
+    use crate::interpreter::assert_lisp;
+
+    #[test]
+    fn test_create_image() {
+        assert_lisp(
+            r#"(create-image "/tmp/a.PNG" nil nil :ascent 'center)"#,
+            r#"(image :type png :file "/tmp/a.PNG" :ascent center)"#,
+        );
+        assert_lisp(r#"(create-image "GIF89a" nil t)"#, r#"(image :type gif :data "GIF89a")"#);
+        assert_lisp(r#"(create-image "x" 'svg t)"#, r#"(image :type svg :data "x")"#);
+        assert_lisp(r#"(condition-case nil (create-image "a.txt") (error 'failed))"#, "failed");
+        assert_lisp(
+            r#"(let ((image (create-image "a.jpg")))
+                 (list (valid-image-p image) (valid-image-p '(image :type png))
+                       (image-size image) (image-size image t)
+                       (image-type-available-p 'png)))"#,
+            "(t nil (0.0 . 0.0) (0 . 0) nil)",
+        );
+    }
+}
@@ -0,0 +1,406 @@
+//! Columns and indentation.
+use crate::character::CharWidth;
+use crate::core::{
+    env::{Env, sym},
+    gc::{Context, Rt, Rto},
+    object::{Object, ObjectType, OpenBuffer, OptionalFlag},
+};
+use crate::editfns::{delete_region, forward_line, insert_text, line_beginning, line_end};
+use anyhow::{Result, bail};
+use rune_macros::defun;
+
+defvar!(TAB_STOP_LIST);
+
+/// The column after `chr` when it starts at `column`. A tab moves to the next
+/// tab stop.
+pub(crate) fn next_column(column: usize, chr: char, widths: &CharWidth) -> usize {
+    if chr == '\t' {
+        let tab_width = widths.tab_width();
+        (column / tab_width + 1) * tab_width
+    } else {
+        column + widths.of(chr)
+    }
+}
+
+/// Return the horizontal position of point. The beginning of the line is
+/// column 0.
+#[defun]
+pub(crate) fn current_column(env: &Rt<Env>, cx: &Context) -> Result<usize> {
+    let widths = CharWidth::new(env, cx);
+    let buffer = env.current_buffer.get();
+    column_at(buffer, buffer.point(), &widths)
+}
+
+/// The column of `pos`, which must be in the accessible portion of the buffer.
+pub(crate) fn column_at(buffer: &OpenBuffer, pos: usize, widths: &CharWidth) -> Result<usize> {
+    let text = &buffer.text;
+    let line = text.char_to_line(buffer.in_range(pos)?);
+    let bol = (text.line_to_char(line) + 1).max(buffer.point_min());
+    let (s1, s2) = buffer.slice_with_gap(bol, pos)?;
+    Ok(s1
+        .chars()
+        .chain(s2.chars())
+        .fold(0, |column, chr| next_column(column, chr, widths)))
+}
+
+/// Move point to column COLUMN in the current line and return the column
+/// that was reached. If COLUMN is in the middle of a character, point is left
+/// after it, and if the line is too short point is left at the end of it.
+///
+/// If FORCE is non-nil and COLUMN is in the middle of a tab, the tab is
+/// changed to spaces when `indent-tabs-mode` is nil, and otherwise spaces are
+/// inserted before it to reach COLUMN. If FORCE is t and the line is too
+/// short, it is indented to COLUMN.
+#[defun]
+fn move_to_column(
+    column: usize,
+    force: Option<&Rto<Object>>,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<usize> {
+    let force = match force.map(|x| x.bind(cx)) {
+        Some(x) if x == sym::TRUE => Force::Indent,
+        Some(x) if !x.is_nil() => Force::Tabs,
+        _ => Force::No,
+    };
+    move_to_column_force(column, force, env, cx)
+}
+
+/// The FORCE argument of `move-to-column`.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Force {
+    /// Only move point
+    No,
+    /// Change a tab that COLUMN is in the middle of to spaces
+    Tabs,
+    /// Also indent a line that is too short to COLUMN
+    Indent,
+}
+
+/// Move point to `column` like `move-to-column`.
+pub(crate) fn move_to_column_force(
+    column: usize,
+    force: Force,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<usize> {
+    let (pos, reached, tab_start) = {
+        let widths = CharWidth::new(env, cx);
+        let buffer = env.current_buffer.get();
+        let bol = line_beginning(buffer, 1);
+        let (s1, s2) = buffer.slice_with_gap(bol, line_end(buffer, 1))?;
+        let (mut pos, mut reached, mut tab_start) = (bol, 0, None);
+        for chr in s1.chars().chain(s2.chars()) {
+            if reached >= column {
+                break;
+            }
+            tab_start = (chr == '\t').then_some(reached);
+            reached = next_column(reached, chr, &widths);
+            pos += 1;
+        }
+        (pos, reached, tab_start)
+    };
+    env.current_buffer.get_mut().goto_char(pos);
+    if force == Force::No {
+        return Ok(reached);
+    }
+    if let (Some(start), true) = (tab_start, reached > column) {
+        let tabs = env.vars.get(sym::INDENT_TABS_MODE).is_some_and(|x| !x.bind(cx).is_nil());
+        let tab = pos - 1;
+        if tabs {
+            env.current_buffer.get_mut().goto_char(tab);
+            insert_text(&" ".repeat(column - start), env, cx)?;
+        } else {
+            delete_region(tab, pos, env, cx)?;
+            env.current_buffer.get_mut().goto_char(tab);
+            insert_text(&" ".repeat(reached - start), env, cx)?;
+            env.current_buffer.get_mut().goto_char(tab + column - start);
+        }
+        return Ok(column);
+    }
+    if reached < column && force == Force::Indent {
+        return indent_to(column, None, env, cx);
+    }
+    Ok(reached)
+}
+
+/// Indent from point with tabs and spaces until COLUMN is reached, and return
+/// COLUMN. At least MINIMUM spaces are inserted, even if that goes past
+/// COLUMN. Tabs are only used if `indent-tabs-mode` is non-nil.
+#[defun]
+pub(crate) fn indent_to(
+    column: usize,
+    minimum: Option<usize>,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<usize> {
+    let from = current_column(env, cx)?;
+    let target = column.max(from + minimum.unwrap_or(0));
+    let tab_width = CharWidth::new(env, cx).tab_width();
+    let tabs = env.vars.get(sym::INDENT_TABS_MODE).is_some_and(|x| !x.bind(cx).is_nil());
+    let mut indent = String::new();
+    let mut reached = from;
+    while tabs && (reached / tab_width + 1) * tab_width <= target {
+        indent.push('\t');
+        reached = (reached / tab_width + 1) * tab_width;
+    }
+    indent.push_str(&" ".repeat(target - reached));
+    if !indent.is_empty() {
+        insert_text(&indent, env, cx)?;
+    }
+    Ok(target)
+}
+
+/// The position after the spaces and tabs at the start of the line beginning
+/// at `bol`, and the column they reach.
+fn indentation(buffer: &OpenBuffer, bol: usize, widths: &CharWidth) -> Result<(usize, usize)> {
+    let (s1, s2) = buffer.slice_with_gap(bol, line_end(buffer, 1).max(bol))?;
+    let (mut pos, mut column) = (bol, 0);
+    for chr in s1.chars().chain(s2.chars()).take_while(|x| matches!(x, ' ' | '\t')) {
+        column = next_column(column, chr, widths);
+        pos += 1;
+    }
+    Ok((pos, column))
+}
+
+/// Return the indentation of the current line, the column of the first
+/// character that is not a space or a tab.
+#[defun]
+fn current_indentation(env: &Rt<Env>, cx: &Context) -> Result<usize> {
+    let widths = CharWidth::new(env, cx);
+    let buffer = env.current_buffer.get();
+    Ok(indentation(buffer, line_beginning(buffer, 1), &widths)?.1)
+}
+
+/// Move point to the first character on the current line that is not a space
+/// or a tab.
+#[defun]
+fn back_to_indentation(env: &mut Rt<Env>, cx: &Context) -> Result<()> {
+    let pos = {
+        let widths = CharWidth::new(env, cx);
+        let buffer = env.current_buffer.get();
+        indentation(buffer, line_beginning(buffer, 1), &widths)?.0
+    };
+    env.current_buffer.get_mut().goto_char(pos);
+    Ok(())
+}
+
+/// Indent the current line to COLUMN, replacing its existing indentation, and
+/// move point to the end of the indentation. The line is left alone if it is
+/// already indented to COLUMN.
+#[defun]
+pub(crate) fn indent_line_to(column: usize, env: &mut Rt<Env>, cx: &mut Context) -> Result<()> {
+    let (bol, end, current) = {
+        let widths = CharWidth::new(env, cx);
+        let buffer = env.current_buffer.get();
+        let bol = line_beginning(buffer, 1);
+        let (end, current) = indentation(buffer, bol, &widths)?;
+        (bol, end, current)
+    };
+    if current == column {
+        env.current_buffer.get_mut().goto_char(end);
+        return Ok(());
+    }
+    delete_region(bol, end, env, cx)?;
+    env.current_buffer.get_mut().goto_char(bol);
+    indent_to(column, None, env, cx)?;
+    Ok(())
+}
+
+/// Indent all lines starting in the region between START and END by ARG
+/// columns, or unindent them if ARG is negative. Lines are never indented
+/// past column 0, and lines that are blank have their whitespace removed. A
+/// line whose start is before START is not changed.
+#[defun]
+fn indent_rigidly(
+    start: usize,
+    end: usize,
+    arg: &Rto<Object>,
+    _interactive: OptionalFlag,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<()> {
+    let arg: i64 = arg.bind(cx).try_into()?;
+    let (start, mut end) = (start.min(end), start.max(end));
+    let mut point = env.current_buffer.get().point();
+    let buffer = env.current_buffer.get_mut();
+    buffer.goto_char(start);
+    if line_beginning(buffer, 1) != buffer.point() {
+        forward_line(Some(1), env);
+    }
+    while env.current_buffer.get().point() < end {
+        let (bol, old_end, blank, current) = {
+            let widths = CharWidth::new(env, cx);
+            let buffer = env.current_buffer.get();
+            let bol = buffer.point();
+            let (indent_end, current) = indentation(buffer, bol, &widths)?;
+            (bol, indent_end, indent_end == line_end(buffer, 1), current)
+        };
+        let column = if blank { 0 } else { (current as i64 + arg).max(0) as usize };
+        indent_line_to(column, env, cx)?;
+        // keep END and point on the same text
+        let new_end = env.current_buffer.get().point();
+        let delta = new_end as isize - old_end as isize;
+        end = end.saturating_add_signed(delta);
+        if point >= old_end {
+            point = point.saturating_add_signed(delta);
+        } else if point > bol {
+            point = point.min(new_end);
+        }
+        if forward_line(Some(1), env) != 0 {
+            break;
+        }
+    }
+    env.current_buffer.get_mut().goto_char(point);
+    Ok(())
+}
+
+/// The first tab stop after `column`. `tab-stop-list` lists the tab stops,
+/// and after its last element they repeat with the distance between its last
+/// two, or every `tab-width` columns if it has fewer elements.
+fn next_tab_stop(column: usize, env: &Rt<Env>, cx: &Context) -> Result<usize> {
+    let tab_width = CharWidth::new(env, cx).tab_width();
+    let list = env.vars.get(sym::TAB_STOP_LIST).map(|x| x.bind(cx));
+    let mut stops = Vec::new();
+    for stop in list.map(Object::as_list).transpose()?.into_iter().flatten() {
+        let stop = stop?;
+        match stop.untag() {
+            ObjectType::Int(x) if x >= 0 => stops.push(x as usize),
+            _ => bail!("Invalid tab stop: {stop}"),
+        }
+    }
+    if let Some(&stop) = stops.iter().find(|&&x| x > column) {
+        return Ok(stop);
+    }
+    let (last, step) = match stops[..] {
+        [.., prev, last] if last > prev => (last, last - prev),
+        [.., last] => (last, tab_width),
+        [] => (0, tab_width),
+    };
+    Ok(last + step * ((column - last) / step + 1))
+}
+
+/// Delete the spaces and tabs before point and indent to the next tab stop.
+/// See `tab-stop-list` for where the tab stops are.
+#[defun]
+fn tab_to_tab_stop(env: &mut Rt<Env>, cx: &mut Context) -> Result<()> {
+    let column = next_tab_stop(current_column(env, cx)?, env, cx)?;
+    let (start, pt) = {
+        let buffer = env.current_buffer.get();
+        let (s1, s2) = buffer.slice_with_gap(line_beginning(buffer, 1), buffer.point())?;
+        let spaces = s1.chars().chain(s2.chars()).rev().take_while(|x| matches!(x, ' ' | '\t'));
+        (buffer.point() - spaces.count(), buffer.point())
+    };
+    delete_region(start, pt, env, cx)?;
+    env.current_buffer.get_mut().goto_char(start);
+    indent_to(column, None, env, cx)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::interpreter::assert_lisp;
+
+    #[test]
+    fn test_current_column() {
+        assert_lisp(r#"(progn (insert "ab\ncd") (current-column))"#, "2");
+        assert_lisp(r#"(progn (insert "a\tb") (current-column))"#, "9");
+        assert_lisp(r#"(let ((tab-width 4)) (insert "a\tb") (current-column))"#, "5");
+        assert_lisp(r#"(progn (insert "中文x") (current-column))"#, "5");
+    }
+
+    #[test]
+    fn test_move_to_column() {
+        assert_lisp(
+            r#"(progn (insert "abc\nabcdef") (list (move-to-column 4) (point) (move-to-column 20) (point)))"#,
+            "(4 9 6 11)",
+        );
+        assert_lisp(
+            r#"(progn (insert "中文") (goto-char 1) (list (move-to-column 1) (point)))"#,
+            "(2 2)",
+        );
+        // FORCE changes a tab to spaces
+        assert_lisp(
+            r#"(progn (insert "a\tb") (list (move-to-column 3 t) (point) (buffer-string)))"#,
+            r#"(3 4 "a       b")"#,
+        );
+        assert_lisp(
+            r#"(let ((indent-tabs-mode t))
+                 (insert "a\tb") (list (move-to-column 3 t) (point) (buffer-string)))"#,
+            r#"(3 4 "a  \tb")"#,
+        );
+        // t extends a short line
+        assert_lisp(
+            r#"(progn (insert "ab") (list (move-to-column 5 t) (buffer-string)))"#,
+            r#"(5 "ab   ")"#,
+        );
+    }
+
+    #[test]
+    fn test_indent_to() {
+        assert_lisp(
+            r#"(progn (insert "ab") (list (indent-to 10) (buffer-string)))"#,
+            r#"(10 "ab        ")"#,
+        );
+        assert_lisp(
+            r#"(let ((indent-tabs-mode t)) (insert "ab") (list (indent-to 10) (buffer-string)))"#,
+            r#"(10 "ab\t  ")"#,
+        );
+        assert_lisp(
+            r#"(progn (insert "abc") (list (indent-to 2 1) (buffer-string)))"#,
+            r#"(4 "abc ")"#,
+        );
+    }
+
+    #[test]
+    fn test_current_indentation() {
+        assert_lisp(
+            r#"(progn (insert "  \t x\nfoo") (list (current-indentation) (point)
+                      (progn (forward-line -1) (back-to-indentation) (current-indentation))
+                      (point)))"#,
+            "(0 10 9 5)",
+        );
+    }
+
+    #[test]
+    fn test_indent_line_to() {
+        assert_lisp(
+            r#"(progn (insert "\t  foo") (indent-line-to 4) (list (point) (buffer-string)))"#,
+            r#"(5 "    foo")"#,
+        );
+        assert_lisp(
+            r#"(let ((indent-tabs-mode t))
+                 (insert "foo") (indent-line-to 10) (list (point) (buffer-string)))"#,
+            r#"(4 "\t  foo")"#,
+        );
+    }
+
+    #[test]
+    fn test_indent_rigidly() {
+        assert_lisp(
+            r#"(progn (insert "a\n  b\n   \n    c")
+                      (goto-char 5)
+                      (indent-rigidly 1 (point-max) -3)
+                      (list (point) (buffer-string)))"#,
+            r#"(3 "a\nb\n\n c")"#,
+        );
+        assert_lisp(
+            r#"(progn (insert "a\nb") (indent-rigidly 2 (point-max) 2) (buffer-string))"#,
+            r#""a\n  b""#,
+        );
+    }
+
+    #[test]
+    fn test_tab_to_tab_stop() {
+        assert_lisp(
+            r#"(progn (insert "ab  ") (tab-to-tab-stop) (tab-to-tab-stop) (buffer-string))"#,
+            r#""ab              ""#,
+        );
+        assert_lisp(
+            r#"(let ((tab-stop-list '(4 6)))
+                 (insert "a") (tab-to-tab-stop) (tab-to-tab-stop) (tab-to-tab-stop)
+                 (current-column))"#,
+            "8",
+        );
+    }
+}
@@ -5,7 +5,10 @@ use crate::{
         env::{CallFrame, Env, sym},
         error::{Type, TypeError},
         gc::{Context, Rt, Rto, Slot},
-        object::{Function, Gc, List, ListType, NIL, Object, ObjectType, Symbol, TRUE, TagType},
+        object::{
+            Function, Gc, LispBuffer, List, ListType, NIL, Object, ObjectType, Symbol, TRUE,
+            TagType,
+        },
     },
     data::LispError,
     eval::{ErrorType, EvalError, EvalResult, add_trace},
@@ -60,6 +63,7 @@ impl Interpreter<'_, '_> {
         cons: &Rto<Gc<&Cons>>,
         cx: &'ob mut Context,
     ) -> EvalResult<'ob> {
+        crate::keyboard::maybe_quit(self.env, cx)?;
         let cons = cons.bind(cx);
         let forms = cons.cdr();
         root!(forms, cx);
@@ -85,6 +89,9 @@ impl Interpreter<'_, '_> {
                 sym::CONDITION_CASE => self.condition_case(forms, cx),
                 sym::SAVE_CURRENT_BUFFER => self.save_current_buffer(forms, cx),
                 sym::SAVE_EXCURSION => self.save_excursion(forms, cx),
+                sym::SAVE_RESTRICTION => self.save_restriction(forms, cx),
+                sym::SAVE_MATCH_DATA => self.save_match_data(forms, cx),
+                sym::WITH_CURRENT_BUFFER => self.with_current_buffer(forms, cx),
                 sym::UNWIND_PROTECT => self.unwind_protect(forms, cx),
                 _ => {
                     root!(sym, cx);
@@ -151,7 +158,7 @@ impl Interpreter<'_, '_> {
             // (defvar x)
             None => NIL,
         };
-        self.env.defvar(name.bind(cx), value)?;
+        self.env.defvar(name.bind(cx), value, cx)?;
         Ok(value)
     }
 
@@ -161,7 +168,7 @@ impl Interpreter<'_, '_> {
         args: &Rto<Object>,
         cx: &'ob mut Context,
     ) -> EvalResult<'ob> {
-        let Some(func) = sym.bind(cx).follow_indirect(cx) else {
+        let Some(func) = sym.bind(cx).indirect_function(cx)? else {
             bail_err!("Invalid function: {sym}")
         };
         root!(func, cx);
@@ -442,7 +449,7 @@ impl Interpreter<'_, '_> {
 
     fn var_set(&mut self, name: Symbol, new_value: Object, cx: &Context) -> AnyResult<()> {
         let mut iter = self.vars.iter().rev();
-        match iter.find(|cons| (cons.car(cx) == name)) {
+        match iter.find(|cons| cons.car(cx) == name) {
             Some(value) => {
                 value.bind(cx).set_cdr(new_value).expect("variables should never be immutable");
                 Ok(())
@@ -597,15 +604,73 @@ impl Interpreter<'_, '_> {
         }
     }
 
+    /// Evaluate `forms` as a progn, then call `restore` whether or not it
+    /// succeeded.
+    fn implicit_progn_and_restore<'ob>(
+        &mut self,
+        forms: ElemStreamIter<'_>,
+        cx: &'ob mut Context,
+        restore: impl FnOnce(&mut Self, &Context),
+    ) -> EvalResult<'ob> {
+        match self.implicit_progn(forms, cx) {
+            Ok(x) => {
+                root!(x, cx);
+                restore(self, cx);
+                Ok(x.bind(cx))
+            }
+            Err(e) => {
+                restore(self, cx);
+                Err(e)
+            }
+        }
+    }
+
     fn save_excursion<'ob>(&mut self, form: &Rto<Object>, cx: &'ob mut Context) -> EvalResult<'ob> {
-        let point = self.env.current_buffer.get().text.cursor();
+        let mut excursion = Some(self.env.current_buffer.get_mut().save_excursion());
         let buffer = self.env.current_buffer.get().lisp_buffer(cx);
         root!(buffer, cx);
-        let result = rebind!(self.eval_progn(form, cx)?);
-        self.env.set_buffer(buffer.bind(cx));
-        let buf = self.env.current_buffer.get_mut();
-        buf.text.set_cursor(point.chars());
-        Ok(result)
+        rooted_iter!(forms, form, cx);
+        self.implicit_progn_and_restore(forms, cx, |this, cx| {
+            let buffer = buffer.bind(cx);
+            // If the buffer was killed there is nothing to restore
+            let restore = this.env.with_buffer_mut(buffer, |b| {
+                if let Some(excursion) = excursion.take() {
+                    b.restore_excursion(excursion);
+                }
+            });
+            if restore.is_ok() {
+                this.env.set_buffer(buffer, cx);
+            }
+        })
+    }
+
+    fn save_restriction<'ob>(
+        &mut self,
+        form: &Rto<Object>,
+        cx: &'ob mut Context,
+    ) -> EvalResult<'ob> {
+        let narrowing = self.env.current_buffer.get().narrowing();
+        let buffer = self.env.current_buffer.get().lisp_buffer(cx);
+        root!(buffer, cx);
+        rooted_iter!(forms, form, cx);
+        self.implicit_progn_and_restore(forms, cx, |this, cx| {
+            let _ = this.env.with_buffer_mut(buffer.bind(cx), |b| b.set_narrowing(narrowing));
+        })
+    }
+
+    fn save_match_data<'ob>(
+        &mut self,
+        form: &Rto<Object>,
+        cx: &'ob mut Context,
+    ) -> EvalResult<'ob> {
+        let match_data = self.env.match_data.clone();
+        let buffer = self.env.match_buffer.bind(cx);
+        root!(buffer, cx);
+        rooted_iter!(forms, form, cx);
+        self.implicit_progn_and_restore(forms, cx, |this, cx| {
+            this.env.match_data = match_data;
+            this.env.match_buffer.set(buffer.bind(cx));
+        })
     }
 
     fn save_current_buffer<'ob>(
@@ -615,9 +680,35 @@ impl Interpreter<'_, '_> {
     ) -> EvalResult<'ob> {
         let buffer = self.env.current_buffer.get().lisp_buffer(cx);
         root!(buffer, cx);
-        let result = rebind!(self.eval_progn(form, cx)?);
-        self.env.set_buffer(buffer.bind(cx));
-        Ok(result)
+        rooted_iter!(forms, form, cx);
+        self.implicit_progn_and_restore(forms, cx, |this, cx| {
+            this.restore_buffer(buffer.bind(cx), cx);
+        })
+    }
+
+    /// Make `buffer` current again, unless it was killed.
+    fn restore_buffer(&mut self, buffer: &LispBuffer, cx: &Context) {
+        if self.env.with_buffer(buffer, |_| {}).is_ok() {
+            self.env.set_buffer(buffer, cx);
+        }
+    }
+
+    fn with_current_buffer<'ob>(
+        &mut self,
+        form: &Rto<Object>,
+        cx: &'ob mut Context,
+    ) -> EvalResult<'ob> {
+        rooted_iter!(forms, form, cx);
+        let Some(buffer_or_name) = forms.next()? else {
+            bail_err!(LispError::arg_cnt(sym::WITH_CURRENT_BUFFER, 1, 0, cx))
+        };
+        let buffer = self.env.current_buffer.get().lisp_buffer(cx);
+        root!(buffer, cx);
+        let new = rebind!(self.eval_form(buffer_or_name, cx)?);
+        crate::buffer::set_buffer(new, self.env, cx)?;
+        self.implicit_progn_and_restore(forms, cx, |this, cx| {
+            this.restore_buffer(buffer.bind(cx), cx);
+        })
     }
 
     fn condition_case<'ob>(&mut self, form: &Rto<Object>, cx: &'ob mut Context) -> EvalResult<'ob> {
@@ -633,28 +724,44 @@ impl Interpreter<'_, '_> {
             Ok(x) => return Ok(rebind!(x, cx)),
             Err(e) => e,
         };
-        if matches!(err.error, ErrorType::Throw(_)) {
+        if matches!(err.error, ErrorType::Throw(_) | ErrorType::Exit(_)) {
             return Err(err);
         }
+        let quit = err.is_quit(self.env, cx);
         while let Some(handler) = forms.next()? {
             match handler.untag(cx) {
                 ObjectType::Cons(cons) => {
                     // Check that conditions match
                     let condition = cons.car();
-                    match condition.untag() {
-                        ObjectType::Symbol(sym::ERROR | sym::VOID_VARIABLE) => {}
+                    let matches = match condition.untag() {
+                        ObjectType::Symbol(sym::ERROR | sym::VOID_VARIABLE) => !quit,
+                        ObjectType::Symbol(sym::QUIT) => quit,
                         // TODO: Remove this once error handling is correctly implemented
-                        ObjectType::Symbol(s) if s.name() == "cl--generic-cyclic-definition" => {}
+                        ObjectType::Symbol(s) if s.name() == "cl--generic-cyclic-definition" => {
+                            !quit
+                        }
+                        ObjectType::Symbol(s) => err.has_condition(s, self.env, cx),
                         ObjectType::Cons(conditions) => {
+                            let mut matches = false;
                             for condition in conditions {
                                 let condition = condition?;
                                 // TODO: Handle different error symbols
-                                if condition != sym::DEBUG && condition != sym::ERROR {
+                                if condition == sym::QUIT {
+                                    matches |= quit;
+                                } else if condition == sym::DEBUG || condition == sym::ERROR {
+                                    matches |= !quit;
+                                } else if let ObjectType::Symbol(s) = condition.untag() {
+                                    matches |= err.has_condition(s, self.env, cx);
+                                } else {
                                     bail_err!("non-error conditions {condition} not yet supported")
                                 }
                             }
+                            matches
                         }
                         _ => bail_err!("Invalid condition handler: {condition}"),
+                    };
+                    if !matches {
+                        continue;
                     }
 
                     // Call handlers with error
@@ -1125,4 +1232,47 @@ mod test {
         check_error("(throw 1 2)", cx);
         check_error("(catch 2 (throw 3 4))", cx);
     }
+
+    #[test]
+    fn test_save_excursion() {
+        assert_lisp(
+            r#"(progn (insert "hello") (goto-char 3) (save-excursion (goto-char 1) (insert "xx")) (point))"#,
+            "5",
+        );
+        assert_lisp(
+            r#"(progn (insert "hello") (goto-char 3)
+                (condition-case err (save-excursion (goto-char 5) (signal 'error nil)) (error nil))
+                (point))"#,
+            "3",
+        );
+        assert_lisp(
+            r#"(progn (insert "hello") (goto-char 3)
+                (catch 'done (save-excursion (goto-char 5) (throw 'done nil)))
+                (point))"#,
+            "3",
+        );
+    }
+
+    #[test]
+    fn test_save_current_buffer() {
+        assert_lisp(
+            r#"(let ((orig (current-buffer)))
+                (save-current-buffer (set-buffer (get-buffer-create "save-current-buffer-test")))
+                (eq orig (current-buffer)))"#,
+            "t",
+        );
+        assert_lisp(
+            r#"(let ((orig (current-buffer)))
+                (condition-case err
+                    (with-current-buffer (get-buffer-create "with-current-buffer-test")
+                      (signal 'error nil))
+                  (error nil))
+                (eq orig (current-buffer)))"#,
+            "t",
+        );
+        assert_lisp(
+            r#"(with-current-buffer (get-buffer-create "with-current-buffer-test") (buffer-name))"#,
+            r#""with-current-buffer-test""#,
+        );
+    }
 }
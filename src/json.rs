@@ -0,0 +1,447 @@
+//! Parsing and serializing JSON.
+//!
+//! Parsing converts JSON directly into lisp objects as it is read, without
+//! building an intermediate `serde_json::Value` tree.
+use crate::core::{
+    cons::Cons,
+    env::{ArgSlice, Env, intern, sym},
+    gc::{Context, Rt},
+    object::{HashTable, Object, ObjectType, TRUE},
+};
+use crate::eval::EvalError;
+use crate::fns::slice_into_list;
+use anyhow::{Result, bail};
+use rune_core::hashmap::{HashMap, HashSet};
+use rune_core::macros::list;
+use rune_macros::defun;
+use serde::de::{DeserializeSeed, Deserializer, IgnoredAny, MapAccess, SeqAccess, Visitor};
+use serde::ser::{self, Serialize, SerializeMap, SerializeSeq, Serializer};
+use std::fmt;
+
+defsym!(JSON_PARSE_ERROR);
+defsym!(JSON_END_OF_FILE);
+defsym!(KW_OBJECT_TYPE);
+defsym!(KW_ARRAY_TYPE);
+defsym!(KW_NULL_OBJECT);
+defsym!(KW_FALSE_OBJECT);
+defsym!(KW_NULL);
+defsym!(KW_FALSE);
+defsym!(ALIST);
+defsym!(PLIST);
+defsym!(ARRAY);
+
+#[derive(Clone, Copy)]
+enum ObjectKind {
+    HashTable,
+    Alist,
+    Plist,
+}
+
+#[derive(Clone, Copy)]
+enum ArrayKind {
+    Array,
+    List,
+}
+
+/// The representation of JSON values, set by keyword arguments.
+pub(crate) struct Config<'ob> {
+    object_type: ObjectKind,
+    array_type: ArrayKind,
+    null_object: Object<'ob>,
+    false_object: Object<'ob>,
+}
+
+impl<'ob> Config<'ob> {
+    pub(crate) fn new(args: &[Object<'ob>]) -> Result<Self> {
+        let mut config = Config {
+            object_type: ObjectKind::HashTable,
+            array_type: ArrayKind::Array,
+            null_object: sym::KW_NULL.into(),
+            false_object: sym::KW_FALSE.into(),
+        };
+        for pair in args.chunks(2) {
+            let &[key, value] = pair else { bail!("Missing value for keyword {}", pair[0]) };
+            match key.untag() {
+                ObjectType::Symbol(sym::KW_OBJECT_TYPE) => {
+                    config.object_type = match value.untag() {
+                        ObjectType::Symbol(sym::HASH_TABLE) => ObjectKind::HashTable,
+                        ObjectType::Symbol(sym::ALIST) => ObjectKind::Alist,
+                        ObjectType::Symbol(sym::PLIST) => ObjectKind::Plist,
+                        _ => bail!("Invalid :object-type {value}"),
+                    }
+                }
+                ObjectType::Symbol(sym::KW_ARRAY_TYPE) => {
+                    config.array_type = match value.untag() {
+                        ObjectType::Symbol(sym::ARRAY) => ArrayKind::Array,
+                        ObjectType::Symbol(sym::LIST) => ArrayKind::List,
+                        _ => bail!("Invalid :array-type {value}"),
+                    }
+                }
+                ObjectType::Symbol(sym::KW_NULL_OBJECT) => config.null_object = value,
+                ObjectType::Symbol(sym::KW_FALSE_OBJECT) => config.false_object = value,
+                _ => bail!("Invalid keyword argument {key}"),
+            }
+        }
+        Ok(config)
+    }
+}
+
+/// Deserializes a JSON value into a lisp object.
+#[derive(Clone, Copy)]
+struct ObjectSeed<'a, 'ob> {
+    config: &'a Config<'ob>,
+    cx: &'ob Context<'ob>,
+}
+
+impl<'de, 'ob> DeserializeSeed<'de> for ObjectSeed<'_, 'ob> {
+    type Value = Object<'ob>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de, 'ob> Visitor<'de> for ObjectSeed<'_, 'ob> {
+    type Value = Object<'ob>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a JSON value")
+    }
+
+    fn visit_unit<E>(self) -> Result<Self::Value, E> {
+        Ok(self.config.null_object)
+    }
+
+    fn visit_bool<E>(self, v: bool) -> Result<Self::Value, E> {
+        Ok(if v { TRUE } else { self.config.false_object })
+    }
+
+    fn visit_i64<E>(self, v: i64) -> Result<Self::Value, E> {
+        Ok(self.cx.add(v))
+    }
+
+    fn visit_u64<E>(self, v: u64) -> Result<Self::Value, E> {
+        match i64::try_from(v) {
+            Ok(v) => Ok(self.cx.add(v)),
+            Err(_) => Ok(self.cx.add(v as f64)),
+        }
+    }
+
+    fn visit_f64<E>(self, v: f64) -> Result<Self::Value, E> {
+        Ok(self.cx.add(v))
+    }
+
+    fn visit_str<E>(self, v: &str) -> Result<Self::Value, E> {
+        Ok(self.cx.add(v))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut elements = Vec::with_capacity(seq.size_hint().unwrap_or_default());
+        while let Some(element) = seq.next_element_seed(self)? {
+            elements.push(element);
+        }
+        Ok(match self.config.array_type {
+            ArrayKind::Array => self.cx.add(elements),
+            ArrayKind::List => slice_into_list(&elements, None, self.cx),
+        })
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let cx = self.cx;
+        match self.config.object_type {
+            ObjectKind::HashTable => {
+                // Lisp hash tables don't compare string keys by value, so
+                // duplicate keys are tracked by name. The last value wins.
+                let mut indices: HashMap<String, usize> = HashMap::default();
+                let mut entries: Vec<(Object, Object)> = Vec::new();
+                while let Some(key) = map.next_key::<String>()? {
+                    let value = map.next_value_seed(self)?;
+                    if let Some(&i) = indices.get(&key) {
+                        entries[i].1 = value;
+                    } else {
+                        indices.insert(key.clone(), entries.len());
+                        entries.push((cx.add(key), value));
+                    }
+                }
+                let mut table = HashTable::with_hasher(std::hash::BuildHasherDefault::default());
+                table.extend(entries);
+                Ok(cx.add(table))
+            }
+            ObjectKind::Alist => {
+                let mut pairs: Vec<Object> = Vec::new();
+                while let Some(key) = map.next_key::<String>()? {
+                    let value = map.next_value_seed(self)?;
+                    pairs.push(Cons::new(intern(&key, cx), value, cx).into());
+                }
+                Ok(slice_into_list(&pairs, None, cx))
+            }
+            ObjectKind::Plist => {
+                let mut plist: Vec<Object> = Vec::new();
+                while let Some(key) = map.next_key::<String>()? {
+                    let value = map.next_value_seed(self)?;
+                    plist.push(intern(&format!(":{key}"), cx).into());
+                    plist.push(value);
+                }
+                Ok(slice_into_list(&plist, None, cx))
+            }
+        }
+    }
+}
+
+/// Signal a `json-parse-error` or `json-end-of-file` for `error`.
+fn parse_error(error: &serde_json::Error, env: &mut Rt<Env>, cx: &Context) -> anyhow::Error {
+    let symbol = if error.is_eof() { sym::JSON_END_OF_FILE } else { sym::JSON_PARSE_ERROR };
+    let line = error.line() as i64;
+    let column = error.column() as i64;
+    let data = list![cx.add(error.to_string()), cx.add(line), cx.add(column); cx];
+    EvalError::signal(symbol.into(), data, env).into()
+}
+
+pub(crate) fn parse<'ob>(
+    text: &str,
+    config: &Config<'ob>,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    let mut deserializer = serde_json::Deserializer::from_str(text);
+    let seed = ObjectSeed { config, cx };
+    let object = seed.deserialize(&mut deserializer).and_then(|obj| {
+        deserializer.end()?;
+        Ok(obj)
+    });
+    object.map_err(|e| parse_error(&e, env, cx))
+}
+
+/// Parse the JSON STRING into a lisp object.
+///
+/// The keyword arguments `:object-type` (`hash-table`, `alist` or `plist`),
+/// `:array-type` (`array` or `list`), `:null-object` and `:false-object`
+/// control how JSON values are represented.
+#[defun]
+fn json_parse_string<'ob>(
+    string: &str,
+    args: ArgSlice,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    let args: Vec<Object> = Rt::bind_slice(env.stack.arg_slice(args), cx).to_vec();
+    let config = Config::new(&args)?;
+    parse(string, &config, env, cx)
+}
+
+/// Parse the JSON value after point in the current buffer and move point
+/// past it. Accepts the same keyword arguments as `json-parse-string`.
+#[defun]
+fn json_parse_buffer<'ob>(
+    args: ArgSlice,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    let args: Vec<Object> = Rt::bind_slice(env.stack.arg_slice(args), cx).to_vec();
+    let config = Config::new(&args)?;
+    let buffer = env.current_buffer.get();
+    let point = buffer.point();
+    let text = {
+        let (s1, s2) = buffer.slice_with_gap(point, buffer.point_max())?;
+        [s1, s2].concat()
+    };
+    // Find where the first value ends without converting it, so that the text
+    // after it is not an error.
+    let mut stream = serde_json::Deserializer::from_str(&text).into_iter::<IgnoredAny>();
+    let end = match stream.next() {
+        Some(Ok(_)) => stream.byte_offset(),
+        Some(Err(e)) => return Err(parse_error(&e, env, cx)),
+        None => {
+            let data = list![cx.add("EOF while parsing a value"); cx];
+            return Err(EvalError::signal(sym::JSON_END_OF_FILE.into(), data, env).into());
+        }
+    };
+    let object = parse(&text[..end], &config, env, cx)?;
+    let point = point + text[..end].chars().count();
+    env.current_buffer.get_mut().goto_char(point);
+    Ok(object)
+}
+
+/// Serializes a lisp object as JSON.
+struct Json<'a, 'ob> {
+    object: Object<'ob>,
+    config: &'a Config<'ob>,
+}
+
+impl<'a, 'ob> Json<'a, 'ob> {
+    fn new(object: Object<'ob>, config: &'a Config<'ob>) -> Self {
+        Json { object, config }
+    }
+
+    /// The name of an object key. Plist keys have their leading colon
+    /// removed.
+    fn key<E: ser::Error>(key: Object, plist: bool) -> Result<String, E> {
+        match key.untag() {
+            ObjectType::String(s) => Ok((**s).to_owned()),
+            ObjectType::Symbol(s) if plist => Ok(s.name().trim_start_matches(':').to_owned()),
+            ObjectType::Symbol(s) => Ok(s.name().to_owned()),
+            _ => Err(E::custom(format!("Wrong type argument: symbolp, {key}"))),
+        }
+    }
+
+    /// Serialize `pairs` as a JSON object. Later duplicates of a key are
+    /// ignored.
+    fn serialize_pairs<S: Serializer>(
+        &self,
+        pairs: &[(Object<'ob>, Object<'ob>)],
+        plist: bool,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let mut seen = HashSet::default();
+        let mut map = serializer.serialize_map(None)?;
+        for (key, value) in pairs {
+            let key = Self::key(*key, plist)?;
+            if seen.insert(key.clone()) {
+                map.serialize_entry(&key, &Json::new(*value, self.config))?;
+            }
+        }
+        map.end()
+    }
+}
+
+impl Serialize for Json<'_, '_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let obj = self.object;
+        if obj == self.config.null_object {
+            return serializer.serialize_unit();
+        }
+        if obj == self.config.false_object {
+            return serializer.serialize_bool(false);
+        }
+        let invalid = || ser::Error::custom(format!("Wrong type argument: json-value-p, {obj}"));
+        match obj.untag() {
+            ObjectType::TRUE => serializer.serialize_bool(true),
+            ObjectType::NIL => serializer.serialize_map(Some(0))?.end(),
+            ObjectType::Int(x) => serializer.serialize_i64(x),
+            ObjectType::Float(x) if x.is_finite() => serializer.serialize_f64(**x),
+            ObjectType::String(s) => serializer.serialize_str(s),
+            ObjectType::Vec(vec) => {
+                let mut seq = serializer.serialize_seq(Some(vec.len()))?;
+                for element in vec.iter() {
+                    seq.serialize_element(&Json::new(element.get(), self.config))?;
+                }
+                seq.end()
+            }
+            ObjectType::HashTable(table) => {
+                let pairs: Vec<_> = (0..table.len()).filter_map(|i| table.get_index(i)).collect();
+                self.serialize_pairs(&pairs, false, serializer)
+            }
+            ObjectType::Cons(cons) => {
+                let elements: Vec<_> =
+                    cons.elements().collect::<Result<_, _>>().map_err(|_| invalid())?;
+                if let ObjectType::Cons(_) = cons.car().untag() {
+                    let mut pairs = Vec::new();
+                    for element in elements {
+                        let ObjectType::Cons(pair) = element.untag() else { return Err(invalid()) };
+                        pairs.push((pair.car(), pair.cdr()));
+                    }
+                    self.serialize_pairs(&pairs, false, serializer)
+                } else {
+                    if elements.len() % 2 != 0 {
+                        return Err(invalid());
+                    }
+                    let pairs: Vec<_> = elements.chunks_exact(2).map(|x| (x[0], x[1])).collect();
+                    self.serialize_pairs(&pairs, true, serializer)
+                }
+            }
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// Return the JSON representation of OBJECT as a string.
+///
+/// Hash tables, alists and plists are serialized as JSON objects and vectors
+/// as JSON arrays. The keyword arguments `:null-object` and `:false-object`
+/// give the objects that represent JSON `null` and `false`.
+#[defun]
+fn json_serialize<'ob>(object: Object<'ob>, args: &[Object<'ob>]) -> Result<String> {
+    serialize(object, &Config::new(args)?)
+}
+
+pub(crate) fn serialize<'ob>(object: Object<'ob>, config: &Config<'ob>) -> Result<String> {
+    Ok(serde_json::to_string(&Json::new(object, config))?)
+}
+
+#[defun]
+#[expect(non_snake_case)]
+fn json__available_p() -> bool {
+    true
+}
+
+#[cfg(test)]
+mod test {
+    use crate::interpreter::assert_lisp;
+
+    #[test]
+    fn test_json_parse_string() {
+        assert_lisp(
+            r#"(json-parse-string "[1, 2.5, \"a\", true, false, null]")"#,
+            r#"[1 2.5 "a" t :false :null]"#,
+        );
+        assert_lisp(
+            r#"(json-parse-string "[[1], {}]" :array-type 'list :object-type 'alist)"#,
+            "((1) nil)",
+        );
+        assert_lisp(
+            r#"(json-parse-string "{\"a\": 1, \"b\": [true]}" :object-type 'alist)"#,
+            "((a . 1) (b . [t]))",
+        );
+        assert_lisp(
+            r#"(json-parse-string "{\"a\": null, \"b\": false}" :object-type 'plist :null-object nil :false-object 'no)"#,
+            "(:a nil :b no)",
+        );
+        assert_lisp(
+            r#"(let ((table (json-parse-string "{\"a\": 1, \"b\": 2, \"a\": 3}")) (pairs nil))
+                 (maphash #'(lambda (k v) (setq pairs (cons (cons k v) pairs))) table)
+                 (nreverse pairs))"#,
+            r#"(("a" . 3) ("b" . 2))"#,
+        );
+        assert_lisp(
+            r#"(condition-case err (json-parse-string "[1,") (error (car err)))"#,
+            "json-end-of-file",
+        );
+        assert_lisp(
+            r#"(condition-case err (json-parse-string "[1] x") (error (car err)))"#,
+            "json-parse-error",
+        );
+    }
+
+    #[test]
+    fn test_json_parse_buffer() {
+        assert_lisp(
+            r#"(progn
+                 (insert " {\"a\": [1, 2]} tail")
+                 (goto-char (point-min))
+                 (list (json-parse-buffer :object-type 'alist) (point)))"#,
+            "(((a . [1 2])) 15)",
+        );
+    }
+
+    #[test]
+    fn test_json_serialize() {
+        assert_lisp(
+            r#"(json-serialize [1 2.5 "a\n" t :false :null])"#,
+            r#""[1,2.5,\"a\\n\",true,false,null]""#,
+        );
+        assert_lisp(r#"(json-serialize nil)"#, r#""{}""#);
+        assert_lisp(r#"(json-serialize '((a . 1) (b . [2]) (a . 3)))"#, r#""{\"a\":1,\"b\":[2]}""#);
+        assert_lisp(
+            r#"(json-serialize '(:a 1 :b nil) :null-object nil)"#,
+            r#""{\"a\":1,\"b\":null}""#,
+        );
+        assert_lisp(
+            r#"(json-serialize (json-parse-string "{\"x\": {\"y\": []}}"))"#,
+            r#""{\"x\":{\"y\":[]}}""#,
+        );
+        assert_lisp(
+            r#"(condition-case nil (json-serialize (list 1 2 3)) (error 'invalid))"#,
+            "invalid",
+        );
+    }
+}
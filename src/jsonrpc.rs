@@ -0,0 +1,226 @@
+//! The transport layer of JSON-RPC, as used by LSP.
+//!
+//! Messages are framed with a `Content-Length` header, which gives the length
+//! of the JSON body in bytes. A transport is a record of the form
+//! `#s(jsonrpc-transport PENDING QUEUE ARGS)`. PENDING is the text received
+//! from the process that does not form a complete message yet, QUEUE is the
+//! list of parsed messages that have not been dispatched, and ARGS are the
+//! keyword arguments passed to the JSON parser. Output from a process filter
+//! is fed to the transport, which parses the complete messages natively so
+//! that jsonrpc.el only sees lisp objects.
+use crate::{
+    core::{
+        env::{Env, sym},
+        error::{Type, TypeError},
+        gc::{Context, Rt, Rto},
+        object::{Function, List, NIL, Object, ObjectType, Record, RecordBuilder},
+    },
+    fns::slice_into_list,
+    json::{Config, parse, serialize},
+};
+use anyhow::{Result, bail};
+use rune_core::macros::call;
+use rune_macros::defun;
+
+const PENDING: usize = 1;
+const QUEUE: usize = 2;
+const ARGS: usize = 3;
+
+fn as_transport<'ob>(obj: Object<'ob>) -> Result<&'ob Record> {
+    match obj.untag() {
+        ObjectType::Record(rec)
+            if rec.first().is_some_and(|x| x.get() == sym::JSONRPC_TRANSPORT) =>
+        {
+            Ok(rec)
+        }
+        _ => Err(TypeError::new(Type::JsonrpcTransport, obj).into()),
+    }
+}
+
+/// Split the first complete message off the front of `data`. Returns the
+/// range of the body and the length of the whole message, or `None` if more
+/// data is needed.
+fn next_frame(data: &str) -> Result<Option<(std::ops::Range<usize>, usize)>> {
+    let Some(header_end) = data.find("\r\n\r\n") else { return Ok(None) };
+    let mut length = None;
+    for line in data[..header_end].split("\r\n") {
+        let Some((name, value)) = line.split_once(':') else {
+            bail!("Invalid JSON-RPC header: {line}")
+        };
+        if name.trim().eq_ignore_ascii_case("content-length") {
+            length = Some(value.trim().parse::<usize>()?);
+        }
+    }
+    let Some(length) = length else { bail!("JSON-RPC message has no Content-Length header") };
+    let start = header_end + 4;
+    let end = start + length;
+    if data.len() < end {
+        return Ok(None);
+    }
+    if !data.is_char_boundary(end) {
+        bail!("JSON-RPC Content-Length {length} does not end on a character boundary");
+    }
+    Ok(Some((start..end, end)))
+}
+
+/// Return a new JSON-RPC transport. ARGS are the keyword arguments used to
+/// parse incoming messages, as in `json-parse-string`.
+#[defun]
+fn jsonrpc_make_transport<'ob>(args: &[Object<'ob>], cx: &'ob Context) -> Result<Object<'ob>> {
+    // Check the arguments up front instead of for every message
+    Config::new(args)?;
+    let mut record = cx.vec_with_capacity(ARGS + 1);
+    let args = slice_into_list(args, None, cx);
+    record.extend([sym::JSONRPC_TRANSPORT.into(), cx.add(""), NIL, args]);
+    Ok(cx.add(RecordBuilder(record)))
+}
+
+/// Return t if OBJECT is a JSON-RPC transport.
+#[defun]
+fn jsonrpc_transport_p(object: Object) -> bool {
+    as_transport(object).is_ok()
+}
+
+/// Add STRING, output received from the other end, to TRANSPORT and parse all
+/// the messages it completes. Return the number of messages waiting to be
+/// dispatched.
+///
+/// A message that is not valid JSON is dropped and signals a
+/// `json-parse-error`. The messages after it are read by the next call.
+#[defun]
+fn jsonrpc_transport_feed<'ob>(
+    transport: Object<'ob>,
+    string: &str,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<usize> {
+    let record = as_transport(transport)?;
+    let pending: &str = record[PENDING].get().try_into()?;
+    let mut data = [pending, string].concat();
+    let mut queue: Vec<Object> =
+        List::try_from(record[QUEUE].get())?.elements().collect::<Result<_, _>>()?;
+    let args: Vec<Object> =
+        List::try_from(record[ARGS].get())?.elements().collect::<Result<_, _>>()?;
+    let config = Config::new(&args)?;
+    let mut result = Ok(());
+    let mut consumed = 0;
+    while let Some((body, len)) = next_frame(&data[consumed..])? {
+        let body = &data[consumed..][body];
+        consumed += len;
+        match parse(body, &config, env, cx) {
+            Ok(message) => queue.push(message),
+            Err(e) => {
+                result = Err(e);
+                break;
+            }
+        }
+    }
+    data.replace_range(..consumed, "");
+    let record = record.try_mut()?;
+    record[PENDING].set(cx.add(data));
+    record[QUEUE].set(slice_into_list(&queue, None, cx));
+    result.map(|()| queue.len())
+}
+
+/// Remove the next message from TRANSPORT and return it, or return nil if
+/// there is none.
+#[defun]
+fn jsonrpc_transport_next(transport: Object) -> Result<Object> {
+    let record = as_transport(transport)?;
+    match record[QUEUE].get().untag() {
+        ObjectType::Cons(cons) => {
+            record.try_mut()?[QUEUE].set(cons.cdr());
+            Ok(cons.car())
+        }
+        _ => Ok(NIL),
+    }
+}
+
+/// Call FUNCTION with each message waiting in TRANSPORT, in the order they
+/// were received, and return the number of messages dispatched. Each message
+/// is removed from the queue before FUNCTION is called, so an error in
+/// FUNCTION leaves the remaining messages queued.
+#[defun]
+fn jsonrpc_transport_dispatch(
+    transport: &Rto<Object>,
+    function: &Rto<Function>,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<usize> {
+    let mut count = 0;
+    loop {
+        let record = as_transport(transport.bind(cx))?;
+        let ObjectType::Cons(cons) = record[QUEUE].get().untag() else { return Ok(count) };
+        record.try_mut()?[QUEUE].set(cons.cdr());
+        let message = cons.car();
+        call!(function, message; env, cx)?;
+        count += 1;
+    }
+}
+
+/// Return OBJECT serialized as JSON and framed with a `Content-Length`
+/// header, ready to be sent to a JSON-RPC process. ARGS are the keyword
+/// arguments of `json-serialize`.
+#[defun]
+fn jsonrpc_frame_message<'ob>(object: Object<'ob>, args: &[Object<'ob>]) -> Result<String> {
+    let body = serialize(object, &Config::new(args)?)?;
+    Ok(format!("Content-Length: {}\r\n\r\n{body}", body.len()))
+}
+
+defsym!(JSONRPC_TRANSPORT);
+
+#[cfg(test)]
+mod test {
+    use super::next_frame;
+    use crate::interpreter::assert_lisp;
+
+    #[test]
+    fn test_next_frame() {
+        assert_eq!(next_frame("Content-Length: 2\r\n\r\n{}").unwrap(), Some((21..23, 23)));
+        assert_eq!(next_frame("Content-Length: 2\r\n\r\n{").unwrap(), None);
+        assert_eq!(next_frame("Content-Length: 2\r\n").unwrap(), None);
+        assert_eq!(
+            next_frame("content-length: 1\r\nContent-Type: x\r\n\r\n1 rest").unwrap(),
+            Some((38..39, 39))
+        );
+        assert!(next_frame("Content-Type: x\r\n\r\n{}").is_err());
+        assert!(next_frame("Content-Length: 1\r\n\r\né").is_err());
+    }
+
+    #[test]
+    fn test_jsonrpc_transport() {
+        assert_lisp(
+            r#"(let ((transport (jsonrpc-make-transport :object-type 'plist))
+                     (msg (jsonrpc-frame-message '(:id 1 :result "é"))))
+                 (list (jsonrpc-transport-p transport)
+                       (jsonrpc-transport-feed transport (substring msg 0 10))
+                       (jsonrpc-transport-feed transport (concat (substring msg 10) msg))
+                       (jsonrpc-transport-next transport)
+                       (jsonrpc-transport-next transport)
+                       (jsonrpc-transport-next transport)))"#,
+            r#"(t 0 2 (:id 1 :result "é") (:id 1 :result "é") nil)"#,
+        );
+        assert_lisp(
+            r#"(let ((transport (jsonrpc-make-transport :object-type 'alist)) (ids nil))
+                 (jsonrpc-transport-feed
+                  transport "Content-Length: 8\r\n\r\n{\"id\":1}Content-Length: 8\r\n\r\n{\"id\":2}")
+                 (list (jsonrpc-transport-dispatch
+                        transport #'(lambda (msg) (setq ids (cons (cdr (assq 'id msg)) ids))))
+                       ids))"#,
+            "(2 (2 1))",
+        );
+        assert_lisp(
+            r#"(let ((transport (jsonrpc-make-transport)))
+                 (list (condition-case err
+                           (jsonrpc-transport-feed
+                            transport "Content-Length: 1\r\n\r\n[Content-Length: 2\r\n\r\n[]")
+                         (error (car err)))
+                       (jsonrpc-transport-feed transport "")))"#,
+            "(json-end-of-file 1)",
+        );
+        assert_lisp(
+            r#"(jsonrpc-frame-message '(:a 1))"#,
+            r#""Content-Length: 7\r\n\r\n{\"a\":1}""#,
+        );
+    }
+}
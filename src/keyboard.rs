@@ -0,0 +1,353 @@
+//! Keyboard input and the command loop.
+use crate::callint::call_interactively;
+use crate::chartab::translate_char;
+use crate::core::{
+    env::{Env, sym},
+    gc::{Context, Rt, Rto},
+    object::{
+        CharTable, CharTableInner, Gc, NIL, Object, ObjectType, OptionalFlag, Symbol, TRUE,
+        char_to_int,
+    },
+};
+use crate::eval::EvalError;
+use crate::frame::run_resize_hooks;
+use crate::quail::with_input_method;
+use crate::xdisp::redisplay_frame;
+use anyhow::{Result, bail};
+use rune_core::macros::{list, root};
+use rune_macros::defun;
+use rune_tui::RawMode;
+use std::collections::VecDeque;
+use std::io::{self, IsTerminal, Read, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+defvar!(THIS_COMMAND);
+defvar!(LAST_COMMAND);
+defvar!(LAST_COMMAND_EVENT);
+defvar!(QUIT_FLAG);
+defvar!(INHIBIT_QUIT);
+defvar!(KEYBOARD_TRANSLATE_TABLE);
+defsym!(QUIT);
+
+/// Set by the SIGINT handler. It is moved into `quit-flag` at the next safe
+/// point, because lisp objects can't be touched from a signal handler.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn handle_interrupt(_: libc::c_int) {
+    INTERRUPTED.store(true, Ordering::Relaxed);
+}
+
+/// Make SIGINT (C-c in the terminal) request a quit instead of killing the
+/// process, so that runaway lisp can be interrupted.
+pub(crate) fn install_interrupt_handler() {
+    let handler = handle_interrupt as extern "C" fn(libc::c_int);
+    unsafe { libc::signal(libc::SIGINT, handler as libc::sighandler_t) };
+}
+
+/// Signal `quit` if a quit has been requested and `inhibit-quit` is nil. A
+/// quit is requested by SIGINT or by setting `quit-flag`. This is called at
+/// points where evaluation can safely be abandoned: before evaluating a form,
+/// calling a function, or jumping backwards in bytecode.
+pub(crate) fn maybe_quit(env: &mut Rt<Env>, cx: &Context) -> Result<(), EvalError> {
+    if INTERRUPTED.load(Ordering::Relaxed) && INTERRUPTED.swap(false, Ordering::Relaxed) {
+        env.vars.insert(sym::QUIT_FLAG, TRUE);
+    }
+    let is_set = |env: &Rt<Env>, var| env.vars.get(var).is_some_and(|x| !x.bind(cx).is_nil());
+    if !is_set(env, sym::QUIT_FLAG) || is_set(env, sym::INHIBIT_QUIT) {
+        return Ok(());
+    }
+    env.vars.insert(sym::QUIT_FLAG, NIL);
+    Err(EvalError::signal(sym::QUIT.into(), NIL, env))
+}
+
+/// Read a single UTF-8 encoded character from `input`, or `None` at the end of
+/// input.
+fn read_char(input: &mut impl Read) -> io::Result<Option<char>> {
+    let mut buf = [0; 4];
+    if input.read(&mut buf[..1])? == 0 {
+        return Ok(None);
+    }
+    let len = match buf[0] {
+        0xC0..=0xDF => 2,
+        0xE0..=0xEF => 3,
+        0xF0..=0xF7 => 4,
+        _ => 1,
+    };
+    input.read_exact(&mut buf[1..len])?;
+    match std::str::from_utf8(&buf[..len]) {
+        Ok(s) => Ok(s.chars().next()),
+        Err(_) => Ok(Some(char::REPLACEMENT_CHARACTER)),
+    }
+}
+
+/// Read an event from the terminal, printing PROMPT first. Characters are
+/// returned as integers.
+#[defun]
+fn read_event<'ob>(
+    prompt: Option<&str>,
+    _inherit_input_method: OptionalFlag,
+    _seconds: Option<Object>,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    if let Some(prompt) = prompt {
+        print!("{prompt}");
+        io::stdout().flush()?;
+    }
+    let _raw = RawMode::new();
+    match read_char(&mut io::stdin())? {
+        Some(c) => Ok(cx.add(c)),
+        None => {
+            let data = list![cx.add("Error reading from stdin"); cx];
+            Err(EvalError::signal(sym::END_OF_FILE.into(), data, env).into())
+        }
+    }
+}
+
+/// Make the command loop translate the character FROM to TO as it is typed,
+/// by setting it in `keyboard-translate-table`.
+#[defun]
+fn keyboard_translate(from: char, to: char, env: &mut Rt<Env>, cx: &Context) -> Result<char> {
+    let table = match env.vars.get(sym::KEYBOARD_TRANSLATE_TABLE).map(|x| x.bind(cx).untag()) {
+        Some(ObjectType::CharTable(table)) => table,
+        _ => {
+            let table: Gc<&CharTable> = cx.add_as(CharTableInner::new(None));
+            env.set_var(sym::KEYBOARD_TRANSLATE_TABLE, table.into())?;
+            table.untag()
+        }
+    };
+    table.set(char_to_int(from) as usize, cx.add(to));
+    Ok(to)
+}
+
+/// Translate a typed character with `keyboard-translate-table`.
+fn translate_key(key: char, env: &Rt<Env>, cx: &Context) -> char {
+    match env.vars.get(sym::KEYBOARD_TRANSLATE_TABLE).map(|x| x.bind(cx).untag()) {
+        Some(ObjectType::CharTable(table)) => translate_char(table, key),
+        _ => key,
+    }
+}
+
+/// Run `key` through the input method of the current buffer, returning the
+/// keys to execute. Control characters end the pending key sequence and are
+/// passed through. Returns `None` if the key is not handled by an input
+/// method.
+fn input_method_keys(
+    key: char,
+    pending: &mut String,
+    env: &Rt<Env>,
+    cx: &Context,
+) -> Option<String> {
+    let keys = with_input_method(env, cx, |method| {
+        if key.is_control() {
+            let mut keys = method.flush(pending);
+            keys.push(key);
+            keys
+        } else {
+            method.feed(pending, key)
+        }
+    });
+    match keys {
+        Some(keys) => Some(keys),
+        // the input method was turned off in the middle of a sequence
+        None if !pending.is_empty() => {
+            let mut keys = std::mem::take(pending);
+            keys.push(key);
+            Some(keys)
+        }
+        None => None,
+    }
+}
+
+/// Execute CMD as an editor command, setting `this-command' to it first.
+#[defun]
+fn command_execute<'ob>(
+    cmd: &Rto<Object>,
+    record_flag: OptionalFlag,
+    keys: OptionalFlag,
+    _special: OptionalFlag,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<Object<'ob>> {
+    env.set_var(sym::THIS_COMMAND, cmd.bind(cx))?;
+    call_interactively(cmd, record_flag, keys, env, cx)
+}
+
+/// The command bound to `key`. There are no keymaps yet, so the bindings are
+/// fixed.
+fn key_binding(key: char) -> Option<Symbol<'static>> {
+    match key {
+        '\r' => Some(sym::NEWLINE),
+        '\x7f' => Some(sym::DELETE_BACKWARD_CHAR),
+        '\x01' => Some(sym::BEGINNING_OF_LINE),
+        '\x02' => Some(sym::BACKWARD_CHAR),
+        '\x04' => Some(sym::DELETE_CHAR),
+        '\x05' => Some(sym::END_OF_LINE),
+        '\x06' => Some(sym::FORWARD_CHAR),
+        '\x1c' => Some(sym::TOGGLE_INPUT_METHOD),
+        c if !c.is_control() => Some(sym::SELF_INSERT_COMMAND),
+        _ => None,
+    }
+}
+
+/// Run the command bound to `key`.
+fn execute_key(key: char, env: &mut Rt<Env>, cx: &mut Context) -> Result<()> {
+    env.set_var(sym::LAST_COMMAND_EVENT, cx.add(key))?;
+    let Some(cmd) = key_binding(key) else { bail!("{key:?} is undefined") };
+    let cmd: Object = cmd.into();
+    root!(cmd, cx);
+    command_execute(cmd, None, None, None, env, cx)?;
+    let this_command = env.vars.get(sym::THIS_COMMAND).map(|x| x.bind(cx)).unwrap_or_default();
+    env.set_var(sym::LAST_COMMAND, this_command)
+}
+
+/// Read keys from the terminal and run the commands bound to them until the
+/// input ends or C-x C-c is typed. Returns the exit code if `kill-emacs` was
+/// called.
+pub(crate) fn command_loop(env: &mut Rt<Env>, cx: &mut Context) -> Option<u8> {
+    let _raw = RawMode::new();
+    let terminal = io::stdout().is_terminal();
+    if terminal {
+        let _ = rune_tui::enter_alternate_screen(&mut io::stdout());
+        crate::xdisp::invalidate_display();
+    }
+    let result = read_commands(env, cx);
+    if terminal {
+        let _ = rune_tui::leave_alternate_screen(&mut io::stdout());
+    }
+    let Err(e) = result else { return None };
+    let code = crate::eval::exit_code(&e);
+    if code.is_none() {
+        eprint!("Error: {e}\r\n");
+    }
+    code
+}
+
+fn read_commands(env: &mut Rt<Env>, cx: &mut Context) -> Result<()> {
+    let mut ctl_x = false;
+    let mut echo = String::new();
+    // The keys produced by the input method that have not been run yet, and
+    // the typed keys that it has not translated yet
+    let mut queue = VecDeque::new();
+    let mut pending = String::new();
+    loop {
+        let key = match queue.pop_front() {
+            Some(key) => key,
+            None => {
+                if let Err(e) = run_resize_hooks(env, cx)
+                    && echo.is_empty()
+                {
+                    echo = e.to_string();
+                }
+                if echo.is_empty() {
+                    echo.push_str(&pending);
+                }
+                if !redisplay_frame(&echo, env, cx)? && !echo.is_empty() {
+                    eprint!("{echo}\r\n");
+                }
+                echo.clear();
+                let key = match read_char(&mut io::stdin()) {
+                    Ok(Some(key)) => key,
+                    Ok(None) => return Ok(()),
+                    // A resize interrupted the wait, so redisplay at the new size
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(e) => return Err(e.into()),
+                };
+                let key = translate_key(key, env, cx);
+                if !ctl_x
+                    && key != '\x07'
+                    && let Some(keys) = input_method_keys(key, &mut pending, env, cx)
+                {
+                    queue.extend(keys.chars());
+                    continue;
+                }
+                key
+            }
+        };
+        // C-g cancels a prefix key. Raw mode turns it into a plain character
+        // instead of a signal.
+        if key == '\x07' {
+            ctl_x = false;
+            pending.clear();
+            echo.push_str("Quit");
+            continue;
+        }
+        if std::mem::take(&mut ctl_x) {
+            if key == '\x03' {
+                return crate::emacs::kill_emacs(None, None, env, cx);
+            }
+            echo = format!("C-x {key:?} is undefined");
+            continue;
+        }
+        if key == '\x18' {
+            ctl_x = true;
+            echo.push_str("C-x-");
+            continue;
+        }
+        if let Err(e) = execute_key(key, env, cx) {
+            if crate::eval::exit_code(&e).is_some() {
+                return Err(e);
+            }
+            echo = e.to_string();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::interpreter::assert_lisp;
+
+    #[test]
+    fn test_read_char() {
+        let input = &mut io::Cursor::new("aé\r");
+        assert_eq!(read_char(input).unwrap(), Some('a'));
+        assert_eq!(read_char(input).unwrap(), Some('é'));
+        assert_eq!(read_char(input).unwrap(), Some('\r'));
+        assert_eq!(read_char(input).unwrap(), None);
+    }
+
+    #[test]
+    fn test_keyboard_translate() {
+        assert_lisp(
+            "(progn (setq keyboard-translate-table nil)
+                    (keyboard-translate ?\\( ?\\[)
+                    (list (aref keyboard-translate-table ?\\()
+                          (aref keyboard-translate-table ?a)))",
+            "(91 nil)",
+        );
+    }
+
+    #[test]
+    fn test_command_execute() {
+        assert_lisp(
+            r#"(progn (insert "ab") (goto-char 1) (command-execute 'forward-char)
+                 (list (point) this-command))"#,
+            "(2 forward-char)",
+        );
+    }
+
+    #[test]
+    fn test_quit() {
+        assert_lisp(
+            "(condition-case nil (progn (setq quit-flag t) (identity nil)) (quit 'quit))",
+            "quit",
+        );
+        assert_lisp(
+            "(condition-case nil (condition-case nil (progn (setq quit-flag t) (identity nil)) (error 'error)) (quit 'quit))",
+            "quit",
+        );
+        assert_lisp(
+            "(condition-case nil (progn (setq quit-flag t) (identity nil)) ((error quit) 'caught))",
+            "caught",
+        );
+        // a quit is delayed until inhibit-quit is nil again
+        assert_lisp(
+            "(condition-case nil
+               (list (let ((inhibit-quit t)) (setq quit-flag t) (identity nil) quit-flag) (identity nil))
+             (quit 'quit))",
+            "quit",
+        );
+        assert_lisp("(condition-case nil (signal 'quit nil) (quit 'quit))", "quit");
+    }
+}
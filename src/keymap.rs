@@ -1,21 +1,134 @@
 //! Keymap handling.
-use crate::core::object::Object;
+use crate::core::{
+    cons::Cons,
+    env::{Env, sym},
+    gc::{Context, Rt},
+    object::{CharTableInner, NIL, Object, ObjectType},
+};
+use anyhow::{Result, bail};
+use rune_core::macros::list;
 use rune_macros::defun;
 
-// TODO: implement keymaps
+defsym!(KEYMAP);
+
+/// Return a new keymap, which holds the bindings of characters in a
+/// char-table. STRING is the prompt of the keymap when it is used as a menu.
 #[defun]
-fn make_keymap(_string: Option<&str>) {}
+fn make_keymap<'ob>(string: Option<&str>, cx: &'ob Context) -> Object<'ob> {
+    let table: Object = cx.add(CharTableInner::new(None));
+    match string {
+        Some(prompt) => list![sym::KEYMAP, table, cx.add(prompt); cx],
+        None => list![sym::KEYMAP, table; cx],
+    }
+}
 
+/// Return a new keymap without a char-table. STRING is the prompt of the
+/// keymap when it is used as a menu.
 #[defun]
-fn make_sparse_keymap(_string: Option<&str>) {}
+fn make_sparse_keymap<'ob>(string: Option<&str>, cx: &'ob Context) -> Object<'ob> {
+    match string {
+        Some(prompt) => list![sym::KEYMAP, cx.add(prompt); cx],
+        None => list![sym::KEYMAP; cx],
+    }
+}
 
+/// Return t if OBJECT is a keymap, a list that starts with `keymap`.
 #[defun]
-fn use_global_map(_keymap: Object) {}
+pub(crate) fn keymapp(object: Object) -> bool {
+    matches!(object.untag(), ObjectType::Cons(cons) if cons.car() == sym::KEYMAP)
+}
+
+fn keymap_cons<'ob>(keymap: Object<'ob>) -> Result<&'ob Cons> {
+    match keymap.untag() {
+        ObjectType::Cons(cons) if cons.car() == sym::KEYMAP => Ok(cons),
+        _ => bail!("Wrong type argument: keymapp, {keymap}"),
+    }
+}
+
+/// The last cons of `keymap` before its parent, which is the rest of the list
+/// after the next `keymap` symbol.
+fn keymap_end(keymap: &Cons) -> &Cons {
+    let mut end = keymap;
+    loop {
+        match end.cdr().untag() {
+            ObjectType::Cons(next) if next.car() != sym::KEYMAP => end = next,
+            _ => return end,
+        }
+    }
+}
 
+/// Return the parent of KEYMAP, or nil if it has none.
 #[defun]
-fn set_keymap_parent<'ob>(_keymap: Object<'ob>, _parent: Object<'ob>) {}
+fn keymap_parent(keymap: Object) -> Result<Object> {
+    let parent = keymap_end(keymap_cons(keymap)?).cdr();
+    Ok(if keymapp(parent) { parent } else { NIL })
+}
+
+/// Make PARENT the parent of KEYMAP, so that KEYMAP inherits its bindings.
+/// PARENT can be nil to remove the parent.
+#[defun]
+fn set_keymap_parent<'ob>(keymap: Object<'ob>, parent: Object<'ob>) -> Result<Object<'ob>> {
+    let end = keymap_end(keymap_cons(keymap)?);
+    if !parent.is_nil() {
+        let mut ancestor = keymap_cons(parent)?;
+        loop {
+            if Object::from(ancestor) == keymap {
+                bail!("Cyclic keymap inheritance");
+            }
+            match keymap_end(ancestor).cdr().untag() {
+                ObjectType::Cons(next) => ancestor = next,
+                _ => break,
+            }
+        }
+    }
+    end.set_cdr(parent)?;
+    Ok(parent)
+}
 
 #[defun]
 pub(crate) fn define_key<'ob>(_keymap: Object<'ob>, _key: Object<'ob>, _def: Object<'ob>) {}
 
+#[defun]
+fn use_global_map(_keymap: Object) {}
+
+/// Make KEYMAP the local keymap of the current buffer. If it is nil, the
+/// buffer has no local keymap.
+#[defun]
+fn use_local_map(keymap: Object, env: &mut Rt<Env>, cx: &Context) -> Result<()> {
+    if !keymap.is_nil() {
+        keymap_cons(keymap)?;
+    }
+    env.set_buffer_slot(sym::CURRENT_LOCAL_MAP, keymap, cx);
+    Ok(())
+}
+
+/// Return the local keymap of the current buffer, or nil if it has none.
+#[defun]
+fn current_local_map<'ob>(env: &Rt<Env>, cx: &'ob Context) -> Object<'ob> {
+    env.buffer_slot(sym::CURRENT_LOCAL_MAP, cx).unwrap_or(NIL)
+}
+
 defvar!(MINIBUFFER_LOCAL_MAP);
+
+#[cfg(test)]
+mod test {
+    use crate::interpreter::assert_lisp;
+
+    #[test]
+    fn test_keymap_parent() {
+        assert_lisp(
+            "(let ((map (make-sparse-keymap)) (parent (make-keymap)))
+               (set-keymap-parent map parent)
+               (list (eq (keymap-parent map) parent) (keymap-parent parent)
+                     (condition-case nil (set-keymap-parent parent map) (error 'cycle))))",
+            "(t nil cycle)",
+        );
+        assert_lisp(
+            "(let ((map (make-sparse-keymap)))
+               (use-local-map map)
+               (list (eq (current-local-map) map)
+                     (progn (use-local-map nil) (current-local-map))))",
+            "(t nil)",
+        );
+    }
+}
@@ -1,3 +1,9 @@
 //! The library module defines additional utility functions for Rune.
 
+pub(crate) mod diff;
+pub(crate) mod filename;
 pub(crate) mod filevercmp;
+pub(crate) mod interval_tree;
+pub(crate) mod number;
+pub(crate) mod span_queue;
+pub(crate) mod text_props;
@@ -0,0 +1,155 @@
+//! Differences between two sequences.
+//!
+//! This uses the greedy algorithm from Myers' "An O(ND) Difference Algorithm
+//! and Its Variations", which finds a shortest edit script in time
+//! proportional to the length of the sequences times the number of edits.
+//! Only the parts after the common prefix and before the common suffix are
+//! searched, so small edits to long texts are cheap.
+use std::ops::Range;
+use std::time::Instant;
+
+/// A change that replaces `old[old]` with `new[new]`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Hunk {
+    pub(crate) old: Range<usize>,
+    pub(crate) new: Range<usize>,
+}
+
+/// The changes that turn `old` into `new`, in order. Returns `None` if that
+/// takes more than `max_cost` insertions and deletions, or if `deadline`
+/// passes before the changes are found.
+pub(crate) fn diff<T: PartialEq>(
+    old: &[T],
+    new: &[T],
+    max_cost: usize,
+    deadline: Option<Instant>,
+) -> Option<Vec<Hunk>> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let (old_rest, new_rest) = (&old[prefix..], &new[prefix..]);
+    let suffix = old_rest
+        .iter()
+        .rev()
+        .zip(new_rest.iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let a = &old_rest[..old_rest.len() - suffix];
+    let b = &new_rest[..new_rest.len() - suffix];
+    let mut hunks = Vec::new();
+    let (mut x, mut y) = (0, 0);
+    for (run_x, run_y, len) in common_runs(a, b, max_cost, deadline)? {
+        if run_x > x || run_y > y {
+            hunks.push(Hunk { old: prefix + x..prefix + run_x, new: prefix + y..prefix + run_y });
+        }
+        (x, y) = (run_x + len, run_y + len);
+    }
+    Some(hunks)
+}
+
+/// The runs of elements that `a` and `b` keep in a shortest edit script, as
+/// `(x, y, len)` for the run that starts at `a[x]` and `b[y]`. The last run is
+/// an empty one at the end of both.
+fn common_runs<T: PartialEq>(
+    a: &[T],
+    b: &[T],
+    max_cost: usize,
+    deadline: Option<Instant>,
+) -> Option<Vec<(usize, usize, usize)>> {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let max = n + m;
+    let offset = max + 1;
+    // The furthest x reached on each diagonal k = x - y
+    let mut v = vec![0; 2 * max as usize + 3];
+    // The diagonals -d..=d of `v` after each number of edits d
+    let mut trace: Vec<Vec<isize>> = Vec::new();
+    'search: for d in 0..=max {
+        if d as usize > max_cost || deadline.is_some_and(|x| Instant::now() > x) {
+            return None;
+        }
+        for k in (-d..=d).step_by(2) {
+            let i = (k + offset) as usize;
+            let mut x =
+                if k == -d || (k != d && v[i - 1] < v[i + 1]) { v[i + 1] } else { v[i - 1] + 1 };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[i] = x;
+            if x >= n && y >= m {
+                trace.push(v[(offset - d) as usize..=(offset + d) as usize].to_vec());
+                break 'search;
+            }
+        }
+        trace.push(v[(offset - d) as usize..=(offset + d) as usize].to_vec());
+    }
+
+    // Walk back from the end, finding the run that ends each step
+    let mut runs = vec![(n as usize, m as usize, 0)];
+    let (mut x, mut y) = (n, m);
+    for d in (0..trace.len() as isize).rev() {
+        let k = x - y;
+        if d == 0 {
+            if x > 0 {
+                runs.push((0, 0, x as usize));
+            }
+            break;
+        }
+        let prev = |k: isize| trace[d as usize - 1][(k + d - 1) as usize];
+        let down = k == -d || (k != d && prev(k - 1) < prev(k + 1));
+        let prev_k = if down { k + 1 } else { k - 1 };
+        let prev_x = prev(prev_k);
+        let start_x = if down { prev_x } else { prev_x + 1 };
+        if x > start_x {
+            runs.push((start_x as usize, (start_x - k) as usize, (x - start_x) as usize));
+        }
+        (x, y) = (prev_x, prev_x - prev_k);
+    }
+    runs.reverse();
+    Some(runs)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn apply(old: &str, new: &str) -> String {
+        let (old, new): (Vec<char>, Vec<char>) = (old.chars().collect(), new.chars().collect());
+        let mut result = old.clone();
+        for hunk in diff(&old, &new, usize::MAX, None).unwrap().into_iter().rev() {
+            result.splice(hunk.old, new[hunk.new].iter().copied());
+        }
+        result.into_iter().collect()
+    }
+
+    #[test]
+    fn test_diff() {
+        let hunks = |old: &str, new: &str| {
+            let (old, new): (Vec<char>, Vec<char>) = (old.chars().collect(), new.chars().collect());
+            diff(&old, &new, usize::MAX, None).unwrap()
+        };
+        assert_eq!(hunks("abc", "abc"), []);
+        assert_eq!(hunks("", "ab"), [Hunk { old: 0..0, new: 0..2 }]);
+        assert_eq!(hunks("axc", "ayc"), [Hunk { old: 1..2, new: 1..2 }]);
+        assert_eq!(
+            hunks("abcabba", "cbabac"),
+            [
+                Hunk { old: 0..2, new: 0..0 },
+                Hunk { old: 3..3, new: 1..2 },
+                Hunk { old: 5..6, new: 4..4 },
+                Hunk { old: 7..7, new: 5..6 },
+            ]
+        );
+        for (old, new) in
+            [("abcabba", "cbabac"), ("hello world", "yellow word!"), ("", ""), ("a", "")]
+        {
+            assert_eq!(apply(old, new), new);
+        }
+    }
+
+    #[test]
+    fn test_max_cost() {
+        let (old, new) = (['a', 'b', 'c'], ['x', 'y', 'z']);
+        assert!(diff(&old, &new, 5, None).is_none());
+        assert_eq!(diff(&old, &new, 6, None).unwrap(), [Hunk { old: 0..3, new: 0..3 }]);
+    }
+}
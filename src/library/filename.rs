@@ -0,0 +1,243 @@
+//! A model of file names that follows Emacs rules instead of `std::path`.
+//!
+//! Emacs treats file names as plain strings where a trailing slash is
+//! significant (`"foo/"` names a directory while `"foo"` names a file), and a
+//! doubled slash in the middle of a name discards everything before it
+//! (`"/foo//bar"` is `"/bar"`). These functions operate on strings directly so
+//! those rules can be preserved.
+
+const SEP: char = '/';
+
+/// Return the directory part of `name`, including the trailing slash. Returns
+/// `None` if `name` has no directory part.
+pub(crate) fn directory(name: &str) -> Option<&str> {
+    name.rfind(SEP).map(|idx| &name[..=idx])
+}
+
+/// Return the part of `name` after the last slash.
+pub(crate) fn nondirectory(name: &str) -> &str {
+    match name.rfind(SEP) {
+        Some(idx) => &name[idx + 1..],
+        None => name,
+    }
+}
+
+/// Return `name` with a trailing slash, so that it is treated as a
+/// directory. The empty string is converted to `"./"`.
+pub(crate) fn as_directory(name: &str) -> String {
+    if name.is_empty() {
+        "./".to_owned()
+    } else if name.ends_with(SEP) {
+        name.to_owned()
+    } else {
+        format!("{name}{SEP}")
+    }
+}
+
+/// Return `name` without trailing slashes. The root directory is left
+/// unchanged, and a name consisting of exactly two slashes is preserved as it
+/// has a special meaning on some systems.
+pub(crate) fn directory_file_name(name: &str) -> &str {
+    if name == "//" {
+        return name;
+    }
+    let trimmed = name.trim_end_matches(SEP);
+    if trimmed.is_empty() && !name.is_empty() { "/" } else { trimmed }
+}
+
+/// Return the index of the period that starts the extension of `name`, if any.
+/// Leading periods (as in `.emacs`) do not start an extension.
+fn extension_start(name: &str) -> Option<usize> {
+    let file = nondirectory(name);
+    let offset = name.len() - file.len();
+    let stripped = file.trim_start_matches('.');
+    let leading = file.len() - stripped.len();
+    stripped.rfind('.').map(|idx| offset + leading + idx)
+}
+
+/// Return the extension of `name`, without the period.
+pub(crate) fn extension(name: &str) -> Option<&str> {
+    extension_start(name).map(|idx| &name[idx + 1..])
+}
+
+/// Return `name` with its extension (and the period) removed.
+pub(crate) fn sans_extension(name: &str) -> &str {
+    match extension_start(name) {
+        Some(idx) => &name[..idx],
+        None => name,
+    }
+}
+
+/// Return the nondirectory part of `name` without its extension.
+pub(crate) fn base(name: &str) -> &str {
+    sans_extension(nondirectory(name))
+}
+
+/// Return true if `name` is absolute, meaning it starts with a slash or a
+/// tilde.
+pub(crate) fn is_absolute(name: &str) -> bool {
+    name.starts_with(SEP) || name.starts_with('~')
+}
+
+/// Join `components` onto `directory`, inserting a separator only where one is
+/// needed. Empty components are skipped.
+pub(crate) fn concat<'a>(directory: &str, components: impl IntoIterator<Item = &'a str>) -> String {
+    let mut path = String::from(directory);
+    for component in components {
+        if component.is_empty() {
+            continue;
+        }
+        if !path.is_empty() && !path.ends_with(SEP) {
+            path.push(SEP);
+        }
+        path.push_str(component);
+    }
+    path
+}
+
+/// Expand a leading `~` in `name` using `home`. Names of the form `~user` are
+/// returned unchanged.
+fn expand_home(name: &str, home: &str) -> Option<String> {
+    let rest = name.strip_prefix('~')?;
+    if rest.is_empty() || rest.starts_with(SEP) {
+        let home = home.trim_end_matches(SEP);
+        Some(format!("{home}{rest}"))
+    } else {
+        None
+    }
+}
+
+/// Convert `name` to an absolute, canonical form. Relative names are taken to
+/// be relative to `default_directory`, `~` is replaced with `home`, and `.`
+/// and `..` components are removed. A trailing slash is preserved.
+pub(crate) fn expand(name: &str, default_directory: &str, home: &str) -> String {
+    // A double slash or `/~` discards everything that comes before it
+    let name = match (name.rfind("//"), name.rfind("/~")) {
+        (Some(x), Some(y)) if y > x => &name[y + 1..],
+        (Some(x), _) => &name[x + 1..],
+        (None, Some(y)) => &name[y + 1..],
+        (None, None) => name,
+    };
+    let full = if let Some(expanded) = expand_home(name, home) {
+        expanded
+    } else if name.starts_with(SEP) {
+        name.to_owned()
+    } else {
+        let dir = match expand_home(default_directory, home) {
+            Some(dir) => dir,
+            None if default_directory.starts_with(SEP) => default_directory.to_owned(),
+            None => format!("/{default_directory}"),
+        };
+        concat(&dir, [name])
+    };
+
+    let mut parts: Vec<&str> = Vec::new();
+    for part in full.split(SEP) {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            x => parts.push(x),
+        }
+    }
+    let mut result = String::with_capacity(full.len());
+    for part in &parts {
+        result.push(SEP);
+        result.push_str(part);
+    }
+    if result.is_empty() || name.ends_with(SEP) {
+        result.push(SEP);
+    }
+    result
+}
+
+/// Replace a leading `home` directory in `name` with `~`.
+pub(crate) fn abbreviate(name: &str, home: &str) -> String {
+    let home = home.trim_end_matches(SEP);
+    if home.is_empty() {
+        return name.to_owned();
+    }
+    match name.strip_prefix(home) {
+        Some("") => "~".to_owned(),
+        Some(rest) if rest.starts_with(SEP) => format!("~{rest}"),
+        _ => name.to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_directory() {
+        assert_eq!(directory("/usr/lib/foo.el"), Some("/usr/lib/"));
+        assert_eq!(directory("/usr/lib/"), Some("/usr/lib/"));
+        assert_eq!(directory("foo.el"), None);
+        assert_eq!(directory("/"), Some("/"));
+        assert_eq!(nondirectory("/usr/lib/foo.el"), "foo.el");
+        assert_eq!(nondirectory("/usr/lib/"), "");
+        assert_eq!(nondirectory("foo"), "foo");
+    }
+
+    #[test]
+    fn test_as_directory() {
+        assert_eq!(as_directory(""), "./");
+        assert_eq!(as_directory("foo"), "foo/");
+        assert_eq!(as_directory("foo/"), "foo/");
+        assert_eq!(directory_file_name("foo/"), "foo");
+        assert_eq!(directory_file_name("foo///"), "foo");
+        assert_eq!(directory_file_name("/"), "/");
+        assert_eq!(directory_file_name("///"), "/");
+        assert_eq!(directory_file_name("//"), "//");
+        assert_eq!(directory_file_name(""), "");
+    }
+
+    #[test]
+    fn test_extension() {
+        assert_eq!(extension("foo.el"), Some("el"));
+        assert_eq!(extension("foo.tar.gz"), Some("gz"));
+        assert_eq!(extension("/a.b/foo"), None);
+        assert_eq!(extension(".emacs"), None);
+        assert_eq!(extension("..foo.el"), Some("el"));
+        assert_eq!(extension("foo."), Some(""));
+        assert_eq!(sans_extension("/a/foo.tar.gz"), "/a/foo.tar");
+        assert_eq!(sans_extension("/a.b/foo"), "/a.b/foo");
+        assert_eq!(base("/a/foo.el"), "foo");
+    }
+
+    #[test]
+    fn test_concat() {
+        assert_eq!(concat("foo", ["bar"]), "foo/bar");
+        assert_eq!(concat("foo/", ["bar"]), "foo/bar");
+        assert_eq!(concat("foo", ["bar/", "zot"]), "foo/bar/zot");
+        assert_eq!(concat("foo", ["", "bar"]), "foo/bar");
+        assert_eq!(concat("", ["bar"]), "bar");
+    }
+
+    #[test]
+    fn test_expand() {
+        let home = "/home/user";
+        assert_eq!(expand("foo", "/tmp/", home), "/tmp/foo");
+        assert_eq!(expand("foo", "/tmp", home), "/tmp/foo");
+        assert_eq!(expand("foo/", "/tmp/", home), "/tmp/foo/");
+        assert_eq!(expand("../foo", "/tmp/bar/", home), "/tmp/foo");
+        assert_eq!(expand("./foo/.", "/tmp/", home), "/tmp/foo");
+        assert_eq!(expand("/..", "/tmp/", home), "/");
+        assert_eq!(expand("~", "/tmp/", home), "/home/user");
+        assert_eq!(expand("~/foo", "/tmp/", home), "/home/user/foo");
+        assert_eq!(expand("foo", "~/src/", home), "/home/user/src/foo");
+        assert_eq!(expand("/foo//bar", "/tmp/", home), "/bar");
+        assert_eq!(expand("/foo/~/bar", "/tmp/", home), "/home/user/bar");
+        assert_eq!(expand("", "/tmp/", home), "/tmp");
+    }
+
+    #[test]
+    fn test_abbreviate() {
+        let home = "/home/user";
+        assert_eq!(abbreviate("/home/user/foo", home), "~/foo");
+        assert_eq!(abbreviate("/home/user", home), "~");
+        assert_eq!(abbreviate("/home/username", home), "/home/username");
+        assert_eq!(abbreviate("/tmp", home), "/tmp");
+    }
+}
@@ -0,0 +1,224 @@
+//! An interval tree for ranges of text, such as overlays, that have to follow
+//! the text around them as it is edited.
+//!
+//! Intervals are kept in a vector sorted by start position and the tree is
+//! implicit in that order: the node for a range of the vector is its midpoint,
+//! and the halves on either side are its children. Each node records the
+//! largest end position in its subtree so that a search can skip subtrees that
+//! end before the range of interest. Since an edit has to shift every interval
+//! after it anyway, the augmentation is rebuilt after each change.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Interval {
+    pub(crate) id: usize,
+    pub(crate) start: usize,
+    pub(crate) end: usize,
+    /// Text inserted at the start is not part of the interval
+    pub(crate) front_advance: bool,
+    /// Text inserted at the end is part of the interval
+    pub(crate) rear_advance: bool,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct IntervalTree {
+    intervals: Vec<Interval>,
+    /// The largest end position in the subtree rooted at each index
+    max_end: Vec<usize>,
+}
+
+impl IntervalTree {
+    pub(crate) fn insert(&mut self, interval: Interval) {
+        debug_assert!(interval.start <= interval.end);
+        let idx = self.intervals.partition_point(|x| x.start <= interval.start);
+        self.intervals.insert(idx, interval);
+        self.rebuild();
+    }
+
+    pub(crate) fn remove(&mut self, id: usize) -> Option<Interval> {
+        let idx = self.intervals.iter().position(|x| x.id == id)?;
+        let interval = self.intervals.remove(idx);
+        self.rebuild();
+        Some(interval)
+    }
+
+    pub(crate) fn get(&self, id: usize) -> Option<&Interval> {
+        self.intervals.iter().find(|x| x.id == id)
+    }
+
+    /// All intervals ordered by their start position.
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &Interval> {
+        self.intervals.iter()
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.intervals.clear();
+        self.max_end.clear();
+    }
+
+    /// Find all intervals that start at or before `end` and end at or after
+    /// `start`, ordered by their start position.
+    pub(crate) fn overlapping(&self, start: usize, end: usize) -> Vec<&Interval> {
+        let mut found = Vec::new();
+        self.search(0, self.intervals.len(), start, end, &mut found);
+        found
+    }
+
+    fn search<'a>(
+        &'a self,
+        lo: usize,
+        hi: usize,
+        start: usize,
+        end: usize,
+        found: &mut Vec<&'a Interval>,
+    ) {
+        let mid = lo + (hi - lo) / 2;
+        if lo >= hi || self.max_end[mid] < start {
+            return;
+        }
+        self.search(lo, mid, start, end, found);
+        let interval = &self.intervals[mid];
+        // everything to the right starts even later
+        if interval.start > end {
+            return;
+        }
+        if interval.end >= start {
+            found.push(interval);
+        }
+        self.search(mid + 1, hi, start, end, found);
+    }
+
+    /// Adjust the intervals for `len` characters inserted at `pos`.
+    pub(crate) fn insert_text(&mut self, pos: usize, len: usize) {
+        for interval in &mut self.intervals {
+            let empty = interval.start == interval.end;
+            // An empty interval only moves its start past the new text if
+            // its end moves too
+            if interval.start > pos
+                || (interval.start == pos
+                    && interval.front_advance
+                    && (!empty || interval.rear_advance))
+            {
+                interval.start += len;
+            }
+            if interval.end > pos || (interval.end == pos && interval.rear_advance) {
+                interval.end += len;
+            }
+        }
+        self.rebuild();
+    }
+
+    /// Adjust the intervals for the text between `beg` and `end` being
+    /// deleted. Positions inside the deleted text move to `beg`.
+    pub(crate) fn delete_text(&mut self, beg: usize, end: usize) {
+        let adjust = |pos: usize| {
+            if pos >= end { pos - (end - beg) } else { pos.min(beg) }
+        };
+        for interval in &mut self.intervals {
+            interval.start = adjust(interval.start);
+            interval.end = adjust(interval.end);
+        }
+        self.rebuild();
+    }
+
+    fn rebuild(&mut self) {
+        self.max_end.resize(self.intervals.len(), 0);
+        self.build(0, self.intervals.len());
+    }
+
+    fn build(&mut self, lo: usize, hi: usize) -> usize {
+        if lo >= hi {
+            return 0;
+        }
+        let mid = lo + (hi - lo) / 2;
+        let left = self.build(lo, mid);
+        let right = self.build(mid + 1, hi);
+        let max = self.intervals[mid].end.max(left).max(right);
+        self.max_end[mid] = max;
+        max
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn interval(id: usize, start: usize, end: usize) -> Interval {
+        Interval { id, start, end, front_advance: false, rear_advance: false }
+    }
+
+    fn ids(found: &[&Interval]) -> Vec<usize> {
+        let mut ids: Vec<_> = found.iter().map(|x| x.id).collect();
+        ids.sort_unstable();
+        ids
+    }
+
+    #[test]
+    fn test_overlapping() {
+        let mut tree = IntervalTree::default();
+        tree.insert(interval(0, 5, 10));
+        tree.insert(interval(1, 0, 3));
+        tree.insert(interval(2, 8, 20));
+        tree.insert(interval(3, 4, 4));
+        assert_eq!(ids(&tree.overlapping(0, 0)), vec![1]);
+        assert_eq!(ids(&tree.overlapping(4, 4)), vec![3]);
+        assert_eq!(ids(&tree.overlapping(9, 12)), vec![0, 2]);
+        assert_eq!(ids(&tree.overlapping(21, 30)), Vec::<usize>::new());
+        assert_eq!(tree.remove(0), Some(interval(0, 5, 10)));
+        assert_eq!(ids(&tree.overlapping(9, 12)), vec![2]);
+        assert_eq!(tree.remove(0), None);
+    }
+
+    #[test]
+    fn test_overlapping_random() {
+        let mut tree = IntervalTree::default();
+        let mut all = Vec::new();
+        for id in 0..200 {
+            let start = rand::random::<u32>() as usize % 1000;
+            let end = start + rand::random::<u32>() as usize % 50;
+            tree.insert(interval(id, start, end));
+            all.push(interval(id, start, end));
+        }
+        for _ in 0..200 {
+            let start = rand::random::<u32>() as usize % 1000;
+            let end = start + rand::random::<u32>() as usize % 100;
+            let expect: Vec<_> = all.iter().filter(|x| x.start <= end && x.end >= start).collect();
+            assert_eq!(ids(&tree.overlapping(start, end)), ids(&expect));
+        }
+    }
+
+    #[test]
+    fn test_insert_text() {
+        let mut tree = IntervalTree::default();
+        tree.insert(interval(0, 2, 5));
+        tree.insert(Interval { front_advance: true, rear_advance: true, ..interval(1, 2, 5) });
+        tree.insert(interval(2, 2, 2));
+        tree.insert(Interval { front_advance: true, ..interval(3, 2, 2) });
+        tree.insert(Interval { front_advance: true, rear_advance: true, ..interval(4, 2, 2) });
+        tree.insert_text(2, 3);
+        let get = |id| tree.get(id).map(|x| (x.start, x.end)).unwrap();
+        assert_eq!(get(0), (2, 8));
+        assert_eq!(get(1), (5, 8));
+        assert_eq!(get(2), (2, 2));
+        assert_eq!(get(3), (2, 2));
+        assert_eq!(get(4), (5, 5));
+        tree.insert_text(8, 1);
+        let get = |id| tree.get(id).map(|x| (x.start, x.end)).unwrap();
+        assert_eq!(get(0), (2, 8));
+        assert_eq!(get(1), (5, 9));
+    }
+
+    #[test]
+    fn test_delete_text() {
+        let mut tree = IntervalTree::default();
+        tree.insert(interval(0, 2, 5));
+        tree.insert(interval(1, 4, 10));
+        tree.insert(interval(2, 12, 15));
+        tree.delete_text(3, 8);
+        let get = |id| tree.get(id).map(|x| (x.start, x.end)).unwrap();
+        assert_eq!(get(0), (2, 3));
+        assert_eq!(get(1), (3, 5));
+        assert_eq!(get(2), (7, 10));
+        assert_eq!(ids(&tree.overlapping(6, 6)), Vec::<usize>::new());
+        assert_eq!(ids(&tree.overlapping(3, 3)), vec![0, 1]);
+    }
+}
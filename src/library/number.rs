@@ -0,0 +1,217 @@
+//! Converting numbers to and from text the same way Emacs does.
+//!
+//! Floats are printed with the fewest digits that read back as the same
+//! value, always with a decimal point or an exponent so they are not read as
+//! integers. Infinities and NaNs have their own syntax (`1.0e+INF` and
+//! `0.0e+NaN`) so they can be read back as well.
+
+/// A number parsed from text.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Number {
+    Int(i64),
+    Float(f64),
+}
+
+/// The bits of a NaN payload. This excludes the quiet bit.
+const NAN_PAYLOAD: u64 = (1 << 51) - 1;
+
+/// Return the printed representation of `float`.
+pub(crate) fn float_to_string(float: f64) -> String {
+    if float.is_nan() {
+        let sign = if float.is_sign_negative() { "-" } else { "" };
+        let payload = float.to_bits() & NAN_PAYLOAD;
+        return format!("{sign}{payload}.0e+NaN");
+    }
+    if float.is_infinite() {
+        return if float < 0.0 { "-1.0e+INF" } else { "1.0e+INF" }.to_owned();
+    }
+    // Use the fewest digits that read back as the same float. 15 digits is the
+    // most that every float can be printed with unchanged.
+    let mut string = String::new();
+    for precision in f64::DIGITS as usize..=17 {
+        string = format_g(float, precision);
+        if string.parse::<f64>() == Ok(float) {
+            break;
+        }
+    }
+    // Make sure it is not read back as an integer
+    if !string.contains(['.', 'e']) {
+        string.push_str(".0");
+    }
+    string
+}
+
+/// Format a finite `float` like the C printf format `%.Pg`, where P is
+/// `precision`.
+fn format_g(float: f64, precision: usize) -> String {
+    let scientific = format!("{float:.*e}", precision - 1);
+    let (mantissa, exponent) = scientific.split_once('e').unwrap();
+    let exponent: i32 = exponent.parse().unwrap();
+    if exponent < -4 || exponent >= precision as i32 {
+        let sign = if exponent < 0 { '-' } else { '+' };
+        let mantissa = strip_zeros(mantissa);
+        format!("{mantissa}e{sign}{:02}", exponent.unsigned_abs())
+    } else {
+        let decimals = (precision as i32 - 1 - exponent) as usize;
+        strip_zeros(&format!("{float:.decimals$}")).to_owned()
+    }
+}
+
+/// Remove trailing zeros after the decimal point, and the decimal point if
+/// nothing is left after it.
+fn strip_zeros(string: &str) -> &str {
+    if string.contains('.') {
+        string.trim_end_matches('0').trim_end_matches('.')
+    } else {
+        string
+    }
+}
+
+/// Parse the longest prefix of `string` that is a number in `radix`. Return
+/// the number and the length of the prefix, or `None` if there is no number.
+///
+/// Floats can only be parsed in radix 10. A float needs digits after the
+/// decimal point or an exponent, so `1.` is the integer 1. Integers too large
+/// to fit are returned as floats.
+pub(crate) fn parse_prefix(string: &str, radix: u32) -> Option<(Number, usize)> {
+    let bytes = string.as_bytes();
+    let digit = |i: usize| bytes.get(i).and_then(|x| char::from(*x).to_digit(radix));
+    let decimal = |i: usize| bytes.get(i).is_some_and(u8::is_ascii_digit);
+    let negative = bytes.first() == Some(&b'-');
+    let sign_len = usize::from(matches!(bytes.first(), Some(b'-' | b'+')));
+    let mut pos = sign_len;
+
+    let mut int: Option<i64> = Some(0);
+    let mut approx = 0.0_f64;
+    let lead_int = digit(pos).is_some();
+    while let Some(d) = digit(pos) {
+        int = int.and_then(|x| x.checked_mul(i64::from(radix))?.checked_add(i64::from(d)));
+        approx = approx * f64::from(radix) + f64::from(d);
+        pos += 1;
+    }
+    if int.is_none() && radix == 10 {
+        // parse it again to round correctly
+        approx = string[sign_len..pos].parse().unwrap_or(approx);
+    }
+    if bytes.get(pos) == Some(&b'.') {
+        pos += 1;
+    }
+    let mut trail_int = false;
+    let mut exponent = false;
+    let mut special = None;
+    if radix == 10 {
+        while decimal(pos) {
+            trail_int = true;
+            pos += 1;
+        }
+        if matches!(bytes.get(pos), Some(b'e' | b'E')) {
+            let mut end = pos + 1;
+            let plus = bytes.get(end) == Some(&b'+');
+            if matches!(bytes.get(end), Some(b'+' | b'-')) {
+                end += 1;
+            }
+            if decimal(end) {
+                exponent = true;
+                while decimal(end) {
+                    end += 1;
+                }
+                pos = end;
+            } else if plus && bytes[end..].starts_with(b"INF") {
+                exponent = true;
+                special = Some(f64::INFINITY);
+                pos = end + 3;
+            } else if plus && bytes[end..].starts_with(b"NaN") {
+                exponent = true;
+                // the integer part is the payload of the NaN
+                let payload = int.unwrap_or(0) as u64 & NAN_PAYLOAD;
+                special = Some(f64::from_bits(f64::NAN.to_bits() | payload));
+                pos = end + 3;
+            }
+        }
+    }
+    if trail_int || (lead_int && exponent) {
+        let value = match special {
+            Some(value) => value,
+            None => string[sign_len..pos].parse::<f64>().ok()?,
+        };
+        return Some((Number::Float(if negative { -value } else { value }), pos));
+    }
+    if !lead_int {
+        return None;
+    }
+    let number = match int {
+        Some(int) if negative => Number::Int(-int),
+        Some(int) => Number::Int(int),
+        None if negative => Number::Float(-approx),
+        None => Number::Float(approx),
+    };
+    Some((number, pos))
+}
+
+/// Parse `string` as a number the way the reader does. Return `None` if any
+/// of it is not part of the number.
+pub(crate) fn parse(string: &str) -> Option<Number> {
+    match parse_prefix(string, 10) {
+        Some((number, len)) if len == string.len() => Some(number),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_float_to_string() {
+        assert_eq!(float_to_string(1.0), "1.0");
+        assert_eq!(float_to_string(-0.0), "-0.0");
+        assert_eq!(float_to_string(0.1), "0.1");
+        assert_eq!(float_to_string(1.5), "1.5");
+        assert_eq!(float_to_string(100.0), "100.0");
+        assert_eq!(float_to_string(1.0 / 3.0), "0.3333333333333333");
+        assert_eq!(float_to_string(0.1 + 0.2), "0.30000000000000004");
+        assert_eq!(float_to_string(1e14), "100000000000000.0");
+        assert_eq!(float_to_string(1e15), "1e+15");
+        assert_eq!(float_to_string(1.5e300), "1.5e+300");
+        assert_eq!(float_to_string(0.0001), "0.0001");
+        assert_eq!(float_to_string(0.00001), "1e-05");
+        assert_eq!(float_to_string(-1.25e-7), "-1.25e-07");
+        assert_eq!(float_to_string(f64::INFINITY), "1.0e+INF");
+        assert_eq!(float_to_string(f64::NEG_INFINITY), "-1.0e+INF");
+        assert_eq!(float_to_string(f64::NAN), "0.0e+NaN");
+        assert_eq!(float_to_string(-f64::NAN), "-0.0e+NaN");
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse("1"), Some(Number::Int(1)));
+        assert_eq!(parse("+1"), Some(Number::Int(1)));
+        assert_eq!(parse("-12"), Some(Number::Int(-12)));
+        assert_eq!(parse("1."), Some(Number::Int(1)));
+        assert_eq!(parse("1.5"), Some(Number::Float(1.5)));
+        assert_eq!(parse("-.5"), Some(Number::Float(-0.5)));
+        assert_eq!(parse("1e3"), Some(Number::Float(1000.0)));
+        assert_eq!(parse("1.e3"), Some(Number::Float(1000.0)));
+        assert_eq!(parse("2.5E-1"), Some(Number::Float(0.25)));
+        assert_eq!(parse("1.0e+INF"), Some(Number::Float(f64::INFINITY)));
+        assert_eq!(parse("-1.0e+INF"), Some(Number::Float(f64::NEG_INFINITY)));
+        let Some(Number::Float(nan)) = parse("-0.0e+NaN") else { panic!("expected NaN") };
+        assert!(nan.is_nan() && nan.is_sign_negative());
+        assert_eq!(parse("99999999999999999999"), Some(Number::Float(1e20)));
+        for symbol in ["", "-", "+", ".", "e5", ".e5", "1+", "1e", "1e+", "inf", "nan", "1.0e-INF"]
+        {
+            assert_eq!(parse(symbol), None, "{symbol}");
+        }
+    }
+
+    #[test]
+    fn test_parse_prefix() {
+        assert_eq!(parse_prefix("12abc", 10), Some((Number::Int(12), 2)));
+        assert_eq!(parse_prefix("1.5x", 10), Some((Number::Float(1.5), 3)));
+        assert_eq!(parse_prefix("1e", 10), Some((Number::Int(1), 1)));
+        assert_eq!(parse_prefix("ff", 16), Some((Number::Int(255), 2)));
+        assert_eq!(parse_prefix("-1.5", 16), Some((Number::Int(-1), 3)));
+        assert_eq!(parse_prefix("102", 2), Some((Number::Int(2), 2)));
+        assert_eq!(parse_prefix("x", 10), None);
+    }
+}
@@ -0,0 +1,118 @@
+//! A queue of spans of text that still need to be processed.
+//!
+//! The spans are kept sorted and disjoint, and are adjusted as text is
+//! inserted and deleted so they keep covering the same text. Text inserted
+//! anywhere is added to the queue, since it has never been processed.
+
+#[derive(Debug, Default)]
+pub(crate) struct SpanQueue {
+    /// The `(start, end)` character offsets of the spans, sorted and with no
+    /// two spans touching
+    spans: Vec<(usize, usize)>,
+}
+
+impl SpanQueue {
+    /// A queue holding the text between `start` and `end`.
+    pub(crate) fn new(start: usize, end: usize) -> Self {
+        let mut queue = Self::default();
+        queue.add(start, end);
+        queue
+    }
+
+    /// Add the text between `start` and `end`, merging it with any spans it
+    /// touches.
+    pub(crate) fn add(&mut self, start: usize, end: usize) {
+        if start >= end {
+            return;
+        }
+        let first = self.spans.partition_point(|x| x.1 < start);
+        let last = self.spans.partition_point(|x| x.0 <= end);
+        let touching = &self.spans[first..last];
+        let start = touching.first().map_or(start, |x| x.0.min(start));
+        let end = touching.last().map_or(end, |x| x.1.max(end));
+        self.spans.splice(first..last, [(start, end)]);
+    }
+
+    /// Remove the text between `start` and `end` from the queue, returning
+    /// the parts of the spans that were in it.
+    pub(crate) fn take(&mut self, start: usize, end: usize) -> Vec<(usize, usize)> {
+        let first = self.spans.partition_point(|x| x.1 <= start);
+        let last = self.spans.partition_point(|x| x.0 < end);
+        if first >= last {
+            return Vec::new();
+        }
+        let taken: Vec<_> =
+            self.spans[first..last].iter().map(|x| (x.0.max(start), x.1.min(end))).collect();
+        let mut kept = Vec::new();
+        if self.spans[first].0 < start {
+            kept.push((self.spans[first].0, start));
+        }
+        if self.spans[last - 1].1 > end {
+            kept.push((end, self.spans[last - 1].1));
+        }
+        self.spans.splice(first..last, kept);
+        taken
+    }
+
+    /// Adjust the spans for `len` characters inserted at `pos`, and add the
+    /// new text.
+    pub(crate) fn insert_text(&mut self, pos: usize, len: usize) {
+        if len == 0 {
+            return;
+        }
+        for span in &mut self.spans {
+            if span.0 >= pos {
+                span.0 += len;
+            }
+            if span.1 >= pos {
+                span.1 += len;
+            }
+        }
+        self.add(pos, pos + len);
+    }
+
+    /// Adjust the spans for the text between `beg` and `end` being deleted.
+    pub(crate) fn delete_text(&mut self, beg: usize, end: usize) {
+        if beg >= end {
+            return;
+        }
+        let len = end - beg;
+        let adjust = |pos: usize| if pos >= end { pos - len } else { pos.min(beg) };
+        let spans = std::mem::take(&mut self.spans);
+        for (start, end) in spans {
+            self.add(adjust(start), adjust(end));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_add_and_take() {
+        let mut queue = SpanQueue::new(0, 5);
+        queue.add(10, 15);
+        queue.add(5, 7);
+        assert_eq!(queue.spans, [(0, 7), (10, 15)]);
+        queue.add(6, 11);
+        assert_eq!(queue.spans, [(0, 15)]);
+        assert_eq!(queue.take(3, 6), [(3, 6)]);
+        assert_eq!(queue.spans, [(0, 3), (6, 15)]);
+        assert_eq!(queue.take(0, 20), [(0, 3), (6, 15)]);
+        assert!(queue.spans.is_empty());
+    }
+
+    #[test]
+    fn test_edits() {
+        let mut queue = SpanQueue::new(5, 10);
+        queue.insert_text(2, 3);
+        assert_eq!(queue.spans, [(2, 5), (8, 13)]);
+        queue.insert_text(10, 2);
+        assert_eq!(queue.spans, [(2, 5), (8, 15)]);
+        queue.delete_text(4, 9);
+        assert_eq!(queue.spans, [(2, 10)]);
+        queue.delete_text(0, 20);
+        assert!(queue.spans.is_empty());
+    }
+}
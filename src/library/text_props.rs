@@ -0,0 +1,184 @@
+//! The runs of text properties in a buffer.
+//!
+//! Each run is a non-empty range of text that has the same properties. The
+//! properties themselves are not stored here, only an id for them, so runs
+//! with equal ids have equal properties and adjacent runs are merged. Text
+//! that is not covered by any run has no properties.
+
+use super::interval_tree::{Interval, IntervalTree};
+use rune_core::hashmap::HashMap;
+
+#[derive(Debug, Default)]
+pub(crate) struct TextProperties {
+    runs: IntervalTree,
+    /// The properties of each run, keyed by the id of the run in the tree
+    props: HashMap<usize, usize>,
+    next_run: usize,
+}
+
+impl TextProperties {
+    /// The properties of the character at `pos`.
+    pub(crate) fn get(&self, pos: usize) -> Option<usize> {
+        self.run_at(pos).map(|x| self.props[&x.id])
+    }
+
+    /// The position after `pos` where the properties change, or `None` if they
+    /// stay the same to the end of the text.
+    pub(crate) fn next_change(&self, pos: usize) -> Option<usize> {
+        match self.run_at(pos) {
+            Some(run) => Some(run.end),
+            None => self.runs.iter().map(|x| x.start).find(|x| *x > pos),
+        }
+    }
+
+    /// Set the properties of the text between `start` and `end`, replacing
+    /// what was there before.
+    pub(crate) fn set(&mut self, start: usize, end: usize, props: Option<usize>) {
+        if start >= end {
+            return;
+        }
+        let covered: Vec<_> = self.runs_in(start, end).into_iter().copied().collect();
+        for run in covered {
+            let old = self.remove_run(run.id);
+            if run.start < start {
+                self.insert_run(run.start, start, old);
+            }
+            if run.end > end {
+                self.insert_run(end, run.end, old);
+            }
+        }
+        if let Some(props) = props {
+            self.insert_run(start, end, props);
+            self.merge_at(start);
+            self.merge_at(end);
+        }
+    }
+
+    /// The runs between `start` and `end`, including the gaps between them,
+    /// clipped to the range.
+    pub(crate) fn segments(&self, start: usize, end: usize) -> Vec<(usize, usize, Option<usize>)> {
+        let mut segments = Vec::new();
+        let mut pos = start;
+        for run in self.runs_in(start, end) {
+            if run.start > pos {
+                segments.push((pos, run.start, None));
+            }
+            let run_end = run.end.min(end);
+            segments.push((pos.max(run.start), run_end, Some(self.props[&run.id])));
+            pos = run_end;
+        }
+        if pos < end {
+            segments.push((pos, end, None));
+        }
+        segments
+    }
+
+    /// Adjust the runs for `len` characters inserted at `pos`. The new text
+    /// has no properties.
+    pub(crate) fn insert_text(&mut self, pos: usize, len: usize) {
+        if len == 0 {
+            return;
+        }
+        // Split the run around the new text
+        if let Some(run) = self.run_at(pos).copied().filter(|x| x.start < pos) {
+            let props = self.remove_run(run.id);
+            self.insert_run(run.start, pos, props);
+            self.insert_run(pos, run.end, props);
+        }
+        self.runs.insert_text(pos, len);
+    }
+
+    /// Adjust the runs for the text between `beg` and `end` being deleted.
+    pub(crate) fn delete_text(&mut self, beg: usize, end: usize) {
+        if beg >= end {
+            return;
+        }
+        self.runs.delete_text(beg, end);
+        let empty: Vec<_> = self.runs.iter().filter(|x| x.start == x.end).map(|x| x.id).collect();
+        for id in empty {
+            self.remove_run(id);
+        }
+        self.merge_at(beg);
+    }
+
+    fn run_at(&self, pos: usize) -> Option<&Interval> {
+        self.runs.overlapping(pos, pos).into_iter().find(|x| x.end > pos)
+    }
+
+    fn runs_in(&self, start: usize, end: usize) -> Vec<&Interval> {
+        let mut runs = self.runs.overlapping(start, end);
+        runs.retain(|x| x.start < end && x.end > start);
+        runs
+    }
+
+    fn insert_run(&mut self, start: usize, end: usize, props: usize) {
+        let id = self.next_run;
+        self.next_run += 1;
+        // New text at either edge of a run is never part of it
+        self.runs
+            .insert(Interval { id, start, end, front_advance: true, rear_advance: false });
+        self.props.insert(id, props);
+    }
+
+    fn remove_run(&mut self, id: usize) -> usize {
+        self.runs.remove(id);
+        self.props.remove(&id).expect("text property run should have properties")
+    }
+
+    /// Join the runs on either side of `pos` if they have the same properties.
+    fn merge_at(&mut self, pos: usize) {
+        let adjacent = self.runs.overlapping(pos, pos);
+        let before = adjacent.iter().find(|x| x.end == pos && x.start < pos);
+        let after = adjacent.iter().find(|x| x.start == pos && x.end > pos);
+        let (Some(before), Some(after)) = (before.copied().copied(), after.copied().copied())
+        else {
+            return;
+        };
+        if self.props[&before.id] == self.props[&after.id] {
+            let props = self.remove_run(before.id);
+            self.remove_run(after.id);
+            self.insert_run(before.start, after.end, props);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_set() {
+        let mut props = TextProperties::default();
+        props.set(2, 6, Some(1));
+        props.set(4, 8, Some(2));
+        assert_eq!(
+            props.segments(0, 10),
+            vec![(0, 2, None), (2, 4, Some(1)), (4, 8, Some(2)), (8, 10, None)]
+        );
+        props.set(4, 6, Some(1));
+        assert_eq!(props.get(5), Some(1));
+        assert_eq!(props.next_change(2), Some(6));
+        assert_eq!(props.next_change(0), Some(2));
+        assert_eq!(props.next_change(8), None);
+        props.set(0, 10, None);
+        assert_eq!(props.segments(0, 10), vec![(0, 10, None)]);
+    }
+
+    #[test]
+    fn test_edits() {
+        let mut props = TextProperties::default();
+        props.set(2, 6, Some(1));
+        props.insert_text(4, 2);
+        assert_eq!(
+            props.segments(0, 10),
+            vec![(0, 2, None), (2, 4, Some(1)), (4, 6, None), (6, 8, Some(1)), (8, 10, None)]
+        );
+        props.insert_text(2, 1);
+        assert_eq!(props.get(2), None);
+        assert_eq!(props.get(3), Some(1));
+        props.delete_text(5, 7);
+        assert_eq!(props.segments(0, 8), vec![(0, 3, None), (3, 7, Some(1)), (7, 8, None)]);
+        props.delete_text(0, 8);
+        assert_eq!(props.segments(0, 1), vec![(0, 1, None)]);
+    }
+}
@@ -1,19 +1,22 @@
 //! Loading elisp from files and strings.
 use crate::core::cons::Cons;
-use crate::core::env::{Env, sym};
+use crate::core::env::{CallFrame, Env, sym};
 use crate::core::error::{Type, TypeError};
-use crate::core::gc::{Context, Rt, Rto};
+use crate::core::gc::{Context, Rt, Rto, Slot};
 use crate::core::object::{
     Function, Gc, LispString, NIL, Object, ObjectType, OptionalFlag, Symbol, TRUE, TagType,
-    WithLifetime,
+    int_to_char,
 };
+use crate::eval::EvalError;
 use crate::reader;
 use crate::{interpreter, rooted_iter};
 use anyhow::{Context as _, anyhow};
 use anyhow::{Result, bail, ensure};
 use fallible_streaming_iterator::FallibleStreamingIterator;
-use rune_core::macros::{call, rebind, root};
+use rune_core::macros::{call, list, rebind, root};
 use rune_macros::defun;
+use rune_regex::{Input, Regex};
+use std::fmt::Write as _;
 use std::fs;
 use std::path::{Path, PathBuf};
 
@@ -60,17 +63,158 @@ pub(crate) fn read_from_string<'ob>(
     Ok(Cons::new(obj, new_pos as i64, cx).into())
 }
 
+/// Read one lisp object from STREAM.
+///
+/// STREAM can be a string, which is read from the start, a buffer, which is
+/// read from point and leaves point after the object, or a function, which is
+/// called with no arguments to get each character and with one argument to
+/// unread a character. t reads a line from stdin. If STREAM is nil, the value
+/// of `standard-input` is used instead.
+#[defun]
+fn read<'ob>(
+    stream: Option<&Rto<Object>>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<Object<'ob>> {
+    let stream = match stream.map(|x| x.bind(cx)) {
+        Some(stream) if !stream.is_nil() => stream,
+        _ => env.vars.get(sym::STANDARD_INPUT).map(|x| x.bind(cx)).unwrap_or_default(),
+    };
+    match stream.untag() {
+        ObjectType::String(string) => read_str(string, env, cx).map(|(obj, _)| obj),
+        ObjectType::Buffer(buffer) => {
+            let (text, point) = env.with_buffer(buffer, |b| {
+                let (s1, s2) = b.slice_with_gap(b.point(), b.point_max())?;
+                anyhow::Ok(([s1, s2].concat(), b.point()))
+            })??;
+            let (obj, end) = read_str(&text, env, cx)?;
+            let point = point + text[..end].chars().count();
+            env.with_buffer_mut(buffer, |b| b.goto_char(point))?;
+            Ok(obj)
+        }
+        ObjectType::NIL | ObjectType::Symbol(sym::TRUE) => {
+            let line = crate::minibuf::read_line(
+                "Lisp expression: ",
+                &mut std::io::stdin().lock(),
+                env,
+                cx,
+            )?;
+            read_str(&line, env, cx).map(|(obj, _)| obj)
+        }
+        _ => {
+            let func: Function = stream.try_into()?;
+            root!(func, cx);
+            read_from_function(func, env, cx)
+        }
+    }
+}
+
+/// Read an object from `text`, signaling `end-of-file` if there is none.
+fn read_str<'ob>(text: &str, env: &mut Rt<Env>, cx: &'ob Context) -> Result<(Object<'ob>, usize)> {
+    match reader::read(text, cx) {
+        Ok(x) => Ok(x),
+        Err(reader::Error::EmptyStream) => {
+            let data = list![cx.add("End of file during parsing"); cx];
+            Err(EvalError::signal(sym::END_OF_FILE.into(), data, env).into())
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Read an object from the characters returned by calling `func`. Characters
+/// read past the end of the object are unread by passing them to `func`.
+fn read_from_function<'ob>(
+    func: &Rto<Function>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<Object<'ob>> {
+    let mut text = String::new();
+    let end = loop {
+        let frame = &mut CallFrame::new(env);
+        let chr = match func.call(frame, None, cx)?.untag() {
+            ObjectType::NIL => None,
+            ObjectType::Int(c) => {
+                let Ok(chr) = int_to_char(c) else { bail!("{c} is an invalid char") };
+                Some(chr)
+            }
+            other => bail!(TypeError::new(Type::Int, other)),
+        };
+        let Some(chr) = chr else { break text.len() };
+        text.push(chr);
+        // Objects can only end at a delimiter, so only try to read there
+        if !(chr.is_whitespace() || matches!(chr, ')' | ']' | '"')) {
+            continue;
+        }
+        match reader::read(&text, cx) {
+            Ok((_, end)) if end < text.len() || !chr.is_whitespace() => break end,
+            Ok(_) => {}
+            Err(e) if e.is_incomplete() => {}
+            Err(e) => bail!(e),
+        }
+    };
+    let unread: Vec<char> = text[end..].chars().rev().collect();
+    for chr in unread {
+        let frame = &mut CallFrame::new(env);
+        frame.push_arg(cx.add(chr));
+        func.call(frame, None, cx)?;
+    }
+    read_str(&text[..end], env, cx).map(|(obj, _)| obj)
+}
+
+/// Read one form from STREAM like `read`, recording the position of each
+/// symbol in it. The positions are stored in `read-symbol-positions-list` as
+/// an alist of (SYMBOL . POSITION), where POSITION is the character offset of
+/// the symbol in STREAM. Only strings are supported as streams.
+#[defun]
+fn read_positioning_symbols<'ob>(
+    stream: &str,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    // TODO: return symbols with position objects instead of an alist
+    let (obj, _, positions) = reader::read_with_positions(stream, cx)?;
+    let alist: Vec<Object> = positions
+        .into_iter()
+        .filter(|(obj, _)| matches!(obj.untag(), ObjectType::Symbol(_)))
+        .map(|(obj, pos)| Cons::new(obj, stream[..pos].chars().count(), cx).into())
+        .collect();
+    env.set_var(sym::READ_SYMBOL_POSITIONS_LIST, crate::fns::slice_into_list(&alist, None, cx))?;
+    Ok(obj)
+}
+
 pub(crate) fn load_internal(contents: &str, cx: &mut Context, env: &mut Rt<Env>) -> Result<bool> {
+    readevalloop(contents, None, cx, env)?;
+    Ok(true)
+}
+
+/// Read and evaluate each form in `contents`. If `printflag` is non-nil, the
+/// value of each form is printed to it. An incomplete form at the end signals
+/// `end-of-file`.
+fn readevalloop(
+    contents: &str,
+    printflag: Option<&Rto<Object>>,
+    cx: &mut Context,
+    env: &mut Rt<Env>,
+) -> Result<()> {
     let mut pos = 0;
     let macroexpand: Option<Function> = None;
     root!(macroexpand, cx);
     if let Some(fun) = sym::INTERNAL_MACROEXPAND_FOR_LOAD.func(cx) {
         macroexpand.set(Some(fun));
     }
+    let share_strings = env
+        .vars
+        .get(sym::LOAD_SHARE_STRING_CONSTANTS)
+        .is_some_and(|x| !x.bind(cx).is_nil());
     loop {
-        let (obj, new_pos) = match reader::read(&contents[pos..], cx) {
+        let read = if share_strings { reader::read_sharing_strings } else { reader::read };
+        let (obj, new_pos) = match read(&contents[pos..], cx) {
             Ok((obj, pos)) => (obj, pos),
-            Err(reader::Error::EmptyStream) => return Ok(true),
+            Err(reader::Error::EmptyStream) => return Ok(()),
+            Err(e) if e.is_incomplete() => {
+                let data = list![cx.add("End of file during parsing"); cx];
+                return Err(EvalError::signal(sym::END_OF_FILE.into(), data, env).into());
+            }
             Err(mut e) => {
                 e.update_pos(pos);
                 bail!(e);
@@ -87,17 +231,106 @@ pub(crate) fn load_internal(contents: &str, cx: &mut Context, env: &mut Rt<Env>)
         } else {
             interpreter::eval(obj, None, env, cx)
         };
-        if let Err(e) = result {
-            let content = &contents[pos..(new_pos + pos)];
-            println!("-----LOAD ERROR START-----\n {content}");
-            println!("-----LOAD ERROR END-----");
-            return Err(e);
+        let value = match result {
+            Ok(value) => rebind!(value, cx),
+            Err(e) => {
+                let content = &contents[pos..(new_pos + pos)];
+                println!("-----LOAD ERROR START-----\n {content}");
+                println!("-----LOAD ERROR END-----");
+                return Err(e);
+            }
+        };
+        if let Some(printflag) = printflag.filter(|x| !x.bind(cx).is_nil()) {
+            root!(value, cx);
+            crate::print::print(value, Some(printflag), env, cx)?;
         }
         assert_ne!(new_pos, 0);
         pos += new_pos;
     }
 }
 
+/// The value of `lexical-binding` set in the `-*-` line at the start of
+/// `text`, if there is one.
+fn lexical_binding_cookie(text: &str) -> Option<bool> {
+    let line = text.lines().next()?;
+    let start = line.find("-*-")? + 3;
+    let end = start + line[start..].find("-*-")?;
+    line[start..end].split(';').find_map(|var| {
+        let (name, value) = var.split_once(':')?;
+        (name.trim() == "lexical-binding").then(|| value.trim() != "nil")
+    })
+}
+
+/// Execute the accessible portion of BUFFER as lisp code. BUFFER defaults to
+/// the current buffer. If PRINTFLAG is non-nil, the value of each form is
+/// printed to it. `lexical-binding` is set from the `-*-` line of the buffer.
+/// Definitions are recorded in `load-history` under FILENAME, which defaults
+/// to `buffer-file-name`.
+#[defun]
+fn eval_buffer(
+    buffer: Option<&Rto<Object>>,
+    printflag: Option<&Rto<Object>>,
+    filename: Option<&Rto<Object>>,
+    _unibyte: OptionalFlag,
+    _do_allow_print: OptionalFlag,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<()> {
+    let old_buffer = env.current_buffer.get().lisp_buffer(cx);
+    root!(old_buffer, cx);
+    let buffer = match buffer.map(|x| x.bind(cx)) {
+        Some(buffer) if !buffer.is_nil() => crate::buffer::resolve_buffer(buffer, cx)?,
+        _ => env.current_buffer.get().lisp_buffer(cx),
+    };
+    let text = env.with_buffer(buffer, |b| {
+        let (s1, s2) = b.slice_with_gap(b.point_min(), b.point_max())?;
+        anyhow::Ok([s1, s2].concat())
+    })??;
+    env.set_buffer(buffer, cx);
+    let mut count = 0;
+    if let Some(lexical) = lexical_binding_cookie(&text) {
+        env.varbind(sym::LEXICAL_BINDING, lexical.into(), cx);
+        count += 1;
+    }
+    let filename = match filename.map(|x| x.bind(cx)) {
+        Some(filename) if !filename.is_nil() => Some(filename),
+        _ => env.vars.get(sym::BUFFER_FILE_NAME).map(|x| x.bind(cx)).filter(|x| !x.is_nil()),
+    };
+    root!(filename, cx);
+    if let Some(filename) = filename.as_ref() {
+        let load_list = Cons::new1(filename.bind(cx), cx).into();
+        env.varbind(sym::CURRENT_LOAD_LIST, load_list, cx);
+        count += 1;
+    }
+    let result = readevalloop(&text, printflag, cx, env);
+    if let Some(filename) = filename.as_ref().filter(|_| result.is_ok()) {
+        let load_list = env.vars.get(sym::CURRENT_LOAD_LIST).map_or(NIL, |x| x.bind(cx));
+        record_load_history(filename.bind(cx), load_list, env, cx)?;
+    }
+    env.unbind(count, cx);
+    let old_buffer = old_buffer.bind(cx);
+    if env.with_buffer(old_buffer, |_| {}).is_ok() {
+        env.set_buffer(old_buffer, cx);
+    }
+    result
+}
+
+/// Execute the region between START and END as lisp code. If PRINTFLAG is
+/// non-nil, the value of each form is printed to it.
+#[defun]
+fn eval_region(
+    start: usize,
+    end: usize,
+    printflag: Option<&Rto<Object>>,
+    _read_function: Option<&Rto<Object>>,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<()> {
+    let (s1, s2) = env.current_buffer.get().slice_with_gap(start.min(end), start.max(end))?;
+    let text = [s1, s2].concat();
+    readevalloop(&text, printflag, cx, env)
+}
+
 fn eager_expand<'ob>(
     obj: &Rto<Object>,
     macroexpand: &Rto<Function>,
@@ -118,27 +351,90 @@ fn eager_expand<'ob>(
     }
     let result = call!(macroexpand, val, TRUE; name, env, cx)?;
     root!(result, cx);
-    interpreter::eval(result, None, env, cx)
+    let fold = env.vars.get(sym::LOAD_FOLD_CONSTANTS).is_some_and(|x| !x.bind(cx).is_nil());
+    // forms that can't be optimized are evaluated as they are
+    let optimized = if fold { crate::optimize::optimize(result, env, cx).ok() } else { None };
+    let form = match optimized {
+        Some(form) => rebind!(form, cx),
+        None => result.bind(cx),
+    };
+    root!(form, cx);
+    interpreter::eval(form, None, env, cx)
 }
 
-fn file_in_path(file: &str, path: &str) -> Option<PathBuf> {
-    let path = Path::new(path).join(file);
-    if path.exists() {
-        Some(path)
-    } else {
-        let with_ext = path.with_extension("el");
-        with_ext.exists().then_some(with_ext)
+/// Find `file` in `dir`, trying each of `suffixes` in order. The bare filename
+/// is tried last unless `must_suffix` is set. If a file with a later suffix,
+/// like the source of a compiled file, is newer than the first one found, it
+/// is used instead when `prefer_newer` is set. Otherwise a warning is printed.
+fn file_in_path(
+    file: &str,
+    dir: &str,
+    suffixes: &[String],
+    must_suffix: bool,
+    prefer_newer: bool,
+) -> Option<PathBuf> {
+    let path = Path::new(dir).join(file);
+    let mut found = suffixes
+        .iter()
+        .map(|suffix| {
+            let mut with_ext = path.clone().into_os_string();
+            with_ext.push(suffix);
+            PathBuf::from(with_ext)
+        })
+        .filter(|x| x.is_file());
+    let Some(first) = found.next() else {
+        return (!must_suffix && path.is_file()).then_some(path);
+    };
+    let modified = |x: &Path| x.metadata().and_then(|x| x.modified()).ok();
+    let first_modified = modified(&first);
+    let newest = found.filter(|x| modified(x) > first_modified).max_by_key(|x| modified(x));
+    match newest {
+        Some(newest) if prefer_newer => Some(newest),
+        Some(newest) => {
+            let (old, new) = (first.display(), newest.display());
+            println!("File `{new}' is newer than `{old}'; using older file");
+            Some(first)
+        }
+        None => Some(first),
+    }
+}
+
+fn load_suffixes(cx: &Context, env: &Rt<Env>) -> Result<Vec<String>> {
+    let Some(suffixes) = env.vars.get(sym::LOAD_SUFFIXES) else { return Ok(Vec::new()) };
+    let mut result = Vec::new();
+    for suffix in suffixes.bind(cx).as_list().context("`load-suffixes' was not a list")? {
+        let suffix: &str = suffix?.try_into()?;
+        result.push(suffix.to_owned());
     }
+    Ok(result)
+}
+
+fn prefer_newer(cx: &Context, env: &Rt<Env>) -> bool {
+    env.vars.get(sym::LOAD_PREFER_NEWER).is_some_and(|x| !x.bind(cx).is_nil())
 }
 
-fn find_file_in_load_path(file: &str, cx: &Context, env: &Rt<Env>) -> Result<PathBuf> {
-    let load_path = env.vars.get(sym::LOAD_PATH).unwrap();
-    let paths = load_path.bind(cx).as_list().context("`load-path' was not a list")?;
+fn find_file_in_load_path(
+    file: &str,
+    suffixes: &[String],
+    must_suffix: bool,
+    cx: &Context,
+    env: &Rt<Env>,
+) -> Result<PathBuf> {
+    let prefer_newer = prefer_newer(cx, env);
+    let load_path = env.vars.get(sym::LOAD_PATH).map_or(NIL, |x| x.bind(cx));
+    let paths = load_path.as_list().context("`load-path' was not a list")?;
     let mut final_file = None;
     for path in paths {
         match path?.untag() {
             ObjectType::String(path) => {
-                if let Some(x) = file_in_path(file, path) {
+                if let Some(x) = file_in_path(file, path, suffixes, must_suffix, prefer_newer) {
+                    final_file = Some(x);
+                    break;
+                }
+            }
+            // nil in `load-path' means `default-directory'
+            ObjectType::NIL => {
+                if let Some(x) = file_in_path(file, "", suffixes, must_suffix, prefer_newer) {
                     final_file = Some(x);
                     break;
                 }
@@ -152,26 +448,81 @@ fn find_file_in_load_path(file: &str, cx: &Context, env: &Rt<Env>) -> Result<Pat
     final_file.ok_or_else(|| anyhow!("Unable to find file `{file}' in load-path"))
 }
 
+/// Record an entry of the form `(KIND . VALUE)` in `current-load-list` if a
+/// file is being loaded. This is later saved to `load-history` when the file
+/// is done loading.
+pub(crate) fn record_load_entry(kind: Symbol, value: Object, env: &mut Rt<Env>, cx: &Context) {
+    let Some(list) = env.vars.get(sym::CURRENT_LOAD_LIST).map(|x| x.bind(cx)) else { return };
+    // While a file is loaded or evaluated, the list ends with its name
+    let loading = list
+        .as_list()
+        .ok()
+        .and_then(|x| x.last())
+        .is_some_and(|x| x.is_ok_and(|x| matches!(x.untag(), ObjectType::String(_))));
+    if !loading {
+        return;
+    }
+    let entry: Object = Cons::new(kind, value, cx).into();
+    let list: Object = Cons::new(entry, list, cx).into();
+    env.vars.insert(sym::CURRENT_LOAD_LIST, list);
+}
+
+/// Add the load list of `file` to `load-history`, replacing any previous
+/// entry for the same file.
+fn record_load_history(
+    file: Object,
+    load_list: Object,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<()> {
+    let mut entries = Vec::new();
+    for entry in load_list.as_list()? {
+        entries.push(entry?);
+    }
+    // The list ends with the file it was bound for
+    if entries.last() == Some(&file) {
+        entries.pop();
+    }
+    entries.reverse();
+    let entry = Cons::new(file, crate::fns::slice_into_list(&entries, None, cx), cx);
+    let mut history = vec![entry.into()];
+    if let Some(prev) = env.vars.get(sym::LOAD_HISTORY) {
+        for elem in prev.bind(cx).as_list()? {
+            let elem = elem?;
+            let is_same_file = matches!(elem.untag(), ObjectType::Cons(x) if x.car() == file);
+            if !is_same_file {
+                history.push(elem);
+            }
+        }
+    }
+    let history = crate::fns::slice_into_list(&history, None, cx);
+    env.vars.insert(sym::LOAD_HISTORY, history);
+    Ok(())
+}
+
 #[defun]
 pub(crate) fn load(
     file: &Rto<Gc<&LispString>>,
     noerror: OptionalFlag,
     nomessage: OptionalFlag,
+    nosuffix: OptionalFlag,
+    must_suffix: OptionalFlag,
     cx: &mut Context,
     env: &mut Rt<Env>,
 ) -> Result<bool> {
     let noerror = noerror.is_some();
     let nomessage = nomessage.is_some();
     let file: &str = file.untag(cx);
-    let final_file = if Path::new(file).exists() {
-        PathBuf::from(file)
-    } else {
-        match find_file_in_load_path(file, cx, env) {
+    let suffixes = if nosuffix.is_some() { Vec::new() } else { load_suffixes(cx, env)? };
+    let must_suffix = must_suffix.is_some() && nosuffix.is_none();
+    let final_file = match file_in_path(file, "", &suffixes, must_suffix, prefer_newer(cx, env)) {
+        Some(x) => x,
+        None => match find_file_in_load_path(file, &suffixes, must_suffix, cx, env) {
             Ok(x) => x,
             Err(e) => {
                 return if noerror { Ok(false) } else { Err(e) };
             }
-        }
+        },
     };
 
     let filename = String::from(file);
@@ -179,39 +530,264 @@ pub(crate) fn load(
         println!("Loading {filename}...");
     }
     let new_load_file = cx.add(final_file.to_string_lossy().to_string());
-    let prev_load_file = match env.vars.get_mut(sym::LOAD_FILE_NAME) {
-        Some(val) => {
-            let prev = val.bind(cx);
-            val.set(new_load_file);
-            prev
-        }
-        None => NIL,
-    };
-    root!(prev_load_file, cx);
+    root!(new_load_file, cx);
+    env.varbind(sym::LOAD_FILE_NAME, new_load_file.bind(cx), cx);
+    env.varbind(sym::LOAD_TRUE_FILE_NAME, new_load_file.bind(cx), cx);
+    env.varbind(sym::LOAD_IN_PROGRESS, TRUE, cx);
+    let load_list = Cons::new1(new_load_file.bind(cx), cx).into();
+    env.varbind(sym::CURRENT_LOAD_LIST, load_list, cx);
     let result = match fs::read_to_string(&final_file)
         .with_context(|| format!("Couldn't open file {:?}", final_file.as_os_str()))
     {
-        Ok(content) => load_internal(&content, cx, env),
+        Ok(content) => {
+            // skip the interpreter line of scripts
+            let content = match content.strip_prefix("#!") {
+                Some(rest) => rest.find('\n').map_or("", |i| &rest[i..]),
+                None => &content,
+            };
+            load_internal(content, cx, env)
+        }
         Err(e) => match noerror {
             true => Ok(false),
             false => Err(e),
         },
     };
+    let load_list = env.vars.get(sym::CURRENT_LOAD_LIST).map_or(NIL, |x| x.bind(cx));
+    root!(load_list, cx);
+    env.unbind(4, cx);
 
-    if !nomessage && result.is_ok() {
-        println!("Loading {filename} Done");
+    if result.is_ok() {
+        record_load_history(new_load_file.bind(cx), load_list.bind(cx), env, cx)?;
+        if !nomessage {
+            println!("Loading {filename} Done");
+        }
+        // Features provided by the file run their `eval-after-load` forms
+        // now that the whole file is loaded
+        root!(features, new(Vec<Slot<Object>>), cx);
+        for entry in load_list.bind(cx).as_list()? {
+            if let ObjectType::Cons(entry) = entry?.untag()
+                && entry.car() == sym::PROVIDE
+            {
+                features.push(entry.cdr());
+            }
+        }
+        // The load list is newest first
+        for i in (0..features.len()).rev() {
+            run_after_load_functions(&features[i], env, cx)?;
+        }
+        let func: Function = sym::DO_AFTER_LOAD_EVALUATION.into();
+        root!(func, cx);
+        call!(func, new_load_file.bind(cx); env, cx)?;
     }
-    env.vars.insert(sym::LOAD_FILE_NAME, &*prev_load_file);
     result
 }
 
+/// A regexp matching the absolute names of the files that
+/// `eval-after-load` considers to be FILE. If FILE has no extension, any of
+/// the load suffixes may follow it.
+fn load_history_regexp(file: &str, suffixes: &[String]) -> String {
+    let start = if Path::new(file).is_absolute() { "\\`" } else { "\\(\\`\\|/\\)" };
+    let mut regexp = format!("{start}{}", crate::search::regexp_quote(file));
+    if Path::new(file).extension().is_none() {
+        let suffixes: Vec<_> = suffixes.iter().map(|x| crate::search::regexp_quote(x)).collect();
+        write!(regexp, "\\({}\\)?", suffixes.join("\\|")).unwrap();
+    }
+    regexp.push_str("\\'");
+    regexp
+}
+
+/// True if the file name `file` matches `regexp`.
+fn file_matches(regexp: &str, file: &str) -> Result<bool> {
+    let regexp = Regex::new(regexp)?;
+    Ok(regexp.search_forward(&Input::new(file))?.is_some())
+}
+
+/// Call the functions registered in `after-load-alist` for `key`, which is
+/// either a feature or a file name regexp.
+pub(crate) fn run_after_load_functions(
+    key: &Rto<Object>,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<()> {
+    root!(functions, new(Vec<Slot<Object>>), cx);
+    let alist = env.vars.get(sym::AFTER_LOAD_ALIST).map_or(NIL, |x| x.bind(cx));
+    for elt in alist.as_list()? {
+        if let ObjectType::Cons(elt) = elt?.untag()
+            && crate::fns::equal(elt.car(), key.bind(cx))
+        {
+            for func in elt.cdr().as_list()? {
+                functions.push(func?);
+            }
+        }
+    }
+    for i in 0..functions.len() {
+        let func: Function = functions[i].bind(cx).try_into()?;
+        root!(func, cx);
+        call!(func; env, cx)?;
+    }
+    Ok(())
+}
+
+/// Arrange for FORM to be run after FILE is loaded. If FILE is already
+/// loaded, FORM is also run now, and its value is returned.
+///
+/// FILE is either a file name or a feature. A relative file name matches
+/// any loaded file whose absolute name ends with it, and if it has no
+/// extension, any of the `load-suffixes` may follow it. A feature runs FORM
+/// at the end of the file that `provide`s it, or immediately if it is
+/// provided outside of `load`.
+///
+/// FORM is either a function, which is called with no arguments, or an
+/// expression to evaluate. It is run again each time a matching file is
+/// loaded. The forms are kept in `after-load-alist`.
+#[defun]
+fn eval_after_load<'ob>(
+    file: &Rto<Object>,
+    form: &Rto<Object>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<Object<'ob>> {
+    let key = match file.untag(cx) {
+        ObjectType::String(name) => {
+            let suffixes = load_suffixes(cx, env)?;
+            cx.add(load_history_regexp(name, &suffixes))
+        }
+        ObjectType::Symbol(_) => file.bind(cx),
+        x => bail!(TypeError::new(Type::String, x)),
+    };
+    root!(key, cx);
+    let func = if crate::data::functionp(form.bind(cx)) {
+        form.bind(cx)
+    } else {
+        let lambda = list![sym::FUNCTION, list![sym::LAMBDA, NIL, form.bind(cx); cx]; cx];
+        root!(lambda, cx);
+        let lexical = env.vars.get(sym::LEXICAL_BINDING).map_or(NIL, |x| x.bind(cx));
+        root!(lexical, cx);
+        let func = interpreter::eval(lambda, Some(lexical), env, cx)?;
+        rebind!(func, cx)
+    };
+    root!(func, cx);
+
+    let alist = env.vars.get(sym::AFTER_LOAD_ALIST).map_or(NIL, |x| x.bind(cx));
+    let mut entry = None;
+    for elt in alist.as_list()? {
+        if let ObjectType::Cons(elt) = elt?.untag()
+            && crate::fns::equal(elt.car(), key.bind(cx))
+        {
+            entry = Some(elt);
+            break;
+        }
+    }
+    let entry = match entry {
+        Some(entry) => entry,
+        None => {
+            let entry = Cons::new1(key.bind(cx), cx);
+            let alist: Object = Cons::new(entry, alist, cx).into();
+            env.vars.insert(sym::AFTER_LOAD_ALIST, alist);
+            entry
+        }
+    };
+    let mut last = entry;
+    let mut present = false;
+    for tail in entry.conses().skip(1) {
+        last = tail?;
+        present |= crate::fns::equal(last.car(), func.bind(cx));
+    }
+    if !present {
+        last.set_cdr(Cons::new1(func.bind(cx), cx).into())?;
+    }
+
+    let loaded = match key.untag(cx) {
+        ObjectType::String(regexp) => {
+            let history = env.vars.get(sym::LOAD_HISTORY).map_or(NIL, |x| x.bind(cx));
+            let mut loaded = false;
+            for elt in history.as_list()? {
+                if let ObjectType::Cons(elt) = elt?.untag()
+                    && let ObjectType::String(name) = elt.car().untag()
+                    && file_matches(regexp, name)?
+                {
+                    loaded = true;
+                    break;
+                }
+            }
+            loaded
+        }
+        ObjectType::Symbol(feature) => crate::fns::featurep(feature, None),
+        _ => false,
+    };
+    if !loaded {
+        return Ok(NIL);
+    }
+    let func: Function = func.bind(cx).try_into()?;
+    root!(func, cx);
+    Ok(call!(func; env, cx)?)
+}
+
+/// Run the `eval-after-load` forms for the files matching ABS-FILE, the
+/// absolute name of a file that was just loaded, and then the hook
+/// `after-load-functions`. This is called by `load`.
+#[defun]
+fn do_after_load_evaluation(
+    abs_file: &Rto<Object>,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<()> {
+    let file: &str = abs_file.bind(cx).try_into()?;
+    let file = file.to_owned();
+    root!(regexps, new(Vec<Slot<Object>>), cx);
+    let alist = env.vars.get(sym::AFTER_LOAD_ALIST).map_or(NIL, |x| x.bind(cx));
+    for elt in alist.as_list()? {
+        if let ObjectType::Cons(elt) = elt?.untag()
+            && let ObjectType::String(regexp) = elt.car().untag()
+            && file_matches(regexp, &file)?
+        {
+            regexps.push(elt.car());
+        }
+    }
+    for i in 0..regexps.len() {
+        run_after_load_functions(&regexps[i], env, cx)?;
+    }
+    let hook = env.vars.get(sym::AFTER_LOAD_FUNCTIONS).map_or(NIL, |x| x.bind(cx));
+    root!(hook, cx);
+    run_hook_with_object(hook, abs_file, env, cx)
+}
+
+/// Call each function in `hook`, the value of a hook variable, with `arg`.
+fn run_hook_with_object(
+    hook: &Rto<Object>,
+    arg: &Rto<Object>,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<()> {
+    root!(functions, new(Vec<Slot<Object>>), cx);
+    match hook.untag(cx) {
+        ObjectType::NIL => {}
+        ObjectType::Cons(list) => {
+            for func in list {
+                functions.push(func?);
+            }
+        }
+        _ => functions.push(hook.bind(cx)),
+    }
+    for i in 0..functions.len() {
+        let func: Function = functions[i].bind(cx).try_into()?;
+        root!(func, cx);
+        call!(func, arg.bind(cx); env, cx)?;
+    }
+    Ok(())
+}
+
 #[defun]
 pub(crate) fn intern<'ob>(string: &str, cx: &'ob Context) -> Symbol<'ob> {
     crate::core::env::intern(string, cx)
 }
 
 #[defun]
-pub(crate) fn intern_soft(string: Object, obarray: OptionalFlag) -> Result<Symbol> {
+pub(crate) fn intern_soft<'ob>(
+    string: Object<'ob>,
+    obarray: OptionalFlag,
+    cx: &'ob Context,
+) -> Result<Symbol<'ob>> {
     ensure!(obarray.is_none(), "intern-soft obarray not implemented");
     match string.untag() {
         ObjectType::Symbol(sym) => {
@@ -222,25 +798,81 @@ pub(crate) fn intern_soft(string: Object, obarray: OptionalFlag) -> Result<Symbo
             }
         }
         ObjectType::String(string) => {
+            Ok(crate::core::env::intern_soft(string, cx).unwrap_or(sym::NIL))
+        }
+        x => Err(TypeError::new(Type::String, x).into()),
+    }
+}
+
+/// Call FUNCTION on every symbol in OBARRAY. OBARRAY defaults to the global
+/// obarray. A vector of symbols is also accepted as an obarray, and so is a
+/// hash table whose values are symbols, like an abbrev table.
+#[defun]
+fn mapatoms(
+    function: &Rto<Function>,
+    obarray: Option<&Rto<Object>>,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<()> {
+    root!(symbols, new(Vec), cx);
+    match obarray.map(|x| x.untag(cx)) {
+        None | Some(ObjectType::NIL) => {
             let map = crate::core::env::INTERNED_SYMBOLS.lock().unwrap();
-            match map.get(string) {
-                Some(sym) => Ok(unsafe { sym.with_lifetime() }),
-                None => Ok(sym::NIL),
+            let mut all: Vec<_> = map.symbols().collect();
+            if crate::fns::deterministic(env, cx) {
+                all.sort_unstable_by(|a, b| a.name().cmp(b.name()));
+            }
+            for symbol in all {
+                symbols.push(Object::from(symbol));
             }
         }
-        x => Err(TypeError::new(Type::String, x).into()),
+        Some(ObjectType::Vec(vec)) => {
+            let is_symbol = |x: &Object| matches!(x.untag(), ObjectType::Symbol(_));
+            for symbol in vec.iter().map(|x| x.get()).filter(is_symbol) {
+                symbols.push(symbol);
+            }
+        }
+        Some(ObjectType::HashTable(table)) => {
+            for i in 0..table.len() {
+                let Some((_, symbol)) = table.get_index(i) else { continue };
+                if let ObjectType::Symbol(_) = symbol.untag() {
+                    symbols.push(symbol);
+                }
+            }
+        }
+        Some(x) => bail!(TypeError::new(Type::Vec, x)),
+    }
+    for i in 0..symbols.len() {
+        let symbol = symbols[i].bind(cx);
+        call!(function, symbol; env, cx)?;
     }
+    Ok(())
 }
 
 defsym!(INTERNAL_MACROEXPAND_FOR_LOAD);
+// Non-nil means equal string constants in each form that `load' reads share
+// a single object, which reduces allocation for large files.
+defvar_bool!(LOAD_SHARE_STRING_CONSTANTS, false);
+// Non-nil means constant expressions in the forms that `load' evaluates are
+// folded first. See `crate::optimize`.
+defvar_bool!(LOAD_FOLD_CONSTANTS, true);
 defvar!(LEXICAL_BINDING, true);
 defvar!(CURRENT_LOAD_LIST);
 defvar!(LOAD_HISTORY);
 defvar!(LOAD_PATH, list![format!("{}/lisp", env!("CARGO_MANIFEST_DIR"))]);
 defvar!(LOAD_FILE_NAME);
+defvar!(LOAD_TRUE_FILE_NAME);
+defvar!(LOAD_IN_PROGRESS);
+defvar!(LOAD_SUFFIXES, list![".elc", ".el"]);
+// Non-nil means `load' uses the newest of the files it finds with the
+// different `load-suffixes', instead of the first one.
+defvar_bool!(LOAD_PREFER_NEWER, false);
 defvar!(BYTE_BOOLEAN_VARS);
 defvar!(MACROEXP__DYNVARS);
 defvar!(AFTER_LOAD_ALIST);
+defvar!(AFTER_LOAD_FUNCTIONS);
+defvar!(READ_SYMBOL_POSITIONS_LIST);
+defvar!(STANDARD_INPUT, true);
 
 #[cfg(test)]
 mod test {
@@ -262,4 +894,149 @@ mod test {
         let val = interpreter::eval(obj, None, env, cx).unwrap();
         assert_eq!(val, 4.5);
     }
+
+    #[test]
+    fn test_read() {
+        use crate::interpreter::assert_lisp;
+        assert_lisp(r#"(read "(a . b) c")"#, "(a . b)");
+        assert_lisp(r#"(condition-case nil (read " ; comment") (end-of-file 'eof))"#, "eof");
+        assert_lisp(
+            r#"(progn (insert "(a b) c") (goto-char 1)
+                 (list (read (current-buffer)) (point) (read (current-buffer)) (point)))"#,
+            "((a b) 6 c 8)",
+        );
+        assert_lisp(
+            "(let ((chars '(?f ?o ?o ?\\s ?b)))
+               (list (read #'(lambda (&optional c)
+                             (if c (setq chars (cons c chars))
+                               (prog1 (car chars) (setq chars (cdr chars))))))
+                     chars))",
+            "(foo (?\\s ?b))",
+        );
+        assert_lisp(
+            "(let ((chars '(?\\( ?a ?\\) ?b)))
+               (list (read #'(lambda (&optional c)
+                             (if c (setq chars (cons c chars))
+                               (prog1 (car chars) (setq chars (cdr chars))))))
+                     chars))",
+            "((a) (?b))",
+        );
+    }
+
+    #[test]
+    fn test_mapatoms() {
+        use crate::interpreter::assert_lisp;
+        assert_lisp(
+            "(let ((n 0)) (mapatoms #'(lambda (s) (if (eq s 'car) (setq n (1+ n))))) n)",
+            "1",
+        );
+        assert_lisp(
+            "(let (syms) (mapatoms #'(lambda (s) (setq syms (cons s syms))) [foo 0 bar]) syms)",
+            "(bar foo)",
+        );
+    }
+
+    #[test]
+    fn test_read_positioning_symbols() {
+        crate::interpreter::assert_lisp(
+            r#"(list (read-positioning-symbols "(é foo (bar 1))") read-symbol-positions-list)"#,
+            "((é foo (bar 1)) ((é . 1) (foo . 3) (bar . 8)))",
+        );
+    }
+
+    #[test]
+    fn test_lexical_binding_cookie() {
+        assert_eq!(
+            lexical_binding_cookie(";;; foo.el --- bar -*- lexical-binding: t -*-"),
+            Some(true)
+        );
+        assert_eq!(
+            lexical_binding_cookie(";; -*- mode: lisp; lexical-binding: nil; -*-\n(foo)"),
+            Some(false)
+        );
+        assert_eq!(lexical_binding_cookie(";; -*- mode: lisp -*-"), None);
+        assert_eq!(lexical_binding_cookie("(foo)\n;; -*- lexical-binding: t -*-"), None);
+    }
+
+    #[test]
+    fn test_eval_buffer() {
+        use crate::interpreter::assert_lisp;
+        assert_lisp(
+            r#"(progn (setq lexical-binding t)
+                 (insert ";; -*- lexical-binding: nil -*-\n(setq eb-1 lexical-binding) (setq eb-2 2) 3")
+                 (eval-buffer nil (current-buffer))
+                 (list eb-1 eb-2 lexical-binding (buffer-substring 76 (point-max))))"#,
+            r#"(nil 2 t "\nnil\n\n2\n\n3\n")"#,
+        );
+        assert_lisp(
+            r#"(progn (insert "(provide 'eb-feature)") (eval-buffer nil nil "eb-file")
+                 (car load-history))"#,
+            r#"("eb-file" (provide . eb-feature))"#,
+        );
+        // Test environments don't set variable defaults, so bind
+        // `current-load-list` to its default to check provide leaves it alone
+        assert_lisp(
+            "(let ((current-load-list nil)) (provide 'eb-outside) current-load-list)",
+            "nil",
+        );
+        assert_lisp(
+            r#"(progn (insert "(setq eb-4 1) (setq eb-5 2) (setq") (eval-region 1 14)
+                 (list eb-4 (boundp 'eb-5)
+                       (condition-case nil (eval-region 15 (point-max)) (end-of-file 'eof))))"#,
+            "(1 nil eof)",
+        );
+    }
+
+    #[test]
+    fn test_eval_after_load() {
+        use crate::interpreter::assert_lisp;
+        let dir = crate::fileio::TempDir::new("eal");
+        let file = dir.path().join("eal-test.el");
+        std::fs::write(&file, "(provide 'eal-feature)").unwrap();
+        let file = file.to_str().unwrap();
+        // `load-suffixes` is unbound in tests. Without it "eal-test" would
+        // only match a file named exactly that, not "eal-test.el"
+        assert_lisp(
+            &format!(
+                r#"(let ((load-suffixes '(".el")))
+                     (setq eal-log nil)
+                     (eval-after-load 'eal-feature '(setq eal-log (cons 'feature eal-log)))
+                     (eval-after-load "eal-test" #'(lambda () (setq eal-log (cons 'file eal-log))))
+                     (eval-after-load "eal-other" #'(lambda () (setq eal-log (cons 'other eal-log))))
+                     (setq after-load-functions
+                           (list #'(lambda (file) (setq eal-log (cons 'hook eal-log)))))
+                     (load "{file}" nil t)
+                     (list eal-log (eval-after-load 'eal-feature ''now)
+                           (eval-after-load "eal-other" ''later)))"#
+            ),
+            "((hook file feature) now nil)",
+        );
+    }
+
+    #[test]
+    fn test_file_in_path() {
+        let dir = env!("CARGO_MANIFEST_DIR");
+        let suffixes = vec![".toml".to_owned()];
+        let found = file_in_path("Cargo", dir, &suffixes, false, false).unwrap();
+        assert!(found.ends_with("Cargo.toml"));
+        let found = file_in_path("Cargo.toml", dir, &suffixes, false, false).unwrap();
+        assert!(found.ends_with("Cargo.toml"));
+        assert!(file_in_path("Cargo.toml", dir, &suffixes, true, false).is_none());
+        assert!(file_in_path("Cargo", dir, &[], false, false).is_none());
+    }
+
+    #[test]
+    fn test_file_in_path_newer_source() {
+        let dir = crate::fileio::TempDir::new("newer");
+        let compiled = std::fs::File::create(dir.path().join("lib.elc")).unwrap();
+        std::fs::write(dir.path().join("lib.el"), "").unwrap();
+        let old = std::time::SystemTime::now() - std::time::Duration::from_secs(60);
+        compiled.set_modified(old).unwrap();
+        let suffixes = vec![".elc".to_owned(), ".el".to_owned()];
+        let dir_name = dir.path().to_str().unwrap();
+        let found = file_in_path("lib", dir_name, &suffixes, false, false).unwrap();
+        assert!(found.ends_with("lib.elc"));
+        let found = file_in_path("lib", dir_name, &suffixes, false, true).unwrap();
+        assert!(found.ends_with("lib.el"));
+    }
 }
@@ -49,3 +49,10 @@ macro_rules! defvar {
     ($sym:ident, $value:expr) => {};
     ($sym:ident, $name:literal, $value:expr) => {};
 }
+
+// A variable that becomes buffer-local when it is set
+macro_rules! defvar_local {
+    ($sym:ident) => {};
+    ($sym:ident, $value:expr) => {};
+    ($sym:ident, $name:literal, $value:expr) => {};
+}
@@ -5,155 +5,353 @@ mod macros;
 mod core;
 #[macro_use]
 mod debug;
+mod abbrev;
 mod alloc;
 mod arith;
+mod bindat;
 mod buffer;
 mod bytecode;
+mod callint;
+mod callproc;
 mod casefiddle;
 mod character;
 mod chartab;
+mod coding;
 mod data;
+mod decompress;
 mod dired;
+mod doc;
 mod editfns;
 mod emacs;
 mod eval;
+mod extend;
 mod fileio;
+mod filelocal;
 mod filelock;
+mod filenotify;
 mod floatfns;
 mod fns;
+mod font_lock;
+mod frame;
+mod image;
+mod indent;
 mod interpreter;
+mod json;
+mod jsonrpc;
+mod keyboard;
 mod keymap;
 mod library;
 mod lisp;
 mod lread;
+mod minibuf;
+mod mode_line;
+mod optimize;
+mod pdump;
+mod pp;
 mod print;
+mod process;
+mod quail;
+mod radix_tree;
 mod reader;
+mod rect;
+mod repl;
 mod search;
+mod sort;
+mod syntax;
+mod textprop;
 mod threads;
 mod timefns;
+mod trace;
+mod treesit;
+mod undo;
+mod window;
+mod xdisp;
+mod xfaces;
 
 use crate::core::{
     env::{Env, intern, sym},
     gc::{Context, RootSet, Rt},
-    object::{Gc, LispString, NIL},
+    object::{Gc, LispString, NIL, TRUE},
 };
 use crate::eval::EvalError;
-use clap::Parser;
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser};
 use rune_core::macros::root;
-use std::io::{self, Write};
+use std::io::{self, IsTerminal};
+use std::path::PathBuf;
+use std::process::ExitCode;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
+    /// Load FILE. Files and `--eval` expressions are processed in the order
+    /// they are given.
     #[arg(short, long, value_name = "FILE")]
     load: Vec<String>,
+    /// Evaluate EXPR
+    #[arg(long, value_name = "EXPR")]
+    eval: Vec<String>,
+    /// Run noninteractively and exit once the command line is processed
+    #[arg(long)]
+    batch: bool,
+    /// Run FILE as a lisp script. Implies `--batch` and `-Q`.
+    #[arg(long, value_name = "FILE")]
+    script: Option<String>,
+    /// Change to DIR before doing anything else
+    #[arg(long, value_name = "DIR")]
+    chdir: Option<PathBuf>,
+    /// Don't load the user's init file
+    #[arg(short = 'Q', long)]
+    quick: bool,
     #[arg(short, long)]
     repl: bool,
     #[arg(short, long)]
     no_bootstrap: bool,
     #[arg(long)]
     eval_stdin: bool,
+    #[arg(short, long)]
+    edit: bool,
+    /// Restore the state from an image created by `dump-emacs-portable`
+    /// instead of loading the bootstrap files
+    #[arg(long, value_name = "FILE")]
+    dump_file: Option<PathBuf>,
+    /// Record the objects allocated by each builtin function. The counts are
+    /// reported by `rune--allocation-report`.
+    #[arg(long)]
+    debug_alloc: bool,
+    /// Arguments left for scripts in `command-line-args-left`
+    #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+    args: Vec<String>,
 }
 
-fn main() -> Result<(), ()> {
-    let args = Args::parse();
+/// The exit code when batch processing fails, the same as GNU Emacs.
+const BATCH_ERROR: u8 = 255;
+
+/// A file to load or an expression to evaluate from the command line.
+enum Action {
+    Load(String),
+    Eval(String),
+}
+
+/// The `--load` and `--eval` arguments in the order they were given.
+fn actions(matches: &ArgMatches) -> Vec<Action> {
+    let indexed = |id: &str| {
+        let indices = matches.indices_of(id).into_iter().flatten();
+        indices.zip(matches.get_many::<String>(id).into_iter().flatten().cloned())
+    };
+    let mut actions: Vec<_> = indexed("load")
+        .map(|(i, file)| (i, Action::Load(file)))
+        .chain(indexed("eval").map(|(i, expr)| (i, Action::Eval(expr))))
+        .collect();
+    actions.sort_by_key(|(i, _)| *i);
+    actions.into_iter().map(|(_, action)| action).collect()
+}
+
+fn main() -> ExitCode {
+    let matches = Args::command().get_matches();
+    let args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    let batch = args.batch || args.script.is_some();
+    if let Some(dir) = &args.chdir
+        && let Err(e) = std::env::set_current_dir(dir)
+    {
+        eprintln!("Error: Can't change to directory {}: {e}", dir.display());
+        return ExitCode::from(BATCH_ERROR);
+    }
 
+    if args.debug_alloc {
+        crate::core::gc::stats::enable();
+    }
     let roots = &RootSet::default();
     let cx = &mut Context::new(roots);
     root!(env, new(Env), cx);
 
     sym::init_symbols();
     crate::core::env::init_variables(cx, env);
-    crate::data::defalias(intern("not", cx), (sym::NULL).into(), None)
+    crate::extend::init_extensions(cx);
+    crate::data::defalias(intern("not", cx), (sym::NULL).into(), None, cx)
         .expect("null should be defined");
+    init_command_line(&args, batch, env, cx);
+
+    match run(&args, &matches, batch, env, cx) {
+        // kill-emacs has already shut down
+        Err(Stop::Exit(code)) => ExitCode::from(code),
+        result => {
+            let code = if result.is_ok() { 0 } else { BATCH_ERROR };
+            ExitCode::from(emacs::shut_down(env, cx).unwrap_or(code))
+        }
+    }
+}
 
+/// Why rune stopped before doing everything asked of it on the command line.
+enum Stop {
+    /// An error, which has been reported
+    Error,
+    /// `kill-emacs` was called with this exit code
+    Exit(u8),
+}
+
+/// Process the command line, and then run the repl or editor if requested.
+fn run(
+    args: &Args,
+    matches: &ArgMatches,
+    batch: bool,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<(), Stop> {
     if args.eval_stdin {
         return eval_stdin(cx, env);
     }
 
-    if !args.no_bootstrap {
+    if let Some(dump_file) = &args.dump_file {
+        if let Err(e) = pdump::load_dump(dump_file, env, cx) {
+            eprintln!("Error: {e}");
+            return Err(Stop::Error);
+        }
+    } else if !args.no_bootstrap {
         bootstrap(env, cx)?;
     }
 
-    for file in args.load {
-        load(&file, cx, env)?;
+    if !(batch || args.quick) && (args.repl || args.edit) {
+        load_init_file(env, cx)?;
     }
 
-    if args.repl {
-        repl(env, cx);
+    for action in actions(matches) {
+        match action {
+            Action::Load(file) => load(&file, !batch, cx, env)?,
+            Action::Eval(expr) => eval_str(&expr, !batch, cx, env)?,
+        }
+    }
+    if let Some(script) = &args.script {
+        load(script, false, cx, env)?;
+    }
+    if batch {
+        return Ok(());
+    }
+
+    // start a repl by default when run from a terminal
+    let default_repl = !args.edit && args.load.is_empty() && args.eval.is_empty();
+    let repl = args.repl || (default_repl && io::stdin().is_terminal());
+    if repl || args.edit {
+        keyboard::install_interrupt_handler();
+    }
+    if args.edit {
+        frame::install_resize_handler();
+    }
+    if repl && let Some(code) = repl::repl(env, cx) {
+        return Err(Stop::Exit(code));
     }
-    Ok(())
-}
 
-fn parens_closed(buffer: &str) -> bool {
-    let open = buffer.chars().filter(|&x| x == '(').count();
-    let close = buffer.chars().filter(|&x| x == ')').count();
-    open <= close
+    if args.edit
+        && let Some(code) = keyboard::command_loop(env, cx)
+    {
+        return Err(Stop::Exit(code));
+    }
+    Ok(())
 }
 
-fn repl(env: &mut Rt<Env>, cx: &mut Context) {
-    let mut buffer = String::new();
-    let stdin = io::stdin();
-    loop {
-        print!("> ");
-        io::stdout().flush().unwrap();
-        stdin.read_line(&mut buffer).unwrap();
-        if buffer.trim() == "exit" {
-            return;
+/// Set the variables that describe how rune was started.
+fn init_command_line(args: &Args, batch: bool, env: &mut Rt<Env>, cx: &Context) {
+    let to_list = |strings: &[String]| {
+        let strings: Vec<_> = strings.iter().map(|x| cx.add(x.as_str())).collect();
+        crate::fns::slice_into_list(&strings, None, cx)
+    };
+    let argv: Vec<String> = std::env::args().collect();
+    env.vars.insert(sym::COMMAND_LINE_ARGS, to_list(&argv));
+    env.vars.insert(sym::COMMAND_LINE_ARGS_LEFT, to_list(&args.args));
+    let environment = to_list(&callproc::environment_strings());
+    env.vars.insert(sym::PROCESS_ENVIRONMENT, environment);
+    env.vars.insert(sym::INITIAL_ENVIRONMENT, environment);
+    let interactive = !batch && (args.repl || args.edit);
+    env.vars.insert(sym::NONINTERACTIVE, if interactive { NIL } else { TRUE });
+    // elprop compares our output with Emacs, so it must not vary between runs
+    let deterministic = cfg!(feature = "deterministic") || args.eval_stdin;
+    env.vars
+        .insert(sym::RUNE__DETERMINISTIC, if deterministic { TRUE } else { NIL });
+    if let Ok(dir) = std::env::current_dir() {
+        let mut dir = dir.to_string_lossy().into_owned();
+        if !dir.ends_with('/') {
+            dir.push('/');
         }
-        if buffer.trim().is_empty() {
-            continue;
-        }
-        if !parens_closed(&buffer) {
-            continue;
-        }
-        let (obj, _) = match reader::read(&buffer, cx) {
-            Ok(obj) => obj,
-            Err(e) => {
-                eprintln!("Error: {e}");
-                buffer.clear();
-                continue;
-            }
-        };
+        env.vars.insert(sym::DEFAULT_DIRECTORY, cx.add(dir));
+    }
+}
 
-        root!(obj, cx);
-        match interpreter::eval(obj, None, env, cx) {
-            Ok(val) => println!("{val}"),
-            Err(e) => {
-                eprintln!("Error: {e}");
-                if let Ok(e) = e.downcast::<EvalError>() {
-                    e.print_backtrace();
-                }
-            }
-        }
-        buffer.clear();
+/// Load the user's init file if there is one. Errors are reported but don't
+/// stop startup.
+fn load_init_file(env: &mut Rt<Env>, cx: &mut Context) -> Result<(), Stop> {
+    let Some(home) = std::env::var_os("HOME") else { return Ok(()) };
+    let init = PathBuf::from(home).join(".config/rune/init.el");
+    if !init.exists() {
+        return Ok(());
+    }
+    match load(&init.to_string_lossy(), false, cx, env) {
+        Err(Stop::Error) => Ok(()),
+        result => result,
     }
 }
 
-fn load(file: &str, cx: &mut Context, env: &mut Rt<Env>) -> Result<(), ()> {
+fn load(file: &str, print: bool, cx: &mut Context, env: &mut Rt<Env>) -> Result<(), Stop> {
     let file: Gc<&LispString> = cx.add_as(file);
     root!(file, cx);
-    match crate::lread::load(file, None, None, cx, env) {
+    match crate::lread::load(file, None, None, None, None, cx, env) {
         Ok(val) => {
-            println!("{val}");
+            if print {
+                println!("{val}");
+            }
             Ok(())
         }
+        Err(e) => Err(stop(e)),
+    }
+}
+
+/// Evaluate the forms in `text`, printing the value of the last one if
+/// `print` is true.
+fn eval_str(text: &str, print: bool, cx: &mut Context, env: &mut Rt<Env>) -> Result<(), Stop> {
+    let obj = match reader::read(text, cx) {
+        Ok((obj, _)) => obj,
         Err(e) => {
             eprintln!("Error: {e}");
-            if let Ok(e) = e.downcast::<EvalError>() {
-                e.print_backtrace();
+            return Err(Stop::Error);
+        }
+    };
+    root!(obj, cx);
+    match interpreter::eval(obj, None, env, cx) {
+        Ok(val) => {
+            if print {
+                println!("{val}");
             }
-            Err(())
+            Ok(())
         }
+        Err(e) => Err(stop(e)),
+    }
+}
+
+/// Report `e` unless it is an exit from `kill-emacs`.
+fn stop(e: anyhow::Error) -> Stop {
+    match eval::exit_code(&e) {
+        Some(code) => Stop::Exit(code),
+        None => {
+            report_error(e);
+            Stop::Error
+        }
+    }
+}
+
+pub(crate) fn report_error(e: anyhow::Error) {
+    eprintln!("Error: {e}");
+    if let Ok(e) = e.downcast::<EvalError>() {
+        e.print_backtrace();
     }
 }
 
-fn eval_stdin(cx: &mut Context, env: &mut Rt<Env>) -> Result<(), ()> {
+fn eval_stdin(cx: &mut Context, env: &mut Rt<Env>) -> Result<(), Stop> {
     let mut buffer = String::new();
     let mut point = 0;
     let mut count = 0;
     loop {
-        io::stdin().read_line(&mut buffer).unwrap();
+        // Exit when the process driving us closes stdin
+        if io::stdin().read_line(&mut buffer).unwrap() == 0 {
+            return Ok(());
+        }
         let obj = match reader::read(&buffer[point..], cx) {
             Ok((obj, offset)) => {
                 point += offset;
@@ -169,25 +367,40 @@ fn eval_stdin(cx: &mut Context, env: &mut Rt<Env>) -> Result<(), ()> {
         root!(obj, cx);
         match interpreter::eval(obj, None, env, cx) {
             Ok(val) => println!(";; ELPROP_START:{count}\n{val}\n;; ELPROP_END\n"),
-            Err(e) => println!(";; ELPROP_START:{count}\nError: {e}\n;; ELPROP_END\n"),
+            Err(e) => {
+                if let Some(code) = eval::exit_code(&e) {
+                    return Err(Stop::Exit(code));
+                }
+                println!(";; ELPROP_START:{count}\nError: {e}\n;; ELPROP_END\n");
+            }
         }
         count += 1;
-        std::thread::sleep(std::time::Duration::from_millis(10));
-        // timeout after ~1 minute
-        if count > 6000 {
-            break;
-        }
     }
-    Err(())
+    Err(Stop::Error)
 }
 
-fn bootstrap(env: &mut Rt<Env>, cx: &mut Context) -> Result<(), ()> {
+fn bootstrap(env: &mut Rt<Env>, cx: &mut Context) -> Result<(), Stop> {
     buffer::get_buffer_create(cx.add("*scratch*"), Some(NIL), cx).unwrap();
-    load("bootstrap.el", cx, env)
+    load("bootstrap.el", true, cx, env)
 }
 
 #[test]
 fn verify_cli() {
-    use clap::CommandFactory;
     Args::command().debug_assert()
 }
+
+#[test]
+fn test_action_order() {
+    let argv = ["rune", "--eval", "(a)", "-l", "b.el", "--eval", "(c)", "--batch", "x", "-y"];
+    let matches = Args::command().get_matches_from(argv);
+    let actions: Vec<_> = actions(&matches)
+        .into_iter()
+        .map(|x| match x {
+            Action::Load(x) | Action::Eval(x) => x,
+        })
+        .collect();
+    assert_eq!(actions, ["(a)", "b.el", "(c)"]);
+    let args = Args::from_arg_matches(&matches).unwrap();
+    assert!(args.batch);
+    assert_eq!(args.args, ["x", "-y"]);
+}
@@ -0,0 +1,440 @@
+//! Minibuffer input.
+//!
+//! There is no minibuffer yet, so input is read a line at a time from stdin,
+//! the same way Emacs reads it in batch mode.
+use crate::core::{
+    env::{Env, sym},
+    error::{Type, TypeError},
+    gc::{Context, Rt, Rto},
+    object::{Function, Gc, LispString, NIL, Object, ObjectType, OptionalFlag, TRUE},
+};
+use crate::data::functionp;
+use crate::eval::EvalError;
+use crate::fns::slice_into_list;
+use crate::reader;
+use anyhow::{Result, bail};
+use rune_core::macros::{call, list, root};
+use rune_macros::defun;
+use std::io::{self, BufRead, Write};
+
+defsym!(END_OF_FILE);
+defvar!(COMPLETION_IGNORE_CASE);
+
+/// Print `prompt` and read a line from `input` without its line ending.
+/// Signals `end-of-file` if there is no more input.
+pub(crate) fn read_line(
+    prompt: &str,
+    input: &mut impl BufRead,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<String> {
+    print!("{prompt}");
+    io::stdout().flush()?;
+    let mut line = String::new();
+    if input.read_line(&mut line)? == 0 {
+        let data = list![cx.add("Error reading from stdin"); cx];
+        return Err(EvalError::signal(sym::END_OF_FILE.into(), data, env).into());
+    }
+    if line.ends_with('\n') {
+        line.pop();
+        if line.ends_with('\r') {
+            line.pop();
+        }
+    }
+    Ok(line)
+}
+
+/// The default value to use for empty input. If DEFAULT is a list, its first
+/// element is used.
+fn first_default(default: Option<Object>) -> Option<Object> {
+    match default?.untag() {
+        ObjectType::Cons(cons) => Some(cons.car()),
+        ObjectType::NIL => None,
+        _ => default,
+    }
+}
+
+/// Read a string from the minibuffer, prompting with PROMPT.
+///
+/// If READ is non-nil, the input is read as a lisp object and that object is
+/// returned instead. Empty input then reads DEFAULT-VALUE. INITIAL-CONTENTS,
+/// KEYMAP, HIST and INHERIT-INPUT-METHOD have no effect when reading from
+/// stdin.
+#[defun]
+#[expect(clippy::too_many_arguments)]
+fn read_from_minibuffer<'ob>(
+    prompt: &str,
+    _initial_contents: Option<Object>,
+    _keymap: Option<Object>,
+    read: OptionalFlag,
+    _hist: Option<Object>,
+    default_value: Option<Object<'ob>>,
+    _inherit_input_method: Option<Object>,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    let line = read_line(prompt, &mut io::stdin().lock(), env, cx)?;
+    if read.is_none() {
+        return Ok(cx.add(line));
+    }
+    let line = match first_default(default_value).map(|x| x.untag()) {
+        Some(ObjectType::String(default)) if line.trim().is_empty() => default.to_string(),
+        _ => line,
+    };
+    match reader::read(&line, cx) {
+        Ok((obj, _)) => Ok(obj),
+        Err(e) => bail!(e),
+    }
+}
+
+/// Read a string from the minibuffer, prompting with PROMPT. If the input is
+/// empty and DEFAULT-VALUE is non-nil, return DEFAULT-VALUE instead, or its
+/// first element if it is a list.
+#[defun]
+fn read_string<'ob>(
+    prompt: &str,
+    _initial_input: Option<Object>,
+    _history: Option<Object>,
+    default_value: Option<Object<'ob>>,
+    _inherit_input_method: Option<Object>,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    let line = read_line(prompt, &mut io::stdin().lock(), env, cx)?;
+    match first_default(default_value) {
+        Some(default) if line.is_empty() => Ok(default),
+        _ => Ok(cx.add(line)),
+    }
+}
+
+/// Prompt until one of the `yes` or `no` answers is given.
+fn ask(
+    prompt: &str,
+    (yes, no): (&[&str], &[&str]),
+    retry: &str,
+    input: &mut impl BufRead,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<bool> {
+    let mut prompt = prompt.to_owned();
+    loop {
+        let answer = read_line(&prompt, input, env, cx)?;
+        let answer = answer.trim();
+        if yes.contains(&answer) {
+            return Ok(true);
+        }
+        if no.contains(&answer) {
+            return Ok(false);
+        }
+        if !prompt.starts_with(retry) {
+            prompt.insert_str(0, retry);
+        }
+    }
+}
+
+/// Ask the user a yes or no question, returning t for "yes" and nil for "no".
+#[defun]
+pub(crate) fn yes_or_no_p<'ob>(
+    prompt: &str,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    let prompt = format!("{prompt}(yes or no) ");
+    let answers: (&[&str], &[&str]) = (&["yes"], &["no"]);
+    let yes =
+        ask(&prompt, answers, "Please answer yes or no.  ", &mut io::stdin().lock(), env, cx)?;
+    Ok(if yes { TRUE } else { NIL })
+}
+
+/// Ask the user a "y or n" question, returning t for "y" and nil for "n".
+#[defun]
+fn y_or_n_p<'ob>(prompt: &str, env: &mut Rt<Env>, cx: &'ob Context) -> Result<Object<'ob>> {
+    let prompt = format!("{prompt}(y or n) ");
+    let answers: (&[&str], &[&str]) = (&["y", "Y"], &["n", "N"]);
+    let yes = ask(&prompt, answers, "Please answer y or n.  ", &mut io::stdin().lock(), env, cx)?;
+    Ok(if yes { TRUE } else { NIL })
+}
+
+/// Whether `name` starts with `prefix`, ignoring case if `ignore_case` is
+/// set.
+fn has_prefix(name: &str, prefix: &str, ignore_case: bool) -> bool {
+    if !ignore_case {
+        return name.starts_with(prefix);
+    }
+    let mut chars = name.chars();
+    prefix.chars().all(|p| chars.next().is_some_and(|c| chars_match(c, p, true)))
+}
+
+fn chars_match(a: char, b: char, ignore_case: bool) -> bool {
+    a == b || (ignore_case && a.to_lowercase().eq(b.to_lowercase()))
+}
+
+/// The name of a completion candidate, which is a string or a symbol.
+fn candidate_name(candidate: Object) -> Option<String> {
+    match candidate.untag() {
+        ObjectType::String(s) => Some(s.inner().to_owned()),
+        ObjectType::Symbol(s) => Some(s.name().to_owned()),
+        _ => None,
+    }
+}
+
+/// The names of the candidates in COLLECTION that start with `prefix` and
+/// satisfy PREDICATE. COLLECTION is a list of strings or symbols, an alist
+/// whose keys are strings or symbols, a hash table, or an obarray, which is a
+/// vector of symbols. PREDICATE is called with the element of the list, the
+/// symbol from the obarray, or the key and value from the hash table.
+fn completions(
+    prefix: &str,
+    collection: &Rto<Object>,
+    predicate: Option<&Rto<Object>>,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<Vec<String>> {
+    let ignore_case =
+        env.vars.get(sym::COMPLETION_IGNORE_CASE).is_some_and(|x| !x.bind(cx).is_nil());
+    let mut names = Vec::new();
+    // The arguments to call PREDICATE with for each of the names
+    root!(args, new(Vec), cx);
+    let collection = collection.bind(cx);
+    let is_hash_table = matches!(collection.untag(), ObjectType::HashTable(_));
+    let mut add = |name: Option<String>, candidate: &[Object]| {
+        if let Some(name) = name.filter(|x| has_prefix(x, prefix, ignore_case)) {
+            names.push(name);
+            candidate.iter().for_each(|x| args.push(*x));
+        }
+    };
+    match collection.untag() {
+        ObjectType::NIL => {}
+        ObjectType::Cons(cons) => {
+            for elem in cons.elements() {
+                let elem = elem?;
+                let key = match elem.untag() {
+                    ObjectType::Cons(cons) => cons.car(),
+                    _ => elem,
+                };
+                add(candidate_name(key), &[elem]);
+            }
+        }
+        ObjectType::HashTable(table) => {
+            for idx in 0..table.len() {
+                let Some((key, value)) = table.get_index(idx) else { continue };
+                add(candidate_name(key), &[key, value]);
+            }
+        }
+        ObjectType::Vec(vec) => {
+            for symbol in vec.iter().map(|x| x.get()) {
+                if let ObjectType::Symbol(_) = symbol.untag() {
+                    add(candidate_name(symbol), &[symbol]);
+                }
+            }
+        }
+        x => bail!(TypeError::new(Type::List, x)),
+    }
+    let Some(predicate) = predicate else { return Ok(names) };
+    let predicate: Function = predicate.bind(cx).try_into()?;
+    root!(predicate, cx);
+    let mut matches = Vec::new();
+    for (idx, name) in names.into_iter().enumerate() {
+        let result = if is_hash_table {
+            let (key, value) = (args[idx * 2].bind(cx), args[idx * 2 + 1].bind(cx));
+            call!(predicate, key, value; env, cx)?
+        } else {
+            let candidate = args[idx].bind(cx);
+            call!(predicate, candidate; env, cx)?
+        };
+        if !result.is_nil() {
+            matches.push(name);
+        }
+    }
+    Ok(matches)
+}
+
+/// Call a COLLECTION that is a function to do the completion itself.
+fn call_completion_function<'ob>(
+    string: &Rto<Gc<&LispString>>,
+    collection: &Rto<Object>,
+    predicate: Option<&Rto<Object>>,
+    action: Object,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<Object<'ob>> {
+    let function: Function = collection.bind(cx).try_into()?;
+    root!(function, cx);
+    let string: Object = string.bind(cx).into();
+    let predicate = predicate.map_or(NIL, |x| x.bind(cx));
+    Ok(call!(function, string, predicate, action; env, cx)?)
+}
+
+/// Return the longest common prefix of all the completions of STRING in
+/// COLLECTION, nil if there are none, or t if STRING is the only one.
+///
+/// COLLECTION can be a list of strings or symbols, an alist whose keys are
+/// strings or symbols, a hash table, an obarray, or a function, which is
+/// called with STRING, PREDICATE and nil. If PREDICATE is non-nil only the
+/// completions that satisfy it are used. Case is ignored if
+/// `completion-ignore-case` is non-nil.
+#[defun]
+fn try_completion<'ob>(
+    string: &Rto<Gc<&LispString>>,
+    collection: &Rto<Object>,
+    predicate: Option<&Rto<Object>>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<Object<'ob>> {
+    if functionp(collection.bind(cx)) {
+        return call_completion_function(string, collection, predicate, NIL, env, cx);
+    }
+    let prefix: &str = string.untag(cx);
+    let prefix = prefix.to_owned();
+    let mut matches = completions(&prefix, collection, predicate, env, cx)?;
+    matches.dedup();
+    let Some(first) = matches.first() else { return Ok(NIL) };
+    let ignore_case =
+        env.vars.get(sym::COMPLETION_IGNORE_CASE).is_some_and(|x| !x.bind(cx).is_nil());
+    // the number of characters that all the matches have in common
+    let common = matches[1..].iter().fold(first.chars().count(), |len, name| {
+        let same = first.chars().zip(name.chars());
+        same.take(len).take_while(|(a, b)| chars_match(*a, *b, ignore_case)).count()
+    });
+    let prefix_len = prefix.chars().count();
+    // don't change the case of what was typed if nothing was added to it
+    if ignore_case && common == prefix_len && first.chars().count() > common {
+        return Ok(string.bind(cx).into());
+    }
+    if matches.len() == 1 && *first == prefix {
+        return Ok(TRUE);
+    }
+    let common: String = first.chars().take(common).collect();
+    Ok(cx.add(common))
+}
+
+/// Return a list of all the completions of STRING in COLLECTION. See
+/// `try-completion` for the meaning of COLLECTION and PREDICATE. A function
+/// COLLECTION is called with STRING, PREDICATE and t. If HIDE-SPACES is
+/// non-nil, completions that start with a space are left out unless STRING
+/// does too.
+#[defun]
+fn all_completions<'ob>(
+    string: &Rto<Gc<&LispString>>,
+    collection: &Rto<Object>,
+    predicate: Option<&Rto<Object>>,
+    hide_spaces: OptionalFlag,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<Object<'ob>> {
+    if functionp(collection.bind(cx)) {
+        return call_completion_function(string, collection, predicate, TRUE, env, cx);
+    }
+    let prefix: &str = string.untag(cx);
+    let prefix = prefix.to_owned();
+    let mut matches = completions(&prefix, collection, predicate, env, cx)?;
+    if hide_spaces.is_some() && !prefix.starts_with(' ') {
+        matches.retain(|x| !x.starts_with(' '));
+    }
+    let matches: Vec<Object> = matches.into_iter().map(|x| cx.add(x)).collect();
+    Ok(slice_into_list(&matches, None, cx))
+}
+
+/// Return t if STRING is one of the completions in COLLECTION. See
+/// `try-completion` for the meaning of COLLECTION and PREDICATE. A function
+/// COLLECTION is called with STRING, PREDICATE and `lambda`.
+#[defun]
+fn test_completion<'ob>(
+    string: &Rto<Gc<&LispString>>,
+    collection: &Rto<Object>,
+    predicate: Option<&Rto<Object>>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<Object<'ob>> {
+    if functionp(collection.bind(cx)) {
+        let lambda = sym::LAMBDA.into();
+        return call_completion_function(string, collection, predicate, lambda, env, cx);
+    }
+    let prefix: &str = string.untag(cx);
+    let prefix = prefix.to_owned();
+    let matches = completions(&prefix, collection, predicate, env, cx)?;
+    // a completion that starts with STRING and is as long as it is STRING
+    let len = prefix.chars().count();
+    Ok(if matches.iter().any(|x| x.chars().count() == len) { TRUE } else { NIL })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::gc::RootSet;
+    use crate::interpreter::assert_lisp;
+
+    #[test]
+    fn test_read_line() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, new(Env), cx);
+        let input = &mut io::Cursor::new("foo\r\nbar");
+        assert_eq!(read_line("", input, env, cx).unwrap(), "foo");
+        assert_eq!(read_line("", input, env, cx).unwrap(), "bar");
+        assert!(read_line("", input, env, cx).is_err());
+    }
+
+    #[test]
+    fn test_ask() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, new(Env), cx);
+        let answers: (&[&str], &[&str]) = (&["yes"], &["no"]);
+        let input = &mut io::Cursor::new("maybe\nno\nyes\n");
+        assert!(!ask("", answers, "", input, env, cx).unwrap());
+        assert!(ask("", answers, "", input, env, cx).unwrap());
+        assert!(ask("", answers, "", input, env, cx).is_err());
+    }
+
+    #[test]
+    fn test_try_completion() {
+        assert_lisp(r#"(try-completion "fo" '("foo" "foobar" "baz"))"#, r#""foo""#);
+        assert_lisp(r#"(try-completion "foo" '("foo"))"#, "t");
+        assert_lisp(r#"(try-completion "x" '("foo"))"#, "nil");
+        assert_lisp(r#"(try-completion "b" '(("bar" . 1) (baz . 2)))"#, r#""ba""#);
+        assert_lisp(
+            r#"(try-completion "b" '("bar" "baz" "bax") #'(lambda (x) (null (equal x "bar"))))"#,
+            r#""ba""#,
+        );
+        assert_lisp(
+            r#"(try-completion "b" '("bar" "baz") #'(lambda (x) (equal x "baz")))"#,
+            r#""baz""#,
+        );
+        assert_lisp(
+            r#"(let ((completion-ignore-case t)) (list (try-completion "FO" '("foo" "fob"))
+                                                      (try-completion "F" '("foo" "fob"))))"#,
+            r#"("FO" "fo")"#,
+        );
+        assert_lisp(
+            r#"(try-completion "f" #'(lambda (string pred action) (list string action)))"#,
+            r#"("f" nil)"#,
+        );
+    }
+
+    #[test]
+    fn test_all_completions() {
+        assert_lisp(r#"(all-completions "fo" '("foo" "bar" fox))"#, r#"("foo" "fox")"#);
+        assert_lisp(
+            r#"(let ((table (make-hash-table :test 'equal)))
+                 (puthash "one" 1 table) (puthash "other" 2 table) (puthash "two" 3 table)
+                 (list (all-completions "o" table)
+                       (all-completions "o" table #'(lambda (k v) (= v 2)))))"#,
+            r#"(("one" "other") ("other"))"#,
+        );
+        assert_lisp(r#"(all-completions "a" [abc 0 abd bcd])"#, r#"("abc" "abd")"#);
+        assert_lisp(r#"(all-completions "" '(" hidden" "shown") nil t)"#, r#"("shown")"#);
+        assert_lisp(
+            r#"(let ((completion-ignore-case t)) (all-completions "AB" '("abc" "Abd" "bc")))"#,
+            r#"("abc" "Abd")"#,
+        );
+    }
+
+    #[test]
+    fn test_test_completion() {
+        assert_lisp(r#"(test-completion "foo" '("foo" "foobar"))"#, "t");
+        assert_lisp(r#"(test-completion "fo" '("foo" "foobar"))"#, "nil");
+        assert_lisp(r#"(test-completion "foo" '("foo") #'(lambda (x) nil))"#, "nil");
+        assert_lisp(r#"(let ((completion-ignore-case t)) (test-completion "FOO" '("foo")))"#, "t");
+    }
+}
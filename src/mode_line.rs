@@ -0,0 +1,270 @@
+//! The mode line.
+//!
+//! `mode-line-format` is interpreted natively. A construct is one of:
+//!
+//! - a string, shown with its `%`-constructs replaced
+//! - a symbol, whose value is shown. Strings in symbols are shown as they
+//!   are, and other values are interpreted as constructs
+//! - `(:eval FORM)`, which shows the value of FORM as a construct
+//! - `(:propertize ELT PROPS...)`, which shows ELT. Strings can't carry text
+//!   properties yet, so PROPS is ignored
+//! - `(SYMBOL THEN ELSE)`, which shows THEN if SYMBOL is non-nil and
+//!   otherwise ELSE
+//! - `(WIDTH REST...)`, which shows REST padded to WIDTH columns, or
+//!   truncated to -WIDTH columns if WIDTH is negative
+//! - any other list, whose elements are shown one after another
+//!
+//! Errors in `:eval` forms show nothing, so a broken form can't break
+//! redisplay.
+use crate::buffer::resolve_buffer;
+use crate::core::{
+    env::{Env, sym},
+    gc::{Context, Rt, Rto, Slot},
+    object::{LispBuffer, NIL, Narrowing, Object, ObjectType},
+};
+use crate::indent::current_column;
+use crate::interpreter;
+use crate::window::{init_windows, live_window_arg};
+use anyhow::Result;
+use rune_core::macros::root;
+use rune_macros::defun;
+use std::fmt::Write as _;
+
+defvar_local!(MODE_LINE_FORMAT, list!["%*%+ ", "%b", "   L%l C%c   (", sym::MODE_NAME, ")"]);
+defsym!(KW_EVAL);
+defsym!(KW_PROPERTIZE);
+
+/// How deeply constructs can nest, which stops symbols that refer to
+/// themselves.
+const MAX_DEPTH: usize = 100;
+
+/// Append `format` to `out` with its `%`-constructs replaced. A number after
+/// the `%` is the least width of the field. Numbers are right justified in
+/// it and text is left justified.
+fn expand_percent(format: &str, out: &mut String, env: &Rt<Env>, cx: &Context) -> Result<()> {
+    let mut chars = format.chars().peekable();
+    while let Some(chr) = chars.next() {
+        if chr != '%' {
+            out.push(chr);
+            continue;
+        }
+        let mut width = 0;
+        while let Some(digit) = chars.peek().and_then(|x| x.to_digit(10)) {
+            width = width * 10 + digit as usize;
+            chars.next();
+        }
+        let Some(spec) = chars.next() else { break };
+        let buffer = env.current_buffer.get();
+        let var = |sym| env.vars.get(sym).map_or(NIL, |x| x.bind(cx));
+        let number = match spec {
+            'l' => {
+                let text = &buffer.text;
+                let first = text.char_to_line(buffer.point_min() - 1);
+                Some(text.char_to_line(buffer.point() - 1) - first + 1)
+            }
+            'c' => Some(current_column(env, cx)?),
+            'C' => Some(current_column(env, cx)? + 1),
+            _ => None,
+        };
+        if let Some(number) = number {
+            write!(out, "{number:>width$}")?;
+            continue;
+        }
+        let text = match spec {
+            'b' => buffer.name.as_str(),
+            'f' | 'm' => {
+                let value = var(if spec == 'f' { sym::BUFFER_FILE_NAME } else { sym::MODE_NAME });
+                match value.untag() {
+                    ObjectType::String(s) => s.as_ref(),
+                    _ => "",
+                }
+            }
+            // Buffers can't be read-only yet
+            '*' | '+' if buffer.modified_p() => "*",
+            '*' | '+' => "-",
+            'n' if buffer.narrowing() != Narrowing::default() => " Narrow",
+            '%' => "%",
+            _ => "",
+        };
+        write!(out, "{text:<width$}")?;
+    }
+    Ok(())
+}
+
+/// Append the mode line construct `elt` to `out`, with `depth` the number of
+/// constructs it is nested in.
+fn format_element(
+    elt: &Rto<Object>,
+    depth: usize,
+    out: &mut String,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<()> {
+    if depth > MAX_DEPTH {
+        return Ok(());
+    }
+    let value = elt.bind(cx);
+    match value.untag() {
+        ObjectType::String(format) => {
+            let format = format.to_string();
+            expand_percent(&format, out, env, cx)
+        }
+        ObjectType::Symbol(symbol) if symbol != sym::NIL && symbol != sym::TRUE => {
+            let Some(value) = env.vars.get(symbol).map(|x| x.bind(cx)) else { return Ok(()) };
+            if let ObjectType::String(s) = value.untag() {
+                out.push_str(s);
+                return Ok(());
+            }
+            root!(value, cx);
+            format_element(value, depth + 1, out, env, cx)
+        }
+        ObjectType::Cons(_) => {
+            root!(items, new(Vec<Slot<Object>>), cx);
+            for item in value.as_list()? {
+                items.push(item?);
+            }
+            format_list(items, depth, out, env, cx)
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Append the mode line construct that is the list `items` to `out`.
+fn format_list(
+    items: &Rt<Vec<Slot<Object>>>,
+    depth: usize,
+    out: &mut String,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<()> {
+    let nested = depth + 1;
+    match items[0].bind(cx).untag() {
+        ObjectType::Symbol(sym::KW_EVAL) => {
+            let Some(form) = items.get(1) else { return Ok(()) };
+            let Ok(value) = interpreter::eval(form, None, env, cx) else { return Ok(()) };
+            root!(value, cx);
+            format_element(value, nested, out, env, cx)
+        }
+        ObjectType::Symbol(sym::KW_PROPERTIZE) => match items.get(1) {
+            Some(elt) => format_element(elt, nested, out, env, cx),
+            None => Ok(()),
+        },
+        ObjectType::Symbol(symbol) if !symbol.name().starts_with(':') => {
+            let set = env.vars.get(symbol).is_some_and(|x| !x.bind(cx).is_nil());
+            match items.get(if set { 1 } else { 2 }) {
+                Some(elt) => format_element(elt, nested, out, env, cx),
+                None => Ok(()),
+            }
+        }
+        ObjectType::Symbol(_) => Ok(()),
+        ObjectType::Int(width) => {
+            let mut field = String::new();
+            for elt in &items[1..] {
+                format_element(elt, nested, &mut field, env, cx)?;
+            }
+            let limit = width.unsigned_abs() as usize;
+            if width < 0 {
+                out.extend(field.chars().take(limit));
+            } else {
+                write!(out, "{field:<limit$}")?;
+            }
+            Ok(())
+        }
+        _ => {
+            for elt in items.iter() {
+                format_element(elt, nested, out, env, cx)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Format `format`, or the `mode-line-format` of `buffer` if it is `None`,
+/// with `buffer` current.
+fn format_in_buffer(
+    format: Option<&Rto<Object>>,
+    buffer: &Rto<&LispBuffer>,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<String> {
+    let old = env.current_buffer.get().lisp_buffer(cx);
+    root!(old, cx);
+    env.set_buffer(buffer.bind(cx), cx);
+    let mut out = String::new();
+    let result = match format {
+        Some(format) => format_element(format, 0, &mut out, env, cx),
+        None => {
+            let format = env.vars.get(sym::MODE_LINE_FORMAT).map_or(NIL, |x| x.bind(cx));
+            root!(format, cx);
+            format_element(format, 0, &mut out, env, cx)
+        }
+    };
+    // An :eval form could have killed the buffer
+    let old = old.bind(cx);
+    if env.with_buffer(old, |_| {}).is_ok() {
+        env.set_buffer(old, cx);
+    }
+    result.map(|()| out)
+}
+
+/// The mode line of the selected window.
+pub(crate) fn selected_mode_line(env: &mut Rt<Env>, cx: &mut Context) -> Result<String> {
+    init_windows(env, cx);
+    let buffer = env.window_tree.buffer(env.window_tree.selected()).unwrap();
+    root!(buffer, cx);
+    format_in_buffer(None, buffer, env, cx)
+}
+
+/// Format FORMAT as a mode line construct and return the string. The
+/// `%`-constructs and variables refer to BUFFER, or to the buffer of WINDOW
+/// if BUFFER is nil. WINDOW defaults to the selected window. FACE is ignored,
+/// since strings can't carry text properties yet.
+#[defun]
+fn format_mode_line(
+    format: &Rto<Object>,
+    _face: Option<&Rto<Object>>,
+    window: Option<&Rto<Object>>,
+    buffer: Option<&Rto<Object>>,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<String> {
+    let buffer = match buffer.map(|x| x.bind(cx)).filter(|x| !x.is_nil()) {
+        Some(buffer) => resolve_buffer(buffer, cx)?,
+        None => {
+            let id = live_window_arg(window.map(|x| x.bind(cx)), env, cx)?;
+            env.window_tree.buffer(id).unwrap()
+        }
+    };
+    root!(buffer, cx);
+    format_in_buffer(Some(format), buffer, env, cx)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::interpreter::assert_lisp;
+
+    #[test]
+    fn test_percent_constructs() {
+        assert_lisp(
+            r#"(progn (setq mode-name "Fundamental") (insert "ab\ncd") (goto-char 5)
+                      (list (equal (format-mode-line "%b") (buffer-name))
+                            (format-mode-line "L%l C%c %3C|%12m|%*%%" nil nil (current-buffer))))"#,
+            r#"(t "L2 C1   2|Fundamental |*%")"#,
+        );
+    }
+
+    #[test]
+    fn test_constructs() {
+        assert_lisp(
+            r#"(progn
+                 (setq test-mode-line-flag t test-mode-line-name "%b")
+                 (list (format-mode-line
+                        '("a" (:eval (concat "b" "c")) (:eval (car 1))
+                          (test-mode-line-flag "d" "e") (test-mode-line-unbound "f" "g")))
+                       (format-mode-line
+                        '((5 "ab") "|" (-2 "xyz") "|" test-mode-line-name "|"
+                          (:propertize "p" face bold) 7 :eval))))"#,
+            r#"("abcdg" "ab   |xy|%b|p")"#,
+        );
+    }
+}
@@ -0,0 +1,403 @@
+//! Constant folding of macroexpanded forms.
+//!
+//! Before `load` evaluates a form, calls to arithmetic functions, `concat`,
+//! and functions with a non-nil `pure` property are replaced by their value
+//! when every argument is a constant. `if` and `cond` with constant
+//! conditions are replaced by the branch that would be taken. Setting
+//! `load-fold-constants` to nil disables this, which is useful when
+//! debugging.
+use crate::{
+    core::{
+        cons::Cons,
+        env::{CallFrame, Env, sym},
+        gc::{Context, Rt, Rto},
+        object::{NIL, Object, ObjectType, Symbol},
+    },
+    fns::slice_into_list,
+    rooted_iter,
+};
+use anyhow::Result;
+use fallible_streaming_iterator::FallibleStreamingIterator;
+use rune_core::macros::{list, rebind, root};
+
+/// Functions that are folded when called with constant arguments, in
+/// addition to those with a `pure` property.
+const FOLDABLE: &[Symbol] = &[
+    sym::ADD,
+    sym::SUB,
+    sym::MUL,
+    sym::DIV,
+    sym::ADD_ONE,
+    sym::SUB_ONE,
+    sym::NUM_EQ,
+    sym::NUM_NE,
+    sym::LESS_THAN,
+    sym::LESS_THAN_OR_EQ,
+    sym::GREATER_THAN,
+    sym::GREATER_THAN_OR_EQ,
+    sym::MODULO,
+    sym::REMAINDER,
+    sym::MAX,
+    sym::MIN,
+    sym::ABS,
+    sym::LOGAND,
+    sym::LOGIOR,
+    sym::ASH,
+    sym::CONCAT,
+];
+
+/// Return the value of `form` if it is a constant.
+fn constant_value(form: Object) -> Option<Object> {
+    match form.untag() {
+        ObjectType::Int(_)
+        | ObjectType::Float(_)
+        | ObjectType::String(_)
+        | ObjectType::ByteString(_) => Some(form),
+        ObjectType::Symbol(sym) if sym.is_const() => Some(form),
+        ObjectType::Cons(cons) if cons.car() == sym::QUOTE => match cons.cdr().untag() {
+            ObjectType::Cons(arg) if arg.cdr().is_nil() => Some(arg.car()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Return a form that evaluates to `value`.
+fn quote<'ob>(value: Object<'ob>, cx: &'ob Context) -> Object<'ob> {
+    match value.untag() {
+        ObjectType::Int(_) | ObjectType::Float(_) | ObjectType::String(_) => value,
+        ObjectType::Symbol(sym) if sym.is_const() => value,
+        _ => list![sym::QUOTE, value; cx],
+    }
+}
+
+/// Fold the constant expressions in `form`, which has to be fully
+/// macroexpanded.
+pub(crate) fn optimize<'ob>(
+    form: &Rto<Object>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<Object<'ob>> {
+    let ObjectType::Cons(cons) = form.untag(cx) else { return Ok(form.bind(cx)) };
+    let head = cons.car();
+    let forms = cons.cdr();
+    root!(head, cx);
+    root!(forms, cx);
+    let ObjectType::Symbol(name) = head.untag(cx) else {
+        // ((lambda ...) ARGS...)
+        return rebuild(head, forms, |_| true, env, cx);
+    };
+    match name {
+        sym::QUOTE | sym::INTERACTIVE => Ok(form.bind(cx)),
+        sym::FUNCTION => optimize_function(form, forms, env, cx),
+        sym::IF => optimize_if(form, forms, env, cx),
+        sym::COND => optimize_cond(forms, env, cx),
+        sym::LET | sym::LET_STAR => optimize_let(form, head, forms, env, cx),
+        sym::CONDITION_CASE => optimize_condition_case(form, forms, env, cx),
+        sym::SETQ => rebuild(head, forms, |i| i % 2 == 1, env, cx),
+        sym::DEFVAR | sym::DEFCONST => rebuild(head, forms, |i| i == 1, env, cx),
+        sym::PROGN
+        | sym::INLINE
+        | sym::PROG1
+        | sym::PROG2
+        | sym::AND
+        | sym::OR
+        | sym::WHILE
+        | sym::CATCH
+        | sym::THROW
+        | sym::UNWIND_PROTECT
+        | sym::SAVE_CURRENT_BUFFER
+        | sym::SAVE_EXCURSION
+        | sym::SAVE_RESTRICTION
+        | sym::SAVE_MATCH_DATA
+        | sym::WITH_CURRENT_BUFFER => rebuild(head, forms, |_| true, env, cx),
+        _ => optimize_call(form, head, forms, env, cx),
+    }
+}
+
+/// Return a copy of the list `forms`, where the elements for which `pred`
+/// returns true when given their index are optimized.
+fn optimize_list<'ob>(
+    forms: &Rto<Object>,
+    pred: impl Fn(usize) -> bool,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<Object<'ob>> {
+    root!(elements, new(Vec), cx);
+    rooted_iter!(iter, forms, cx);
+    let mut idx = 0;
+    while let Some(form) = iter.next()? {
+        if pred(idx) {
+            let result = optimize(form, env, cx)?;
+            elements.push(result);
+        } else {
+            elements.push(form.bind(cx));
+        }
+        idx += 1;
+    }
+    Ok(slice_into_list(Rt::bind_slice(elements, cx), None, cx))
+}
+
+/// Return `(HEAD . FORMS)`, where the elements of FORMS selected by `pred`
+/// are optimized.
+fn rebuild<'ob>(
+    head: &Rto<Object>,
+    forms: &Rto<Object>,
+    pred: impl Fn(usize) -> bool,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<Object<'ob>> {
+    let tail = rebind!(optimize_list(forms, pred, env, cx)?);
+    Ok(Cons::new(head.bind(cx), tail, cx).into())
+}
+
+/// Optimize the body of `(function (lambda ARGS BODY...))`.
+fn optimize_function<'ob>(
+    form: &Rto<Object>,
+    forms: &Rto<Object>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<Object<'ob>> {
+    let ObjectType::Cons(cons) = forms.untag(cx) else { return Ok(form.bind(cx)) };
+    let lambda = cons.car();
+    let Ok((sym::LAMBDA, _)) = lambda.as_cons_pair() else { return Ok(form.bind(cx)) };
+    root!(lambda, cx);
+    let lambda = rebind!(optimize_list(lambda, |i| i >= 2, env, cx)?);
+    Ok(list![sym::FUNCTION, lambda; cx])
+}
+
+/// Optimize `(if COND THEN ELSE...)`. If COND is a constant, only the branch
+/// that would be taken is kept.
+fn optimize_if<'ob>(
+    form: &Rto<Object>,
+    forms: &Rto<Object>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<Object<'ob>> {
+    root!(elements, new(Vec), cx);
+    rooted_iter!(iter, forms, cx);
+    let Some(cond) = iter.next()? else { return Ok(form.bind(cx)) };
+    let cond = rebind!(optimize(cond, env, cx)?);
+    match constant_value(cond).map(|x| x.is_nil()) {
+        Some(false) => match iter.next()? {
+            Some(then) => optimize(then, env, cx),
+            None => Ok(form.bind(cx)),
+        },
+        Some(true) => {
+            // skip THEN
+            if iter.next()?.is_none() {
+                return Ok(form.bind(cx));
+            }
+            while let Some(x) = iter.next()? {
+                let result = optimize(x, env, cx)?;
+                elements.push(result);
+            }
+            match Rt::bind_slice(elements, cx) {
+                [] => Ok(NIL),
+                [x] => Ok(*x),
+                body => Ok(Cons::new(sym::PROGN, slice_into_list(body, None, cx), cx).into()),
+            }
+        }
+        None => {
+            elements.push(cond);
+            while let Some(x) = iter.next()? {
+                let result = optimize(x, env, cx)?;
+                elements.push(result);
+            }
+            let tail = slice_into_list(Rt::bind_slice(elements, cx), None, cx);
+            Ok(Cons::new(sym::IF, tail, cx).into())
+        }
+    }
+}
+
+/// Optimize `(cond CLAUSES...)`. Clauses whose condition is constant nil are
+/// removed, as are the clauses after one whose condition is constant non-nil.
+fn optimize_cond<'ob>(
+    forms: &Rto<Object>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<Object<'ob>> {
+    root!(clauses, new(Vec), cx);
+    rooted_iter!(iter, forms, cx);
+    while let Some(clause) = iter.next()? {
+        if !matches!(clause.untag(cx), ObjectType::Cons(_)) {
+            clauses.push(clause.bind(cx));
+            continue;
+        }
+        let clause = rebind!(optimize_list(clause, |_| true, env, cx)?);
+        let ObjectType::Cons(cons) = clause.untag() else { unreachable!() };
+        let never_taken = constant_value(cons.car()).map(|x| x.is_nil());
+        if never_taken == Some(true) {
+            continue;
+        }
+        clauses.push(clause);
+        if never_taken == Some(false) {
+            break;
+        }
+    }
+    if clauses.is_empty() {
+        return Ok(NIL);
+    }
+    let tail = slice_into_list(Rt::bind_slice(clauses, cx), None, cx);
+    Ok(Cons::new(sym::COND, tail, cx).into())
+}
+
+/// Optimize `(let BINDINGS BODY...)` or `(let* BINDINGS BODY...)`.
+fn optimize_let<'ob>(
+    form: &Rto<Object>,
+    head: &Rto<Object>,
+    forms: &Rto<Object>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<Object<'ob>> {
+    root!(elements, new(Vec), cx);
+    root!(bindings, new(Vec), cx);
+    rooted_iter!(iter, forms, cx);
+    let Some(varlist) = iter.next()? else { return Ok(form.bind(cx)) };
+    rooted_iter!(varlist, varlist, cx);
+    while let Some(binding) = varlist.next()? {
+        if matches!(binding.untag(cx), ObjectType::Cons(_)) {
+            // (VAR VALUE)
+            let binding = optimize_list(binding, |i| i == 1, env, cx)?;
+            bindings.push(binding);
+        } else {
+            bindings.push(binding.bind(cx));
+        }
+    }
+    let varlist = slice_into_list(Rt::bind_slice(bindings, cx), None, cx);
+    elements.push(varlist);
+    while let Some(x) = iter.next()? {
+        let result = optimize(x, env, cx)?;
+        elements.push(result);
+    }
+    let tail = slice_into_list(Rt::bind_slice(elements, cx), None, cx);
+    Ok(Cons::new(head.bind(cx), tail, cx).into())
+}
+
+/// Optimize `(condition-case VAR BODYFORM HANDLERS...)`.
+fn optimize_condition_case<'ob>(
+    form: &Rto<Object>,
+    forms: &Rto<Object>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<Object<'ob>> {
+    root!(elements, new(Vec), cx);
+    rooted_iter!(iter, forms, cx);
+    let Some(var) = iter.next()? else { return Ok(form.bind(cx)) };
+    elements.push(var.bind(cx));
+    let Some(body) = iter.next()? else { return Ok(form.bind(cx)) };
+    let body = optimize(body, env, cx)?;
+    elements.push(body);
+    while let Some(handler) = iter.next()? {
+        if matches!(handler.untag(cx), ObjectType::Cons(_)) {
+            // (CONDITIONS BODY...)
+            let handler = optimize_list(handler, |i| i >= 1, env, cx)?;
+            elements.push(handler);
+        } else {
+            elements.push(handler.bind(cx));
+        }
+    }
+    let tail = slice_into_list(Rt::bind_slice(elements, cx), None, cx);
+    Ok(Cons::new(sym::CONDITION_CASE, tail, cx).into())
+}
+
+fn is_foldable(func: Symbol, env: &Rt<Env>, cx: &Context) -> bool {
+    FOLDABLE.contains(&func) || !crate::data::get(func, sym::PURE, env, cx).is_nil()
+}
+
+/// Optimize the arguments of the call `(FUNC ARGS...)`, and replace it with
+/// its value if FUNC is foldable and every argument is a constant.
+fn optimize_call<'ob>(
+    form: &Rto<Object>,
+    head: &Rto<Object>,
+    forms: &Rto<Object>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<Object<'ob>> {
+    let func: Symbol = head.bind(cx).try_into()?;
+    // The arguments of macros are not forms
+    if let Some(Ok((sym::MACRO | sym::AUTOLOAD, _))) =
+        func.follow_indirect(cx).map(|x| x.as_cons_pair())
+    {
+        return Ok(form.bind(cx));
+    }
+    let args = rebind!(optimize_list(forms, |_| true, env, cx)?);
+    root!(args, cx);
+    let call =
+        |cx: &'ob Context| -> Object<'ob> { Cons::new(head.bind(cx), args.bind(cx), cx).into() };
+
+    let func: Symbol = head.bind(cx).try_into()?;
+    if !is_foldable(func, env, cx) {
+        return Ok(call(cx));
+    }
+    root!(values, new(Vec), cx);
+    for arg in args.bind(cx).into_list()? {
+        match constant_value(arg?) {
+            Some(value) => values.push(value),
+            None => return Ok(call(cx)),
+        }
+    }
+    let Some(func) = func.follow_indirect(cx) else { return Ok(call(cx)) };
+    root!(func, cx);
+    let name = head.bind(cx).to_string();
+    let frame = &mut CallFrame::new(env);
+    frame.push_arg_slice(Rt::bind_slice(values, cx));
+    let value = func.call(frame, Some(&name), cx).ok();
+    // Leave the error to be signaled when the form is evaluated
+    let Some(value) = value else { return Ok(call(cx)) };
+    let value = rebind!(value, cx);
+    Ok(quote(value, cx))
+}
+
+defsym!(PURE);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::gc::RootSet;
+
+    fn check(form: &str, expect: &str, env: &mut Rt<Env>, cx: &mut Context) {
+        let obj = crate::reader::read(form, cx).unwrap().0;
+        root!(obj, cx);
+        let result = rebind!(optimize(obj, env, cx).unwrap());
+        let expect = crate::reader::read(expect, cx).unwrap().0;
+        assert_eq!(result, expect, "{form}");
+    }
+
+    #[test]
+    fn test_fold_constants() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        sym::init_symbols();
+        root!(env, new(Env), cx);
+        check("(+ 1 (* 2 3))", "7", env, cx);
+        check("(concat \"a\" \"b\")", "\"ab\"", env, cx);
+        check("(setq a (1+ 1))", "(setq a 2)", env, cx);
+        check("(quote (+ 1 2))", "(quote (+ 1 2))", env, cx);
+        check("(/ 1 0)", "(/ 1 0)", env, cx);
+        check("(% 1 0)", "(% 1 0)", env, cx);
+        check("(ash 1 64)", "0", env, cx);
+        check("(list (- 5 1) a)", "(list 4 a)", env, cx);
+        check("(if (> 2 1) (foo) (bar))", "(foo)", env, cx);
+        check("(if nil (foo) (bar) (baz))", "(progn (bar) (baz))", env, cx);
+        check("(if (< 2 1) (foo))", "nil", env, cx);
+        check("(if x (foo) (+ 1 1))", "(if x (foo) 2)", env, cx);
+        check("(cond ((< 2 1) a) (x b) ('t c) (y d))", "(cond (x b) ('t c))", env, cx);
+        check("(let ((x (+ 1 2)) y) (list x))", "(let ((x 3) y) (list x))", env, cx);
+        check(
+            "(function (lambda (x) \"doc\" (+ x (+ 1 1))))",
+            "(function (lambda (x) \"doc\" (+ x 2)))",
+            env,
+            cx,
+        );
+        check(
+            "(condition-case err (+ 1 1) (error (+ 2 2)))",
+            "(condition-case err 2 (error 4))",
+            env,
+            cx,
+        );
+        check("(car '(1 2))", "(car '(1 2))", env, cx);
+        crate::data::put(sym::CAR, sym::PURE, sym::TRUE.into(), env);
+        check("(car '((1) 2))", "'(1)", env, cx);
+    }
+}
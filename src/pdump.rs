@@ -0,0 +1,749 @@
+//! Dumping the lisp state to an image file and loading it at startup.
+//!
+//! Loading `bootstrap.el` evaluates thousands of forms. Instead, the state
+//! it leaves behind can be dumped with `dump-emacs-portable` and restored
+//! with `--dump-file`, which only has to rebuild the objects.
+//!
+//! The image is a table of every object reachable from the interned symbols
+//! and the global variables and properties. Objects refer to each other by
+//! their index in the table rather than their address, so the image does not
+//! depend on where it is loaded. Builtin functions are stored by name and
+//! looked up when the image is loaded.
+//!
+//! At startup the image file is mapped into memory and decoded in place.
+//! Unlike the GNU Emacs pdumper, the objects are not used from the mapping
+//! directly: the garbage collector can only manage objects in its own
+//! blocks, so each object is rebuilt on the heap.
+use crate::core::{
+    cons::Cons,
+    env::{Env, INTERNED_SYMBOLS, intern, sym},
+    gc::{Context, Rt},
+    object::{
+        ByteFn, CharTableInner, FnArgs, FunctionType, Gc, HashTable, LispVec, NIL, Object,
+        ObjectType, RawObj, RecordBuilder, Symbol, SymbolWithPosInner, TRUE,
+    },
+};
+use crate::data::fset;
+use crate::fns::slice_into_list;
+use anyhow::{Context as _, Result, anyhow, bail, ensure};
+use rune_core::hashmap::{HashMap, HashSet};
+use rune_macros::defun;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+const MAGIC: &[u8; 8] = b"RUNEDUMP";
+/// Incremented whenever the layout of the image changes
+const FORMAT_VERSION: u32 = 1;
+/// Marks a missing function, value or parent
+const NONE: u32 = u32::MAX;
+/// Set on symbols that are special variables
+const SPECIAL: u8 = 1;
+/// Set on variables that become buffer-local when they are set
+const LOCAL_IF_SET: u8 = 2;
+
+const INT: u8 = 0;
+const FLOAT: u8 = 1;
+const SYMBOL: u8 = 2;
+const UNINTERNED_SYMBOL: u8 = 3;
+const STRING: u8 = 4;
+const BYTE_STRING: u8 = 5;
+const CONS: u8 = 6;
+const VECTOR: u8 = 7;
+const RECORD: u8 = 8;
+const HASH_TABLE: u8 = 9;
+const BYTE_FN: u8 = 10;
+const SUBR_FN: u8 = 11;
+const BUFFER: u8 = 12;
+const CHAR_TABLE: u8 = 13;
+const SYMBOL_WITH_POS: u8 = 14;
+
+defsym!(DUMPED_WITH_PDUMPER);
+defsym!(LOAD_TIME);
+defsym!(DUMP_FILE_NAME);
+
+/// The image that was loaded at startup and how long it took.
+static LOADED_DUMP: OnceLock<(PathBuf, Duration)> = OnceLock::new();
+
+#[derive(Default)]
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn u8(&mut self, x: u8) {
+        self.0.push(x);
+    }
+
+    fn u32(&mut self, x: u32) {
+        self.0.extend(x.to_le_bytes());
+    }
+
+    fn u64(&mut self, x: u64) {
+        self.0.extend(x.to_le_bytes());
+    }
+
+    fn len(&mut self, x: usize) -> Result<()> {
+        let Ok(len) = x.try_into() else { bail!("Object of length {x} is too large to dump") };
+        self.u32(len);
+        Ok(())
+    }
+
+    fn bytes(&mut self, x: &[u8]) -> Result<()> {
+        self.len(x.len())?;
+        self.0.extend(x);
+        Ok(())
+    }
+}
+
+/// Assigns every object an index in the table and encodes it.
+struct Dumper<'ob> {
+    ids: HashMap<RawObj, u32>,
+    objects: Vec<Object<'ob>>,
+}
+
+impl<'ob> Dumper<'ob> {
+    fn id(&mut self, obj: Object<'ob>) -> Result<u32> {
+        if let Some(id) = self.ids.get(&obj.into_raw()) {
+            return Ok(*id);
+        }
+        let Ok(id) = self.objects.len().try_into() else { bail!("Too many objects to dump") };
+        self.ids.insert(obj.into_raw(), id);
+        self.objects.push(obj);
+        Ok(id)
+    }
+
+    fn ids(
+        &mut self,
+        out: &mut Writer,
+        objects: impl ExactSizeIterator<Item = Object<'ob>>,
+    ) -> Result<()> {
+        out.len(objects.len())?;
+        for obj in objects {
+            let id = self.id(obj)?;
+            out.u32(id);
+        }
+        Ok(())
+    }
+
+    fn encode(&mut self, obj: Object<'ob>, out: &mut Writer, env: &Rt<Env>) -> Result<()> {
+        match obj.untag() {
+            ObjectType::Int(x) => {
+                out.u8(INT);
+                out.u64(x as u64);
+            }
+            ObjectType::Float(x) => {
+                out.u8(FLOAT);
+                out.u64(x.to_bits());
+            }
+            ObjectType::Symbol(x) => {
+                out.u8(if x.interned() { SYMBOL } else { UNINTERNED_SYMBOL });
+                out.bytes(x.name().as_bytes())?;
+            }
+            ObjectType::String(x) => {
+                out.u8(STRING);
+                out.bytes(x.as_bytes())?;
+            }
+            ObjectType::ByteString(x) => {
+                out.u8(BYTE_STRING);
+                out.bytes(x.inner())?;
+            }
+            ObjectType::Cons(x) => {
+                out.u8(CONS);
+                let (car, cdr) = (self.id(x.car())?, self.id(x.cdr())?);
+                out.u32(car);
+                out.u32(cdr);
+            }
+            ObjectType::Vec(x) => {
+                out.u8(VECTOR);
+                self.ids(out, x.iter().map(|x| x.get()))?;
+            }
+            ObjectType::Record(x) => {
+                out.u8(RECORD);
+                self.ids(out, x.iter().map(|x| x.get()))?;
+            }
+            ObjectType::HashTable(x) => {
+                out.u8(HASH_TABLE);
+                out.len(x.len())?;
+                for (key, value) in (0..x.len()).filter_map(|i| x.get_index(i)) {
+                    let (key, value) = (self.id(key)?, self.id(value)?);
+                    out.u32(key);
+                    out.u32(value);
+                }
+            }
+            ObjectType::ByteFn(x) => {
+                out.u8(BYTE_FN);
+                out.u64(x.args.into_arg_spec());
+                out.u64(x.depth as u64);
+                out.bytes(x.codes())?;
+                self.ids(out, x.consts().iter().copied())?;
+            }
+            ObjectType::SubrFn(x) => {
+                out.u8(SUBR_FN);
+                out.bytes(x.name.as_bytes())?;
+            }
+            ObjectType::Buffer(x) => {
+                out.u8(BUFFER);
+                // killed buffers have no name and are loaded as nil
+                let name = env.with_buffer(x, |b| b.name.clone()).unwrap_or_default();
+                out.bytes(name.as_bytes())?;
+            }
+            ObjectType::SymbolWithPos(x) => {
+                out.u8(SYMBOL_WITH_POS);
+                let sym = self.id(x.sym().into())?;
+                out.u32(sym);
+                out.u64(x.pos() as u64);
+            }
+            ObjectType::CharTable(x) => {
+                out.u8(CHAR_TABLE);
+                let init = self.id(x.init())?;
+                out.u32(init);
+                let parent = x.parent().map(|x| self.id(x.into())).transpose()?.unwrap_or(NONE);
+                out.u32(parent);
+                let entries = x.entries();
+                out.len(entries.len())?;
+                for (idx, value) in entries {
+                    out.u64(idx as u64);
+                    let value = self.id(value)?;
+                    out.u32(value);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Encode the state of every symbol that has a function, value, property or
+/// is special.
+fn dump_symbols<'ob>(dumper: &mut Dumper<'ob>, env: &Rt<Env>, cx: &'ob Context) -> Result<Writer> {
+    let mut symbols: Vec<Symbol> = {
+        let map = INTERNED_SYMBOLS.lock().unwrap();
+        map.symbols().map(|x| cx.bind(x)).collect()
+    };
+    // variables can also be bound to uninterned symbols
+    let mut seen: HashSet<Symbol> = symbols.iter().copied().collect();
+    let vars = env.vars.iter().map(|(x, _)| x.bind(cx));
+    let props = env.props.iter().map(|(x, _)| x.bind(cx));
+    for symbol in vars.chain(props) {
+        if seen.insert(symbol) {
+            symbols.push(symbol);
+        }
+    }
+    let mut out = Writer::default();
+    let mut count = 0;
+    let mut entries = Writer::default();
+    for symbol in symbols {
+        let func: Option<Object> = symbol.func(cx).map(Into::into);
+        let value = env.default_value(symbol, cx);
+        let props = env.props.get(symbol);
+        let mut flags = 0;
+        if symbol.is_special() {
+            flags |= SPECIAL;
+        }
+        if symbol.is_local_if_set() {
+            flags |= LOCAL_IF_SET;
+        }
+        if func.is_none() && value.is_none() && props.is_none() && flags == 0 {
+            continue;
+        }
+        count += 1;
+        let id = dumper.id(symbol.into())?;
+        entries.u32(id);
+        entries.u8(flags);
+        let func = func.map(|x| dumper.id(x)).transpose()?.unwrap_or(NONE);
+        entries.u32(func);
+        let value = value.map(|x| dumper.id(x)).transpose()?.unwrap_or(NONE);
+        entries.u32(value);
+        let props: Vec<_> = props
+            .map(|x| x.iter().map(|x| (x.0.bind(cx), x.1.bind(cx))).collect())
+            .unwrap_or_default();
+        entries.len(props.len())?;
+        for (prop, value) in props {
+            let (prop, value) = (dumper.id(prop.into())?, dumper.id(value)?);
+            entries.u32(prop);
+            entries.u32(value);
+        }
+    }
+    out.len(count)?;
+    out.0.extend(entries.0);
+    Ok(out)
+}
+
+/// Dump the current state of rune into an image file FILENAME. Starting rune
+/// with `--dump-file FILENAME` restores the state without loading the lisp
+/// files again.
+#[defun]
+fn dump_emacs_portable(
+    filename: &str,
+    _track_referrers: Option<Object>,
+    env: &Rt<Env>,
+    cx: &Context,
+) -> Result<()> {
+    let mut dumper = Dumper { ids: HashMap::default(), objects: Vec::new() };
+    let symbols = dump_symbols(&mut dumper, env, cx)?;
+    let mut objects = Writer::default();
+    // encoding an object adds the objects it refers to to the end of the table
+    let mut i = 0;
+    while let Some(obj) = dumper.objects.get(i) {
+        dumper.encode(*obj, &mut objects, env)?;
+        i += 1;
+    }
+    let mut out = Writer::default();
+    out.0.extend(MAGIC);
+    out.u32(FORMAT_VERSION);
+    out.bytes(env!("CARGO_PKG_VERSION").as_bytes())?;
+    out.len(dumper.objects.len())?;
+    out.0.extend(objects.0);
+    out.0.extend(symbols.0);
+    std::fs::write(filename, out.0).with_context(|| format!("Failed to write dump {filename}"))
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let end = self.pos.checked_add(len).filter(|x| *x <= self.data.len());
+        let Some(end) = end else { bail!("Dump file is truncated") };
+        let bytes = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into()?))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into()?))
+    }
+
+    fn len(&mut self) -> Result<usize> {
+        Ok(self.u32()? as usize)
+    }
+
+    fn bytes(&mut self) -> Result<&'a [u8]> {
+        let len = self.len()?;
+        self.take(len)
+    }
+
+    fn str(&mut self) -> Result<&'a str> {
+        Ok(std::str::from_utf8(self.bytes()?)?)
+    }
+
+    fn ids(&mut self) -> Result<Vec<u32>> {
+        let len = self.len()?;
+        (0..len).map(|_| self.u32()).collect()
+    }
+}
+
+/// An object from the image whose references have not been resolved yet.
+enum Entry<'a> {
+    Cons(u32, u32),
+    Vector(Vec<u32>),
+    Record(Vec<u32>),
+    HashTable(Vec<(u32, u32)>),
+    ByteFn {
+        spec: u64,
+        depth: u64,
+        codes: &'a [u8],
+        consts: Vec<u32>,
+    },
+    CharTable {
+        init: u32,
+        parent: u32,
+        entries: Vec<(u64, u32)>,
+    },
+    SymbolWithPos(u32, u64),
+    Done,
+}
+
+/// The objects of the image. Objects that can contain themselves are first
+/// created empty and filled in once every object exists.
+struct Loader<'ob> {
+    objects: Vec<Option<Object<'ob>>>,
+}
+
+impl<'ob> Loader<'ob> {
+    fn get(&self, id: u32) -> Result<Object<'ob>> {
+        let obj = self.objects.get(id as usize).copied().flatten();
+        obj.ok_or_else(|| anyhow!("Invalid object reference in dump: {id}"))
+    }
+
+    fn get_all(&self, ids: &[u32]) -> Result<Vec<Object<'ob>>> {
+        ids.iter().map(|x| self.get(*x)).collect()
+    }
+}
+
+/// Create the object that `tag` describes, or an empty object to be filled in
+/// later.
+fn read_object<'a, 'ob>(
+    tag: u8,
+    reader: &mut Reader<'a>,
+    cx: &'ob Context,
+) -> Result<(Option<Object<'ob>>, Entry<'a>)> {
+    let pair = |reader: &mut Reader| -> Result<(u32, u32)> { Ok((reader.u32()?, reader.u32()?)) };
+    let obj = match tag {
+        INT => cx.add(reader.u64()? as i64),
+        FLOAT => cx.add(f64::from_bits(reader.u64()?)),
+        SYMBOL => intern(reader.str()?, cx).into(),
+        UNINTERNED_SYMBOL => Symbol::new_uninterned(reader.str()?, cx).into(),
+        STRING => cx.add(reader.str()?),
+        BYTE_STRING => cx.add(reader.bytes()?.to_vec()),
+        SUBR_FN => {
+            let name = reader.str()?;
+            match intern(name, cx).func(cx).map(|x| x.untag()) {
+                Some(FunctionType::SubrFn(subr)) => subr.into(),
+                _ => bail!("Dump refers to unknown builtin function {name}"),
+            }
+        }
+        BUFFER => match reader.str()? {
+            "" => NIL,
+            name => crate::buffer::get_buffer_create(cx.add(name), None, cx)?,
+        },
+        CONS => {
+            let (car, cdr) = pair(reader)?;
+            return Ok((Some(Cons::new(NIL, NIL, cx).into()), Entry::Cons(car, cdr)));
+        }
+        VECTOR => {
+            let ids = reader.ids()?;
+            return Ok((Some(cx.add(vec![NIL; ids.len()])), Entry::Vector(ids)));
+        }
+        RECORD => {
+            let ids = reader.ids()?;
+            let mut record = cx.vec_with_capacity(ids.len());
+            record.resize(ids.len(), NIL);
+            return Ok((Some(cx.add(RecordBuilder(record))), Entry::Record(ids)));
+        }
+        HASH_TABLE => {
+            let len = reader.len()?;
+            let pairs = (0..len).map(|_| pair(reader)).collect::<Result<_>>()?;
+            let table = HashTable::with_hasher(std::hash::BuildHasherDefault::default());
+            return Ok((Some(cx.add(table)), Entry::HashTable(pairs)));
+        }
+        BYTE_FN => {
+            let (spec, depth, codes) = (reader.u64()?, reader.u64()?, reader.bytes()?);
+            let consts = reader.ids()?;
+            return Ok((None, Entry::ByteFn { spec, depth, codes, consts }));
+        }
+        CHAR_TABLE => {
+            let (init, parent) = pair(reader)?;
+            let len = reader.len()?;
+            let entries = (0..len)
+                .map(|_| -> Result<_> { Ok((reader.u64()?, reader.u32()?)) })
+                .collect::<Result<_>>()?;
+            return Ok((None, Entry::CharTable { init, parent, entries }));
+        }
+        SYMBOL_WITH_POS => {
+            let (sym, pos) = (reader.u32()?, reader.u64()?);
+            return Ok((None, Entry::SymbolWithPos(sym, pos)));
+        }
+        _ => bail!("Invalid object tag in dump: {tag}"),
+    };
+    Ok((Some(obj), Entry::Done))
+}
+
+/// Load the objects of an image and restore the symbols.
+fn load_image(data: &[u8], env: &mut Rt<Env>, cx: &Context) -> Result<()> {
+    let mut reader = Reader { data, pos: 0 };
+    ensure!(reader.take(MAGIC.len())? == MAGIC, "Not a rune dump file");
+    let version = reader.u32()?;
+    ensure!(version == FORMAT_VERSION, "Unsupported dump format version {version}");
+    let rune_version = reader.str()?;
+    ensure!(
+        rune_version == env!("CARGO_PKG_VERSION"),
+        "Dump was created by rune version {rune_version}"
+    );
+
+    let len = reader.len()?;
+    let mut loader = Loader { objects: Vec::with_capacity(len) };
+    let mut entries = Vec::with_capacity(len);
+    for _ in 0..len {
+        let tag = reader.u8()?;
+        let (obj, entry) = read_object(tag, &mut reader, cx)?;
+        loader.objects.push(obj);
+        entries.push(entry);
+    }
+    // Char tables and functions need their contents when they are created.
+    // Everything they refer to already exists, though it may still be empty.
+    // Symbols with position come first because they can be contained in both.
+    for (i, entry) in entries.iter().enumerate() {
+        if let Entry::SymbolWithPos(sym, pos) = entry {
+            let ObjectType::Symbol(sym) = loader.get(*sym)?.untag() else {
+                bail!("Symbol with position in dump does not contain a symbol")
+            };
+            loader.objects[i] = Some(cx.add(SymbolWithPosInner::new(sym, *pos as usize)));
+        }
+    }
+    for (i, entry) in entries.iter().enumerate() {
+        if let Entry::CharTable { init, .. } = entry {
+            let table = CharTableInner::new(Some(loader.get(*init)?));
+            loader.objects[i] = Some(cx.add(table));
+        }
+    }
+    for (i, entry) in entries.iter().enumerate() {
+        if let Entry::ByteFn { spec, depth, codes, consts } = entry {
+            let consts: Gc<&LispVec> = cx.add_as(loader.get_all(consts)?);
+            let args = FnArgs::from_arg_spec(*spec as i64)?;
+            let func = unsafe { ByteFn::make(codes, consts.untag(), args, *depth as usize) };
+            loader.objects[i] = Some(cx.add(func));
+        }
+    }
+    for (i, entry) in entries.iter().enumerate() {
+        let obj = loader.get(i as u32)?;
+        match (entry, obj.untag()) {
+            (Entry::Cons(car, cdr), ObjectType::Cons(cons)) => {
+                cons.set_car(loader.get(*car)?)?;
+                cons.set_cdr(loader.get(*cdr)?)?;
+            }
+            (Entry::Vector(ids), ObjectType::Vec(vec)) => {
+                for (cell, id) in vec.try_mut()?.iter().zip(ids) {
+                    cell.set(loader.get(*id)?);
+                }
+            }
+            (Entry::Record(ids), ObjectType::Record(record)) => {
+                for (cell, id) in record.try_mut()?.iter().zip(ids) {
+                    cell.set(loader.get(*id)?);
+                }
+            }
+            (Entry::HashTable(pairs), ObjectType::HashTable(table)) => {
+                for (key, value) in pairs {
+                    table.insert(loader.get(*key)?, loader.get(*value)?);
+                }
+            }
+            (Entry::CharTable { parent, entries, .. }, ObjectType::CharTable(table)) => {
+                for (idx, value) in entries {
+                    table.set(*idx as usize, loader.get(*value)?);
+                }
+                if *parent != NONE {
+                    let ObjectType::CharTable(parent) = loader.get(*parent)?.untag() else {
+                        bail!("Char table parent in dump is not a char table")
+                    };
+                    table.set_parent(Some(parent));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let symbols = reader.len()?;
+    let mut functions = Vec::new();
+    for _ in 0..symbols {
+        let ObjectType::Symbol(symbol) = loader.get(reader.u32()?)?.untag() else {
+            bail!("Invalid symbol in dump")
+        };
+        let flags = reader.u8()?;
+        if flags & SPECIAL != 0 {
+            symbol.make_special();
+        }
+        if flags & LOCAL_IF_SET != 0 {
+            symbol.make_local_if_set();
+        }
+        let func = reader.u32()?;
+        if func != NONE {
+            functions.push((symbol, loader.get(func)?));
+        }
+        let value = reader.u32()?;
+        if value != NONE {
+            env.vars.insert(symbol, loader.get(value)?);
+        }
+        let props = reader.len()?;
+        for _ in 0..props {
+            let ObjectType::Symbol(prop) = loader.get(reader.u32()?)?.untag() else {
+                bail!("Invalid property name in dump")
+            };
+            let value = loader.get(reader.u32()?)?;
+            env.set_prop(symbol, prop, value);
+        }
+    }
+    ensure!(reader.pos == data.len(), "Dump file has trailing data");
+    restore_functions(functions, cx)
+}
+
+/// Set the functions of the dumped symbols. `fset` refuses an alias when
+/// the chain it completes is too long, but a chain can grow past that limit
+/// by redefining its far end. So the functions are cleared first and each
+/// alias is set before the symbol it points to, which ends every chain that
+/// `fset` follows at a symbol without a function.
+fn restore_functions<'ob>(
+    mut functions: Vec<(Symbol<'ob>, Object<'ob>)>,
+    cx: &'ob Context,
+) -> Result<()> {
+    for (symbol, _) in &functions {
+        fset(*symbol, NIL, cx)?;
+    }
+    let definitions: HashMap<Symbol, Object<'ob>> = functions.iter().copied().collect();
+    let depth = |mut func: Object<'ob>| {
+        let mut depth = 0;
+        while let ObjectType::Symbol(sym) = func.untag() {
+            match definitions.get(&sym) {
+                Some(next) if depth < definitions.len() => func = *next,
+                _ => break,
+            }
+            depth += 1;
+        }
+        depth
+    };
+    functions.sort_by_cached_key(|(_, func)| std::cmp::Reverse(depth(*func)));
+    for (symbol, func) in functions {
+        fset(symbol, func, cx)
+            .with_context(|| format!("Failed to restore the function of {symbol}"))?;
+    }
+    Ok(())
+}
+
+/// A read-only mapping of a dump file.
+#[cfg(unix)]
+struct Mapping {
+    ptr: *mut libc::c_void,
+    len: usize,
+}
+
+#[cfg(unix)]
+impl Mapping {
+    fn new(file: &File) -> Result<Self> {
+        use std::os::fd::AsRawFd;
+        let len = usize::try_from(file.metadata()?.len())?;
+        ensure!(len > 0, "Dump file is empty");
+        let (prot, flags) = (libc::PROT_READ, libc::MAP_PRIVATE);
+        // SAFETY: The file is open for reading and the mapping is only read
+        // through `bytes`, which borrows the mapping.
+        let ptr =
+            unsafe { libc::mmap(std::ptr::null_mut(), len, prot, flags, file.as_raw_fd(), 0) };
+        if ptr == libc::MAP_FAILED {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        Ok(Self { ptr, len })
+    }
+
+    fn bytes(&self) -> &[u8] {
+        // SAFETY: The mapping is `len` bytes long and lives as long as `self`.
+        unsafe { std::slice::from_raw_parts(self.ptr.cast(), self.len) }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        // SAFETY: `ptr` and `len` describe a mapping created by `mmap`.
+        unsafe { libc::munmap(self.ptr, self.len) };
+    }
+}
+
+/// Other systems read the whole dump file instead of mapping it.
+#[cfg(not(unix))]
+struct Mapping(Vec<u8>);
+
+#[cfg(not(unix))]
+impl Mapping {
+    fn new(mut file: &File) -> Result<Self> {
+        use std::io::Read;
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)?;
+        Ok(Self(data))
+    }
+
+    fn bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+/// Restore the state saved in the image file at `path`.
+pub(crate) fn load_dump(path: &Path, env: &mut Rt<Env>, cx: &Context) -> Result<()> {
+    let start = Instant::now();
+    let mapping = File::open(path)
+        .map_err(Into::into)
+        .and_then(|file| Mapping::new(&file))
+        .with_context(|| format!("Failed to read dump file {}", path.display()))?;
+    load_image(mapping.bytes(), env, cx)?;
+    LOADED_DUMP.set((path.to_owned(), start.elapsed())).ok();
+    Ok(())
+}
+
+/// Return statistics about the dump file rune was started with, or nil if it
+/// was not started from a dump.
+#[defun]
+fn pdumper_stats<'ob>(cx: &'ob Context) -> Object<'ob> {
+    let Some((path, time)) = LOADED_DUMP.get() else { return NIL };
+    let stats = [
+        (sym::DUMPED_WITH_PDUMPER, TRUE),
+        (sym::LOAD_TIME, cx.add(time.as_secs_f64())),
+        (sym::DUMP_FILE_NAME, cx.add(path.to_string_lossy().into_owned())),
+    ];
+    let stats: Vec<Object> = stats.into_iter().map(|(k, v)| Cons::new(k, v, cx).into()).collect();
+    slice_into_list(&stats, None, cx)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::gc::RootSet;
+    use crate::interpreter::eval;
+    use rune_core::macros::{rebind, root};
+
+    fn eval_str<'ob>(text: &str, env: &mut Rt<Env>, cx: &'ob mut Context) -> Object<'ob> {
+        let obj = crate::reader::read(text, cx).unwrap().0;
+        root!(obj, cx);
+        rebind!(eval(obj, None, env, cx).unwrap())
+    }
+
+    #[test]
+    fn test_dump_round_trip() {
+        let dir = crate::fileio::TempDir::new("pdump");
+        let file = dir.path().join("test.pdmp");
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        sym::init_symbols();
+        root!(env, new(Env), cx);
+        let setup = format!(
+            r#"(progn
+                 (defvar pdump--test-var (let ((x (list 1 2.5 "s" [a b] (make-symbol "u"))))
+                                           (setcdr (nthcdr 4 x) x)
+                                           x))
+                 (defalias 'pdump--test-fn #'(lambda (x) (+ x 1)))
+                 (put 'pdump--test-var 'prop 'value)
+                 (dump-emacs-portable {:?}))"#,
+            file.display().to_string()
+        );
+        eval_str(&setup, env, cx);
+
+        // load into a fresh environment
+        root!(loaded, new(Env), cx);
+        crate::data::fset(intern("pdump--test-fn", cx), NIL, cx).unwrap();
+        load_dump(&file, loaded, cx).unwrap();
+        let result = eval_str(
+            "(list (pdump--test-fn 2)
+                   (get 'pdump--test-var 'prop)
+                   (eq pdump--test-var (nthcdr 5 pdump--test-var))
+                   (car (nthcdr 3 pdump--test-var)))",
+            loaded,
+            cx,
+        );
+        assert_eq!(result.to_string(), "(3 value t [a b])");
+        assert!(load_image(b"not a dump", loaded, cx).is_err());
+    }
+
+    #[test]
+    fn test_restore_long_alias_chain() {
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        sym::init_symbols();
+        // Restored from the far end, each alias would complete a chain
+        // longer than fset allows
+        let tail = intern("pdump--chain-tail", cx);
+        let mut functions: Vec<(Symbol, Object)> = vec![(tail, sym::CAR.into())];
+        let mut prev = tail;
+        for i in 0..=crate::core::object::MAX_FUNCTION_INDIRECTION {
+            let alias = intern(&format!("pdump--chain-{i}"), cx);
+            functions.push((alias, prev.into()));
+            prev = alias;
+        }
+        restore_functions(functions, cx).unwrap();
+        assert_eq!(tail.func(cx).unwrap().to_string(), "car");
+    }
+}
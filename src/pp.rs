@@ -0,0 +1,216 @@
+//! Pretty printing lisp objects.
+use crate::core::{
+    env::{Env, sym},
+    gc::{Context, Rt, Rto},
+    object::{Object, ObjectType, Symbol},
+};
+use crate::print::{print_to_string, write_to_stream};
+use anyhow::Result;
+use rune_macros::defun;
+
+defsym!(LISP_INDENT_FUNCTION);
+
+/// The width used when `fill-column` is not an integer.
+const DEFAULT_WIDTH: usize = 70;
+
+/// The number of arguments before the body of `symbol`, if it has a body. The
+/// distinguished arguments are printed on the first line and the body is
+/// indented by 2 on the lines after it.
+fn body_indent(symbol: Symbol, env: &Rt<Env>, cx: &Context) -> Option<usize> {
+    if let ObjectType::Int(n) = crate::data::get(symbol, sym::LISP_INDENT_FUNCTION, env, cx).untag()
+    {
+        return usize::try_from(n).ok();
+    }
+    let indent = match symbol.name() {
+        "progn" | "save-excursion" | "save-restriction" | "save-current-buffer" => 0,
+        "let"
+        | "let*"
+        | "lambda"
+        | "while"
+        | "when"
+        | "unless"
+        | "dolist"
+        | "dotimes"
+        | "catch"
+        | "prog1"
+        | "unwind-protect"
+        | "with-current-buffer"
+        | "closure" => 1,
+        "if" | "condition-case" | "prog2" | "defun" | "defmacro" | "defsubst" => 2,
+        _ => return None,
+    };
+    Some(indent)
+}
+
+/// Lays out objects over multiple lines so they fit in `width`.
+struct PrettyPrinter<'env, 'rt> {
+    out: String,
+    width: usize,
+    env: &'env Rt<Env<'rt>>,
+}
+
+impl PrettyPrinter<'_, '_> {
+    /// The column that the next character will be printed at.
+    fn column(&self) -> usize {
+        let line = self.out.rsplit('\n').next().unwrap_or_default();
+        line.chars().count()
+    }
+
+    fn newline(&mut self, indent: usize) {
+        self.out.push('\n');
+        self.out.extend(std::iter::repeat_n(' ', indent));
+    }
+
+    fn print(&mut self, obj: Object, cx: &Context) -> Result<()> {
+        let flat = print_to_string(obj, true, self.env, cx)?;
+        if self.column() + flat.chars().count() <= self.width {
+            self.out.push_str(&flat);
+            return Ok(());
+        }
+        match obj.untag() {
+            ObjectType::Cons(cons) => {
+                // Only proper lists are broken over multiple lines
+                let Ok(elements) = cons.elements().collect::<Result<Vec<_>, _>>() else {
+                    self.out.push_str(&flat);
+                    return Ok(());
+                };
+                self.print_list(&elements, cx)
+            }
+            ObjectType::Vec(vec) => {
+                let elements: Vec<_> = vec.iter().map(|x| x.get()).collect();
+                self.out.push('[');
+                self.fill(&elements, self.column(), cx)?;
+                self.out.push(']');
+                Ok(())
+            }
+            _ => {
+                self.out.push_str(&flat);
+                Ok(())
+            }
+        }
+    }
+
+    fn print_list(&mut self, elements: &[Object], cx: &Context) -> Result<()> {
+        let start = self.column();
+        let quote = match elements {
+            [head, _] => match head.untag() {
+                ObjectType::Symbol(sym::QUOTE) => Some("'"),
+                ObjectType::Symbol(sym::FUNCTION) => Some("#'"),
+                ObjectType::Symbol(sym::BACKQUOTE) => Some("`"),
+                ObjectType::Symbol(sym::UNQUOTE) => Some(","),
+                ObjectType::Symbol(sym::SPLICE) => Some(",@"),
+                _ => None,
+            },
+            _ => None,
+        };
+        if let Some(quote) = quote {
+            self.out.push_str(quote);
+            return self.print(elements[1], cx);
+        }
+        self.out.push('(');
+        let ObjectType::Symbol(head) = elements[0].untag() else {
+            // a list of data is filled
+            self.fill(elements, start + 1, cx)?;
+            self.out.push(')');
+            return Ok(());
+        };
+        self.print(elements[0], cx)?;
+        let rest = &elements[1..];
+        if let Some(indent) = body_indent(head, self.env, cx) {
+            let (args, body) = rest.split_at(indent.min(rest.len()));
+            for arg in args {
+                self.out.push(' ');
+                self.print(*arg, cx)?;
+            }
+            for form in body {
+                self.newline(start + 2);
+                self.print(*form, cx)?;
+            }
+        } else if let Some((first, rest)) = rest.split_first() {
+            // align the arguments of a function call with the first one
+            self.out.push(' ');
+            let column = self.column();
+            self.print(*first, cx)?;
+            for arg in rest {
+                self.newline(column);
+                self.print(*arg, cx)?;
+            }
+        }
+        self.out.push(')');
+        Ok(())
+    }
+
+    /// Print `elements` separated by spaces, starting a new line at `indent`
+    /// when the next element does not fit.
+    fn fill(&mut self, elements: &[Object], indent: usize, cx: &Context) -> Result<()> {
+        for (i, obj) in elements.iter().enumerate() {
+            if i != 0 {
+                let flat = print_to_string(*obj, true, self.env, cx)?;
+                if self.column() + 1 + flat.chars().count() > self.width {
+                    self.newline(indent);
+                } else {
+                    self.out.push(' ');
+                }
+            }
+            self.print(*obj, cx)?;
+        }
+        Ok(())
+    }
+}
+
+/// Return a string containing the pretty-printed representation of OBJECT,
+/// followed by a newline. Lines are broken to fit in `fill-column`.
+#[defun]
+pub(crate) fn pp_to_string(object: Object, env: &Rt<Env>, cx: &Context) -> Result<String> {
+    let width = match env.vars.get(sym::FILL_COLUMN).map(|x| x.bind(cx).untag()) {
+        Some(ObjectType::Int(n)) => usize::try_from(n).unwrap_or(DEFAULT_WIDTH),
+        _ => DEFAULT_WIDTH,
+    };
+    let mut printer = PrettyPrinter { out: String::new(), width, env };
+    printer.print(object, cx)?;
+    printer.out.push('\n');
+    Ok(printer.out)
+}
+
+/// Output the pretty-printed representation of OBJECT to STREAM, which
+/// defaults to `standard-output`.
+#[defun]
+fn pp(
+    object: &Rto<Object>,
+    stream: Option<&Rto<Object>>,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<()> {
+    let text = pp_to_string(object.bind(cx), env, cx)?;
+    write_to_stream(&text, stream, env, cx)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::interpreter::assert_lisp;
+
+    #[test]
+    fn test_pp_to_string() {
+        assert_lisp(r#"(pp-to-string '(a "b" [c]))"#, r#""(a \"b\" [c])\n""#);
+        assert_lisp(
+            r#"(let ((fill-column 30) (print-quoted t))
+                 (pp-to-string '(defun foo (x) (let ((y (+ x 1))) (if (> y 2) (bar y) 'baz)))))"#,
+            r#""(defun foo (x)
+  (let ((y (+ x 1)))
+    (if (> y 2) (bar y) 'baz)))\n""#,
+        );
+        assert_lisp(
+            r#"(let ((fill-column 20))
+                 (pp-to-string '(foo (bar 1 2 3) (baz 4 5 6) [1 2 3 4 5 6 7 8 9 10])))"#,
+            r#""(foo (bar 1 2 3)
+     (baz 4 5 6)
+     [1 2 3 4 5 6 7
+      8 9 10])\n""#,
+        );
+        assert_lisp(
+            r#"(let ((fill-column 10)) (pp-to-string '((1 2 3) (4 5 6))))"#,
+            r#""((1 2 3)
+ (4 5 6))\n""#,
+        );
+    }
+}
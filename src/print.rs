@@ -1,6 +1,19 @@
 //! Printing utilities.
-use crate::core::object::Object;
+use crate::core::{
+    cons::Cons,
+    env::{CallFrame, Env, sym},
+    gc::{Context, Rt, Rto},
+    object::{
+        Function, LispHashTable, LispVec, Object, ObjectType, OptionalFlag, PrintState, Record,
+        Symbol, char_raw_byte,
+    },
+};
+use crate::library::number;
+use anyhow::Result;
+use rune_core::macros::root;
 use rune_macros::defun;
+use std::fmt::{self, Write as _};
+use std::io::Write as _;
 
 #[defun]
 fn error_message_string(obj: Object) -> String {
@@ -11,3 +24,467 @@ fn error_message_string(obj: Object) -> String {
 defvar!(PRINT_LENGTH);
 defvar!(PRINT_LEVEL);
 defvar_bool!(PRINT_ESCAPE_NEWLINES, false);
+defvar_bool!(PRINT_CIRCLE, false);
+defvar_bool!(PRINT_QUOTED, true);
+defvar_bool!(PRINT_SYMBOLS_BARE, false);
+defvar!(STANDARD_OUTPUT, true);
+
+/// Prints objects as text, following the print control variables.
+struct Printer {
+    /// Print objects so they can be read back, like `prin1`. Otherwise strings
+    /// and symbols are printed without quoting, like `princ`.
+    escape: bool,
+    /// The value of `print-length`.
+    length: Option<usize>,
+    /// The value of `print-level`.
+    level: Option<usize>,
+    /// The value of `print-quoted`.
+    quoted: bool,
+    /// The value of `print-escape-newlines`.
+    escape_newlines: bool,
+    /// The value of `print-symbols-bare`.
+    symbols_bare: bool,
+    /// How many lists and vectors enclose the object being printed.
+    depth: usize,
+    state: PrintState,
+}
+
+impl Printer {
+    fn new(obj: Object, escape: bool, env: &Rt<Env>, cx: &Context) -> Self {
+        let var = |sym: Symbol| env.vars.get(sym).map(|x| x.bind(cx)).unwrap_or_default();
+        let limit = |sym: Symbol| match var(sym).untag() {
+            ObjectType::Int(n) => usize::try_from(n).ok(),
+            _ => None,
+        };
+        let state = if var(sym::PRINT_CIRCLE).is_nil() {
+            PrintState::default()
+        } else {
+            PrintState::with_labels(obj.untag())
+        };
+        Self {
+            escape,
+            length: limit(sym::PRINT_LENGTH),
+            level: limit(sym::PRINT_LEVEL),
+            quoted: !var(sym::PRINT_QUOTED).is_nil(),
+            escape_newlines: !var(sym::PRINT_ESCAPE_NEWLINES).is_nil(),
+            symbols_bare: !var(sym::PRINT_SYMBOLS_BARE).is_nil(),
+            depth: 0,
+            state,
+        }
+    }
+
+    fn print(&mut self, obj: Object, out: &mut String) -> fmt::Result {
+        match obj.untag() {
+            ObjectType::Cons(cons) => self.print_list(cons, out),
+            ObjectType::Vec(vec) => {
+                let ptr = (vec as *const LispVec).cast();
+                self.print_elements(ptr, "[", vec.iter().map(|x| x.get()), "]", out)
+            }
+            ObjectType::Record(record) => {
+                let ptr = (record as *const Record).cast();
+                self.print_elements(ptr, "#s(", record.iter().map(|x| x.get()), ")", out)
+            }
+            ObjectType::HashTable(table) => self.print_hash_table(table, out),
+            ObjectType::String(string) if self.escape => {
+                self.print_string(string, out);
+                Ok(())
+            }
+            ObjectType::String(string) => {
+                out.push_str(string);
+                Ok(())
+            }
+            ObjectType::ByteString(string) if self.escape => write!(out, "\"{string}\""),
+            ObjectType::ByteString(string) => {
+                out.extend(string.iter().map(|x| char::from(*x)));
+                Ok(())
+            }
+            ObjectType::Symbol(symbol) if self.escape => {
+                print_symbol_name(symbol.name(), out);
+                Ok(())
+            }
+            ObjectType::SymbolWithPos(symbol) if self.symbols_bare => {
+                self.print(symbol.sym().into(), out)
+            }
+            other => write!(out, "{other}"),
+        }
+    }
+
+    /// Start printing a list or vector at `ptr`. Returns false if it was
+    /// printed as a reference or elided because of `print-level` instead.
+    fn enter(&mut self, ptr: *const u8, out: &mut String) -> Result<bool, fmt::Error> {
+        if self.level.is_some_and(|level| self.depth >= level) {
+            out.push_str("...");
+            return Ok(false);
+        }
+        if !self.state.start(ptr, out)? {
+            return Ok(false);
+        }
+        self.depth += 1;
+        Ok(true)
+    }
+
+    fn exit(&mut self) {
+        self.depth -= 1;
+        self.state.end();
+    }
+
+    fn print_list(&mut self, cons: &Cons, out: &mut String) -> fmt::Result {
+        if !self.enter((cons as *const Cons).cast(), out)? {
+            return Ok(());
+        }
+        if let Some((prefix, arg)) = self.quoted_form(cons) {
+            out.push_str(prefix);
+            self.print(arg, out)?;
+            self.exit();
+            return Ok(());
+        }
+        out.push('(');
+        let mut cons = cons;
+        let mut tails = 0;
+        for count in 1.. {
+            self.print(cons.car(), out)?;
+            match cons.cdr().untag() {
+                // a labeled tail has to be printed on its own
+                ObjectType::Cons(tail) if self.state.is_labeled((tail as *const Cons).cast()) => {
+                    out.push_str(" . ");
+                    self.print(cons.cdr(), out)?;
+                    break;
+                }
+                ObjectType::Cons(tail) => {
+                    cons = tail;
+                    out.push(' ');
+                }
+                ObjectType::NIL => break,
+                _ => {
+                    out.push_str(" . ");
+                    self.print(cons.cdr(), out)?;
+                    break;
+                }
+            }
+            if self.length.is_some_and(|length| count >= length) {
+                out.push_str("...");
+                break;
+            }
+            let ptr = (cons as *const Cons).cast();
+            if let Some(depth) = self.state.depth(ptr) {
+                write!(out, ". #{depth}")?;
+                break;
+            }
+            self.state.push_tail(ptr);
+            tails += 1;
+        }
+        for _ in 0..tails {
+            self.state.end();
+        }
+        self.exit();
+        out.push(')');
+        Ok(())
+    }
+
+    /// If `print-quoted` is non-nil and `cons` is a form like `(quote x)`,
+    /// return the prefix to print it with and its argument.
+    fn quoted_form<'ob>(&self, cons: &'ob Cons) -> Option<(&'static str, Object<'ob>)> {
+        if !self.quoted {
+            return None;
+        }
+        let prefix = match cons.car().untag() {
+            ObjectType::Symbol(sym::QUOTE) => "'",
+            ObjectType::Symbol(sym::FUNCTION) => "#'",
+            ObjectType::Symbol(sym::BACKQUOTE) => "`",
+            ObjectType::Symbol(sym::UNQUOTE) => ",",
+            ObjectType::Symbol(sym::SPLICE) => ",@",
+            _ => return None,
+        };
+        match cons.cdr().untag() {
+            ObjectType::Cons(rest)
+                if rest.cdr().is_nil() && !self.state.is_labeled((rest as *const Cons).cast()) =>
+            {
+                Some((prefix, rest.car()))
+            }
+            _ => None,
+        }
+    }
+
+    fn print_elements<'ob>(
+        &mut self,
+        ptr: *const u8,
+        open: &str,
+        elements: impl Iterator<Item = Object<'ob>>,
+        close: &str,
+        out: &mut String,
+    ) -> fmt::Result {
+        if !self.enter(ptr, out)? {
+            return Ok(());
+        }
+        out.push_str(open);
+        for (i, obj) in elements.enumerate() {
+            if i != 0 {
+                out.push(' ');
+            }
+            if self.length.is_some_and(|length| i >= length) {
+                out.push_str("...");
+                break;
+            }
+            self.print(obj, out)?;
+        }
+        self.exit();
+        out.push_str(close);
+        Ok(())
+    }
+
+    fn print_hash_table(&mut self, table: &LispHashTable, out: &mut String) -> fmt::Result {
+        if !self.enter((table as *const LispHashTable).cast(), out)? {
+            return Ok(());
+        }
+        out.push_str("#s(hash-table (");
+        for i in 0..table.len() {
+            let Some((key, value)) = table.get_index(i) else { continue };
+            if i != 0 {
+                out.push(' ');
+            }
+            self.print(key, out)?;
+            out.push(' ');
+            self.print(value, out)?;
+        }
+        self.exit();
+        out.push_str("))");
+        Ok(())
+    }
+
+    fn print_string(&self, string: &str, out: &mut String) {
+        out.push('"');
+        for chr in string.chars() {
+            match chr {
+                '"' | '\\' => {
+                    out.push('\\');
+                    out.push(chr);
+                }
+                '\n' if self.escape_newlines => out.push_str("\\n"),
+                '\x0C' if self.escape_newlines => out.push_str("\\f"),
+                chr => match char_raw_byte(chr) {
+                    Some(byte) => {
+                        let _ = write!(out, "\\{byte:03o}");
+                    }
+                    None => out.push(chr),
+                },
+            }
+        }
+        out.push('"');
+    }
+}
+
+/// Print a symbol name so that reading it back gives the same symbol.
+fn print_symbol_name(name: &str, out: &mut String) {
+    if name.is_empty() {
+        out.push_str("##");
+        return;
+    }
+    // names that would read as numbers need a backslash
+    if number::parse(name).is_some() || name == "." || name.starts_with('?') {
+        out.push('\\');
+    }
+    for chr in name.chars() {
+        let special =
+            matches!(chr, '"' | '\\' | '\'' | ';' | '#' | '(' | ')' | ',' | '`' | '[' | ']');
+        if special || chr <= ' ' {
+            out.push('\\');
+        }
+        out.push(chr);
+    }
+}
+
+/// The printed representation of `obj`. If `escape` is true, it is printed
+/// like `prin1`, otherwise like `princ`.
+pub(crate) fn print_to_string(
+    obj: Object,
+    escape: bool,
+    env: &Rt<Env>,
+    cx: &Context,
+) -> Result<String> {
+    let mut out = String::new();
+    Printer::new(obj, escape, env, cx).print(obj, &mut out)?;
+    Ok(out)
+}
+
+/// Send `text` to the output stream `printcharfun`, which defaults to
+/// `standard-output`. A stream is either a buffer, which the text is inserted
+/// into, a function, which is called with each character, or t, which prints
+/// to stdout.
+pub(crate) fn write_to_stream(
+    text: &str,
+    printcharfun: Option<&Rto<Object>>,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<()> {
+    let stream = match printcharfun.map(|x| x.bind(cx)) {
+        Some(stream) if !stream.is_nil() => stream,
+        _ => env.vars.get(sym::STANDARD_OUTPUT).map(|x| x.bind(cx)).unwrap_or_default(),
+    };
+    match stream.untag() {
+        ObjectType::NIL | ObjectType::Symbol(sym::TRUE) => {
+            print!("{text}");
+            std::io::stdout().flush()?;
+        }
+        ObjectType::Buffer(buffer) => env.with_buffer_mut(buffer, |b| b.insert_str(text))?,
+        _ => {
+            let func: Function = stream.try_into()?;
+            root!(func, cx);
+            for chr in text.chars() {
+                let frame = &mut CallFrame::new(env);
+                frame.push_arg(cx.add(chr));
+                func.call(frame, None, cx)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Return a string containing the printed representation of OBJECT. If
+/// NOESCAPE is non-nil, strings and symbols are printed without quoting, like
+/// `princ`.
+#[defun]
+pub(crate) fn prin1_to_string(
+    object: Object,
+    noescape: OptionalFlag,
+    _overrides: Option<Object>,
+    env: &Rt<Env>,
+    cx: &Context,
+) -> Result<String> {
+    print_to_string(object, noescape.is_none(), env, cx)
+}
+
+/// Output the printed representation of OBJECT to PRINTCHARFUN, quoted so
+/// that it can be read back.
+#[defun]
+fn prin1<'ob>(
+    object: &Rto<Object>,
+    printcharfun: Option<&Rto<Object>>,
+    _overrides: Option<&Rto<Object>>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<Object<'ob>> {
+    let text = print_to_string(object.bind(cx), true, env, cx)?;
+    write_to_stream(&text, printcharfun, env, cx)?;
+    Ok(object.bind(cx))
+}
+
+/// Output the printed representation of OBJECT to PRINTCHARFUN, without
+/// quoting strings and symbols.
+#[defun]
+fn princ<'ob>(
+    object: &Rto<Object>,
+    printcharfun: Option<&Rto<Object>>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<Object<'ob>> {
+    let text = print_to_string(object.bind(cx), false, env, cx)?;
+    write_to_stream(&text, printcharfun, env, cx)?;
+    Ok(object.bind(cx))
+}
+
+/// Output the printed representation of OBJECT to PRINTCHARFUN like `prin1`,
+/// with a newline before and after it.
+#[defun]
+pub(crate) fn print<'ob>(
+    object: &Rto<Object>,
+    printcharfun: Option<&Rto<Object>>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<Object<'ob>> {
+    let text = print_to_string(object.bind(cx), true, env, cx)?;
+    write_to_stream(&format!("\n{text}\n"), printcharfun, env, cx)?;
+    Ok(object.bind(cx))
+}
+
+/// Output a newline to PRINTCHARFUN.
+#[defun]
+fn terpri(
+    printcharfun: Option<&Rto<Object>>,
+    _ensure: OptionalFlag,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<bool> {
+    // TODO: ENSURE should only print a newline when not at the start of a line
+    write_to_stream("\n", printcharfun, env, cx)?;
+    Ok(true)
+}
+
+/// Output CHARACTER to PRINTCHARFUN.
+#[defun]
+fn write_char(
+    character: &Rto<Object>,
+    printcharfun: Option<&Rto<Object>>,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<char> {
+    let character: char = character.bind(cx).try_into()?;
+    write_to_stream(character.encode_utf8(&mut [0; 4]), printcharfun, env, cx)?;
+    Ok(character)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::interpreter::assert_lisp;
+
+    #[test]
+    fn test_prin1_to_string() {
+        assert_lisp(r#"(prin1-to-string "a\"b\\c")"#, r#""\"a\\\"b\\\\c\"""#);
+        assert_lisp(r#"(prin1-to-string "a\"b" t)"#, r#""a\"b""#);
+        assert_lisp(r#"(prin1-to-string (intern "a b(c)"))"#, r#""a\\ b\\(c\\)""#);
+        assert_lisp(r#"(prin1-to-string (intern "12"))"#, r#""\\12""#);
+        assert_lisp(
+            "(let ((print-quoted t)) (prin1-to-string '('a #'b `(c ,d ,@e))))",
+            r#""('a #'b `(c ,d ,@e))""#,
+        );
+        assert_lisp("(let ((print-quoted nil)) (prin1-to-string ''a))", r#""(quote a)""#);
+        assert_lisp(
+            r#"(let ((print-escape-newlines t)) (prin1-to-string "a\nb"))"#,
+            r#""\"a\\nb\"""#,
+        );
+    }
+
+    #[test]
+    fn test_print_limits() {
+        assert_lisp(
+            "(let ((print-length 2)) (list (prin1-to-string '(1 2 3)) (prin1-to-string [1 2 3])
+                                         (prin1-to-string '(1 2))))",
+            r#"("(1 2 ...)" "[1 2 ...]" "(1 2)")"#,
+        );
+        assert_lisp(
+            "(let ((print-level 1)) (prin1-to-string '(1 (2 [3]) [4])))",
+            r#""(1 ... ...)""#,
+        );
+        assert_lisp(
+            "(let ((print-level 2)) (prin1-to-string '(1 (2 [3]) [4])))",
+            r#""(1 (2 ...) [4])""#,
+        );
+    }
+
+    #[test]
+    fn test_print_circle() {
+        let circle = "(let ((x (list 1))) (setcdr x x) (prin1-to-string x))";
+        assert_lisp(circle, "\"(1 . #0)\"");
+        assert_lisp(&format!("(let ((print-circle t)) {circle})"), "\"#1=(1 . #1#)\"");
+        assert_lisp(
+            "(let ((print-circle t) (x (list 1))) (prin1-to-string (list x x)))",
+            "\"(#1=(1) #1#)\"",
+        );
+    }
+
+    #[test]
+    fn test_output_streams() {
+        assert_lisp(
+            r#"(progn (prin1 "a" (current-buffer)) (princ "b" (current-buffer))
+                      (print 'c (current-buffer)) (terpri (current-buffer))
+                      (write-char ?d (current-buffer)) (buffer-string))"#,
+            r#""\"a\"b\nc\n\nd""#,
+        );
+        assert_lisp(
+            r#"(let ((chars nil) (standard-output (current-buffer)))
+                 (princ '(a "b") #'(lambda (c) (setq chars (cons c chars))))
+                 (princ 1)
+                 (list (apply #'string (reverse chars)) (buffer-string)))"#,
+            r#"("(a b)" "1")"#,
+        );
+    }
+}
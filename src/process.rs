@@ -0,0 +1,1391 @@
+//! Asynchronous subprocesses and network connections.
+//!
+//! Process objects are represented as records of the form
+//! `#s(process NAME BUFFER FILTER SENTINEL PLIST)`. The lisp-visible parts of
+//! the process live in the record (which is kept alive by the environment),
+//! while the operating system resources are stored in the `process_state`
+//! table of the environment, keyed by the process name.
+//!
+//! Subprocesses talk to rune either through pipes or through a
+//! pseudo-terminal, which programs like shells need to run interactively.
+//! Each subprocess is the leader of its own session, so signals can be sent to
+//! it and to all of its children at once.
+use crate::{
+    coding::{CodingSystem, Decoded, StreamDecoder},
+    core::{
+        cons::Cons,
+        env::{ArgSlice, Env, intern, sym},
+        error::{Type, TypeError},
+        gc::{Context, Rt, Rto},
+        object::{
+            Function, IntoObject, List, NIL, Object, ObjectType, Record, RecordBuilder, TRUE,
+        },
+    },
+    fns::slice_into_list,
+};
+use anyhow::{Result, bail, ensure};
+use rune_core::{
+    hashmap::HashMap,
+    macros::{call, list, root},
+};
+use rune_macros::defun;
+use std::{
+    fs::{File, OpenOptions},
+    io::{ErrorKind, Read, Write},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    os::{
+        fd::{AsRawFd, FromRawFd, OwnedFd},
+        unix::{
+            fs::OpenOptionsExt,
+            process::{CommandExt, ExitStatusExt},
+        },
+    },
+    path::Path,
+    process::{Command, ExitStatus, Stdio},
+    sync::mpsc,
+    thread,
+    time::{Duration, Instant},
+};
+
+const NAME: usize = 1;
+const BUFFER: usize = 2;
+const FILTER: usize = 3;
+const SENTINEL: usize = 4;
+const PLIST: usize = 5;
+
+/// How long to sleep between polls of the open connections.
+const POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Status {
+    Connect,
+    Open,
+    Listen,
+    Closed,
+    Failed,
+    Run,
+    /// The subprocess exited with this code
+    Exit(i32),
+    /// The subprocess was killed by this signal
+    Signal(i32),
+}
+
+impl Status {
+    fn symbol(self) -> Object<'static> {
+        match self {
+            Status::Connect => sym::CONNECT.into(),
+            Status::Open => sym::OPEN.into(),
+            Status::Listen => sym::LISTEN.into(),
+            Status::Closed => sym::CLOSED.into(),
+            Status::Failed => sym::FAILED.into(),
+            Status::Run => sym::RUN.into(),
+            Status::Exit(_) => sym::EXIT.into(),
+            Status::Signal(_) => sym::SIGNAL.into(),
+        }
+    }
+
+    fn is_live(self) -> bool {
+        matches!(self, Status::Open | Status::Listen | Status::Connect | Status::Run)
+    }
+
+    /// The status of a subprocess that has finished, and the message for its
+    /// sentinel.
+    fn of_exit(status: ExitStatus) -> (Self, String) {
+        match (status.code(), status.signal()) {
+            (Some(0), _) => (Status::Exit(0), "finished\n".to_owned()),
+            (Some(code), _) => {
+                (Status::Exit(code), format!("exited abnormally with code {code}\n"))
+            }
+            (None, Some(signal)) => {
+                let core = if status.core_dumped() { " (core dumped)" } else { "" };
+                (Status::Signal(signal), format!("{}{core}\n", signal_description(signal)))
+            }
+            (None, None) => (Status::Exit(-1), "exited abnormally\n".to_owned()),
+        }
+    }
+}
+
+/// The description of `signal` used in sentinel messages, like "killed".
+fn signal_description(signal: i32) -> String {
+    // SAFETY: The string is copied before any other call to strsignal
+    let desc = unsafe {
+        let ptr = libc::strsignal(signal);
+        if ptr.is_null() {
+            return format!("signal {signal}");
+        }
+        std::ffi::CStr::from_ptr(ptr).to_string_lossy().into_owned()
+    };
+    let mut chars = desc.chars();
+    match chars.next() {
+        Some(first) => first.to_lowercase().chain(chars).collect(),
+        None => format!("signal {signal}"),
+    }
+}
+
+/// A subprocess started by `make-process`.
+#[derive(Debug)]
+struct Subprocess {
+    child: std::process::Child,
+    /// Where input for the process is written. This is `None` once EOF has
+    /// been sent through a pipe.
+    input: Option<File>,
+    /// Where the output of the process is read from. This is `None` once it
+    /// has reached EOF.
+    output: Option<File>,
+    /// The name of the pseudo-terminal, if the process has one. `input` is the
+    /// master side of it.
+    tty: Option<String>,
+}
+
+#[derive(Debug)]
+enum Connection {
+    /// A non-blocking connect that has not completed yet.
+    Pending(mpsc::Receiver<std::io::Result<TcpStream>>),
+    Stream(TcpStream),
+    Listener(TcpListener),
+    Child(Subprocess),
+    None,
+}
+
+#[derive(Debug)]
+pub(crate) struct Process {
+    conn: Connection,
+    status: Status,
+    host: String,
+    service: u16,
+    /// The process ID of a subprocess
+    pid: Option<u32>,
+    /// Messages for the sentinel that have not been delivered yet.
+    events: Vec<String>,
+    decoder: StreamDecoder,
+    encoding: CodingSystem,
+}
+
+impl Process {
+    fn new(conn: Connection, status: Status, host: &str, service: u16) -> Self {
+        Self {
+            conn,
+            status,
+            host: host.to_owned(),
+            service,
+            pid: None,
+            events: Vec::new(),
+            decoder: StreamDecoder::new(CodingSystem::UTF_8),
+            encoding: CodingSystem::UTF_8,
+        }
+    }
+
+    fn set_coding(&mut self, coding: Option<(CodingSystem, CodingSystem)>) {
+        if let Some((decoding, encoding)) = coding {
+            self.decoder.coding = decoding;
+            self.encoding = encoding;
+        }
+    }
+}
+
+/// The OS state of the processes of an environment, keyed by name.
+pub(crate) type ProcessMap = HashMap<String, Process>;
+
+/// Something that happened on a connection that needs to be reported back to
+/// lisp via a filter or sentinel.
+enum Event {
+    Output(String, String),
+    Status(String, String),
+    Accept {
+        server: String,
+        client: String,
+        host: String,
+    },
+}
+
+fn as_process<'ob>(obj: Object<'ob>) -> Result<&'ob Record> {
+    match obj.untag() {
+        ObjectType::Record(rec) if rec.first().is_some_and(|x| x.get() == sym::PROCESS) => Ok(rec),
+        _ => Err(TypeError::new(Type::Process, obj).into()),
+    }
+}
+
+fn process_name_of(process: &Record) -> Result<String> {
+    let name: &str = process[NAME].get().try_into()?;
+    Ok(name.to_owned())
+}
+
+/// Find a live process object by name or object.
+fn resolve_process<'ob>(
+    process: Object<'ob>,
+    env: &Rt<Env>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    match process.untag() {
+        ObjectType::String(name) => match find_process(name, env, cx) {
+            Some(x) => Ok(x),
+            None => bail!("Process {name} does not exist"),
+        },
+        _ => {
+            as_process(process)?;
+            Ok(process)
+        }
+    }
+}
+
+fn find_process<'ob>(name: &str, env: &Rt<Env>, cx: &'ob Context) -> Option<Object<'ob>> {
+    env.processes
+        .iter()
+        .map(|x| x.bind(cx))
+        .find(|x| as_process(*x).is_ok_and(|p| process_name_of(p).is_ok_and(|n| n == name)))
+}
+
+fn unique_process_name(name: &str, env: &Rt<Env>, cx: &Context) -> String {
+    if find_process(name, env, cx).is_none() {
+        return name.to_owned();
+    }
+    (2..)
+        .map(|i| format!("{name}<{i}>"))
+        .find(|x| find_process(x, env, cx).is_none())
+        .unwrap()
+}
+
+fn plist_get<'ob>(plist: &[Object<'ob>], key: Object) -> Object<'ob> {
+    plist
+        .chunks(2)
+        .find(|x| x[0] == key)
+        .and_then(|x| x.get(1).copied())
+        .unwrap_or(NIL)
+}
+
+fn parse_service(service: Object) -> Result<u16> {
+    match service.untag() {
+        ObjectType::Int(x) => Ok(u16::try_from(x)?),
+        ObjectType::String(s) => Ok(s.parse()?),
+        ObjectType::Symbol(s) if s == sym::TRUE => Ok(0),
+        _ => Err(TypeError::new(Type::Int, service).into()),
+    }
+}
+
+fn new_process_object<'ob>(
+    name: &str,
+    buffer: Object<'ob>,
+    filter: Object<'ob>,
+    sentinel: Object<'ob>,
+    plist: Object<'ob>,
+    cx: &'ob Context,
+) -> Object<'ob> {
+    let mut record = cx.vec_with_capacity(PLIST + 1);
+    record.extend([sym::PROCESS.into(), cx.add(name), buffer, filter, sentinel, plist]);
+    RecordBuilder(record).into_obj(cx).into()
+}
+
+/// The `:buffer` argument of a process, which is created if it is a name.
+fn buffer_arg<'ob>(args: &[Object<'ob>], cx: &'ob Context) -> Result<Object<'ob>> {
+    let buffer = plist_get(args, sym::KW_BUFFER.into());
+    match buffer.untag() {
+        ObjectType::NIL | ObjectType::Buffer(_) => Ok(buffer),
+        _ => crate::buffer::get_buffer_create(buffer, None, cx),
+    }
+}
+
+/// The `:coding` argument of a process, as the coding systems for decoding
+/// and encoding. It is either one coding system for both or a cons of them.
+fn coding_arg(args: &[Object]) -> Result<Option<(CodingSystem, CodingSystem)>> {
+    let coding = plist_get(args, sym::KW_CODING.into());
+    Ok(match coding.untag() {
+        ObjectType::NIL => None,
+        ObjectType::Cons(cons) => {
+            Some((CodingSystem::from_obj(cons.car())?, CodingSystem::from_obj(cons.cdr())?))
+        }
+        _ => {
+            let coding = CodingSystem::from_obj(coding)?;
+            Some((coding, coding))
+        }
+    })
+}
+
+#[defun]
+fn make_network_process<'ob>(
+    args: ArgSlice,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    let args: Vec<Object> = Rt::bind_slice(env.stack.arg_slice(args), cx).to_vec();
+    let args = args.as_slice();
+    let name: &str = plist_get(args, sym::KW_NAME.into()).try_into()?;
+    let host = match plist_get(args, sym::KW_HOST.into()).untag() {
+        ObjectType::NIL => "localhost",
+        ObjectType::Symbol(s) if s.name() == "local" => "127.0.0.1",
+        ObjectType::String(s) => s.as_ref(),
+        x => bail!(TypeError::new(Type::String, x)),
+    };
+    let service = parse_service(plist_get(args, sym::KW_SERVICE.into()))?;
+    let is_server = !plist_get(args, sym::KW_SERVER.into()).is_nil();
+    let nowait = !plist_get(args, sym::KW_NOWAIT.into()).is_nil();
+    let buffer = buffer_arg(args, cx)?;
+    let coding = coding_arg(args)?;
+
+    let (conn, status) = if is_server {
+        let listener = TcpListener::bind((host, service))?;
+        listener.set_nonblocking(true)?;
+        (Connection::Listener(listener), Status::Listen)
+    } else if nowait {
+        let addrs: Vec<_> = (host, service).to_socket_addrs()?.collect();
+        let (send, recv) = mpsc::channel();
+        thread::spawn(move || {
+            let stream = TcpStream::connect(addrs.as_slice());
+            let _ = send.send(stream);
+        });
+        (Connection::Pending(recv), Status::Connect)
+    } else {
+        let stream = TcpStream::connect((host, service))?;
+        stream.set_nonblocking(true)?;
+        (Connection::Stream(stream), Status::Open)
+    };
+
+    let name = unique_process_name(name, env, cx);
+    let process = new_process_object(
+        &name,
+        buffer,
+        plist_get(args, sym::KW_FILTER.into()),
+        plist_get(args, sym::KW_SENTINEL.into()),
+        slice_into_list(args, None, cx),
+        cx,
+    );
+    let mut proc = Process::new(conn, status, host, service);
+    proc.set_coding(coding);
+    env.process_state.insert(name, proc);
+    env.processes.push(process);
+    Ok(process)
+}
+
+/// Set or clear `O_NONBLOCK` on `fd`.
+fn set_nonblocking(fd: &impl AsRawFd, nonblocking: bool) -> std::io::Result<()> {
+    let fd = fd.as_raw_fd();
+    unsafe {
+        let flags = libc::fcntl(fd, libc::F_GETFL);
+        if flags < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let flags = if nonblocking { flags | libc::O_NONBLOCK } else { flags & !libc::O_NONBLOCK };
+        if libc::fcntl(fd, libc::F_SETFL, flags) < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Open a new pseudo-terminal and return its master side and the name of
+/// its slave side.
+fn open_pty() -> Result<(OwnedFd, String)> {
+    let master = unsafe { libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY) };
+    ensure!(master >= 0, "Failed to open a pty: {}", std::io::Error::last_os_error());
+    // SAFETY: posix_openpt returned a new file descriptor that nothing else
+    // owns
+    let master = unsafe { OwnedFd::from_raw_fd(master) };
+    let mut name = [0; 128];
+    let fd = master.as_raw_fd();
+    let failed = unsafe {
+        libc::grantpt(fd) != 0
+            || libc::unlockpt(fd) != 0
+            || libc::ptsname_r(fd, name.as_mut_ptr(), name.len()) != 0
+    };
+    ensure!(!failed, "Failed to set up a pty: {}", std::io::Error::last_os_error());
+    let name = unsafe { std::ffi::CStr::from_ptr(name.as_ptr()) };
+    Ok((master, name.to_string_lossy().into_owned()))
+}
+
+/// Start `command` as a subprocess that reads and writes through a
+/// pseudo-terminal if `pty` is true, or through pipes otherwise. Its stderr
+/// goes to the same place as its stdout.
+fn spawn(
+    command: &[String],
+    pty: bool,
+    dir: Option<&Path>,
+    environment: Vec<(String, Option<String>)>,
+) -> Result<Subprocess> {
+    let Some((program, args)) = command.split_first() else { bail!("No program to run") };
+    let mut cmd = Command::new(program);
+    cmd.args(args);
+    if let Some(dir) = dir {
+        cmd.current_dir(dir);
+    }
+    for (name, value) in environment {
+        match value {
+            Some(value) => cmd.env(name, value),
+            None => cmd.env_remove(name),
+        };
+    }
+    let (input, output, tty) = if pty {
+        let (master, tty) = open_pty()?;
+        let slave = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NOCTTY)
+            .open(&tty)?;
+        cmd.stdin(slave.try_clone()?).stdout(slave.try_clone()?).stderr(slave);
+        // SAFETY: setsid and ioctl are async-signal-safe
+        unsafe {
+            cmd.pre_exec(|| {
+                // Make the pty the controlling terminal of a new session
+                if libc::setsid() < 0 || libc::ioctl(0, libc::TIOCSCTTY, 0) < 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+        let input = File::from(master.try_clone()?);
+        (Some(input), File::from(master), Some(tty))
+    } else {
+        let (reader, writer) = std::io::pipe()?;
+        cmd.stdin(Stdio::piped()).stdout(writer.try_clone()?).stderr(writer);
+        // SAFETY: setsid is async-signal-safe
+        unsafe {
+            cmd.pre_exec(|| {
+                if libc::setsid() < 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+        (None, File::from(OwnedFd::from(reader)), None)
+    };
+    let mut child = cmd.spawn()?;
+    // Close our copies of the child's side, so that we see EOF when it exits
+    drop(cmd);
+    let input = input.or_else(|| child.stdin.take().map(|x| File::from(OwnedFd::from(x))));
+    set_nonblocking(&output, true)?;
+    Ok(Subprocess { child, input, output: Some(output), tty })
+}
+
+/// Start a subprocess and return a process object for it.
+///
+/// The arguments are keywords and values:
+///
+/// :name NAME -- the name of the process, which is made unique.
+///
+/// :buffer BUFFER -- the buffer or buffer name that output is inserted into.
+///
+/// :command COMMAND -- a list of the program to run and its arguments.
+///
+/// :connection-type TYPE -- `pty` to talk to the process through a
+/// pseudo-terminal or `pipe` to use pipes. The default comes from
+/// `process-connection-type`.
+///
+/// :coding CODING -- the coding system for output and input, or a cons of
+/// the one used to decode output and the one used to encode input.
+///
+/// :filter FILTER -- the process filter.
+///
+/// :sentinel SENTINEL -- the process sentinel.
+///
+/// The process runs in `default-directory`, with the environment in
+/// `process-environment`.
+#[defun]
+fn make_process<'ob>(args: ArgSlice, env: &mut Rt<Env>, cx: &'ob Context) -> Result<Object<'ob>> {
+    let args: Vec<Object> = Rt::bind_slice(env.stack.arg_slice(args), cx).to_vec();
+    let args = args.as_slice();
+    let name: &str = plist_get(args, sym::KW_NAME.into()).try_into()?;
+    let command: List = plist_get(args, sym::KW_COMMAND.into()).try_into()?;
+    let command = command
+        .elements()
+        .map(|x| Ok(<&str>::try_from(x?)?.to_owned()))
+        .collect::<Result<Vec<_>>>()?;
+    let pty = match plist_get(args, sym::KW_CONNECTION_TYPE.into()).untag() {
+        ObjectType::NIL => {
+            env.vars.get(sym::PROCESS_CONNECTION_TYPE).is_some_and(|x| !x.bind(cx).is_nil())
+        }
+        ObjectType::Symbol(sym::PTY) => true,
+        ObjectType::Symbol(sym::PIPE) => false,
+        x => bail!("Unsupported connection type: {x}"),
+    };
+    let buffer = buffer_arg(args, cx)?;
+    let coding = coding_arg(args)?;
+    let dir = match env.vars.get(sym::DEFAULT_DIRECTORY).map(|x| x.bind(cx).untag()) {
+        Some(ObjectType::String(dir)) if Path::new(dir.as_ref()).is_dir() => {
+            Some(Path::new(dir.as_ref()))
+        }
+        _ => None,
+    };
+    let environment = crate::callproc::environment_overrides(env, cx)?;
+    let subprocess = spawn(&command, pty, dir, environment)?;
+    let pid = subprocess.child.id();
+
+    let name = unique_process_name(name, env, cx);
+    let process = new_process_object(
+        &name,
+        buffer,
+        plist_get(args, sym::KW_FILTER.into()),
+        plist_get(args, sym::KW_SENTINEL.into()),
+        slice_into_list(args, None, cx),
+        cx,
+    );
+    let mut proc = Process::new(Connection::Child(subprocess), Status::Run, "", 0);
+    proc.pid = Some(pid);
+    proc.set_coding(coding);
+    env.process_state.insert(name, proc);
+    env.processes.push(process);
+    Ok(process)
+}
+
+#[defun]
+fn processp(object: Object) -> bool {
+    as_process(object).is_ok()
+}
+
+#[defun]
+fn get_process<'ob>(name: &str, env: &Rt<Env>, cx: &'ob Context) -> Object<'ob> {
+    find_process(name, env, cx).unwrap_or(NIL)
+}
+
+#[defun]
+fn process_list<'ob>(env: &Rt<Env>, cx: &'ob Context) -> Object<'ob> {
+    let procs: Vec<_> = env.processes.iter().map(|x| x.bind(cx)).collect();
+    slice_into_list(&procs, None, cx)
+}
+
+#[defun]
+fn process_name(process: Object) -> Result<String> {
+    process_name_of(as_process(process)?)
+}
+
+#[defun]
+fn process_buffer(process: Object) -> Result<Object> {
+    Ok(as_process(process)?[BUFFER].get())
+}
+
+#[defun]
+fn set_process_buffer<'ob>(process: Object<'ob>, buffer: Object<'ob>) -> Result<Object<'ob>> {
+    as_process(process)?.try_mut()?[BUFFER].set(buffer);
+    Ok(buffer)
+}
+
+#[defun]
+fn process_filter(process: Object) -> Result<Object> {
+    Ok(as_process(process)?[FILTER].get())
+}
+
+#[defun]
+fn set_process_filter<'ob>(process: Object<'ob>, filter: Object<'ob>) -> Result<Object<'ob>> {
+    as_process(process)?.try_mut()?[FILTER].set(filter);
+    Ok(filter)
+}
+
+#[defun]
+fn process_sentinel(process: Object) -> Result<Object> {
+    Ok(as_process(process)?[SENTINEL].get())
+}
+
+#[defun]
+fn set_process_sentinel<'ob>(process: Object<'ob>, sentinel: Object<'ob>) -> Result<Object<'ob>> {
+    as_process(process)?.try_mut()?[SENTINEL].set(sentinel);
+    Ok(sentinel)
+}
+
+#[defun]
+fn process_status<'ob>(
+    process: Object<'ob>,
+    env: &Rt<Env>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    let process = resolve_process(process, env, cx)?;
+    let name = process_name_of(as_process(process)?)?;
+    Ok(env.process_state.get(&name).map_or(sym::CLOSED.into(), |x| x.status.symbol()))
+}
+
+#[defun]
+fn process_live_p(process: Object, env: &Rt<Env>) -> Result<bool> {
+    let name = process_name_of(as_process(process)?)?;
+    Ok(env.process_state.get(&name).is_some_and(|x| x.status.is_live()))
+}
+
+#[defun]
+fn process_contact<'ob>(
+    process: Object<'ob>,
+    env: &Rt<Env>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    let name = process_name_of(as_process(process)?)?;
+    let Some(proc) = env.process_state.get(&name) else { return Ok(NIL) };
+    if proc.pid.is_some() {
+        return Ok(TRUE);
+    }
+    let host = cx.add(proc.host.as_str());
+    Ok(Cons::new(host, Cons::new1(i64::from(proc.service), cx), cx).into())
+}
+
+#[defun]
+fn set_process_coding_system(
+    process: Object,
+    decoding: Option<Object>,
+    encoding: Option<Object>,
+    env: &mut Rt<Env>,
+) -> Result<bool> {
+    let name = process_name_of(as_process(process)?)?;
+    let decoding = CodingSystem::from_obj(decoding.unwrap_or(NIL))?;
+    let encoding = CodingSystem::from_obj(encoding.unwrap_or(NIL))?;
+    let Some(proc) = env.process_state.get_mut(&name) else {
+        bail!("Process {name} is not running")
+    };
+    proc.decoder.coding = decoding;
+    proc.encoding = encoding;
+    Ok(false)
+}
+
+/// Return a cons of the coding systems PROCESS uses to decode its output and
+/// encode its input.
+#[defun]
+fn process_coding_system<'ob>(
+    process: Object,
+    env: &Rt<Env>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    let name = process_name_of(as_process(process)?)?;
+    let Some(proc) = env.process_state.get(&name) else {
+        bail!("Process {name} is not running")
+    };
+    let decoding = intern(&proc.decoder.coding.name(), cx);
+    let encoding = intern(&proc.encoding.name(), cx);
+    Ok(Cons::new(decoding, encoding, cx).into())
+}
+
+#[defun]
+fn process_send_string(
+    process: Object,
+    string: &str,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<bool> {
+    let process = resolve_process(process, env, cx)?;
+    let name = process_name_of(as_process(process)?)?;
+    let Some(proc) = env.process_state.get_mut(&name) else {
+        bail!("Process {name} is not running")
+    };
+    let bytes = proc.encoding.encode(string);
+    match &mut proc.conn {
+        Connection::Stream(stream) => {
+            // The stream is non-blocking, so temporarily switch it back to
+            // write all of the data.
+            stream.set_nonblocking(false)?;
+            let result = stream.write_all(&bytes);
+            stream.set_nonblocking(true)?;
+            result?;
+        }
+        Connection::Child(Subprocess { input: Some(input), .. }) => {
+            write_blocking(input, &bytes)?;
+        }
+        _ => bail!("Process {name} is not running"),
+    }
+    Ok(false)
+}
+
+/// The process object for PROCESS, which may also be a process name or nil
+/// for the process of the current buffer.
+fn process_or_current<'ob>(
+    process: Option<Object<'ob>>,
+    env: &Rt<Env>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    if let Some(process) = process.filter(|x| !x.is_nil()) {
+        return resolve_process(process, env, cx);
+    }
+    let buffer = cx.add(env.current_buffer.get().lisp_buffer(cx));
+    let has_buffer = |x: &Object| as_process(*x).is_ok_and(|p| p[BUFFER].get() == buffer);
+    match env.processes.iter().map(|x| x.bind(cx)).find(has_buffer) {
+        Some(process) => Ok(process),
+        None => bail!("Current buffer has no process"),
+    }
+}
+
+/// Call `func` with the subprocess named `name`.
+fn with_subprocess<T>(
+    name: &str,
+    env: &mut Rt<Env>,
+    func: impl FnOnce(&mut Subprocess) -> Result<T>,
+) -> Result<T> {
+    match env.process_state.get_mut(name).map(|x| &mut x.conn) {
+        Some(Connection::Child(sub)) => func(sub),
+        _ => bail!("Process {name} is not active"),
+    }
+}
+
+/// Write all of `bytes` to the non-blocking `file`.
+fn write_blocking(file: &mut File, bytes: &[u8]) -> Result<()> {
+    // A pty shares its non-blocking flag with the output side
+    set_nonblocking(file, false)?;
+    let result = file.write_all(bytes);
+    set_nonblocking(file, true)?;
+    Ok(result?)
+}
+
+/// Send `signal` to the process group of `sub`. If `current_group` is true
+/// and the process has a pty, the signal goes to the foreground process group
+/// of the terminal instead, as if the character for it had been typed.
+fn send_signal(sub: &Subprocess, current_group: bool, signal: libc::c_int) -> Result<()> {
+    let foreground = match &sub.input {
+        Some(input) if current_group && sub.tty.is_some() => unsafe {
+            libc::tcgetpgrp(input.as_raw_fd())
+        },
+        _ => -1,
+    };
+    let group = if foreground > 0 { foreground } else { sub.child.id() as libc::pid_t };
+    if unsafe { libc::kill(-group, signal) } < 0 {
+        bail!("Failed to signal process: {}", std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn signal_process_arg<'ob>(
+    process: Option<Object<'ob>>,
+    current_group: Option<Object>,
+    signal: libc::c_int,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    let process = process_or_current(process, env, cx)?;
+    let name = process_name_of(as_process(process)?)?;
+    let current_group = current_group.is_some_and(|x| !x.is_nil());
+    with_subprocess(&name, env, |sub| send_signal(sub, current_group, signal))?;
+    Ok(process)
+}
+
+/// Interrupt PROCESS by sending it SIGINT. PROCESS may be a process, the
+/// name of one, or nil for the process of the current buffer. If
+/// CURRENT-GROUP is non-nil and PROCESS has a pty, the signal goes to the
+/// job in the foreground of the terminal instead of the process itself.
+#[defun]
+fn interrupt_process<'ob>(
+    process: Option<Object<'ob>>,
+    current_group: Option<Object>,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    signal_process_arg(process, current_group, libc::SIGINT, env, cx)
+}
+
+/// Kill PROCESS by sending it SIGKILL. See `interrupt-process` for the
+/// meaning of the arguments.
+#[defun]
+fn kill_process<'ob>(
+    process: Option<Object<'ob>>,
+    current_group: Option<Object>,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    signal_process_arg(process, current_group, libc::SIGKILL, env, cx)
+}
+
+/// Send SIGQUIT to PROCESS. See `interrupt-process` for the meaning of the
+/// arguments.
+#[defun]
+fn quit_process<'ob>(
+    process: Option<Object<'ob>>,
+    current_group: Option<Object>,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    signal_process_arg(process, current_group, libc::SIGQUIT, env, cx)
+}
+
+/// Make PROCESS see end-of-file in its input. A pipe is closed, so no more
+/// input can be sent, while the EOF character is sent through a pty.
+#[defun]
+fn process_send_eof<'ob>(
+    process: Option<Object<'ob>>,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    let process = process_or_current(process, env, cx)?;
+    let name = process_name_of(as_process(process)?)?;
+    with_subprocess(&name, env, |sub| match (&mut sub.input, &sub.tty) {
+        (Some(input), Some(_)) => write_blocking(input, b"\x04"),
+        (input, None) => {
+            *input = None;
+            Ok(())
+        }
+        (None, Some(_)) => Ok(()),
+    })?;
+    Ok(process)
+}
+
+/// Tell PROCESS that its terminal is HEIGHT lines by WIDTH columns. Return t
+/// on success, or nil if PROCESS does not have a pty.
+#[defun]
+fn set_process_window_size(
+    process: Object,
+    height: usize,
+    width: usize,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<bool> {
+    let name = process_name_of(as_process(resolve_process(process, env, cx)?)?)?;
+    with_subprocess(&name, env, |sub| {
+        let (Some(input), Some(_)) = (&sub.input, &sub.tty) else { return Ok(false) };
+        let size = libc::winsize {
+            ws_row: u16::try_from(height)?,
+            ws_col: u16::try_from(width)?,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        };
+        if unsafe { libc::ioctl(input.as_raw_fd(), libc::TIOCSWINSZ, &size) } < 0 {
+            bail!("Failed to set window size: {}", std::io::Error::last_os_error());
+        }
+        Ok(true)
+    })
+}
+
+/// Return the name of the terminal PROCESS uses, or nil if it talks to
+/// rune through pipes.
+#[defun]
+fn process_tty_name(
+    process: Object,
+    _stream: Option<Object>,
+    env: &Rt<Env>,
+) -> Result<Option<String>> {
+    let name = process_name_of(as_process(process)?)?;
+    Ok(match env.process_state.get(&name).map(|x| &x.conn) {
+        Some(Connection::Child(sub)) => sub.tty.clone(),
+        _ => None,
+    })
+}
+
+/// Return the process ID of PROCESS, or nil if it is a network connection.
+#[defun]
+fn process_id(process: Object, env: &Rt<Env>) -> Result<Option<u32>> {
+    let name = process_name_of(as_process(process)?)?;
+    Ok(env.process_state.get(&name).and_then(|x| x.pid))
+}
+
+/// Return the exit code of PROCESS, or the number of the signal that killed
+/// it. Return 0 if it is still running.
+#[defun]
+fn process_exit_status(process: Object, env: &Rt<Env>) -> Result<i64> {
+    let name = process_name_of(as_process(process)?)?;
+    Ok(match env.process_state.get(&name).map(|x| x.status) {
+        Some(Status::Exit(code) | Status::Signal(code)) => i64::from(code),
+        _ => 0,
+    })
+}
+
+/// Delete PROCESS, closing its connection or killing it. If it was live,
+/// its sentinel is called with "deleted\n".
+#[defun]
+fn delete_process(process: &Rto<Object>, env: &mut Rt<Env>, cx: &mut Context) -> Result<bool> {
+    let process = resolve_process(process.bind(cx), env, cx)?;
+    let name = process_name_of(as_process(process)?)?;
+    let idx = env.processes.iter().position(|x| x.bind(cx) == process);
+    if let Some(idx) = idx {
+        env.processes.swap_remove(idx);
+    }
+    let Some(mut proc) = env.process_state.remove(&name) else { return Ok(false) };
+    match &mut proc.conn {
+        Connection::Stream(stream) => {
+            let _ = stream.shutdown(std::net::Shutdown::Both);
+        }
+        Connection::Child(sub) => {
+            let _ = send_signal(sub, false, libc::SIGKILL);
+            let _ = sub.child.wait();
+        }
+        _ => {}
+    }
+    if proc.status.is_live() {
+        root!(process, cx);
+        run_sentinel(process, "deleted\n", env, cx)?;
+    }
+    Ok(false)
+}
+
+/// How the output read from processes is split up before it is passed to
+/// filters.
+#[derive(Debug, Clone, Copy)]
+struct ReadPolicy {
+    /// The most bytes read at once, from `read-process-output-max`.
+    max: usize,
+    /// Whether small reads are combined into chunks of up to `max` bytes, from
+    /// `process-adaptive-read-buffering`. Otherwise every read is passed to
+    /// the filter by itself.
+    adaptive: bool,
+}
+
+impl ReadPolicy {
+    fn from_env(env: &Rt<Env>, cx: &Context) -> Self {
+        let max = match env.vars.get(sym::READ_PROCESS_OUTPUT_MAX).map(|x| x.bind(cx).untag()) {
+            Some(ObjectType::Int(x)) => usize::try_from(x).unwrap_or(0),
+            _ => 0,
+        };
+        let adaptive = env
+            .vars
+            .get(sym::PROCESS_ADAPTIVE_READ_BUFFERING)
+            .is_some_and(|x| !x.bind(cx).is_nil());
+        Self { max: max.max(READ_CHUNK_MIN), adaptive }
+    }
+}
+
+/// The smallest chunk of output read at once, however small
+/// `read-process-output-max` is.
+const READ_CHUNK_MIN: usize = 64;
+
+/// Decode a chunk of output from a process. Bytes that are not decoded are
+/// inserted as raw characters.
+fn decode_output(decoded: Decoded) -> String {
+    match decoded {
+        Decoded::Text(text) => text,
+        Decoded::Bytes(bytes) => bytes.into_iter().map(char::from).collect(),
+    }
+}
+
+/// Read everything that is available from the non-blocking `reader`, split
+/// into chunks according to `policy`. Also returns true if it reached EOF or
+/// failed.
+fn read_available(reader: &mut impl Read, policy: ReadPolicy) -> (Vec<Vec<u8>>, bool) {
+    let mut chunks: Vec<Vec<u8>> = Vec::new();
+    let mut buf = vec![0; policy.max];
+    let closed = loop {
+        match reader.read(&mut buf) {
+            Ok(0) => break true,
+            Ok(n) => match chunks.last_mut() {
+                Some(last) if policy.adaptive && last.len() < policy.max => {
+                    let fits = n.min(policy.max - last.len());
+                    last.extend_from_slice(&buf[..fits]);
+                    if fits < n {
+                        chunks.push(buf[fits..n].to_vec());
+                    }
+                }
+                _ => chunks.push(buf[..n].to_vec()),
+            },
+            Err(e) if e.kind() == ErrorKind::WouldBlock => break false,
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            // Reading a pty whose other side is closed fails with EIO
+            Err(_) => break true,
+        }
+    };
+    (chunks, closed)
+}
+
+/// Decode the `chunks` of output read from the process `name` and queue them
+/// for its filter. Characters split across chunks are held back in `decoder`
+/// until the rest of them arrives, or until `closed`.
+fn push_output(
+    name: &str,
+    decoder: &mut StreamDecoder,
+    chunks: Vec<Vec<u8>>,
+    closed: bool,
+    events: &mut Vec<Event>,
+) {
+    let mut decoded: Vec<_> = chunks.iter().map(|x| decode_output(decoder.decode(x))).collect();
+    if closed {
+        decoded.push(decode_output(decoder.finish()));
+    }
+    for output in decoded.into_iter().filter(|x| !x.is_empty()) {
+        events.push(Event::Output(name.to_owned(), output));
+    }
+}
+
+/// Poll the connections in `map` once without blocking and collect what
+/// happened. If `only` is given, just the process with that name is polled.
+fn poll_connections(map: &mut ProcessMap, policy: ReadPolicy, only: Option<&str>) -> Vec<Event> {
+    let mut events = Vec::new();
+    let mut accepted = Vec::new();
+    for (name, proc) in map.iter_mut() {
+        if only.is_some_and(|x| x != name) {
+            continue;
+        }
+        for msg in proc.events.drain(..) {
+            events.push(Event::Status(name.clone(), msg));
+        }
+        match &mut proc.conn {
+            Connection::Pending(recv) => match recv.try_recv() {
+                Ok(Ok(stream)) => {
+                    proc.status = Status::Open;
+                    if stream.set_nonblocking(true).is_ok() {
+                        proc.conn = Connection::Stream(stream);
+                        events.push(Event::Status(name.clone(), "open\n".into()));
+                    }
+                }
+                Ok(Err(e)) => {
+                    proc.status = Status::Failed;
+                    proc.conn = Connection::None;
+                    let msg = format!("failed with code {}\n", e.raw_os_error().unwrap_or(1));
+                    events.push(Event::Status(name.clone(), msg));
+                }
+                Err(mpsc::TryRecvError::Empty) => {}
+                Err(mpsc::TryRecvError::Disconnected) => {
+                    proc.status = Status::Failed;
+                    proc.conn = Connection::None;
+                }
+            },
+            Connection::Stream(stream) => {
+                let (chunks, closed) = read_available(stream, policy);
+                push_output(name, &mut proc.decoder, chunks, closed, &mut events);
+                if closed {
+                    proc.status = Status::Closed;
+                    proc.conn = Connection::None;
+                    let msg = "connection broken by remote peer\n".to_owned();
+                    events.push(Event::Status(name.clone(), msg));
+                }
+            }
+            Connection::Listener(listener) => {
+                while let Ok((stream, addr)) = listener.accept() {
+                    if stream.set_nonblocking(true).is_err() {
+                        continue;
+                    }
+                    let host = addr.ip().to_string();
+                    let client = format!("{name} <{host}:{}>", addr.port());
+                    let mut new =
+                        Process::new(Connection::Stream(stream), Status::Open, &host, addr.port());
+                    new.events.push(format!("open from {host}\n"));
+                    accepted.push((client.clone(), new));
+                    events.push(Event::Accept { server: name.clone(), client, host });
+                }
+            }
+            Connection::Child(sub) => {
+                // Check for exit first, so that all of the output is read
+                // before the process is reported as finished
+                let exit = sub.child.try_wait().ok().flatten();
+                if let Some(file) = &mut sub.output {
+                    let (chunks, closed) = read_available(file, policy);
+                    if closed {
+                        sub.output = None;
+                    }
+                    let closed = closed || exit.is_some();
+                    push_output(name, &mut proc.decoder, chunks, closed, &mut events);
+                }
+                if let Some(exit) = exit {
+                    let (status, msg) = Status::of_exit(exit);
+                    proc.status = status;
+                    proc.conn = Connection::None;
+                    events.push(Event::Status(name.clone(), msg));
+                }
+            }
+            Connection::None => {}
+        }
+    }
+    map.extend(accepted);
+    events
+}
+
+/// Create the lisp object for a connection accepted by a server. It
+/// inherits the buffer, filter, and sentinel of the server process, or has
+/// none if the server has been deleted.
+fn accept_client(
+    server: &str,
+    client: &str,
+    host: &str,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<()> {
+    let (buffer, filter, sentinel) = match find_process(server, env, cx) {
+        Some(server) => {
+            let server = as_process(server)?;
+            (server[BUFFER].get(), server[FILTER].get(), server[SENTINEL].get())
+        }
+        None => (NIL, NIL, NIL),
+    };
+    let plist = list![sym::KW_HOST, cx.add(host), sym::KW_SERVER, cx.add(server); cx];
+    let process = new_process_object(client, buffer, filter, sentinel, plist, cx);
+    env.processes.push(process);
+    Ok(())
+}
+
+/// Pass `output` to the filter of `process`.
+fn run_filter(
+    process: &Rto<Object>,
+    output: &str,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<()> {
+    let filter = as_process(process.bind(cx))?[FILTER].get();
+    if filter.is_nil() {
+        // The default filter inserts the output into the process buffer
+        if let ObjectType::Buffer(buffer) = as_process(process.bind(cx))?[BUFFER].get().untag() {
+            let output = cx.add(output);
+            env.with_buffer_mut(buffer, |buf| buf.insert(output))??;
+        }
+        return Ok(());
+    }
+    let filter: Function = filter.try_into()?;
+    root!(filter, cx);
+    let output = cx.add(output);
+    call!(filter, process, output; env, cx)?;
+    Ok(())
+}
+
+/// Call the sentinel of `process` with `msg`.
+fn run_sentinel(
+    process: &Rto<Object>,
+    msg: &str,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<()> {
+    let sentinel = as_process(process.bind(cx))?[SENTINEL].get();
+    if sentinel.is_nil() {
+        return Ok(());
+    }
+    let sentinel: Function = sentinel.try_into()?;
+    root!(sentinel, cx);
+    let msg = cx.add(msg);
+    call!(sentinel, process, msg; env, cx)?;
+    Ok(())
+}
+
+fn deliver_output(name: &str, output: &str, env: &mut Rt<Env>, cx: &mut Context) -> Result<()> {
+    let Some(process) = find_process(name, env, cx) else { return Ok(()) };
+    root!(process, cx);
+    run_filter(process, output, env, cx)
+}
+
+fn deliver_status(name: &str, msg: &str, env: &mut Rt<Env>, cx: &mut Context) -> Result<()> {
+    let Some(process) = find_process(name, env, cx) else { return Ok(()) };
+    root!(process, cx);
+    run_sentinel(process, msg, env, cx)
+}
+
+/// Dispatch all pending events, including file notifications. Returns true if
+/// any output was received from `process` (or from any process if `process` is
+/// `None`). If `just_this_one` is true, only `process` is polled.
+///
+/// An error from a filter or sentinel doesn't stop the rest of the events
+/// from being delivered. The first one is returned after all of them are.
+fn dispatch_events(
+    process: Option<&str>,
+    just_this_one: bool,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<bool> {
+    crate::filenotify::dispatch_file_events(env, cx)?;
+    let policy = ReadPolicy::from_env(env, cx);
+    let only = if just_this_one { process } else { None };
+    let mut got_output = false;
+    let mut result = Ok(());
+    for event in poll_connections(&mut env.process_state, policy, only) {
+        let delivered = match event {
+            Event::Output(name, output) => {
+                got_output |= process.is_none_or(|x| x == name);
+                deliver_output(&name, &output, env, cx)
+            }
+            Event::Status(name, msg) => deliver_status(&name, &msg, env, cx),
+            Event::Accept { server, client, host } => {
+                accept_client(&server, &client, &host, env, cx)
+            }
+        };
+        if result.is_ok() {
+            result = delivered;
+        }
+    }
+    result.map(|()| got_output)
+}
+
+fn timeout_duration(
+    seconds: Option<&Rto<Object>>,
+    millisec: Option<&Rto<Object>>,
+    cx: &Context,
+) -> Result<Option<Duration>> {
+    let secs = match seconds.map(|x| x.bind(cx).untag()) {
+        None => None,
+        Some(ObjectType::Int(x)) => Some(x as f64),
+        Some(ObjectType::Float(x)) => Some(**x),
+        Some(x) => bail!(TypeError::new(Type::Number, x)),
+    };
+    let millis = match millisec.map(|x| x.bind(cx).untag()) {
+        None => 0.0,
+        Some(ObjectType::Int(x)) => x as f64 / 1000.0,
+        Some(x) => bail!(TypeError::new(Type::Int, x)),
+    };
+    Ok(secs.map(|x| Duration::from_secs_f64((x + millis).max(0.0))))
+}
+
+#[defun]
+fn accept_process_output(
+    process: Option<&Rto<Object>>,
+    seconds: Option<&Rto<Object>>,
+    millisec: Option<&Rto<Object>>,
+    just_this_one: Option<&Rto<Object>>,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<bool> {
+    let name = match process {
+        Some(x) => Some(process_name_of(as_process(x.bind(cx))?)?),
+        None => None,
+    };
+    let only_this = just_this_one.is_some_and(|x| !x.bind(cx).is_nil());
+    ensure!(!only_this || name.is_some(), "just-this-one requires a process");
+    let timeout = timeout_duration(seconds, millisec, cx)?;
+    let start = Instant::now();
+    loop {
+        let got_output = dispatch_events(name.as_deref(), only_this, env, cx)?;
+        if got_output {
+            return Ok(true);
+        }
+        if let Some(name) = &name {
+            let live = env.process_state.get(name).is_some_and(|x| x.status.is_live());
+            if !live {
+                return Ok(false);
+            }
+        }
+        match timeout {
+            Some(timeout) if start.elapsed() >= timeout => return Ok(false),
+            None if name.is_none() => return Ok(false),
+            _ => thread::sleep(POLL_INTERVAL),
+        }
+    }
+}
+
+defsym!(PROCESS);
+defsym!(CONNECT);
+defsym!(OPEN);
+defsym!(LISTEN);
+defsym!(CLOSED);
+defsym!(FAILED);
+defsym!(KW_NAME);
+defsym!(KW_HOST);
+defsym!(KW_SERVICE);
+defsym!(KW_SERVER);
+defsym!(KW_NOWAIT);
+defsym!(KW_BUFFER);
+defsym!(KW_FILTER);
+defsym!(KW_SENTINEL);
+defsym!(KW_COMMAND);
+defsym!(KW_CONNECTION_TYPE);
+defsym!(RUN);
+defsym!(EXIT);
+defsym!(PTY);
+defsym!(PIPE);
+// Non-nil means `make-process` talks to subprocesses through a pty by
+// default instead of through pipes.
+defvar_bool!(PROCESS_CONNECTION_TYPE, true);
+defsym!(KW_CODING);
+// The most bytes of output read from a process at once.
+defvar!(READ_PROCESS_OUTPUT_MAX, 4096);
+// Non-nil means output that arrives in small pieces is combined before it is
+// passed to process filters.
+defvar_bool!(PROCESS_ADAPTIVE_READ_BUFFERING, true);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::gc::RootSet;
+
+    #[test]
+    fn test_network_client_server() {
+        sym::init_symbols();
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, new(Env), cx);
+        env.stack.push(Object::from(sym::KW_NAME));
+        env.stack.push(cx.add("test-server"));
+        env.stack.push(Object::from(sym::KW_SERVICE));
+        env.stack.push(TRUE);
+        env.stack.push(Object::from(sym::KW_SERVER));
+        env.stack.push(TRUE);
+        let server = make_network_process(ArgSlice::new(6), env, cx).unwrap();
+        assert_eq!(process_status(server, env, cx).unwrap(), sym::LISTEN);
+        let port = {
+            let Connection::Listener(l) = &env.process_state["test-server"].conn else {
+                unreachable!()
+            };
+            l.local_addr().unwrap().port()
+        };
+        env.stack.push(Object::from(sym::KW_NAME));
+        env.stack.push(cx.add("test-client"));
+        env.stack.push(Object::from(sym::KW_HOST));
+        env.stack.push(cx.add("127.0.0.1"));
+        env.stack.push(Object::from(sym::KW_SERVICE));
+        env.stack.push(i64::from(port));
+        let client = make_network_process(ArgSlice::new(6), env, cx).unwrap();
+        assert_eq!(process_status(client, env, cx).unwrap(), sym::OPEN);
+        root!(client, cx);
+        process_send_string(client.bind(cx), "hello", env, cx).unwrap();
+        // accept the connection and read the data on the server side
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while env.processes.len() < 3 && Instant::now() < deadline {
+            dispatch_events(None, false, env, cx).unwrap();
+        }
+        assert_eq!(env.processes.len(), 3);
+        assert!(!process_list(env, cx).is_nil());
+        delete_process(client, env, cx).unwrap();
+        assert!(get_process("test-client", env, cx).is_nil());
+        assert!(!env.process_state.contains_key("test-client"));
+    }
+
+    /// A reader that returns one of `pieces` for each read.
+    struct Pieces(Vec<&'static [u8]>);
+
+    impl Read for Pieces {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            if self.0.is_empty() {
+                return Err(ErrorKind::WouldBlock.into());
+            }
+            let piece = self.0.remove(0);
+            buf[..piece.len()].copy_from_slice(piece);
+            Ok(piece.len())
+        }
+    }
+
+    fn outputs(events: Vec<Event>) -> Vec<String> {
+        events
+            .into_iter()
+            .map(|x| match x {
+                Event::Output(_, output) => output,
+                _ => unreachable!(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_output_split_across_reads() {
+        let bytes = "aλ€b".as_bytes();
+        let policy = ReadPolicy { max: 64, adaptive: false };
+        let mut reader = Pieces(vec![&bytes[..2], &bytes[2..4], &bytes[4..]]);
+        let (chunks, closed) = read_available(&mut reader, policy);
+        assert_eq!(chunks.len(), 3);
+        assert!(!closed);
+        let mut decoder = StreamDecoder::new(CodingSystem::UTF_8);
+        let mut events = Vec::new();
+        push_output("test", &mut decoder, chunks, false, &mut events);
+        assert_eq!(outputs(events), ["a", "λ", "€b"]);
+
+        // A character that is cut off by the end of the output is flushed as
+        // raw bytes when the process closes
+        let mut events = Vec::new();
+        push_output("test", &mut decoder, vec![bytes[..4].to_vec()], true, &mut events);
+        let raw = crate::core::object::raw_byte_char(0xe2);
+        assert_eq!(outputs(events), ["aλ".to_owned(), raw.to_string()]);
+    }
+
+    #[test]
+    fn test_adaptive_read_buffering() {
+        let bytes = "λλλλλλλλ".as_bytes();
+        let pieces = bytes.chunks(3).collect();
+        let policy = ReadPolicy { max: 5, adaptive: true };
+        let (chunks, _) = read_available(&mut Pieces(pieces), policy);
+        assert!(chunks.iter().all(|x| x.len() <= 5));
+        assert_eq!(chunks.concat(), bytes);
+        let mut decoder = StreamDecoder::new(CodingSystem::UTF_8);
+        let mut events = Vec::new();
+        push_output("test", &mut decoder, chunks, false, &mut events);
+        assert_eq!(outputs(events).concat(), "λλλλλλλλ");
+    }
+
+    #[test]
+    fn test_subprocess() {
+        sym::init_symbols();
+        let roots = &RootSet::default();
+        let cx = &mut Context::new(roots);
+        root!(env, new(Env), cx);
+        env.stack.push(Object::from(sym::KW_NAME));
+        env.stack.push(cx.add("test-cat"));
+        env.stack.push(Object::from(sym::KW_COMMAND));
+        env.stack.push(list!["cat"; cx]);
+        env.stack.push(Object::from(sym::KW_CONNECTION_TYPE));
+        env.stack.push(Object::from(sym::PIPE));
+        let process = make_process(ArgSlice::new(6), env, cx).unwrap();
+        assert_eq!(process_status(process, env, cx).unwrap(), sym::RUN);
+        assert!(process_tty_name(process, None, env).unwrap().is_none());
+        assert!(process_id(process, env).unwrap().is_some());
+        root!(process, cx);
+        process_send_string(process.bind(cx), "hello", env, cx).unwrap();
+        process_send_eof(Some(process.bind(cx)), env, cx).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while process_live_p(process.bind(cx), env).unwrap() && Instant::now() < deadline {
+            dispatch_events(None, false, env, cx).unwrap();
+        }
+        assert_eq!(process_status(process.bind(cx), env, cx).unwrap(), sym::EXIT);
+        assert_eq!(process_exit_status(process.bind(cx), env).unwrap(), 0);
+    }
+}
@@ -0,0 +1,264 @@
+//! Input methods.
+//!
+//! This implements the runtime part of quail natively. A package maps
+//! sequences of keys to the text they stand for, and the command loop feeds
+//! typed characters through the input method of the current buffer before
+//! running the commands bound to them.
+use crate::core::{
+    env::{Env, sym},
+    gc::{Context, Rt},
+    object::{NIL, Object, ObjectType, TRUE, int_to_char},
+};
+use anyhow::{Result, bail};
+use rune_core::hashmap::HashMap;
+use rune_macros::defun;
+use std::collections::BTreeMap;
+use std::sync::{LazyLock, Mutex};
+
+defvar_local!(CURRENT_INPUT_METHOD);
+defvar_local!(CURRENT_INPUT_METHOD_TITLE);
+defvar!(DEFAULT_INPUT_METHOD);
+
+#[derive(Debug, Default)]
+pub(crate) struct QuailPackage {
+    title: String,
+    /// The translations of each key sequence. The first one is used when the
+    /// sequence is typed.
+    rules: BTreeMap<String, Vec<String>>,
+}
+
+impl QuailPackage {
+    /// The translations of `keys`, and whether it is the prefix of a longer
+    /// key sequence.
+    fn lookup(&self, keys: &str) -> (Option<&[String]>, bool) {
+        let found = self.rules.get(keys).map(Vec::as_slice);
+        let mut after = self
+            .rules
+            .range::<str, _>((std::ops::Bound::Excluded(keys), std::ops::Bound::Unbounded));
+        let longer = after.next().is_some_and(|(key, _)| key.starts_with(keys));
+        (found, longer)
+    }
+
+    /// The text for the keys in `pending`, or the keys themselves if they are
+    /// not a key sequence of this package.
+    pub(crate) fn flush(&self, pending: &mut String) -> String {
+        let keys = std::mem::take(pending);
+        match self.lookup(&keys).0.and_then(|x| x.first()) {
+            Some(text) => text.clone(),
+            None => keys,
+        }
+    }
+
+    /// Feed `key` to the package, returning the text that is finished.
+    /// `pending` holds the keys typed so far that could still be the start
+    /// of a longer sequence.
+    pub(crate) fn feed(&self, pending: &mut String, key: char) -> String {
+        let mut output = String::new();
+        loop {
+            let keys = format!("{pending}{key}");
+            let (found, longer) = self.lookup(&keys);
+            if longer {
+                *pending = keys;
+            } else if let Some(text) = found.and_then(|x| x.first()) {
+                pending.clear();
+                output.push_str(text);
+            } else if pending.is_empty() {
+                output.push(key);
+            } else {
+                // The key can't extend the pending sequence, so it starts a
+                // new one
+                output += &self.flush(pending);
+                continue;
+            }
+            return output;
+        }
+    }
+}
+
+static PACKAGES: LazyLock<Mutex<HashMap<String, QuailPackage>>> = LazyLock::new(Mutex::default);
+
+/// The name of the package that `quail-defrule` adds rules to.
+static CURRENT_PACKAGE: Mutex<Option<String>> = Mutex::new(None);
+
+/// Call `func` with the input method of the current buffer, if it has one.
+pub(crate) fn with_input_method<T>(
+    env: &Rt<Env>,
+    cx: &Context,
+    func: impl FnOnce(&QuailPackage) -> T,
+) -> Option<T> {
+    let name = env.vars.get(sym::CURRENT_INPUT_METHOD)?.bind(cx);
+    let ObjectType::String(name) = name.untag() else { return None };
+    PACKAGES.lock().unwrap().get(&**name).map(func)
+}
+
+/// The translations of a rule, which can be a string, a character, or a
+/// vector of them for a choice of translations.
+fn translations(translation: Object) -> Result<Vec<String>> {
+    match translation.untag() {
+        ObjectType::String(s) => Ok(vec![s.to_string()]),
+        ObjectType::Int(c) => Ok(vec![int_to_char(c)?.to_string()]),
+        ObjectType::Vec(vec) => {
+            let mut choices = Vec::new();
+            for x in vec.iter() {
+                choices.extend(translations(x.get())?);
+            }
+            Ok(choices)
+        }
+        _ => bail!("Invalid quail translation: {translation}"),
+    }
+}
+
+/// Define NAME as an input method for LANGUAGE, whose mode line indicator is
+/// TITLE, and make it the package that `quail-defrule` adds rules to. The
+/// other arguments of quail are accepted but ignored.
+#[defun]
+fn quail_define_package(name: &str, _language: &str, title: Object, _rest: &[Object]) {
+    let title = match title.untag() {
+        ObjectType::String(s) => s.to_string(),
+        _ => name.to_owned(),
+    };
+    let package = QuailPackage { title, rules: BTreeMap::new() };
+    PACKAGES.lock().unwrap().insert(name.to_owned(), package);
+    *CURRENT_PACKAGE.lock().unwrap() = Some(name.to_owned());
+}
+
+/// Make typing KEY insert TRANSLATION in the input method NAME, or the last
+/// defined one if NAME is nil. TRANSLATION is a string, a character, or a
+/// vector of choices. If APPEND is non-nil, the translations are added to
+/// the existing ones of KEY instead of replacing them.
+#[defun]
+fn quail_defrule(
+    key: &str,
+    translation: Object,
+    name: Option<&str>,
+    append: Option<Object>,
+) -> Result<()> {
+    if key.is_empty() {
+        bail!("Empty quail key");
+    }
+    let choices = translations(translation)?;
+    let name = match name {
+        Some(name) => name.to_owned(),
+        None => match &*CURRENT_PACKAGE.lock().unwrap() {
+            Some(name) => name.clone(),
+            None => bail!("No quail package is defined"),
+        },
+    };
+    let mut packages = PACKAGES.lock().unwrap();
+    let Some(package) = packages.get_mut(&name) else { bail!("No quail package {name}") };
+    let rule = package.rules.entry(key.to_owned()).or_default();
+    if append.is_none_or(|x| x.is_nil()) {
+        rule.clear();
+    }
+    rule.extend(choices);
+    Ok(())
+}
+
+/// Look up KEY in the input method NAME, or the last defined one if NAME is
+/// nil. Return the list of translations of KEY, t if KEY is only the start of
+/// longer key sequences, or nil if no key sequence starts with it.
+#[defun]
+fn quail_lookup_key<'ob>(key: &str, name: Option<&str>, cx: &'ob Context) -> Result<Object<'ob>> {
+    let name = match name {
+        Some(name) => name.to_owned(),
+        None => CURRENT_PACKAGE.lock().unwrap().clone().unwrap_or_default(),
+    };
+    let packages = PACKAGES.lock().unwrap();
+    let Some(package) = packages.get(&name) else { bail!("No quail package {name}") };
+    Ok(match package.lookup(key) {
+        (Some(found), _) => {
+            let found: Vec<Object> = found.iter().map(|x| cx.add(x.as_str())).collect();
+            crate::fns::slice_into_list(&found, None, cx)
+        }
+        (None, true) => TRUE,
+        (None, false) => NIL,
+    })
+}
+
+/// Make INPUT-METHOD the input method of the current buffer.
+#[defun]
+fn activate_input_method(input_method: Object, env: &mut Rt<Env>, cx: &Context) -> Result<()> {
+    if input_method.is_nil() {
+        return deactivate_input_method(env);
+    }
+    let ObjectType::String(name) = input_method.untag() else {
+        bail!("Invalid input method: {input_method}");
+    };
+    let title = match PACKAGES.lock().unwrap().get(&**name) {
+        Some(package) => package.title.clone(),
+        None => bail!("Invalid input method: {name}"),
+    };
+    env.set_var(sym::CURRENT_INPUT_METHOD, input_method)?;
+    env.set_var(sym::CURRENT_INPUT_METHOD_TITLE, cx.add(title))
+}
+
+/// Turn off the input method of the current buffer.
+#[defun]
+fn deactivate_input_method(env: &mut Rt<Env>) -> Result<()> {
+    env.set_var(sym::CURRENT_INPUT_METHOD, NIL)?;
+    env.set_var(sym::CURRENT_INPUT_METHOD_TITLE, NIL)
+}
+
+/// Turn the input method of the current buffer off if it is on, and
+/// otherwise turn on `default-input-method`.
+#[defun]
+fn toggle_input_method(env: &mut Rt<Env>, cx: &Context) -> Result<()> {
+    let var = |sym| env.vars.get(sym).map_or(NIL, |x| x.bind(cx));
+    if !var(sym::CURRENT_INPUT_METHOD).is_nil() {
+        return deactivate_input_method(env);
+    }
+    let default = var(sym::DEFAULT_INPUT_METHOD);
+    if default.is_nil() {
+        bail!("No input method is selected");
+    }
+    activate_input_method(default, env, cx)
+}
+
+/// Return the names of the defined input methods.
+#[defun]
+fn quail_package_names<'ob>(cx: &'ob Context) -> Object<'ob> {
+    let mut names: Vec<_> = PACKAGES.lock().unwrap().keys().cloned().collect();
+    names.sort();
+    let names: Vec<Object> = names.into_iter().map(|x| cx.add(x)).collect();
+    crate::fns::slice_into_list(&names, None, cx)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::interpreter::assert_lisp;
+
+    #[test]
+    fn test_feed() {
+        let mut package = QuailPackage::default();
+        for (key, text) in [("a", "α"), ("b", "β"), ("a'", "ά"), ("th", "θ")] {
+            package.rules.insert(key.to_owned(), vec![text.to_owned()]);
+        }
+        let mut pending = String::new();
+        let mut output = String::new();
+        for key in "a'tabthx".chars() {
+            output += &package.feed(&mut pending, key);
+        }
+        assert_eq!(output, "άtαβθx");
+        assert_eq!(package.feed(&mut pending, 'a'), "");
+        assert_eq!(pending, "a");
+        assert_eq!(package.flush(&mut pending), "α");
+        assert!(pending.is_empty());
+    }
+
+    #[test]
+    fn test_quail_rules() {
+        assert_lisp(
+            r#"(progn
+                 (quail-define-package "test-greek" "Greek" "Ω")
+                 (quail-defrule "a" ?α)
+                 (quail-defrule "ka" "κα")
+                 (quail-defrule "ka" ["κά"] nil t)
+                 (list (quail-lookup-key "ka") (quail-lookup-key "k") (quail-lookup-key "z")
+                       (progn (activate-input-method "test-greek")
+                              (list current-input-method current-input-method-title))
+                       (progn (toggle-input-method) current-input-method)))"#,
+            r#"(("κα" "κά") t nil ("test-greek" "Ω") nil)"#,
+        );
+    }
+}
@@ -0,0 +1,325 @@
+//! Radix trees mapping strings to values.
+//!
+//! This is a native version of the tree in radix-tree.el, meant for large
+//! completion and abbrev tables. A tree is a record of the form
+//! `#s(radix-tree ROOT)`. Each node is a vector `[PREFIX CHILDREN VALUE]`,
+//! where PREFIX is the part of the key that the node adds to its parent,
+//! CHILDREN is a list of nodes sorted by prefix, and VALUE is a list holding
+//! the value of the key that ends at the node, or nil if no key ends there.
+//! The children of a node never start with the same character.
+use crate::{
+    core::{
+        env::{Env, sym},
+        error::{Type, TypeError},
+        gc::{Context, Rt, Rto},
+        object::{Function, IntoObject, LispVec, List, NIL, Object, ObjectType, RecordBuilder},
+    },
+    fns::slice_into_list,
+};
+use anyhow::Result;
+use rune_core::macros::{call, list, root};
+use rune_macros::defun;
+
+const ROOT: usize = 1;
+
+const PREFIX: usize = 0;
+const CHILDREN: usize = 1;
+const VALUE: usize = 2;
+
+#[derive(Copy, Clone)]
+struct Node<'ob>(&'ob LispVec);
+
+impl<'ob> Node<'ob> {
+    fn new(prefix: &str, children: Object<'ob>, value: Object<'ob>, cx: &'ob Context) -> Self {
+        Self::from_obj(cx.add(vec![cx.add(prefix), children, value]))
+    }
+
+    fn from_obj(obj: Object<'ob>) -> Self {
+        let ObjectType::Vec(vec) = obj.untag() else { unreachable!("radix tree node was {obj}") };
+        Self(vec)
+    }
+
+    fn prefix(self) -> &'ob str {
+        self.0[PREFIX].get().try_into().expect("radix tree prefix was not a string")
+    }
+
+    fn children(self) -> Vec<Node<'ob>> {
+        let children = List::try_from(self.0[CHILDREN].get()).unwrap_or_default();
+        children.elements().filter_map(Result::ok).map(Self::from_obj).collect()
+    }
+
+    fn value(self) -> Option<Object<'ob>> {
+        match self.0[VALUE].get().untag() {
+            ObjectType::Cons(cons) => Some(cons.car()),
+            _ => None,
+        }
+    }
+
+    fn set(self, idx: usize, obj: Object) -> Result<()> {
+        self.0.try_mut()?[idx].set(obj);
+        Ok(())
+    }
+
+    fn set_children(self, children: &[Node<'ob>], cx: &'ob Context) -> Result<()> {
+        let children: Vec<Object> = children.iter().map(|x| x.0.into()).collect();
+        self.set(CHILDREN, slice_into_list(&children, None, cx))
+    }
+
+    fn set_value(self, value: Option<Object<'ob>>, cx: &'ob Context) -> Result<()> {
+        self.set(VALUE, value.map_or(NIL, |x| list![x; cx]))
+    }
+}
+
+/// The length in bytes of the longest common prefix of A and B.
+fn common_prefix_len(a: &str, b: &str) -> usize {
+    a.char_indices()
+        .zip(b.chars())
+        .find(|((_, x), y)| x != y)
+        .map_or(a.len().min(b.len()), |((idx, _), _)| idx)
+}
+
+/// Set the value of KEY below NODE, or remove KEY if VALUE is None. KEY does
+/// not include the prefix of NODE itself.
+fn insert<'ob>(
+    node: Node<'ob>,
+    key: &str,
+    value: Option<Object<'ob>>,
+    cx: &'ob Context,
+) -> Result<()> {
+    if key.is_empty() {
+        return node.set_value(value, cx);
+    }
+    let mut children = node.children();
+    let first = key.chars().next();
+    match children.iter().position(|x| x.prefix().chars().next() == first) {
+        None => {
+            let Some(value) = value else { return Ok(()) };
+            let leaf = Node::new(key, NIL, list![value; cx], cx);
+            let idx = children.partition_point(|x| x.prefix() < key);
+            children.insert(idx, leaf);
+        }
+        Some(idx) => {
+            let child = children[idx];
+            let prefix = child.prefix();
+            let common = common_prefix_len(prefix, key);
+            if common < prefix.len() {
+                if value.is_none() {
+                    return Ok(());
+                }
+                // Split the child where the keys diverge
+                let rest =
+                    Node::new(&prefix[common..], child.0[CHILDREN].get(), child.0[VALUE].get(), cx);
+                child.set(PREFIX, cx.add(&prefix[..common]))?;
+                child.set(CHILDREN, list![rest.0; cx])?;
+                child.set(VALUE, NIL)?;
+            }
+            insert(child, &key[common..], value, cx)?;
+            let grandchildren = child.children();
+            match (child.value(), grandchildren.as_slice()) {
+                (None, []) => {
+                    children.remove(idx);
+                }
+                // Merge a child that only leads to one other node
+                (None, [only]) => {
+                    let prefix = format!("{}{}", child.prefix(), only.prefix());
+                    child.set(PREFIX, cx.add(prefix))?;
+                    child.set(CHILDREN, only.0[CHILDREN].get())?;
+                    child.set(VALUE, only.0[VALUE].get())?;
+                }
+                _ => return Ok(()),
+            }
+        }
+    }
+    node.set_children(&children, cx)
+}
+
+fn lookup<'ob>(node: Node<'ob>, key: &str) -> Option<Object<'ob>> {
+    if key.is_empty() {
+        return node.value();
+    }
+    let child = node.children().into_iter().find(|x| key.starts_with(x.prefix()))?;
+    lookup(child, &key[child.prefix().len()..])
+}
+
+/// Find the node holding the keys that start with PREFIX. The key leading to
+/// that node is appended to PATH, and may be longer than PREFIX.
+fn find_prefix<'ob>(node: Node<'ob>, prefix: &str, path: &mut String) -> Option<Node<'ob>> {
+    if prefix.is_empty() {
+        return Some(node);
+    }
+    for child in node.children() {
+        let child_prefix = child.prefix();
+        if let Some(rest) = prefix.strip_prefix(child_prefix) {
+            path.push_str(child_prefix);
+            return find_prefix(child, rest, path);
+        } else if child_prefix.starts_with(prefix) {
+            path.push_str(child_prefix);
+            return Some(child);
+        }
+    }
+    None
+}
+
+/// Collect the keys and values below NODE in lexicographic order.
+fn mappings<'ob>(node: Node<'ob>, path: &mut String, out: &mut Vec<(String, Object<'ob>)>) {
+    if let Some(value) = node.value() {
+        out.push((path.clone(), value));
+    }
+    for child in node.children() {
+        let len = path.len();
+        path.push_str(child.prefix());
+        mappings(child, path, out);
+        path.truncate(len);
+    }
+}
+
+fn root_node(tree: Object) -> Result<Node> {
+    match tree.untag() {
+        ObjectType::Record(rec) if rec.first().is_some_and(|x| x.get() == sym::RADIX_TREE) => {
+            Ok(Node::from_obj(rec[ROOT].get()))
+        }
+        _ => Err(TypeError::new(Type::RadixTree, tree).into()),
+    }
+}
+
+/// Return a new empty radix tree.
+#[defun]
+fn make_radix_tree<'ob>(cx: &'ob Context) -> Object<'ob> {
+    let root = Node::new("", NIL, NIL, cx);
+    let mut record = cx.vec_with_capacity(ROOT + 1);
+    record.push(sym::RADIX_TREE.into());
+    record.push(root.0.into());
+    RecordBuilder(record).into_obj(cx).into()
+}
+
+/// Return t if OBJECT is a radix tree.
+#[defun]
+fn radix_tree_p(object: Object) -> bool {
+    root_node(object).is_ok()
+}
+
+/// Set the value of KEY in TREE to VALUE and return TREE.
+///
+/// If VALUE is nil, KEY is removed instead. If TREE is nil, a new tree is
+/// created, so the result should be stored like with radix-tree.el.
+#[defun]
+fn radix_tree_insert<'ob>(
+    tree: Object<'ob>,
+    key: &str,
+    value: Object<'ob>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    let tree = if tree.is_nil() { make_radix_tree(cx) } else { tree };
+    let value = (!value.is_nil()).then_some(value);
+    insert(root_node(tree)?, key, value, cx)?;
+    Ok(tree)
+}
+
+/// Return the value of KEY in TREE, or nil if it is not present.
+#[defun]
+fn radix_tree_lookup<'ob>(tree: Object<'ob>, key: &str) -> Result<Object<'ob>> {
+    if tree.is_nil() {
+        return Ok(NIL);
+    }
+    Ok(lookup(root_node(tree)?, key).unwrap_or_default())
+}
+
+/// Return the number of keys in TREE.
+#[defun]
+fn radix_tree_count(tree: Object) -> Result<usize> {
+    if tree.is_nil() {
+        return Ok(0);
+    }
+    let mut out = Vec::new();
+    mappings(root_node(tree)?, &mut String::new(), &mut out);
+    Ok(out.len())
+}
+
+/// Call FUNCTION with each key in TREE that starts with PREFIX and its value.
+///
+/// The keys are visited in lexicographic order. FUNCTION should not modify
+/// TREE.
+#[defun]
+fn radix_tree_iter_prefix(
+    tree: &Rto<Object>,
+    prefix: &Rto<Object>,
+    function: &Rto<Function>,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<()> {
+    let mut keys = Vec::new();
+    root!(values, new(Vec), cx);
+    let tree = tree.bind(cx);
+    if !tree.is_nil() {
+        let prefix: &str = prefix.bind(cx).try_into()?;
+        let mut path = String::new();
+        let mut found = Vec::new();
+        if let Some(node) = find_prefix(root_node(tree)?, prefix, &mut path) {
+            mappings(node, &mut path, &mut found);
+        }
+        for (key, value) in found {
+            keys.push(key);
+            values.push(value);
+        }
+    }
+    for (idx, key) in keys.into_iter().enumerate() {
+        let key = cx.add(key);
+        let value = values[idx].bind(cx);
+        call!(function, key, value; env, cx)?;
+    }
+    Ok(())
+}
+
+defsym!(RADIX_TREE);
+
+#[cfg(test)]
+mod test {
+    use super::common_prefix_len;
+    use crate::interpreter::assert_lisp;
+
+    #[test]
+    fn test_common_prefix_len() {
+        assert_eq!(common_prefix_len("foobar", "foobaz"), 5);
+        assert_eq!(common_prefix_len("foo", "foobar"), 3);
+        assert_eq!(common_prefix_len("foobar", "foo"), 3);
+        assert_eq!(common_prefix_len("éa", "éb"), 2);
+        assert_eq!(common_prefix_len("abc", "xyz"), 0);
+    }
+
+    #[test]
+    fn test_radix_tree() {
+        assert_lisp(
+            r#"(let ((tree (make-radix-tree)))
+                 (radix-tree-insert tree "foobar" 1)
+                 (radix-tree-insert tree "foobaz" 2)
+                 (radix-tree-insert tree "foo" 3)
+                 (radix-tree-insert tree "bar" 4)
+                 (list (radix-tree-lookup tree "foobar") (radix-tree-lookup tree "foobaz")
+                       (radix-tree-lookup tree "foo") (radix-tree-lookup tree "fooba")
+                       (radix-tree-lookup tree "bar") (radix-tree-count tree)))"#,
+            "(1 2 3 nil 4 4)",
+        );
+        assert_lisp(
+            r#"(let ((tree (radix-tree-insert nil "abc" 1)))
+                 (setq tree (radix-tree-insert tree "abd" 2))
+                 (radix-tree-insert tree "abc" nil)
+                 (list (radix-tree-p tree) (radix-tree-lookup tree "abc")
+                       (radix-tree-lookup tree "abd") (radix-tree-count tree)))"#,
+            "(t nil 2 1)",
+        );
+    }
+
+    #[test]
+    fn test_radix_tree_iter_prefix() {
+        assert_lisp(
+            r#"(let ((tree (make-radix-tree)) result)
+                 (mapc #'(lambda (key) (radix-tree-insert tree key (length key)))
+                       '("car" "cdr" "cadr" "caar" "cons" "list"))
+                 (radix-tree-iter-prefix tree "ca" #'(lambda (k v) (setq result (cons (cons k v) result))))
+                 (radix-tree-iter-prefix tree "c" #'(lambda (k _) (setq result (cons k result))))
+                 (radix-tree-iter-prefix tree "x" #'(lambda (k _) (setq result (cons k result))))
+                 (nreverse result))"#,
+            r#"(("caar" . 4) ("cadr" . 4) ("car" . 3) "caar" "cadr" "car" "cdr" "cons")"#,
+        );
+    }
+}
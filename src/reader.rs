@@ -1,10 +1,16 @@
 //! Lisp reader that reads an object from a string.
 use crate::core::{
+    cons::Cons,
     env::{intern, sym},
     gc::Context,
-    object::{Object, Symbol},
+    object::{
+        ByteString, HashTable, IntoObject, LispHashTable, LispVec, NIL, Object, ObjectType, Record,
+        RecordBuilder, Symbol, char_raw_byte, int_to_char, raw_byte_char,
+    },
 };
 use crate::fns;
+use crate::library::number::{self, Number};
+use rune_core::hashmap::{HashMap, HashSet};
 use rune_core::macros::list;
 use std::fmt::Display;
 use std::str;
@@ -12,6 +18,8 @@ use std::{fmt, iter::Peekable, str::CharIndices};
 
 type Result<T> = std::result::Result<T, Error>;
 
+defsym!(DATA);
+
 /// Errors that can occur during reading a sexp from a string
 #[derive(PartialEq, Debug, Copy, Clone)]
 pub(crate) enum Error {
@@ -26,6 +34,7 @@ pub(crate) enum Error {
     UnknownMacroCharacter(char, usize),
     ParseInt(u8, usize),
     MalformedUnicdoe(usize),
+    InvalidReadSyntax(&'static str, usize),
     EmptyStream,
 }
 
@@ -39,6 +48,7 @@ impl Display for Error {
             Error::ExtraCloseBracket(i) => write!(f, "Extra Closing brace: at {i}"),
             Error::UnexpectedChar(chr, i) => write!(f, "Unexpected character {chr}: at {i}"),
             Error::MalformedUnicdoe(i) => write!(f, "Malformed unicode: at {i}"),
+            Error::InvalidReadSyntax(msg, i) => write!(f, "Invalid read syntax: {msg}: at {i}"),
             Error::EmptyStream => write!(f, "Empty Stream"),
             Error::ExtraItemInCdr(i) => write!(f, "Extra item in cdr: at {i}"),
             Error::MissingQuotedItem(i) => write!(f, "Missing element after quote: at {i}"),
@@ -62,6 +72,7 @@ impl Error {
             | Error::MissingStringDel(i)
             | Error::UnexpectedChar(_, i)
             | Error::MalformedUnicdoe(i)
+            | Error::InvalidReadSyntax(_, i)
             | Error::ExtraItemInCdr(i)
             | Error::ExtraCloseParen(i)
             | Error::ExtraCloseBracket(i)
@@ -72,6 +83,18 @@ impl Error {
         }
     }
 
+    /// True if more input could complete the object being read.
+    pub(crate) fn is_incomplete(&self) -> bool {
+        matches!(
+            self,
+            Error::MissingCloseParen(_)
+                | Error::MissingCloseBracket(_)
+                | Error::MissingStringDel(_)
+                | Error::MissingQuotedItem(_)
+                | Error::EmptyStream
+        )
+    }
+
     pub(crate) fn update_pos(&mut self, offset: usize) {
         if let Some(pos) = self.mut_pos() {
             *pos += offset;
@@ -90,7 +113,7 @@ enum Token<'a> {
     Unquote(usize),
     Splice(usize),
     Sharp(usize),
-    QuestionMark(usize, char),
+    QuestionMark(usize, i64),
     Ident(&'a str),
     String(&'a str),
 }
@@ -107,7 +130,12 @@ impl Display for Token<'_> {
             Token::Unquote(_) => write!(f, ","),
             Token::Splice(_) => write!(f, ",@"),
             Token::Sharp(_) => write!(f, "#"),
-            Token::QuestionMark(_, chr) => write!(f, "?{chr}"),
+            Token::QuestionMark(_, code) => {
+                match u32::try_from(*code).ok().and_then(char::from_u32) {
+                    Some(chr) => write!(f, "?{chr}"),
+                    None => write!(f, "?\\x{code:x}"),
+                }
+            }
             Token::Ident(x) => write!(f, "{x}"),
             Token::String(x) => write!(f, "\"{x}\""),
         }
@@ -213,42 +241,24 @@ impl<'a> Tokenizer<'a> {
             Some((start, item)) => {
                 if item == '\\' {
                     let Token::Ident(tok) = self.get_symbol(start, item) else { unreachable!() };
-                    let Some(chr) = tok.chars().nth(1) else {
+                    if tok.len() == 1 {
                         return Err(Error::MissingQuotedItem(start));
-                    };
-                    if chr == 'u' || chr == 'x' {
-                        match u32::from_str_radix(&tok[2..], 16) {
-                            Ok(digits) => match char::from_u32(digits) {
-                                Some(c) => Ok(Token::QuestionMark(start, c)),
-                                None => Err(Error::MalformedUnicdoe(start)),
-                            },
-                            Err(_) => Err(Error::MalformedUnicdoe(start)),
+                    }
+                    match parse_escape(&tok[1..], false) {
+                        Some((code, "")) => Ok(Token::QuestionMark(start, code)),
+                        _ if tok[1..].starts_with(['u', 'U', 'x', 'N']) => {
+                            Err(Error::MalformedUnicdoe(start))
+                        }
+                        _ => {
+                            Err(Error::InvalidReadSyntax("Invalid escape character syntax", start))
                         }
-                    } else if tok.chars().count() == 2 {
-                        let new = match chr {
-                            'a' => '\u{07}',
-                            'b' => '\u{08}',
-                            'd' => '\u{7F}',
-                            'e' => '\u{1B}',
-                            'f' => '\u{0C}',
-                            'n' => '\n',
-                            'r' => '\r',
-                            's' => ' ',
-                            't' => '\t',
-                            'v' => '\u{0B}',
-                            c => c,
-                        };
-                        Ok(Token::QuestionMark(start, new))
-                    } else {
-                        // TODO implement keycode parsing
-                        Ok(Token::QuestionMark(start, '\0'))
                     }
                 } else {
                     match self.iter.peek() {
                         Some((i, chr)) if symbol_char(*chr) && *chr != '?' => {
                             Err(Error::UnexpectedChar(*chr, *i)) // ?aa
                         }
-                        _ => Ok(Token::QuestionMark(idx, item)), // ?a
+                        _ => Ok(Token::QuestionMark(idx, item as i64)), // ?a
                     }
                 }
             }
@@ -309,42 +319,228 @@ fn intern_symbol<'ob>(symbol: &str, cx: &'ob Context) -> Symbol<'ob> {
 /// Parse a symbol from a string. This will either by a true symbol or a number
 /// literal.
 fn parse_symbol<'a>(slice: &str, cx: &'a Context) -> Object<'a> {
-    match slice.parse::<i64>() {
-        Ok(num) => cx.add(num),
-        Err(_) => match slice.parse::<f64>() {
-            Ok(num) => cx.add(num),
-            Err(_) => cx.add(intern_symbol(slice, cx)),
-        },
+    match number::parse(slice) {
+        Some(Number::Int(num)) => cx.add(num),
+        Some(Number::Float(num)) => cx.add(num),
+        None => cx.add(intern_symbol(slice, cx)),
+    }
+}
+
+const CHAR_ALT: i64 = 1 << 22;
+const CHAR_SUPER: i64 = 1 << 23;
+const CHAR_HYPER: i64 = 1 << 24;
+const CHAR_SHIFT: i64 = 1 << 25;
+const CHAR_CTL: i64 = 1 << 26;
+const CHAR_META: i64 = 1 << 27;
+const CHAR_MODIFIERS: i64 = CHAR_ALT | CHAR_SUPER | CHAR_HYPER | CHAR_SHIFT | CHAR_CTL | CHAR_META;
+/// The largest character code Emacs allows.
+const MAX_CHAR: i64 = 0x3F_FFFF;
+
+/// Apply the control modifier to `code`. Letters and the characters from `@`
+/// to `_` become ASCII control characters and `?` becomes DEL. Anything else
+/// gets the control bit.
+fn control(code: i64) -> i64 {
+    let base = code & !CHAR_MODIFIERS;
+    let modifiers = code & CHAR_MODIFIERS;
+    match base {
+        0x3F => 0x7F | modifiers,
+        0x40..=0x5F | 0x61..=0x7A => (base & 0o37) | modifiers,
+        _ => code | CHAR_CTL,
+    }
+}
+
+/// Parse up to `max` leading digits of `radix` from `s`. Returns the value and
+/// the rest of `s`.
+fn parse_digits(s: &str, radix: u32, max: usize) -> Option<(i64, &str)> {
+    let len = s.chars().take(max).take_while(|c| c.is_digit(radix)).count();
+    let value = i64::from_str_radix(s.get(..len).filter(|x| !x.is_empty())?, radix).ok()?;
+    Some((value, &s[len..]))
+}
+
+/// Parse a character escape, which is the text after the backslash in `?\C-a`
+/// or `"\x41"`. Returns the character code and the rest of `s`. Inside
+/// strings `\s` is always a space instead of the super modifier.
+fn parse_escape(s: &str, in_string: bool) -> Option<(i64, &str)> {
+    let mut chars = s.chars();
+    let chr = chars.next()?;
+    let rest = chars.as_str();
+    let modifier = match chr {
+        'A' => CHAR_ALT,
+        's' if !in_string => CHAR_SUPER,
+        'H' => CHAR_HYPER,
+        'S' => CHAR_SHIFT,
+        'C' => CHAR_CTL,
+        'M' => CHAR_META,
+        _ => 0,
+    };
+    if let Some(rest) = rest.strip_prefix('-').filter(|_| modifier != 0) {
+        let (code, rest) = parse_modified_char(rest, in_string)?;
+        let code = if modifier == CHAR_CTL { control(code) } else { code | modifier };
+        return Some((code, rest));
+    }
+    match chr {
+        '^' => {
+            let (code, rest) = parse_modified_char(rest, in_string)?;
+            Some((control(code), rest))
+        }
+        'x' => parse_digits(rest, 16, 8).filter(|(code, _)| *code <= MAX_CHAR),
+        'u' => parse_digits(rest, 16, 4).filter(|(code, _)| valid_char(*code)),
+        'U' => parse_digits(rest, 16, 8).filter(|(code, _)| valid_char(*code)),
+        'N' => {
+            let (code, rest) = parse_digits(rest.strip_prefix("{U+")?, 16, 8)?;
+            Some((code, rest.strip_prefix('}')?)).filter(|(code, _)| valid_char(*code))
+        }
+        '0'..='7' => parse_digits(s, 8, 3),
+        chr => {
+            let code = match chr {
+                'a' => '\u{07}',
+                'b' => '\u{08}',
+                'd' => '\u{7F}',
+                'e' => '\u{1B}',
+                'f' => '\u{0C}',
+                'n' => '\n',
+                'r' => '\r',
+                's' => ' ',
+                't' => '\t',
+                'v' => '\u{0B}',
+                c => c,
+            };
+            Some((code as i64, rest))
+        }
     }
 }
 
+/// Parse the character after a modifier prefix like `C-`, which is either a
+/// single character or another escape.
+fn parse_modified_char(s: &str, in_string: bool) -> Option<(i64, &str)> {
+    let mut chars = s.chars();
+    match chars.next()? {
+        '\\' => parse_escape(chars.as_str(), in_string),
+        chr => Some((chr as i64, chars.as_str())),
+    }
+}
+
+/// Whether `code` is a Unicode character. Raw bytes are not.
+fn valid_char(code: i64) -> bool {
+    int_to_char(code).is_ok_and(|x| char_raw_byte(x).is_none())
+}
+
 /// process escape characters in the string slice and return the resulting
+/// string. If an escape is invalid, return its offset in the slice instead.
+///
+/// Hex and octal escapes from 128 to 255 and meta characters are raw bytes.
+/// A string that only contains raw bytes and ASCII is read as a unibyte
+/// string, otherwise the raw bytes become raw byte characters in a multibyte
 /// string.
-fn unescape_string<'a>(string: &str, cx: &'a Context) -> Object<'a> {
-    let mut escaped = false;
-    let unescape = |c: char| {
-        if escaped {
-            escaped = false;
-            // TODO: Handle unicode, hex, and octal escapes
-            match c {
-                'n' => Some('\n'),
-                't' => Some('\t'),
-                'r' => Some('\r'),
-                '\n' | ' ' => None,
-                _ => Some(c),
-            }
-        } else if c == '\\' {
-            escaped = true;
-            None
+fn unescape_string<'a>(string: &str, cx: &'a Context) -> std::result::Result<Object<'a>, usize> {
+    let mut chars = Vec::with_capacity(string.len());
+    let mut has_raw_bytes = false;
+    let mut multibyte = false;
+    let mut rest = string;
+    while let Some(chr) = rest.chars().next() {
+        let offset = string.len() - rest.len();
+        rest = &rest[chr.len_utf8()..];
+        if chr != '\\' {
+            multibyte |= !chr.is_ascii();
+            chars.push(chr);
+            continue;
+        }
+        // an escaped newline or space is ignored
+        if let Some(skipped) = rest.strip_prefix(['\n', ' ']) {
+            rest = skipped;
+            continue;
+        }
+        let numeric = rest.starts_with(|c: char| c == 'x' || c.is_digit(8));
+        let (code, remaining) = parse_escape(rest, true).ok_or(offset)?;
+        rest = remaining;
+        let (code, raw_byte) = match code & CHAR_MODIFIERS {
+            0 => (code, numeric && (0x80..=0xFF).contains(&code)),
+            CHAR_META if code & !CHAR_META < 0x80 => ((code & !CHAR_META) | 0x80, true),
+            _ => return Err(offset),
+        };
+        let chr = if raw_byte {
+            raw_byte_char(code as u8)
         } else {
-            Some(c)
+            int_to_char(code).map_err(|_| offset)?
+        };
+        has_raw_bytes |= raw_byte;
+        multibyte |= !raw_byte && !chr.is_ascii();
+        chars.push(chr);
+    }
+    if has_raw_bytes && !multibyte {
+        let bytes: Vec<u8> = chars.iter().map(|&c| char_raw_byte(c).unwrap_or(c as u8)).collect();
+        return Ok(cx.add(bytes));
+    }
+    let mut new = cx.string_with_capacity(string.len());
+    for chr in chars {
+        new.push(chr);
+    }
+    Ok(cx.add(new))
+}
+
+/// Replace every reference to `placeholder` inside `obj` with `value`.
+fn substitute(mut obj: Object, placeholder: Object, value: Object, seen: &mut HashSet<*const u8>) {
+    let replace = |x: Object, seen: &mut HashSet<*const u8>| {
+        if x.ptr_eq(placeholder) {
+            Some(value)
+        } else {
+            substitute(x, placeholder, value, seen);
+            None
         }
     };
-    let mut new = cx.string_with_capacity(string.len());
-    for c in string.chars().filter_map(unescape) {
-        new.push(c);
+    loop {
+        match obj.untag() {
+            ObjectType::Cons(cons) => {
+                if !seen.insert((cons as *const Cons).cast()) {
+                    return;
+                }
+                if let Some(value) = replace(cons.car(), seen) {
+                    cons.set_car(value).expect("read objects should be mutable");
+                }
+                // loop over the cdr so long lists don't overflow the stack
+                if cons.cdr().ptr_eq(placeholder) {
+                    cons.set_cdr(value).expect("read objects should be mutable");
+                    return;
+                }
+                obj = cons.cdr();
+            }
+            ObjectType::Vec(vec) => {
+                if !seen.insert((vec as *const LispVec).cast()) {
+                    return;
+                }
+                for cell in vec.try_mut().expect("read objects should be mutable") {
+                    if let Some(value) = replace(cell.get(), seen) {
+                        cell.set(value);
+                    }
+                }
+                return;
+            }
+            ObjectType::Record(record) => {
+                if !seen.insert((record as *const Record).cast()) {
+                    return;
+                }
+                for cell in record.try_mut().expect("read objects should be mutable") {
+                    if let Some(value) = replace(cell.get(), seen) {
+                        cell.set(value);
+                    }
+                }
+                return;
+            }
+            ObjectType::HashTable(table) => {
+                if !seen.insert((table as *const LispHashTable).cast()) {
+                    return;
+                }
+                for i in 0..table.len() {
+                    let Some((key, val)) = table.get_index(i) else { continue };
+                    if let Some(value) = replace(val, seen) {
+                        table.insert(key, value);
+                    }
+                }
+                return;
+            }
+            _ => return,
+        }
     }
-    cx.add(new)
 }
 
 /// Return true if `chr` is a valid symbol character.
@@ -370,6 +566,15 @@ struct Reader<'a, 'ob> {
     tokens: Tokenizer<'a>,
     /// New objects are allocated in the context.
     cx: &'ob Context<'ob>,
+    /// Objects labeled with `#N=`, which can be referenced with `#N#`.
+    labels: HashMap<usize, Object<'ob>>,
+    /// The byte offset of each form read, if positions are being recorded.
+    /// Forms are added when they are finished, so nested forms come before
+    /// the forms that contain them.
+    positions: Option<Vec<(Object<'ob>, usize)>>,
+    /// The string constants read so far, by their source text, if equal
+    /// constants are shared.
+    strings: Option<HashMap<&'a str, Object<'ob>>>,
 }
 
 impl<'a, 'ob> Reader<'a, 'ob> {
@@ -414,16 +619,34 @@ impl<'a, 'ob> Reader<'a, 'ob> {
     }
 
     fn read_vec(&mut self, delim: usize) -> Result<Object<'ob>> {
-        let mut objects = self.cx.vec_new();
+        let objects = self.read_vec_elements(delim)?;
+        Ok(self.cx.add(objects))
+    }
+
+    /// Read the elements of a vector up to the closing bracket.
+    fn read_vec_elements(&mut self, delim: usize) -> Result<Vec<Object<'ob>>> {
+        let mut objects = Vec::new();
         while let Some(token) = self.tokens.next() {
             match token? {
-                Token::CloseBracket(_) => return Ok(self.cx.add(objects)),
+                Token::CloseBracket(_) => return Ok(objects),
                 tok => objects.push(self.read_sexp(tok)?),
             }
         }
         Err(Error::MissingCloseBracket(delim))
     }
 
+    /// Read the elements of a proper list up to the closing paren.
+    fn read_list_elements(&mut self, delim: usize) -> Result<Vec<Object<'ob>>> {
+        let mut objects = Vec::new();
+        while let Some(token) = self.tokens.next() {
+            match token? {
+                Token::CloseParen(_) => return Ok(objects),
+                tok => objects.push(self.read_sexp(tok)?),
+            }
+        }
+        Err(Error::MissingCloseParen(delim))
+    }
+
     /// Quote an item using `symbol`.
     fn quote_item(&mut self, pos: usize, symbol: Symbol) -> Result<Object<'ob>> {
         match self.tokens.next() {
@@ -442,8 +665,8 @@ impl<'a, 'ob> Reader<'a, 'ob> {
         }
 
         match self.tokens.next() {
-            Some(Ok(Token::Ident(ident))) => match usize::from_str_radix(ident, radix.into()) {
-                Ok(x) => Ok(self.cx.add(x as i64)),
+            Some(Ok(Token::Ident(ident))) => match i64::from_str_radix(ident, radix.into()) {
+                Ok(x) => Ok(self.cx.add(x)),
                 Err(_) => Err(Error::ParseInt(radix, pos)),
             },
             _ => Err(Error::ParseInt(radix, pos)),
@@ -465,36 +688,183 @@ impl<'a, 'ob> Reader<'a, 'ob> {
                 }
                 None => Err(Error::MissingQuotedItem(pos)),
             },
+            Some('(') => self.read_propertized_string(pos),
+            Some('[') => self.read_byte_code(pos),
+            Some('s') => self.read_record(pos),
             Some('b') => self.read_radix(pos, 2),
             Some('o') => self.read_radix(pos, 8),
             Some('x') => self.read_radix(pos, 16),
             Some(chr) if chr.is_ascii_digit() => {
-                let mut radix = (chr as u8) - b'0';
+                let mut num = usize::from(chr as u8 - b'0');
+                // The digit that made the number too large to be a radix
+                let mut radix_overflow = None;
                 loop {
                     match self.tokens.read_char() {
-                        Some('r') => break,
+                        Some('r') => match u8::try_from(num) {
+                            Ok(radix) => return self.read_radix(pos, radix),
+                            // TODO: Better error for radix overflow
+                            Err(_) => {
+                                let chr = radix_overflow.unwrap_or('r');
+                                return Err(Error::UnknownMacroCharacter(chr, pos));
+                            }
+                        },
+                        Some('=') => return self.read_labeled(pos, num),
+                        Some('#') => match self.labels.get(&num) {
+                            Some(obj) => return Ok(*obj),
+                            None => return Err(Error::InvalidReadSyntax("Undefined label", pos)),
+                        },
                         Some(chr) if chr.is_ascii_digit() => {
-                            match radix
+                            match num
                                 .checked_mul(10)
-                                .and_then(|r| r.checked_add(chr as u8 - b'0'))
+                                .and_then(|r| r.checked_add(usize::from(chr as u8 - b'0')))
                             {
-                                Some(r) => radix = r,
-                                // TODO: Better error for radix overflow
-                                None => return Err(Error::UnknownMacroCharacter(chr, pos)),
+                                Some(r) => {
+                                    if r > u8::MAX.into() && radix_overflow.is_none() {
+                                        radix_overflow = Some(chr);
+                                    }
+                                    num = r;
+                                }
+                                None => {
+                                    return Err(Error::InvalidReadSyntax("Number too large", pos));
+                                }
                             }
                         }
                         Some(chr) => return Err(Error::UnknownMacroCharacter(chr, pos)),
                         None => return Err(Error::MissingQuotedItem(pos)),
                     }
                 }
-                self.read_radix(pos, radix)
             }
             Some(chr) => Err(Error::UnknownMacroCharacter(chr, pos)),
             None => Err(Error::MissingQuotedItem(pos)),
         }
     }
 
+    /// Read the object after a `#N=` label. References to the label inside the
+    /// object are read as a placeholder cons, which is replaced once the object
+    /// is complete.
+    fn read_labeled(&mut self, pos: usize, label: usize) -> Result<Object<'ob>> {
+        let cons = Cons::new(NIL, NIL, self.cx);
+        let placeholder: Object = cons.into();
+        self.labels.insert(label, placeholder);
+        let Some(token) = self.tokens.next() else { return Err(Error::MissingQuotedItem(pos)) };
+        let obj = self.read_sexp(token?)?;
+        let obj = match obj.untag() {
+            // A cons can be copied into the placeholder, so the references to
+            // it are already correct
+            ObjectType::Cons(value) if !obj.ptr_eq(placeholder) => {
+                cons.set_car(value.car()).expect("placeholder should be mutable");
+                cons.set_cdr(value.cdr()).expect("placeholder should be mutable");
+                placeholder
+            }
+            _ => {
+                substitute(obj, placeholder, obj, &mut HashSet::default());
+                obj
+            }
+        };
+        self.labels.insert(label, obj);
+        Ok(obj)
+    }
+
+    /// Read a string with text properties, like `#("foo" 0 1 (face bold))`.
+    /// Strings don't have text properties yet, so they are dropped.
+    fn read_propertized_string(&mut self, pos: usize) -> Result<Object<'ob>> {
+        let elements = self.read_list_elements(pos + 1)?;
+        match elements.first() {
+            Some(string) if matches!(string.untag(), ObjectType::String(_)) => Ok(*string),
+            _ => Err(Error::InvalidReadSyntax("Invalid string property list", pos)),
+        }
+    }
+
+    /// Read a byte-code function literal, like `#[257 "\300\207" [] 2]`. The
+    /// byte code can also be a vector of integers, which is how byte-code
+    /// functions are printed.
+    fn read_byte_code(&mut self, pos: usize) -> Result<Object<'ob>> {
+        let error = Error::InvalidReadSyntax("Invalid byte-code object", pos);
+        let elements = self.read_vec_elements(pos + 1)?;
+        let [args, code, consts, depth, rest @ ..] = &elements[..] else { return Err(error) };
+        let (ObjectType::Int(args), ObjectType::Vec(consts), ObjectType::Int(depth)) =
+            (args.untag(), consts.untag(), depth.untag())
+        else {
+            return Err(error);
+        };
+        let code: &ByteString = match code.untag() {
+            ObjectType::ByteString(code) => code,
+            ObjectType::String(code) if code.is_ascii() => {
+                code.as_bytes().to_vec().into_obj(self.cx).untag()
+            }
+            ObjectType::Vec(code) => {
+                let bytes = code.iter().map(|x| match x.get().untag() {
+                    ObjectType::Int(byte) => u8::try_from(byte).ok(),
+                    _ => None,
+                });
+                let bytes: Vec<u8> = bytes.collect::<Option<_>>().ok_or(error)?;
+                bytes.into_obj(self.cx).untag()
+            }
+            _ => return Err(error),
+        };
+        let depth = usize::try_from(depth).map_err(|_| error)?;
+        let (doc, spec) = (rest.first().copied(), rest.get(1).copied());
+        match crate::alloc::make_byte_code(args, code, consts, depth, doc, spec, &[], self.cx) {
+            Ok(func) => Ok(self.cx.add(func)),
+            Err(_) => Err(error),
+        }
+    }
+
+    /// Read a record literal like `#s(foo 1 2)`. If the type is `hash-table`,
+    /// the rest of the record describes a hash table instead, like
+    /// `#s(hash-table test equal data (k1 v1 k2 v2))`.
+    fn read_record(&mut self, pos: usize) -> Result<Object<'ob>> {
+        let elements = match self.tokens.next() {
+            Some(Ok(Token::OpenParen(i))) => self.read_list_elements(i)?,
+            Some(Err(e)) => return Err(e),
+            _ => return Err(Error::InvalidReadSyntax("#s", pos)),
+        };
+        let Some((&type_, slots)) = elements.split_first() else {
+            return Err(Error::InvalidReadSyntax("#s", pos));
+        };
+        if type_ != sym::HASH_TABLE {
+            let mut record = self.cx.vec_with_capacity(elements.len());
+            record.extend_from_slice(&elements);
+            return Ok(self.cx.add(RecordBuilder(record)));
+        }
+        let error = Error::InvalidReadSyntax("Invalid hash table literal", pos);
+        let data = match slots {
+            // rune prints hash tables with only the data
+            [data] => *data,
+            _ => {
+                if slots.len() % 2 != 0 {
+                    return Err(error);
+                }
+                let mut pairs = slots.chunks_exact(2);
+                pairs.find(|x| x[0] == sym::DATA).map(|x| x[1]).unwrap_or_default()
+            }
+        };
+        let Ok(data) = data.as_list() else { return Err(error) };
+        let data = data.collect::<std::result::Result<Vec<_>, _>>().map_err(|_| error)?;
+        if data.len() % 2 != 0 {
+            return Err(error);
+        }
+        let mut table = HashTable::with_hasher(std::hash::BuildHasherDefault::default());
+        for pair in data.chunks_exact(2) {
+            table.insert(pair[0], pair[1]);
+        }
+        Ok(self.cx.add(table))
+    }
+
     fn read_sexp(&mut self, token: Token<'a>) -> Result<Object<'ob>> {
+        let obj = self.read_form(token)?;
+        if let Some(positions) = &mut self.positions {
+            let mut pos = self.tokens.relative_pos(token);
+            if let Token::String(_) = token {
+                // string tokens start after the opening quote
+                pos -= 1;
+            }
+            positions.push((obj, pos));
+        }
+        Ok(obj)
+    }
+
+    fn read_form(&mut self, token: Token<'a>) -> Result<Object<'ob>> {
         match token {
             Token::OpenParen(i) => self.read_list(i),
             Token::CloseParen(i) => Err(Error::ExtraCloseParen(i)),
@@ -505,9 +875,22 @@ impl<'a, 'ob> Reader<'a, 'ob> {
             Token::Splice(i) => self.quote_item(i, sym::SPLICE),
             Token::Backquote(i) => self.quote_item(i, sym::BACKQUOTE),
             Token::Sharp(i) => self.read_sharp(i),
-            Token::QuestionMark(_, c) => Ok((c as i64).into()),
+            Token::QuestionMark(_, c) => Ok(c.into()),
             Token::Ident(x) => Ok(parse_symbol(x, self.cx)),
-            Token::String(x) => Ok(unescape_string(x, self.cx)),
+            Token::String(x) => {
+                if let Some(obj) = self.strings.as_ref().and_then(|strings| strings.get(x)) {
+                    crate::core::gc::stats::record_shared_string();
+                    return Ok(*obj);
+                }
+                let obj = unescape_string(x, self.cx).map_err(|offset| {
+                    let pos = self.tokens.relative_pos(token) + offset;
+                    Error::InvalidReadSyntax("Invalid escape character syntax", pos)
+                })?;
+                if let Some(strings) = &mut self.strings {
+                    strings.insert(x, obj);
+                }
+                Ok(obj)
+            }
         }
     }
 }
@@ -515,17 +898,61 @@ impl<'a, 'ob> Reader<'a, 'ob> {
 /// read a lisp object from `slice`. Return the object and index of next
 /// remaining character in the slice.
 pub(crate) fn read<'ob>(slice: &str, cx: &'ob Context) -> Result<(Object<'ob>, usize)> {
-    let mut reader = Reader { tokens: Tokenizer::new(slice), cx };
-    match reader.tokens.next() {
-        Some(Ok(t)) => reader.read_sexp(t).map(|x| (x, reader.tokens.cur_pos())),
-        Some(Err(e)) => Err(e),
-        None => Err(Error::EmptyStream),
+    let mut reader = Reader::new(slice, cx, false);
+    reader.read_top().map(|x| (x, reader.tokens.cur_pos()))
+}
+
+/// Read a lisp object from `slice` like [`read`], but share a single object
+/// between string constants with the same text. This should only be used for
+/// code, where string constants are not modified.
+pub(crate) fn read_sharing_strings<'ob>(
+    slice: &str,
+    cx: &'ob Context,
+) -> Result<(Object<'ob>, usize)> {
+    let mut reader = Reader::new(slice, cx, false);
+    reader.strings = Some(HashMap::default());
+    reader.read_top().map(|x| (x, reader.tokens.cur_pos()))
+}
+
+/// Form positions recorded by [`read_with_positions`].
+pub(crate) type Positions<'ob> = Vec<(Object<'ob>, usize)>;
+
+/// Read a lisp object from `slice` like [`read`], but also return the byte
+/// offset in `slice` where each form in the object starts.
+pub(crate) fn read_with_positions<'ob>(
+    slice: &str,
+    cx: &'ob Context,
+) -> Result<(Object<'ob>, usize, Positions<'ob>)> {
+    let mut reader = Reader::new(slice, cx, true);
+    let obj = reader.read_top()?;
+    let positions = reader.positions.unwrap_or_default();
+    Ok((obj, reader.tokens.cur_pos(), positions))
+}
+
+impl<'a, 'ob> Reader<'a, 'ob> {
+    fn new(slice: &'a str, cx: &'ob Context<'ob>, positions: bool) -> Self {
+        Self {
+            tokens: Tokenizer::new(slice),
+            cx,
+            labels: HashMap::default(),
+            positions: positions.then(Vec::new),
+            strings: None,
+        }
+    }
+
+    /// Read the first object in the slice.
+    fn read_top(&mut self) -> Result<Object<'ob>> {
+        match self.tokens.next() {
+            Some(Ok(t)) => self.read_sexp(t),
+            Some(Err(e)) => Err(e),
+            None => Err(Error::EmptyStream),
+        }
     }
 }
 
 #[cfg(test)]
 mod test {
-    use crate::core::{cons::Cons, gc::RootSet};
+    use crate::core::{gc::RootSet, object::DisplayCircle};
 
     use super::*;
 
@@ -560,6 +987,11 @@ mod test {
         check_reader!(-3.0, "-3.0", cx);
         check_reader!(1, "+1", cx);
         check_reader!(1, "001", cx);
+        check_reader!(1, "1.", cx);
+        check_reader!(0.5, ".5", cx);
+        check_reader!(1000.0, "1e3", cx);
+        check_reader!(f64::INFINITY, "1.0e+INF", cx);
+        check_reader!(f64::NEG_INFINITY, "-1.0e+INF", cx);
         check_reader!(1, "#o001", cx);
         check_reader!(8, "#o10", cx);
         check_reader!(8, "#8r10", cx);
@@ -575,6 +1007,8 @@ mod test {
         check_reader!(0xdead_beef_i64, "#xDeAdBeEf", cx);
         check_reader!(171, "#12r0123", cx);
         check_reader!(49360, "#36r1234", cx);
+        check_reader!(-31, "#x-1F", cx);
+        check_reader!(-5, "#b-101", cx);
         assert_error("#37r1234", Error::ParseInt(37, 0), cx);
         assert_error("#257r1234", Error::UnknownMacroCharacter('7', 0), cx);
         assert_error("#123456r1234", Error::UnknownMacroCharacter('4', 0), cx);
@@ -612,6 +1046,9 @@ mod test {
         check_reader!(intern("1", cx), "\\1", cx);
         check_reader!(intern("3.0.0", cx), "3.0.0", cx);
         check_reader!(intern("1+", cx), "1+", cx);
+        check_reader!(intern("inf", cx), "inf", cx);
+        check_reader!(intern("NaN", cx), "NaN", cx);
+        check_reader!(intern("1e", cx), "1e", cx);
         check_reader!(intern("+1", cx), "\\+1", cx);
         check_reader!(intern(" x", cx), "\\ x", cx);
         check_reader!(intern("\\x", cx), "\\\\x", cx);
@@ -633,6 +1070,24 @@ mod test {
 baz""#,
             cx
         );
+        check_reader!("AB\x07", r#""\101\x42\a""#, cx);
+        check_reader!("\u{3bb}", r#""\u03bb""#, cx);
+        check_reader!("\u{1}\u{7f}", r#""\C-a\^?""#, cx);
+        check_reader!(" -", r#""\s-""#, cx);
+        check_reader!(vec![b'a', 0xFF], r#""a\377""#, cx);
+        check_reader!(vec![0xE1], r#""\M-a""#, cx);
+        check_reader!(format!("\u{3bb}{}", raw_byte_char(0xFF)), r#""\u03bb\377""#, cx);
+        assert!(read(r#""\U0010FFFF""#, cx).is_err());
+        assert_error(
+            r#"("a" "b\xZ")"#,
+            Error::InvalidReadSyntax("Invalid escape character syntax", 7),
+            cx,
+        );
+        assert_error(
+            r#""\C-\u03bb""#,
+            Error::InvalidReadSyntax("Invalid escape character syntax", 1),
+            cx,
+        );
     }
 
     #[test]
@@ -666,6 +1121,33 @@ baz""#,
         check_reader!(0xabc_u32, "?\\xabc", cx);
     }
 
+    #[test]
+    fn read_char_modifiers() {
+        let roots = &RootSet::default();
+        let cx = &Context::new(roots);
+        check_reader!(1, "?\\C-a", cx);
+        check_reader!(1, "?\\C-A", cx);
+        check_reader!(1, "?\\^a", cx);
+        check_reader!(127, "?\\C-?", cx);
+        check_reader!(0x400_0025, "?\\C-%", cx);
+        check_reader!(0x800_0061, "?\\M-a", cx);
+        check_reader!(0x800_0001, "?\\M-\\C-a", cx);
+        check_reader!(0x800_0001, "?\\C-\\M-a", cx);
+        check_reader!(0x80_0061, "?\\s-a", cx);
+        check_reader!(0x200_0061, "?\\S-a", cx);
+        check_reader!(0x100_0061, "?\\H-a", cx);
+        check_reader!(0x40_0061, "?\\A-a", cx);
+        check_reader!(0x800_000A, "?\\M-\\n", cx);
+        check_reader!(8, "?\\10", cx);
+        check_reader!(0x1F600, "?\\U0001F600", cx);
+        check_reader!(0x41, "?\\N{U+41}", cx);
+        check_reader!(list!(1, 2; cx), "(?\\C-a ?\\C-b)", cx);
+        assert_error("?\\C-", Error::InvalidReadSyntax("Invalid escape character syntax", 1), cx);
+        assert_error("?\\C-ab", Error::InvalidReadSyntax("Invalid escape character syntax", 1), cx);
+        assert_error(" ?\\xg", Error::MalformedUnicdoe(2), cx);
+        assert_error("?\\ud800", Error::MalformedUnicdoe(1), cx);
+    }
+
     #[test]
     fn read_sharp() {
         let roots = &RootSet::default();
@@ -682,6 +1164,97 @@ baz""#,
         assert_error("#a", Error::UnknownMacroCharacter('a', 0), cx);
     }
 
+    #[test]
+    fn read_sharp_literals() {
+        let roots = &RootSet::default();
+        let cx = &Context::new(roots);
+        check_reader!("foo", r#"#("foo" 0 3 (face bold))"#, cx);
+        assert_error("(#(1 2))", Error::InvalidReadSyntax("Invalid string property list", 1), cx);
+
+        let record = read("#s(foo 1 \"a\")", cx).unwrap().0;
+        assert_eq!(record.to_string(), "#s(foo 1 \"a\")");
+        assert_error(" #s()", Error::InvalidReadSyntax("#s", 1), cx);
+        assert_error(" #s1", Error::InvalidReadSyntax("#s", 1), cx);
+
+        let table = read("#s(hash-table size 2 test equal data (a 1 b (2)))", cx).unwrap().0;
+        assert_eq!(table.to_string(), "#s(hash-table (a 1 b (2)))");
+        let table = read("#s(hash-table (a 1))", cx).unwrap().0;
+        assert_eq!(table.to_string(), "#s(hash-table (a 1))");
+        let invalid = Error::InvalidReadSyntax("Invalid hash table literal", 0);
+        assert_error("#s(hash-table data (a 1 b))", invalid, cx);
+        assert_error("#s(hash-table test)", invalid, cx);
+
+        let func = read(r#"#[257 "\300\207" [x] 2]"#, cx).unwrap().0;
+        assert_eq!(func.to_string(), "#[257 [192 135 ] [x ] 2]");
+        let func = read("#[257 [192 135] [x] 2]", cx).unwrap().0;
+        assert_eq!(func.to_string(), "#[257 [192 135 ] [x ] 2]");
+        let invalid = Error::InvalidReadSyntax("Invalid byte-code object", 1);
+        assert_error(" #[257 [192 135] x 2]", invalid, cx);
+        assert_error(" #[257 [256] [] 2]", invalid, cx);
+        assert_error(" #[257 \"\"]", invalid, cx);
+        assert_error(" #[257 \"\" [] 2", Error::MissingCloseBracket(2), cx);
+    }
+
+    #[test]
+    fn read_circle() {
+        let roots = &RootSet::default();
+        let cx = &Context::new(roots);
+        let obj = read("#1=(a . #1#)", cx).unwrap().0;
+        let ObjectType::Cons(cons) = obj.untag() else { unreachable!("Expected cons") };
+        assert_eq!(cons.car(), intern("a", cx));
+        assert!(cons.cdr().ptr_eq(obj));
+
+        let obj = read("(#1=(a) b #1#)", cx).unwrap().0;
+        let ObjectType::Cons(cons) = obj.untag() else { unreachable!("Expected cons") };
+        let list: Vec<_> = obj.as_list().unwrap().map(std::result::Result::unwrap).collect();
+        assert!(list[0].ptr_eq(list[2]));
+        assert!(cons.car().ptr_eq(list[2]));
+
+        let obj = read("#1=[a #1# #2=(b . #1#) #2#]", cx).unwrap().0;
+        let ObjectType::Vec(vec) = obj.untag() else { unreachable!("Expected vector") };
+        assert!(vec[1].get().ptr_eq(obj));
+        assert!(vec[2].get().ptr_eq(vec[3].get()));
+        let ObjectType::Cons(cons) = vec[2].get().untag() else { unreachable!("Expected cons") };
+        assert!(cons.cdr().ptr_eq(obj));
+
+        assert_error("(a #1#)", Error::InvalidReadSyntax("Undefined label", 3), cx);
+        assert_error("#1=", Error::MissingQuotedItem(0), cx);
+    }
+
+    #[test]
+    fn circle_round_trip() {
+        let roots = &RootSet::default();
+        let cx = &Context::new(roots);
+        for input in [
+            "#1=(a . #1#)",
+            "#1=(#1# . #1#)",
+            "(#1=(a) b #1#)",
+            "(a #1=(b c) . #1#)",
+            "#1=[a #1# #2=(b . #1#) #2#]",
+            "#1=#s(foo #1#)",
+            "#1=#s(hash-table (a #1#))",
+            "(#1=(a) #1# #2=[b] #2#)",
+        ] {
+            let obj = read(input, cx).unwrap().0;
+            assert_eq!(DisplayCircle(obj).to_string(), input);
+        }
+    }
+
+    #[test]
+    fn read_positions() {
+        let roots = &RootSet::default();
+        let cx = &Context::new(roots);
+        let (obj, end, positions) = read_with_positions(" (foo 'bar \"é\" baz) 1", cx).unwrap();
+        assert_eq!(end, 20);
+        let foo = intern("foo", cx).into();
+        let bar = intern("bar", cx).into();
+        let baz = intern("baz", cx).into();
+        let quoted = list![sym::QUOTE, bar; cx];
+        let expect = vec![(foo, 2), (bar, 7), (quoted, 6), (cx.add("é"), 11), (baz, 16), (obj, 1)];
+        assert_eq!(positions, expect);
+        assert!(read_with_positions("(foo", cx).is_err());
+    }
+
     #[test]
     fn test_read_vec() {
         let roots = &RootSet::default();
@@ -716,6 +1289,24 @@ baz""#,
         assert_error("(1 . #o9 3)", Error::ParseInt(8, 5), cx);
     }
 
+    #[test]
+    fn shared_strings() {
+        let roots = &RootSet::default();
+        let cx = &Context::new(roots);
+        fn elements(obj: Object) -> Vec<Object> {
+            let ObjectType::Cons(cons) = obj.untag() else { unreachable!() };
+            cons.elements().map(std::result::Result::unwrap).collect()
+        }
+        let shared =
+            elements(read_sharing_strings(r#"("abc" "abc" "ab\c" ("abc"))"#, cx).unwrap().0);
+        assert!(shared[0].ptr_eq(shared[1]));
+        assert!(!shared[0].ptr_eq(shared[2]));
+        assert_eq!(shared[0], shared[2]);
+        assert!(shared[0].ptr_eq(elements(shared[3])[0]));
+        let unshared = elements(read(r#"("abc" "abc")"#, cx).unwrap().0);
+        assert!(!unshared[0].ptr_eq(unshared[1]));
+    }
+
     #[test]
     fn comments() {
         let roots = &RootSet::default();
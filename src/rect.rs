@@ -0,0 +1,229 @@
+//! Rectangles of text.
+//!
+//! A rectangle is given by the positions of two of its corners. It covers the
+//! lines from the line of the first corner to the line of the second, between
+//! the columns of the two corners.
+use crate::character::CharWidth;
+use crate::core::{
+    env::Env,
+    gc::{Context, Rt, Rto},
+    object::{Gc, LispString, Object, OpenBuffer, OptionalFlag},
+};
+use crate::editfns::{delete_region, forward_line, insert_text, line_beginning};
+use crate::indent::{Force, column_at, current_column, move_to_column_force, next_column};
+use anyhow::Result;
+use rune_macros::defun;
+
+#[derive(Debug, Clone, Copy)]
+struct Rectangle {
+    /// The index of the first line of the rectangle
+    first_line: usize,
+    height: usize,
+    left: usize,
+    right: usize,
+}
+
+impl Rectangle {
+    fn new(start: usize, end: usize, env: &Rt<Env>, cx: &Context) -> Result<Self> {
+        let widths = CharWidth::new(env, cx);
+        let buffer = env.current_buffer.get();
+        let (start, end) = (start.min(end), start.max(end));
+        let a = column_at(buffer, start, &widths)?;
+        let b = column_at(buffer, end, &widths)?;
+        let text = &buffer.text;
+        let first_line = text.char_to_line(start - 1);
+        let height = text.char_to_line(end - 1) - first_line + 1;
+        Ok(Self { first_line, height, left: a.min(b), right: a.max(b) })
+    }
+
+    /// The start of the `n`th line of the rectangle.
+    fn line_start(&self, buffer: &OpenBuffer, n: usize) -> usize {
+        (buffer.text.line_to_char(self.first_line + n) + 1).max(buffer.point_min())
+    }
+
+    /// The part of the line starting at `bol` that is in the rectangle. Tabs
+    /// and characters that are only partly in it become spaces, and the
+    /// string is padded with spaces if the line is too short.
+    fn extract_line(&self, buffer: &OpenBuffer, bol: usize, widths: &CharWidth) -> Result<String> {
+        let (s1, s2) = buffer.slice_with_gap(bol, buffer.point_max())?;
+        let mut line = String::new();
+        let mut column = 0;
+        for chr in s1.chars().chain(s2.chars()).take_while(|x| *x != '\n') {
+            if column >= self.right {
+                break;
+            }
+            let next = next_column(column, chr, widths);
+            if column >= self.left && next <= self.right && chr != '\t' {
+                line.push(chr);
+            } else if next > self.left {
+                line.push_str(&" ".repeat(next.min(self.right) - column.max(self.left)));
+            }
+            column = next;
+        }
+        line.push_str(&" ".repeat(self.right.saturating_sub(column.max(self.left))));
+        Ok(line)
+    }
+
+    /// Call `func` with point at the start of each line of the rectangle.
+    fn for_each_line(
+        &self,
+        env: &mut Rt<Env>,
+        cx: &mut Context,
+        mut func: impl FnMut(&mut Rt<Env>, &mut Context) -> Result<()>,
+    ) -> Result<()> {
+        for n in 0..self.height {
+            let buffer = env.current_buffer.get_mut();
+            let bol = self.line_start(buffer, n);
+            buffer.goto_char(bol);
+            func(env, cx)?;
+        }
+        Ok(())
+    }
+
+    /// Delete the part of the current line that is in the rectangle. Tabs
+    /// that cross its edges are changed to spaces first. If `fill` is true,
+    /// lines that end before the rectangle are indented to its left edge.
+    fn delete_line(&self, fill: bool, env: &mut Rt<Env>, cx: &mut Context) -> Result<()> {
+        let force = if fill { Force::Indent } else { Force::Tabs };
+        if move_to_column_force(self.left, force, env, cx)? != self.left {
+            return Ok(());
+        }
+        let start = env.current_buffer.get().point();
+        move_to_column_force(self.right, Force::Tabs, env, cx)?;
+        let end = env.current_buffer.get().point();
+        delete_region(start, end, env, cx)
+    }
+}
+
+/// Return the contents of the rectangle with corners START and END as a list
+/// of strings, one for each line. Tabs are expanded to spaces and short lines
+/// are padded with spaces, so every string is as wide as the rectangle.
+#[defun]
+fn extract_rectangle<'ob>(
+    start: usize,
+    end: usize,
+    env: &Rt<Env>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    let rect = Rectangle::new(start, end, env, cx)?;
+    let widths = CharWidth::new(env, cx);
+    let buffer = env.current_buffer.get();
+    let mut lines = Vec::new();
+    for n in 0..rect.height {
+        let line = rect.extract_line(buffer, rect.line_start(buffer, n), &widths)?;
+        lines.push(cx.add(line));
+    }
+    Ok(crate::fns::slice_into_list(&lines, None, cx))
+}
+
+/// Delete the text in the rectangle with corners START and END, moving the
+/// text after it on each line to the left. If FILL is non-nil, lines that end
+/// before the rectangle are indented to its left edge.
+#[defun]
+fn delete_rectangle(
+    start: usize,
+    end: usize,
+    fill: OptionalFlag,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<()> {
+    let rect = Rectangle::new(start, end, env, cx)?;
+    let excursion = env.current_buffer.get_mut().save_excursion();
+    let result = rect.for_each_line(env, cx, |env, cx| rect.delete_line(fill.is_some(), env, cx));
+    env.current_buffer.get_mut().restore_excursion(excursion);
+    result
+}
+
+/// Replace the text in the rectangle with corners START and END with STRING
+/// on each line, and leave point after the last one. Lines that end before
+/// the rectangle are indented to its left edge.
+#[defun]
+fn string_rectangle(
+    start: usize,
+    end: usize,
+    string: &Rto<Gc<&LispString>>,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<()> {
+    let rect = Rectangle::new(start, end, env, cx)?;
+    let string = string.untag(cx).inner().to_owned();
+    rect.for_each_line(env, cx, |env, cx| {
+        move_to_column_force(rect.left, Force::Indent, env, cx)?;
+        rect.delete_line(false, env, cx)?;
+        insert_text(&string, env, cx)
+    })
+}
+
+/// Insert the strings in RECTANGLE as a rectangle whose upper left corner is
+/// at point. Each string goes on its own line at the column of point, and
+/// lines are added at the end of the buffer if needed. The mark is left at
+/// the upper left corner and point at the lower right.
+#[defun]
+fn insert_rectangle(rectangle: &Rto<Object>, env: &mut Rt<Env>, cx: &mut Context) -> Result<()> {
+    let mut lines = Vec::new();
+    for line in rectangle.bind(cx).as_list()? {
+        let line: &str = line?.try_into()?;
+        lines.push(line.to_owned());
+    }
+    let column = current_column(env, cx)?;
+    let point = env.current_buffer.get().point();
+    env.current_buffer.get_mut().set_mark(Some(point))?;
+    for (n, line) in lines.iter().enumerate() {
+        if n > 0 {
+            forward_line(Some(1), env);
+            let buffer = env.current_buffer.get();
+            if line_beginning(buffer, 1) != buffer.point() {
+                insert_text("\n", env, cx)?;
+            }
+            move_to_column_force(column, Force::Indent, env, cx)?;
+        }
+        insert_text(line, env, cx)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::interpreter::assert_lisp;
+
+    #[test]
+    fn test_extract_rectangle() {
+        assert_lisp(
+            r#"(progn (insert "abcdef\nab\n\tx\nabcdefgh")
+                      (extract-rectangle 3 20))"#,
+            r#"("cdef" "    " "    " "cdef")"#,
+        );
+    }
+
+    #[test]
+    fn test_delete_rectangle() {
+        assert_lisp(
+            r#"(progn (insert "abcdef\nab\n\tx\nabcdefgh")
+                      (goto-char 3)
+                      (delete-rectangle 3 20)
+                      (list (point) (buffer-string)))"#,
+            r#"(3 "ab\nab\n    x\nabgh")"#,
+        );
+    }
+
+    #[test]
+    fn test_string_rectangle() {
+        assert_lisp(
+            r#"(progn (insert "abcdef\na\nabcdef")
+                      (string-rectangle 2 13 "XY")
+                      (list (point) (buffer-string)))"#,
+            r#"(15 "aXYdef\naXY\naXYdef")"#,
+        );
+    }
+
+    #[test]
+    fn test_insert_rectangle() {
+        assert_lisp(
+            r#"(progn (insert "abc\nd")
+                      (goto-char 2)
+                      (insert-rectangle '("12" "34" "56"))
+                      (list (point) (mark) (buffer-string)))"#,
+            r#"(14 2 "a12bc\nd34\n 56")"#,
+        );
+    }
+}
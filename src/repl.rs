@@ -0,0 +1,162 @@
+//! An interactive read-eval-print loop.
+//!
+//! Input is read with line editing, and lines are collected until they form
+//! a complete expression, so forms can span multiple lines. Results are
+//! pretty-printed. History is saved between sessions.
+use crate::core::{
+    env::Env,
+    gc::{Context, Rt},
+};
+use crate::reader;
+use rune_core::macros::{rebind, root};
+use rustyline::{DefaultEditor, error::ReadlineError};
+use std::path::PathBuf;
+
+const PROMPT: &str = "> ";
+/// The prompt for the continuation lines of a form
+const CONTINUE_PROMPT: &str = ".. ";
+
+fn history_file() -> Option<PathBuf> {
+    let dir = match std::env::var_os("XDG_STATE_HOME") {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(std::env::var_os("HOME")?).join(".local/state"),
+    };
+    Some(dir.join("rune/history"))
+}
+
+/// The result of reading the input collected so far.
+#[derive(Debug, PartialEq)]
+enum Input {
+    /// The input ends in the middle of a form
+    Incomplete,
+    /// The input is complete
+    Complete,
+    /// The input can't be read. Contains the error message.
+    Invalid(String),
+}
+
+fn check_input(input: &str, cx: &Context) -> Input {
+    let mut pos = 0;
+    loop {
+        match reader::read(&input[pos..], cx) {
+            Ok((_, end)) => pos += end,
+            Err(reader::Error::EmptyStream) => return Input::Complete,
+            Err(e) if e.is_incomplete() => return Input::Incomplete,
+            Err(e) => return Input::Invalid(e.to_string()),
+        }
+    }
+}
+
+/// Evaluate each form in `input` and print the results. Returns the exit code
+/// if `kill-emacs` was called.
+fn eval_input(input: &str, env: &mut Rt<Env>, cx: &mut Context) -> Option<u8> {
+    let mut pos = 0;
+    loop {
+        let obj = match reader::read(&input[pos..], cx) {
+            Ok((obj, end)) => {
+                pos += end;
+                obj
+            }
+            Err(reader::Error::EmptyStream) => return None,
+            Err(e) => {
+                eprintln!("Error: {e}");
+                return None;
+            }
+        };
+        root!(obj, cx);
+        match crate::interpreter::eval(obj, None, env, cx) {
+            Ok(val) => {
+                let val = rebind!(val, cx);
+                match crate::pp::pp_to_string(val, env, cx) {
+                    Ok(text) => print!("{text}"),
+                    Err(_) => println!("{val}"),
+                }
+            }
+            Err(e) => {
+                let code = crate::eval::exit_code(&e);
+                if code.is_none() {
+                    crate::report_error(e);
+                }
+                return code;
+            }
+        }
+    }
+}
+
+/// Run the repl until the input ends. Returns the exit code if `kill-emacs`
+/// was called.
+pub(crate) fn repl(env: &mut Rt<Env>, cx: &mut Context) -> Option<u8> {
+    let mut editor = match DefaultEditor::new() {
+        Ok(editor) => editor,
+        Err(e) => {
+            eprintln!("Error: Failed to start the repl: {e}");
+            return None;
+        }
+    };
+    let history = history_file();
+    if let Some(history) = &history {
+        // there is no history the first time
+        let _ = editor.load_history(history);
+    }
+    let mut input = String::new();
+    let mut exit = None;
+    while exit.is_none() {
+        let prompt = if input.is_empty() { PROMPT } else { CONTINUE_PROMPT };
+        match editor.readline(prompt) {
+            Ok(line) => {
+                if input.is_empty() && line.trim() == "exit" {
+                    break;
+                }
+                input.push_str(&line);
+                input.push('\n');
+            }
+            // discard the current input
+            Err(ReadlineError::Interrupted) => {
+                input.clear();
+                continue;
+            }
+            Err(ReadlineError::Eof) => break,
+            Err(e) => {
+                eprintln!("Error: {e}");
+                break;
+            }
+        }
+        match check_input(&input, cx) {
+            Input::Incomplete => continue,
+            Input::Invalid(e) => eprintln!("Error: {e}"),
+            Input::Complete => exit = eval_input(&input, env, cx),
+        }
+        if !input.trim().is_empty() {
+            let _ = editor.add_history_entry(input.trim_end());
+        }
+        input.clear();
+    }
+    if let Some(history) = &history {
+        if let Some(dir) = history.parent() {
+            let _ = std::fs::create_dir_all(dir);
+        }
+        if let Err(e) = editor.save_history(history) {
+            eprintln!("Error: Failed to save history: {e}");
+        }
+    }
+    exit
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::gc::RootSet;
+
+    #[test]
+    fn test_check_input() {
+        let roots = &RootSet::default();
+        let cx = &Context::new(roots);
+        assert_eq!(check_input("(+ 1\n", cx), Input::Incomplete);
+        assert_eq!(check_input("(+ 1 2) [a\n", cx), Input::Incomplete);
+        assert_eq!(check_input("\"abc\n", cx), Input::Incomplete);
+        assert_eq!(check_input("'", cx), Input::Incomplete);
+        assert_eq!(check_input("(+ 1 2)\n(car x)\n", cx), Input::Complete);
+        assert_eq!(check_input("  \n", cx), Input::Complete);
+        assert!(matches!(check_input("(a . b c)", cx), Input::Invalid(_)));
+    }
+}
@@ -1,83 +1,571 @@
 //! Search utilities.
 use crate::core::{
-    cons::Cons,
-    env::Env,
-    gc::{Context, Rt},
-    object::{List, NIL, Object, ObjectType, OptionalFlag},
+    env::{Env, sym},
+    gc::{Context, Rt, Rto},
+    object::{Function, LispBuffer, List, NIL, Object, ObjectType, OpenBuffer, OptionalFlag},
 };
+use crate::editfns::{signal_after_change, signal_before_change};
+use crate::eval::EvalError;
 use anyhow::{Result, bail, ensure};
-use fallible_iterator::FallibleIterator;
-use fancy_regex::Regex;
+use rune_core::macros::{call, list, root};
 use rune_macros::defun;
+use rune_regex::{Captures, GapText, Input, Regex, RegexBuilder};
+use std::ops::Range;
+
+defvar_bool!(CASE_FOLD_SEARCH, true);
+defsym!(SEARCH_FAILED);
+defsym!(INVALID_REGEXP);
+
+fn case_fold_search(env: &Rt<Env>, cx: &Context) -> bool {
+    env.vars.get(sym::CASE_FOLD_SEARCH).is_some_and(|x| !x.bind(cx).is_nil())
+}
+
+/// Compile `regexp`, honoring `case-fold-search`. Syntax errors are signaled
+/// as `invalid-regexp`.
+fn compile_regexp(regexp: &str, env: &mut Rt<Env>, cx: &Context) -> Result<Regex> {
+    let case_fold = case_fold_search(env, cx);
+    match RegexBuilder::new(regexp).case_fold(case_fold).build() {
+        Ok(re) => Ok(re),
+        Err(e) => {
+            let data = list![e.to_string(); cx];
+            Err(EvalError::signal(sym::INVALID_REGEXP.into(), data, env).into())
+        }
+    }
+}
+
+/// Compile `string` as a literal pattern, honoring `case-fold-search`.
+fn compile_literal(string: &str, env: &Rt<Env>, cx: &Context) -> Result<Regex> {
+    let case_fold = case_fold_search(env, cx);
+    Ok(RegexBuilder::new(string).literal(true).case_fold(case_fold).build()?)
+}
+
+/// Convert the byte offsets in `caps` to lisp positions using `to_pos`.
+/// Groups that did not match have no positions.
+fn caps_positions(caps: &Captures, to_pos: impl Fn(usize) -> usize) -> Vec<Option<(usize, usize)>> {
+    caps.iter().map(|x| x.map(|x| (to_pos(x.start), to_pos(x.end)))).collect()
+}
+
+/// Set the match data to `positions`, which are in `buffer`, or in a string
+/// if it is `None`. Trailing unmatched groups are omitted.
+fn set_match_positions(
+    mut positions: Vec<Option<(usize, usize)>>,
+    buffer: Option<&LispBuffer>,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) {
+    while positions.last().is_some_and(Option::is_none) {
+        positions.pop();
+    }
+    env.match_data = positions;
+    env.match_buffer.set(buffer.map_or(NIL, |x| cx.add(x)));
+}
+
+fn char_to_byte(string: &str, pos: usize) -> usize {
+    string.char_indices().nth(pos).map_or(string.len(), |(idx, _)| idx)
+}
+
+fn byte_to_char(string: &str, pos: usize) -> usize {
+    string[..pos].chars().count()
+}
 
 #[defun]
 fn string_match<'ob>(
     regexp: &str,
     string: &str,
     start: Option<i64>,
-    _inhibit_modify: OptionalFlag,
+    inhibit_modify: OptionalFlag,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    let re = compile_regexp(regexp, env, cx)?;
+    let len = string.chars().count() as i64;
+    let start = match start.unwrap_or(0) {
+        x if x < 0 => x + len,
+        x => x,
+    };
+    ensure!((0..=len).contains(&start), "Args out of range: {string:?}, {start}");
+    let input = Input::new(string).range(char_to_byte(string, start as usize)..string.len());
+    let Some(caps) = re.search_forward(&input)? else { return Ok(NIL) };
+    let to_char = |pos| byte_to_char(string, pos);
+    if inhibit_modify.is_none() {
+        set_match_positions(caps_positions(&caps, to_char), None, env, cx);
+    }
+    Ok(to_char(caps.get(0).unwrap().start).into())
+}
+
+/// The accessible portion of the current buffer, viewed as one string by the
+/// regex engine. Lisp positions are 1-based character positions.
+struct BufferText<'a> {
+    first: &'a str,
+    second: &'a str,
+    first_chars: usize,
+    point_min: usize,
+}
+
+impl<'a> BufferText<'a> {
+    fn new(buffer: &'a OpenBuffer<'_>) -> Self {
+        let point_min = buffer.point_min();
+        let (first, second) = buffer.text.slice(point_min - 1..buffer.point_max() - 1);
+        Self { first, second, first_chars: first.chars().count(), point_min }
+    }
+
+    fn text(&self) -> GapText<'a> {
+        GapText::new(self.first, self.second)
+    }
+
+    fn len(&self) -> usize {
+        self.first.len() + self.second.len()
+    }
+
+    fn pos_to_byte(&self, pos: usize) -> usize {
+        let chars = pos.saturating_sub(self.point_min);
+        match chars.checked_sub(self.first_chars) {
+            Some(chars) => self.first.len() + char_to_byte(self.second, chars),
+            None => char_to_byte(self.first, chars),
+        }
+    }
+
+    fn byte_to_pos(&self, byte: usize) -> usize {
+        let chars = match byte.checked_sub(self.first.len()) {
+            Some(byte) => self.first_chars + byte_to_char(self.second, byte),
+            None => byte_to_char(self.first, byte),
+        };
+        chars + self.point_min
+    }
+}
+
+/// Search the current buffer for `re` starting at point. Moves point to the
+/// end of the match when searching forward, or the beginning when searching
+/// backward. `pattern` is the string that `re` was compiled from.
+#[expect(clippy::too_many_arguments)]
+fn search_buffer<'ob>(
+    re: &Regex,
+    pattern: &str,
+    bound: Option<usize>,
+    noerror: Option<Object>,
+    count: Option<i64>,
+    forward: bool,
     env: &mut Rt<Env>,
     cx: &'ob Context,
 ) -> Result<Object<'ob>> {
-    // TODO: implement inhibit-modify
-    let re = Regex::new(&lisp_regex_to_rust(regexp))?;
-
-    let start = start.unwrap_or(0) as usize;
-    if let Some(matches) = re.captures_iter(&string[start..]).next() {
-        let mut all: Vec<Object> = Vec::new();
-        let matches = matches?;
-        let mut groups = matches.iter();
-        // TODO: match data should be char position, not byte
-        while let Some(Some(group)) = groups.next() {
-            all.push(group.start().into());
-            all.push(group.end().into());
+    let count = count.unwrap_or(1);
+    let forward = forward == (count >= 0);
+    let buffer = env.current_buffer.get();
+    let point = buffer.point();
+    let point_max = buffer.point_max();
+    let bound = match bound {
+        Some(bound) if forward => {
+            ensure!(bound >= point, "Invalid search bound (wrong side of point)");
+            bound.min(point_max)
         }
-        let match_data = crate::fns::slice_into_list(&all, None, cx);
-        env.match_data.set(match_data);
-        let head: &Cons = match_data.try_into().unwrap();
-        Ok(head.car())
-    } else {
-        Ok(NIL)
+        Some(bound) => {
+            ensure!(bound <= point, "Invalid search bound (wrong side of point)");
+            bound.max(buffer.point_min())
+        }
+        None if forward => point_max,
+        None => buffer.point_min(),
+    };
+    if count == 0 {
+        return Ok(point.into());
+    }
+
+    let text = BufferText::new(buffer);
+    let gap_text = text.text();
+    let point_byte = text.pos_to_byte(point);
+    let bound_byte = text.pos_to_byte(bound);
+    let mut pos = point_byte;
+    let mut found = None;
+    for _ in 0..count.unsigned_abs() {
+        let range = if forward { pos..bound_byte } else { bound_byte..pos };
+        let input = Input::new(&gap_text).range(range).point(point_byte);
+        let caps = if forward { re.search_forward(&input)? } else { re.search_backward(&input)? };
+        let Some(caps) = caps else {
+            found = None;
+            break;
+        };
+        let whole = caps.get(0).unwrap();
+        pos = if forward { whole.end } else { whole.start };
+        found = Some(caps);
+    }
+
+    match found {
+        Some(caps) => {
+            let new_point = text.byte_to_pos(pos);
+            let positions = caps_positions(&caps, |byte| text.byte_to_pos(byte));
+            let buffer = env.current_buffer.get().lisp_buffer(cx);
+            set_match_positions(positions, Some(buffer), env, cx);
+            env.current_buffer.get_mut().goto_char(new_point);
+            Ok(new_point.into())
+        }
+        None => match noerror {
+            None => {
+                let data = list![pattern; cx];
+                Err(EvalError::signal(sym::SEARCH_FAILED.into(), data, env).into())
+            }
+            Some(x) if x == sym::TRUE => Ok(NIL),
+            Some(_) => {
+                env.current_buffer.get_mut().goto_char(bound);
+                Ok(NIL)
+            }
+        },
     }
 }
 
 #[defun]
-fn replace_match(
-    newtext: &str,
-    _fixedcase: OptionalFlag,
-    _literal: OptionalFlag,
-    string: Option<&str>,
-    subexp: Option<usize>,
-    env: &Rt<Env>,
+fn re_search_forward<'ob>(
+    regexp: &str,
+    bound: Option<usize>,
+    noerror: Option<Object>,
+    count: Option<i64>,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    let re = compile_regexp(regexp, env, cx)?;
+    search_buffer(&re, regexp, bound, noerror, count, true, env, cx)
+}
+
+#[defun]
+fn re_search_backward<'ob>(
+    regexp: &str,
+    bound: Option<usize>,
+    noerror: Option<Object>,
+    count: Option<i64>,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    let re = compile_regexp(regexp, env, cx)?;
+    search_buffer(&re, regexp, bound, noerror, count, false, env, cx)
+}
+
+#[defun]
+fn search_forward<'ob>(
+    string: &str,
+    bound: Option<usize>,
+    noerror: Option<Object>,
+    count: Option<i64>,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    let re = compile_literal(string, env, cx)?;
+    search_buffer(&re, string, bound, noerror, count, true, env, cx)
+}
+
+#[defun]
+fn search_backward<'ob>(
+    string: &str,
+    bound: Option<usize>,
+    noerror: Option<Object>,
+    count: Option<i64>,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    let re = compile_literal(string, env, cx)?;
+    search_buffer(&re, string, bound, noerror, count, false, env, cx)
+}
+
+#[defun]
+fn looking_at(
+    regexp: &str,
+    inhibit_modify: OptionalFlag,
+    env: &mut Rt<Env>,
     cx: &Context,
+) -> Result<bool> {
+    let re = compile_regexp(regexp, env, cx)?;
+    let buffer = env.current_buffer.get();
+    let point = buffer.point();
+    let text = BufferText::new(buffer);
+    let gap_text = text.text();
+    let point_byte = text.pos_to_byte(point);
+    let input = Input::new(&gap_text).range(point_byte..text.len()).point(point_byte);
+    let Some(caps) = re.looking_at(&input)? else { return Ok(false) };
+    if inhibit_modify.is_none() {
+        let positions = caps_positions(&caps, |byte| text.byte_to_pos(byte));
+        let buffer = env.current_buffer.get().lisp_buffer(cx);
+        set_match_positions(positions, Some(buffer), env, cx);
+    }
+    Ok(true)
+}
+
+/// Like `looking-at`, but does not change the match data. Regexps without
+/// special characters are compared against the buffer directly.
+#[defun]
+fn looking_at_p(regexp: &str, env: &mut Rt<Env>, cx: &Context) -> Result<bool> {
+    if regexp.contains(['.', '*', '+', '?', '[', '^', '$', '\\']) {
+        return looking_at(regexp, Some(()), env, cx);
+    }
+    let case_fold = case_fold_search(env, cx);
+    let buffer = env.current_buffer.get();
+    let text = &buffer.text;
+    let point = text.cursor().chars();
+    let end = point + regexp.chars().count();
+    if end >= buffer.point_max() {
+        return Ok(false);
+    }
+    let (first, second) = text.slice(point..end);
+    let chars_eq = |(a, b): (char, char)| a == b || (case_fold && fold_case(a) == fold_case(b));
+    Ok(first.chars().chain(second.chars()).zip(regexp.chars()).all(chars_eq))
+}
+
+fn fold_case(c: char) -> char {
+    let mut lower = c.to_lowercase();
+    match (lower.next(), lower.next()) {
+        (Some(l), None) => l,
+        _ => c,
+    }
+}
+
+/// Return how the case of a replacement should be adjusted to match the
+/// text it replaces. This follows the rules of `replace-match`.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+enum CaseAction {
+    NoChange,
+    AllCaps,
+    CapInitial,
+}
+
+fn case_action(matched: &str) -> CaseAction {
+    let mut some_multiletter_word = false;
+    let mut some_lowercase = false;
+    let mut some_uppercase = false;
+    let mut some_nonuppercase_initial = false;
+    let mut prev = '\n';
+    for c in matched.chars() {
+        let initial = !prev.is_alphanumeric();
+        if c.is_lowercase() {
+            some_lowercase = true;
+            if initial {
+                some_nonuppercase_initial = true;
+            } else {
+                some_multiletter_word = true;
+            }
+        } else if c.is_uppercase() {
+            some_uppercase = true;
+            if !initial {
+                some_multiletter_word = true;
+            }
+        } else if initial && c.is_alphanumeric() {
+            some_nonuppercase_initial = true;
+        }
+        prev = c;
+    }
+    if !some_lowercase && some_multiletter_word {
+        CaseAction::AllCaps
+    } else if !some_nonuppercase_initial && some_multiletter_word {
+        CaseAction::CapInitial
+    } else if !some_nonuppercase_initial && some_uppercase {
+        CaseAction::AllCaps
+    } else {
+        CaseAction::NoChange
+    }
+}
+
+fn apply_case(text: &str, action: CaseAction) -> String {
+    match action {
+        CaseAction::NoChange => text.to_owned(),
+        CaseAction::AllCaps => text.to_uppercase(),
+        CaseAction::CapInitial => {
+            let mut result = String::with_capacity(text.len());
+            let mut prev = '\n';
+            for c in text.chars() {
+                if !prev.is_alphanumeric() {
+                    result.extend(c.to_uppercase());
+                } else {
+                    result.push(c);
+                }
+                prev = c;
+            }
+            result
+        }
+    }
+}
+
+/// Build the text that replaces a match. Unless `literal` is true, `\&` is
+/// replaced with the whole match, `\N` with the Nth subexpression, and `\\`
+/// with a single backslash. `group` returns the text of a subexpression, or
+/// `None` if it did not match.
+fn replacement_text(
+    newtext: &str,
+    fixedcase: bool,
+    literal: bool,
+    replaced: &str,
+    group: impl Fn(usize) -> Option<String>,
 ) -> Result<String> {
-    // TODO: Handle newtext interpolation. Treat \ as special. See docstring for more.
-    //
-    // TODO: Handle automatic case adjustment
-    let Some(string) = string else { bail!("replace-match for buffers not yet implemented") };
-    let mut match_data = env.match_data.bind(cx).as_list()?.fallible();
+    let mut result = String::with_capacity(newtext.len());
+    if literal {
+        result.push_str(newtext);
+    } else {
+        let mut chars = newtext.chars();
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                result.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some('&') => result.push_str(&group(0).unwrap_or_default()),
+                Some(n @ '0'..='9') => {
+                    let idx = n.to_digit(10).unwrap() as usize;
+                    result.push_str(&group(idx).unwrap_or_default());
+                }
+                Some('\\') => result.push('\\'),
+                _ => bail!("Invalid use of `\\' in replacement text"),
+            }
+        }
+    }
+    if fixedcase {
+        Ok(result)
+    } else {
+        Ok(apply_case(&result, case_action(replaced)))
+    }
+}
+
+fn subexp_err(subexp: usize) -> String {
+    format!("replace-match subexpression {subexp} does not exist")
+}
+
+#[defun]
+fn replace_match<'ob>(
+    newtext: &Rto<Object>,
+    fixedcase: OptionalFlag,
+    literal: OptionalFlag,
+    string: Option<&Rto<Object>>,
+    subexp: Option<usize>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<Object<'ob>> {
+    let newtext: &str = newtext.bind(cx).try_into()?;
+    let positions = env.match_data.clone();
     let subexp = subexp.unwrap_or(0);
-    let sub_err = || format!("replace-match subexpression {subexp} does not exist");
-    for _ in 0..(subexp * 2) {
-        ensure!(match_data.next()?.is_some(), sub_err());
+    let Some(Some((beg, end))) = positions.get(subexp).copied() else {
+        bail!(subexp_err(subexp))
+    };
+    ensure!(beg <= end, "Args out of range: {beg}, {end}");
+    if let Some(string) = string {
+        let string: &str = string.bind(cx).try_into()?;
+        let byte_range =
+            |(beg, end): (usize, usize)| char_to_byte(string, beg)..char_to_byte(string, end);
+        let group = |idx: usize| {
+            let range: Range<usize> = byte_range(positions.get(idx).copied()??);
+            string.get(range).map(ToOwned::to_owned)
+        };
+        let target = byte_range((beg, end));
+        let replaced = string.get(target.clone()).unwrap_or_default();
+        let new =
+            replacement_text(newtext, fixedcase.is_some(), literal.is_some(), replaced, group)?;
+        let mut new_string = String::with_capacity(string.len() + new.len());
+        new_string.push_str(&string[..target.start]);
+        new_string.push_str(&new);
+        new_string.push_str(&string[target.end..]);
+        Ok(cx.add(new_string))
+    } else {
+        // match data holds 1-based buffer positions
+        let buffer = env.current_buffer.get();
+        let (min, max) = (buffer.point_min(), buffer.point_max());
+        ensure!(beg >= min && end <= max, "Args out of range: {beg}, {end}");
+        let slice = |(beg, end): (usize, usize)| {
+            let (a, b) = buffer.text.slice(beg - 1..end - 1);
+            format!("{a}{b}")
+        };
+        let group = |idx: usize| {
+            let (beg, end) = positions.get(idx).copied()??;
+            (beg >= min && beg <= end && end <= max).then(|| slice((beg, end)))
+        };
+        let replaced = slice((beg, end));
+        let new =
+            replacement_text(newtext, fixedcase.is_some(), literal.is_some(), &replaced, group)?;
+        signal_before_change(beg, end, env, cx)?;
+        let buffer = env.current_buffer.get_mut();
+        buffer.delete(beg, end)?;
+        buffer.goto_char(beg);
+        buffer.insert_str(&new);
+        signal_after_change(beg, beg + new.chars().count(), end - beg, env, cx)?;
+        Ok(NIL)
     }
-    let Some(beg) = match_data.next()? else { bail!(sub_err()) };
-    let Some(end) = match_data.next()? else { bail!(sub_err()) };
+}
 
-    // TODO: match data should be char position, not byte
-    let beg: usize = beg.try_into()?;
-    let end: usize = end.try_into()?;
+#[defun]
+#[expect(clippy::too_many_arguments)]
+fn replace_regexp_in_string<'ob>(
+    regexp: &Rto<Object>,
+    rep: &Rto<Object>,
+    string: &Rto<Object>,
+    fixedcase: Option<&Rto<Object>>,
+    literal: Option<&Rto<Object>>,
+    subexp: Option<&Rto<Object>>,
+    start: Option<&Rto<Object>>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<Object<'ob>> {
+    // Copy the strings out of the heap, since calling REP can trigger garbage
+    // collection
+    let regexp: &str = regexp.bind(cx).try_into()?;
+    let regexp = regexp.to_owned();
+    let string: &str = string.bind(cx).try_into()?;
+    let string = string.to_owned();
+    let is_set = |x: Option<&Rto<Object>>| x.is_some_and(|x| !x.bind(cx).is_nil());
+    let fixedcase = is_set(fixedcase);
+    let literal = is_set(literal);
+    let subexp: usize = match subexp.map(|x| x.bind(cx)) {
+        Some(x) if !x.is_nil() => x.try_into()?,
+        _ => 0,
+    };
+    let start: usize = match start.map(|x| x.bind(cx)) {
+        Some(x) if !x.is_nil() => x.try_into()?,
+        _ => 0,
+    };
+    let rep_text = match rep.bind(cx).untag() {
+        ObjectType::String(s) => Some(String::from(&**s)),
+        _ => None,
+    };
+    let re = compile_regexp(&regexp, env, cx)?;
+    let saved = env.match_data.clone();
+    let saved_buffer = env.match_buffer.bind(cx);
+    root!(saved_buffer, cx);
 
-    // replace the range beg..end in string with newtext
-    let mut new_string = String::new();
-    new_string.push_str(&string[..beg]);
-    new_string.push_str(newtext);
-    new_string.push_str(&string[end..]);
-    Ok(new_string)
+    let mut result = String::new();
+    let mut pos = char_to_byte(&string, start);
+    while pos < string.len() {
+        let input = Input::new(string.as_str()).range(pos..string.len());
+        let Some(caps) = re.search_forward(&input)? else { break };
+        let whole = caps.get(0).unwrap();
+        let mb = whole.start;
+        // If we matched the empty string, make sure we advance by one char
+        let me = match string[mb..].chars().next() {
+            Some(c) if whole.is_empty() => mb + c.len_utf8(),
+            _ => whole.end,
+        };
+        let matched = &string[mb..me];
+        // positions in the match data are relative to the matched substring
+        let to_char =
+            |byte: usize| byte_to_char(matched, byte.saturating_sub(mb).min(matched.len()));
+        let newtext = match &rep_text {
+            Some(text) => text.clone(),
+            None => {
+                set_match_positions(caps_positions(&caps, to_char), None, env, cx);
+                let func: Function = rep.bind(cx).try_into()?;
+                root!(func, cx);
+                let arg = cx.add(&string[whole.clone()]);
+                let value = call!(func, arg; env, cx)?;
+                let value: &str = value.try_into()?;
+                value.to_owned()
+            }
+        };
+        let Some(target) = caps.get(subexp) else { bail!(subexp_err(subexp)) };
+        let group = |idx: usize| caps.get(idx).map(|r| string[r].to_owned());
+        let new = replacement_text(&newtext, fixedcase, literal, &string[target.clone()], group)?;
+        result.push_str(&string[pos..mb]);
+        result.push_str(&string[mb..target.start.max(mb)]);
+        result.push_str(&new);
+        result.push_str(&string[target.end.min(me).max(mb)..me]);
+        pos = me;
+    }
+    result.push_str(&string[pos.min(string.len())..]);
+    env.match_data = saved;
+    env.match_buffer.set(saved_buffer.bind(cx));
+    Ok(cx.add(result))
 }
 
 #[defun]
-fn regexp_quote(string: &str) -> String {
+pub(crate) fn regexp_quote(string: &str) -> String {
     let mut quoted = String::new();
     for ch in string.chars() {
         if let '[' | '*' | '.' | '\\' | '?' | '+' | '^' | '$' = ch {
@@ -88,118 +576,310 @@ fn regexp_quote(string: &str) -> String {
     quoted
 }
 
-fn lisp_regex_to_rust(regexp: &str) -> String {
-    let mut norm_regex = String::new();
-    let mut chars = regexp.char_indices();
-    while let Some((idx, ch)) = chars.next() {
-        match ch {
-            // Invert the escaping of parens. i.e. \( => ( and ( => \(
-            '(' | ')' | '{' | '}' => {
-                norm_regex.push('\\');
-                norm_regex.push(ch);
-            }
-            '\\' => match chars.next() {
-                Some((_, c @ '('..=')' | c @ '{' | c @ '}')) => norm_regex.push(c),
-                Some((_, '`')) => norm_regex += "\\A",
-                Some((_, '\'')) => norm_regex += "\\z",
-                Some((_, c)) => {
-                    norm_regex.push('\\');
-                    norm_regex.push(c);
-                }
-                None => norm_regex.push('\\'),
-            },
-            '[' => {
-                let word = "[:word:]";
-                if regexp[idx..].starts_with(word) {
-                    chars.nth(word.len() - 2);
-                    norm_regex.push_str("a-zA-Z");
-                } else {
-                    norm_regex.push('[');
-                }
-            }
-            c => norm_regex.push(c),
+/// Return a regexp that matches STRING, where each letter also matches its
+/// accented forms, like `e` matching `é`. LAX is ignored. If FROM is
+/// non-nil, the regexp only matches the part of STRING from that index.
+#[defun]
+fn char_fold_to_regexp(string: &str, _lax: OptionalFlag, from: Option<usize>) -> String {
+    let mut regexp = String::new();
+    for c in string.chars().skip(from.unwrap_or(0)) {
+        let mut accented = rune_regex::accented_chars(c).peekable();
+        if accented.peek().is_some() {
+            regexp.push('[');
+            regexp.push(c);
+            regexp.extend(accented);
+            regexp.push(']');
+        } else {
+            regexp.push_str(&regexp_quote(c.encode_utf8(&mut [0; 4])));
         }
     }
-    norm_regex
+    regexp
 }
 
+/// Return the match data of the last search, which is a list with the start
+/// and end of each group, starting with the whole match. The positions are
+/// always integers, since there are no markers. If INTEGERS is non-nil and
+/// the last search was in a buffer, the buffer is added to the end. If REUSE
+/// is a list that is long enough, the data is stored in it instead, and the
+/// rest of it is set to nil.
 #[defun]
 fn match_data<'ob>(
-    integer: OptionalFlag,
-    reuse: OptionalFlag,
-    reseat: OptionalFlag,
+    integers: OptionalFlag,
+    reuse: Option<Object<'ob>>,
+    _reseat: OptionalFlag,
     env: &Rt<Env>,
     cx: &'ob Context,
 ) -> Result<Object<'ob>> {
-    ensure!(integer.is_none(), "match-data integer field is not implemented");
-    ensure!(reuse.is_none(), "match-data reuse field is not implemented");
-    ensure!(reseat.is_none(), "match-data reseat field is not implemented");
-    Ok(env.match_data.bind(cx))
+    let mut data: Vec<Object> = env
+        .match_data
+        .iter()
+        .flat_map(|x| x.map_or([NIL, NIL], |(beg, end)| [beg.into(), end.into()]))
+        .collect();
+    let buffer = env.match_buffer.bind(cx);
+    if integers.is_some() && !buffer.is_nil() {
+        data.push(buffer);
+    }
+    if let Some(ObjectType::Cons(reuse)) = reuse.map(|x| x.untag()) {
+        let conses: Vec<_> = reuse.conses().collect::<Result<_, _>>()?;
+        if conses.len() >= data.len() {
+            let values = data.into_iter().chain(std::iter::repeat(NIL));
+            for (cons, value) in conses.into_iter().zip(values) {
+                cons.set_car(value)?;
+            }
+            return Ok(reuse.into());
+        }
+    }
+    Ok(crate::fns::slice_into_list(&data, None, cx))
 }
 
+/// Set the match data from LIST, which is like the value of `match-data`. A
+/// buffer at the end of LIST is the buffer that the positions are in.
 #[defun]
-fn set_match_data<'ob>(list: List, _reseat: OptionalFlag, env: &mut Rt<Env>) -> Object<'ob> {
-    // TODO: add reseat when markers implemented
-    let obj: Object = list.into();
-    env.match_data.set(obj);
-    NIL
+fn set_match_data<'ob>(
+    list: List<'ob>,
+    _reseat: OptionalFlag,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    let mut positions = Vec::new();
+    let mut buffer = None;
+    let mut iter = list.elements();
+    while let Some(beg) = iter.next() {
+        let beg = beg?;
+        if let ObjectType::Buffer(x) = beg.untag() {
+            buffer = Some(x);
+            break;
+        }
+        let Some(end) = iter.next() else { break };
+        let end = end?;
+        if beg.is_nil() || end.is_nil() {
+            positions.push(None);
+        } else {
+            positions.push(Some((beg.try_into()?, end.try_into()?)));
+        }
+    }
+    set_match_positions(positions, buffer, env, cx);
+    Ok(NIL)
 }
 
+/// Return the position where group SUBEXP of the last match starts, or nil
+/// if it did not match.
 #[defun]
-fn match_beginning<'ob>(subexp: usize, env: &Rt<Env>, cx: &'ob Context) -> Result<Object<'ob>> {
-    let list = env.match_data.bind(cx).as_list()?;
-    Ok(list.fallible().nth(subexp)?.unwrap_or_default())
+fn match_beginning(subexp: usize, env: &Rt<Env>) -> Option<usize> {
+    env.match_data.get(subexp).copied().flatten().map(|x| x.0)
 }
 
+/// Return the position where group SUBEXP of the last match ends, or nil if
+/// it did not match.
 #[defun]
-fn match_end<'ob>(subexp: usize, env: &Rt<Env>, cx: &'ob Context) -> Result<Object<'ob>> {
-    let list = env.match_data.bind(cx).as_list()?;
-    Ok(list.fallible().nth(subexp + 1)?.unwrap_or_default())
+fn match_end(subexp: usize, env: &Rt<Env>) -> Option<usize> {
+    env.match_data.get(subexp).copied().flatten().map(|x| x.1)
 }
 
 #[defun]
 #[expect(non_snake_case)]
-fn match_data__translate(n: i64, env: &Rt<Env>, cx: &Context) -> Result<()> {
-    let search_regs: List = env.match_data.bind(cx).try_into()?;
-    for reg in search_regs.conses() {
-        let reg = reg?;
-        if let ObjectType::Int(old) = reg.car().untag() {
-            reg.set_car((old + n).into())?;
-        } else {
-            bail!("match data was not int");
-        }
+fn match_data__translate(n: i64, env: &mut Rt<Env>) -> Result<()> {
+    for (beg, end) in env.match_data.iter_mut().flatten() {
+        *beg = usize::try_from(*beg as i64 + n)?;
+        *end = usize::try_from(*end as i64 + n)?;
     }
     Ok(())
 }
 
 #[cfg(test)]
 mod test {
-    use crate::core::gc::RootSet;
-    use rune_core::macros::root;
+    use crate::interpreter::assert_lisp;
 
     use super::*;
 
     #[test]
     fn lisp_regex() {
-        assert_eq!(lisp_regex_to_rust("foo"), "foo");
-        assert_eq!(lisp_regex_to_rust("\\foo"), "\\foo");
-        assert_eq!(lisp_regex_to_rust("\\(foo\\)"), "(foo)");
-        assert_eq!(lisp_regex_to_rust("(foo)"), "\\(foo\\)");
-        assert_eq!(lisp_regex_to_rust("\\`"), "\\A");
-        assert_eq!(lisp_regex_to_rust("\\'"), "\\z");
-        assert_eq!(lisp_regex_to_rust("[[:word:]]"), "[a-zA-Z]");
-        assert_eq!(lisp_regex_to_rust("[[:word:]_]"), "[a-zA-Z_]");
+        let matches =
+            |regexp: &str, text: &str| Regex::new(regexp).unwrap().is_match(text).unwrap();
+        assert!(matches("foo", "foo"));
+        assert!(matches("\\foo", "foo"));
+        assert!(matches("\\(foo\\)", "foo"));
+        assert!(matches("(foo)", "(foo)"));
+        assert!(!matches("(foo)", "foo"));
+        assert!(matches("\\`foo", "foo"));
+        assert!(!matches("\\`foo", "afoo"));
+        assert!(matches("foo\\'", "foo"));
+        assert!(!matches("foo\\'", "foo\n"));
+        assert!(matches("\\`[[:word:]]+\\'", "ab"));
+        assert!(!matches("[[:word:]]", "_"));
+        assert!(matches("[[:word:]_]", "_"));
+    }
+
+    #[test]
+    fn test_string_match() {
+        assert_lisp("(string-match \"b\\\\(a\\\\)r\" \"foo bar\")", "4");
+        assert_lisp(
+            "(progn (string-match \"b\\\\(a\\\\)r\" \"foo bar\") (match-data))",
+            "(4 7 5 6)",
+        );
+        assert_lisp("(progn (string-match \"r\" \"λλr\") (match-end 0))", "3");
+        assert_lisp("(progn (string-match \"a\\\\|\\\\(b\\\\)\" \"a\") (match-data))", "(0 1)");
+        assert_lisp("(progn (string-match \"\\\\(a\\\\)\\\\|b\" \"b\") (match-data))", "(0 1)");
+        assert_lisp("(progn (string-match \"\\\\(a\\\\)?b\" \"b\") (match-beginning 1))", "nil");
+        assert_lisp("(string-match \"o\" \"foo\" 2)", "2");
+        assert_lisp("(string-match \"\\\\`o\" \"foo\" 1)", "nil");
+        assert_lisp("(string-match \"x\" \"foo\")", "nil");
+        assert_lisp("(let ((case-fold-search nil)) (string-match \"A\" \"a\"))", "nil");
+        assert_lisp("(let ((case-fold-search t)) (string-match \"A\" \"a\"))", "0");
+        assert_lisp(
+            "(condition-case err (string-match \"\\\\(\" \"\") (error err))",
+            "(invalid-regexp \"Unmatched ( or \\\\(\")",
+        );
     }
 
     #[test]
     fn test_replace_match() {
-        let roots = &RootSet::default();
-        let cx = &mut Context::new(roots);
-        root!(env, new(Env), cx);
-        let string = "foo bar baz";
-        let newtext = "quux";
-        string_match("bar", string, None, None, env, cx).unwrap();
-        let result = replace_match(newtext, None, None, Some(string), None, env, cx).unwrap();
-        assert_eq!(result, "foo quux baz");
+        assert_lisp(
+            r#"(let ((string "foo bar baz"))
+                 (string-match "bar" string)
+                 (replace-match "quux" nil nil string))"#,
+            r#""foo quux baz""#,
+        );
+        assert_lisp(
+            r#"(let ((string "foo bar baz"))
+                 (string-match "b\\(a\\)r" string)
+                 (list (replace-match "<\\&\\1>" nil nil string)
+                       (replace-match "<\\&>" nil t string 1)))"#,
+            r#"("foo <bara> baz" "foo b<\\&>r baz")"#,
+        );
+    }
+
+    #[test]
+    fn test_case_action() {
+        assert_eq!(case_action("foo"), CaseAction::NoChange);
+        assert_eq!(case_action("FOO"), CaseAction::AllCaps);
+        assert_eq!(case_action("Foo Bar"), CaseAction::CapInitial);
+        assert_eq!(case_action("F"), CaseAction::AllCaps);
+        assert_eq!(apply_case("foo bar", CaseAction::CapInitial), "Foo Bar");
+    }
+
+    #[test]
+    fn test_replace_regexp_in_string() {
+        assert_lisp("(replace-regexp-in-string \"o+\" \"0\" \"foo boo\")", "\"f0 b0\"");
+        assert_lisp(
+            "(replace-regexp-in-string \"\\\\(a\\\\)\\\\(b\\\\)\" \"\\\\2\\\\1\" \"abab\")",
+            "\"baba\"",
+        );
+        assert_lisp("(replace-regexp-in-string \"^\" \"> \" \"a\nb\")", "\"> a\n> b\"");
+        assert_lisp("(replace-regexp-in-string \"x*\" \"-\" \"ab\")", "\"-a-b\"");
+        assert_lisp("(replace-regexp-in-string \"o\" \"0\" \"foo\" nil nil nil 1)", "\"00\"");
+        assert_lisp(
+            "(replace-regexp-in-string \"b\\\\(a\\\\)r\" \"o\" \"bar\" nil nil 1)",
+            "\"bor\"",
+        );
+        assert_lisp("(replace-regexp-in-string \"[a-z]+\" #'upcase \"ab cd\")", "\"AB CD\"");
+    }
+
+    #[test]
+    fn test_re_search() {
+        assert_lisp(
+            "(progn (insert \"foo bar baz\") (goto-char 1) (list (re-search-forward \"ba\\\\(.\\\\)\") (match-data) (point)))",
+            "(8 (5 8 7 8) 8)",
+        );
+        assert_lisp(
+            "(progn (insert \"foo bar baz\") (list (re-search-backward \"ba.\") (match-data) (point)))",
+            "(9 (9 12) 9)",
+        );
+        assert_lisp(
+            "(progn (insert \"foo bar baz\") (goto-char 1) (re-search-forward \"ba.\" nil nil 2))",
+            "12",
+        );
+        assert_lisp(
+            "(progn (insert \"foo\") (goto-char 1) (re-search-forward \"x\" nil t))",
+            "nil",
+        );
+        assert_lisp(
+            "(progn (insert \"foo\") (goto-char 1) (condition-case err (re-search-forward \"x\") (error (car err))))",
+            "search-failed",
+        );
+        assert_lisp("(progn (insert \"foo\") (goto-char 2) (looking-at \"o+\"))", "t");
+        assert_lisp("(progn (insert \"foo\") (goto-char 2) (looking-at \"f\"))", "nil");
+    }
+
+    #[test]
+    fn test_search_literal() {
+        assert_lisp(
+            "(progn (insert \"a.b a.b\") (goto-char 1) (list (search-forward \"a.b\") (match-data)))",
+            "(4 (1 4))",
+        );
+        assert_lisp(
+            "(progn (insert \"a.b a.b\") (list (search-backward \"a.b\") (point)))",
+            "(5 5)",
+        );
+        assert_lisp(
+            "(progn (insert \"x [y] [y]\") (goto-char 1) (search-forward \"[y]\" nil nil 2))",
+            "10",
+        );
+        assert_lisp(
+            "(progn (insert \"foo FOO\") (goto-char 1) (let ((case-fold-search nil)) (search-forward \"FOO\")))",
+            "8",
+        );
+        assert_lisp(
+            "(progn (insert \"foo FOO\") (goto-char 1) (let ((case-fold-search t)) (search-forward \"FOO\")))",
+            "4",
+        );
+        assert_lisp(
+            "(progn (insert \"foo bar\") (goto-char 1) (list (search-forward \"bar\" 5 1) (point)))",
+            "(nil 5)",
+        );
+        assert_lisp(
+            "(progn (insert \"foo\") (goto-char 1) (condition-case err (search-forward \"x\") (error err)))",
+            "(search-failed \"x\")",
+        );
+    }
+
+    #[test]
+    fn test_looking_at_p() {
+        assert_lisp("(progn (insert \"foo bar\") (goto-char 4) (looking-at-p \" bar\"))", "t");
+        assert_lisp("(progn (insert \"foo bar\") (goto-char 4) (looking-at-p \" ba[rz]\"))", "t");
+        assert_lisp("(progn (insert \"foo\") (goto-char 1) (looking-at-p \"oox\"))", "nil");
+        assert_lisp(
+            "(progn (insert \"foo\") (goto-char 1) (string-match \"b\" \"ab\") (looking-at-p \"f.o\") (match-data))",
+            "(1 2)",
+        );
+    }
+
+    #[test]
+    fn test_char_fold_to_regexp() {
+        assert_lisp(
+            r#"(let ((regexp (char-fold-to-regexp "cafe.")))
+                 (list (string-match regexp "un café.") (string-match regexp "un cafe.")
+                       (string-match regexp "un cafex") (char-fold-to-regexp "x.-" nil 1)))"#,
+            r#"(3 3 nil "\\.-")"#,
+        );
+    }
+
+    #[test]
+    fn test_match_data() {
+        assert_lisp(
+            r#"(progn
+                 (insert "foo bar")
+                 (goto-char 1)
+                 (re-search-forward "b\\(a\\)r")
+                 (let ((outer (match-data t)))
+                   (save-match-data (string-match "o" "xo"))
+                   (condition-case nil
+                       (save-match-data (string-match "x" "x") (signal 'error nil))
+                     (error nil))
+                   (list (equal (match-data t) outer) (eq (nth 4 outer) (current-buffer))
+                         (match-data)
+                         (let ((reuse (list 0 0 0 0 0))) (match-data nil reuse) reuse)
+                         (progn (set-match-data (list 1 2 nil nil 3 4))
+                                (list (match-beginning 2) (match-end 1) (match-data))))))"#,
+            "(t t (5 8 6 7) (5 8 6 7 nil) (3 nil (1 2 nil nil 3 4)))",
+        );
+    }
+
+    #[test]
+    fn test_replace_match_buffer() {
+        assert_lisp(
+            "(progn (insert \"foo bar\") (goto-char 1) (re-search-forward \"b\\\\(a\\\\)r\") (replace-match \"[\\\\1]\") (goto-char 1) (re-search-forward \"\\\\[a]$\"))",
+            "8",
+        );
     }
 }
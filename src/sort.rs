@@ -0,0 +1,265 @@
+//! Sorting and reversing the lines of a region.
+//!
+//! The lines are rearranged in a string and the part of the region that
+//! changed is replaced in one edit, so the undo list gets a single change and
+//! markers outside of it stay where they were.
+use crate::core::{
+    env::{Env, sym},
+    gc::{Context, Rt, Rto},
+    object::{Object, ObjectType, OpenBuffer},
+};
+use crate::editfns::{delete_region, insert_text};
+use crate::library::number;
+use anyhow::{Result, anyhow};
+use rune_macros::defun;
+use std::cmp::Ordering;
+
+defvar!(SORT_FOLD_CASE);
+defvar!(SORT_NUMERIC_BASE, 10);
+
+/// Call `func` with the lines of the region between `beg` and `end` to
+/// rearrange them, and replace the region with the result. The newline at the
+/// end of the region, if there is one, stays at the end.
+fn rearrange_lines(
+    beg: usize,
+    end: usize,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+    func: impl FnOnce(&mut Vec<&str>) -> Result<()>,
+) -> Result<()> {
+    let (beg, end) = (beg.min(end), beg.max(end));
+    let old = {
+        let (s1, s2) = env.current_buffer.get().slice_with_gap(beg, end)?;
+        format!("{s1}{s2}")
+    };
+    let body = old.strip_suffix('\n');
+    let mut lines: Vec<&str> = body.unwrap_or(&old).split('\n').collect();
+    func(&mut lines)?;
+    let mut new = lines.join("\n");
+    if body.is_some() {
+        new.push('\n');
+    }
+
+    let (old, new): (Vec<char>, Vec<char>) = (old.chars().collect(), new.chars().collect());
+    let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b);
+    let changed = prefix..old.len() - suffix.count();
+    if changed.is_empty() {
+        return Ok(());
+    }
+    let text: String = new[changed.clone()].iter().collect();
+    let start = beg + changed.start;
+    let excursion = env.current_buffer.get_mut().save_excursion();
+    let result = delete_region(start, beg + changed.end, env, cx).and_then(|()| {
+        env.current_buffer.get_mut().goto_char(start);
+        insert_text(&text, env, cx)
+    });
+    env.current_buffer.get_mut().restore_excursion(excursion);
+    result
+}
+
+/// Stably sort `lines` by the keys that `key` gives them, in descending order
+/// if `reverse` is true.
+fn sort_by_key<K>(
+    lines: &mut Vec<&str>,
+    reverse: bool,
+    key: impl Fn(&str) -> Result<K>,
+    compare: impl Fn(&K, &K) -> Ordering,
+) -> Result<()> {
+    let mut keyed = lines.iter().map(|line| Ok((key(line)?, *line))).collect::<Result<Vec<_>>>()?;
+    if reverse {
+        keyed.sort_by(|a, b| compare(&b.0, &a.0));
+    } else {
+        keyed.sort_by(|a, b| compare(&a.0, &b.0));
+    }
+    *lines = keyed.into_iter().map(|(_, line)| line).collect();
+    Ok(())
+}
+
+/// Field FIELD of `line`, where fields are separated by spaces and tabs. The
+/// first field is 1, and negative fields count from the end of the line.
+fn line_field(line: &str, field: i64) -> Result<&str> {
+    let fields: Vec<&str> = line.split([' ', '\t']).filter(|x| !x.is_empty()).collect();
+    let idx = match field {
+        0 => 0,
+        1.. => field - 1,
+        _ => fields.len() as i64 + field,
+    };
+    usize::try_from(idx)
+        .ok()
+        .and_then(|idx| fields.get(idx).copied())
+        .ok_or_else(|| anyhow!("Line has too few fields: {line}"))
+}
+
+/// The number at the start of `field`. A `0x` prefix makes it hexadecimal and
+/// a leading 0 octal, and otherwise it is read in `base`.
+fn field_number(field: &str, base: u32) -> f64 {
+    let (digits, base) = match field.as_bytes() {
+        [b'0', b'x' | b'X', digit, ..] if digit.is_ascii_hexdigit() => (&field[2..], 16),
+        [b'0', b'0'..=b'7', ..] => (&field[1..], 8),
+        _ => (field, base),
+    };
+    match number::parse_prefix(digits, base) {
+        Some((number::Number::Int(x), _)) => x as f64,
+        Some((number::Number::Float(x), _)) => x,
+        None => 0.0,
+    }
+}
+
+fn fold_case(env: &Rt<Env>, cx: &Context) -> bool {
+    env.vars.get(sym::SORT_FOLD_CASE).is_some_and(|x| !x.bind(cx).is_nil())
+}
+
+/// Sort the lines in the region between BEG and END alphabetically, or in
+/// reverse order if REVERSE is non-nil. Case is ignored if `sort-fold-case`
+/// is non-nil. Lines that compare equal keep their order.
+#[defun]
+fn sort_lines(
+    reverse: &Rto<Object>,
+    beg: usize,
+    end: usize,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<()> {
+    let (reverse, fold) = (!reverse.bind(cx).is_nil(), fold_case(env, cx));
+    rearrange_lines(beg, end, env, cx, |lines| {
+        let key = |line: &str| Ok(if fold { line.to_lowercase() } else { line.to_owned() });
+        sort_by_key(lines, reverse, key, Ord::cmp)
+    })
+}
+
+/// Sort the lines in the region between BEG and END by field FIELD of each
+/// line, compared alphabetically. Fields are separated by spaces and tabs,
+/// the first field is 1, and a negative FIELD counts from the end of the
+/// line. Signal an error if a line has too few fields.
+#[defun]
+fn sort_fields(
+    field: &Rto<Object>,
+    beg: usize,
+    end: usize,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<()> {
+    let field: i64 = field.bind(cx).try_into()?;
+    let fold = fold_case(env, cx);
+    rearrange_lines(beg, end, env, cx, |lines| {
+        let key = |line: &str| {
+            let field = line_field(line, field)?;
+            Ok(if fold { field.to_lowercase() } else { field.to_owned() })
+        };
+        sort_by_key(lines, false, key, Ord::cmp)
+    })
+}
+
+/// Sort the lines in the region between BEG and END by the number at the
+/// start of field FIELD of each line. Numbers that start with `0x` are read
+/// as hexadecimal and ones that start with 0 as octal, and others are read in
+/// the base `sort-numeric-base`. A field that is not a number counts as 0.
+/// See `sort-fields` for how fields are counted.
+#[defun]
+fn sort_numeric_fields(
+    field: &Rto<Object>,
+    beg: usize,
+    end: usize,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<()> {
+    let field: i64 = field.bind(cx).try_into()?;
+    let base = match env.vars.get(sym::SORT_NUMERIC_BASE).map(|x| x.bind(cx).untag()) {
+        Some(ObjectType::Int(x @ 2..=16)) => x as u32,
+        _ => 10,
+    };
+    rearrange_lines(beg, end, env, cx, |lines| {
+        let key = |line: &str| Ok(field_number(line_field(line, field)?, base));
+        sort_by_key(lines, false, key, f64::total_cmp)
+    })
+}
+
+/// The largest part of the region between `beg` and `end` that starts at
+/// the beginning of a line and ends at the end of one.
+fn whole_lines(beg: usize, end: usize, buffer: &OpenBuffer) -> (usize, usize) {
+    let text = &buffer.text;
+    let bolp = |pos: usize| pos == buffer.point_min() || text.char_at(pos - 2) == Some('\n');
+    let eolp = |pos: usize| pos == buffer.point_max() || text.char_at(pos - 1) == Some('\n');
+    let beg = if bolp(beg) {
+        beg
+    } else {
+        let line = text.char_to_line(beg - 1) + 1;
+        if line < text.len_lines() {
+            (text.line_to_char(line) + 1).min(buffer.point_max())
+        } else {
+            buffer.point_max()
+        }
+    };
+    let end = if eolp(end) && !bolp(end) {
+        end
+    } else {
+        // the end of the line before
+        text.line_to_char(text.char_to_line(end - 1))
+    };
+    (beg, end.max(beg))
+}
+
+/// Reverse the order of the lines in the region between BEG and END. Only
+/// the lines that are wholly in the region are moved.
+#[defun]
+fn reverse_region(beg: usize, end: usize, env: &mut Rt<Env>, cx: &mut Context) -> Result<()> {
+    let (beg, end) = {
+        let buffer = env.current_buffer.get();
+        let (beg, end) = (buffer.in_range(beg)? + 1, buffer.in_range(end)? + 1);
+        whole_lines(beg.min(end), beg.max(end), buffer)
+    };
+    rearrange_lines(beg, end, env, cx, |lines| {
+        lines.reverse();
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::interpreter::assert_lisp;
+
+    #[test]
+    fn test_sort_lines() {
+        assert_lisp(
+            r#"(progn (insert "pear\nApple\nfig\napple\n")
+                      (sort-lines nil (point-min) (point-max))
+                      (buffer-string))"#,
+            r#""Apple\napple\nfig\npear\n""#,
+        );
+        assert_lisp(
+            r#"(let ((sort-fold-case t))
+                 (insert "b\nB\na\nA")
+                 (sort-lines t (point-min) (point-max))
+                 (buffer-string))"#,
+            r#""b\nB\na\nA""#,
+        );
+    }
+
+    #[test]
+    fn test_sort_fields() {
+        assert_lisp(
+            r#"(progn (insert "x  c 1\ny\tb 0x10\nz a 010")
+                      (list (progn (sort-fields 2 (point-min) (point-max)) (buffer-string))
+                            (progn (sort-numeric-fields -1 (point-min) (point-max))
+                                   (buffer-string))
+                            (condition-case nil (sort-fields 4 (point-min) (point-max))
+                              (error 'too-few))))"#,
+            r#"("z a 010\ny\tb 0x10\nx  c 1" "x  c 1\nz a 010\ny\tb 0x10" too-few)"#,
+        );
+    }
+
+    #[test]
+    fn test_reverse_region() {
+        assert_lisp(
+            r#"(progn (insert "one\ntwo\nthree\nfour\n")
+                      (reverse-region 2 (point-max))
+                      (buffer-string))"#,
+            r#""one\nfour\nthree\ntwo\n""#,
+        );
+    }
+}
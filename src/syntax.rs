@@ -0,0 +1,1494 @@
+//! Syntax tables and the scanning engine built on them.
+//!
+//! Each buffer has a syntax table, which is the standard syntax table until it
+//! sets its own with `set-syntax-table`. A syntax table is a char-table that
+//! inherits the entries it doesn't set from its parent. Entries are raw syntax
+//! descriptors of the form `(CODE . MATCHING-CHAR)`, where the low 16 bits of
+//! CODE are the syntax class and the bits above them are the flags. Characters
+//! that no table in the chain sets have the syntax they have in the standard
+//! syntax table of Emacs.
+//!
+//! The scanner moves over the two chunks of text on either side of the gap of
+//! the buffer without copying them. Comments are delimited by single
+//! characters with comment starter and ender syntax, or comment fences; the
+//! two-character comment delimiters described by the flags 1-4 are not
+//! recognized yet.
+//!
+//! `syntax-ppss` keeps the states of a parse from the start of the buffer at
+//! line starts along the way, so later calls only parse from the last state
+//! before the position they want. Edits drop the states after the changed
+//! text, and changing the syntax table drops all of them.
+use crate::core::{
+    cons::Cons,
+    env::{ArgSlice, Env, sym},
+    gc::{Context, Rt},
+    object::{
+        CharTable, CharTableInner, Gc, NIL, Object, ObjectType, OpenBuffer, TRUE, char_to_int,
+        int_to_char,
+    },
+};
+use crate::eval::EvalError;
+use anyhow::{Result, bail, ensure};
+use rune_core::macros::list;
+use rune_macros::defun;
+
+defvar!(PARSE_SEXP_IGNORE_COMMENTS);
+defsym!(SCAN_ERROR);
+
+/// The syntax classes, in the order of their codes.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Class {
+    Whitespace,
+    Punct,
+    Word,
+    Symbol,
+    Open,
+    Close,
+    Quote,
+    String,
+    Math,
+    Escape,
+    CharQuote,
+    Comment,
+    EndComment,
+    Inherit,
+    CommentFence,
+    StringFence,
+}
+
+const CLASSES: [Class; 16] = [
+    Class::Whitespace,
+    Class::Punct,
+    Class::Word,
+    Class::Symbol,
+    Class::Open,
+    Class::Close,
+    Class::Quote,
+    Class::String,
+    Class::Math,
+    Class::Escape,
+    Class::CharQuote,
+    Class::Comment,
+    Class::EndComment,
+    Class::Inherit,
+    Class::CommentFence,
+    Class::StringFence,
+];
+
+/// The designator character of each class, indexed by its code.
+const CLASS_CHARS: [char; 16] =
+    [' ', '.', 'w', '_', '(', ')', '\'', '"', '$', '\\', '/', '<', '>', '@', '!', '|'];
+
+/// The flag characters of syntax descriptors and the bits they set in the
+/// syntax code.
+const FLAGS: [(char, i64); 8] = [
+    ('1', 1 << 16),
+    ('2', 1 << 17),
+    ('3', 1 << 18),
+    ('4', 1 << 19),
+    ('p', 1 << 20),
+    ('b', 1 << 21),
+    ('n', 1 << 22),
+    ('c', 1 << 23),
+];
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct Syntax {
+    class: Class,
+    matching: Option<char>,
+    flags: i64,
+}
+
+impl Syntax {
+    const fn new(class: Class, matching: Option<char>) -> Self {
+        Self { class, matching, flags: 0 }
+    }
+
+    /// Parse a raw syntax descriptor, which is nil for characters whose syntax
+    /// is inherited from the parent table.
+    fn from_raw(raw: Object) -> Option<Self> {
+        let ObjectType::Cons(cons) = raw.untag() else {
+            return None;
+        };
+        let ObjectType::Int(code) = cons.car().untag() else {
+            return None;
+        };
+        let class = *CLASSES.get((code & 0xFFFF) as usize)?;
+        let matching = match cons.cdr().untag() {
+            ObjectType::Int(c) => int_to_char(c).ok(),
+            _ => None,
+        };
+        Some(Self { class, matching, flags: code & !0xFFFF })
+    }
+
+    /// Parse a syntax descriptor string like "()" or ". 12b".
+    fn parse(desc: &str) -> Result<Self> {
+        let mut chars = desc.chars();
+        let class = match chars.next() {
+            Some('-') => Class::Whitespace,
+            Some(c) => match CLASS_CHARS.iter().position(|x| *x == c) {
+                Some(code) => CLASSES[code],
+                None => bail!("Invalid syntax description letter: {c}"),
+            },
+            None => bail!("Empty syntax descriptor"),
+        };
+        let matching = chars.next().filter(|c| *c != ' ');
+        let mut flags = 0;
+        for c in chars {
+            if let Some((_, bit)) = FLAGS.iter().find(|x| x.0 == c) {
+                flags |= bit;
+            }
+        }
+        Ok(Self { class, matching, flags })
+    }
+
+    fn to_raw<'ob>(self, cx: &'ob Context) -> Object<'ob> {
+        let code = self.class as i64 | self.flags;
+        Cons::new(code, self.matching.map(char_to_int), cx).into()
+    }
+
+    fn prefix(self) -> bool {
+        self.flags & (1 << 20) != 0
+    }
+
+    fn nested(self) -> bool {
+        self.flags & (1 << 22) != 0
+    }
+
+    /// The style of a comment delimiter: 0 for style a, 1 for b and 2 for c.
+    fn comment_style(self) -> u8 {
+        u8::from(self.flags & (1 << 21) != 0) | (u8::from(self.flags & (1 << 23) != 0) << 1)
+    }
+}
+
+/// The syntax of characters that no syntax table sets, which is the syntax
+/// of the standard syntax table of Emacs.
+fn default_syntax(chr: char) -> Syntax {
+    match chr {
+        'a'..='z' | 'A'..='Z' | '0'..='9' | '$' | '%' => Syntax::new(Class::Word, None),
+        '(' => Syntax::new(Class::Open, Some(')')),
+        ')' => Syntax::new(Class::Close, Some('(')),
+        '[' => Syntax::new(Class::Open, Some(']')),
+        ']' => Syntax::new(Class::Close, Some('[')),
+        '{' => Syntax::new(Class::Open, Some('}')),
+        '}' => Syntax::new(Class::Close, Some('{')),
+        '"' => Syntax::new(Class::String, None),
+        '\\' => Syntax::new(Class::Escape, None),
+        '_' | '-' | '+' | '*' | '/' | '&' | '|' | '<' | '>' | '=' => {
+            Syntax::new(Class::Symbol, None)
+        }
+        '.' | ',' | ';' | ':' | '?' | '!' | '#' | '@' | '~' | '^' | '\'' | '`' => {
+            Syntax::new(Class::Punct, None)
+        }
+        c if !c.is_ascii() => Syntax::new(Class::Word, None),
+        _ => Syntax::new(Class::Whitespace, None),
+    }
+}
+
+/// The syntax table of a buffer, with the standard table that entries with
+/// the inherit class refer to.
+#[derive(Copy, Clone)]
+struct SyntaxTable<'ob> {
+    table: &'ob CharTable,
+    standard: &'ob CharTable,
+}
+
+impl<'ob> SyntaxTable<'ob> {
+    fn current(env: &mut Rt<Env>, cx: &'ob Context) -> Self {
+        Self { table: syntax_table(env, cx), standard: standard_table(env, cx) }
+    }
+
+    fn lookup(table: &CharTable, chr: char) -> Option<Syntax> {
+        let idx = char_to_int(chr) as usize;
+        let mut table = Some(table);
+        while let Some(current) = table {
+            if let Some(syntax) = Syntax::from_raw(current.get(idx)) {
+                return Some(syntax);
+            }
+            table = current.parent();
+        }
+        None
+    }
+
+    fn get(&self, chr: char) -> Syntax {
+        match Self::lookup(self.table, chr) {
+            Some(syntax) if syntax.class != Class::Inherit => syntax,
+            _ => match Self::lookup(self.standard, chr) {
+                Some(syntax) if syntax.class != Class::Inherit => syntax,
+                _ => default_syntax(chr),
+            },
+        }
+    }
+
+    fn class(&self, chr: char) -> Class {
+        self.get(chr).class
+    }
+}
+
+/// The standard syntax table, which is created the first time it is needed.
+fn standard_table<'ob>(env: &mut Rt<Env>, cx: &'ob Context) -> &'ob CharTable {
+    if let ObjectType::CharTable(table) = env.standard_syntax_table.untag(cx) {
+        return table;
+    }
+    let table: Gc<&CharTable> = cx.add_as(CharTableInner::new(None));
+    env.standard_syntax_table.set(Object::from(table));
+    table.untag()
+}
+
+/// Return the standard syntax table, which is used by buffers that have not
+/// set their own.
+#[defun]
+fn standard_syntax_table<'ob>(env: &mut Rt<Env>, cx: &'ob Context) -> &'ob CharTable {
+    standard_table(env, cx)
+}
+
+/// Return the syntax table of the current buffer.
+#[defun]
+pub(crate) fn syntax_table<'ob>(env: &mut Rt<Env>, cx: &'ob Context) -> &'ob CharTable {
+    match env.buffer_slot(sym::SYNTAX_TABLE, cx).map(|x| x.untag()) {
+        Some(ObjectType::CharTable(table)) => table,
+        _ => standard_table(env, cx),
+    }
+}
+
+/// Make TABLE the syntax table of the current buffer.
+#[defun]
+fn set_syntax_table<'ob>(
+    table: &'ob CharTable,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> &'ob CharTable {
+    env.set_buffer_slot(sym::SYNTAX_TABLE, Object::from(table), cx);
+    env.current_buffer.get_mut().syntax_cache.clear();
+    table
+}
+
+/// Return a new syntax table that inherits from OLDTABLE, or from the standard
+/// syntax table if OLDTABLE is nil.
+#[defun]
+fn make_syntax_table<'ob>(
+    oldtable: Option<&'ob CharTable>,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> &'ob CharTable {
+    let parent = oldtable.unwrap_or_else(|| standard_table(env, cx));
+    let table: Gc<&CharTable> = cx.add_as(CharTableInner::new(None));
+    let table = table.untag();
+    table.set_parent(Some(parent));
+    table
+}
+
+/// Convert a character argument to an index into a syntax table.
+fn char_index(chr: Object) -> Result<usize> {
+    match chr.untag() {
+        ObjectType::Int(c) => Ok(char_to_int(int_to_char(c)?) as usize),
+        _ => bail!("Wrong type argument: characterp, {chr}"),
+    }
+}
+
+/// Set the syntax of CHAR in TABLE, or the syntax table of the current buffer,
+/// to NEWENTRY. CHAR can be a cons `(MIN . MAX)` to set the syntax of a range
+/// of characters. NEWENTRY is a syntax descriptor string like "w" or ". 12b",
+/// or a raw syntax descriptor.
+#[defun]
+fn modify_syntax_entry<'ob>(
+    char: Object<'ob>,
+    newentry: Object<'ob>,
+    table: Option<&'ob CharTable>,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<()> {
+    let table = match table {
+        Some(table) => table,
+        None => syntax_table(env, cx),
+    };
+    let raw = match newentry.untag() {
+        ObjectType::String(desc) => Syntax::parse(desc)?.to_raw(cx),
+        _ => newentry,
+    };
+    let (min, max) = match char.untag() {
+        ObjectType::Cons(range) => (char_index(range.car())?, char_index(range.cdr())?),
+        _ => (char_index(char)?, char_index(char)?),
+    };
+    for idx in min..=max {
+        table.set(idx, raw);
+    }
+    env.current_buffer.get_mut().syntax_cache.clear();
+    Ok(())
+}
+
+/// Return the designator character of the syntax class of CHARACTER in the
+/// syntax table of the current buffer.
+#[defun]
+fn char_syntax(character: char, env: &mut Rt<Env>, cx: &Context) -> char {
+    CLASS_CHARS[SyntaxTable::current(env, cx).class(character) as usize]
+}
+
+/// Convert the syntax descriptor string SYNTAX to a raw syntax descriptor.
+#[defun]
+fn string_to_syntax<'ob>(syntax: &str, cx: &'ob Context) -> Result<Object<'ob>> {
+    Ok(Syntax::parse(syntax)?.to_raw(cx))
+}
+
+/// Return the designator character of the syntax class with code SYNTAX.
+#[defun]
+fn syntax_class_to_char(syntax: usize) -> Result<char> {
+    match CLASS_CHARS.get(syntax) {
+        Some(chr) => Ok(*chr),
+        None => bail!("Args out of range: {syntax}"),
+    }
+}
+
+/// Return the character that matches CHARACTER if it is a paren in the syntax
+/// table of the current buffer, or nil otherwise.
+#[defun]
+fn matching_paren(character: char, env: &mut Rt<Env>, cx: &Context) -> Option<char> {
+    let syntax = SyntaxTable::current(env, cx).get(character);
+    match syntax.class {
+        Class::Open | Class::Close => syntax.matching,
+        _ => None,
+    }
+}
+
+/// Move point backward over any characters with prefix syntax, which are the
+/// characters of the quote class or with the p flag.
+#[defun]
+fn backward_prefix_chars(env: &mut Rt<Env>, cx: &Context) {
+    let pos = with_scanner(None, env, cx, |scanner| {
+        let syntax = scanner.syntax;
+        let cursor = &mut scanner.cursor;
+        while let Some(c) = cursor.char_before() {
+            let prefix = syntax.get(c);
+            if prefix.class != Class::Quote && !prefix.prefix() {
+                break;
+            }
+            cursor.backward();
+        }
+        cursor.pos
+    });
+    env.current_buffer.get_mut().goto_char(pos);
+}
+
+/// A position in the accessible portion of a buffer, which moves over the text
+/// on both sides of the gap.
+#[derive(Copy, Clone)]
+struct Cursor<'a> {
+    chunks: (&'a str, &'a str),
+    /// The byte offset of the position from the start of the first chunk
+    byte: usize,
+    pos: usize,
+    zv: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(buffer: &'a OpenBuffer, pos: usize) -> Self {
+        Self::with_bounds(buffer, buffer.point_min(), buffer.point_max(), pos)
+    }
+
+    /// A cursor that moves between the positions `begv` and `zv`.
+    fn with_bounds(buffer: &'a OpenBuffer, begv: usize, zv: usize, pos: usize) -> Self {
+        let pos = pos.clamp(begv, zv);
+        let (s1, s2) = buffer.text.slice(begv - 1..pos - 1);
+        let chunks = buffer.text.slice(begv - 1..zv - 1);
+        Self { chunks, byte: s1.len() + s2.len(), pos, zv }
+    }
+
+    fn char_after(&self) -> Option<char> {
+        let (s1, s2) = self.chunks;
+        match s1.get(self.byte..).filter(|x| !x.is_empty()) {
+            Some(rest) => rest.chars().next(),
+            None => s2[self.byte - s1.len()..].chars().next(),
+        }
+    }
+
+    fn char_before(&self) -> Option<char> {
+        let (s1, s2) = self.chunks;
+        if self.byte > s1.len() {
+            s2[..self.byte - s1.len()].chars().next_back()
+        } else {
+            s1[..self.byte].chars().next_back()
+        }
+    }
+
+    fn forward(&mut self) -> Option<char> {
+        let chr = self.char_after()?;
+        self.byte += chr.len_utf8();
+        self.pos += 1;
+        Some(chr)
+    }
+
+    fn backward(&mut self) -> Option<char> {
+        let chr = self.char_before()?;
+        self.byte -= chr.len_utf8();
+        self.pos -= 1;
+        Some(chr)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum CommentStyle {
+    /// A comment started by a comment starter of the style
+    Plain(u8),
+    /// A comment between two comment fences
+    Fence,
+}
+
+impl Syntax {
+    /// The style of the comment that the character starts, and its initial
+    /// nesting, which is -1 for comments that don't nest.
+    fn comment_start(self) -> Option<(CommentStyle, i64)> {
+        match self.class {
+            Class::Comment => {
+                let nesting = if self.nested() { 1 } else { -1 };
+                Some((CommentStyle::Plain(self.comment_style()), nesting))
+            }
+            Class::CommentFence => Some((CommentStyle::Fence, -1)),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum StringEnd {
+    /// A string that ends at the character it started with
+    Char(char),
+    /// A string between two string fences
+    Fence,
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+struct Level {
+    /// The start of the last expression at this depth
+    last: Option<usize>,
+    /// The start of the last complete expression at this depth
+    prev: Option<usize>,
+}
+
+/// The state of a parse, as described by `parse-partial-sexp`.
+#[derive(Clone, Debug)]
+struct ParseState {
+    depth: i64,
+    min_depth: i64,
+    string: Option<StringEnd>,
+    /// The nesting of the comment the parse is in, which is -1 for comments
+    /// that don't nest
+    comment: Option<i64>,
+    comment_style: CommentStyle,
+    /// Whether the parse stopped right after an escape character
+    quoted: bool,
+    /// The start of the string or comment the parse is in
+    comstr_start: Option<usize>,
+    /// One level for each open list and one for the top level
+    levels: Vec<Level>,
+}
+
+impl Default for ParseState {
+    fn default() -> Self {
+        Self {
+            depth: 0,
+            min_depth: 0,
+            string: None,
+            comment: None,
+            comment_style: CommentStyle::Plain(0),
+            quoted: false,
+            comstr_start: None,
+            levels: vec![Level::default()],
+        }
+    }
+}
+
+impl ParseState {
+    /// Read the state returned by an earlier call to `parse-partial-sexp`.
+    fn from_lisp(state: Object) -> Result<Self> {
+        let items: Vec<Object> = state.as_list()?.collect::<Result<_, _>>()?;
+        let item = |idx: usize| items.get(idx).copied().unwrap_or(NIL);
+        let mut new = Self::default();
+        if let ObjectType::Int(depth) = item(0).untag() {
+            new.depth = depth;
+            new.min_depth = depth;
+        }
+        new.string = match item(3).untag() {
+            ObjectType::Int(c) => Some(StringEnd::Char(int_to_char(c)?)),
+            _ if item(3).is_nil() => None,
+            _ => Some(StringEnd::Fence),
+        };
+        new.comment = match item(4).untag() {
+            ObjectType::Int(nesting) => Some(nesting),
+            _ if item(4).is_nil() => None,
+            _ => Some(-1),
+        };
+        new.quoted = !item(5).is_nil();
+        new.comment_style = match item(7).untag() {
+            ObjectType::Int(style) => CommentStyle::Plain(style.try_into()?),
+            _ if item(7) == sym::SYNTAX_TABLE => CommentStyle::Fence,
+            _ => CommentStyle::Plain(0),
+        };
+        if let ObjectType::Int(start) = item(8).untag() {
+            new.comstr_start = Some(start.try_into()?);
+        }
+        for open in item(9).as_list()? {
+            new.level().last = Some(open?.try_into()?);
+            new.levels.push(Level::default());
+        }
+        Ok(new)
+    }
+
+    fn to_lisp<'ob>(&self, cx: &'ob Context) -> Object<'ob> {
+        let outer = &self.levels[..self.levels.len() - 1];
+        let opens: Vec<Object> = outer.iter().filter_map(|x| x.last).map(|x| cx.add(x)).collect();
+        let string = match self.string {
+            Some(StringEnd::Char(c)) => cx.add(c),
+            Some(StringEnd::Fence) => TRUE,
+            None => NIL,
+        };
+        let comment = match self.comment {
+            Some(-1) => TRUE,
+            Some(nesting) => cx.add(nesting),
+            None => NIL,
+        };
+        let style = match (self.comment, self.comment_style) {
+            (Some(_), CommentStyle::Plain(style)) if style != 0 => cx.add(i64::from(style)),
+            (Some(_), CommentStyle::Fence) => sym::SYNTAX_TABLE.into(),
+            _ => NIL,
+        };
+        list![
+            self.depth,
+            outer.last().and_then(|x| x.last),
+            self.levels.last().unwrap().prev,
+            string,
+            comment,
+            self.quoted,
+            self.min_depth,
+            style,
+            self.comstr_start,
+            crate::fns::slice_into_list(&opens, None, cx),
+            NIL;
+            cx
+        ]
+    }
+
+    /// The innermost level.
+    fn level(&mut self) -> &mut Level {
+        self.levels.last_mut().unwrap()
+    }
+}
+
+/// Where `parse-partial-sexp` stops at comments and strings.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+enum CommentStop {
+    #[default]
+    Never,
+    /// After the start of a comment
+    Start,
+    /// After the start or end of a comment or string
+    Boundary,
+}
+
+/// The conditions that stop a parse before it reaches its end.
+#[derive(Copy, Clone, Debug, Default)]
+struct Stop {
+    target_depth: Option<i64>,
+    before_sexp: bool,
+    comment: CommentStop,
+}
+
+/// The `scan-error` that a scan signals.
+struct ScanError {
+    message: &'static str,
+    last_good: usize,
+    pos: usize,
+}
+
+impl ScanError {
+    fn unbalanced(last_good: usize, pos: usize) -> Self {
+        Self { message: "Unbalanced parentheses", last_good, pos }
+    }
+
+    fn premature(last_good: usize, pos: usize) -> Self {
+        Self { message: "Containing expression ends prematurely", last_good, pos }
+    }
+
+    fn signal(self, env: &mut Rt<Env>, cx: &Context) -> anyhow::Error {
+        let data = list![cx.add(self.message), self.last_good, self.pos; cx];
+        EvalError::signal(sym::SCAN_ERROR.into(), data, env).into()
+    }
+}
+
+#[derive(Copy, Clone)]
+struct Scanner<'a> {
+    syntax: SyntaxTable<'a>,
+    cursor: Cursor<'a>,
+    ignore_comments: bool,
+}
+
+impl Scanner<'_> {
+    /// Whether the character after `cursor` is quoted by an odd number of
+    /// escape characters before it.
+    fn quoted(&self, mut cursor: Cursor) -> bool {
+        let mut quoted = false;
+        while let Some(c) = cursor.char_before() {
+            if !matches!(self.syntax.class(c), Class::Escape | Class::CharQuote) {
+                break;
+            }
+            quoted = !quoted;
+            cursor.backward();
+        }
+        quoted
+    }
+
+    /// Move over `count` words, backward if it is negative. Returns false if
+    /// the edge of the accessible portion was reached first.
+    fn scan_words(&mut self, count: i64) -> bool {
+        let syntax = self.syntax;
+        let word = |c: Option<char>| c.is_some_and(|c| syntax.class(c) == Class::Word);
+        let cursor = &mut self.cursor;
+        for _ in 0..count.unsigned_abs() {
+            if count > 0 {
+                while !word(cursor.char_after()) {
+                    if cursor.forward().is_none() {
+                        return false;
+                    }
+                }
+                while word(cursor.char_after()) {
+                    cursor.forward();
+                }
+            } else {
+                while !word(cursor.char_before()) {
+                    if cursor.backward().is_none() {
+                        return false;
+                    }
+                }
+                while word(cursor.char_before()) {
+                    cursor.backward();
+                }
+            }
+        }
+        true
+    }
+
+    /// Move over the rest of a symbol, stopping at `end`. `quoted` is set if
+    /// the scan stopped right after an escape character.
+    fn forward_symbol(&mut self, end: usize, quoted: &mut bool) {
+        while self.cursor.pos < end {
+            let Some(c) = self.cursor.char_after() else {
+                return;
+            };
+            match self.syntax.class(c) {
+                Class::Escape | Class::CharQuote => {
+                    self.cursor.forward();
+                    if self.cursor.pos >= end {
+                        *quoted = true;
+                        return;
+                    }
+                    self.cursor.forward();
+                }
+                Class::Word | Class::Symbol | Class::Quote => {
+                    self.cursor.forward();
+                }
+                _ => return,
+            }
+        }
+    }
+
+    /// Move back over a symbol, including the quote characters and escaped
+    /// characters in it.
+    fn backward_symbol(&mut self) {
+        loop {
+            let mut cursor = self.cursor;
+            let Some(c) = cursor.backward() else { return };
+            if self.quoted(cursor) {
+                cursor.backward();
+            } else if !matches!(self.syntax.class(c), Class::Word | Class::Symbol | Class::Quote) {
+                return;
+            }
+            self.cursor = cursor;
+        }
+    }
+
+    /// Move over the rest of a string, stopping after its terminator. Returns
+    /// false if the string doesn't end before `end`. `quoted` is set if the
+    /// scan stopped right after an escape character, and the character after
+    /// it is skipped if it is set on entry.
+    fn forward_string(&mut self, end: usize, term: StringEnd, quoted: &mut bool) -> bool {
+        if *quoted {
+            if self.cursor.pos >= end {
+                return false;
+            }
+            *quoted = false;
+            self.cursor.forward();
+        }
+        while self.cursor.pos < end {
+            let Some(c) = self.cursor.forward() else {
+                return false;
+            };
+            match (self.syntax.class(c), term) {
+                (Class::String, StringEnd::Char(t)) if c == t => return true,
+                (Class::StringFence, StringEnd::Fence) => return true,
+                (Class::Escape | Class::CharQuote, _) => {
+                    if self.cursor.pos >= end {
+                        *quoted = true;
+                        return false;
+                    }
+                    self.cursor.forward();
+                }
+                _ => {}
+            }
+        }
+        false
+    }
+
+    /// Move over the rest of a comment, stopping after its ender. Returns
+    /// false if the comment doesn't end before `end`. `nesting` is the depth
+    /// of nested comments, or -1 if the comment doesn't nest.
+    fn forward_comment(&mut self, end: usize, style: CommentStyle, nesting: &mut i64) -> bool {
+        while self.cursor.pos < end {
+            let Some(c) = self.cursor.forward() else {
+                return false;
+            };
+            let syntax = self.syntax.get(c);
+            match syntax.class {
+                Class::EndComment if style == CommentStyle::Plain(syntax.comment_style()) => {
+                    if *nesting > 1 {
+                        *nesting -= 1;
+                    } else {
+                        return true;
+                    }
+                }
+                Class::CommentFence if style == CommentStyle::Fence => return true,
+                Class::Comment
+                    if *nesting > 0
+                        && syntax.nested()
+                        && style == CommentStyle::Plain(syntax.comment_style()) =>
+                {
+                    *nesting += 1;
+                }
+                _ => {}
+            }
+        }
+        false
+    }
+
+    /// Move back to the start of the comment that the comment ender after the
+    /// cursor ends. Returns false, without moving, if it doesn't end a
+    /// comment.
+    fn backward_comment(&mut self, ender: Syntax) -> bool {
+        let end = self.cursor.pos;
+        let mut start = self.cursor;
+        while start.char_before().is_some_and(|c| c != '\n') {
+            start.backward();
+        }
+        // A comment ended by a newline starts on the same line, so parsing
+        // from the start of the line finds it. Other comments can span lines,
+        // and are found by parsing from the start of the accessible portion.
+        let mut starts = vec![start];
+        if self.cursor.char_after() != Some('\n') {
+            while start.backward().is_some() {}
+            starts.push(start);
+        }
+        let style = CommentStyle::Plain(ender.comment_style());
+        for start in starts {
+            let mut scanner = Scanner { cursor: start, ..*self };
+            let mut state = ParseState::default();
+            scanner.parse(&mut state, end, Stop::default());
+            if state.comment.is_some()
+                && state.comment_style == style
+                && let Some(comment_start) = state.comstr_start
+            {
+                while self.cursor.pos > comment_start {
+                    self.cursor.backward();
+                }
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Move over `count` balanced expressions, backward if it is negative,
+    /// starting at the paren depth `depth`. An expression ends when the depth
+    /// reaches zero. If `sexpflag` is set, words and strings are expressions
+    /// too. Returns `None` if the edge of the accessible portion was reached
+    /// at depth zero first.
+    fn scan_lists(
+        &mut self,
+        count: i64,
+        mut depth: i64,
+        sexpflag: bool,
+    ) -> Result<Option<usize>, ScanError> {
+        let min_depth = depth.min(0);
+        for _ in 0..count.unsigned_abs() {
+            let done = if count > 0 {
+                self.forward_sexp(&mut depth, min_depth, sexpflag)?
+            } else {
+                self.backward_sexp(&mut depth, min_depth, sexpflag)?
+            };
+            if !done {
+                return Ok(None);
+            }
+        }
+        Ok(Some(self.cursor.pos))
+    }
+
+    fn forward_sexp(
+        &mut self,
+        depth: &mut i64,
+        min_depth: i64,
+        sexpflag: bool,
+    ) -> Result<bool, ScanError> {
+        let zv = self.cursor.zv;
+        let mut last_good = self.cursor.pos;
+        loop {
+            if *depth == min_depth {
+                last_good = self.cursor.pos;
+            }
+            let Some(c) = self.cursor.forward() else {
+                break;
+            };
+            let syntax = self.syntax.get(c);
+            if syntax.prefix() {
+                continue;
+            }
+            match syntax.class {
+                Class::Escape | Class::CharQuote | Class::Word | Class::Symbol => {
+                    if matches!(syntax.class, Class::Escape | Class::CharQuote)
+                        && self.cursor.forward().is_none()
+                    {
+                        return Err(ScanError::unbalanced(last_good, self.cursor.pos));
+                    }
+                    if *depth == 0 && sexpflag {
+                        self.forward_symbol(zv, &mut false);
+                        return Ok(true);
+                    }
+                }
+                Class::Comment | Class::CommentFence if self.ignore_comments => {
+                    let (style, mut nesting) = syntax.comment_start().unwrap();
+                    if !self.forward_comment(zv, style, &mut nesting) {
+                        break;
+                    }
+                }
+                Class::Open => {
+                    *depth += 1;
+                    if *depth == 0 {
+                        return Ok(true);
+                    }
+                }
+                Class::Close => {
+                    *depth -= 1;
+                    if *depth == 0 {
+                        return Ok(true);
+                    }
+                    if *depth < min_depth {
+                        return Err(ScanError::premature(last_good, self.cursor.pos));
+                    }
+                }
+                Class::String | Class::StringFence => {
+                    let term = match syntax.class {
+                        Class::String => StringEnd::Char(c),
+                        _ => StringEnd::Fence,
+                    };
+                    if !self.forward_string(zv, term, &mut false) {
+                        return Err(ScanError::unbalanced(last_good, self.cursor.pos));
+                    }
+                    if *depth == 0 && sexpflag {
+                        return Ok(true);
+                    }
+                }
+                _ => {}
+            }
+        }
+        match *depth {
+            0 => Ok(false),
+            _ => Err(ScanError::unbalanced(last_good, self.cursor.pos)),
+        }
+    }
+
+    fn backward_sexp(
+        &mut self,
+        depth: &mut i64,
+        min_depth: i64,
+        sexpflag: bool,
+    ) -> Result<bool, ScanError> {
+        let mut last_good = self.cursor.pos;
+        while let Some(c) = self.cursor.backward() {
+            let syntax = self.syntax.get(c);
+            if *depth == min_depth {
+                last_good = self.cursor.pos;
+            }
+            let class = if syntax.class != Class::EndComment && self.quoted(self.cursor) {
+                self.cursor.backward();
+                Class::Word
+            } else if syntax.prefix() {
+                continue;
+            } else {
+                syntax.class
+            };
+            match class {
+                Class::Word | Class::Symbol | Class::Escape | Class::CharQuote
+                    if *depth == 0 && sexpflag =>
+                {
+                    self.backward_symbol();
+                    return Ok(true);
+                }
+                Class::Close => {
+                    *depth += 1;
+                    if *depth == 0 {
+                        return Ok(true);
+                    }
+                }
+                Class::Open => {
+                    *depth -= 1;
+                    if *depth == 0 {
+                        return Ok(true);
+                    }
+                    if *depth < min_depth {
+                        return Err(ScanError::premature(last_good, self.cursor.pos));
+                    }
+                }
+                Class::EndComment if self.ignore_comments => {
+                    self.backward_comment(syntax);
+                }
+                Class::String | Class::StringFence | Class::CommentFence => {
+                    loop {
+                        let Some(start) = self.cursor.backward() else {
+                            return Err(ScanError::unbalanced(last_good, self.cursor.pos));
+                        };
+                        let found = match class {
+                            Class::String => start == c && self.syntax.class(start) == class,
+                            _ => self.syntax.class(start) == class,
+                        };
+                        if found && !self.quoted(self.cursor) {
+                            break;
+                        }
+                    }
+                    if class != Class::CommentFence && *depth == 0 && sexpflag {
+                        return Ok(true);
+                    }
+                }
+                _ => {}
+            }
+        }
+        match *depth {
+            0 => Ok(false),
+            _ => Err(ScanError::unbalanced(last_good, self.cursor.pos)),
+        }
+    }
+
+    /// Parse forward to `end`, updating `state`, unless one of the conditions
+    /// of `stop` is met first.
+    fn parse(&mut self, state: &mut ParseState, end: usize, stop: Stop) {
+        if state.quoted && state.string.is_none() && state.comment.is_none() {
+            // Finish the symbol that the escape character is part of
+            if self.cursor.pos >= end {
+                return;
+            }
+            state.quoted = false;
+            self.cursor.forward();
+            self.parse_symbol(state, end);
+        }
+        loop {
+            if state.string.is_some() || state.comment.is_some() {
+                if !self.parse_string_or_comment(state, end, stop) {
+                    return;
+                }
+                continue;
+            }
+            let start = self.cursor;
+            if start.pos >= end {
+                return;
+            }
+            let Some(c) = self.cursor.forward() else {
+                return;
+            };
+            let syntax = self.syntax.get(c);
+            if syntax.prefix() {
+                continue;
+            }
+            let starts_sexp = matches!(
+                syntax.class,
+                Class::Escape
+                    | Class::CharQuote
+                    | Class::Word
+                    | Class::Symbol
+                    | Class::Open
+                    | Class::String
+                    | Class::StringFence
+            );
+            if starts_sexp {
+                if stop.before_sexp {
+                    self.cursor = start;
+                    return;
+                }
+                state.level().last = Some(start.pos);
+            }
+            match syntax.class {
+                Class::Escape | Class::CharQuote => {
+                    if self.cursor.pos >= end {
+                        state.quoted = true;
+                        return;
+                    }
+                    self.cursor.forward();
+                    self.parse_symbol(state, end);
+                }
+                Class::Word | Class::Symbol => self.parse_symbol(state, end),
+                Class::Open => {
+                    state.depth += 1;
+                    state.levels.push(Level::default());
+                    if stop.target_depth == Some(state.depth) {
+                        return;
+                    }
+                }
+                Class::Close => {
+                    state.depth -= 1;
+                    state.min_depth = state.min_depth.min(state.depth);
+                    if state.levels.len() > 1 {
+                        state.levels.pop();
+                    }
+                    let level = state.level();
+                    level.prev = level.last;
+                    if stop.target_depth == Some(state.depth) {
+                        return;
+                    }
+                }
+                Class::String | Class::StringFence => {
+                    state.string = match syntax.class {
+                        Class::String => Some(StringEnd::Char(c)),
+                        _ => Some(StringEnd::Fence),
+                    };
+                    state.comstr_start = Some(start.pos);
+                    if stop.comment == CommentStop::Boundary {
+                        return;
+                    }
+                }
+                Class::Comment | Class::CommentFence => {
+                    let (style, nesting) = syntax.comment_start().unwrap();
+                    state.comment = Some(nesting);
+                    state.comment_style = style;
+                    state.comstr_start = Some(start.pos);
+                    if stop.comment != CommentStop::Never {
+                        return;
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Move over the rest of a symbol in a parse, stopping at `end`.
+    fn parse_symbol(&mut self, state: &mut ParseState, end: usize) {
+        self.forward_symbol(end, &mut state.quoted);
+        if !state.quoted {
+            let level = state.level();
+            level.prev = level.last;
+        }
+    }
+
+    /// Move over the rest of the string or comment a parse is in. Returns false
+    /// if the parse stops, because it reached `end` first or stops at the end
+    /// of strings and comments.
+    fn parse_string_or_comment(&mut self, state: &mut ParseState, end: usize, stop: Stop) -> bool {
+        if let Some(term) = state.string {
+            if !self.forward_string(end, term, &mut state.quoted) {
+                return false;
+            }
+            state.string = None;
+            let level = state.level();
+            level.prev = level.last;
+        } else if let Some(nesting) = &mut state.comment {
+            if !self.forward_comment(end, state.comment_style, nesting) {
+                return false;
+            }
+            state.comment = None;
+        }
+        state.comstr_start = None;
+        stop.comment != CommentStop::Boundary
+    }
+}
+
+/// Call `func` with a scanner over the accessible portion of the current
+/// buffer that starts at `from`, or point if it is `None`.
+fn with_scanner<T>(
+    from: Option<usize>,
+    env: &mut Rt<Env>,
+    cx: &Context,
+    func: impl FnOnce(&mut Scanner<'_>) -> T,
+) -> T {
+    let syntax = SyntaxTable::current(env, cx);
+    let ignore_comments = env
+        .vars
+        .get(sym::PARSE_SEXP_IGNORE_COMMENTS)
+        .is_some_and(|x| !x.bind(cx).is_nil());
+    let buffer = env.current_buffer.get();
+    let cursor = Cursor::new(buffer, from.unwrap_or(buffer.point()));
+    func(&mut Scanner { syntax, cursor, ignore_comments })
+}
+
+/// Return the position after COUNT words from FROM, or before them if COUNT
+/// is negative. Return nil if the edge of the accessible portion of the
+/// buffer is reached first.
+#[defun]
+fn scan_words(from: usize, count: i64, env: &mut Rt<Env>, cx: &Context) -> Option<usize> {
+    with_scanner(Some(from), env, cx, |scanner| {
+        scanner.scan_words(count).then_some(scanner.cursor.pos)
+    })
+}
+
+/// Move point forward ARG words, or backward if ARG is negative. If the edge
+/// of the accessible portion of the buffer is reached first, move there and
+/// return nil. Otherwise return t.
+#[defun]
+fn forward_word(arg: Option<i64>, env: &mut Rt<Env>, cx: &Context) -> bool {
+    let (found, pos) = with_scanner(None, env, cx, |scanner| {
+        (scanner.scan_words(arg.unwrap_or(1)), scanner.cursor.pos)
+    });
+    env.current_buffer.get_mut().goto_char(pos);
+    found
+}
+
+/// Move point backward ARG words, or forward if ARG is negative. Like
+/// `forward-word`, return nil if the edge of the buffer is reached first.
+#[defun]
+fn backward_word(arg: Option<i64>, env: &mut Rt<Env>, cx: &Context) -> bool {
+    forward_word(Some(-arg.unwrap_or(1)), env, cx)
+}
+
+fn scan(
+    from: usize,
+    count: i64,
+    depth: i64,
+    sexpflag: bool,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<Option<usize>> {
+    let result =
+        with_scanner(Some(from), env, cx, |scanner| scanner.scan_lists(count, depth, sexpflag));
+    result.map_err(|err| err.signal(env, cx))
+}
+
+/// Return the position after COUNT balanced lists from FROM, or before them
+/// if COUNT is negative. Scanning starts at paren depth DEPTH and a list ends
+/// when the depth reaches zero, so a DEPTH of 1 moves out of the enclosing
+/// list. Return nil if the edge of the accessible portion of the buffer is
+/// reached at depth zero. If it is reached inside a list, or the scan leaves
+/// a list it started in, signal a `scan-error`. Comments are skipped if
+/// `parse-sexp-ignore-comments` is non-nil.
+#[defun]
+fn scan_lists(
+    from: usize,
+    count: i64,
+    depth: i64,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<Option<usize>> {
+    scan(from, count, depth, false, env, cx)
+}
+
+/// Return the position after COUNT balanced expressions from FROM, or before
+/// them if COUNT is negative. Words and strings are expressions, as well as
+/// lists. Return nil if the edge of the accessible portion of the buffer is
+/// reached first, and signal a `scan-error` if it is reached inside a list.
+#[defun]
+fn scan_sexps(from: usize, count: i64, env: &mut Rt<Env>, cx: &Context) -> Result<Option<usize>> {
+    scan(from, count, 0, true, env, cx)
+}
+
+/// Move point forward over ARG balanced expressions, or backward if ARG is
+/// negative. Moving backward also moves over the prefix characters before the
+/// expression.
+#[defun]
+fn forward_sexp(arg: Option<i64>, env: &mut Rt<Env>, cx: &Context) -> Result<()> {
+    let arg = arg.unwrap_or(1);
+    let current = env.current_buffer.get();
+    let point = current.point();
+    let edge = if arg < 0 { current.point_min() } else { current.point_max() };
+    let pos = scan(point, arg, 0, true, env, cx)?.unwrap_or(edge);
+    env.current_buffer.get_mut().goto_char(pos);
+    if arg < 0 {
+        backward_prefix_chars(env, cx);
+    }
+    Ok(())
+}
+
+/// Move point backward over ARG balanced expressions, or forward if ARG is
+/// negative.
+#[defun]
+fn backward_sexp(arg: Option<i64>, env: &mut Rt<Env>, cx: &Context) -> Result<()> {
+    forward_sexp(Some(-arg.unwrap_or(1)), env, cx)
+}
+
+/// Move point forward out of ARG levels of parentheses, or backward if ARG is
+/// negative. If ESCAPE-STRINGS is non-nil and point is in a string, move out
+/// of the string first.
+#[defun]
+fn up_list(
+    arg: Option<i64>,
+    escape_strings: Option<Object>,
+    _no_syntax_crossing: Option<Object>,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<()> {
+    let arg = arg.unwrap_or(1);
+    let escape_strings = escape_strings.is_some_and(|x| !x.is_nil());
+    let mut pos = env.current_buffer.get().point();
+    if escape_strings {
+        let string_start = with_scanner(None, env, cx, |scanner| {
+            let point = scanner.cursor.pos;
+            while scanner.cursor.backward().is_some() {}
+            let mut state = ParseState::default();
+            scanner.parse(&mut state, point, Stop::default());
+            state.string.and(state.comstr_start)
+        });
+        if let Some(start) = string_start {
+            pos = match arg {
+                0.. => scan(start, 1, 0, true, env, cx)?.unwrap_or(start),
+                _ => start,
+            };
+        }
+    }
+    let count = arg.signum();
+    for _ in 0..arg.unsigned_abs() {
+        match scan(pos, count, 1, false, env, cx)? {
+            Some(next) => pos = next,
+            None => {
+                let data = list![cx.add("Unbalanced parentheses"), pos, pos; cx];
+                return Err(EvalError::signal(sym::SCAN_ERROR.into(), data, env).into());
+            }
+        }
+    }
+    env.current_buffer.get_mut().goto_char(pos);
+    Ok(())
+}
+
+/// Parse from FROM to TO in the current buffer, leaving point where the parse
+/// stopped, and return the parser state. The elements of the state are:
+///
+/// 0. the depth in parens
+/// 1. the start of the innermost containing list, or nil
+/// 2. the start of the last complete expression, or nil
+/// 3. the character that ends the string the parse is in, t for a string
+///    fence, or nil outside strings
+/// 4. t inside a comment that doesn't nest, the nesting of a comment that
+///    does, or nil outside comments
+/// 5. t if the parse stopped right after an escape character
+/// 6. the smallest paren depth the parse reached
+/// 7. the style of the comment the parse is in: nil for style a, 1 for b, 2
+///    for c, and `syntax-table` for a comment fence
+/// 8. the start of the comment or string the parse is in, or nil
+/// 9. the starts of the open lists, outermost first
+/// 10. nil, since two-character comment delimiters are not supported
+///
+/// If TARGETDEPTH is non-nil, stop when the depth reaches it. If STOPBEFORE
+/// is non-nil, stop at the start of an expression. If OLDSTATE is non-nil,
+/// the parse continues from that state instead of the top level. If
+/// COMMENTSTOP is `syntax-table`, stop after the start or end of a comment or
+/// string, and if it is any other non-nil value, after the start of a
+/// comment.
+#[defun]
+#[expect(clippy::too_many_arguments)]
+fn parse_partial_sexp<'ob>(
+    from: usize,
+    to: usize,
+    targetdepth: Option<i64>,
+    stopbefore: Option<Object>,
+    oldstate: Option<Object>,
+    commentstop: Option<Object>,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    ensure!(to >= from, "End position is smaller than start position");
+    let mut state = match oldstate {
+        Some(oldstate) => ParseState::from_lisp(oldstate)?,
+        None => ParseState::default(),
+    };
+    let stop = Stop {
+        target_depth: targetdepth,
+        before_sexp: stopbefore.is_some_and(|x| !x.is_nil()),
+        comment: match commentstop {
+            Some(x) if x == sym::SYNTAX_TABLE => CommentStop::Boundary,
+            Some(x) if !x.is_nil() => CommentStop::Start,
+            _ => CommentStop::Never,
+        },
+    };
+    let pos = with_scanner(Some(from), env, cx, |scanner| {
+        let end = to.min(scanner.cursor.zv);
+        scanner.parse(&mut state, end, stop);
+        scanner.cursor.pos
+    });
+    env.current_buffer.get_mut().goto_char(pos);
+    Ok(state.to_lisp(cx))
+}
+
+/// The number of characters between the states that `syntax-ppss` caches.
+const PPSS_INTERVAL: usize = 2048;
+
+/// The states of a parse from the start of a buffer, at the first line start
+/// after every [`PPSS_INTERVAL`] characters. Positions are character offsets.
+#[derive(Debug, Default)]
+pub(crate) struct SyntaxCache {
+    states: Vec<(usize, ParseState)>,
+}
+
+impl SyntaxCache {
+    /// Drop the states after `pos`, since the text before them changed.
+    pub(crate) fn invalidate(&mut self, pos: usize) {
+        let valid = self.states.partition_point(|x| x.0 <= pos);
+        self.states.truncate(valid);
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.states.clear();
+    }
+}
+
+/// The state of a parse from the start of the current buffer to `pos`,
+/// ignoring any narrowing. The parse resumes from the last cached state
+/// before `pos`, and caches the states it passes.
+fn ppss(pos: usize, env: &mut Rt<Env>, cx: &Context) -> ParseState {
+    let syntax = SyntaxTable::current(env, cx);
+    let buffer = env.current_buffer.get();
+    let zv = buffer.text.len_chars() + 1;
+    let target = pos.clamp(1, zv);
+    let cache = &buffer.syntax_cache.states;
+    let (from, mut state) = match cache.partition_point(|x| x.0 < target) {
+        0 => (1, ParseState::default()),
+        idx => (cache[idx - 1].0 + 1, cache[idx - 1].1.clone()),
+    };
+    let cursor = Cursor::with_bounds(buffer, 1, zv, from);
+    let mut scanner = Scanner { syntax, cursor, ignore_comments: false };
+    let mut new_states = Vec::new();
+    loop {
+        let mut next = scanner.cursor;
+        for _ in 0..PPSS_INTERVAL {
+            next.forward();
+        }
+        while next.forward().is_some_and(|c| c != '\n') {}
+        if next.pos >= target {
+            break;
+        }
+        scanner.parse(&mut state, next.pos, Stop::default());
+        new_states.push((next.pos - 1, state.clone()));
+    }
+    scanner.parse(&mut state, target, Stop::default());
+    let cache = &mut env.current_buffer.get_mut().syntax_cache.states;
+    let last = cache.last().map(|x| x.0);
+    cache.extend(new_states.into_iter().filter(|x| last.is_none_or(|last| x.0 > last)));
+    state
+}
+
+/// Return the state of a parse from the start of the buffer to POS, which
+/// defaults to point, and move point to POS. The state is the one that
+/// `parse-partial-sexp` returns, except that elements 2 and 6 are not
+/// reliable. The states at line starts along the way are cached, so later
+/// calls only parse from the last state before the position they want.
+#[defun]
+fn syntax_ppss<'ob>(pos: Option<usize>, env: &mut Rt<Env>, cx: &'ob Context) -> Object<'ob> {
+    let pos = pos.unwrap_or_else(|| env.current_buffer.get().point());
+    let state = ppss(pos, env, cx);
+    env.current_buffer.get_mut().goto_char(pos);
+    state.to_lisp(cx)
+}
+
+/// Forget the states that `syntax-ppss` cached after BEG. Edits to the buffer
+/// do this by themselves.
+#[defun]
+fn syntax_ppss_flush_cache(beg: usize, _ignored: ArgSlice, env: &mut Rt<Env>) {
+    env.current_buffer.get_mut().syntax_cache.invalidate(beg.saturating_sub(1));
+}
+
+#[cfg(test)]
+mod test {
+    use crate::interpreter::assert_lisp;
+
+    #[test]
+    fn test_buffer_syntax_table() {
+        assert_lisp(
+            "(let ((table (make-syntax-table)))
+               (set-syntax-table table)
+               (list (eq (syntax-table) table)
+                     (eq (char-table-parent table) (standard-syntax-table))
+                     (with-current-buffer (get-buffer-create \"syntax-table-test\")
+                       (eq (syntax-table) (standard-syntax-table)))
+                     (eq (syntax-table) table)
+                     (progn (kill-all-local-variables)
+                            (eq (syntax-table) (standard-syntax-table)))))",
+            "(t t t t t)",
+        );
+    }
+
+    #[test]
+    fn test_syntax_entries() {
+        assert_lisp(
+            r#"(progn
+                 (set-syntax-table (make-syntax-table))
+                 (modify-syntax-entry ?< "(>")
+                 (modify-syntax-entry '(?0 . ?9) "_")
+                 (list (string-to-syntax "()") (string-to-syntax ". 12b") (string-to-syntax "w")
+                       (char-syntax ?<) (char-syntax ?5) (char-syntax ?a) (char-syntax ?\n)
+                       (matching-paren ?<) (matching-paren ?}) (matching-paren ?a)
+                       (syntax-class-to-char 7)))"#,
+            "((4 . 41) (2293761) (2) 40 95 119 32 62 123 nil 34)",
+        );
+    }
+
+    #[test]
+    fn test_scan_words() {
+        assert_lisp(
+            r#"(progn
+                 (insert "foo-bar baz")
+                 (list (scan-words 1 1) (scan-words 1 2)
+                       (progn (goto-char 1) (forward-word 3) (point))
+                       (forward-word 1) (point) (scan-words 12 -1)
+                       (progn (backward-word 2) (point))))"#,
+            "(4 8 12 nil 12 9 5)",
+        );
+    }
+
+    const SEXP_SETUP: &str = r#"(set-syntax-table (make-syntax-table))
+                                (modify-syntax-entry ?\; "<")
+                                (modify-syntax-entry ?\n ">")
+                                (modify-syntax-entry ?' "'")
+                                (setq parse-sexp-ignore-comments t)
+                                (insert "(foo \"b)\" ; x)\n 'bar) baz")"#;
+
+    #[test]
+    fn test_scan_sexps() {
+        assert_lisp(
+            &format!(
+                "(progn {SEXP_SETUP}
+                   (list (scan-sexps 1 1) (scan-sexps 22 -1) (scan-lists 3 1 1)
+                         (scan-sexps 23 1) (scan-sexps 26 1)
+                         (condition-case nil (scan-lists 22 1 1) (error 'unbalanced))
+                         (progn (goto-char 21) (backward-sexp) (point))
+                         (progn (goto-char 3) (up-list) (point))))"
+            ),
+            "(22 1 22 26 nil unbalanced 17 22)",
+        );
+    }
+
+    #[test]
+    fn test_parse_partial_sexp() {
+        assert_lisp(
+            &format!(
+                "(progn {SEXP_SETUP}
+                   (list (parse-partial-sexp 1 13) (parse-partial-sexp 1 8)
+                         (parse-partial-sexp 8 26 nil nil (parse-partial-sexp 1 8))
+                         (progn (parse-partial-sexp 1 26 0) (point))))"
+            ),
+            "((1 1 6 nil t nil 0 nil 11 (1) nil) (1 1 2 34 nil nil 0 nil 6 (1) nil)
+              (0 nil 23 nil nil nil 0 nil nil nil nil) 22)",
+        );
+    }
+
+    #[test]
+    fn test_syntax_ppss() {
+        assert_lisp(
+            r#"(progn
+                 (set-syntax-table (make-syntax-table))
+                 (modify-syntax-entry ?\; "<")
+                 (modify-syntax-entry ?\n ">")
+                 (insert "(" (make-string 3000 ?x) "\n; " (make-string 3000 ?y) "\n)")
+                 (list (syntax-ppss 3004) (car (syntax-ppss (point-max)))
+                       (progn (goto-char 1) (insert "\"") (nth 3 (syntax-ppss 3005)))
+                       (point)))"#,
+            "((1 1 2 nil t nil 0 nil 3003 (1) nil) 0 34 3005)",
+        );
+    }
+}
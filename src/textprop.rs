@@ -0,0 +1,210 @@
+//! Text properties.
+//!
+//! The property runs of a buffer only hold an id for their property list. The
+//! lists themselves are kept in the environment so that they are traced by the
+//! garbage collector, and equal lists share an id so that runs with the same
+//! properties can be merged. Strings do not carry text properties yet.
+use crate::{
+    core::{
+        cons::Cons,
+        env::Env,
+        error::{Type, TypeError},
+        gc::{Context, Rt},
+        object::{LispBuffer, List, NIL, Object, ObjectType},
+    },
+    fns::{copy_sequence, eq, plist_get},
+};
+use anyhow::{Result, bail};
+use rune_macros::defun;
+
+/// The buffer that OBJECT refers to, or the current buffer if it is nil.
+fn property_buffer<'ob>(
+    object: Option<Object<'ob>>,
+    env: &Rt<Env>,
+    cx: &'ob Context,
+) -> Result<&'ob LispBuffer> {
+    match object.map(|x| x.untag()) {
+        None => Ok(env.current_buffer.get().lisp_buffer(cx)),
+        Some(ObjectType::Buffer(buffer)) => Ok(buffer),
+        Some(ObjectType::String(_)) => bail!("Text properties on strings are not supported"),
+        Some(x) => Err(TypeError::new(Type::Buffer, x).into()),
+    }
+}
+
+fn plist_pairs(plist: Object) -> Result<Vec<(Object, Object)>> {
+    let mut pairs = Vec::new();
+    let mut iter = List::try_from(plist)?.elements();
+    while let Some(key) = iter.next() {
+        let Some(value) = iter.next() else { break };
+        pairs.push((key?, value?));
+    }
+    Ok(pairs)
+}
+
+/// True if the property lists have the same properties with `eq` values,
+/// regardless of order.
+fn plist_equal(a: Object, b: Object) -> bool {
+    let (Ok(a), Ok(b)) = (plist_pairs(a), plist_pairs(b)) else { return false };
+    a.len() == b.len()
+        && a.iter()
+            .all(|(key, value)| b.iter().any(|(k, v)| k.ptr_eq(*key) && v.ptr_eq(*value)))
+}
+
+/// The id of the property list equal to `plist`, adding it if there is none.
+fn intern_properties(plist: Object, env: &mut Rt<Env>, cx: &Context) -> Option<usize> {
+    if plist.is_nil() {
+        return None;
+    }
+    let existing = env.text_properties.iter().position(|x| plist_equal(x.bind(cx), plist));
+    Some(existing.unwrap_or_else(|| {
+        env.text_properties.push(plist);
+        env.text_properties.len() - 1
+    }))
+}
+
+fn properties<'ob>(id: Option<usize>, env: &Rt<Env>, cx: &'ob Context) -> Object<'ob> {
+    id.map_or(NIL, |id| env.text_properties[id].bind(cx))
+}
+
+/// The property list of the character at the 1-based `pos` in `buffer`.
+fn properties_at<'ob>(
+    pos: usize,
+    buffer: &LispBuffer,
+    env: &Rt<Env>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    let id = env.with_buffer(buffer, |b| b.in_range(pos).map(|pos| b.properties.get(pos)))??;
+    Ok(properties(id, env, cx))
+}
+
+/// Return the property list of the character at POSITION in OBJECT.
+#[defun]
+fn text_properties_at<'ob>(
+    position: usize,
+    object: Option<Object<'ob>>,
+    env: &Rt<Env>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    let buffer = property_buffer(object, env, cx)?;
+    copy_sequence(properties_at(position, buffer, env, cx)?, cx)
+}
+
+/// Return the value of PROP for the character at POSITION in OBJECT.
+#[defun]
+fn get_text_property<'ob>(
+    position: usize,
+    prop: Object<'ob>,
+    object: Option<Object<'ob>>,
+    env: &Rt<Env>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    let buffer = property_buffer(object, env, cx)?;
+    plist_get(properties_at(position, buffer, env, cx)?, prop)
+}
+
+/// Set `property` to `value` for the text between the character offsets `beg`
+/// and `end` of `buffer`, or remove it if `value` is `None`.
+pub(crate) fn set_property<'ob>(
+    buffer: &LispBuffer,
+    beg: usize,
+    end: usize,
+    property: Object<'ob>,
+    value: Option<Object<'ob>>,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<()> {
+    let segments = env.with_buffer(buffer, |b| b.properties.segments(beg, end))?;
+    for (beg, end, old) in segments {
+        let mut plist = match value {
+            Some(value) => Cons::new(property, Cons::new(value, NIL, cx), cx).into(),
+            None => NIL,
+        };
+        for (key, val) in plist_pairs(properties(old, env, cx))?.into_iter().rev() {
+            if !eq(key, property, env, cx) {
+                plist = Cons::new(key, Cons::new(val, plist, cx), cx).into();
+            }
+        }
+        let new = intern_properties(plist, env, cx);
+        if new != old {
+            env.with_buffer_mut(buffer, |b| b.set_properties(beg, end, new))?;
+        }
+    }
+    Ok(())
+}
+
+/// Set the property PROPERTY to VALUE for the text between START and END in
+/// OBJECT.
+#[defun]
+fn put_text_property<'ob>(
+    start: usize,
+    end: usize,
+    property: Object<'ob>,
+    value: Object<'ob>,
+    object: Option<Object<'ob>>,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<()> {
+    let buffer = property_buffer(object, env, cx)?;
+    let (start, end) = env.with_buffer(buffer, |b| -> Result<_> {
+        let (start, end) = (b.in_range(start)?, b.in_range(end)?);
+        Ok(if start <= end { (start, end) } else { (end, start) })
+    })??;
+    set_property(buffer, start, end, property, Some(value), env, cx)
+}
+
+/// Return the position of the next change in the text properties after
+/// POSITION in OBJECT, or nil if they stay the same to the end. If LIMIT is
+/// non-nil, return it instead when there is no change before it.
+#[defun]
+fn next_property_change<'ob>(
+    position: usize,
+    object: Option<Object<'ob>>,
+    limit: Option<usize>,
+    env: &Rt<Env>,
+    cx: &'ob Context,
+) -> Result<Option<usize>> {
+    let buffer = property_buffer(object, env, cx)?;
+    let next = env.with_buffer(buffer, |b| -> Result<_> {
+        let pos = b.in_range(position)?;
+        Ok(b.properties.next_change(pos).map(|x| x + 1).filter(|x| *x < b.point_max()))
+    })??;
+    Ok(match limit {
+        Some(limit) => Some(next.map_or(limit, |x| x.min(limit))),
+        None => next,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::interpreter::assert_lisp;
+
+    #[test]
+    fn test_text_properties() {
+        assert_lisp(
+            r#"(progn (insert "hello world")
+                 (put-text-property 1 6 'face 'bold)
+                 (put-text-property 3 8 'invisible t)
+                 (list (get-text-property 1 'face) (get-text-property 7 'face)
+                       (text-properties-at 4) (text-properties-at 9)
+                       (next-property-change 1) (next-property-change 3)
+                       (next-property-change 8) (next-property-change 1 nil 2)))"#,
+            "(bold nil (face bold invisible t) nil 3 6 nil 2)",
+        );
+    }
+
+    #[test]
+    fn test_text_properties_edits() {
+        assert_lisp(
+            r#"(progn (insert "hello world")
+                 (put-text-property 1 6 'face 'bold)
+                 (goto-char 3)
+                 (insert "xx")
+                 (let ((inserted (get-text-property 3 'face))
+                       (moved (get-text-property 5 'face)))
+                   (delete-region 2 6)
+                   (list inserted moved (next-property-change 1)
+                         (get-text-property 3 'face) (get-text-property 4 'face))))"#,
+            "(nil bold 4 bold nil)",
+        );
+    }
+}
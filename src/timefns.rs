@@ -1,12 +1,14 @@
 //! Time analysis
 use crate::core::{
+    cons::Cons,
     env::{Env, sym},
     gc::{Context, Rt},
-    object::Object,
+    object::{Object, ObjectType},
 };
+use anyhow::{Result, bail};
 use rune_core::macros::list;
 use rune_macros::defun;
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 
 defvar!(CURRENT_TIME_LIST, true);
 
@@ -27,3 +29,86 @@ fn current_time<'ob>(cx: &'ob Context, env: &Rt<Env>) -> Object<'ob> {
 
     list![high, low, micros, 0; cx]
 }
+
+/// Return the current time, or TIME, as a float number of seconds since the
+/// epoch. TIME can be a number or a list of the form (HIGH LOW USEC PSEC),
+/// where the trailing elements can be omitted.
+#[defun]
+fn float_time(time: Option<Object>) -> Result<f64> {
+    let Some(time) = time else {
+        let duration = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .expect("System time is before the epoch");
+        return Ok(duration.as_secs_f64());
+    };
+    match time.untag() {
+        ObjectType::Int(x) => Ok(x as f64),
+        ObjectType::Float(x) => Ok(**x),
+        ObjectType::Cons(cons) => {
+            let [high, low, usec, psec] = time_parts(cons)?;
+            Ok((high * 0x10000 + low) as f64 + usec as f64 / 1e6 + psec as f64 / 1e12)
+        }
+        _ => bail!("Invalid time specification"),
+    }
+}
+
+/// The parts of a time list of the form (HIGH LOW USEC PSEC). Missing parts
+/// are 0.
+fn time_parts(time: &Cons) -> Result<[i64; 4]> {
+    let mut parts = [0; 4];
+    for (part, elt) in parts.iter_mut().zip(time) {
+        let ObjectType::Int(x) = elt?.untag() else { bail!("Invalid time specification") };
+        *part = x;
+    }
+    Ok(parts)
+}
+
+/// Convert `time` to the `(HIGH LOW USEC PSEC)` format used by
+/// `current-time`.
+pub(crate) fn system_time_to_lisp<'ob>(time: SystemTime, cx: &'ob Context) -> Object<'ob> {
+    let duration = time.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default();
+    let secs = duration.as_secs();
+    let nanos = duration.subsec_nanos();
+    list![secs >> 16, secs & 0xffff, nanos / 1000, (nanos % 1000) * 1000; cx]
+}
+
+/// Convert a time value in any of the formats accepted by `float-time` to a
+/// [`SystemTime`]. Time lists are converted without loss of precision.
+pub(crate) fn lisp_to_system_time(time: Object) -> Result<SystemTime> {
+    let duration = match time.untag() {
+        ObjectType::Cons(cons) => {
+            let [high, low, usec, psec] = time_parts(cons)?;
+            let secs = u64::try_from(high * 0x10000 + low)?;
+            let nanos = u64::try_from(usec * 1000 + psec / 1000)?;
+            Duration::from_secs(secs) + Duration::from_nanos(nanos)
+        }
+        _ => Duration::try_from_secs_f64(float_time(Some(time))?)?,
+    };
+    Ok(SystemTime::UNIX_EPOCH + duration)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::gc::RootSet;
+
+    #[test]
+    fn test_float_time() {
+        let roots = &RootSet::default();
+        let cx = &Context::new(roots);
+        assert!(float_time(None).unwrap() > 1.5e9);
+        assert!((float_time(Some(cx.add(12))).unwrap() - 12.0).abs() < f64::EPSILON);
+        let time = list![1, 2, 500_000; cx];
+        assert!((float_time(Some(time)).unwrap() - 65538.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_system_time_round_trip() {
+        let roots = &RootSet::default();
+        let cx = &Context::new(roots);
+        let time = SystemTime::UNIX_EPOCH + Duration::new(1_700_000_000, 123_456_789);
+        let lisp = system_time_to_lisp(time, cx);
+        assert_eq!(lisp, list![25939, 61696, 123_456, 789_000; cx]);
+        assert_eq!(lisp_to_system_time(lisp).unwrap(), time);
+    }
+}
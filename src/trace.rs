@@ -0,0 +1,265 @@
+//! Tracing function calls.
+//!
+//! A traced function is replaced by a closure that passes its arguments to
+//! `trace--call` along with the original definition, so any callable can be
+//! traced, whether it is a builtin, a closure, or byte-compiled. Each call is
+//! logged on entry with its arguments and on exit with its return value,
+//! indented by the number of traced calls it is nested in:
+//!
+//! ```text
+//! 1 -> (fact 2)
+//! | 2 -> (fact 1)
+//! | 2 <- fact: 1
+//! 1 <- fact: 2
+//! ```
+use crate::{
+    buffer::get_buffer_create,
+    core::{
+        cons::Cons,
+        env::{CallFrame, Env, sym},
+        gc::{Context, Rt, Rto},
+        object::{Function, NIL, Object, ObjectType, Symbol},
+    },
+    data::fset,
+    print::print_to_string,
+};
+use anyhow::{Result, bail};
+use rune_core::macros::{call, list, rebind, root};
+use rune_macros::defun;
+
+defvar!(TRACE_BUFFER, "*trace-output*");
+defvar!(TRACE_LEVEL, 0);
+defsym!(TRACE__ORIGINAL);
+defsym!(ARGS);
+
+/// Printed before the first line of each outermost traced call.
+const SEPARATOR: &str = "======================================================================\n";
+
+/// Return true if `func` is a wrapper made by [`trace_function`].
+fn is_wrapper(func: Object) -> bool {
+    let Ok((sym::CLOSURE, rest)) = func.as_cons_pair() else { return false };
+    let rest: Object = match rest {
+        ObjectType::Cons(cons) => cons.into(),
+        _ => return false,
+    };
+    match rest.as_list().ok().and_then(|mut x| x.nth(2)) {
+        Some(Ok(body)) => matches!(body.as_cons_pair(), Ok((sym::TRACE__CALL, _))),
+        _ => false,
+    }
+}
+
+/// Trace FUNCTION. Every call of FUNCTION is logged to BUFFER, which defaults
+/// to `trace-buffer`, with its arguments and return value. If BUFFER is t the
+/// log is written to stderr instead. If CONTEXT is non-nil, it is a function
+/// that is called with no arguments on entry and exit, and whose value is
+/// added to each line of the log.
+///
+/// Calling `trace-function` on a function that is already traced only
+/// changes BUFFER and CONTEXT. Use `untrace-function` to stop tracing.
+#[defun]
+fn trace_function<'ob>(
+    function: Symbol<'ob>,
+    buffer: Option<Object<'ob>>,
+    context: Option<Object<'ob>>,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<Symbol<'ob>> {
+    let Some(current) = function.func(cx) else { bail!("Void Function: {function}") };
+    let definition = if is_wrapper(current.into()) {
+        crate::data::get(function, sym::TRACE__ORIGINAL, env, cx)
+    } else {
+        current.into()
+    };
+    match definition.as_cons_pair() {
+        Ok((sym::MACRO, _)) => bail!("Cannot trace macro: {function}"),
+        Ok((sym::AUTOLOAD, _)) => bail!("Cannot trace autoloaded function: {function}"),
+        _ => {}
+    }
+    let buffer = match buffer {
+        Some(buffer) if !buffer.is_nil() => buffer,
+        _ => env.vars.get(sym::TRACE_BUFFER).map(|x| x.bind(cx)).unwrap_or_default(),
+    };
+    let quote = |x: Object<'ob>| list![sym::QUOTE, x; cx];
+    let context = context.unwrap_or_default();
+    let body = list![
+        sym::TRACE__CALL,
+        quote(function.into()),
+        quote(definition),
+        quote(buffer),
+        quote(context),
+        sym::ARGS;
+        cx
+    ];
+    let wrapper =
+        list![sym::CLOSURE, list![sym::TRUE; cx], list![sym::AND_REST, sym::ARGS; cx], body; cx];
+    fset(function, wrapper, cx)?;
+    env.set_prop(function, sym::TRACE__ORIGINAL, definition);
+    Ok(function)
+}
+
+/// Trace FUNCTION without displaying the trace buffer. There is no display
+/// to show it on, so this is the same as `trace-function`.
+#[defun]
+fn trace_function_background<'ob>(
+    function: Symbol<'ob>,
+    buffer: Option<Object<'ob>>,
+    context: Option<Object<'ob>>,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<Symbol<'ob>> {
+    trace_function(function, buffer, context, env, cx)
+}
+
+/// Return non-nil if FUNCTION is traced.
+#[defun]
+fn trace_is_traced(function: Symbol, cx: &Context) -> bool {
+    function.func(cx).is_some_and(|x| is_wrapper(x.into()))
+}
+
+/// Stop tracing FUNCTION and restore its original definition. If FUNCTION
+/// has been redefined since it was traced, the new definition is kept.
+#[defun]
+fn untrace_function(function: Symbol, env: &mut Rt<Env>, cx: &Context) -> Result<()> {
+    if trace_is_traced(function, cx) {
+        let definition = crate::data::get(function, sym::TRACE__ORIGINAL, env, cx);
+        fset(function, definition, cx)?;
+    }
+    env.set_prop(function, sym::TRACE__ORIGINAL, NIL);
+    Ok(())
+}
+
+/// Append `text` to the trace log `buffer`, which is a buffer, the name of
+/// one, or t for stderr.
+fn write_trace(text: &str, buffer: Object, env: &mut Rt<Env>, cx: &Context) -> Result<()> {
+    let buffer = match buffer.untag() {
+        ObjectType::Symbol(sym::TRUE) => {
+            eprint!("{text}");
+            return Ok(());
+        }
+        ObjectType::Buffer(buffer) => buffer,
+        _ => match get_buffer_create(buffer, None, cx)?.untag() {
+            ObjectType::Buffer(buffer) => buffer,
+            _ => unreachable!("get-buffer-create should return a buffer"),
+        },
+    };
+    env.with_buffer_mut(buffer, |b| {
+        let excursion = b.save_excursion();
+        b.goto_char(b.point_max());
+        b.insert_str(text);
+        b.restore_excursion(excursion);
+    })
+}
+
+/// The text added to a log line for the trace CONTEXT function.
+fn context_string(context: &Rto<Object>, env: &mut Rt<Env>, cx: &mut Context) -> Result<String> {
+    if context.bind(cx).is_nil() {
+        return Ok(String::new());
+    }
+    let func: Function = context.bind(cx).try_into()?;
+    root!(func, cx);
+    let value = call!(func; env, cx)?;
+    let value = rebind!(value, cx);
+    Ok(format!(" [{}]", print_to_string(value, true, env, cx)?))
+}
+
+/// Call DEFINITION, the original definition of the traced FUNCTION, with
+/// ARGS, logging the call to BUFFER. This is the body of the wrapper made by
+/// `trace-function`.
+#[defun]
+#[expect(non_snake_case)]
+fn trace__call<'ob>(
+    function: &Rto<Object>,
+    definition: &Rto<Function>,
+    buffer: &Rto<Object>,
+    context: &Rto<Object>,
+    args: &Rto<Object>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<Object<'ob>> {
+    let level = match env.vars.get(sym::TRACE_LEVEL).map(|x| x.bind(cx).untag()) {
+        Some(ObjectType::Int(level)) => level.max(0) + 1,
+        _ => 1,
+    };
+    let indent = "| ".repeat(usize::try_from(level - 1)?);
+    let call = Cons::new(function.bind(cx), args.bind(cx), cx);
+    let call = print_to_string(call.into(), true, env, cx)?;
+    let ctx = context_string(context, env, cx)?;
+    let separator = if level == 1 { SEPARATOR } else { "" };
+    let entry = format!("{separator}{indent}{level} -> {call}{ctx}\n");
+    write_trace(&entry, buffer.bind(cx), env, cx)?;
+
+    let name = function.bind_as::<Symbol, _>(cx)?.name().to_owned();
+    let arg_list: Vec<Object> = args.bind(cx).as_list()?.collect::<Result<_, _>>()?;
+    env.varbind(sym::TRACE_LEVEL, cx.add(level), cx);
+    let result = {
+        let frame = &mut CallFrame::new(env);
+        for arg in arg_list {
+            frame.push_arg(arg);
+        }
+        definition.call(frame, Some(&name), cx)
+    };
+    let value = match result {
+        Ok(x) => rebind!(x, cx),
+        Err(e) => {
+            env.unbind(1, cx);
+            let exit = format!("{indent}{level} <- {name}: !non-local exit!\n");
+            write_trace(&exit, buffer.bind(cx), env, cx)?;
+            return Err(e.into());
+        }
+    };
+    root!(value, cx);
+    env.unbind(1, cx);
+    let printed = print_to_string(value.bind(cx), true, env, cx)?;
+    let ctx = context_string(context, env, cx)?;
+    let exit = format!("{indent}{level} <- {name}: {printed}{ctx}\n");
+    write_trace(&exit, buffer.bind(cx), env, cx)?;
+    Ok(value.bind(cx))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::interpreter::assert_lisp;
+
+    #[test]
+    fn test_trace_function() {
+        assert_lisp(
+            r#"(progn
+                 (defalias 'trace-test-fact
+                   #'(lambda (n) (if (< n 2) 1 (* n (trace-test-fact (1- n))))))
+                 (trace-function 'trace-test-fact "*trace-test*")
+                 (let ((value (trace-test-fact 3))
+                       (traced (trace-is-traced 'trace-test-fact)))
+                   (untrace-function 'trace-test-fact)
+                   (trace-test-fact 2)
+                   (list value traced (trace-is-traced 'trace-test-fact)
+                         (progn (set-buffer "*trace-test*") (buffer-string)))))"#,
+            r#"(6 t nil "======================================================================
+1 -> (trace-test-fact 3)
+| 2 -> (trace-test-fact 2)
+| | 3 -> (trace-test-fact 1)
+| | 3 <- trace-test-fact: 1
+| 2 <- trace-test-fact: 2
+1 <- trace-test-fact: 6
+")"#,
+        );
+    }
+
+    #[test]
+    fn test_trace_builtin() {
+        assert_lisp(
+            r#"(progn
+                 (defalias 'trace-test-car #'car)
+                 (trace-function 'trace-test-car "*trace-builtin*" #'(lambda () 'ctx))
+                 (list (trace-test-car '(1 2))
+                       (condition-case nil (trace-test-car 1) (error 'failed))
+                       (progn (set-buffer "*trace-builtin*") (buffer-string))))"#,
+            r#"(1 failed "======================================================================
+1 -> (trace-test-car (1 2)) [ctx]
+1 <- trace-test-car: 1 [ctx]
+======================================================================
+1 -> (trace-test-car 1) [ctx]
+1 <- trace-test-car: !non-local exit!
+")"#,
+        );
+    }
+}
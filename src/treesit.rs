@@ -0,0 +1,564 @@
+//! Tree-sitter parsers for buffers.
+//!
+//! Parsers and nodes are records that refer to native parser state in
+//! [`PARSERS`]. Each parser keeps a copy of the text it last parsed, and
+//! buffer changes are applied to its tree as edits so the next parse is
+//! incremental. Grammars are loaded from shared libraries named
+//! `libtree-sitter-LANG` found in `treesit-extra-load-path`, the
+//! `tree-sitter` directory in `user-emacs-directory`, or the system library
+//! path.
+use crate::core::{
+    cons::Cons,
+    env::{Env, intern, sym},
+    gc::{Context, Rt},
+    object::{NIL, Object, ObjectType, OptionalFlag, Record, RecordBuilder, Symbol},
+};
+use crate::fns::slice_into_list;
+use anyhow::{Result, anyhow, bail};
+use rune_core::hashmap::HashMap;
+use rune_macros::defun;
+use std::path::PathBuf;
+use std::sync::{
+    LazyLock, Mutex,
+    atomic::{AtomicUsize, Ordering},
+};
+use tree_sitter::{InputEdit, Language, Node, Parser, Point, Query, QueryCursor, Tree};
+
+defsym!(TREESIT_PARSER);
+defsym!(TREESIT_NODE);
+defvar!(TREESIT_EXTRA_LOAD_PATH);
+
+// The slots of a parser record
+const PARSER_ID: usize = 1;
+const PARSER_BUFFER: usize = 2;
+const PARSER_LANGUAGE: usize = 3;
+
+// The slots of a node record
+const NODE_PARSER: usize = 1;
+const NODE_START: usize = 2;
+const NODE_END: usize = 3;
+const NODE_KIND: usize = 4;
+
+struct ParserState {
+    parser: Parser,
+    language: String,
+    /// The name of the buffer being parsed
+    buffer: String,
+    /// The text of the buffer as of the last change
+    text: String,
+    tree: Option<Tree>,
+    /// True if `text` has changed since `tree` was parsed
+    stale: bool,
+}
+
+impl ParserState {
+    /// Return the syntax tree of the current text, reparsing it if needed.
+    fn tree(&mut self) -> Result<(&Tree, &str)> {
+        if self.stale || self.tree.is_none() {
+            let tree = self.parser.parse(&self.text, self.tree.as_ref());
+            self.tree =
+                Some(tree.ok_or_else(|| anyhow!("Failed to parse buffer {}", self.buffer))?);
+            self.stale = false;
+        }
+        Ok((self.tree.as_ref().unwrap(), &self.text))
+    }
+}
+
+static PARSERS: LazyLock<Mutex<HashMap<usize, ParserState>>> = LazyLock::new(Mutex::default);
+static NEXT_PARSER_ID: AtomicUsize = AtomicUsize::new(0);
+/// Grammars that have already been loaded. The libraries they are loaded
+/// from are never unloaded.
+static LANGUAGES: LazyLock<Mutex<HashMap<String, Language>>> = LazyLock::new(Mutex::default);
+
+/// The directories that grammar libraries are searched for in.
+fn grammar_dirs(env: &Rt<Env>, cx: &Context) -> Vec<PathBuf> {
+    let extra = env.vars.get(sym::TREESIT_EXTRA_LOAD_PATH).map(|x| x.bind(cx));
+    let mut dirs: Vec<PathBuf> = match extra.map(|x| x.as_list()) {
+        Some(Ok(list)) => list
+            .filter_map(|x| x.ok().and_then(|x| <&str>::try_from(x).ok()).map(PathBuf::from))
+            .collect(),
+        _ => Vec::new(),
+    };
+    let user_dir = env.vars.get(intern("user-emacs-directory", cx)).map(|x| x.bind(cx).untag());
+    let user_dir = match user_dir {
+        Some(ObjectType::String(dir)) => (**dir).to_owned(),
+        _ => "~/.emacs.d/".to_owned(),
+    };
+    if let Ok(dir) = crate::fileio::expand_file_name(&user_dir, None, env, cx) {
+        dirs.push(PathBuf::from(dir).join("tree-sitter"));
+    }
+    dirs
+}
+
+fn load_language(name: &str, env: &Rt<Env>, cx: &Context) -> Result<Language> {
+    let mut languages = LANGUAGES.lock().unwrap();
+    if let Some(language) = languages.get(name) {
+        return Ok(language.clone());
+    }
+    let filename = format!("libtree-sitter-{name}{}", std::env::consts::DLL_SUFFIX);
+    let candidates = grammar_dirs(env, cx).into_iter().map(|x| x.join(&filename));
+    let symbol = format!("tree_sitter_{}", name.replace('-', "_"));
+    let mut errors = Vec::new();
+    // fall back to the system library path
+    for path in candidates.chain([PathBuf::from(&filename)]) {
+        // SAFETY: loading a grammar runs no initialization code, and the
+        // language function takes no arguments and returns a pointer to
+        // static data.
+        let language = unsafe {
+            libloading::Library::new(&path).and_then(|library| {
+                let language = {
+                    let func =
+                        library.get::<unsafe extern "C" fn() -> Language>(symbol.as_bytes())?;
+                    func()
+                };
+                std::mem::forget(library);
+                Ok(language)
+            })
+        };
+        match language {
+            Ok(language) => {
+                languages.insert(name.to_owned(), language.clone());
+                return Ok(language);
+            }
+            Err(e) => errors.push(e.to_string()),
+        }
+    }
+    bail!("Cannot load language definition for {name}: {}", errors.join("; "))
+}
+
+fn as_parser<'ob>(obj: Object<'ob>) -> Result<&'ob Record> {
+    match obj.untag() {
+        ObjectType::Record(rec) if rec.first().is_some_and(|x| x.get() == sym::TREESIT_PARSER) => {
+            Ok(rec)
+        }
+        _ => bail!("Wrong type argument: treesit-parser-p, {obj}"),
+    }
+}
+
+fn as_node<'ob>(obj: Object<'ob>) -> Result<&'ob Record> {
+    match obj.untag() {
+        ObjectType::Record(rec) if rec.first().is_some_and(|x| x.get() == sym::TREESIT_NODE) => {
+            Ok(rec)
+        }
+        _ => bail!("Wrong type argument: treesit-node-p, {obj}"),
+    }
+}
+
+fn parser_id(parser: &Record) -> Result<usize> {
+    parser[PARSER_ID].get().try_into()
+}
+
+/// Call `func` with the state of `parser`.
+fn with_parser<T>(parser: &Record, func: impl FnOnce(&mut ParserState) -> Result<T>) -> Result<T> {
+    let id = parser_id(parser)?;
+    let mut parsers = PARSERS.lock().unwrap();
+    let Some(state) = parsers.get_mut(&id) else { bail!("Parser has been deleted") };
+    func(state)
+}
+
+fn parser_record<'ob>(
+    id: usize,
+    buffer: Object<'ob>,
+    language: &str,
+    cx: &'ob Context,
+) -> Object<'ob> {
+    let mut record = cx.vec_with_capacity(PARSER_LANGUAGE + 1);
+    record.extend([
+        sym::TREESIT_PARSER.into(),
+        cx.add(id as i64),
+        buffer,
+        intern(language, cx).into(),
+    ]);
+    cx.add(RecordBuilder(record))
+}
+
+/// The byte range and type of a node. This is what a node record holds,
+/// since tree-sitter nodes can't outlive the lock on their parser.
+type Span = (usize, usize, &'static str);
+
+fn span(node: Node) -> Span {
+    (node.start_byte(), node.end_byte(), node.kind())
+}
+
+fn node_record<'ob>(parser: Object<'ob>, span: Span, cx: &'ob Context) -> Object<'ob> {
+    let (start, end, kind) = span;
+    let mut record = cx.vec_with_capacity(NODE_KIND + 1);
+    record.extend([
+        sym::TREESIT_NODE.into(),
+        parser,
+        cx.add(start as i64),
+        cx.add(end as i64),
+        cx.add(kind),
+    ]);
+    cx.add(RecordBuilder(record))
+}
+
+/// Call `func` with the tree-sitter node that `node` refers to, and the text
+/// it was parsed from.
+fn with_node<T>(node: &Record, func: impl FnOnce(Node, &str) -> Result<T>) -> Result<T> {
+    let parser = as_parser(node[NODE_PARSER].get())?;
+    let start: usize = node[NODE_START].get().try_into()?;
+    let end: usize = node[NODE_END].get().try_into()?;
+    let kind: &str = node[NODE_KIND].get().try_into()?;
+    with_parser(parser, |state| {
+        let (tree, text) = state.tree()?;
+        // find the node again by its range and type
+        let mut found = tree.root_node().descendant_for_byte_range(start, end);
+        while let Some(node) = found {
+            if node.start_byte() != start || node.end_byte() != end {
+                break;
+            }
+            if node.kind() == kind {
+                return func(node, text);
+            }
+            found = node.parent();
+        }
+        bail!("Node is outdated")
+    })
+}
+
+/// Convert the buffer position `pos` to a byte offset in `text`.
+fn pos_to_byte(text: &str, pos: usize) -> usize {
+    let chars = pos.saturating_sub(1);
+    text.char_indices().nth(chars).map_or(text.len(), |(i, _)| i)
+}
+
+/// Convert the byte offset `byte` in `text` to a buffer position.
+fn byte_to_pos(text: &str, byte: usize) -> usize {
+    text[..byte.min(text.len())].chars().count() + 1
+}
+
+/// The row and byte column of the byte offset `byte` in `text`.
+fn point_at(text: &str, byte: usize) -> Point {
+    let before = &text[..byte];
+    let row = before.matches('\n').count();
+    let column = before.rfind('\n').map_or(byte, |x| byte - x - 1);
+    Point::new(row, column)
+}
+
+/// The edit that turns `old` into `new`, where the characters between `beg`
+/// and `end` in `new` replaced `old_len` characters in `old`. Positions are
+/// character offsets.
+fn input_edit(old: &str, new: &str, beg: usize, end: usize, old_len: usize) -> InputEdit {
+    let byte =
+        |text: &str, chars: usize| text.char_indices().nth(chars).map_or(text.len(), |x| x.0);
+    let start_byte = byte(old, beg);
+    let old_end_byte = byte(old, beg + old_len);
+    let new_end_byte = byte(new, end);
+    InputEdit {
+        start_byte,
+        old_end_byte,
+        new_end_byte,
+        start_position: point_at(old, start_byte),
+        old_end_position: point_at(old, old_end_byte),
+        new_end_position: point_at(new, new_end_byte),
+    }
+}
+
+/// Update the parsers of the current buffer after the text between `beg` and
+/// `end` replaced `old_len` characters. This is called for every change, even
+/// when the change hooks are inhibited.
+pub(crate) fn after_change(beg: usize, end: usize, old_len: usize, env: &Rt<Env>) {
+    let mut parsers = PARSERS.lock().unwrap();
+    let buffer = env.current_buffer.get();
+    let mut new_text = None;
+    for state in parsers.values_mut().filter(|x| x.buffer == buffer.name) {
+        let new_text = new_text.get_or_insert_with(|| buffer.whole_text());
+        let edit = input_edit(&state.text, new_text, beg - 1, end - 1, old_len);
+        if let Some(tree) = &mut state.tree {
+            tree.edit(&edit);
+        }
+        state.text.clone_from(new_text);
+        state.stale = true;
+    }
+}
+
+/// Return the parser of the current buffer for PARSER-OR-LANG, which is a
+/// parser, a language symbol, or nil for the first parser.
+fn resolve_parser<'ob>(
+    parser_or_lang: Option<Object<'ob>>,
+    env: &Rt<Env>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    let parser_or_lang = parser_or_lang.unwrap_or_default();
+    if as_parser(parser_or_lang).is_ok() {
+        return Ok(parser_or_lang);
+    }
+    let language = match parser_or_lang.untag() {
+        ObjectType::NIL => None,
+        ObjectType::Symbol(s) => Some(s.name().to_owned()),
+        _ => bail!("Wrong type argument: treesit-parser-p, {parser_or_lang}"),
+    };
+    let buffer = env.current_buffer.get();
+    let parsers = PARSERS.lock().unwrap();
+    let mut ids: Vec<_> = parsers
+        .iter()
+        .filter(|(_, x)| {
+            x.buffer == buffer.name && language.as_ref().is_none_or(|l| *l == x.language)
+        })
+        .map(|(id, x)| (*id, x.language.clone()))
+        .collect();
+    ids.sort_unstable();
+    let Some((id, language)) = ids.into_iter().next() else {
+        bail!("No parser for {parser_or_lang} in buffer {}", buffer.name)
+    };
+    Ok(parser_record(id, buffer.lisp_buffer(cx).into(), &language, cx))
+}
+
+/// Return non-nil if LANGUAGE has a grammar that can be loaded.
+#[defun]
+fn treesit_language_available_p(language: Symbol, env: &Rt<Env>, cx: &Context) -> bool {
+    load_language(language.name(), env, cx).is_ok()
+}
+
+/// Create and return a parser in BUFFER for LANGUAGE.
+///
+/// BUFFER defaults to the current buffer. If a parser for LANGUAGE already
+/// exists in BUFFER, it is returned instead unless NO-REUSE is non-nil.
+#[defun]
+fn treesit_parser_create<'ob>(
+    language: Symbol,
+    buffer: Option<Object<'ob>>,
+    no_reuse: OptionalFlag,
+    _tag: Option<Object>,
+    env: &Rt<Env>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    let buffer = match buffer {
+        Some(buffer) if !buffer.is_nil() => crate::buffer::resolve_buffer(buffer, cx)?,
+        _ => env.current_buffer.get().lisp_buffer(cx),
+    };
+    let (name, text) = env.with_buffer(buffer, |b| (b.name.clone(), b.whole_text()))?;
+    let language_name = language.name();
+    if no_reuse.is_none() {
+        let parsers = PARSERS.lock().unwrap();
+        let existing =
+            parsers.iter().find(|(_, x)| x.buffer == name && x.language == language_name);
+        if let Some((id, _)) = existing {
+            return Ok(parser_record(*id, buffer.into(), language_name, cx));
+        }
+    }
+    let grammar = load_language(language_name, env, cx)?;
+    let mut parser = Parser::new();
+    parser.set_language(&grammar)?;
+    let state = ParserState {
+        parser,
+        language: language_name.to_owned(),
+        buffer: name,
+        text,
+        tree: None,
+        stale: true,
+    };
+    let id = NEXT_PARSER_ID.fetch_add(1, Ordering::Relaxed);
+    PARSERS.lock().unwrap().insert(id, state);
+    Ok(parser_record(id, buffer.into(), language_name, cx))
+}
+
+/// Delete PARSER so it no longer tracks its buffer.
+#[defun]
+fn treesit_parser_delete(parser: Object) -> Result<()> {
+    let id = parser_id(as_parser(parser)?)?;
+    PARSERS.lock().unwrap().remove(&id);
+    Ok(())
+}
+
+/// Return the parsers of BUFFER, which defaults to the current buffer.
+#[defun]
+fn treesit_parser_list<'ob>(
+    buffer: Option<Object<'ob>>,
+    env: &Rt<Env>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    let buffer = match buffer {
+        Some(buffer) if !buffer.is_nil() => crate::buffer::resolve_buffer(buffer, cx)?,
+        _ => env.current_buffer.get().lisp_buffer(cx),
+    };
+    let name = env.with_buffer(buffer, |b| b.name.clone())?;
+    let parsers = PARSERS.lock().unwrap();
+    let mut ids: Vec<_> = parsers.iter().filter(|(_, x)| x.buffer == name).collect();
+    ids.sort_unstable_by_key(|(id, _)| **id);
+    let records: Vec<_> = ids
+        .into_iter()
+        .map(|(id, x)| parser_record(*id, buffer.into(), &x.language, cx))
+        .collect();
+    Ok(slice_into_list(&records, None, cx))
+}
+
+#[defun]
+fn treesit_parser_p(object: Object) -> bool {
+    as_parser(object).is_ok()
+}
+
+#[defun]
+fn treesit_node_p(object: Object) -> bool {
+    as_node(object).is_ok()
+}
+
+#[defun]
+fn treesit_parser_buffer(parser: Object) -> Result<Object> {
+    Ok(as_parser(parser)?[PARSER_BUFFER].get())
+}
+
+#[defun]
+fn treesit_parser_language(parser: Object) -> Result<Object> {
+    Ok(as_parser(parser)?[PARSER_LANGUAGE].get())
+}
+
+/// Return the root node of PARSER.
+#[defun]
+fn treesit_parser_root_node<'ob>(parser: Object<'ob>, cx: &'ob Context) -> Result<Object<'ob>> {
+    let root = with_parser(as_parser(parser)?, |state| Ok(span(state.tree()?.0.root_node())))?;
+    Ok(node_record(parser, root, cx))
+}
+
+/// Return the smallest node at POS. If NAMED is non-nil, only named nodes
+/// are considered. PARSER-OR-LANG is a parser or a language symbol, and
+/// defaults to the first parser in the current buffer.
+#[defun]
+fn treesit_node_at<'ob>(
+    pos: usize,
+    parser_or_lang: Option<Object<'ob>>,
+    named: OptionalFlag,
+    env: &Rt<Env>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    let parser = resolve_parser(parser_or_lang, env, cx)?;
+    let node = with_parser(as_parser(parser)?, |state| {
+        let (tree, text) = state.tree()?;
+        let byte = pos_to_byte(text, pos);
+        let root = tree.root_node();
+        let node = if named.is_some() {
+            root.named_descendant_for_byte_range(byte, byte)
+        } else {
+            root.descendant_for_byte_range(byte, byte)
+        };
+        Ok(span(node.unwrap_or(root)))
+    })?;
+    Ok(node_record(parser, node, cx))
+}
+
+#[defun]
+fn treesit_node_type(node: Object) -> Result<Object> {
+    Ok(as_node(node)?[NODE_KIND].get())
+}
+
+#[defun]
+fn treesit_node_start(node: Object) -> Result<usize> {
+    with_node(as_node(node)?, |node, text| Ok(byte_to_pos(text, node.start_byte())))
+}
+
+#[defun]
+fn treesit_node_end(node: Object) -> Result<usize> {
+    with_node(as_node(node)?, |node, text| Ok(byte_to_pos(text, node.end_byte())))
+}
+
+#[defun]
+fn treesit_node_text(node: Object, _no_property: OptionalFlag) -> Result<String> {
+    with_node(as_node(node)?, |node, text| Ok(text[node.byte_range()].to_owned()))
+}
+
+#[defun]
+fn treesit_node_parent<'ob>(node: Object<'ob>, cx: &'ob Context) -> Result<Object<'ob>> {
+    let record = as_node(node)?;
+    match with_node(record, |node, _| Ok(node.parent().map(span)))? {
+        Some(parent) => Ok(node_record(record[NODE_PARSER].get(), parent, cx)),
+        None => Ok(NIL),
+    }
+}
+
+/// Query NODE with QUERY and return the captured nodes.
+///
+/// NODE can be a node, a parser, or a language symbol for the parser of the
+/// current buffer. QUERY is a query string, or a list of patterns that is
+/// printed as one. The result is a list of (CAPTURE-NAME . NODE), or just the
+/// nodes if NODE-ONLY is non-nil. BEG and END limit the captures to nodes
+/// that intersect that region.
+#[defun]
+fn treesit_query_capture<'ob>(
+    node: Object<'ob>,
+    query: Object,
+    beg: Option<usize>,
+    end: Option<usize>,
+    node_only: OptionalFlag,
+    env: &Rt<Env>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    let node = match as_node(node) {
+        Ok(_) => node,
+        Err(_) => treesit_parser_root_node(resolve_parser(Some(node), env, cx)?, cx)?,
+    };
+    let source = match query.untag() {
+        ObjectType::String(s) => (**s).to_owned(),
+        _ => crate::print::print_to_string(query, true, env, cx)?,
+    };
+    let record = as_node(node)?;
+    let parser = record[NODE_PARSER].get();
+    let captures = with_node(record, |root, text| {
+        let query = Query::new(&root.language(), &source)?;
+        let mut cursor = QueryCursor::new();
+        if beg.is_some() || end.is_some() {
+            let beg = beg.map_or(0, |x| pos_to_byte(text, x));
+            let end = end.map_or(text.len(), |x| pos_to_byte(text, x));
+            cursor.set_byte_range(beg..end);
+        }
+        let names = query.capture_names();
+        let captures = cursor.captures(&query, root, text.as_bytes());
+        let captures = captures.map(|(m, i)| {
+            let capture = m.captures[i];
+            (names[capture.index as usize].to_owned(), span(capture.node))
+        });
+        Ok(captures.collect::<Vec<_>>())
+    })?;
+    let mut results = Vec::new();
+    for (name, span) in captures {
+        let node = node_record(parser, span, cx);
+        if node_only.is_some() {
+            results.push(node);
+        } else {
+            results.push(Cons::new(intern(&name, cx), node, cx).into());
+        }
+    }
+    Ok(slice_into_list(&results, None, cx))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::interpreter::assert_lisp;
+
+    #[test]
+    fn test_input_edit() {
+        // replace "b" with "xyz"
+        let edit = input_edit("a\nbc", "a\nxyzc", 2, 5, 1);
+        assert_eq!(edit.start_byte, 2);
+        assert_eq!(edit.old_end_byte, 3);
+        assert_eq!(edit.new_end_byte, 5);
+        assert_eq!(edit.start_position, Point::new(1, 0));
+        assert_eq!(edit.old_end_position, Point::new(1, 1));
+        assert_eq!(edit.new_end_position, Point::new(1, 3));
+        // delete a multibyte character
+        let edit = input_edit("aéb", "ab", 1, 1, 1);
+        assert_eq!((edit.start_byte, edit.old_end_byte, edit.new_end_byte), (1, 3, 1));
+    }
+
+    #[test]
+    fn test_positions() {
+        let text = "aé\nb";
+        assert_eq!(pos_to_byte(text, 1), 0);
+        assert_eq!(pos_to_byte(text, 3), 3);
+        assert_eq!(pos_to_byte(text, 10), text.len());
+        assert_eq!(byte_to_pos(text, 3), 3);
+        assert_eq!(point_at(text, 5), Point::new(1, 1));
+    }
+
+    #[test]
+    fn test_missing_language() {
+        assert_lisp("(treesit-language-available-p 'rune-no-such-language)", "nil");
+        assert_lisp(
+            "(condition-case nil (treesit-parser-create 'rune-no-such-language) (error 'err))",
+            "err",
+        );
+        assert_lisp("(treesit-parser-list)", "nil");
+        assert_lisp("(treesit-parser-p 'foo)", "nil");
+    }
+}
@@ -0,0 +1,227 @@
+//! Change groups and combined change hooks.
+//!
+//! Every buffer records the changes made to its text in an undo log, unless
+//! undo is disabled in it (see [`Change`]). A change group handle is a list of
+//! the form ((BUFFER . STATE) ...), where STATE is the length of the undo log
+//! of BUFFER when the group was prepared, or t if undo was disabled.
+//! Cancelling the group reverts every change recorded after that point.
+use crate::{
+    core::{
+        cons::Cons,
+        env::{Env, sym},
+        error::{Type, TypeError},
+        gc::{Context, Rt, Rto},
+        object::{Change, Function, Gc, LispBuffer, List, Object, ObjectType, TRUE},
+    },
+    editfns::{delete_region, insert_text, signal_after_change, signal_before_change},
+};
+use anyhow::{Result, bail};
+use rune_core::macros::{call, list, rebind, root};
+use rune_macros::defun;
+
+/// The buffers in a change group handle and their undo log lengths, which are
+/// `None` for buffers where undo was disabled.
+fn group_elements<'ob>(handle: Object<'ob>) -> Result<Vec<(&'ob LispBuffer, Option<usize>)>> {
+    let mut elements = Vec::new();
+    for elt in List::try_from(handle)? {
+        let elt: &Cons = elt?.try_into()?;
+        let ObjectType::Buffer(buffer) = elt.car().untag() else {
+            bail!(TypeError::new(Type::Buffer, elt.car()))
+        };
+        let state = match elt.cdr().untag() {
+            ObjectType::Int(len) => Some(usize::try_from(len)?),
+            ObjectType::Symbol(sym::TRUE) => None,
+            x => bail!("Invalid change group state: {x}"),
+        };
+        elements.push((buffer, state));
+    }
+    Ok(elements)
+}
+
+/// Return a handle for the state of the current buffer, for a change group.
+/// If BUFFER is non-nil, make a handle for BUFFER instead.
+///
+/// Pass the handle to `activate-change-group` before making the changes of
+/// the group, and finish the group with either `accept-change-group` to keep
+/// the changes or `cancel-change-group` to undo them all. Handles for several
+/// buffers can be combined with `nconc`.
+#[defun]
+fn prepare_change_group<'ob>(
+    buffer: Option<Gc<&'ob LispBuffer>>,
+    env: &Rt<Env>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    let buffer = match buffer {
+        Some(buffer) => buffer.untag(),
+        None => env.current_buffer.get().lisp_buffer(cx),
+    };
+    let state = match env.with_buffer(buffer, |b| b.undo_log.as_ref().map(Vec::len))? {
+        Some(len) => cx.add(len),
+        None => TRUE,
+    };
+    Ok(list![Cons::new(buffer, state, cx); cx])
+}
+
+/// Activate a change group made with `prepare-change-group`. Undo is
+/// enabled in its buffers until the group is finished, so that the changes
+/// can be reverted.
+#[defun]
+fn activate_change_group(handle: Object, env: &mut Rt<Env>) -> Result<()> {
+    for (buffer, _) in group_elements(handle)? {
+        env.with_buffer_mut(buffer, |b| {
+            b.undo_log.get_or_insert_with(Vec::new);
+        })?;
+    }
+    Ok(())
+}
+
+/// Finish a change group made with `prepare-change-group`, keeping its
+/// changes.
+#[defun]
+fn accept_change_group(handle: Object, env: &mut Rt<Env>) -> Result<()> {
+    for (buffer, state) in group_elements(handle)? {
+        if state.is_none() {
+            env.with_buffer_mut(buffer, |b| b.undo_log = None)?;
+        }
+    }
+    Ok(())
+}
+
+/// Finish a change group made with `prepare-change-group`, reverting all of
+/// its changes. The changes are undone in the whole buffer, even if it has
+/// been narrowed since.
+#[defun]
+fn cancel_change_group(handle: &Rto<Object>, env: &mut Rt<Env>, cx: &mut Context) -> Result<()> {
+    let old = env.current_buffer.get().lisp_buffer(cx);
+    root!(old, cx);
+    let count = group_elements(handle.bind(cx))?.len();
+    let mut result = Ok(());
+    for i in 0..count {
+        let (buffer, state) = group_elements(handle.bind(cx))?[i];
+        // Nothing is recorded while the changes are reverted
+        let (mut kept, changes) = env.with_buffer_mut(buffer, |b| {
+            let mut log = b.undo_log.take().unwrap_or_default();
+            let changes = log.split_off(state.unwrap_or(0).min(log.len()));
+            (Some(log), changes)
+        })?;
+        env.set_buffer(buffer, cx);
+        result = revert_changes(changes, env, cx);
+        let (buffer, _) = group_elements(handle.bind(cx))?[i];
+        env.with_buffer_mut(buffer, |b| b.undo_log = state.and_then(|_| kept.take()))?;
+        if result.is_err() {
+            break;
+        }
+    }
+    if env.with_buffer(old.bind(cx), |_| {}).is_ok() {
+        env.set_buffer(old.bind(cx), cx);
+    }
+    result
+}
+
+/// Undo `changes` in the current buffer, newest first, running the change
+/// hooks for each of them.
+fn revert_changes(changes: Vec<Change>, env: &mut Rt<Env>, cx: &mut Context) -> Result<()> {
+    let buffer = env.current_buffer.get_mut();
+    let narrowing = buffer.narrowing();
+    let excursion = buffer.save_excursion();
+    buffer.widen();
+    let mut result = Ok(());
+    for change in changes.into_iter().rev() {
+        result = match change {
+            Change::Insert { beg, end } => delete_region(beg + 1, end + 1, env, cx),
+            Change::Delete { pos, text } => {
+                env.current_buffer.get_mut().goto_char(pos + 1);
+                insert_text(&text, env, cx)
+            }
+        };
+        if result.is_err() {
+            break;
+        }
+    }
+    let buffer = env.current_buffer.get_mut();
+    buffer.restore_excursion(excursion);
+    buffer.set_narrowing(narrowing);
+    result
+}
+
+/// Call BODY, which changes the text between BEG and END, running the change
+/// hooks once for the whole region instead of for each change BODY makes.
+/// Return the value of BODY.
+///
+/// This is much faster for bulk edits, like `indent-region`, when the hooks
+/// are expensive.
+#[defun]
+fn combine_change_calls_1<'ob>(
+    beg: usize,
+    end: usize,
+    body: &Rto<Function>,
+    env: &mut Rt<Env>,
+    cx: &'ob mut Context,
+) -> Result<Object<'ob>> {
+    let (beg, end) = if beg <= end { (beg, end) } else { (end, beg) };
+    let inhibit = env.vars.get(sym::INHIBIT_MODIFICATION_HOOKS);
+    if inhibit.is_some_and(|x| !x.bind(cx).is_nil()) {
+        return Ok(call!(body; env, cx)?);
+    }
+    signal_before_change(beg, end, env, cx)?;
+    let old_len = env.current_buffer.get().text.len_chars();
+    env.varbind(sym::INHIBIT_MODIFICATION_HOOKS, TRUE, cx);
+    let value = match call!(body; env, cx) {
+        Ok(x) => rebind!(x, cx),
+        Err(e) => {
+            env.unbind(1, cx);
+            return Err(e.into());
+        }
+    };
+    root!(value, cx);
+    env.unbind(1, cx);
+    let new_len = env.current_buffer.get().text.len_chars();
+    let new_end = (end + new_len).saturating_sub(old_len).max(beg);
+    signal_after_change(beg, new_end, end - beg, env, cx)?;
+    Ok(value.bind(cx))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::interpreter::assert_lisp;
+
+    #[test]
+    fn test_change_group() {
+        assert_lisp(
+            r#"(progn
+                 (insert "hello world")
+                 (let ((group (prepare-change-group)))
+                   (activate-change-group group)
+                   (goto-char 1)
+                   (insert "say ")
+                   (delete-region 5 11)
+                   (cancel-change-group group)
+                   (list (buffer-string) (point))))"#,
+            r#"("hello world" 1)"#,
+        );
+        assert_lisp(
+            r#"(progn
+                 (insert "abc")
+                 (let ((group (prepare-change-group)))
+                   (activate-change-group group)
+                   (delete-region 1 3)
+                   (accept-change-group group)
+                   (list (buffer-string) (eq (car (car group)) (current-buffer)) (cdr (car group)))))"#,
+            r#"("c" t 1)"#,
+        );
+    }
+
+    #[test]
+    fn test_combine_change_calls() {
+        assert_lisp(
+            r#"(let ((calls nil))
+                 (insert "abcdef")
+                 (setq after-change-functions
+                       (list #'(lambda (beg end len) (setq calls (cons (list beg end len) calls)))))
+                 (list (combine-change-calls-1
+                        2 4 #'(lambda () (delete-region 2 3) (goto-char 2) (insert "xyz") 'done))
+                       calls (buffer-string)))"#,
+            r#"(done ((2 6 2)) "axyzcdef")"#,
+        );
+    }
+}
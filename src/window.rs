@@ -0,0 +1,620 @@
+//! Windows.
+//!
+//! The windows of a frame form a tree. Live windows are the leaves and each
+//! show a buffer, while internal windows combine their children either side
+//! by side or one above the other. There is only one frame, so there is a
+//! single tree, created the first time it is needed. See `frame.rs` for the
+//! frame itself.
+//!
+//! Window sizes are not tracked yet, so splitting always divides a window
+//! without regard to the size requested.
+use crate::buffer::resolve_buffer;
+use crate::core::{
+    env::{Env, sym},
+    error::{Type, TypeError},
+    gc::{Context, Rt},
+    object::{LispBuffer, NIL, Object, ObjectType, OptionalFlag, RecordBuilder, WithLifetime},
+};
+use crate::fns::slice_into_list;
+use crate::frame::frame_object;
+use anyhow::{Result, bail, ensure};
+use rune_macros::defun;
+
+#[derive(Debug, Default)]
+pub(crate) struct WindowTree<'a> {
+    /// Every window that has been created, indexed by id. Deleted windows are
+    /// kept so that ids stay valid.
+    windows: Vec<Window<'a>>,
+    root: usize,
+    selected: usize,
+}
+
+#[derive(Debug)]
+struct Window<'a> {
+    parent: Option<usize>,
+    deleted: bool,
+    contents: Contents<'a>,
+}
+
+#[derive(Debug)]
+enum Contents<'a> {
+    /// A live window. `point` is only used when the window is not selected,
+    /// since the selected window uses the point of its buffer. Positions are
+    /// character offsets.
+    Buffer {
+        buffer: &'a LispBuffer,
+        point: usize,
+        start: usize,
+    },
+    /// An internal window, with children ordered from left to right or top
+    /// to bottom.
+    Combination {
+        horizontal: bool,
+        children: Vec<usize>,
+    },
+}
+
+impl<'a> WindowTree<'a> {
+    fn add(&mut self, parent: Option<usize>, contents: Contents<'a>) -> usize {
+        self.windows.push(Window { parent, deleted: false, contents });
+        self.windows.len() - 1
+    }
+
+    fn is_valid(&self, id: usize) -> bool {
+        self.windows.get(id).is_some_and(|x| !x.deleted)
+    }
+
+    fn is_live(&self, id: usize) -> bool {
+        self.is_valid(id) && matches!(self.windows[id].contents, Contents::Buffer { .. })
+    }
+
+    pub(crate) fn selected(&self) -> usize {
+        self.selected
+    }
+
+    /// The buffer shown in a live window.
+    pub(crate) fn buffer(&self, id: usize) -> Option<&'a LispBuffer> {
+        match self.windows.get(id)?.contents {
+            Contents::Buffer { buffer, .. } => Some(buffer),
+            Contents::Combination { .. } => None,
+        }
+    }
+
+    /// The buffers shown in live windows, starting with the buffer of the
+    /// selected window.
+    pub(crate) fn buffers(&self) -> Vec<&'a LispBuffer> {
+        let mut ids = vec![self.selected];
+        self.live_windows(self.root, &mut ids);
+        let mut buffers: Vec<&LispBuffer> = Vec::new();
+        for buffer in ids.into_iter().filter_map(|id| self.buffer(id)) {
+            if !buffers.iter().any(|x| std::ptr::eq(*x, buffer)) {
+                buffers.push(buffer);
+            }
+        }
+        buffers
+    }
+
+    /// The start of a live window, as a character offset.
+    pub(crate) fn start(&self, id: usize) -> Option<usize> {
+        match self.windows.get(id)?.contents {
+            Contents::Buffer { start, .. } => Some(start),
+            Contents::Combination { .. } => None,
+        }
+    }
+
+    pub(crate) fn set_start(&mut self, id: usize, pos: usize) {
+        if let Some(Contents::Buffer { start, .. }) =
+            self.windows.get_mut(id).map(|x| &mut x.contents)
+        {
+            *start = pos;
+        }
+    }
+
+    fn point(&self, id: usize) -> Option<usize> {
+        match self.windows.get(id)?.contents {
+            Contents::Buffer { point, .. } => Some(point),
+            Contents::Combination { .. } => None,
+        }
+    }
+
+    fn set_point(&mut self, id: usize, pos: usize) {
+        if let Some(Contents::Buffer { point, .. }) =
+            self.windows.get_mut(id).map(|x| &mut x.contents)
+        {
+            *point = pos;
+        }
+    }
+
+    fn set_buffer(&mut self, id: usize, buffer: &LispBuffer, point: usize) {
+        let buffer = unsafe { buffer.with_lifetime() };
+        self.windows[id].contents = Contents::Buffer { buffer, point, start: 0 };
+    }
+
+    fn children(&self, id: usize) -> &[usize] {
+        match &self.windows[id].contents {
+            Contents::Combination { children, .. } => children,
+            Contents::Buffer { .. } => &[],
+        }
+    }
+
+    fn children_mut(&mut self, id: usize) -> &mut Vec<usize> {
+        match &mut self.windows[id].contents {
+            Contents::Combination { children, .. } => children,
+            Contents::Buffer { .. } => unreachable!("live windows have no children"),
+        }
+    }
+
+    fn is_combination(&self, id: usize, horizontal: bool) -> bool {
+        matches!(self.windows[id].contents, Contents::Combination { horizontal: h, .. } if h == horizontal)
+    }
+
+    /// Put `new` where `old` is in the tree.
+    fn replace(&mut self, old: usize, new: usize) {
+        let parent = self.windows[old].parent;
+        self.windows[new].parent = parent;
+        match parent {
+            Some(parent) => {
+                for child in self.children_mut(parent) {
+                    if *child == old {
+                        *child = new;
+                    }
+                }
+            }
+            None => self.root = new,
+        }
+    }
+
+    /// The live windows under `id`, in order.
+    fn live_windows(&self, id: usize, windows: &mut Vec<usize>) {
+        match &self.windows[id].contents {
+            Contents::Buffer { .. } => windows.push(id),
+            Contents::Combination { children, .. } => {
+                for child in children {
+                    self.live_windows(*child, windows);
+                }
+            }
+        }
+    }
+
+    fn mark_deleted(&mut self, id: usize) {
+        self.windows[id].deleted = true;
+        for child in self.children(id).to_vec() {
+            self.mark_deleted(child);
+        }
+    }
+
+    /// Split the live window `id` into two, returning the new window. The new
+    /// window shows the same buffer and goes after `id`, unless `before` is
+    /// true.
+    fn split(&mut self, id: usize, horizontal: bool, before: bool) -> usize {
+        let Contents::Buffer { buffer, point, start } = self.windows[id].contents else {
+            unreachable!("only live windows can be split")
+        };
+        let new = self.add(None, Contents::Buffer { buffer, point, start });
+        match self.windows[id].parent.filter(|x| self.is_combination(*x, horizontal)) {
+            Some(parent) => {
+                let children = self.children_mut(parent);
+                let idx = children.iter().position(|x| *x == id).unwrap();
+                children.insert(if before { idx } else { idx + 1 }, new);
+                self.windows[new].parent = Some(parent);
+            }
+            None => {
+                let children = if before { vec![new, id] } else { vec![id, new] };
+                let internal = self.add(None, Contents::Combination { horizontal, children });
+                self.replace(id, internal);
+                self.windows[id].parent = Some(internal);
+                self.windows[new].parent = Some(internal);
+            }
+        }
+        new
+    }
+
+    /// Remove window `id` from the tree. If it contained the selected window,
+    /// one of its siblings is selected instead.
+    fn delete(&mut self, id: usize) -> Result<()> {
+        let Some(parent) = self.windows[id].parent else {
+            bail!("Attempt to delete minibuffer or sole ordinary window")
+        };
+        let children = self.children_mut(parent);
+        let idx = children.iter().position(|x| *x == id).unwrap();
+        children.remove(idx);
+        let sibling = children[idx.saturating_sub(1)];
+        self.mark_deleted(id);
+        if let [child] = *self.children(parent) {
+            // A combination of one window is replaced by the window
+            self.replace(parent, child);
+            self.windows[parent].deleted = true;
+            let horizontal = match self.windows[child].contents {
+                Contents::Combination { horizontal, .. } => Some(horizontal),
+                Contents::Buffer { .. } => None,
+            };
+            let grandparent = self.windows[child].parent;
+            let same_direction = |x: &usize| horizontal.is_some_and(|h| self.is_combination(*x, h));
+            if let Some(grandparent) = grandparent.filter(same_direction) {
+                // and its children merge with a combination in the same direction
+                let merged = self.children(child).to_vec();
+                let siblings = self.children_mut(grandparent);
+                let idx = siblings.iter().position(|x| *x == child).unwrap();
+                siblings.splice(idx..=idx, merged.iter().copied());
+                for window in merged {
+                    self.windows[window].parent = Some(grandparent);
+                }
+                self.windows[child].deleted = true;
+            }
+        }
+        if self.windows[self.selected].deleted {
+            let mut windows = Vec::new();
+            self.live_windows(sibling, &mut windows);
+            self.selected = windows[0];
+        }
+        Ok(())
+    }
+
+    /// Make `id` the only window.
+    fn delete_others(&mut self, id: usize) {
+        let root = self.root;
+        self.mark_deleted(root);
+        self.windows[id].deleted = false;
+        self.windows[id].parent = None;
+        self.root = id;
+        self.selected = id;
+    }
+}
+
+fn as_window(obj: Object) -> Result<usize> {
+    match obj.untag() {
+        ObjectType::Record(rec) if rec.first().is_some_and(|x| x.get() == sym::WINDOW) => {
+            Ok(rec[1].get().try_into()?)
+        }
+        _ => Err(TypeError::new(Type::Window, obj).into()),
+    }
+}
+
+/// Create the window tree if it doesn't exist yet, and make a window object
+/// for every window that doesn't have one.
+pub(crate) fn init_windows(env: &mut Rt<Env>, cx: &Context) {
+    if env.window_tree.windows.is_empty() {
+        let buffer = env.current_buffer.get();
+        let (lisp_buffer, point) = (buffer.lisp_buffer(cx), buffer.point() - 1);
+        let buffer = unsafe { lisp_buffer.with_lifetime() };
+        env.window_tree.add(None, Contents::Buffer { buffer, point, start: 0 });
+    }
+    while env.windows.len() < env.window_tree.windows.len() {
+        let id = env.windows.len();
+        let mut record = cx.vec_with_capacity(2);
+        record.push(sym::WINDOW.into());
+        record.push(id.into());
+        let window = cx.add(RecordBuilder(record));
+        env.windows.push(window);
+    }
+}
+
+/// The id of WINDOW, or of the selected window if it is nil.
+fn window_arg(window: Option<Object>, env: &mut Rt<Env>, cx: &Context) -> Result<usize> {
+    init_windows(env, cx);
+    let Some(window) = window else { return Ok(env.window_tree.selected) };
+    let id = as_window(window)?;
+    ensure!(env.window_tree.is_valid(id), "Window {id} has been deleted");
+    Ok(id)
+}
+
+/// The id of the live window WINDOW, or of the selected window if it is nil.
+pub(crate) fn live_window_arg(
+    window: Option<Object>,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<usize> {
+    let id = window_arg(window, env, cx)?;
+    if !env.window_tree.is_live(id) {
+        bail!(TypeError::new(Type::Window, window.unwrap_or_default()));
+    }
+    Ok(id)
+}
+
+fn window_object<'ob>(id: usize, env: &Rt<Env>, cx: &'ob Context) -> Object<'ob> {
+    env.windows[id].bind(cx)
+}
+
+/// The point of live window `id`, as a character offset.
+fn window_point_of(id: usize, env: &Rt<Env>) -> Result<usize> {
+    let tree = &env.window_tree;
+    let buffer = tree.buffer(id).unwrap();
+    if id == tree.selected {
+        env.with_buffer(buffer, |b| b.point() - 1)
+    } else {
+        Ok(tree.point(id).unwrap())
+    }
+}
+
+/// Select window `id` and make its buffer current, saving the point of the
+/// previously selected window.
+fn select(id: usize, env: &mut Rt<Env>, cx: &Context) -> Result<()> {
+    let old = env.window_tree.selected;
+    if env.window_tree.is_live(old) {
+        let point = window_point_of(old, env)?;
+        env.window_tree.set_point(old, point);
+    }
+    env.window_tree.selected = id;
+    let buffer = env.window_tree.buffer(id).unwrap();
+    let point = env.window_tree.point(id).unwrap();
+    env.set_buffer(buffer, cx);
+    env.current_buffer.get_mut().goto_char(point + 1);
+    Ok(())
+}
+
+#[defun]
+fn windowp(object: Object) -> bool {
+    as_window(object).is_ok()
+}
+
+#[defun]
+fn window_live_p(object: Object, env: &Rt<Env>) -> bool {
+    as_window(object).is_ok_and(|id| env.window_tree.is_live(id))
+}
+
+#[defun]
+fn selected_window<'ob>(env: &mut Rt<Env>, cx: &'ob Context) -> Object<'ob> {
+    init_windows(env, cx);
+    window_object(env.window_tree.selected, env, cx)
+}
+
+/// Select WINDOW and make its buffer current.
+#[defun]
+fn select_window<'ob>(
+    window: Object<'ob>,
+    _norecord: OptionalFlag,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    let id = live_window_arg(Some(window), env, cx)?;
+    if id != env.window_tree.selected {
+        select(id, env, cx)?;
+    }
+    Ok(window)
+}
+
+#[defun]
+fn frame_root_window<'ob>(
+    _frame_or_window: Option<Object>,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Object<'ob> {
+    init_windows(env, cx);
+    window_object(env.window_tree.root, env, cx)
+}
+
+/// Return the frame of WINDOW, which is always the selected frame.
+#[defun]
+fn window_frame<'ob>(
+    window: Option<Object>,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    window_arg(window, env, cx)?;
+    Ok(frame_object(env, cx))
+}
+
+#[defun]
+fn window_parent<'ob>(
+    window: Option<Object>,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    let id = window_arg(window, env, cx)?;
+    Ok(match env.window_tree.windows[id].parent {
+        Some(parent) => window_object(parent, env, cx),
+        None => NIL,
+    })
+}
+
+#[defun]
+fn window_buffer<'ob>(
+    window: Option<Object>,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    let id = window_arg(window, env, cx)?;
+    Ok(match env.window_tree.buffer(id) {
+        Some(buffer) => cx.add(cx.bind(buffer)),
+        None => NIL,
+    })
+}
+
+/// Make WINDOW display BUFFER-OR-NAME, starting from the buffer's point.
+#[defun]
+fn set_window_buffer(
+    window: Object,
+    buffer_or_name: Object,
+    _keep_margins: OptionalFlag,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<()> {
+    let window = (!window.is_nil()).then_some(window);
+    let id = live_window_arg(window, env, cx)?;
+    let buffer = resolve_buffer(buffer_or_name, cx)?;
+    let point = env.with_buffer(buffer, |b| b.point() - 1)?;
+    env.window_tree.set_buffer(id, buffer, point);
+    Ok(())
+}
+
+/// Return the position of point in WINDOW. For the selected window this is
+/// the point of its buffer.
+#[defun]
+fn window_point(window: Option<Object>, env: &mut Rt<Env>, cx: &Context) -> Result<usize> {
+    let id = live_window_arg(window, env, cx)?;
+    Ok(window_point_of(id, env)? + 1)
+}
+
+#[defun]
+fn set_window_point(window: Object, pos: usize, env: &mut Rt<Env>, cx: &Context) -> Result<usize> {
+    let window = (!window.is_nil()).then_some(window);
+    let id = live_window_arg(window, env, cx)?;
+    let buffer = env.window_tree.buffer(id).unwrap();
+    if id == env.window_tree.selected {
+        env.with_buffer_mut(buffer, |b| b.goto_char(pos))?;
+    } else {
+        let pos = env.with_buffer(buffer, |b| b.clip_to_buffer(pos))?;
+        env.window_tree.set_point(id, pos);
+    }
+    Ok(pos)
+}
+
+/// Return the position of the first character shown in WINDOW.
+#[defun]
+fn window_start(window: Option<Object>, env: &mut Rt<Env>, cx: &Context) -> Result<usize> {
+    let id = live_window_arg(window, env, cx)?;
+    let start = env.window_tree.start(id).unwrap();
+    let buffer = env.window_tree.buffer(id).unwrap();
+    env.with_buffer(buffer, |b| start.clamp(b.point_min() - 1, b.point_max() - 1) + 1)
+}
+
+/// Make POS the first character shown in WINDOW.
+#[defun]
+fn set_window_start(
+    window: Object,
+    pos: usize,
+    _noforce: OptionalFlag,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<usize> {
+    let window = (!window.is_nil()).then_some(window);
+    let id = live_window_arg(window, env, cx)?;
+    let buffer = env.window_tree.buffer(id).unwrap();
+    let start = env.with_buffer(buffer, |b| b.clip_to_buffer(pos))?;
+    env.window_tree.set_start(id, start);
+    Ok(pos)
+}
+
+/// Split WINDOW in two and return the new window, which shows the same
+/// buffer. SIDE is where the new window goes: `below' (or nil), `above',
+/// `right' (or t), or `left'. SIZE is ignored.
+#[defun]
+fn split_window<'ob>(
+    window: Option<Object>,
+    _size: Option<Object>,
+    side: Option<Object>,
+    _pixelwise: OptionalFlag,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    let id = live_window_arg(window, env, cx)?;
+    let (horizontal, before) = match side.map(|x| x.untag()) {
+        None | Some(ObjectType::Symbol(sym::BELOW)) => (false, false),
+        Some(ObjectType::Symbol(sym::ABOVE)) => (false, true),
+        Some(ObjectType::Symbol(sym::LEFT)) => (true, true),
+        Some(ObjectType::Symbol(sym::RIGHT | sym::TRUE)) => (true, false),
+        Some(_) => bail!("Invalid side: {}", side.unwrap()),
+    };
+    let point = window_point_of(id, env)?;
+    env.window_tree.set_point(id, point);
+    let new = env.window_tree.split(id, horizontal, before);
+    init_windows(env, cx);
+    Ok(window_object(new, env, cx))
+}
+
+/// Delete WINDOW, giving its space to its siblings. If WINDOW was selected,
+/// one of its siblings is selected instead.
+#[defun]
+fn delete_window(window: Option<Object>, env: &mut Rt<Env>, cx: &Context) -> Result<()> {
+    let id = window_arg(window, env, cx)?;
+    let selected = env.window_tree.selected;
+    env.window_tree.delete(id)?;
+    let new_selected = env.window_tree.selected;
+    if new_selected != selected {
+        env.window_tree.selected = selected;
+        select(new_selected, env, cx)?;
+    }
+    Ok(())
+}
+
+/// Make WINDOW fill its frame, deleting all other windows.
+#[defun]
+fn delete_other_windows(window: Option<Object>, env: &mut Rt<Env>, cx: &Context) -> Result<()> {
+    let id = live_window_arg(window, env, cx)?;
+    let selected = env.window_tree.selected;
+    env.window_tree.delete_others(id);
+    if id != selected {
+        env.window_tree.selected = selected;
+        select(id, env, cx)?;
+    }
+    Ok(())
+}
+
+/// Return a list of the live windows, starting with WINDOW or the selected
+/// window.
+#[defun]
+fn window_list<'ob>(
+    _frame: Option<Object>,
+    _minibuf: Option<Object>,
+    window: Option<Object>,
+    env: &mut Rt<Env>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    let id = live_window_arg(window, env, cx)?;
+    let mut ids = Vec::new();
+    env.window_tree.live_windows(env.window_tree.root, &mut ids);
+    let pos = ids.iter().position(|x| *x == id).unwrap_or(0);
+    ids.rotate_left(pos);
+    let windows: Vec<_> = ids.into_iter().map(|id| window_object(id, env, cx)).collect();
+    Ok(slice_into_list(&windows, None, cx))
+}
+
+defsym!(WINDOW);
+defsym!(BELOW);
+defsym!(ABOVE);
+defsym!(LEFT);
+defsym!(RIGHT);
+
+#[cfg(test)]
+mod test {
+    use crate::interpreter::assert_lisp;
+
+    #[test]
+    fn test_split_window() {
+        assert_lisp(
+            "(let* ((w1 (selected-window)) (w2 (split-window)) (w3 (split-window w2 nil 'right)))
+               (list (length (window-list)) (eq (car (window-list)) w1)
+                     (eq (window-parent w2) (window-parent w3))
+                     (eq (window-parent (window-parent w2)) (frame-root-window))
+                     (eq (window-buffer w3) (current-buffer))))",
+            "(3 t t t t)",
+        );
+        assert_lisp(
+            "(let* ((w1 (selected-window)) (w2 (split-window)) (w3 (split-window w1)))
+               (list (equal (window-list) (list w1 w3 w2)) (eq (window-parent w3) (frame-root-window))))",
+            "(t t)",
+        );
+    }
+
+    #[test]
+    fn test_delete_window() {
+        assert_lisp(
+            "(let* ((w1 (selected-window)) (w2 (split-window)) (w3 (split-window w2 nil 'right)))
+               (delete-window w3)
+               (list (window-live-p w3) (window-live-p w2) (eq (window-parent w2) (frame-root-window))
+                     (progn (delete-window w1) (eq (selected-window) w2))
+                     (eq (frame-root-window) w2)))",
+            "(nil t t t t)",
+        );
+        assert_lisp(
+            "(let ((w1 (selected-window))) (split-window) (split-window)
+               (delete-other-windows)
+               (list (length (window-list)) (eq (frame-root-window) w1) (windowp w1)))",
+            "(1 t t)",
+        );
+    }
+
+    #[test]
+    fn test_window_point() {
+        assert_lisp(
+            r#"(progn (insert "hello") (goto-char 2)
+                 (let ((w1 (selected-window)) (w2 (split-window)))
+                   (set-window-point w2 4)
+                   (list (window-point w1) (window-point w2)
+                         (progn (select-window w2) (point))
+                         (progn (goto-char 5) (select-window w1) (point))
+                         (window-point w2))))"#,
+            "(2 4 4 2 5)",
+        );
+    }
+}
@@ -0,0 +1,275 @@
+//! Displaying buffers on the terminal.
+//!
+//! Redisplay is incremental. The selected window is only laid out again when
+//! its buffer changed before the end of the window, point moved, or the window
+//! scrolled, and only the terminal rows that differ from the last redisplay
+//! are repainted.
+use crate::core::{
+    env::{Env, sym},
+    gc::{Context, Rt, Rto},
+    object::{Narrowing, Object, ObjectType, OpenBuffer, OptionalFlag},
+};
+use crate::font_lock::fontify_pending;
+use crate::mode_line::selected_mode_line;
+use crate::window::init_windows;
+use crate::xfaces::face_color;
+use anyhow::Result;
+use rune_macros::defun;
+use rune_tui::{Capabilities, Display, Rgb, Screen, Size, Style};
+use std::{
+    io::{self, IsTerminal},
+    sync::{LazyLock, Mutex},
+};
+
+/// The size of the terminal, or a default size when there is none.
+pub(crate) fn frame_size() -> Size {
+    rune_tui::terminal_size().unwrap_or(Size { rows: 24, cols: 80 })
+}
+
+/// What the terminal can display, which is detected the first time it is
+/// needed. There are no colors when stdout is not a terminal.
+static CAPABILITIES: LazyLock<Capabilities> = LazyLock::new(|| {
+    if io::stdout().is_terminal() {
+        Capabilities::detect()
+    } else {
+        Capabilities { colors: 0, italics: false, underline_styles: false }
+    }
+});
+
+pub(crate) fn capabilities() -> Capabilities {
+    *CAPABILITIES
+}
+
+/// The terminal style of the colors of the `default` face.
+fn default_style(env: &Rt<Env>, cx: &Context) -> Style {
+    let color = |attribute| {
+        let [red, green, blue] = face_color(sym::DEFAULT, attribute, env, cx)?.to_rgb8();
+        Some(Rgb { red, green, blue })
+    };
+    Style { foreground: color(sym::KW_FOREGROUND), background: color(sym::KW_BACKGROUND) }
+}
+
+/// The size of the text of the window, which is the whole frame except for
+/// the mode line and the echo area.
+fn window_size(frame: Size) -> Size {
+    Size { rows: frame.rows.saturating_sub(2).max(1), cols: frame.cols }
+}
+
+/// The text of `buffer` between the character offsets `beg` and `end`,
+/// clamped to its accessible portion. Returns the text and the offset it
+/// starts at.
+fn text_between(buffer: &OpenBuffer, beg: usize, end: usize) -> (String, usize) {
+    let begv = buffer.point_min() - 1;
+    let zv = buffer.point_max() - 1;
+    let beg = beg.clamp(begv, zv);
+    let (s1, s2) = buffer.text.slice(beg..end.clamp(beg, zv));
+    ([s1, s2].concat(), beg)
+}
+
+/// The most characters a window of `size` can show. Each row holds at most
+/// one character per column and a newline.
+fn max_visible_chars(size: Size) -> usize {
+    size.rows * (size.cols + 1)
+}
+
+/// Choose a window start that puts point of `buffer` on `row` of a window
+/// of `size`. Only the text that could fit in the window on either side of
+/// point is searched.
+fn start_for_point(buffer: &OpenBuffer, row: usize, size: Size) -> usize {
+    let point = buffer.point() - 1;
+    let span = max_visible_chars(size);
+    let (text, offset) = text_between(buffer, point.saturating_sub(span), point + span);
+    offset + rune_tui::window_start(&text, point - offset, row, size)
+}
+
+/// What the selected window showed at the last redisplay. If none of it has
+/// changed, the window doesn't need to be laid out again.
+struct Shown {
+    window: usize,
+    buffer: String,
+    narrowing: Narrowing,
+    start: usize,
+    point: usize,
+    size: Size,
+    /// The offset of the first character that did not fit in the window
+    end: Option<usize>,
+    screen: Screen,
+}
+
+struct Redisplay {
+    display: Display,
+    shown: Option<Shown>,
+}
+
+// The terminal is shared by the whole process
+static REDISPLAY: Mutex<Redisplay> = Mutex::new(Redisplay { display: Display::new(), shown: None });
+
+/// Lay out the part of `buffer` shown in `window`, starting from `start` or
+/// from a new start if point is not visible. The last layout is reused if
+/// nothing it shows has changed. Returns the screen and the window start.
+fn layout_buffer(
+    buffer: &mut OpenBuffer,
+    window: usize,
+    start: usize,
+    size: Size,
+    last: &mut Option<Shown>,
+) -> (Screen, usize) {
+    let begv = buffer.point_min() - 1;
+    let zv = buffer.point_max() - 1;
+    let point = buffer.point() - 1;
+    let mut start = start.clamp(begv, zv);
+    let changed_from = buffer.changed_from.take();
+    if let Some(shown) = last.as_ref().filter(|x| {
+        x.window == window
+            && x.buffer == buffer.name
+            && x.narrowing == buffer.narrowing()
+            && x.start == start
+            && x.point == point
+            && x.size == size
+    }) {
+        let after_window = |pos| shown.end.is_some_and(|end| pos >= end);
+        if changed_from.is_none_or(after_window) {
+            return (shown.screen.clone(), start);
+        }
+    }
+    let span = max_visible_chars(size);
+    // One more character than fits, so the layout knows where the window ends
+    let (mut text, mut offset) = text_between(buffer, start, start + span + 1);
+    let mut screen = match point.checked_sub(offset) {
+        Some(point) => rune_tui::layout(&text, 0, point, size),
+        None => Screen::default(),
+    };
+    if screen.cursor.is_none() {
+        start = start_for_point(buffer, size.rows / 2, size);
+        (text, offset) = text_between(buffer, start, start + span + 1);
+        screen = rune_tui::layout(&text, 0, point - offset, size);
+    }
+    let end = screen.end.map(|x| x + offset);
+    let narrowing = buffer.narrowing();
+    let buffer = buffer.name.clone();
+    *last =
+        Some(Shown { window, buffer, narrowing, start, point, size, end, screen: screen.clone() });
+    (screen, start)
+}
+
+/// Lay out the selected window in `size`, scrolling it if point is not
+/// visible.
+fn layout_window(
+    size: Size,
+    last: &mut Option<Shown>,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<Screen> {
+    init_windows(env, cx);
+    let window = env.window_tree.selected();
+    let buffer = env.window_tree.buffer(window).unwrap();
+    let start = env.window_tree.start(window).unwrap();
+    // Fontify the text that could be shown, which is around point if the
+    // window has to scroll to it
+    let span = max_visible_chars(size);
+    let (beg, end) = env.with_buffer(buffer, |b| {
+        let point = b.point() - 1;
+        match point.checked_sub(start) {
+            Some(offset) if offset <= span => (start, start + span + 1),
+            _ => (point.saturating_sub(span), point + span),
+        }
+    })?;
+    // A bad keyword turns jit-lock off instead of breaking redisplay
+    if fontify_pending(buffer, beg, end, env, cx).is_err() {
+        env.with_buffer_mut(buffer, |b| b.unfontified = None)?;
+    }
+    let (screen, start) =
+        env.with_buffer_mut(buffer, |b| layout_buffer(b, window, start, size, last))?;
+    env.window_tree.set_start(window, start);
+    Ok(screen)
+}
+
+/// Forget what the terminal shows, so the next redisplay redraws all of it.
+pub(crate) fn invalidate_display() {
+    let mut redisplay = REDISPLAY.lock().unwrap();
+    redisplay.display.invalidate();
+    redisplay.shown = None;
+}
+
+/// Redraw the terminal with the selected window and its mode line, showing
+/// `echo` in the echo area. Returns false without drawing anything if stdout
+/// is not a terminal.
+pub(crate) fn redisplay_frame(echo: &str, env: &mut Rt<Env>, cx: &mut Context) -> Result<bool> {
+    let stdout = io::stdout();
+    if !stdout.is_terminal() {
+        return Ok(false);
+    }
+    // The size is checked every time, so a resized terminal is redrawn to fit
+    let Some(frame) = rune_tui::terminal_size() else { return Ok(false) };
+    // This runs lisp, which could redisplay, so it has to be done before
+    // taking the lock. A bad mode line format shouldn't stop redisplay.
+    let mode_line = selected_mode_line(env, cx).unwrap_or_default();
+    let mut redisplay = REDISPLAY.lock().unwrap();
+    let Redisplay { display, shown } = &mut *redisplay;
+    let size = window_size(frame);
+    let mut screen = layout_window(size, shown, env, cx)?;
+    screen.rows.resize(size.rows, String::new());
+    screen.rows.push(rune_tui::mode_line(&mode_line, frame.cols));
+    display.set_capabilities(capabilities());
+    display.set_style(default_style(env, cx));
+    // Only the rows that changed since the last redisplay are repainted
+    display.update(&screen, echo, frame, &mut stdout.lock())?;
+    Ok(true)
+}
+
+/// Clear the terminal and redraw it completely.
+#[defun]
+fn redraw_display(env: &mut Rt<Env>, cx: &mut Context) -> Result<()> {
+    invalidate_display();
+    redisplay_frame("", env, cx)?;
+    Ok(())
+}
+
+/// Redraw the display. Return t if it was redrawn, which only happens when
+/// stdout is a terminal.
+#[defun]
+fn redisplay(_force: OptionalFlag, env: &mut Rt<Env>, cx: &mut Context) -> Result<bool> {
+    redisplay_frame("", env, cx)
+}
+
+/// Scroll the selected window so that point is on row ARG, counting from 0
+/// at the top. If ARG is negative, count from the bottom instead. If ARG is
+/// nil or a list, put point in the middle of the window. If REDISPLAY is
+/// non-nil, the display is redrawn afterwards.
+#[defun]
+fn recenter(
+    arg: Option<&Rto<Object>>,
+    redisplay: OptionalFlag,
+    env: &mut Rt<Env>,
+    cx: &mut Context,
+) -> Result<()> {
+    let size = window_size(frame_size());
+    let row = match arg.map(|x| x.bind(cx).untag()) {
+        Some(ObjectType::Int(n)) if n >= 0 => n as usize,
+        Some(ObjectType::Int(n)) => size.rows.saturating_sub(n.unsigned_abs() as usize),
+        _ => size.rows / 2,
+    };
+    init_windows(env, cx);
+    let window = env.window_tree.selected();
+    let buffer = env.window_tree.buffer(window).unwrap();
+    let start = env.with_buffer(buffer, |b| start_for_point(b, row, size))?;
+    env.window_tree.set_start(window, start);
+    if redisplay.is_some() {
+        redisplay_frame("", env, cx)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::interpreter::assert_lisp;
+
+    #[test]
+    fn test_recenter() {
+        assert_lisp(
+            r#"(progn (insert "a\nb\nc\n") (goto-char 5) (recenter 0)
+                 (list (window-start) (progn (goto-char 1) (recenter 0) (window-start))))"#,
+            "(5 1)",
+        );
+    }
+}
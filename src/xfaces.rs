@@ -0,0 +1,666 @@
+//! Colors and faces.
+//!
+//! Colors are given by name or in one of the X11 numeric forms, `#RGB` or
+//! `rgb:R/G/B` with one to four hex digits for each component. The named
+//! colors are the X11 ones, without the numbered variants except for `grayN`.
+//! Case and spaces in names are ignored.
+//!
+//! The attributes of each face are kept as a plist, once for the frame and
+//! once for the defaults of new frames. Attributes that were never set are
+//! unspecified.
+use crate::core::{
+    env::{ArgSlice, Env, sym},
+    gc::{Context, Rt},
+    object::{NIL, Object, ObjectType, Symbol},
+};
+use crate::fns::slice_into_list;
+use crate::xdisp::capabilities;
+use anyhow::{Result, bail, ensure};
+use rune_core::macros::list;
+use rune_macros::defun;
+use rune_tui::Rgb;
+
+defsym!(UNSPECIFIED);
+defsym!(DEFAULT);
+defsym!(KW_FAMILY);
+defsym!(KW_FOUNDRY);
+defsym!(KW_WIDTH);
+defsym!(KW_HEIGHT);
+defsym!(KW_WEIGHT);
+defsym!(KW_SLANT);
+defsym!(KW_UNDERLINE);
+defsym!(KW_OVERLINE);
+defsym!(KW_STRIKE_THROUGH);
+defsym!(KW_BOX);
+defsym!(KW_INVERSE_VIDEO);
+defsym!(KW_FOREGROUND);
+defsym!(KW_DISTANT_FOREGROUND);
+defsym!(KW_BACKGROUND);
+defsym!(KW_STIPPLE);
+defsym!(KW_EXTEND);
+defsym!(KW_INHERIT);
+
+/// The X11 colors, by their lowercase names without spaces.
+const COLOR_NAMES: &[(&str, [u8; 3])] = &[
+    ("aliceblue", [240, 248, 255]),
+    ("antiquewhite", [250, 235, 215]),
+    ("aqua", [0, 255, 255]),
+    ("aquamarine", [127, 255, 212]),
+    ("azure", [240, 255, 255]),
+    ("beige", [245, 245, 220]),
+    ("bisque", [255, 228, 196]),
+    ("black", [0, 0, 0]),
+    ("blanchedalmond", [255, 235, 205]),
+    ("blue", [0, 0, 255]),
+    ("blueviolet", [138, 43, 226]),
+    ("brown", [165, 42, 42]),
+    ("burlywood", [222, 184, 135]),
+    ("cadetblue", [95, 158, 160]),
+    ("chartreuse", [127, 255, 0]),
+    ("chocolate", [210, 105, 30]),
+    ("coral", [255, 127, 80]),
+    ("cornflowerblue", [100, 149, 237]),
+    ("cornsilk", [255, 248, 220]),
+    ("crimson", [220, 20, 60]),
+    ("cyan", [0, 255, 255]),
+    ("darkblue", [0, 0, 139]),
+    ("darkcyan", [0, 139, 139]),
+    ("darkgoldenrod", [184, 134, 11]),
+    ("darkgray", [169, 169, 169]),
+    ("darkgreen", [0, 100, 0]),
+    ("darkkhaki", [189, 183, 107]),
+    ("darkmagenta", [139, 0, 139]),
+    ("darkolivegreen", [85, 107, 47]),
+    ("darkorange", [255, 140, 0]),
+    ("darkorchid", [153, 50, 204]),
+    ("darkred", [139, 0, 0]),
+    ("darksalmon", [233, 150, 122]),
+    ("darkseagreen", [143, 188, 143]),
+    ("darkslateblue", [72, 61, 139]),
+    ("darkslategray", [47, 79, 79]),
+    ("darkturquoise", [0, 206, 209]),
+    ("darkviolet", [148, 0, 211]),
+    ("deeppink", [255, 20, 147]),
+    ("deepskyblue", [0, 191, 255]),
+    ("dimgray", [105, 105, 105]),
+    ("dodgerblue", [30, 144, 255]),
+    ("firebrick", [178, 34, 34]),
+    ("floralwhite", [255, 250, 240]),
+    ("forestgreen", [34, 139, 34]),
+    ("fuchsia", [255, 0, 255]),
+    ("gainsboro", [220, 220, 220]),
+    ("ghostwhite", [248, 248, 255]),
+    ("gold", [255, 215, 0]),
+    ("goldenrod", [218, 165, 32]),
+    ("gray", [190, 190, 190]),
+    ("green", [0, 255, 0]),
+    ("greenyellow", [173, 255, 47]),
+    ("honeydew", [240, 255, 240]),
+    ("hotpink", [255, 105, 180]),
+    ("indianred", [205, 92, 92]),
+    ("indigo", [75, 0, 130]),
+    ("ivory", [255, 255, 240]),
+    ("khaki", [240, 230, 140]),
+    ("lavender", [230, 230, 250]),
+    ("lavenderblush", [255, 240, 245]),
+    ("lawngreen", [124, 252, 0]),
+    ("lemonchiffon", [255, 250, 205]),
+    ("lightblue", [173, 216, 230]),
+    ("lightcoral", [240, 128, 128]),
+    ("lightcyan", [224, 255, 255]),
+    ("lightgoldenrod", [238, 221, 130]),
+    ("lightgoldenrodyellow", [250, 250, 210]),
+    ("lightgray", [211, 211, 211]),
+    ("lightgreen", [144, 238, 144]),
+    ("lightpink", [255, 182, 193]),
+    ("lightsalmon", [255, 160, 122]),
+    ("lightseagreen", [32, 178, 170]),
+    ("lightskyblue", [135, 206, 250]),
+    ("lightslateblue", [132, 112, 255]),
+    ("lightslategray", [119, 136, 153]),
+    ("lightsteelblue", [176, 196, 222]),
+    ("lightyellow", [255, 255, 224]),
+    ("lime", [0, 255, 0]),
+    ("limegreen", [50, 205, 50]),
+    ("linen", [250, 240, 230]),
+    ("magenta", [255, 0, 255]),
+    ("maroon", [176, 48, 96]),
+    ("mediumaquamarine", [102, 205, 170]),
+    ("mediumblue", [0, 0, 205]),
+    ("mediumorchid", [186, 85, 211]),
+    ("mediumpurple", [147, 112, 219]),
+    ("mediumseagreen", [60, 179, 113]),
+    ("mediumslateblue", [123, 104, 238]),
+    ("mediumspringgreen", [0, 250, 154]),
+    ("mediumturquoise", [72, 209, 204]),
+    ("mediumvioletred", [199, 21, 133]),
+    ("midnightblue", [25, 25, 112]),
+    ("mintcream", [245, 255, 250]),
+    ("mistyrose", [255, 228, 225]),
+    ("moccasin", [255, 228, 181]),
+    ("navajowhite", [255, 222, 173]),
+    ("navy", [0, 0, 128]),
+    ("navyblue", [0, 0, 128]),
+    ("oldlace", [253, 245, 230]),
+    ("olive", [128, 128, 0]),
+    ("olivedrab", [107, 142, 35]),
+    ("orange", [255, 165, 0]),
+    ("orangered", [255, 69, 0]),
+    ("orchid", [218, 112, 214]),
+    ("palegoldenrod", [238, 232, 170]),
+    ("palegreen", [152, 251, 152]),
+    ("paleturquoise", [175, 238, 238]),
+    ("palevioletred", [219, 112, 147]),
+    ("papayawhip", [255, 239, 213]),
+    ("peachpuff", [255, 218, 185]),
+    ("peru", [205, 133, 63]),
+    ("pink", [255, 192, 203]),
+    ("plum", [221, 160, 221]),
+    ("powderblue", [176, 224, 230]),
+    ("purple", [160, 32, 240]),
+    ("rebeccapurple", [102, 51, 153]),
+    ("red", [255, 0, 0]),
+    ("rosybrown", [188, 143, 143]),
+    ("royalblue", [65, 105, 225]),
+    ("saddlebrown", [139, 69, 19]),
+    ("salmon", [250, 128, 114]),
+    ("sandybrown", [244, 164, 96]),
+    ("seagreen", [46, 139, 87]),
+    ("seashell", [255, 245, 238]),
+    ("sienna", [160, 82, 45]),
+    ("silver", [192, 192, 192]),
+    ("skyblue", [135, 206, 235]),
+    ("slateblue", [106, 90, 205]),
+    ("slategray", [112, 128, 144]),
+    ("snow", [255, 250, 250]),
+    ("springgreen", [0, 255, 127]),
+    ("steelblue", [70, 130, 180]),
+    ("tan", [210, 180, 140]),
+    ("teal", [0, 128, 128]),
+    ("thistle", [216, 191, 216]),
+    ("tomato", [255, 99, 71]),
+    ("turquoise", [64, 224, 208]),
+    ("violet", [238, 130, 238]),
+    ("violetred", [208, 32, 144]),
+    ("wheat", [245, 222, 179]),
+    ("white", [255, 255, 255]),
+    ("whitesmoke", [245, 245, 245]),
+    ("yellow", [255, 255, 0]),
+    ("yellowgreen", [154, 205, 50]),
+];
+
+/// A color with 16 bits for each component, like the values of
+/// `color-values`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Color {
+    pub(crate) red: u16,
+    pub(crate) green: u16,
+    pub(crate) blue: u16,
+}
+
+impl Color {
+    fn from_rgb8([red, green, blue]: [u8; 3]) -> Self {
+        let scale = |x: u8| u16::from(x) * 257;
+        Self { red: scale(red), green: scale(green), blue: scale(blue) }
+    }
+
+    /// The components with 8 bits each.
+    pub(crate) fn to_rgb8(self) -> [u8; 3] {
+        [(self.red >> 8) as u8, (self.green >> 8) as u8, (self.blue >> 8) as u8]
+    }
+
+    /// The color with components in the hex strings `parts`, which all have
+    /// the same number of digits.
+    fn from_hex(parts: [&str; 3]) -> Option<Self> {
+        let scale = |digits: &str| -> Option<u16> {
+            if digits.is_empty() || digits.len() > 4 {
+                return None;
+            }
+            let value = u32::from_str_radix(digits, 16).ok()?;
+            let max = (1 << (4 * digits.len())) - 1;
+            Some((value * 0xFFFF / max) as u16)
+        };
+        let [red, green, blue] = parts;
+        Some(Self { red: scale(red)?, green: scale(green)?, blue: scale(blue)? })
+    }
+}
+
+/// Parse the color NAME, returning `None` if it is not a color.
+pub(crate) fn parse_color(name: &str) -> Option<Color> {
+    if !name.is_ascii() {
+        return None;
+    }
+    if let Some(hex) = name.strip_prefix('#') {
+        let len = hex.len() / 3;
+        if hex.len() % 3 != 0 || !hex.bytes().all(|x| x.is_ascii_hexdigit()) {
+            return None;
+        }
+        return Color::from_hex([&hex[..len], &hex[len..2 * len], &hex[2 * len..]]);
+    }
+    if let Some(rgb) = name.strip_prefix("rgb:") {
+        let parts: Vec<&str> = rgb.split('/').collect();
+        let [red, green, blue] = parts[..] else { return None };
+        if !rgb.bytes().all(|x| x == b'/' || x.is_ascii_hexdigit()) {
+            return None;
+        }
+        return Color::from_hex([red, green, blue]);
+    }
+    let key = name.to_ascii_lowercase().replace(' ', "").replace("grey", "gray");
+    if let Some(level) = key.strip_prefix("gray").and_then(|x| x.parse::<u32>().ok()) {
+        // Halves round down, so gray50 is #7f7f7f like in X11
+        let value = (level <= 100).then(|| (level * 255 + 49) / 100)?;
+        return Some(Color::from_rgb8([value as u8; 3]));
+    }
+    let idx = COLOR_NAMES.binary_search_by_key(&key.as_str(), |x| x.0).ok()?;
+    Some(Color::from_rgb8(COLOR_NAMES[idx].1))
+}
+
+/// Return the red, green and blue components of COLOR as a list of numbers
+/// from 0 to 65535, or nil if COLOR is not a color. FRAME is ignored.
+#[defun]
+fn color_values<'ob>(color: &str, _frame: Option<Object>, cx: &'ob Context) -> Object<'ob> {
+    match parse_color(color) {
+        Some(Color { red, green, blue }) => {
+            list![i64::from(red), i64::from(green), i64::from(blue); cx]
+        }
+        None => NIL,
+    }
+}
+
+/// Return the red, green and blue components of COLOR as a list of floats
+/// from 0.0 to 1.0, or nil if COLOR is not a color. FRAME is ignored.
+#[defun]
+fn color_name_to_rgb<'ob>(color: &str, _frame: Option<Object>, cx: &'ob Context) -> Object<'ob> {
+    let Some(Color { red, green, blue }) = parse_color(color) else { return NIL };
+    let scale = |x: u16| cx.add(f64::from(x) / 65535.0);
+    list![scale(red), scale(green), scale(blue); cx]
+}
+
+/// Return t if COLOR is a color that can be displayed. FRAME is ignored.
+#[defun]
+fn color_defined_p(color: &str, _frame: Option<Object>) -> bool {
+    parse_color(color).is_some()
+}
+
+/// Return the names of the named colors. FRAME is ignored.
+#[defun]
+fn defined_colors<'ob>(_frame: Option<Object>, cx: &'ob Context) -> Object<'ob> {
+    let names: Vec<Object> = COLOR_NAMES.iter().map(|x| cx.add(x.0)).collect();
+    slice_into_list(&names, None, cx)
+}
+
+/// The names of the first 16 colors of a terminal.
+const TTY_COLOR_NAMES: [&str; 16] = [
+    "black",
+    "red",
+    "green",
+    "yellow",
+    "blue",
+    "magenta",
+    "cyan",
+    "white",
+    "brightblack",
+    "brightred",
+    "brightgreen",
+    "brightyellow",
+    "brightblue",
+    "brightmagenta",
+    "brightcyan",
+    "brightwhite",
+];
+
+/// Return the number of colors the terminal can display, or 0 if it has no
+/// colors. DISPLAY is ignored.
+#[defun]
+fn display_color_cells(_display: Option<Object>) -> usize {
+    capabilities().colors
+}
+
+/// Return the terminal color closest to RGB, a list of red, green and blue
+/// components from 0 to 65535. The value is a list (NAME INDEX R G B) of the
+/// name of the color, its index in the palette of the terminal, and its
+/// components, or nil if the terminal has no colors. FRAME is ignored.
+#[defun]
+fn tty_color_approximate<'ob>(
+    rgb: Object<'ob>,
+    _frame: Option<Object>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    let mut components = Vec::new();
+    for x in rgb.as_list()? {
+        let x: usize = x?.try_into()?;
+        components.push((x.min(0xFFFF) >> 8) as u8);
+    }
+    let [red, green, blue] = components[..] else { bail!("Invalid RGB value: {rgb}") };
+    let Some(index) = Rgb { red, green, blue }.approximate(capabilities().colors) else {
+        return Ok(NIL);
+    };
+    let name = match TTY_COLOR_NAMES.get(usize::from(index)) {
+        Some(name) => (*name).to_owned(),
+        None => format!("color-{index}"),
+    };
+    let Rgb { red, green, blue } = Rgb::indexed(index);
+    let scale = |x: u8| i64::from(x) * 257;
+    Ok(list![cx.add(name), i64::from(index), scale(red), scale(green), scale(blue); cx])
+}
+
+fn is_attribute(attribute: Symbol) -> bool {
+    matches!(
+        attribute,
+        sym::KW_FAMILY
+            | sym::KW_FOUNDRY
+            | sym::KW_WIDTH
+            | sym::KW_HEIGHT
+            | sym::KW_WEIGHT
+            | sym::KW_SLANT
+            | sym::KW_UNDERLINE
+            | sym::KW_OVERLINE
+            | sym::KW_STRIKE_THROUGH
+            | sym::KW_BOX
+            | sym::KW_INVERSE_VIDEO
+            | sym::KW_FOREGROUND
+            | sym::KW_DISTANT_FOREGROUND
+            | sym::KW_BACKGROUND
+            | sym::KW_STIPPLE
+            | sym::KW_EXTEND
+            | sym::KW_INHERIT
+    )
+}
+
+/// Which faces FRAME refers to, as whether it includes the faces of the frame
+/// and the defaults of new frames. Nil is both, t is only the defaults, and
+/// anything else is the frame.
+fn frames(frame: Option<Object>) -> (bool, bool) {
+    match frame {
+        None => (true, true),
+        Some(frame) if frame == sym::TRUE => (false, true),
+        Some(_) => (true, false),
+    }
+}
+
+/// The attribute plist of FACE, on the defaults of new frames if `defaults`
+/// is true. Returns `None` if FACE is not a face there.
+fn face_plist<'ob>(
+    face: Symbol,
+    defaults: bool,
+    env: &Rt<Env>,
+    cx: &'ob Context,
+) -> Option<Object<'ob>> {
+    let table = if defaults { &env.face_defaults } else { &env.faces };
+    table.get(face).map(|x| x.bind(cx))
+}
+
+/// The value of ATTRIBUTE in PLIST, or `None` if it is not there.
+fn plist_value<'ob>(plist: Object<'ob>, attribute: Symbol) -> Result<Option<Object<'ob>>> {
+    let mut iter = plist.as_list()?;
+    while let Some(key) = iter.next() {
+        let value = iter.next().transpose()?.unwrap_or(NIL);
+        if key? == attribute {
+            return Ok(Some(value));
+        }
+    }
+    Ok(None)
+}
+
+/// The value of ATTRIBUTE of FACE, or of the faces it inherits from if it is
+/// unspecified. Returns `unspecified` if none of them specify it.
+fn merged_value<'ob>(
+    face: Symbol,
+    attribute: Symbol,
+    defaults: bool,
+    env: &Rt<Env>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    let mut faces = vec![face];
+    // Stop following inheritance after a while, in case it is circular
+    for _ in 0..20 {
+        let Some(face) = faces.pop() else { break };
+        let Some(plist) = face_plist(face, defaults, env, cx) else { continue };
+        match plist_value(plist, attribute)? {
+            Some(value) if value != sym::UNSPECIFIED => return Ok(value),
+            _ => {}
+        }
+        let parents = plist_value(plist, sym::KW_INHERIT)?.unwrap_or(NIL);
+        match parents.untag() {
+            ObjectType::Symbol(parent) if parent != sym::NIL && parent != sym::UNSPECIFIED => {
+                faces.push(parent);
+            }
+            ObjectType::Cons(_) => {
+                let parents: Vec<Symbol> = parents
+                    .as_list()?
+                    .filter_map(|x| x.ok().and_then(|x| x.try_into().ok()))
+                    .collect();
+                // The first parent is searched first
+                faces.extend(parents.into_iter().rev());
+            }
+            _ => {}
+        }
+    }
+    Ok(sym::UNSPECIFIED.into())
+}
+
+/// The color ATTRIBUTE of FACE on the frame, following inheritance, or
+/// `None` if it is not a color.
+pub(crate) fn face_color(
+    face: Symbol,
+    attribute: Symbol,
+    env: &Rt<Env>,
+    cx: &Context,
+) -> Option<Color> {
+    match merged_value(face, attribute, false, env, cx).ok()?.untag() {
+        ObjectType::String(name) => parse_color(name),
+        _ => None,
+    }
+}
+
+/// Make FACE a face with all of its attributes unspecified, on FRAME, or on
+/// all frames and the defaults of new frames if FRAME is nil. Nothing changes
+/// if FACE is already a face. Return FACE.
+#[defun]
+fn internal_make_lisp_face<'ob>(
+    face: Symbol<'ob>,
+    frame: Option<Object>,
+    env: &mut Rt<Env>,
+) -> Symbol<'ob> {
+    let (on_frame, on_defaults) = frames(frame);
+    if on_frame && env.faces.get(face).is_none() {
+        env.faces.insert(face, NIL);
+    }
+    if on_defaults && env.face_defaults.get(face).is_none() {
+        env.face_defaults.insert(face, NIL);
+    }
+    face
+}
+
+/// Return t if FACE is a face on FRAME, or on the defaults of new frames if
+/// FRAME is t.
+#[defun]
+fn internal_lisp_face_p(face: Symbol, frame: Option<Object>, env: &Rt<Env>, cx: &Context) -> bool {
+    let defaults = frame.is_some_and(|x| x == sym::TRUE);
+    face_plist(face, defaults, env, cx).is_some()
+}
+
+/// Return a list of all the faces.
+#[defun]
+fn face_list<'ob>(env: &Rt<Env>, cx: &'ob Context) -> Object<'ob> {
+    let faces: Vec<Object> =
+        env.face_defaults.iter().map(|(face, _)| face.bind(cx).into()).collect();
+    slice_into_list(&faces, None, cx)
+}
+
+/// Check that VALUE can be the value of ATTRIBUTE, and return the value to
+/// store. Colors must be strings and heights positive numbers, and nil is the
+/// same as `unspecified` for both.
+fn check_value<'ob>(attribute: Symbol, value: Object<'ob>) -> Result<Object<'ob>> {
+    ensure!(is_attribute(attribute), "Invalid face attribute name: {attribute}");
+    if value == sym::UNSPECIFIED {
+        return Ok(value);
+    }
+    match attribute {
+        sym::KW_FOREGROUND | sym::KW_DISTANT_FOREGROUND | sym::KW_BACKGROUND => {
+            match value.untag() {
+                ObjectType::NIL => Ok(sym::UNSPECIFIED.into()),
+                ObjectType::String(_) => Ok(value),
+                _ => bail!("Invalid face color: {value}"),
+            }
+        }
+        sym::KW_HEIGHT => match value.untag() {
+            ObjectType::NIL => Ok(sym::UNSPECIFIED.into()),
+            ObjectType::Int(x) if x > 0 => Ok(value),
+            ObjectType::Float(x) if **x > 0.0 => Ok(value),
+            _ => bail!("Invalid face height: {value}"),
+        },
+        _ => Ok(value),
+    }
+}
+
+/// PLIST with ATTRIBUTE set to VALUE.
+fn set_value<'ob>(
+    plist: Object<'ob>,
+    attribute: Symbol,
+    value: Object<'ob>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    let mut items: Vec<Object> = plist.as_list()?.collect::<Result<_, _>>()?;
+    match items.iter().step_by(2).position(|x| *x == attribute) {
+        Some(idx) => items[2 * idx + 1] = value,
+        None => items.extend([attribute.into(), value]),
+    }
+    Ok(slice_into_list(&items, None, cx))
+}
+
+/// Set attributes of FACE on FRAME from ARGS, which alternate attribute names
+/// and values, making FACE a face if it isn't one. If FRAME is nil the
+/// attributes are set on all frames and on the defaults of new frames, and if
+/// it is t only on the defaults.
+#[defun]
+fn set_face_attribute(
+    face: Symbol,
+    frame: Object,
+    args: ArgSlice,
+    env: &mut Rt<Env>,
+    cx: &Context,
+) -> Result<()> {
+    let args: Vec<Object> = Rt::bind_slice(env.stack.arg_slice(args), cx).to_vec();
+    ensure!(
+        args.len().is_multiple_of(2),
+        "Odd number of arguments to set-face-attribute: {face}"
+    );
+    let frame = (!frame.is_nil()).then_some(frame);
+    internal_make_lisp_face(face, frame, env);
+    let (on_frame, on_defaults) = frames(frame);
+    for pair in args.chunks(2) {
+        let attribute: Symbol = pair[0].try_into()?;
+        let value = check_value(attribute, pair[1])?;
+        if on_frame {
+            let plist = face_plist(face, false, env, cx).unwrap_or(NIL);
+            env.faces.insert(face, set_value(plist, attribute, value, cx)?);
+        }
+        if on_defaults {
+            let plist = face_plist(face, true, env, cx).unwrap_or(NIL);
+            env.face_defaults.insert(face, set_value(plist, attribute, value, cx)?);
+        }
+    }
+    Ok(())
+}
+
+/// Return the value of ATTRIBUTE of FACE on FRAME, or on the defaults of new
+/// frames if FRAME is t. The value is `unspecified` if it was never set.
+///
+/// If INHERIT is non-nil and the value is unspecified, it is taken from the
+/// faces in the `:inherit` attribute of FACE, and theirs in turn. If INHERIT
+/// is a face or a list of faces, those are used after that.
+#[defun]
+fn face_attribute<'ob>(
+    face: Symbol,
+    attribute: Symbol,
+    frame: Option<Object>,
+    inherit: Option<Object>,
+    env: &Rt<Env>,
+    cx: &'ob Context,
+) -> Result<Object<'ob>> {
+    ensure!(is_attribute(attribute), "Invalid face attribute name: {attribute}");
+    let defaults = frame.is_some_and(|x| x == sym::TRUE);
+    let Some(plist) = face_plist(face, defaults, env, cx) else {
+        bail!("Invalid face: {face}")
+    };
+    let Some(inherit) = inherit else {
+        return Ok(plist_value(plist, attribute)?.unwrap_or(sym::UNSPECIFIED.into()));
+    };
+    let value = merged_value(face, attribute, defaults, env, cx)?;
+    if value != sym::UNSPECIFIED || inherit == sym::TRUE {
+        return Ok(value);
+    }
+    let faces: Vec<Symbol> = match inherit.untag() {
+        ObjectType::Symbol(face) => vec![face],
+        _ => inherit
+            .as_list()?
+            .filter_map(|x| x.ok().and_then(|x| x.try_into().ok()))
+            .collect(),
+    };
+    for face in faces {
+        let value = merged_value(face, attribute, defaults, env, cx)?;
+        if value != sym::UNSPECIFIED {
+            return Ok(value);
+        }
+    }
+    Ok(value)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::interpreter::assert_lisp;
+
+    #[test]
+    fn test_parse_color() {
+        let rgb8 = |name| parse_color(name).map(Color::to_rgb8);
+        assert_eq!(rgb8("#ff8000"), Some([255, 128, 0]));
+        assert_eq!(parse_color("#f00"), Some(Color { red: 0xFFFF, green: 0, blue: 0 }));
+        assert_eq!(parse_color("rgb:1/22/333").map(|x| x.green), Some(0x2222));
+        assert_eq!(rgb8("Light Goldenrod"), Some([238, 221, 130]));
+        assert_eq!(rgb8("grey50"), Some([127; 3]));
+        assert_eq!(rgb8("gray100"), Some([255; 3]));
+        for bad in ["#ff00", "#gg0000", "rgb:1/2", "gray101", "no-such-color", "é"] {
+            assert_eq!(parse_color(bad), None);
+        }
+    }
+
+    #[test]
+    fn test_color_values() {
+        assert_lisp(r##"(color-values "#102030")"##, "(4112 8224 12336)");
+        assert_lisp(r#"(color-name-to-rgb "white")"#, "(1.0 1.0 1.0)");
+        assert_lisp(r#"(list (color-defined-p "red") (color-values "bogus"))"#, "(t nil)");
+    }
+
+    #[test]
+    fn test_tty_colors() {
+        assert_lisp("(<= 0 (display-color-cells))", "t");
+        // black is in every palette
+        assert_lisp(
+            r#"(and (member (tty-color-approximate '(0 0 0))
+                            '(nil ("black" 0 0 0 0) ("color-16" 16 0 0 0)))
+                    t)"#,
+            "t",
+        );
+    }
+
+    #[test]
+    fn test_face_attributes() {
+        assert_lisp(
+            r#"(progn
+                 (set-face-attribute 'test-base nil :foreground "red" :height 120)
+                 (set-face-attribute 'test-face nil :inherit 'test-base :height nil)
+                 (set-face-attribute 'test-face t :background "blue")
+                 (list (face-attribute 'test-face :foreground)
+                       (face-attribute 'test-face :foreground nil t)
+                       (face-attribute 'test-face :height nil t)
+                       (face-attribute 'test-face :background)
+                       (face-attribute 'test-face :background t)
+                       (internal-lisp-face-p 'test-face)
+                       (condition-case nil (set-face-attribute 'test-face nil :bogus 1)
+                         (error 'invalid))))"#,
+            r#"(unspecified "red" 120 unspecified "blue" t invalid)"#,
+        );
+    }
+}